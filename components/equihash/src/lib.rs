@@ -5,7 +5,8 @@
 //!
 //! This crate implements Equihash as specified for the Zcash consensus rules. It can
 //! verify solutions for any valid `(n, k)` parameters, as long as the row indices are no
-//! larger than 32 bits (that is, `ceiling(((n / (k + 1)) + 1) / 8) <= 4`).
+//! larger than 32 bits (that is, `ceiling(((n / (k + 1)) + 1) / 8) <= 4`). It also
+//! includes a simple CPU solver, suitable for mining blocks on local test networks.
 //!
 //! References
 //! ==========
@@ -22,9 +23,11 @@
 
 mod minimal;
 mod params;
+mod solver;
 mod verify;
 
 #[cfg(test)]
 mod test_vectors;
 
+pub use solver::solve;
 pub use verify::{is_valid_solution, Error};
@@ -50,6 +50,61 @@ pub(crate) fn expand_array(vin: &[u8], bit_len: usize, byte_pad: usize) -> Vec<u
     vout
 }
 
+pub(crate) fn compress_array(vin: &[u8], bit_len: usize, byte_pad: usize) -> Vec<u8> {
+    assert!(bit_len >= 8);
+    assert!(u32::BITS as usize >= 7 + bit_len);
+
+    let in_width = (bit_len + 7) / 8 + byte_pad;
+    let out_len = bit_len * vin.len() / (8 * in_width);
+
+    // Shortcut for parameters where compression is a no-op
+    if out_len == vin.len() {
+        return vin.to_vec();
+    }
+
+    let mut vout: Vec<u8> = vec![0; out_len];
+    let bit_len_mask: u32 = (1 << bit_len) - 1;
+
+    // The acc_bits least-significant bits of acc_value represent a bit sequence
+    // in big-endian order.
+    let mut acc_bits = 0;
+    let mut acc_value: u32 = 0;
+
+    let mut j = 0;
+    for out in vout.iter_mut() {
+        // When we have fewer than 8 bits left in the accumulator, read the next
+        // input element.
+        if acc_bits < 8 {
+            acc_value <<= bit_len;
+            for x in byte_pad..in_width {
+                acc_value |= (
+                    // Apply bit_len_mask across byte boundaries
+                    u32::from(vin[j + x]) & ((bit_len_mask >> (8 * (in_width - x - 1))) & 0xFF)
+                ) << (8 * (in_width - x - 1)); // Big-endian
+            }
+            j += in_width;
+            acc_bits += bit_len;
+        }
+
+        acc_bits -= 8;
+        *out = ((acc_value >> acc_bits) & 0xFF) as u8;
+    }
+
+    vout
+}
+
+/// Returns the minimal encoding of the given solution indices.
+pub(crate) fn minimal_from_indices(p: Params, indices: &[u32]) -> Vec<u8> {
+    let c_bit_len = p.collision_bit_length();
+    let digit_bytes = ((c_bit_len + 1) + 7) / 8;
+    assert!(digit_bytes <= size_of::<u32>());
+    let byte_pad = size_of::<u32>() - digit_bytes;
+
+    // Big-endian so that the encoding matches `indices_from_minimal`
+    let array: Vec<u8> = indices.iter().flat_map(|i| i.to_be_bytes()).collect();
+    compress_array(&array, c_bit_len + 1, byte_pad)
+}
+
 /// Returns `None` if the parameters are invalid for this minimal encoding.
 pub(crate) fn indices_from_minimal(p: Params, minimal: &[u8]) -> Option<Vec<u32>> {
     let c_bit_len = p.collision_bit_length();
@@ -76,12 +131,13 @@ pub(crate) fn indices_from_minimal(p: Params, minimal: &[u8]) -> Option<Vec<u32>
 
 #[cfg(test)]
 mod tests {
-    use super::{expand_array, indices_from_minimal, Params};
+    use super::{compress_array, expand_array, indices_from_minimal, minimal_from_indices, Params};
 
     #[test]
     fn array_expansion() {
         let check_array = |(bit_len, byte_pad), compact, expanded| {
             assert_eq!(expand_array(compact, bit_len, byte_pad), expanded);
+            assert_eq!(compress_array(expanded, bit_len, byte_pad), compact);
         };
 
         // 8 11-bit chunks, all-ones
@@ -153,6 +209,10 @@ mod tests {
                 indices_from_minimal(Params { n: 80, k: 3 }, minimal).unwrap(),
                 indices,
             );
+            assert_eq!(
+                minimal_from_indices(Params { n: 80, k: 3 }, indices),
+                minimal
+            );
         };
 
         // The solutions here are not intended to be valid.
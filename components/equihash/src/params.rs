@@ -30,7 +30,6 @@ impl Params {
     pub(crate) fn collision_byte_length(&self) -> usize {
         (self.collision_bit_length() + 7) / 8
     }
-    #[cfg(test)]
     pub(crate) fn hash_length(&self) -> usize {
         ((self.k as usize) + 1) * self.collision_byte_length()
     }
@@ -0,0 +1,240 @@
+//! A CPU solver for the [Equihash] proof-of-work algorithm.
+//!
+//! This is a straightforward implementation of Wagner's algorithm, intended for mining
+//! blocks on local test networks rather than for competitive mining. For the Zcash
+//! parameters `(n = 200, k = 9)` it requires several hundred megabytes of memory.
+//!
+//! [Equihash]: https://zips.z.cash/protocol/protocol.pdf#equihash
+
+use blake2b_simd::State as Blake2bState;
+
+use crate::{
+    minimal::{expand_array, minimal_from_indices},
+    params::Params,
+    verify::{generate_hash, initialise_state, Error, Kind},
+};
+
+/// The rows of a single round of Wagner's algorithm.
+///
+/// The hashes of the rows are stored contiguously, to avoid an allocation per row. Each
+/// row only retains the part of its hash that has not yet been collided on.
+struct Rows {
+    hash_len: usize,
+    hashes: Vec<u8>,
+}
+
+impl Rows {
+    fn len(&self) -> usize {
+        self.hashes.len() / self.hash_len
+    }
+
+    fn hash(&self, i: u32) -> &[u8] {
+        let start = i as usize * self.hash_len;
+        &self.hashes[start..start + self.hash_len]
+    }
+}
+
+/// Generates the initial rows, one for each of the `2^(collision_bit_length + 1)` indices.
+fn initial_rows(p: &Params, state: &Blake2bState) -> Rows {
+    let hash_len = p.hash_length();
+    let rows = 1u32 << (p.collision_bit_length() + 1);
+    let mut hashes = Vec::with_capacity(rows as usize * hash_len);
+
+    let indices_per_hash_output = p.indices_per_hash_output();
+    for i in (0..rows).step_by(indices_per_hash_output as usize) {
+        let hash = generate_hash(state, i / indices_per_hash_output);
+        for (_, chunk) in (i..rows).zip(hash.as_bytes().chunks((p.n / 8) as usize)) {
+            hashes.extend(expand_array(chunk, p.collision_bit_length(), 0));
+        }
+    }
+
+    Rows { hash_len, hashes }
+}
+
+/// Interprets the leading collision bytes of a hash as an integer.
+fn collision_key(p: &Params, hash: &[u8]) -> u32 {
+    hash[..p.collision_byte_length()]
+        .iter()
+        .fold(0, |acc, b| (acc << 8) | u32::from(*b))
+}
+
+/// Performs one round of Wagner's algorithm, pairing up the rows that collide on their
+/// leading collision bytes.
+///
+/// Returns the rows for the next round, along with the indices of the rows from which
+/// each of them was derived. In the final round, only pairs whose entire hashes collide
+/// are retained, and the returned rows are empty.
+fn collide(p: &Params, rows: &Rows, final_round: bool) -> (Rows, Vec<(u32, u32)>) {
+    let trim = p.collision_byte_length();
+    let mut order: Vec<u32> = (0..rows.len() as u32).collect();
+    order.sort_unstable_by_key(|i| collision_key(p, rows.hash(*i)));
+
+    let mut next = Rows {
+        hash_len: rows.hash_len - trim,
+        hashes: vec![],
+    };
+    let mut parents = vec![];
+    let mut xor = vec![0; next.hash_len];
+
+    let mut start = 0;
+    while start < order.len() {
+        let key = collision_key(p, rows.hash(order[start]));
+        let end = start
+            + order[start..]
+                .iter()
+                .take_while(|i| collision_key(p, rows.hash(**i)) == key)
+                .count();
+
+        for (l, a) in order[start..end].iter().enumerate() {
+            for b in &order[start + l + 1..end] {
+                let a_hash = &rows.hash(*a)[trim..];
+                let b_hash = &rows.hash(*b)[trim..];
+                for (x, (a, b)) in xor.iter_mut().zip(a_hash.iter().zip(b_hash.iter())) {
+                    *x = a ^ b;
+                }
+                let is_zero = xor.iter().all(|x| *x == 0);
+
+                if final_round {
+                    if is_zero {
+                        parents.push((*a, *b));
+                    }
+                } else if !is_zero {
+                    // A zero hash before the final round almost always comes from two
+                    // rows derived from the same indices, which can only lead to
+                    // solutions with duplicate indices.
+                    next.hashes.extend_from_slice(&xor);
+                    parents.push((*a, *b));
+                }
+            }
+        }
+
+        start = end;
+    }
+
+    (next, parents)
+}
+
+/// Returns the indices from which the given row of the final round was derived, ordered
+/// as they must appear in a solution.
+fn indices_of(parents: &[Vec<(u32, u32)>], row: u32) -> Vec<u32> {
+    match parents.split_last() {
+        None => vec![row],
+        Some((last, rest)) => {
+            let (a, b) = last[row as usize];
+            let mut a = indices_of(rest, a);
+            let mut b = indices_of(rest, b);
+            if b[0] < a[0] {
+                std::mem::swap(&mut a, &mut b);
+            }
+            a.extend(b);
+            a
+        }
+    }
+}
+
+fn distinct_indices(indices: &[u32]) -> bool {
+    let mut sorted = indices.to_vec();
+    sorted.sort_unstable();
+    sorted.windows(2).all(|w| w[0] != w[1])
+}
+
+/// Returns the solutions for `(input, nonce)` as lists of indices, in ascending order.
+pub(crate) fn solve_indices(p: Params, input: &[u8], nonce: &[u8]) -> Vec<Vec<u32>> {
+    let mut state = initialise_state(p.n, p.k, p.hash_output());
+    state.update(input);
+    state.update(nonce);
+
+    let mut rows = initial_rows(&p, &state);
+    let mut parents = Vec::with_capacity(p.k as usize);
+    for round in 1..=p.k {
+        let (next, pairs) = collide(&p, &rows, round == p.k);
+        rows = next;
+        parents.push(pairs);
+    }
+
+    let mut solutions: Vec<_> = (0..parents[p.k as usize - 1].len() as u32)
+        .map(|row| indices_of(&parents, row))
+        .filter(|indices| distinct_indices(indices))
+        .collect();
+    solutions.sort();
+    solutions.dedup();
+    solutions
+}
+
+/// Finds solutions for `(input, nonce)` with the parameters `(n, k)`.
+///
+/// Returns the minimal encodings of the solutions found, each of which is valid according
+/// to [`is_valid_solution`]. There may be no solutions for a given nonce, in which case the
+/// caller should try another.
+///
+/// The solver generates `2^(n / (k + 1) + 1)` rows in each round, so it is only practical
+/// for parameters where that is at most a few million.
+///
+/// [`is_valid_solution`]: crate::is_valid_solution
+pub fn solve(n: u32, k: u32, input: &[u8], nonce: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let p = Params::new(n, k).ok_or(Error(Kind::InvalidParams))?;
+    // Indices must fit in the row index type, and in the minimal encoding.
+    if p.collision_bit_length() + 1 > 25 {
+        return Err(Error(Kind::InvalidParams));
+    }
+
+    Ok(solve_indices(p, input, nonce)
+        .iter()
+        .map(|indices| minimal_from_indices(p, indices))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{solve, solve_indices};
+    use crate::{is_valid_solution, params::Params, test_vectors::VALID_TEST_VECTORS};
+
+    #[test]
+    fn valid_test_vectors() {
+        // The (200, 9) vectors are covered by `zcash_parameters`, and the (144, 5) vectors
+        // require too much memory.
+        for tv in VALID_TEST_VECTORS
+            .iter()
+            .filter(|tv| (tv.params.n, tv.params.k) == (96, 5))
+        {
+            assert_eq!(solve_indices(tv.params, tv.input, &tv.nonce), tv.solutions);
+        }
+    }
+
+    #[test]
+    fn solutions_round_trip() {
+        let input = b"Equihash is an asymmetric PoW based on the Generalised Birthday problem.";
+        for (n, k) in [(48, 5), (96, 5)] {
+            let mut found = 0;
+            for i in 0..8u8 {
+                let nonce = [i; 32];
+                for soln in solve(n, k, input, &nonce).unwrap() {
+                    is_valid_solution(n, k, input, &nonce, &soln).unwrap();
+                    found += 1;
+                }
+            }
+            assert!(found > 0);
+        }
+    }
+
+    #[test]
+    fn zcash_parameters() {
+        let tv = VALID_TEST_VECTORS
+            .iter()
+            .find(|tv| (tv.params.n, tv.params.k) == (200, 9))
+            .unwrap();
+        assert_eq!(solve_indices(tv.params, tv.input, &tv.nonce), tv.solutions);
+
+        for soln in solve(200, 9, tv.input, &tv.nonce).unwrap() {
+            is_valid_solution(200, 9, tv.input, &tv.nonce, &soln).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_params() {
+        assert!(solve(200, 2, b"", &[0; 32]).is_err());
+        // Valid for verification, but the indices would not fit in the row index type.
+        assert!(solve(208, 7, b"", &[0; 32]).is_err());
+        assert!(Params::new(208, 7).is_some());
+    }
+}
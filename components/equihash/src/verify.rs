@@ -83,7 +83,7 @@ impl Node {
 
 /// An Equihash solution failed to verify.
 #[derive(Debug)]
-pub struct Error(Kind);
+pub struct Error(pub(crate) Kind);
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -114,7 +114,7 @@ impl fmt::Display for Kind {
     }
 }
 
-fn initialise_state(n: u32, k: u32, digest_len: u8) -> Blake2bState {
+pub(crate) fn initialise_state(n: u32, k: u32, digest_len: u8) -> Blake2bState {
     let mut personalization: Vec<u8> = Vec::from("ZcashPoW");
     personalization.write_u32::<LittleEndian>(n).unwrap();
     personalization.write_u32::<LittleEndian>(k).unwrap();
@@ -125,7 +125,7 @@ fn initialise_state(n: u32, k: u32, digest_len: u8) -> Blake2bState {
         .to_state()
 }
 
-fn generate_hash(base_state: &Blake2bState, i: u32) -> Blake2bHash {
+pub(crate) fn generate_hash(base_state: &Blake2bState, i: u32) -> Blake2bHash {
     let mut lei = [0u8; 4];
     (&mut lei[..]).write_u32::<LittleEndian>(i).unwrap();
 
@@ -0,0 +1,145 @@
+//! The common fields of a PCZT.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use zcash_primitives::transaction::TxVersion;
+use zcash_protocol::consensus::{BlockHeight, BranchId};
+
+use crate::encoding::{invalid_data, read_proprietary, write_proprietary};
+
+/// Global fields that are relevant to the transaction as a whole.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Global {
+    //
+    // Transaction effecting data.
+    //
+    // These are required fields that are part of the final transaction, and are filled in
+    // by the Creator when initializing the PCZT.
+    //
+    pub(crate) tx_version: u32,
+    pub(crate) version_group_id: u32,
+    /// The consensus branch ID for the chain in which this transaction will be mined.
+    ///
+    /// Non-optional because this commits to the set of consensus rules that will apply to
+    /// the transaction; differences therein can affect every role.
+    pub(crate) consensus_branch_id: u32,
+    pub(crate) lock_time: u32,
+    pub(crate) expiry_height: u32,
+
+    /// Proprietary fields, keyed by names that are chosen by the application that sets
+    /// them.
+    ///
+    /// These are set by the Updater, and are ignored by the other roles. Applications
+    /// should prefix their keys with a name that identifies them, to avoid collisions.
+    pub(crate) proprietary: BTreeMap<String, Vec<u8>>,
+}
+
+impl Global {
+    /// Returns the transaction version (including the "overwintered" flag bit).
+    pub fn tx_version(&self) -> u32 {
+        self.tx_version
+    }
+
+    /// Returns the version group ID of the transaction.
+    pub fn version_group_id(&self) -> u32 {
+        self.version_group_id
+    }
+
+    /// Returns the consensus branch ID for the chain in which the transaction will be
+    /// mined.
+    pub fn consensus_branch_id(&self) -> u32 {
+        self.consensus_branch_id
+    }
+
+    /// Returns the lock time of the transaction.
+    pub fn lock_time(&self) -> u32 {
+        self.lock_time
+    }
+
+    /// Returns the expiry height of the transaction.
+    pub fn expiry_height(&self) -> u32 {
+        self.expiry_height
+    }
+
+    /// Returns the proprietary fields of this PCZT.
+    pub fn proprietary(&self) -> &BTreeMap<String, Vec<u8>> {
+        &self.proprietary
+    }
+
+    pub(crate) fn parsed_branch_id(&self) -> Result<BranchId, crate::roles::Error> {
+        BranchId::try_from(self.consensus_branch_id)
+            .map_err(|_| crate::roles::Error::UnsupportedConsensusBranch)
+    }
+
+    pub(crate) fn parsed_expiry_height(&self) -> BlockHeight {
+        BlockHeight::from_u32(self.expiry_height)
+    }
+
+    /// Merges these global fields with another set.
+    ///
+    /// Returns `None` if they have conflicting data.
+    pub(crate) fn merge(mut self, other: Self) -> Option<Self> {
+        let Self {
+            tx_version,
+            version_group_id,
+            consensus_branch_id,
+            lock_time,
+            expiry_height,
+            proprietary,
+        } = other;
+
+        if self.tx_version != tx_version
+            || self.version_group_id != version_group_id
+            || self.consensus_branch_id != consensus_branch_id
+            || self.lock_time != lock_time
+            || self.expiry_height != expiry_height
+        {
+            return None;
+        }
+
+        for (key, value) in proprietary {
+            match self.proprietary.get(&key) {
+                Some(existing) if existing != &value => return None,
+                _ => {
+                    self.proprietary.insert(key, value);
+                }
+            }
+        }
+
+        Some(self)
+    }
+
+    pub(crate) fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let tx_version = reader.read_u32::<LittleEndian>()?;
+        let version_group_id = reader.read_u32::<LittleEndian>()?;
+        let consensus_branch_id = reader.read_u32::<LittleEndian>()?;
+        let lock_time = reader.read_u32::<LittleEndian>()?;
+        let expiry_height = reader.read_u32::<LittleEndian>()?;
+        let proprietary = read_proprietary(&mut reader)?;
+
+        let expected = TxVersion::Zip225;
+        if tx_version != expected.header() || version_group_id != expected.version_group_id() {
+            return Err(invalid_data("unsupported transaction version"));
+        }
+
+        Ok(Global {
+            tx_version,
+            version_group_id,
+            consensus_branch_id,
+            lock_time,
+            expiry_height,
+            proprietary,
+        })
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.tx_version)?;
+        writer.write_u32::<LittleEndian>(self.version_group_id)?;
+        writer.write_u32::<LittleEndian>(self.consensus_branch_id)?;
+        writer.write_u32::<LittleEndian>(self.lock_time)?;
+        writer.write_u32::<LittleEndian>(self.expiry_height)?;
+        write_proprietary(&mut writer, &self.proprietary)
+    }
+}
@@ -0,0 +1,141 @@
+//! Helpers for the PCZT binary encoding.
+//!
+//! All integers are encoded little-endian. Variable-length byte strings and lists use the
+//! [`Vector`] encoding, and optional fields use the [`Optional`] encoding.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use zcash_encoding::{Optional, Vector};
+
+pub(crate) fn read_array<R: Read, const N: usize>(mut reader: R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub(crate) fn read_bytes<R: Read>(reader: R) -> io::Result<Vec<u8>> {
+    Vector::read(reader, |r| r.read_u8())
+}
+
+pub(crate) fn write_bytes<W: Write>(writer: W, bytes: &[u8]) -> io::Result<()> {
+    Vector::write(writer, bytes, |w, b| w.write_u8(*b))
+}
+
+pub(crate) fn read_optional_array<R: Read, const N: usize>(
+    reader: R,
+) -> io::Result<Option<[u8; N]>> {
+    Optional::read(reader, read_array)
+}
+
+pub(crate) fn write_optional_array<W: Write, const N: usize>(
+    writer: W,
+    value: Option<&[u8; N]>,
+) -> io::Result<()> {
+    Optional::write(writer, value, |mut w, v| w.write_all(v))
+}
+
+pub(crate) fn read_optional_bytes<R: Read>(reader: R) -> io::Result<Option<Vec<u8>>> {
+    Optional::read(reader, read_bytes)
+}
+
+pub(crate) fn write_optional_bytes<W: Write>(writer: W, value: Option<&[u8]>) -> io::Result<()> {
+    Optional::write(writer, value, write_bytes)
+}
+
+fn read_string<R: Read>(reader: R) -> io::Result<String> {
+    String::from_utf8(read_bytes(reader)?).map_err(|_| invalid_data("invalid UTF-8 string"))
+}
+
+fn write_string<W: Write>(writer: W, value: &str) -> io::Result<()> {
+    write_bytes(writer, value.as_bytes())
+}
+
+pub(crate) fn read_optional_string<R: Read>(reader: R) -> io::Result<Option<String>> {
+    Optional::read(reader, read_string)
+}
+
+pub(crate) fn write_optional_string<W: Write>(writer: W, value: Option<&str>) -> io::Result<()> {
+    Optional::write(writer, value, write_string)
+}
+
+/// Reads a map of proprietary fields: a list of `(key, value)` pairs with unique string
+/// keys.
+pub(crate) fn read_proprietary<R: Read>(reader: R) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let entries = Vector::read(reader, |r| {
+        let key = read_string(&mut *r)?;
+        let value = read_bytes(r)?;
+        Ok((key, value))
+    })?;
+
+    let mut proprietary = BTreeMap::new();
+    for (key, value) in entries {
+        if proprietary.insert(key, value).is_some() {
+            return Err(invalid_data("duplicate proprietary field"));
+        }
+    }
+    Ok(proprietary)
+}
+
+pub(crate) fn write_proprietary<W: Write>(
+    writer: W,
+    proprietary: &BTreeMap<String, Vec<u8>>,
+) -> io::Result<()> {
+    Vector::write_sized(writer, proprietary.iter(), |w, (key, value)| {
+        write_string(&mut *w, key)?;
+        write_bytes(w, value)
+    })
+}
+
+pub(crate) fn read_optional_u64<R: Read>(reader: R) -> io::Result<Option<u64>> {
+    Optional::read(reader, |mut r| r.read_u64::<LittleEndian>())
+}
+
+pub(crate) fn write_optional_u64<W: Write>(writer: W, value: Option<u64>) -> io::Result<()> {
+    Optional::write(writer, value, |mut w, v| w.write_u64::<LittleEndian>(v))
+}
+
+/// Reads a Merkle path of depth `N`: a `u32` leaf position followed by `N` 32-byte
+/// sibling hashes.
+pub(crate) fn read_optional_witness<R: Read, const N: usize>(
+    reader: R,
+) -> io::Result<Option<(u32, [[u8; 32]; N])>> {
+    Optional::read(reader, |mut r| {
+        let position = r.read_u32::<LittleEndian>()?;
+        let mut path = [[0; 32]; N];
+        for node in path.iter_mut() {
+            r.read_exact(node)?;
+        }
+        Ok((position, path))
+    })
+}
+
+pub(crate) fn write_optional_witness<W: Write, const N: usize>(
+    writer: W,
+    value: Option<&(u32, [[u8; 32]; N])>,
+) -> io::Result<()> {
+    Optional::write(writer, value, |mut w, (position, path)| {
+        w.write_u32::<LittleEndian>(*position)?;
+        path.iter().try_for_each(|node| w.write_all(node))
+    })
+}
+
+pub(crate) fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Merges two optional fields, failing if both are set to different values.
+pub(crate) fn merge_optional<T: PartialEq>(lhs: &mut Option<T>, rhs: Option<T>) -> bool {
+    match (&lhs, rhs) {
+        // If the RHS is not present, keep the LHS.
+        (_, None) => (),
+        // If the LHS is not present, set it to the RHS.
+        (None, Some(rhs)) => *lhs = Some(rhs),
+        // If both are present and are equal, nothing to do.
+        (Some(l), Some(r)) if l == &r => (),
+        // If both are present and are not equal, fail.
+        (Some(_), Some(_)) => return false,
+    }
+    true
+}
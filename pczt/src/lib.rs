@@ -1 +1,193 @@
+//! *The Partially Created Zcash Transaction (PCZT) format.*
+//!
+//! A PCZT is a container for a Zcash transaction that is in the process of being
+//! constructed. It carries the transaction's effecting data, along with all of the
+//! secret and non-secret information that the various parties involved in authorizing
+//! the transaction need in order to do their job. A PCZT can be serialized with
+//! [`Pczt::serialize`] and handed between processes or devices, so that (for example)
+//! proving can happen on one machine while spend authorization happens on another.
+//!
+//! The lifecycle of a PCZT is split up into a number of roles, each of which is
+//! implemented in a submodule of [`roles`]:
+//!
+//! - [`Creator`](roles::creator::Creator): creates the base PCZT with the global
+//!   transaction fields and no inputs or outputs.
+//! - [`Constructor`](roles::constructor::Constructor): adds the spends and outputs, and
+//!   lays out the transparent, Sapling and Orchard bundles. This fixes the effecting data
+//!   of the transaction.
+//! - [`Updater`](roles::updater::Updater): adds information needed by later roles, such
+//!   as Sapling proof generation keys.
+//! - [`Prover`](roles::prover::Prover): creates the Sapling and Orchard proofs.
+//! - [`Signer`](roles::signer::Signer): creates the transparent, Sapling and Orchard
+//!   spend authorization signatures.
+//! - [`Combiner`](roles::combiner::Combiner): merges several PCZTs that were processed in
+//!   parallel (for example, one that has been proven and one that has been signed).
+//! - [`SpendFinalizer`](roles::spend_finalizer::SpendFinalizer): combines the collected
+//!   transparent signatures into `scriptSig`s.
+//! - [`TransactionExtractor`](roles::tx_extractor::TransactionExtractor): creates the
+//!   binding signatures, and produces the final [`Transaction`].
+//!
+//! Only v5 transactions are supported, because earlier transaction versions commit to
+//! the Sapling proofs in the transaction sighash, which prevents proving and signing from
+//! being performed independently.
+//!
+//! [`Transaction`]: zcash_primitives::transaction::Transaction
 
+#![cfg_attr(docsrs, feature(doc_cfg))]
+#![cfg_attr(docsrs, feature(doc_auto_cfg))]
+// Catch documentation errors caused by code changes.
+#![deny(rustdoc::broken_intra_doc_links)]
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+pub mod common;
+mod encoding;
+mod note_encryption;
+pub mod orchard;
+pub mod roles;
+pub mod sapling;
+pub mod transparent;
+mod tx_data;
+
+const MAGIC_BYTES: &[u8; 4] = b"PCZT";
+const PCZT_VERSION_1: u32 = 1;
+
+/// A partially-created Zcash transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pczt {
+    /// Global fields that are relevant to the transaction as a whole.
+    global: common::Global,
+
+    //
+    // Protocol-specific fields.
+    //
+    // Unlike the `TransactionData` type in `zcash_primitives`, these are not optional.
+    // This is because a PCZT does not always contain a semantically-valid transaction,
+    // and there may be phases where we need to store protocol-specific metadata before
+    // it has been determined whether there are protocol-specific inputs or outputs.
+    //
+    transparent: transparent::Bundle,
+    sapling: sapling::Bundle,
+    orchard: orchard::Bundle,
+}
+
+impl Pczt {
+    /// Parses a PCZT from its encoding.
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let mut reader = bytes;
+        let pczt = Self::read(&mut reader)?;
+        if reader.is_empty() {
+            Ok(pczt)
+        } else {
+            Err(ParseError::TrailingBytes)
+        }
+    }
+
+    /// Serializes this PCZT.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.write(&mut bytes)
+            .expect("writing to a Vec<u8> is infallible");
+        bytes
+    }
+
+    /// Reads a PCZT from the given reader.
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, ParseError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC_BYTES {
+            return Err(ParseError::NotPczt);
+        }
+
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != PCZT_VERSION_1 {
+            return Err(ParseError::UnknownVersion(version));
+        }
+
+        let global = common::Global::read(&mut reader)?;
+        let transparent = transparent::Bundle::read(&mut reader)?;
+        let sapling = sapling::Bundle::read(&mut reader)?;
+        let orchard = orchard::Bundle::read(&mut reader)?;
+
+        Ok(Pczt {
+            global,
+            transparent,
+            sapling,
+            orchard,
+        })
+    }
+
+    /// Writes this PCZT to the given writer.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC_BYTES)?;
+        writer.write_u32::<LittleEndian>(PCZT_VERSION_1)?;
+        self.global.write(&mut writer)?;
+        self.transparent.write(&mut writer)?;
+        self.sapling.write(&mut writer)?;
+        self.orchard.write(&mut writer)
+    }
+
+    /// Returns the global fields of this PCZT.
+    pub fn global(&self) -> &common::Global {
+        &self.global
+    }
+
+    /// Returns the transparent bundle of this PCZT.
+    pub fn transparent(&self) -> &transparent::Bundle {
+        &self.transparent
+    }
+
+    /// Returns the Sapling bundle of this PCZT.
+    pub fn sapling(&self) -> &sapling::Bundle {
+        &self.sapling
+    }
+
+    /// Returns the Orchard bundle of this PCZT.
+    pub fn orchard(&self) -> &orchard::Bundle {
+        &self.orchard
+    }
+}
+
+/// Errors that can occur while parsing a PCZT.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The bytes do not start with the PCZT magic bytes.
+    NotPczt,
+    /// The PCZT has a format version that this library does not support.
+    UnknownVersion(u32),
+    /// The PCZT encoding was followed by unexpected bytes.
+    TrailingBytes,
+    /// The PCZT encoding is malformed.
+    Invalid(io::Error),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::NotPczt => write!(f, "Not a PCZT"),
+            ParseError::UnknownVersion(version) => {
+                write!(f, "Unsupported PCZT format version {}", version)
+            }
+            ParseError::TrailingBytes => write!(f, "Unexpected trailing bytes after PCZT"),
+            ParseError::Invalid(e) => write!(f, "Invalid PCZT encoding: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Invalid(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> Self {
+        ParseError::Invalid(e)
+    }
+}
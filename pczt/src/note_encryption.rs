@@ -0,0 +1,47 @@
+//! Recovery of output memos from the data in a PCZT.
+
+use chacha20poly1305::{aead::AeadInPlace, ChaCha20Poly1305, KeyInit};
+use zcash_note_encryption::{
+    Domain, EphemeralKeyBytes, COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE, NOTE_PLAINTEXT_SIZE,
+};
+use zcash_protocol::memo::MemoBytes;
+
+/// Decrypts `enc_ciphertext` using the key the sender derived for `note`, and returns the
+/// memo it contains.
+///
+/// Unlike outgoing viewing key recovery, this relies only on the note's `rseed` (which
+/// determines `esk`), so it works regardless of the OVK policy used by the Constructor.
+/// Returns `None` if the ciphertext was not encrypted to `note`.
+pub(crate) fn recover_memo<D: Domain<Memo = [u8; 512]>>(
+    note: &D::Note,
+    ephemeral_key: [u8; 32],
+    enc_ciphertext: &[u8],
+) -> Option<MemoBytes> {
+    if enc_ciphertext.len() != ENC_CIPHERTEXT_SIZE {
+        return None;
+    }
+
+    let esk = D::derive_esk(note)?;
+    let shared_secret = D::ka_agree_enc(&esk, &D::get_pk_d(note));
+    let key = D::kdf(shared_secret, &EphemeralKeyBytes(ephemeral_key));
+
+    let mut plaintext: [u8; NOTE_PLAINTEXT_SIZE] =
+        enc_ciphertext[..NOTE_PLAINTEXT_SIZE].try_into().unwrap();
+    ChaCha20Poly1305::new(key.as_ref().into())
+        .decrypt_in_place_detached(
+            [0u8; 12][..].into(),
+            &[],
+            &mut plaintext,
+            enc_ciphertext[NOTE_PLAINTEXT_SIZE..].into(),
+        )
+        .ok()?;
+
+    // The note part of the plaintext must be the note we expect; only the memo is
+    // unknown to us.
+    let expected = D::note_plaintext_bytes(note, &[0; 512]);
+    if plaintext[..COMPACT_NOTE_SIZE] != expected.0[..COMPACT_NOTE_SIZE] {
+        return None;
+    }
+
+    MemoBytes::from_bytes(&plaintext[COMPACT_NOTE_SIZE..]).ok()
+}
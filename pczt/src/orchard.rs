@@ -0,0 +1,682 @@
+//! The Orchard fields of a PCZT.
+
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use ff::PrimeField;
+use nonempty::NonEmpty;
+use orchard::{
+    note::{ExtractedNoteCommitment, Nullifier, RandomSeed, Rho, TransmittedNoteCiphertext},
+    note_encryption::OrchardDomain,
+    primitives::redpallas,
+    value::{NoteValue, ValueCommitTrapdoor, ValueCommitment},
+};
+use zcash_encoding::Vector;
+use zcash_protocol::memo::MemoBytes;
+
+use crate::encoding::{
+    invalid_data, merge_optional, read_array, read_bytes, read_optional_array, read_optional_bytes,
+    read_optional_string, read_optional_u64, read_optional_witness, write_bytes,
+    write_optional_array, write_optional_bytes, write_optional_string, write_optional_u64,
+    write_optional_witness,
+};
+use crate::note_encryption::recover_memo;
+use crate::roles::Error;
+
+/// PCZT fields that are specific to producing the transaction's Orchard bundle (if any).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bundle {
+    /// The Orchard actions in this bundle.
+    ///
+    /// Entries are added by the Constructor, and modified by a Prover, Signer, or
+    /// Combiner.
+    pub(crate) actions: Vec<Action>,
+
+    /// The flags for the Orchard bundle.
+    ///
+    /// Contains:
+    /// - `enableSpendsOrchard` flag (bit 0)
+    /// - `enableOutputsOrchard` flag (bit 1)
+    /// - Reserved, zeros (bits 2..=7)
+    ///
+    /// This is set by the Creator. The Constructor MUST only add spends and outputs that
+    /// are consistent with these flags (i.e. are dummies as appropriate).
+    pub(crate) flags: u8,
+
+    /// The net value of Orchard spends minus outputs.
+    ///
+    /// This is initialized by the Creator, and updated by the Constructor as spends or
+    /// outputs are added to the PCZT. It enables per-spend and per-output values to be
+    /// redacted from the PCZT after they are no longer necessary.
+    pub(crate) value_sum: i64,
+
+    /// The Orchard anchor for this transaction.
+    ///
+    /// Set by the Creator.
+    pub(crate) anchor: [u8; 32],
+
+    /// The Orchard bundle proof.
+    ///
+    /// This is `None` until it is set by the Prover.
+    pub(crate) zkproof: Option<Vec<u8>>,
+
+    /// The Orchard binding signature signing key.
+    ///
+    /// - This is `None` until it is set by the Constructor.
+    /// - The Transaction Extractor uses this to produce the binding signature.
+    pub(crate) bsk: Option<[u8; 32]>,
+}
+
+/// Information about an Orchard action within a transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Action {
+    //
+    // Action effecting data.
+    //
+    // These are required fields that are part of the final transaction, and are filled in
+    // by the Constructor when laying out the bundle.
+    //
+    pub(crate) cv_net: [u8; 32],
+    pub(crate) spend: Spend,
+    pub(crate) output: Output,
+
+    /// The value commitment randomness.
+    ///
+    /// - This is set by the Constructor.
+    /// - The Constructor folds it into the `bsk`.
+    /// - This is required by the Prover.
+    pub(crate) rcv: Option<[u8; 32]>,
+}
+
+/// Information about the spend part of an Orchard action.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Spend {
+    //
+    // Spend-specific Orchard effecting data.
+    //
+    // These are required fields that are part of the final transaction, and are filled in
+    // by the Constructor when adding a spend.
+    //
+    pub(crate) nullifier: [u8; 32],
+    pub(crate) rk: [u8; 32],
+
+    /// The spend authorization signature.
+    ///
+    /// This is set by the Signer.
+    pub(crate) spend_auth_sig: Option<[u8; 64]>,
+
+    /// The address that received the note being spent.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) recipient: Option<[u8; 43]>,
+
+    /// The value of the input being spent.
+    ///
+    /// - This is required by the Prover.
+    /// - This may be used by Signers to verify that the value correctly matches `cv`.
+    ///
+    /// This exposes the input value to all participants. For Signers who don't need this
+    /// information, or after signatures have been applied, this can be redacted.
+    pub(crate) value: Option<u64>,
+
+    /// The rho value for the note being spent.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) rho: Option<[u8; 32]>,
+
+    /// The seed randomness for the note being spent.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) rseed: Option<[u8; 32]>,
+
+    /// The full viewing key that received the note being spent.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) fvk: Option<[u8; 96]>,
+
+    /// A witness from the note to the bundle's anchor.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) witness: Option<(u32, [[u8; 32]; 32])>,
+
+    /// The spend authorization randomizer.
+    ///
+    /// - This is chosen by the Constructor.
+    /// - This is required by the Signer for creating `spend_auth_sig`, and may be used to
+    ///   validate `rk`.
+    pub(crate) alpha: Option<[u8; 32]>,
+}
+
+/// Information about the output part of an Orchard action.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    //
+    // Output-specific Orchard effecting data.
+    //
+    // These are required fields that are part of the final transaction, and are filled in
+    // by the Constructor when adding an output.
+    //
+    pub(crate) cmx: [u8; 32],
+    pub(crate) ephemeral_key: [u8; 32],
+    /// The encrypted note plaintext for the output.
+    ///
+    /// Encoded as a `Vec<u8>` because its length depends on the transaction version.
+    pub(crate) enc_ciphertext: Vec<u8>,
+    /// The encrypted outgoing plaintext for the output.
+    ///
+    /// Encoded as a `Vec<u8>` because its length depends on the transaction version.
+    pub(crate) out_ciphertext: Vec<u8>,
+
+    /// The address that will receive the output.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) recipient: Option<[u8; 43]>,
+
+    /// The value of the output.
+    ///
+    /// This may be used by Signers to verify that the value matches `cv`, and to confirm
+    /// the values and change involved in the transaction.
+    ///
+    /// This exposes the output value to all participants. For Signers who don't need this
+    /// information, or after proofs have been created, this can be redacted.
+    pub(crate) value: Option<u64>,
+
+    /// The seed randomness for the output.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) rseed: Option<[u8; 32]>,
+
+    /// The user-facing address to which this output is being sent, if any.
+    ///
+    /// - This is set by the Constructor for outputs that pay an address given by the
+    ///   user, and is `None` for change and dummy outputs.
+    /// - This may be used by Signers to display the recipient, and by the wallet that
+    ///   created the transaction to record it.
+    pub(crate) user_address: Option<String>,
+}
+
+impl Bundle {
+    /// Returns the Orchard actions of this bundle.
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    /// Returns the flags for the Orchard bundle.
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Returns the net value of Orchard spends minus outputs.
+    pub fn value_sum(&self) -> i64 {
+        self.value_sum
+    }
+
+    /// Returns the Orchard anchor for this transaction.
+    pub fn anchor(&self) -> [u8; 32] {
+        self.anchor
+    }
+
+    /// Returns `true` if this bundle has a proof.
+    pub fn has_proof(&self) -> bool {
+        self.zkproof.is_some()
+    }
+
+    pub(crate) fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let actions = Vector::read(&mut reader, |r| Action::read(r))?;
+        let flags = reader.read_u8()?;
+        let value_sum = reader.read_i64::<LittleEndian>()?;
+        let anchor = read_array(&mut reader)?;
+        let zkproof = read_optional_bytes(&mut reader)?;
+        let bsk = read_optional_array(&mut reader)?;
+        Ok(Bundle {
+            actions,
+            flags,
+            value_sum,
+            anchor,
+            zkproof,
+            bsk,
+        })
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        Vector::write(&mut writer, &self.actions, |w, action| action.write(w))?;
+        writer.write_u8(self.flags)?;
+        writer.write_i64::<LittleEndian>(self.value_sum)?;
+        writer.write_all(&self.anchor)?;
+        write_optional_bytes(&mut writer, self.zkproof.as_deref())?;
+        write_optional_array(&mut writer, self.bsk.as_ref())
+    }
+
+    /// Merges this bundle with another.
+    ///
+    /// Returns `None` if the bundles have conflicting data.
+    pub(crate) fn merge(mut self, other: Self) -> Option<Self> {
+        let Self {
+            actions,
+            flags,
+            value_sum,
+            anchor,
+            zkproof,
+            bsk,
+        } = other;
+
+        if self.actions.len() != actions.len()
+            || self.flags != flags
+            || self.value_sum != value_sum
+            || self.anchor != anchor
+            || !merge_optional(&mut self.zkproof, zkproof)
+            || !merge_optional(&mut self.bsk, bsk)
+        {
+            return None;
+        }
+
+        for (lhs, rhs) in self.actions.iter_mut().zip(actions) {
+            let Action {
+                cv_net,
+                spend:
+                    Spend {
+                        nullifier,
+                        rk,
+                        spend_auth_sig,
+                        recipient,
+                        value,
+                        rho,
+                        rseed,
+                        fvk,
+                        witness,
+                        alpha,
+                    },
+                output:
+                    Output {
+                        cmx,
+                        ephemeral_key,
+                        enc_ciphertext,
+                        out_ciphertext,
+                        recipient: output_recipient,
+                        value: output_value,
+                        rseed: output_rseed,
+                        user_address,
+                    },
+                rcv,
+            } = rhs;
+
+            if lhs.cv_net != cv_net
+                || lhs.spend.nullifier != nullifier
+                || lhs.spend.rk != rk
+                || lhs.output.cmx != cmx
+                || lhs.output.ephemeral_key != ephemeral_key
+                || lhs.output.enc_ciphertext != enc_ciphertext
+                || lhs.output.out_ciphertext != out_ciphertext
+                || !merge_optional(&mut lhs.spend.spend_auth_sig, spend_auth_sig)
+                || !merge_optional(&mut lhs.spend.recipient, recipient)
+                || !merge_optional(&mut lhs.spend.value, value)
+                || !merge_optional(&mut lhs.spend.rho, rho)
+                || !merge_optional(&mut lhs.spend.rseed, rseed)
+                || !merge_optional(&mut lhs.spend.fvk, fvk)
+                || !merge_optional(&mut lhs.spend.witness, witness)
+                || !merge_optional(&mut lhs.spend.alpha, alpha)
+                || !merge_optional(&mut lhs.output.recipient, output_recipient)
+                || !merge_optional(&mut lhs.output.value, output_value)
+                || !merge_optional(&mut lhs.output.rseed, output_rseed)
+                || !merge_optional(&mut lhs.output.user_address, user_address)
+                || !merge_optional(&mut lhs.rcv, rcv)
+            {
+                return None;
+            }
+        }
+
+        Some(self)
+    }
+
+    pub(crate) fn parsed_flags(&self) -> Result<orchard::bundle::Flags, Error> {
+        orchard::bundle::Flags::from_byte(self.flags).ok_or(Error::InvalidOrchardFlags)
+    }
+
+    pub(crate) fn parsed_anchor(&self) -> Result<orchard::Anchor, Error> {
+        Option::from(orchard::Anchor::from_bytes(self.anchor)).ok_or(Error::InvalidOrchardAnchor)
+    }
+
+    pub(crate) fn parsed_value_balance(&self) -> Result<zcash_protocol::value::ZatBalance, Error> {
+        zcash_protocol::value::ZatBalance::from_i64(self.value_sum).map_err(|_| Error::InvalidValue)
+    }
+
+    /// Returns the Orchard bundle with the given authorization, or `None` if the bundle
+    /// has no actions.
+    pub(crate) fn to_tx_data<A, E, F>(
+        &self,
+        spend_auth: F,
+        bundle_auth: impl FnOnce(&Self) -> Result<A, E>,
+    ) -> Result<Option<orchard::Bundle<A, zcash_protocol::value::ZatBalance>>, E>
+    where
+        A: orchard::bundle::Authorization,
+        E: From<Error>,
+        F: Fn(&Action) -> Result<A::SpendAuth, E>,
+    {
+        let actions = self
+            .actions
+            .iter()
+            .map(|action| {
+                let authorization = spend_auth(action)?;
+                Ok(orchard::Action::from_parts(
+                    action.spend.parsed_nullifier()?,
+                    action.spend.parsed_rk()?,
+                    action.output.parsed_cmx()?,
+                    action.output.to_encrypted_note()?,
+                    action.parsed_cv_net()?,
+                    authorization,
+                ))
+            })
+            .collect::<Result<Vec<_>, E>>()?;
+
+        match NonEmpty::from_vec(actions) {
+            None => Ok(None),
+            Some(actions) => {
+                let flags = self.parsed_flags()?;
+                let value_balance = self.parsed_value_balance()?;
+                let anchor = self.parsed_anchor()?;
+                let authorization = bundle_auth(self)?;
+                Ok(Some(orchard::Bundle::from_parts(
+                    actions,
+                    flags,
+                    value_balance,
+                    anchor,
+                    authorization,
+                )))
+            }
+        }
+    }
+}
+
+impl Action {
+    /// Returns the net value commitment for this action.
+    pub fn cv_net(&self) -> [u8; 32] {
+        self.cv_net
+    }
+
+    /// Returns the spend part of this action.
+    pub fn spend(&self) -> &Spend {
+        &self.spend
+    }
+
+    /// Returns the output part of this action.
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    /// Returns the note created by this action, along with the memo it was encrypted
+    /// with.
+    ///
+    /// This requires the output's recipient, value and `rseed`, and does not require any
+    /// viewing key.
+    pub fn recover_note_and_memo(&self) -> Result<(orchard::Note, MemoBytes), Error> {
+        let note = self.output.parsed_note(&self.spend.nullifier)?;
+        let memo = recover_memo::<OrchardDomain>(
+            &note,
+            self.output.ephemeral_key,
+            &self.output.enc_ciphertext,
+        )
+        .ok_or(Error::InvalidOrchardAction)?;
+        Ok((note, memo))
+    }
+
+    pub(crate) fn parsed_cv_net(&self) -> Result<ValueCommitment, Error> {
+        Option::from(ValueCommitment::from_bytes(&self.cv_net)).ok_or(Error::InvalidOrchardAction)
+    }
+
+    pub(crate) fn parsed_rcv(&self) -> Result<ValueCommitTrapdoor, Error> {
+        Option::from(ValueCommitTrapdoor::from_bytes(
+            self.rcv.ok_or(Error::MissingOrchardActionData("rcv"))?,
+        ))
+        .ok_or(Error::InvalidOrchardAction)
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let cv_net = read_array(&mut reader)?;
+        let spend = Spend::read(&mut reader)?;
+        let output = Output::read(&mut reader)?;
+        let rcv = read_optional_array(&mut reader)?;
+        Ok(Action {
+            cv_net,
+            spend,
+            output,
+            rcv,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.cv_net)?;
+        self.spend.write(&mut writer)?;
+        self.output.write(&mut writer)?;
+        write_optional_array(&mut writer, self.rcv.as_ref())
+    }
+}
+
+impl Spend {
+    /// Returns the nullifier of the note being spent.
+    pub fn nullifier(&self) -> [u8; 32] {
+        self.nullifier
+    }
+
+    /// Returns the randomized verification key for this spend.
+    pub fn rk(&self) -> [u8; 32] {
+        self.rk
+    }
+
+    /// Returns the value of the note being spent, if it has not been redacted.
+    pub fn value(&self) -> Option<u64> {
+        self.value
+    }
+
+    /// Returns `true` if this spend has a spend authorization signature.
+    pub fn has_signature(&self) -> bool {
+        self.spend_auth_sig.is_some()
+    }
+
+    pub(crate) fn parsed_nullifier(&self) -> Result<Nullifier, Error> {
+        Option::from(Nullifier::from_bytes(&self.nullifier)).ok_or(Error::InvalidOrchardAction)
+    }
+
+    pub(crate) fn parsed_rk(
+        &self,
+    ) -> Result<redpallas::VerificationKey<redpallas::SpendAuth>, Error> {
+        redpallas::VerificationKey::try_from(self.rk).map_err(|_| Error::InvalidOrchardAction)
+    }
+
+    pub(crate) fn parsed_alpha(&self) -> Result<pasta_curves::pallas::Scalar, Error> {
+        Option::from(pasta_curves::pallas::Scalar::from_repr(
+            self.alpha.ok_or(Error::MissingOrchardActionData("alpha"))?,
+        ))
+        .ok_or(Error::InvalidOrchardAction)
+    }
+
+    pub(crate) fn parsed_fvk(&self) -> Result<orchard::keys::FullViewingKey, Error> {
+        orchard::keys::FullViewingKey::from_bytes(
+            &self.fvk.ok_or(Error::MissingOrchardActionData("fvk"))?,
+        )
+        .ok_or(Error::InvalidOrchardAction)
+    }
+
+    pub(crate) fn parsed_note(&self) -> Result<orchard::Note, Error> {
+        let recipient = Option::from(orchard::Address::from_raw_address_bytes(
+            &self
+                .recipient
+                .ok_or(Error::MissingOrchardActionData("recipient"))?,
+        ))
+        .ok_or(Error::InvalidOrchardAction)?;
+        let value =
+            NoteValue::from_raw(self.value.ok_or(Error::MissingOrchardActionData("value"))?);
+        let rho = Option::from(Rho::from_bytes(
+            &self.rho.ok_or(Error::MissingOrchardActionData("rho"))?,
+        ))
+        .ok_or(Error::InvalidOrchardAction)?;
+        let rseed = Option::from(RandomSeed::from_bytes(
+            self.rseed.ok_or(Error::MissingOrchardActionData("rseed"))?,
+            &rho,
+        ))
+        .ok_or(Error::InvalidOrchardAction)?;
+        Option::from(orchard::Note::from_parts(recipient, value, rho, rseed))
+            .ok_or(Error::InvalidOrchardAction)
+    }
+
+    pub(crate) fn parsed_witness(&self) -> Result<orchard::tree::MerklePath, Error> {
+        let (position, path) = self
+            .witness
+            .ok_or(Error::MissingOrchardActionData("witness"))?;
+        let auth_path = path
+            .iter()
+            .map(|node| {
+                Option::from(orchard::tree::MerkleHashOrchard::from_bytes(node))
+                    .ok_or(Error::InvalidOrchardAction)
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .try_into()
+            .map_err(|_| Error::InvalidOrchardAction)?;
+        Ok(orchard::tree::MerklePath::from_parts(position, auth_path))
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let nullifier = read_array(&mut reader)?;
+        let rk = read_array(&mut reader)?;
+        let spend_auth_sig = read_optional_array(&mut reader)?;
+        let recipient = read_optional_array(&mut reader)?;
+        let value = read_optional_u64(&mut reader)?;
+        let rho = read_optional_array(&mut reader)?;
+        let rseed = read_optional_array(&mut reader)?;
+        let fvk = read_optional_array(&mut reader)?;
+        let witness = read_optional_witness(&mut reader)?;
+        let alpha = read_optional_array(&mut reader)?;
+        Ok(Spend {
+            nullifier,
+            rk,
+            spend_auth_sig,
+            recipient,
+            value,
+            rho,
+            rseed,
+            fvk,
+            witness,
+            alpha,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.nullifier)?;
+        writer.write_all(&self.rk)?;
+        write_optional_array(&mut writer, self.spend_auth_sig.as_ref())?;
+        write_optional_array(&mut writer, self.recipient.as_ref())?;
+        write_optional_u64(&mut writer, self.value)?;
+        write_optional_array(&mut writer, self.rho.as_ref())?;
+        write_optional_array(&mut writer, self.rseed.as_ref())?;
+        write_optional_array(&mut writer, self.fvk.as_ref())?;
+        write_optional_witness(&mut writer, self.witness.as_ref())?;
+        write_optional_array(&mut writer, self.alpha.as_ref())
+    }
+}
+
+impl Output {
+    /// Returns the extracted note commitment for this output.
+    pub fn cmx(&self) -> [u8; 32] {
+        self.cmx
+    }
+
+    /// Returns the value of this output, if it has not been redacted.
+    pub fn value(&self) -> Option<u64> {
+        self.value
+    }
+
+    /// Returns the user-facing address to which this output is being sent, if known.
+    pub fn user_address(&self) -> Option<&str> {
+        self.user_address.as_deref()
+    }
+
+    pub(crate) fn parsed_cmx(&self) -> Result<ExtractedNoteCommitment, Error> {
+        Option::from(ExtractedNoteCommitment::from_bytes(&self.cmx))
+            .ok_or(Error::InvalidOrchardAction)
+    }
+
+    pub(crate) fn to_encrypted_note(&self) -> Result<TransmittedNoteCiphertext, Error> {
+        Ok(TransmittedNoteCiphertext {
+            epk_bytes: self.ephemeral_key,
+            enc_ciphertext: self
+                .enc_ciphertext
+                .as_slice()
+                .try_into()
+                .map_err(|_| Error::InvalidOrchardAction)?,
+            out_ciphertext: self
+                .out_ciphertext
+                .as_slice()
+                .try_into()
+                .map_err(|_| Error::InvalidOrchardAction)?,
+        })
+    }
+
+    /// Returns the output note, given the nullifier of the note spent in the same action.
+    pub(crate) fn parsed_note(&self, spend_nullifier: &[u8; 32]) -> Result<orchard::Note, Error> {
+        let recipient = Option::from(orchard::Address::from_raw_address_bytes(
+            &self
+                .recipient
+                .ok_or(Error::MissingOrchardActionData("output recipient"))?,
+        ))
+        .ok_or(Error::InvalidOrchardAction)?;
+        let value = NoteValue::from_raw(
+            self.value
+                .ok_or(Error::MissingOrchardActionData("output value"))?,
+        );
+        let rho =
+            Option::from(Rho::from_bytes(spend_nullifier)).ok_or(Error::InvalidOrchardAction)?;
+        let rseed = Option::from(RandomSeed::from_bytes(
+            self.rseed
+                .ok_or(Error::MissingOrchardActionData("output rseed"))?,
+            &rho,
+        ))
+        .ok_or(Error::InvalidOrchardAction)?;
+        Option::from(orchard::Note::from_parts(recipient, value, rho, rseed))
+            .ok_or(Error::InvalidOrchardAction)
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let cmx = read_array(&mut reader)?;
+        let ephemeral_key = read_array(&mut reader)?;
+        let enc_ciphertext = read_bytes(&mut reader)?;
+        let out_ciphertext = read_bytes(&mut reader)?;
+        let recipient = read_optional_array(&mut reader)?;
+        let value = read_optional_u64(&mut reader)?;
+        let rseed = read_optional_array(&mut reader)?;
+        let user_address = read_optional_string(&mut reader)?;
+
+        if enc_ciphertext.len() != zcash_note_encryption::ENC_CIPHERTEXT_SIZE
+            || out_ciphertext.len() != zcash_note_encryption::OUT_CIPHERTEXT_SIZE
+        {
+            return Err(invalid_data("invalid Orchard output ciphertext length"));
+        }
+
+        Ok(Output {
+            cmx,
+            ephemeral_key,
+            enc_ciphertext,
+            out_ciphertext,
+            recipient,
+            value,
+            rseed,
+            user_address,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.cmx)?;
+        writer.write_all(&self.ephemeral_key)?;
+        write_bytes(&mut writer, &self.enc_ciphertext)?;
+        write_bytes(&mut writer, &self.out_ciphertext)?;
+        write_optional_array(&mut writer, self.recipient.as_ref())?;
+        write_optional_u64(&mut writer, self.value)?;
+        write_optional_array(&mut writer, self.rseed.as_ref())?;
+        write_optional_string(&mut writer, self.user_address.as_deref())
+    }
+}
@@ -0,0 +1,592 @@
+//! Implementations of the PCZT roles.
+//!
+//! The roles are intended to be used in the order described in the [crate-level
+//! documentation](crate), although some (such as the Updater, Prover and Signer) may be
+//! performed in parallel on copies of the same PCZT, and the results merged with the
+//! Combiner.
+
+use std::fmt;
+
+pub mod combiner;
+pub mod constructor;
+pub mod creator;
+pub mod prover;
+pub mod signer;
+pub mod spend_finalizer;
+pub mod tx_extractor;
+pub mod updater;
+
+/// Errors that can occur while performing a PCZT role.
+#[derive(Debug)]
+pub enum Error {
+    /// The consensus branch ID is unknown, or is for a network upgrade that does not
+    /// support v5 transactions.
+    UnsupportedConsensusBranch,
+    /// An index was provided that does not correspond to an input, spend, output, or
+    /// action of the PCZT.
+    InvalidIndex,
+    /// A value in the PCZT is out of range.
+    InvalidValue,
+    /// The inputs to the transaction are insufficient to cover its outputs.
+    InsufficientFunds,
+    /// The Constructor was asked to build a PCZT that has already been constructed.
+    AlreadyConstructed,
+    /// A transparent input is being spent from an unsupported address type, or the
+    /// provided public key does not correspond to the coin's address.
+    InvalidTransparentInput,
+    /// A transparent input is missing the signature required to finalize it.
+    MissingTransparentSignature,
+    /// A transparent input has not been finalized.
+    MissingTransparentScriptSig,
+    /// The Sapling anchor is not a valid encoding.
+    InvalidSaplingAnchor,
+    /// A Sapling spend contains invalid data.
+    InvalidSaplingSpend,
+    /// A Sapling output contains invalid data.
+    InvalidSaplingOutput,
+    /// A Sapling scalar field element (such as `rcv` or `alpha`) is not a valid encoding.
+    InvalidSaplingScalar,
+    /// A Sapling spend is missing data that is required by this role.
+    MissingSaplingSpendData(&'static str),
+    /// A Sapling output is missing data that is required by this role.
+    MissingSaplingOutputData(&'static str),
+    /// The Sapling Merkle path for a note does not lead to the bundle's anchor.
+    SaplingAnchorMismatch,
+    /// The Orchard flags are not a valid encoding.
+    InvalidOrchardFlags,
+    /// The Orchard anchor is not a valid encoding.
+    InvalidOrchardAnchor,
+    /// An Orchard action contains invalid data.
+    InvalidOrchardAction,
+    /// An Orchard action is missing data that is required by this role.
+    MissingOrchardActionData(&'static str),
+    /// The Orchard Merkle path for a note does not lead to the bundle's anchor.
+    OrchardAnchorMismatch,
+    /// The Orchard bundle proof could not be created.
+    OrchardProof,
+    /// The Orchard bundle is missing its proof.
+    MissingOrchardProof,
+    /// The binding signature signing key for a shielded bundle is missing.
+    MissingBindingSigningKey,
+    /// The binding signature signing key for a shielded bundle is not valid.
+    InvalidBindingSigningKey,
+    /// The provided spending key does not match the spend being authorized.
+    WrongSpendingKey,
+    /// The provided signature is not valid for the spend being authorized.
+    InvalidSignature,
+    /// The Combiner was given no PCZTs to combine.
+    NoPczts,
+    /// The Combiner was given PCZTs with conflicting data.
+    DataMismatch,
+    /// The transaction could not be encoded.
+    TransactionEncoding(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnsupportedConsensusBranch => write!(
+                f,
+                "The consensus branch is unknown or does not support v5 transactions"
+            ),
+            Error::InvalidIndex => write!(f, "Index out of range for this PCZT"),
+            Error::InvalidValue => write!(f, "Value out of range"),
+            Error::InsufficientFunds => {
+                write!(f, "The transaction's inputs do not cover its outputs")
+            }
+            Error::AlreadyConstructed => write!(f, "The PCZT has already been constructed"),
+            Error::InvalidTransparentInput => write!(
+                f,
+                "Transparent input is not a P2PKH coin spendable by the given public key"
+            ),
+            Error::MissingTransparentSignature => {
+                write!(f, "Transparent input is missing a required signature")
+            }
+            Error::MissingTransparentScriptSig => {
+                write!(f, "Transparent input has not been finalized")
+            }
+            Error::InvalidSaplingAnchor => write!(f, "Invalid Sapling anchor"),
+            Error::InvalidSaplingSpend => write!(f, "Invalid Sapling spend"),
+            Error::InvalidSaplingOutput => write!(f, "Invalid Sapling output"),
+            Error::InvalidSaplingScalar => write!(f, "Invalid Sapling scalar encoding"),
+            Error::MissingSaplingSpendData(field) => {
+                write!(f, "Sapling spend is missing field `{}`", field)
+            }
+            Error::MissingSaplingOutputData(field) => {
+                write!(f, "Sapling output is missing field `{}`", field)
+            }
+            Error::SaplingAnchorMismatch => {
+                write!(f, "Sapling Merkle path does not match the bundle anchor")
+            }
+            Error::InvalidOrchardFlags => write!(f, "Invalid Orchard flags"),
+            Error::InvalidOrchardAnchor => write!(f, "Invalid Orchard anchor"),
+            Error::InvalidOrchardAction => write!(f, "Invalid Orchard action"),
+            Error::MissingOrchardActionData(field) => {
+                write!(f, "Orchard action is missing field `{}`", field)
+            }
+            Error::OrchardAnchorMismatch => {
+                write!(f, "Orchard Merkle path does not match the bundle anchor")
+            }
+            Error::OrchardProof => write!(f, "Failed to create the Orchard proof"),
+            Error::MissingOrchardProof => write!(f, "Orchard bundle is missing its proof"),
+            Error::MissingBindingSigningKey => {
+                write!(f, "Shielded bundle is missing its binding signing key")
+            }
+            Error::InvalidBindingSigningKey => {
+                write!(f, "Shielded bundle has an invalid binding signing key")
+            }
+            Error::WrongSpendingKey => {
+                write!(
+                    f,
+                    "The spending key does not match the spend being authorized"
+                )
+            }
+            Error::InvalidSignature => {
+                write!(
+                    f,
+                    "The signature is not valid for the spend being authorized"
+                )
+            }
+            Error::NoPczts => write!(f, "No PCZTs were provided to combine"),
+            Error::DataMismatch => write!(f, "The PCZTs being combined have conflicting data"),
+            Error::TransactionEncoding(e) => write!(f, "Failed to encode transaction: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::TransactionEncoding(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use incrementalmerkletree::{frontier::CommitmentTree, witness::IncrementalWitness};
+    use orchard::{
+        keys::Scope,
+        note::{RandomSeed, Rho},
+        tree::MerkleHashOrchard,
+    };
+    use rand_core::OsRng;
+    use ripemd::Ripemd160;
+    use sha2::{Digest, Sha256};
+    use zcash_primitives::{
+        legacy::TransparentAddress,
+        transaction::{
+            components::{amount::NonNegativeAmount, transparent::TxOut, OutPoint},
+            Transaction,
+        },
+    };
+    use zcash_protocol::{
+        consensus::{BlockHeight, BranchId},
+        memo::MemoBytes,
+    };
+
+    use super::{
+        combiner::Combiner, constructor::Constructor, creator::Creator, prover::Prover,
+        signer::Signer, spend_finalizer::SpendFinalizer, tx_extractor::TransactionExtractor,
+        updater::Updater, Error,
+    };
+    use crate::{tx_data::Effects, Pczt};
+
+    struct Keys {
+        sapling: sapling::zip32::ExtendedSpendingKey,
+        orchard: orchard::keys::SpendingKey,
+        transparent: secp256k1::SecretKey,
+    }
+
+    impl Keys {
+        fn new() -> Self {
+            Keys {
+                sapling: sapling::zip32::ExtendedSpendingKey::master(&[1; 32]),
+                orchard: orchard::keys::SpendingKey::from_bytes([2; 32]).unwrap(),
+                transparent: secp256k1::SecretKey::from_slice(&[3; 32]).unwrap(),
+            }
+        }
+
+        fn transparent_pubkey(&self) -> secp256k1::PublicKey {
+            secp256k1::PublicKey::from_secret_key(
+                &secp256k1::Secp256k1::signing_only(),
+                &self.transparent,
+            )
+        }
+
+        fn transparent_address(&self) -> TransparentAddress {
+            let hash = Ripemd160::digest(Sha256::digest(self.transparent_pubkey().serialize()));
+            TransparentAddress::PublicKeyHash(hash.into())
+        }
+    }
+
+    /// Constructs a PCZT that spends one coin or note from each pool, and sends funds to
+    /// each pool, paying a fee of 10000 zatoshis.
+    fn construct(keys: &Keys) -> Pczt {
+        // Sapling note and witness.
+        let sapling_fvk = keys.sapling.to_diversifiable_full_viewing_key();
+        let (_, sapling_addr) = sapling_fvk.default_address();
+        let sapling_note = sapling_addr.create_note(
+            sapling::value::NoteValue::from_raw(50_000),
+            sapling::Rseed::AfterZip212([4; 32]),
+        );
+        let mut sapling_tree = sapling::CommitmentTree::empty();
+        sapling_tree
+            .append(sapling::Node::from_cmu(&sapling_note.cmu()))
+            .unwrap();
+        let sapling_witness = sapling::IncrementalWitness::from_tree(sapling_tree);
+
+        // Orchard note and witness.
+        let orchard_fvk = orchard::keys::FullViewingKey::from(&keys.orchard);
+        let orchard_addr = orchard_fvk.address_at(0u32, Scope::External);
+        let rho = Rho::from_bytes(&[0; 32]).unwrap();
+        let orchard_note = orchard::Note::from_parts(
+            orchard_addr,
+            orchard::value::NoteValue::from_raw(60_000),
+            rho,
+            RandomSeed::from_bytes([5; 32], &rho).unwrap(),
+        )
+        .unwrap();
+        let mut orchard_tree = CommitmentTree::<MerkleHashOrchard, 32>::empty();
+        orchard_tree
+            .append(MerkleHashOrchard::from_cmx(
+                &orchard_note.commitment().into(),
+            ))
+            .unwrap();
+        let orchard_witness = IncrementalWitness::from_tree(orchard_tree);
+
+        let pczt = Creator::new(
+            BranchId::Nu5,
+            BlockHeight::from_u32(2_000_000),
+            sapling_witness.root().into(),
+            orchard_witness.root().into(),
+        )
+        .build()
+        .unwrap();
+
+        let mut constructor = Constructor::new(pczt).unwrap();
+        constructor
+            .add_transparent_input(
+                keys.transparent_pubkey(),
+                OutPoint::new([6; 32], 1),
+                TxOut {
+                    value: NonNegativeAmount::const_from_u64(40_000),
+                    script_pubkey: keys.transparent_address().script(),
+                },
+            )
+            .unwrap();
+        constructor
+            .add_sapling_spend(
+                sapling_fvk.fvk(),
+                sapling_note,
+                sapling_witness.path().unwrap(),
+            )
+            .unwrap();
+        constructor
+            .add_orchard_spend(&orchard_fvk, orchard_note, orchard_witness.path().unwrap())
+            .unwrap();
+        constructor
+            .add_transparent_output(
+                &keys.transparent_address(),
+                NonNegativeAmount::const_from_u64(10_000),
+                None,
+            )
+            .unwrap();
+        constructor
+            .add_sapling_output(
+                None,
+                sapling_addr,
+                NonNegativeAmount::const_from_u64(70_000),
+                MemoBytes::from_bytes(b"sapling memo").unwrap(),
+                Some(SAPLING_USER_ADDRESS.to_owned()),
+            )
+            .unwrap();
+        constructor
+            .add_orchard_output(
+                Some(orchard_fvk.to_ovk(Scope::Internal)),
+                orchard_addr,
+                NonNegativeAmount::const_from_u64(60_000),
+                MemoBytes::empty(),
+                None,
+            )
+            .unwrap();
+
+        constructor.build(OsRng).unwrap()
+    }
+
+    /// The user-facing address recorded for the Sapling output created by [`construct`].
+    const SAPLING_USER_ADDRESS: &str = "zs1user";
+
+    /// Returns the index of the Orchard action that spends the real note.
+    fn orchard_spend_index(pczt: &Pczt) -> usize {
+        pczt.orchard
+            .actions
+            .iter()
+            .position(|action| action.spend.value == Some(60_000))
+            .unwrap()
+    }
+
+    #[test]
+    fn construct_serialize_roundtrip() {
+        let pczt = construct(&Keys::new());
+
+        assert_eq!(pczt.transparent.inputs.len(), 1);
+        assert_eq!(pczt.transparent.outputs.len(), 1);
+        assert_eq!(pczt.sapling.spends.len(), 1);
+        assert_eq!(pczt.sapling.outputs.len(), 2);
+        assert_eq!(pczt.orchard.actions.len(), 2);
+        assert_eq!(pczt.sapling.value_sum, -20_000);
+        assert_eq!(pczt.orchard.value_sum, 0);
+
+        assert_eq!(Pczt::parse(&pczt.serialize()).unwrap(), pczt);
+
+        let mut updater = Updater::new(pczt);
+        updater.set_proprietary("test:field".to_owned(), vec![1, 2, 3]);
+        let pczt = updater.finish();
+        assert_eq!(
+            pczt.global().proprietary().get("test:field"),
+            Some(&vec![1, 2, 3])
+        );
+        assert_eq!(Pczt::parse(&pczt.serialize()).unwrap(), pczt);
+    }
+
+    #[test]
+    fn recovers_output_notes_and_memos() {
+        let pczt = construct(&Keys::new());
+
+        // The Sapling output was created without an OVK, but its memo can still be
+        // recovered from the PCZT.
+        let output = pczt
+            .sapling()
+            .outputs()
+            .iter()
+            .find(|output| output.value() == Some(70_000))
+            .unwrap();
+        assert_eq!(output.user_address(), Some(SAPLING_USER_ADDRESS));
+        let (note, memo) = output.recover_note_and_memo().unwrap();
+        assert_eq!(note.value().inner(), 70_000);
+        assert_eq!(memo, MemoBytes::from_bytes(b"sapling memo").unwrap());
+
+        let action = pczt
+            .orchard()
+            .actions()
+            .iter()
+            .find(|action| action.output().value() == Some(60_000))
+            .unwrap();
+        assert_eq!(action.output().user_address(), None);
+        let (note, memo) = action.recover_note_and_memo().unwrap();
+        assert_eq!(note.value().inner(), 60_000);
+        assert_eq!(memo, MemoBytes::empty());
+
+        assert_eq!(pczt.transparent().outputs()[0].user_address(), None);
+    }
+
+    #[test]
+    fn constructor_rejects_insufficient_funds() {
+        let keys = Keys::new();
+        let pczt = Creator::new(
+            BranchId::Nu5,
+            BlockHeight::from_u32(2_000_000),
+            sapling::Anchor::empty_tree(),
+            orchard::Anchor::empty_tree(),
+        )
+        .build()
+        .unwrap();
+
+        let mut constructor = Constructor::new(pczt).unwrap();
+        constructor
+            .add_transparent_output(
+                &keys.transparent_address(),
+                NonNegativeAmount::const_from_u64(10_000),
+                None,
+            )
+            .unwrap();
+        assert!(matches!(
+            constructor.build(OsRng),
+            Err(Error::InsufficientFunds)
+        ));
+    }
+
+    #[test]
+    fn signer_rejects_wrong_keys() {
+        let keys = Keys::new();
+        let pczt = construct(&keys);
+        let orchard_index = orchard_spend_index(&pczt);
+
+        let other = sapling::zip32::ExtendedSpendingKey::master(&[9; 32]);
+        let mut signer = Signer::new(pczt).unwrap();
+        assert!(matches!(
+            signer.sign_transparent(0, &secp256k1::SecretKey::from_slice(&[9; 32]).unwrap()),
+            Err(Error::WrongSpendingKey)
+        ));
+        assert!(matches!(
+            signer.sign_sapling(0, &other.expsk.ask, OsRng),
+            Err(Error::WrongSpendingKey)
+        ));
+        assert!(matches!(
+            signer.sign_orchard(
+                orchard_index,
+                &orchard::keys::SpendAuthorizingKey::from(
+                    &orchard::keys::SpendingKey::from_bytes([9; 32]).unwrap()
+                ),
+                OsRng,
+            ),
+            Err(Error::WrongSpendingKey)
+        ));
+    }
+
+    #[test]
+    fn signer_appends_external_signatures() {
+        let keys = Keys::new();
+        let pczt = construct(&keys);
+        let orchard_index = orchard_spend_index(&pczt);
+
+        let mut signer = Signer::new(pczt).unwrap();
+        let sighash = signer.shielded_sighash();
+
+        // Signatures over the wrong message are rejected.
+        let sapling_rsk = keys
+            .sapling
+            .expsk
+            .ask
+            .randomize(&signer.sapling_alpha(0).unwrap());
+        assert!(matches!(
+            signer.append_sapling_signature(0, sapling_rsk.sign(OsRng, &[0; 32])),
+            Err(Error::InvalidSignature)
+        ));
+        signer
+            .append_sapling_signature(0, sapling_rsk.sign(OsRng, &sighash))
+            .unwrap();
+
+        let orchard_rsk = orchard::keys::SpendAuthorizingKey::from(&keys.orchard)
+            .randomize(&signer.orchard_alpha(orchard_index).unwrap());
+        assert!(matches!(
+            signer.append_orchard_signature(orchard_index, orchard_rsk.sign(OsRng, &[0; 32])),
+            Err(Error::InvalidSignature)
+        ));
+        signer
+            .append_orchard_signature(orchard_index, orchard_rsk.sign(OsRng, &sighash))
+            .unwrap();
+
+        let secp = secp256k1::Secp256k1::signing_only();
+        let msg = secp256k1::Message::from_slice(&signer.transparent_sighash(0).unwrap()).unwrap();
+        let wrong_msg = secp256k1::Message::from_slice(&[1; 32]).unwrap();
+        assert!(matches!(
+            signer.append_transparent_signature(
+                0,
+                &keys.transparent_pubkey(),
+                &secp.sign_ecdsa(&wrong_msg, &keys.transparent),
+            ),
+            Err(Error::InvalidSignature)
+        ));
+        signer
+            .append_transparent_signature(
+                0,
+                &keys.transparent_pubkey(),
+                &secp.sign_ecdsa(&msg, &keys.transparent),
+            )
+            .unwrap();
+
+        let pczt = signer.finish();
+        assert!(pczt.sapling().spends()[0].has_signature());
+        assert!(pczt.orchard().actions()[orchard_index]
+            .spend()
+            .has_signature());
+        assert_eq!(pczt.transparent().inputs()[0].partial_signatures().len(), 1);
+    }
+
+    #[test]
+    fn combiner_rejects_mismatched_pczts() {
+        let keys = Keys::new();
+        assert!(matches!(
+            Combiner::new(vec![]).combine(),
+            Err(Error::NoPczts)
+        ));
+        assert!(matches!(
+            Combiner::new(vec![construct(&keys), construct(&keys)]).combine(),
+            Err(Error::DataMismatch)
+        ));
+    }
+
+    #[test]
+    fn end_to_end() {
+        let keys = Keys::new();
+        let pczt = construct(&keys);
+        let orchard_index = orchard_spend_index(&pczt);
+        let sighash = Effects::new(&pczt).unwrap().shielded_sighash();
+
+        // Prove and sign in parallel, on separately-serialized copies of the PCZT.
+        let prover_pczt = {
+            let mut updater = Updater::new(Pczt::parse(&pczt.serialize()).unwrap());
+            updater
+                .set_sapling_proof_generation_key(0, keys.sapling.expsk.proof_generation_key())
+                .unwrap();
+
+            let mut prover = Prover::new(updater.finish());
+            assert!(!prover.has_sapling_proofs());
+            assert!(!prover.has_orchard_proof());
+            prover
+                .create_sapling_proofs(
+                    &sapling::prover::mock::MockSpendProver,
+                    &sapling::prover::mock::MockOutputProver,
+                    OsRng,
+                )
+                .unwrap();
+            prover
+                .create_orchard_proof(&orchard::circuit::ProvingKey::build(), OsRng)
+                .unwrap();
+            assert!(prover.has_sapling_proofs());
+            assert!(prover.has_orchard_proof());
+            prover.finish()
+        };
+
+        let signer_pczt = {
+            let mut signer = Signer::new(Pczt::parse(&pczt.serialize()).unwrap()).unwrap();
+            assert_eq!(signer.shielded_sighash(), sighash);
+            signer.sign_transparent(0, &keys.transparent).unwrap();
+            signer
+                .sign_sapling(0, &keys.sapling.expsk.ask, OsRng)
+                .unwrap();
+            signer
+                .sign_orchard(
+                    orchard_index,
+                    &orchard::keys::SpendAuthorizingKey::from(&keys.orchard),
+                    OsRng,
+                )
+                .unwrap();
+            signer.finish()
+        };
+
+        // The transaction can't be extracted until the transparent spend is finalized.
+        let combined = Combiner::new(vec![prover_pczt, signer_pczt])
+            .combine()
+            .unwrap();
+        assert!(matches!(
+            TransactionExtractor::new(combined.clone()).extract(OsRng),
+            Err(Error::MissingTransparentScriptSig)
+        ));
+
+        let finalized = SpendFinalizer::new(combined).finalize_spends().unwrap();
+        assert!(finalized.transparent.inputs[0]
+            .partial_signatures
+            .is_empty());
+        let tx = TransactionExtractor::new(finalized).extract(OsRng).unwrap();
+
+        // The Orchard bundle is fully valid, which also checks that the extracted
+        // transaction commits to the same effects as the PCZT.
+        let mut validator = orchard::bundle::BatchValidator::new();
+        validator.add_bundle(tx.orchard_bundle().unwrap(), sighash);
+        assert!(validator.validate(&orchard::circuit::VerifyingKey::build(), OsRng));
+
+        // The Sapling spend authorization signature is valid (the proofs are mocked).
+        let spend = &tx.sapling_bundle().unwrap().shielded_spends()[0];
+        assert!(spend.rk().verify(&sighash, spend.spend_auth_sig()).is_ok());
+
+        // The transaction round-trips through its encoding.
+        let mut tx_bytes = vec![];
+        tx.write(&mut tx_bytes).unwrap();
+        let parsed = Transaction::read(&tx_bytes[..], BranchId::Nu5).unwrap();
+        assert_eq!(parsed.txid(), tx.txid());
+    }
+}
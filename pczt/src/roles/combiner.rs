@@ -0,0 +1,50 @@
+//! The Combiner role (anyone can execute).
+//!
+//! - Combines several PCZTs that represent the same transaction into a single PCZT.
+
+use super::Error;
+use crate::Pczt;
+
+/// A Combiner for PCZTs.
+pub struct Combiner {
+    pczts: Vec<Pczt>,
+}
+
+impl Combiner {
+    /// Instantiates the Combiner role with the given PCZTs.
+    pub fn new(pczts: Vec<Pczt>) -> Self {
+        Self { pczts }
+    }
+
+    /// Combines the PCZTs.
+    ///
+    /// Returns an error if no PCZTs were provided, or if the PCZTs do not represent the
+    /// same transaction, or contain conflicting values for any field.
+    pub fn combine(self) -> Result<Pczt, Error> {
+        self.pczts
+            .into_iter()
+            .try_fold(None, |acc, pczt| match acc {
+                None => Ok(Some(pczt)),
+                Some(acc) => merge(acc, pczt).map(Some),
+            })
+            .transpose()
+            .unwrap_or(Err(Error::NoPczts))
+    }
+}
+
+fn merge(lhs: Pczt, rhs: Pczt) -> Result<Pczt, Error> {
+    let global = lhs.global.merge(rhs.global).ok_or(Error::DataMismatch)?;
+    let transparent = lhs
+        .transparent
+        .merge(rhs.transparent)
+        .ok_or(Error::DataMismatch)?;
+    let sapling = lhs.sapling.merge(rhs.sapling).ok_or(Error::DataMismatch)?;
+    let orchard = lhs.orchard.merge(rhs.orchard).ok_or(Error::DataMismatch)?;
+
+    Ok(Pczt {
+        global,
+        transparent,
+        sapling,
+        orchard,
+    })
+}
@@ -0,0 +1,608 @@
+//! The Constructor role (anyone can contribute).
+//!
+//! - Adds spends and outputs to the PCZT.
+//! - Lays out the transparent, Sapling and Orchard bundles, including any dummy spends
+//!   and outputs that are required, and shuffles them.
+//! - Computes the binding signature signing keys, and signs any Orchard dummy spends.
+//!
+//! Once [`Constructor::build`] has been called, the effecting data of the transaction is
+//! fixed, and the PCZT can be passed to the other roles.
+
+use ff::{Field, PrimeField};
+use orchard::{
+    keys::Scope,
+    note::{ExtractedNoteCommitment, RandomSeed, Rho},
+    note_encryption::{OrchardDomain, OrchardNoteEncryption},
+    tree::MerkleHashOrchard,
+    value::NoteValue as OrchardNoteValue,
+};
+use pasta_curves::pallas;
+use rand::seq::SliceRandom;
+use rand_core::{CryptoRng, RngCore};
+use ripemd::Ripemd160;
+use sapling::{
+    note_encryption::{sapling_note_encryption, SaplingDomain},
+    value::{NoteValue as SaplingNoteValue, ValueCommitTrapdoor, ValueCommitment},
+};
+use sha2::{Digest, Sha256};
+use zcash_note_encryption::Domain;
+use zcash_primitives::{
+    legacy::TransparentAddress,
+    transaction::{
+        components::{
+            amount::NonNegativeAmount,
+            transparent::{OutPoint, TxOut},
+        },
+        sighash::SIGHASH_ALL,
+    },
+};
+use zcash_protocol::memo::MemoBytes;
+
+use super::Error;
+use crate::{
+    orchard as pczt_orchard, sapling as pczt_sapling, transparent, tx_data::Effects, Pczt,
+};
+
+/// The sequence number used for transparent inputs.
+const DEFAULT_SEQUENCE: u32 = u32::MAX;
+
+struct SaplingSpendInfo {
+    fvk: sapling::keys::FullViewingKey,
+    note: sapling::Note,
+    merkle_path: sapling::MerklePath,
+}
+
+struct SaplingOutputInfo {
+    ovk: Option<sapling::keys::OutgoingViewingKey>,
+    to: sapling::PaymentAddress,
+    value: SaplingNoteValue,
+    memo: [u8; 512],
+    user_address: Option<String>,
+}
+
+struct OrchardSpendInfo {
+    fvk: orchard::keys::FullViewingKey,
+    note: orchard::Note,
+    position: u32,
+    auth_path: [MerkleHashOrchard; 32],
+    /// The spending key for a dummy spend, which the Constructor signs itself.
+    dummy_sk: Option<orchard::keys::SpendingKey>,
+}
+
+struct OrchardOutputInfo {
+    ovk: Option<orchard::keys::OutgoingViewingKey>,
+    recipient: orchard::Address,
+    value: OrchardNoteValue,
+    memo: [u8; 512],
+    user_address: Option<String>,
+}
+
+/// A Constructor for a PCZT.
+pub struct Constructor {
+    pczt: Pczt,
+    transparent_inputs: Vec<transparent::Input>,
+    transparent_outputs: Vec<transparent::Output>,
+    sapling_spends: Vec<SaplingSpendInfo>,
+    sapling_outputs: Vec<SaplingOutputInfo>,
+    orchard_spends: Vec<OrchardSpendInfo>,
+    orchard_outputs: Vec<OrchardOutputInfo>,
+}
+
+impl Constructor {
+    /// Instantiates the Constructor role with the given PCZT.
+    ///
+    /// Returns an error if the PCZT already contains any spends or outputs.
+    pub fn new(pczt: Pczt) -> Result<Self, Error> {
+        if !(pczt.transparent.inputs.is_empty()
+            && pczt.transparent.outputs.is_empty()
+            && pczt.sapling.spends.is_empty()
+            && pczt.sapling.outputs.is_empty()
+            && pczt.orchard.actions.is_empty())
+        {
+            return Err(Error::AlreadyConstructed);
+        }
+
+        Ok(Self {
+            pczt,
+            transparent_inputs: vec![],
+            transparent_outputs: vec![],
+            sapling_spends: vec![],
+            sapling_outputs: vec![],
+            orchard_spends: vec![],
+            orchard_outputs: vec![],
+        })
+    }
+
+    /// Adds a transparent P2PKH coin to be spent in this transaction.
+    ///
+    /// `pubkey` must be the public key that `coin` is locked to.
+    pub fn add_transparent_input(
+        &mut self,
+        pubkey: secp256k1::PublicKey,
+        utxo: OutPoint,
+        coin: TxOut,
+    ) -> Result<(), Error> {
+        match coin.recipient_address() {
+            Some(TransparentAddress::PublicKeyHash(hash))
+                if hash[..] == Ripemd160::digest(Sha256::digest(pubkey.serialize()))[..] => {}
+            _ => return Err(Error::InvalidTransparentInput),
+        }
+
+        self.transparent_inputs.push(transparent::Input {
+            prevout_txid: *utxo.hash(),
+            prevout_index: utxo.n(),
+            sequence: DEFAULT_SEQUENCE,
+            value: coin.value.into_u64(),
+            script_pubkey: coin.script_pubkey.0,
+            sighash_type: SIGHASH_ALL,
+            script_sig: None,
+            partial_signatures: Default::default(),
+        });
+
+        Ok(())
+    }
+
+    /// Adds a transparent output to this transaction.
+    ///
+    /// `user_address` is the encoding of the address that the user asked to pay, if any,
+    /// which is recorded in the PCZT alongside the output.
+    pub fn add_transparent_output(
+        &mut self,
+        to: &TransparentAddress,
+        value: NonNegativeAmount,
+        user_address: Option<String>,
+    ) -> Result<(), Error> {
+        self.transparent_outputs.push(transparent::Output {
+            value: value.into_u64(),
+            script_pubkey: to.script().0,
+            user_address,
+        });
+        Ok(())
+    }
+
+    /// Adds a Sapling note to be spent in this transaction.
+    ///
+    /// Returns an error if the given Merkle path does not have the same anchor as the
+    /// PCZT.
+    pub fn add_sapling_spend(
+        &mut self,
+        fvk: &sapling::keys::FullViewingKey,
+        note: sapling::Note,
+        merkle_path: sapling::MerklePath,
+    ) -> Result<(), Error> {
+        let node = sapling::Node::from_cmu(&note.cmu());
+        if sapling::Anchor::from(merkle_path.root(node)).to_bytes() != self.pczt.sapling.anchor {
+            return Err(Error::SaplingAnchorMismatch);
+        }
+
+        self.sapling_spends.push(SaplingSpendInfo {
+            fvk: fvk.clone(),
+            note,
+            merkle_path,
+        });
+
+        Ok(())
+    }
+
+    /// Adds a Sapling address to send funds to.
+    ///
+    /// `user_address` is the encoding of the address that the user asked to pay, if any,
+    /// which is recorded in the PCZT alongside the output.
+    pub fn add_sapling_output(
+        &mut self,
+        ovk: Option<sapling::keys::OutgoingViewingKey>,
+        to: sapling::PaymentAddress,
+        value: NonNegativeAmount,
+        memo: MemoBytes,
+        user_address: Option<String>,
+    ) -> Result<(), Error> {
+        self.sapling_outputs.push(SaplingOutputInfo {
+            ovk,
+            to,
+            value: SaplingNoteValue::from_raw(value.into_u64()),
+            memo: *memo.as_array(),
+            user_address,
+        });
+        Ok(())
+    }
+
+    /// Adds an Orchard note to be spent in this transaction.
+    ///
+    /// Returns an error if the given Merkle path does not have the same anchor as the
+    /// PCZT.
+    pub fn add_orchard_spend(
+        &mut self,
+        fvk: &orchard::keys::FullViewingKey,
+        note: orchard::Note,
+        merkle_path: incrementalmerkletree::MerklePath<MerkleHashOrchard, 32>,
+    ) -> Result<(), Error> {
+        let position =
+            u32::try_from(u64::from(merkle_path.position())).map_err(|_| Error::InvalidIndex)?;
+        let auth_path: [MerkleHashOrchard; 32] = merkle_path
+            .path_elems()
+            .try_into()
+            .expect("Merkle path has the correct depth");
+
+        let cmx = ExtractedNoteCommitment::from(note.commitment());
+        if orchard::tree::MerklePath::from_parts(position, auth_path)
+            .root(cmx)
+            .to_bytes()
+            != self.pczt.orchard.anchor
+        {
+            return Err(Error::OrchardAnchorMismatch);
+        }
+
+        self.orchard_spends.push(OrchardSpendInfo {
+            fvk: fvk.clone(),
+            note,
+            position,
+            auth_path,
+            dummy_sk: None,
+        });
+
+        Ok(())
+    }
+
+    /// Adds an Orchard address to send funds to.
+    ///
+    /// `user_address` is the encoding of the address that the user asked to pay, if any,
+    /// which is recorded in the PCZT alongside the output.
+    pub fn add_orchard_output(
+        &mut self,
+        ovk: Option<orchard::keys::OutgoingViewingKey>,
+        recipient: orchard::Address,
+        value: NonNegativeAmount,
+        memo: MemoBytes,
+        user_address: Option<String>,
+    ) -> Result<(), Error> {
+        self.orchard_outputs.push(OrchardOutputInfo {
+            ovk,
+            recipient,
+            value: OrchardNoteValue::from_raw(value.into_u64()),
+            memo: *memo.as_array(),
+            user_address,
+        });
+        Ok(())
+    }
+
+    /// Lays out the transaction and returns the constructed PCZT.
+    ///
+    /// Returns an error if the transaction's inputs do not cover its outputs. Any excess
+    /// value is paid as the transaction fee.
+    pub fn build<R: RngCore + CryptoRng>(self, mut rng: R) -> Result<Pczt, Error> {
+        let Self {
+            mut pczt,
+            transparent_inputs,
+            transparent_outputs,
+            sapling_spends,
+            sapling_outputs,
+            orchard_spends,
+            orchard_outputs,
+        } = self;
+
+        pczt.transparent.inputs = transparent_inputs;
+        pczt.transparent.outputs = transparent_outputs;
+
+        build_sapling(&mut pczt.sapling, sapling_spends, sapling_outputs, &mut rng)?;
+        let dummy_spends =
+            build_orchard(&mut pczt.orchard, orchard_spends, orchard_outputs, &mut rng)?;
+
+        // The transaction fee is implied by the value balance, and must be non-negative.
+        let transparent_in = pczt
+            .transparent
+            .inputs
+            .iter()
+            .try_fold(0i128, |acc, input| {
+                Ok::<_, Error>(acc + i128::from(input.coin()?.value.into_u64()))
+            })?;
+        let transparent_out = pczt
+            .transparent
+            .outputs
+            .iter()
+            .try_fold(0i128, |acc, output| {
+                Ok::<_, Error>(acc + i128::from(output.to_txout()?.value.into_u64()))
+            })?;
+        let fee = transparent_in - transparent_out
+            + i128::from(pczt.sapling.value_sum)
+            + i128::from(pczt.orchard.value_sum);
+        if fee < 0 {
+            return Err(Error::InsufficientFunds);
+        }
+
+        // Now that the effecting data is fixed, sign the Orchard dummy spends.
+        if !dummy_spends.is_empty() {
+            let sighash = Effects::new(&pczt)?.shielded_sighash();
+            for (index, sk) in dummy_spends {
+                let action = &mut pczt.orchard.actions[index];
+                let alpha = action.spend.parsed_alpha()?;
+                let ask = orchard::keys::SpendAuthorizingKey::from(&sk);
+                let sig = ask.randomize(&alpha).sign(&mut rng, &sighash);
+                action.spend.spend_auth_sig = Some((&sig).into());
+            }
+        }
+
+        Ok(pczt)
+    }
+}
+
+fn build_sapling<R: RngCore + CryptoRng>(
+    bundle: &mut pczt_sapling::Bundle,
+    spends: Vec<SaplingSpendInfo>,
+    mut outputs: Vec<SaplingOutputInfo>,
+    mut rng: R,
+) -> Result<(), Error> {
+    let bundle_type = sapling::builder::BundleType::DEFAULT;
+    let num_outputs = bundle_type
+        .num_outputs(spends.len(), outputs.len())
+        .map_err(|_| Error::InvalidSaplingOutput)?;
+
+    // Pad the outputs with dummy outputs, and shuffle everything for indistinguishability.
+    while outputs.len() < num_outputs {
+        outputs.push(SaplingOutputInfo {
+            ovk: None,
+            to: sapling::builder::OutputInfo::dummy(&mut rng).recipient(),
+            value: SaplingNoteValue::ZERO,
+            memo: *MemoBytes::empty().as_array(),
+            user_address: None,
+        });
+    }
+    let mut spends = spends;
+    spends.shuffle(&mut rng);
+    outputs.shuffle(&mut rng);
+
+    let mut value_sum = 0i128;
+    let mut bsk = jubjub::Scalar::zero();
+
+    for spend in spends {
+        let alpha = jubjub::Scalar::random(&mut rng);
+        let rk = spend.fvk.vk.ak.randomize(&alpha);
+        let rcv = ValueCommitTrapdoor::random(&mut rng);
+        let cv = ValueCommitment::derive(spend.note.value(), rcv.clone());
+        let position = u64::from(spend.merkle_path.position());
+        let nullifier = spend.note.nf(&spend.fvk.vk.nk, position);
+
+        let mut path = [[0; 32]; 32];
+        for (node, elem) in path.iter_mut().zip(spend.merkle_path.path_elems()) {
+            *node = elem.to_bytes();
+        }
+
+        value_sum += i128::from(spend.note.value().inner());
+        bsk += rcv.inner();
+
+        bundle.spends.push(pczt_sapling::Spend {
+            cv: cv.to_bytes(),
+            nullifier: nullifier.0,
+            rk: rk.into(),
+            zkproof: None,
+            spend_auth_sig: None,
+            recipient: Some(spend.note.recipient().to_bytes()),
+            value: Some(spend.note.value().inner()),
+            rseed: Some(pczt_sapling::Rseed::from_note(&spend.note)),
+            rcv: Some(rcv.inner().to_repr()),
+            proof_generation_key: None,
+            witness: Some((
+                u32::try_from(position).map_err(|_| Error::InvalidSaplingSpend)?,
+                path,
+            )),
+            alpha: Some(alpha.to_repr()),
+        });
+    }
+
+    for output in outputs {
+        let mut rseed = [0; 32];
+        rng.fill_bytes(&mut rseed);
+        let note =
+            sapling::Note::from_parts(output.to, output.value, sapling::Rseed::AfterZip212(rseed));
+
+        let encryptor = sapling_note_encryption(output.ovk, note.clone(), output.memo, &mut rng);
+        let rcv = ValueCommitTrapdoor::random(&mut rng);
+        let cv = ValueCommitment::derive(note.value(), rcv.clone());
+        let cmu = note.cmu();
+        let enc_ciphertext = encryptor.encrypt_note_plaintext();
+        let out_ciphertext = encryptor.encrypt_outgoing_plaintext(&cv, &cmu, &mut rng);
+
+        value_sum -= i128::from(note.value().inner());
+        bsk -= rcv.inner();
+
+        bundle.outputs.push(pczt_sapling::Output {
+            cv: cv.to_bytes(),
+            cmu: cmu.to_bytes(),
+            ephemeral_key: SaplingDomain::epk_bytes(encryptor.epk()).0,
+            enc_ciphertext: enc_ciphertext.to_vec(),
+            out_ciphertext: out_ciphertext.to_vec(),
+            zkproof: None,
+            recipient: Some(note.recipient().to_bytes()),
+            value: Some(note.value().inner()),
+            rseed: Some(rseed),
+            rcv: Some(rcv.inner().to_repr()),
+            user_address: output.user_address,
+        });
+    }
+
+    bundle.value_sum = i64::try_from(value_sum).map_err(|_| Error::InvalidValue)?;
+    if !(bundle.spends.is_empty() && bundle.outputs.is_empty()) {
+        bundle.bsk = Some(bsk.to_repr());
+    }
+
+    Ok(())
+}
+
+/// Lays out the Orchard bundle, returning the indices and spending keys of the dummy
+/// spends that need to be signed.
+fn build_orchard<R: RngCore + CryptoRng>(
+    bundle: &mut pczt_orchard::Bundle,
+    mut spends: Vec<OrchardSpendInfo>,
+    mut outputs: Vec<OrchardOutputInfo>,
+    mut rng: R,
+) -> Result<Vec<(usize, orchard::keys::SpendingKey)>, Error> {
+    let flags = bundle.parsed_flags()?;
+    if (!flags.spends_enabled() && !spends.is_empty())
+        || (!flags.outputs_enabled() && !outputs.is_empty())
+    {
+        return Err(Error::InvalidOrchardFlags);
+    }
+
+    let num_actions = orchard::builder::BundleType::DEFAULT
+        .num_actions(spends.len(), outputs.len())
+        .map_err(|_| Error::InvalidOrchardAction)?;
+
+    // Pad the spends and outputs with dummies, and shuffle everything for
+    // indistinguishability.
+    while spends.len() < num_actions {
+        spends.push(dummy_orchard_spend(&mut rng));
+    }
+    while outputs.len() < num_actions {
+        let fvk = orchard::keys::FullViewingKey::from(&random_orchard_sk(&mut rng));
+        outputs.push(OrchardOutputInfo {
+            ovk: None,
+            recipient: fvk.address_at(0u32, Scope::External),
+            value: OrchardNoteValue::from_raw(0),
+            memo: *MemoBytes::empty().as_array(),
+            user_address: None,
+        });
+    }
+    spends.shuffle(&mut rng);
+    outputs.shuffle(&mut rng);
+
+    let mut value_sum = 0i128;
+    let mut bsk = pallas::Scalar::zero();
+    let mut dummy_spends = vec![];
+
+    for (index, (spend, output)) in spends.into_iter().zip(outputs).enumerate() {
+        let nullifier = spend.note.nullifier(&spend.fvk);
+        let alpha = pallas::Scalar::random(&mut rng);
+        let ak = orchard::keys::SpendValidatingKey::from(spend.fvk.clone());
+        let rk = ak.randomize(&alpha);
+
+        let rho = Option::from(Rho::from_bytes(&nullifier.to_bytes()))
+            .expect("nullifiers are valid rho values");
+        let (output_note, output_rseed) = loop {
+            let mut rseed = [0; 32];
+            rng.fill_bytes(&mut rseed);
+            let note: Option<orchard::Note> = Option::from(RandomSeed::from_bytes(rseed, &rho))
+                .and_then(|rseed| {
+                    Option::from(orchard::Note::from_parts(
+                        output.recipient,
+                        output.value,
+                        rho,
+                        rseed,
+                    ))
+                });
+            if let Some(note) = note {
+                break (note, rseed);
+            }
+        };
+        let cmx = ExtractedNoteCommitment::from(output_note.commitment());
+
+        let rcv_scalar = pallas::Scalar::random(&mut rng);
+        let rcv = Option::from(orchard::value::ValueCommitTrapdoor::from_bytes(
+            rcv_scalar.to_repr(),
+        ))
+        .expect("valid scalar");
+        let cv_net =
+            orchard::value::ValueCommitment::derive(spend.note.value() - output_note.value(), rcv);
+
+        let encryptor = OrchardNoteEncryption::new(output.ovk, output_note, output.memo);
+        let enc_ciphertext = encryptor.encrypt_note_plaintext();
+        let out_ciphertext = encryptor.encrypt_outgoing_plaintext(&cv_net, &cmx, &mut rng);
+
+        value_sum += i128::from(spend.note.value().inner());
+        value_sum -= i128::from(output_note.value().inner());
+        bsk += rcv_scalar;
+
+        let path = spend.auth_path.map(|node| node.to_bytes());
+
+        if let Some(sk) = spend.dummy_sk {
+            dummy_spends.push((index, sk));
+        }
+
+        bundle.actions.push(pczt_orchard::Action {
+            cv_net: cv_net.to_bytes(),
+            spend: pczt_orchard::Spend {
+                nullifier: nullifier.to_bytes(),
+                rk: (&rk).into(),
+                spend_auth_sig: None,
+                recipient: Some(spend.note.recipient().to_raw_address_bytes()),
+                value: Some(spend.note.value().inner()),
+                rho: Some(spend.note.rho().to_bytes()),
+                rseed: Some(*spend.note.rseed().as_bytes()),
+                fvk: Some(spend.fvk.to_bytes()),
+                witness: Some((spend.position, path)),
+                alpha: Some(alpha.to_repr()),
+            },
+            output: pczt_orchard::Output {
+                cmx: cmx.to_bytes(),
+                ephemeral_key: OrchardDomain::epk_bytes(encryptor.epk()).0,
+                enc_ciphertext: enc_ciphertext.to_vec(),
+                out_ciphertext: out_ciphertext.to_vec(),
+                recipient: Some(output_note.recipient().to_raw_address_bytes()),
+                value: Some(output_note.value().inner()),
+                rseed: Some(output_rseed),
+                user_address: output.user_address,
+            },
+            rcv: Some(rcv_scalar.to_repr()),
+        });
+    }
+
+    bundle.value_sum = i64::try_from(value_sum).map_err(|_| Error::InvalidValue)?;
+    if !bundle.actions.is_empty() {
+        bundle.bsk = Some(bsk.to_repr());
+    }
+
+    Ok(dummy_spends)
+}
+
+fn random_orchard_sk<R: RngCore>(mut rng: R) -> orchard::keys::SpendingKey {
+    loop {
+        let mut bytes = [0; 32];
+        rng.fill_bytes(&mut bytes);
+        if let Some(sk) = Option::from(orchard::keys::SpendingKey::from_bytes(bytes)) {
+            break sk;
+        }
+    }
+}
+
+/// Generates a dummy spent note, as defined in [Zcash Protocol Spec § 4.8.3: Dummy Notes
+/// (Orchard)][orcharddummynotes].
+///
+/// [orcharddummynotes]: https://zips.z.cash/protocol/nu5.pdf#orcharddummynotes
+fn dummy_orchard_spend<R: RngCore>(mut rng: R) -> OrchardSpendInfo {
+    let sk = random_orchard_sk(&mut rng);
+    let fvk = orchard::keys::FullViewingKey::from(&sk);
+    let recipient = fvk.address_at(0u32, Scope::External);
+
+    let note = loop {
+        let rho = Option::from(Rho::from_bytes(&pallas::Base::random(&mut rng).to_repr()))
+            .expect("valid field element");
+        let mut rseed = [0; 32];
+        rng.fill_bytes(&mut rseed);
+        let note: Option<orchard::Note> = Option::from(RandomSeed::from_bytes(rseed, &rho))
+            .and_then(|rseed| {
+                Option::from(orchard::Note::from_parts(
+                    recipient,
+                    OrchardNoteValue::from_raw(0),
+                    rho,
+                    rseed,
+                ))
+            });
+        if let Some(note) = note {
+            break note;
+        }
+    };
+
+    // The Merkle path of a dummy note is not checked by the circuit.
+    let auth_path = [(); 32].map(|_| {
+        Option::from(MerkleHashOrchard::from_bytes(
+            &pallas::Base::random(&mut rng).to_repr(),
+        ))
+        .expect("valid field element")
+    });
+
+    OrchardSpendInfo {
+        fvk,
+        note,
+        position: rng.next_u32(),
+        auth_path,
+        dummy_sk: Some(sk),
+    }
+}
@@ -0,0 +1,86 @@
+//! The Creator role (single entity).
+//!
+//! - Creates the base PCZT with no information about spends or outputs.
+
+use std::collections::BTreeMap;
+
+use zcash_primitives::transaction::TxVersion;
+use zcash_protocol::consensus::{BlockHeight, BranchId};
+
+use super::Error;
+use crate::{common::Global, orchard, sapling, transparent, Pczt};
+
+/// Initial flags allowing any modification.
+const ORCHARD_FLAGS_ENABLED: u8 = 0b0000_0011;
+
+/// A Creator for a PCZT.
+pub struct Creator {
+    consensus_branch_id: BranchId,
+    lock_time: u32,
+    expiry_height: BlockHeight,
+    sapling_anchor: ::sapling::Anchor,
+    orchard_anchor: ::orchard::Anchor,
+}
+
+impl Creator {
+    /// Constructs a Creator for a transaction that will be mined under the given
+    /// consensus branch, spending Sapling and Orchard notes that exist in the note
+    /// commitment trees with the given anchors.
+    pub fn new(
+        consensus_branch_id: BranchId,
+        expiry_height: BlockHeight,
+        sapling_anchor: ::sapling::Anchor,
+        orchard_anchor: ::orchard::Anchor,
+    ) -> Self {
+        Self {
+            consensus_branch_id,
+            lock_time: 0,
+            expiry_height,
+            sapling_anchor,
+            orchard_anchor,
+        }
+    }
+
+    /// Sets the lock time for the transaction.
+    pub fn with_lock_time(mut self, lock_time: u32) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// Creates the base PCZT.
+    ///
+    /// Returns an error if the consensus branch does not use v5 transactions.
+    pub fn build(self) -> Result<Pczt, Error> {
+        let tx_version = TxVersion::suggested_for_branch(self.consensus_branch_id);
+        if !matches!(tx_version, TxVersion::Zip225) {
+            return Err(Error::UnsupportedConsensusBranch);
+        }
+
+        Ok(Pczt {
+            global: Global {
+                tx_version: tx_version.header(),
+                version_group_id: tx_version.version_group_id(),
+                consensus_branch_id: self.consensus_branch_id.into(),
+                lock_time: self.lock_time,
+                expiry_height: self.expiry_height.into(),
+                proprietary: BTreeMap::new(),
+            },
+            transparent: transparent::Bundle::default(),
+            sapling: sapling::Bundle {
+                spends: vec![],
+                outputs: vec![],
+                value_sum: 0,
+                anchor: self.sapling_anchor.to_bytes(),
+                bsk: None,
+            },
+            orchard: orchard::Bundle {
+                actions: vec![],
+                flags: ORCHARD_FLAGS_ENABLED,
+                value_sum: 0,
+                anchor: self.orchard_anchor.to_bytes(),
+                zkproof: None,
+                bsk: None,
+            },
+        })
+    }
+}
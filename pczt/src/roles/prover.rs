@@ -0,0 +1,163 @@
+//! The Prover role (capability holders can contribute).
+//!
+//! - Needs all private information for a single spend or output.
+//! - In practice, the party that adds a given spend or output will either act as the
+//!   Prover themselves, or add the necessary data, offload to the Prover, and then
+//!   receive back the PCZT with the proof added.
+
+use orchard::circuit::{Circuit, Instance, ProvingKey};
+use rand_core::{CryptoRng, RngCore};
+use sapling::prover::{OutputProver, SpendProver};
+
+use super::Error;
+use crate::Pczt;
+
+/// A Prover for a PCZT.
+pub struct Prover {
+    pczt: Pczt,
+}
+
+impl Prover {
+    /// Instantiates the Prover role with the given PCZT.
+    pub fn new(pczt: Pczt) -> Self {
+        Self { pczt }
+    }
+
+    /// Returns `true` if all Sapling spends and outputs in the PCZT have proofs.
+    pub fn has_sapling_proofs(&self) -> bool {
+        self.pczt.sapling.spends.iter().all(|s| s.zkproof.is_some())
+            && self
+                .pczt
+                .sapling
+                .outputs
+                .iter()
+                .all(|o| o.zkproof.is_some())
+    }
+
+    /// Returns `true` if the Orchard bundle in the PCZT is either empty or has a proof.
+    pub fn has_orchard_proof(&self) -> bool {
+        self.pczt.orchard.actions.is_empty() || self.pczt.orchard.zkproof.is_some()
+    }
+
+    /// Creates the proofs for all Sapling spends and outputs that do not yet have them.
+    pub fn create_sapling_proofs<S, O, R>(
+        &mut self,
+        spend_prover: &S,
+        output_prover: &O,
+        mut rng: R,
+    ) -> Result<(), Error>
+    where
+        S: SpendProver,
+        O: OutputProver,
+        R: RngCore,
+    {
+        let anchor = self.pczt.sapling.parsed_anchor()?;
+
+        for spend in self
+            .pczt
+            .sapling
+            .spends
+            .iter_mut()
+            .filter(|spend| spend.zkproof.is_none())
+        {
+            let note = spend.parsed_note()?;
+            let circuit = S::prepare_circuit(
+                spend.parsed_proof_generation_key()?,
+                *note.recipient().diversifier(),
+                *note.rseed(),
+                note.value(),
+                spend.parsed_alpha()?,
+                spend.parsed_rcv()?,
+                anchor,
+                spend.parsed_witness()?,
+            )
+            .ok_or(Error::InvalidSaplingSpend)?;
+
+            let proof = spend_prover.create_proof(circuit, &mut rng);
+            spend.zkproof = Some(S::encode_proof(proof));
+        }
+
+        for output in self
+            .pczt
+            .sapling
+            .outputs
+            .iter_mut()
+            .filter(|output| output.zkproof.is_none())
+        {
+            let note = output.parsed_note()?;
+            let circuit = O::prepare_circuit(
+                output.derive_esk()?,
+                note.recipient(),
+                note.rcm(),
+                note.value(),
+                output.parsed_rcv()?,
+            );
+
+            let proof = output_prover.create_proof(circuit, &mut rng);
+            output.zkproof = Some(O::encode_proof(proof));
+        }
+
+        Ok(())
+    }
+
+    /// Creates the proof for the Orchard bundle, if it does not yet have one.
+    pub fn create_orchard_proof<R: RngCore + CryptoRng>(
+        &mut self,
+        pk: &ProvingKey,
+        rng: R,
+    ) -> Result<(), Error> {
+        let bundle = &self.pczt.orchard;
+        if bundle.actions.is_empty() || bundle.zkproof.is_some() {
+            return Ok(());
+        }
+
+        let flags = bundle.parsed_flags()?;
+        let anchor = bundle.parsed_anchor()?;
+
+        let (circuits, instances): (Vec<_>, Vec<_>) = bundle
+            .actions
+            .iter()
+            .map(|action| {
+                let fvk = action.spend.parsed_fvk()?;
+                let note = action.spend.parsed_note()?;
+                let merkle_path = action.spend.parsed_witness()?;
+                let spend = orchard::builder::SpendInfo::new(fvk, note, merkle_path)
+                    .ok_or(Error::InvalidOrchardAction)?;
+                let output_note = action.output.parsed_note(&action.spend.nullifier)?;
+
+                let circuit = Circuit::from_action_context(
+                    spend,
+                    output_note,
+                    action.spend.parsed_alpha()?,
+                    action.parsed_rcv()?,
+                )
+                .ok_or(Error::InvalidOrchardAction)?;
+
+                let instance = Instance::from_parts(
+                    anchor,
+                    action.parsed_cv_net()?,
+                    action.spend.parsed_nullifier()?,
+                    action.spend.parsed_rk()?,
+                    action.output.parsed_cmx()?,
+                    flags.spends_enabled(),
+                    flags.outputs_enabled(),
+                );
+
+                Ok((circuit, instance))
+            })
+            .collect::<Result<Vec<_>, Error>>()?
+            .into_iter()
+            .unzip();
+
+        let proof = orchard::Proof::create(pk, &circuits, &instances, rng)
+            .map_err(|_| Error::OrchardProof)?;
+        self.pczt.orchard.zkproof = Some(proof.as_ref().to_vec());
+
+        Ok(())
+    }
+
+    /// Finishes the Prover role, returning the updated PCZT.
+    pub fn finish(self) -> Pczt {
+        self.pczt
+    }
+}
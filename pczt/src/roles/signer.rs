@@ -0,0 +1,234 @@
+//! The Signer role (capability holders can contribute).
+//!
+//! - Needs the spend authorization randomizers to create signatures.
+//! - Needs sufficient information to verify that the proof is over the correct data,
+//!   without needing to verify the proof itself.
+//! - A Signer should only need to implement:
+//!   - Pedersen commitments using Jubjub or Pallas arithmetic (for note and value
+//!     commitments)
+//!   - RedDSA on Jubjub or Pallas
+//!   - ECDSA on secp256k1
+
+use rand_core::{CryptoRng, RngCore};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+use zcash_primitives::legacy::TransparentAddress;
+
+use super::Error;
+use crate::{tx_data::Effects, Pczt};
+
+/// A Signer for a PCZT.
+pub struct Signer {
+    pczt: Pczt,
+    effects: Effects,
+    shielded_sighash: [u8; 32],
+    secp: secp256k1::Secp256k1<secp256k1::All>,
+}
+
+impl Signer {
+    /// Instantiates the Signer role with the given PCZT.
+    pub fn new(pczt: Pczt) -> Result<Self, Error> {
+        let effects = Effects::new(&pczt)?;
+        let shielded_sighash = effects.shielded_sighash();
+        Ok(Self {
+            pczt,
+            effects,
+            shielded_sighash,
+            secp: secp256k1::Secp256k1::new(),
+        })
+    }
+
+    /// Returns the sighash that is signed by every shielded spend authorization
+    /// signature and binding signature in this transaction.
+    pub fn shielded_sighash(&self) -> [u8; 32] {
+        self.shielded_sighash
+    }
+
+    /// Returns the sighash for the transparent spend at the given index.
+    ///
+    /// This is the message that must be signed by the key that controls the coin being
+    /// spent, for use with [`Self::append_transparent_signature`].
+    pub fn transparent_sighash(&self, index: usize) -> Result<[u8; 32], Error> {
+        self.effects.transparent_sighash(&self.pczt, index)
+    }
+
+    /// Returns the spend authorization randomizer for the Sapling spend at the given
+    /// index.
+    ///
+    /// This is required by signers that create spend authorization signatures outside of
+    /// this role, for use with [`Self::append_sapling_signature`].
+    pub fn sapling_alpha(&self, index: usize) -> Result<jubjub::Scalar, Error> {
+        self.pczt
+            .sapling
+            .spends
+            .get(index)
+            .ok_or(Error::InvalidIndex)?
+            .parsed_alpha()
+    }
+
+    /// Returns the spend authorization randomizer for the Orchard spend at the given
+    /// index.
+    ///
+    /// This is required by signers that create spend authorization signatures outside of
+    /// this role, for use with [`Self::append_orchard_signature`].
+    pub fn orchard_alpha(&self, index: usize) -> Result<pasta_curves::pallas::Scalar, Error> {
+        self.pczt
+            .orchard
+            .actions
+            .get(index)
+            .ok_or(Error::InvalidIndex)?
+            .spend
+            .parsed_alpha()
+    }
+
+    /// Signs the transparent spend at the given index with the given spending key.
+    ///
+    /// Returns an error if the key does not control the coin being spent.
+    pub fn sign_transparent(
+        &mut self,
+        index: usize,
+        sk: &secp256k1::SecretKey,
+    ) -> Result<(), Error> {
+        let sighash = self.transparent_sighash(index)?;
+        let msg = secp256k1::Message::from_slice(&sighash).expect("32 bytes");
+        let sig = self.secp.sign_ecdsa(&msg, sk);
+
+        let pubkey = secp256k1::PublicKey::from_secret_key(&self.secp, sk);
+        self.append_transparent_signature(index, &pubkey, &sig)
+    }
+
+    /// Adds a signature created by the given public key for the transparent spend at the
+    /// given index.
+    ///
+    /// Returns an error if the public key does not control the coin being spent, or if
+    /// the signature is not valid for the spend's sighash.
+    pub fn append_transparent_signature(
+        &mut self,
+        index: usize,
+        pubkey: &secp256k1::PublicKey,
+        signature: &secp256k1::ecdsa::Signature,
+    ) -> Result<(), Error> {
+        let sighash = self.transparent_sighash(index)?;
+
+        let input = self
+            .pczt
+            .transparent
+            .inputs
+            .get_mut(index)
+            .ok_or(Error::InvalidIndex)?;
+
+        let pubkey_bytes = pubkey.serialize();
+        match input.script_pubkey().address() {
+            Some(TransparentAddress::PublicKeyHash(hash))
+                if hash[..] == Ripemd160::digest(Sha256::digest(pubkey_bytes))[..] => {}
+            _ => return Err(Error::WrongSpendingKey),
+        }
+
+        let msg = secp256k1::Message::from_slice(&sighash).expect("32 bytes");
+        self.secp
+            .verify_ecdsa(&msg, signature, pubkey)
+            .map_err(|_| Error::InvalidSignature)?;
+
+        // Signature has to have the sighash type appended to it.
+        let mut sig_bytes = signature.serialize_der()[..].to_vec();
+        sig_bytes.push(input.sighash_type);
+
+        input.partial_signatures.insert(pubkey_bytes, sig_bytes);
+
+        Ok(())
+    }
+
+    /// Signs the Sapling spend at the given index with the given spend authorizing key.
+    ///
+    /// Returns an error if the key does not authorize the spend.
+    pub fn sign_sapling<R: RngCore + CryptoRng>(
+        &mut self,
+        index: usize,
+        ask: &sapling::keys::SpendAuthorizingKey,
+        rng: R,
+    ) -> Result<(), Error> {
+        let rsk = ask.randomize(&self.sapling_alpha(index)?);
+        let rk: [u8; 32] = redjubjub::VerificationKey::from(&rsk).into();
+        if rk != self.pczt.sapling.spends[index].rk {
+            return Err(Error::WrongSpendingKey);
+        }
+
+        self.append_sapling_signature(index, rsk.sign(rng, &self.shielded_sighash))
+    }
+
+    /// Adds the given spend authorization signature to the Sapling spend at the given
+    /// index.
+    ///
+    /// Returns an error if the signature is not valid for the spend.
+    pub fn append_sapling_signature(
+        &mut self,
+        index: usize,
+        signature: redjubjub::Signature<redjubjub::SpendAuth>,
+    ) -> Result<(), Error> {
+        let spend = self
+            .pczt
+            .sapling
+            .spends
+            .get_mut(index)
+            .ok_or(Error::InvalidIndex)?;
+
+        spend
+            .parsed_rk()?
+            .verify(&self.shielded_sighash, &signature)
+            .map_err(|_| Error::InvalidSignature)?;
+        spend.spend_auth_sig = Some(signature.into());
+
+        Ok(())
+    }
+
+    /// Signs the Orchard spend at the given index with the given spend authorizing key.
+    ///
+    /// Returns an error if the key does not authorize the spend.
+    pub fn sign_orchard<R: RngCore + CryptoRng>(
+        &mut self,
+        index: usize,
+        ask: &orchard::keys::SpendAuthorizingKey,
+        rng: R,
+    ) -> Result<(), Error> {
+        let rsk = ask.randomize(&self.orchard_alpha(index)?);
+        let rk: [u8; 32] = (&orchard::primitives::redpallas::VerificationKey::from(&rsk)).into();
+        if rk != self.pczt.orchard.actions[index].spend.rk {
+            return Err(Error::WrongSpendingKey);
+        }
+
+        self.append_orchard_signature(index, rsk.sign(rng, &self.shielded_sighash))
+    }
+
+    /// Adds the given spend authorization signature to the Orchard spend at the given
+    /// index.
+    ///
+    /// Returns an error if the signature is not valid for the spend.
+    pub fn append_orchard_signature(
+        &mut self,
+        index: usize,
+        signature: orchard::primitives::redpallas::Signature<
+            orchard::primitives::redpallas::SpendAuth,
+        >,
+    ) -> Result<(), Error> {
+        let action = self
+            .pczt
+            .orchard
+            .actions
+            .get_mut(index)
+            .ok_or(Error::InvalidIndex)?;
+
+        action
+            .spend
+            .parsed_rk()?
+            .verify(&self.shielded_sighash, &signature)
+            .map_err(|_| Error::InvalidSignature)?;
+        action.spend.spend_auth_sig = Some((&signature).into());
+
+        Ok(())
+    }
+
+    /// Finishes the Signer role, returning the updated PCZT.
+    pub fn finish(self) -> Pczt {
+        self.pczt
+    }
+}
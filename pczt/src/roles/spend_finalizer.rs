@@ -0,0 +1,57 @@
+//! The Spend Finalizer role (anyone can execute).
+//!
+//! - Combines the partial transparent signatures into `scriptSig`s.
+
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+use zcash_primitives::legacy::{Script, TransparentAddress};
+
+use super::Error;
+use crate::Pczt;
+
+/// A Spend Finalizer for a PCZT.
+pub struct SpendFinalizer {
+    pczt: Pczt,
+}
+
+impl SpendFinalizer {
+    /// Instantiates the Spend Finalizer role with the given PCZT.
+    pub fn new(pczt: Pczt) -> Self {
+        Self { pczt }
+    }
+
+    /// Finalizes the spends of the PCZT.
+    ///
+    /// Returns an error if any transparent input is missing a signature required to
+    /// finalize it.
+    pub fn finalize_spends(mut self) -> Result<Pczt, Error> {
+        for input in self
+            .pczt
+            .transparent
+            .inputs
+            .iter_mut()
+            .filter(|input| input.script_sig.is_none())
+        {
+            let script_sig = match input.script_pubkey().address() {
+                Some(TransparentAddress::PublicKeyHash(hash)) => {
+                    let (pubkey, sig) = input
+                        .partial_signatures
+                        .iter()
+                        .find(|(pubkey, _)| {
+                            hash[..] == Ripemd160::digest(Sha256::digest(&pubkey[..]))[..]
+                        })
+                        .ok_or(Error::MissingTransparentSignature)?;
+
+                    // P2PKH scriptSig
+                    Script::default() << &sig[..] << &pubkey[..]
+                }
+                _ => return Err(Error::InvalidTransparentInput),
+            };
+
+            input.script_sig = Some(script_sig.0);
+            input.partial_signatures.clear();
+        }
+
+        Ok(self.pczt)
+    }
+}
@@ -0,0 +1,117 @@
+//! The Transaction Extractor role (anyone can execute).
+//!
+//! - Creates the binding signatures.
+//! - Extracts the final transaction from a fully-authorized PCZT.
+
+use rand_core::{CryptoRng, RngCore};
+use zcash_primitives::transaction::{
+    components::transparent, Authorized, Transaction, TransactionData, TxVersion,
+};
+
+use super::Error;
+use crate::{tx_data::Effects, Pczt};
+
+/// A Transaction Extractor for a PCZT.
+pub struct TransactionExtractor {
+    pczt: Pczt,
+}
+
+impl TransactionExtractor {
+    /// Instantiates the Transaction Extractor role with the given PCZT.
+    pub fn new(pczt: Pczt) -> Self {
+        Self { pczt }
+    }
+
+    /// Extracts the fully-authorized transaction from the PCZT.
+    ///
+    /// Returns an error if any proofs or signatures are missing, or if any transparent
+    /// inputs have not been finalized.
+    pub fn extract<R: RngCore + CryptoRng>(self, mut rng: R) -> Result<Transaction, Error> {
+        let pczt = self.pczt;
+        let sighash = Effects::new(&pczt)?.shielded_sighash();
+
+        let transparent_bundle = {
+            let vin = pczt
+                .transparent
+                .to_vin::<transparent::Authorized>(|input| {
+                    input.script_sig().ok_or(Error::MissingTransparentScriptSig)
+                })?;
+            let vout = pczt.transparent.to_vout()?;
+            if vin.is_empty() && vout.is_empty() {
+                None
+            } else {
+                Some(transparent::Bundle {
+                    vin,
+                    vout,
+                    authorization: transparent::Authorized,
+                })
+            }
+        };
+
+        let sapling_bundle = pczt.sapling.to_tx_data(
+            |spend| {
+                Ok::<_, Error>((
+                    spend
+                        .zkproof
+                        .ok_or(Error::MissingSaplingSpendData("zkproof"))?,
+                    spend
+                        .spend_auth_sig
+                        .ok_or(Error::MissingSaplingSpendData("spend_auth_sig"))?
+                        .into(),
+                ))
+            },
+            |output| {
+                output
+                    .zkproof
+                    .ok_or(Error::MissingSaplingOutputData("zkproof"))
+            },
+            |bundle| {
+                let bsk = redjubjub::SigningKey::<redjubjub::Binding>::try_from(
+                    bundle.bsk.ok_or(Error::MissingBindingSigningKey)?,
+                )
+                .map_err(|_| Error::InvalidBindingSigningKey)?;
+                Ok(sapling::bundle::Authorized {
+                    binding_sig: bsk.sign(&mut rng, &sighash),
+                })
+            },
+        )?;
+
+        let orchard_bundle = pczt.orchard.to_tx_data(
+            |action| {
+                Ok::<_, Error>(
+                    action
+                        .spend
+                        .spend_auth_sig
+                        .ok_or(Error::MissingOrchardActionData("spend_auth_sig"))?
+                        .into(),
+                )
+            },
+            |bundle| {
+                let proof = bundle.zkproof.clone().ok_or(Error::MissingOrchardProof)?;
+                let bsk = orchard::primitives::redpallas::SigningKey::<
+                    orchard::primitives::redpallas::Binding,
+                >::try_from(
+                    bundle.bsk.ok_or(Error::MissingBindingSigningKey)?
+                )
+                .map_err(|_| Error::InvalidBindingSigningKey)?;
+                Ok(orchard::bundle::Authorized::from_parts(
+                    orchard::Proof::new(proof),
+                    bsk.sign(&mut rng, &sighash),
+                ))
+            },
+        )?;
+
+        TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            pczt.global.parsed_branch_id()?,
+            pczt.global.lock_time,
+            pczt.global.parsed_expiry_height(),
+            transparent_bundle,
+            None,
+            sapling_bundle,
+            orchard_bundle,
+        )
+        .freeze()
+        .map_err(Error::TransactionEncoding)
+    }
+}
@@ -0,0 +1,81 @@
+//! The Updater role (anyone can contribute).
+//!
+//! - Adds information necessary for subsequent entities to proceed, such as key paths for
+//!   signing spends.
+
+use ff::{Field, PrimeField};
+
+use super::Error;
+use crate::Pczt;
+
+/// An Updater for a PCZT.
+pub struct Updater {
+    pczt: Pczt,
+}
+
+impl Updater {
+    /// Instantiates the Updater role with the given PCZT.
+    pub fn new(pczt: Pczt) -> Self {
+        Self { pczt }
+    }
+
+    /// Sets the proof generation key for the Sapling spend at the given index.
+    ///
+    /// This is required by the Prover in order to create the Spend proof. Returns an
+    /// error if the key does not correspond to the spend's randomized verification key,
+    /// or (if the note being spent and its witness are known) to the spend's nullifier.
+    ///
+    /// The latter check distinguishes between the external and internal proof
+    /// generation keys of a ZIP 32 account, which share the same `ak`.
+    pub fn set_sapling_proof_generation_key(
+        &mut self,
+        index: usize,
+        proof_generation_key: sapling::ProofGenerationKey,
+    ) -> Result<(), Error> {
+        let spend = self
+            .pczt
+            .sapling
+            .spends
+            .get_mut(index)
+            .ok_or(Error::InvalidIndex)?;
+
+        let alpha = spend.parsed_alpha()?;
+        let rk: [u8; 32] = proof_generation_key.ak.randomize(&alpha).into();
+        if rk != spend.rk {
+            return Err(Error::WrongSpendingKey);
+        }
+
+        if let (Ok(note), Ok(witness)) = (spend.parsed_note(), spend.parsed_witness()) {
+            let nk = proof_generation_key.to_viewing_key().nk;
+            let nf = note.nf(&nk, u64::from(witness.position()));
+            if nf.0 != spend.nullifier {
+                return Err(Error::WrongSpendingKey);
+            }
+        }
+
+        // `SpendValidatingKey` does not expose its encoding; randomizing by zero yields
+        // the key itself as a `VerificationKey`, which does.
+        let ak: [u8; 32] = proof_generation_key
+            .ak
+            .randomize(&jubjub::Scalar::ZERO)
+            .into();
+
+        spend.proof_generation_key = Some((ak, proof_generation_key.nsk.to_repr()));
+
+        Ok(())
+    }
+
+    /// Sets a proprietary global field.
+    ///
+    /// Proprietary fields are ignored by the other roles, and can be used by applications
+    /// to carry their own data alongside the PCZT. `key` should be prefixed with a name
+    /// identifying the application. Any existing value for `key` is replaced.
+    pub fn set_proprietary(&mut self, key: String, value: Vec<u8>) {
+        self.pczt.global.proprietary.insert(key, value);
+    }
+
+    /// Finishes the Updater role, returning the updated PCZT.
+    pub fn finish(self) -> Pczt {
+        self.pczt
+    }
+}
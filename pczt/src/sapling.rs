@@ -0,0 +1,722 @@
+//! The Sapling fields of a PCZT.
+
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use ff::PrimeField;
+use sapling::note_encryption::SaplingDomain;
+use zcash_encoding::Vector;
+use zcash_protocol::memo::MemoBytes;
+
+use crate::encoding::{
+    invalid_data, merge_optional, read_array, read_bytes, read_optional_array,
+    read_optional_string, read_optional_u64, read_optional_witness, write_bytes,
+    write_optional_array, write_optional_string, write_optional_u64, write_optional_witness,
+};
+use crate::note_encryption::recover_memo;
+use crate::roles::Error;
+
+const GROTH_PROOF_SIZE: usize = 48 + 96 + 48;
+
+/// The depth of the Sapling note commitment tree.
+const NOTE_COMMITMENT_TREE_DEPTH: usize = 32;
+
+/// PCZT fields that are specific to producing the transaction's Sapling bundle (if any).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bundle {
+    pub(crate) spends: Vec<Spend>,
+    pub(crate) outputs: Vec<Output>,
+
+    /// The net value of Sapling spends minus outputs.
+    ///
+    /// This is initialized by the Creator, and updated by the Constructor as spends or
+    /// outputs are added to the PCZT. It enables per-spend and per-output values to be
+    /// redacted from the PCZT after they are no longer necessary.
+    pub(crate) value_sum: i64,
+
+    /// The Sapling anchor for this transaction.
+    ///
+    /// Set by the Creator.
+    pub(crate) anchor: [u8; 32],
+
+    /// The Sapling binding signature signing key.
+    ///
+    /// - This is `None` until it is set by the Constructor.
+    /// - The Transaction Extractor uses this to produce the binding signature.
+    pub(crate) bsk: Option<[u8; 32]>,
+}
+
+/// Information about a Sapling spend within a transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Spend {
+    //
+    // Spend-specific Sapling effecting data.
+    //
+    // These are required fields that are part of the final transaction, and are filled in
+    // by the Constructor when adding a spend.
+    //
+    pub(crate) cv: [u8; 32],
+    pub(crate) nullifier: [u8; 32],
+    pub(crate) rk: [u8; 32],
+
+    /// The Spend proof.
+    ///
+    /// This is set by the Prover.
+    pub(crate) zkproof: Option<[u8; GROTH_PROOF_SIZE]>,
+
+    /// The spend authorization signature.
+    ///
+    /// This is set by the Signer.
+    pub(crate) spend_auth_sig: Option<[u8; 64]>,
+
+    /// The address that received the note being spent.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) recipient: Option<[u8; 43]>,
+
+    /// The value of the input being spent.
+    ///
+    /// This may be used by Signers to verify that the value matches `cv`, and to confirm
+    /// the values and change involved in the transaction.
+    ///
+    /// This exposes the input value to all participants. For Signers who don't need this
+    /// information, or after signatures have been applied, this can be redacted.
+    pub(crate) value: Option<u64>,
+
+    /// The seed randomness for the note being spent.
+    ///
+    /// Notes received before ZIP 212 activated carry `rcm` directly instead of a seed.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) rseed: Option<Rseed>,
+
+    /// The value commitment randomness.
+    ///
+    /// - This is set by the Constructor.
+    /// - The Constructor folds it into the `bsk`.
+    /// - This is required by the Prover.
+    pub(crate) rcv: Option<[u8; 32]>,
+
+    /// The proof generation key `(ak, nsk)` corresponding to the recipient that received
+    /// the note being spent.
+    ///
+    /// - This is set by the Updater.
+    /// - This is required by the Prover.
+    pub(crate) proof_generation_key: Option<([u8; 32], [u8; 32])>,
+
+    /// A witness from the note to the bundle's anchor.
+    ///
+    /// - This is set by the Updater.
+    /// - This is required by the Prover.
+    pub(crate) witness: Option<(u32, [[u8; 32]; NOTE_COMMITMENT_TREE_DEPTH])>,
+
+    /// The spend authorization randomizer.
+    ///
+    /// - This is chosen by the Constructor.
+    /// - This is required by the Signer for creating `spend_auth_sig`, and may be used to
+    ///   validate `rk`.
+    pub(crate) alpha: Option<[u8; 32]>,
+}
+
+/// Information about a Sapling output within a transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    //
+    // Output-specific Sapling effecting data.
+    //
+    // These are required fields that are part of the final transaction, and are filled in
+    // by the Constructor when adding an output.
+    //
+    pub(crate) cv: [u8; 32],
+    pub(crate) cmu: [u8; 32],
+    pub(crate) ephemeral_key: [u8; 32],
+    /// The encrypted note plaintext for the output.
+    ///
+    /// Encoded as a `Vec<u8>` because its length depends on the transaction version.
+    pub(crate) enc_ciphertext: Vec<u8>,
+    /// The encrypted outgoing plaintext for the output.
+    ///
+    /// Encoded as a `Vec<u8>` because its length depends on the transaction version.
+    pub(crate) out_ciphertext: Vec<u8>,
+
+    /// The Output proof.
+    ///
+    /// This is set by the Prover.
+    pub(crate) zkproof: Option<[u8; GROTH_PROOF_SIZE]>,
+
+    /// The address that will receive the output.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) recipient: Option<[u8; 43]>,
+
+    /// The value of the output.
+    ///
+    /// This may be used by Signers to verify that the value matches `cv`, and to confirm
+    /// the values and change involved in the transaction.
+    ///
+    /// This exposes the output value to all participants. For Signers who don't need this
+    /// information, or after proofs have been created, this can be redacted.
+    pub(crate) value: Option<u64>,
+
+    /// The seed randomness for the output.
+    ///
+    /// - This is set by the Constructor.
+    /// - This is required by the Prover.
+    pub(crate) rseed: Option<[u8; 32]>,
+
+    /// The value commitment randomness.
+    ///
+    /// - This is set by the Constructor.
+    /// - The Constructor folds it into the `bsk`.
+    /// - This is required by the Prover.
+    pub(crate) rcv: Option<[u8; 32]>,
+
+    /// The user-facing address to which this output is being sent, if any.
+    ///
+    /// - This is set by the Constructor for outputs that pay an address given by the
+    ///   user, and is `None` for change outputs.
+    /// - This may be used by Signers to display the recipient, and by the wallet that
+    ///   created the transaction to record it.
+    pub(crate) user_address: Option<String>,
+}
+
+/// The note commitment randomness for a Sapling note being spent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Rseed {
+    /// The encoding of `rcm` for a note created before ZIP 212 activated.
+    BeforeZip212([u8; 32]),
+    /// The seed from which `rcm` (and `esk`) are derived.
+    AfterZip212([u8; 32]),
+}
+
+impl Rseed {
+    pub(crate) fn from_note(note: &sapling::Note) -> Self {
+        match note.rseed() {
+            sapling::Rseed::BeforeZip212(rcm) => Rseed::BeforeZip212(rcm.to_repr()),
+            sapling::Rseed::AfterZip212(rseed) => Rseed::AfterZip212(*rseed),
+        }
+    }
+
+    fn parse(self) -> Result<sapling::Rseed, Error> {
+        match self {
+            Rseed::BeforeZip212(rcm) => Ok(sapling::Rseed::BeforeZip212(parse_scalar(rcm)?)),
+            Rseed::AfterZip212(rseed) => Ok(sapling::Rseed::AfterZip212(rseed)),
+        }
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let tag = reader.read_u8()?;
+        let bytes = read_array(&mut reader)?;
+        match tag {
+            0 => Ok(Rseed::BeforeZip212(bytes)),
+            1 => Ok(Rseed::AfterZip212(bytes)),
+            _ => Err(invalid_data("invalid Sapling rseed type")),
+        }
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let (tag, bytes) = match self {
+            Rseed::BeforeZip212(rcm) => (0, rcm),
+            Rseed::AfterZip212(rseed) => (1, rseed),
+        };
+        writer.write_u8(tag)?;
+        writer.write_all(bytes)
+    }
+}
+
+impl Bundle {
+    /// Returns the Sapling spends of this bundle.
+    pub fn spends(&self) -> &[Spend] {
+        &self.spends
+    }
+
+    /// Returns the Sapling outputs of this bundle.
+    pub fn outputs(&self) -> &[Output] {
+        &self.outputs
+    }
+
+    /// Returns the net value of Sapling spends minus outputs.
+    pub fn value_sum(&self) -> i64 {
+        self.value_sum
+    }
+
+    /// Returns the Sapling anchor for this transaction.
+    pub fn anchor(&self) -> [u8; 32] {
+        self.anchor
+    }
+
+    pub(crate) fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let spends = Vector::read(&mut reader, |r| Spend::read(r))?;
+        let outputs = Vector::read(&mut reader, |r| Output::read(r))?;
+        let value_sum = reader.read_i64::<LittleEndian>()?;
+        let anchor = read_array(&mut reader)?;
+        let bsk = read_optional_array(&mut reader)?;
+        Ok(Bundle {
+            spends,
+            outputs,
+            value_sum,
+            anchor,
+            bsk,
+        })
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        Vector::write(&mut writer, &self.spends, |w, spend| spend.write(w))?;
+        Vector::write(&mut writer, &self.outputs, |w, output| output.write(w))?;
+        writer.write_i64::<LittleEndian>(self.value_sum)?;
+        writer.write_all(&self.anchor)?;
+        write_optional_array(&mut writer, self.bsk.as_ref())
+    }
+
+    /// Merges this bundle with another.
+    ///
+    /// Returns `None` if the bundles have conflicting data.
+    pub(crate) fn merge(mut self, other: Self) -> Option<Self> {
+        let Self {
+            spends,
+            outputs,
+            value_sum,
+            anchor,
+            bsk,
+        } = other;
+
+        if self.spends.len() != spends.len()
+            || self.outputs.len() != outputs.len()
+            || self.value_sum != value_sum
+            || self.anchor != anchor
+            || !merge_optional(&mut self.bsk, bsk)
+        {
+            return None;
+        }
+
+        for (lhs, rhs) in self.spends.iter_mut().zip(spends) {
+            let Spend {
+                cv,
+                nullifier,
+                rk,
+                zkproof,
+                spend_auth_sig,
+                recipient,
+                value,
+                rseed,
+                rcv,
+                proof_generation_key,
+                witness,
+                alpha,
+            } = rhs;
+
+            if lhs.cv != cv
+                || lhs.nullifier != nullifier
+                || lhs.rk != rk
+                || !merge_optional(&mut lhs.zkproof, zkproof)
+                || !merge_optional(&mut lhs.spend_auth_sig, spend_auth_sig)
+                || !merge_optional(&mut lhs.recipient, recipient)
+                || !merge_optional(&mut lhs.value, value)
+                || !merge_optional(&mut lhs.rseed, rseed)
+                || !merge_optional(&mut lhs.rcv, rcv)
+                || !merge_optional(&mut lhs.proof_generation_key, proof_generation_key)
+                || !merge_optional(&mut lhs.witness, witness)
+                || !merge_optional(&mut lhs.alpha, alpha)
+            {
+                return None;
+            }
+        }
+
+        for (lhs, rhs) in self.outputs.iter_mut().zip(outputs) {
+            let Output {
+                cv,
+                cmu,
+                ephemeral_key,
+                enc_ciphertext,
+                out_ciphertext,
+                zkproof,
+                recipient,
+                value,
+                rseed,
+                rcv,
+                user_address,
+            } = rhs;
+
+            if lhs.cv != cv
+                || lhs.cmu != cmu
+                || lhs.ephemeral_key != ephemeral_key
+                || lhs.enc_ciphertext != enc_ciphertext
+                || lhs.out_ciphertext != out_ciphertext
+                || !merge_optional(&mut lhs.zkproof, zkproof)
+                || !merge_optional(&mut lhs.recipient, recipient)
+                || !merge_optional(&mut lhs.value, value)
+                || !merge_optional(&mut lhs.rseed, rseed)
+                || !merge_optional(&mut lhs.rcv, rcv)
+                || !merge_optional(&mut lhs.user_address, user_address)
+            {
+                return None;
+            }
+        }
+
+        Some(self)
+    }
+
+    pub(crate) fn parsed_anchor(&self) -> Result<bls12_381::Scalar, Error> {
+        Option::from(bls12_381::Scalar::from_repr(self.anchor)).ok_or(Error::InvalidSaplingAnchor)
+    }
+
+    pub(crate) fn parsed_value_balance(&self) -> Result<zcash_protocol::value::ZatBalance, Error> {
+        zcash_protocol::value::ZatBalance::from_i64(self.value_sum).map_err(|_| Error::InvalidValue)
+    }
+
+    /// Returns the Sapling bundle with the given authorization, or `None` if the bundle
+    /// has no spends or outputs.
+    pub(crate) fn to_tx_data<A, E, F, G>(
+        &self,
+        spend_auth: F,
+        output_proof: G,
+        bundle_auth: impl FnOnce(&Self) -> Result<A, E>,
+    ) -> Result<Option<sapling::Bundle<A, zcash_protocol::value::ZatBalance>>, E>
+    where
+        A: sapling::bundle::Authorization,
+        E: From<Error>,
+        F: Fn(&Spend) -> Result<(A::SpendProof, A::AuthSig), E>,
+        G: Fn(&Output) -> Result<A::OutputProof, E>,
+    {
+        let spends = self
+            .spends
+            .iter()
+            .map(|spend| {
+                let (zkproof, spend_auth_sig) = spend_auth(spend)?;
+                Ok(sapling::bundle::SpendDescription::from_parts(
+                    spend.parsed_cv()?,
+                    self.parsed_anchor()?,
+                    sapling::Nullifier(spend.nullifier),
+                    spend.parsed_rk()?,
+                    zkproof,
+                    spend_auth_sig,
+                ))
+            })
+            .collect::<Result<Vec<_>, E>>()?;
+
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|output| {
+                let zkproof = output_proof(output)?;
+                let enc_ciphertext = output
+                    .enc_ciphertext
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| Error::InvalidSaplingOutput)?;
+                let out_ciphertext = output
+                    .out_ciphertext
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| Error::InvalidSaplingOutput)?;
+                Ok(sapling::bundle::OutputDescription::from_parts(
+                    output.parsed_cv()?,
+                    Option::from(sapling::note::ExtractedNoteCommitment::from_bytes(
+                        &output.cmu,
+                    ))
+                    .ok_or(Error::InvalidSaplingOutput)?,
+                    output.ephemeral_key.into(),
+                    enc_ciphertext,
+                    out_ciphertext,
+                    zkproof,
+                ))
+            })
+            .collect::<Result<Vec<_>, E>>()?;
+
+        if spends.is_empty() && outputs.is_empty() {
+            return Ok(None);
+        }
+
+        let authorization = bundle_auth(self)?;
+
+        Ok(sapling::Bundle::from_parts(
+            spends,
+            outputs,
+            self.parsed_value_balance()?,
+            authorization,
+        ))
+    }
+}
+
+impl Spend {
+    /// Returns the value commitment for this spend.
+    pub fn cv(&self) -> [u8; 32] {
+        self.cv
+    }
+
+    /// Returns the nullifier of the note being spent.
+    pub fn nullifier(&self) -> [u8; 32] {
+        self.nullifier
+    }
+
+    /// Returns the randomized verification key for this spend.
+    pub fn rk(&self) -> [u8; 32] {
+        self.rk
+    }
+
+    /// Returns the value of the note being spent, if it has not been redacted.
+    pub fn value(&self) -> Option<u64> {
+        self.value
+    }
+
+    /// Returns `true` if this spend has a Spend proof.
+    pub fn has_proof(&self) -> bool {
+        self.zkproof.is_some()
+    }
+
+    /// Returns `true` if this spend has a spend authorization signature.
+    pub fn has_signature(&self) -> bool {
+        self.spend_auth_sig.is_some()
+    }
+
+    pub(crate) fn parsed_cv(&self) -> Result<sapling::value::ValueCommitment, Error> {
+        Option::from(sapling::value::ValueCommitment::from_bytes_not_small_order(
+            &self.cv,
+        ))
+        .ok_or(Error::InvalidSaplingSpend)
+    }
+
+    pub(crate) fn parsed_rk(
+        &self,
+    ) -> Result<redjubjub::VerificationKey<redjubjub::SpendAuth>, Error> {
+        redjubjub::VerificationKey::try_from(self.rk).map_err(|_| Error::InvalidSaplingSpend)
+    }
+
+    pub(crate) fn parsed_alpha(&self) -> Result<jubjub::Scalar, Error> {
+        parse_scalar(self.alpha.ok_or(Error::MissingSaplingSpendData("alpha"))?)
+    }
+
+    pub(crate) fn parsed_rcv(&self) -> Result<sapling::value::ValueCommitTrapdoor, Error> {
+        parse_rcv(self.rcv.ok_or(Error::MissingSaplingSpendData("rcv"))?)
+    }
+
+    pub(crate) fn parsed_note(&self) -> Result<sapling::Note, Error> {
+        let recipient = sapling::PaymentAddress::from_bytes(
+            &self
+                .recipient
+                .ok_or(Error::MissingSaplingSpendData("recipient"))?,
+        )
+        .ok_or(Error::InvalidSaplingSpend)?;
+        let value = sapling::value::NoteValue::from_raw(
+            self.value.ok_or(Error::MissingSaplingSpendData("value"))?,
+        );
+        let rseed = self
+            .rseed
+            .ok_or(Error::MissingSaplingSpendData("rseed"))?
+            .parse()?;
+        Ok(sapling::Note::from_parts(recipient, value, rseed))
+    }
+
+    pub(crate) fn parsed_proof_generation_key(&self) -> Result<sapling::ProofGenerationKey, Error> {
+        let (ak, nsk) = self
+            .proof_generation_key
+            .ok_or(Error::MissingSaplingSpendData("proof_generation_key"))?;
+        let ak = sapling::keys::SpendValidatingKey::temporary_zcash_from_bytes(&ak)
+            .ok_or(Error::InvalidSaplingSpend)?;
+        let nsk = parse_scalar(nsk)?;
+        Ok(sapling::ProofGenerationKey { ak, nsk })
+    }
+
+    pub(crate) fn parsed_witness(&self) -> Result<sapling::MerklePath, Error> {
+        let (position, path) = self
+            .witness
+            .ok_or(Error::MissingSaplingSpendData("witness"))?;
+        let path = path
+            .iter()
+            .map(|node| {
+                Option::from(sapling::Node::from_bytes(*node)).ok_or(Error::InvalidSaplingSpend)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        sapling::MerklePath::from_parts(path, u64::from(position).into())
+            .map_err(|_| Error::InvalidSaplingSpend)
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let cv = read_array(&mut reader)?;
+        let nullifier = read_array(&mut reader)?;
+        let rk = read_array(&mut reader)?;
+        let zkproof = read_optional_array(&mut reader)?;
+        let spend_auth_sig = read_optional_array(&mut reader)?;
+        let recipient = read_optional_array(&mut reader)?;
+        let value = read_optional_u64(&mut reader)?;
+        let rseed = zcash_encoding::Optional::read(&mut reader, Rseed::read)?;
+        let rcv = read_optional_array(&mut reader)?;
+        let proof_generation_key = zcash_encoding::Optional::read(&mut reader, |mut r| {
+            Ok((read_array(&mut r)?, read_array(&mut r)?))
+        })?;
+        let witness = read_optional_witness(&mut reader)?;
+        let alpha = read_optional_array(&mut reader)?;
+        Ok(Spend {
+            cv,
+            nullifier,
+            rk,
+            zkproof,
+            spend_auth_sig,
+            recipient,
+            value,
+            rseed,
+            rcv,
+            proof_generation_key,
+            witness,
+            alpha,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.cv)?;
+        writer.write_all(&self.nullifier)?;
+        writer.write_all(&self.rk)?;
+        write_optional_array(&mut writer, self.zkproof.as_ref())?;
+        write_optional_array(&mut writer, self.spend_auth_sig.as_ref())?;
+        write_optional_array(&mut writer, self.recipient.as_ref())?;
+        write_optional_u64(&mut writer, self.value)?;
+        zcash_encoding::Optional::write(&mut writer, self.rseed.as_ref(), |w, rseed| {
+            rseed.write(w)
+        })?;
+        write_optional_array(&mut writer, self.rcv.as_ref())?;
+        zcash_encoding::Optional::write(
+            &mut writer,
+            self.proof_generation_key.as_ref(),
+            |w, (ak, nsk)| {
+                w.write_all(ak)?;
+                w.write_all(nsk)
+            },
+        )?;
+        write_optional_witness(&mut writer, self.witness.as_ref())?;
+        write_optional_array(&mut writer, self.alpha.as_ref())
+    }
+}
+
+impl Output {
+    /// Returns the value commitment for this output.
+    pub fn cv(&self) -> [u8; 32] {
+        self.cv
+    }
+
+    /// Returns the note commitment for this output.
+    pub fn cmu(&self) -> [u8; 32] {
+        self.cmu
+    }
+
+    /// Returns the value of this output, if it has not been redacted.
+    pub fn value(&self) -> Option<u64> {
+        self.value
+    }
+
+    /// Returns `true` if this output has an Output proof.
+    pub fn has_proof(&self) -> bool {
+        self.zkproof.is_some()
+    }
+
+    /// Returns the user-facing address to which this output is being sent, if known.
+    pub fn user_address(&self) -> Option<&str> {
+        self.user_address.as_deref()
+    }
+
+    /// Returns the note created by this output, along with the memo it was encrypted
+    /// with.
+    ///
+    /// This requires the output's recipient, value and `rseed`, and does not require any
+    /// viewing key.
+    pub fn recover_note_and_memo(&self) -> Result<(sapling::Note, MemoBytes), Error> {
+        let note = self.parsed_note()?;
+        let memo = recover_memo::<SaplingDomain>(&note, self.ephemeral_key, &self.enc_ciphertext)
+            .ok_or(Error::InvalidSaplingOutput)?;
+        Ok((note, memo))
+    }
+
+    pub(crate) fn parsed_cv(&self) -> Result<sapling::value::ValueCommitment, Error> {
+        Option::from(sapling::value::ValueCommitment::from_bytes_not_small_order(
+            &self.cv,
+        ))
+        .ok_or(Error::InvalidSaplingOutput)
+    }
+
+    pub(crate) fn parsed_rcv(&self) -> Result<sapling::value::ValueCommitTrapdoor, Error> {
+        parse_rcv(self.rcv.ok_or(Error::MissingSaplingOutputData("rcv"))?)
+    }
+
+    pub(crate) fn parsed_note(&self) -> Result<sapling::Note, Error> {
+        let recipient = sapling::PaymentAddress::from_bytes(
+            &self
+                .recipient
+                .ok_or(Error::MissingSaplingOutputData("recipient"))?,
+        )
+        .ok_or(Error::InvalidSaplingOutput)?;
+        let value = sapling::value::NoteValue::from_raw(
+            self.value.ok_or(Error::MissingSaplingOutputData("value"))?,
+        );
+        let rseed = sapling::Rseed::AfterZip212(
+            self.rseed.ok_or(Error::MissingSaplingOutputData("rseed"))?,
+        );
+        Ok(sapling::Note::from_parts(recipient, value, rseed))
+    }
+
+    /// Derives the ephemeral secret key for this output from its `rseed`.
+    pub(crate) fn derive_esk(&self) -> Result<jubjub::Scalar, Error> {
+        let rseed = self.rseed.ok_or(Error::MissingSaplingOutputData("rseed"))?;
+        Ok(jubjub::Scalar::from_bytes_wide(
+            &zcash_spec::PrfExpand::SAPLING_ESK.with(&rseed),
+        ))
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let cv = read_array(&mut reader)?;
+        let cmu = read_array(&mut reader)?;
+        let ephemeral_key = read_array(&mut reader)?;
+        let enc_ciphertext = read_bytes(&mut reader)?;
+        let out_ciphertext = read_bytes(&mut reader)?;
+        let zkproof = read_optional_array(&mut reader)?;
+        let recipient = read_optional_array(&mut reader)?;
+        let value = read_optional_u64(&mut reader)?;
+        let rseed = read_optional_array(&mut reader)?;
+        let rcv = read_optional_array(&mut reader)?;
+        let user_address = read_optional_string(&mut reader)?;
+
+        if enc_ciphertext.len() != zcash_note_encryption::ENC_CIPHERTEXT_SIZE
+            || out_ciphertext.len() != zcash_note_encryption::OUT_CIPHERTEXT_SIZE
+        {
+            return Err(invalid_data("invalid Sapling output ciphertext length"));
+        }
+
+        Ok(Output {
+            cv,
+            cmu,
+            ephemeral_key,
+            enc_ciphertext,
+            out_ciphertext,
+            zkproof,
+            recipient,
+            value,
+            rseed,
+            rcv,
+            user_address,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.cv)?;
+        writer.write_all(&self.cmu)?;
+        writer.write_all(&self.ephemeral_key)?;
+        write_bytes(&mut writer, &self.enc_ciphertext)?;
+        write_bytes(&mut writer, &self.out_ciphertext)?;
+        write_optional_array(&mut writer, self.zkproof.as_ref())?;
+        write_optional_array(&mut writer, self.recipient.as_ref())?;
+        write_optional_u64(&mut writer, self.value)?;
+        write_optional_array(&mut writer, self.rseed.as_ref())?;
+        write_optional_array(&mut writer, self.rcv.as_ref())?;
+        write_optional_string(&mut writer, self.user_address.as_deref())
+    }
+}
+
+fn parse_scalar(bytes: [u8; 32]) -> Result<jubjub::Scalar, Error> {
+    Option::from(jubjub::Scalar::from_repr(bytes)).ok_or(Error::InvalidSaplingScalar)
+}
+
+fn parse_rcv(bytes: [u8; 32]) -> Result<sapling::value::ValueCommitTrapdoor, Error> {
+    Option::from(sapling::value::ValueCommitTrapdoor::from_bytes(bytes))
+        .ok_or(Error::InvalidSaplingScalar)
+}
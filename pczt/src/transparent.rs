@@ -0,0 +1,343 @@
+//! The transparent fields of a PCZT.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use zcash_encoding::Vector;
+use zcash_primitives::{
+    legacy::Script,
+    transaction::{
+        components::transparent::{Authorization, OutPoint, TxIn, TxOut},
+        TxId,
+    },
+};
+use zcash_protocol::value::Zatoshis;
+
+use crate::encoding::{
+    invalid_data, merge_optional, read_array, read_bytes, read_optional_bytes,
+    read_optional_string, write_bytes, write_optional_bytes, write_optional_string,
+};
+
+/// PCZT fields that are specific to producing the transaction's transparent bundle (if
+/// any).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bundle {
+    pub(crate) inputs: Vec<Input>,
+    pub(crate) outputs: Vec<Output>,
+}
+
+/// Information about a transparent input within a transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Input {
+    //
+    // Transparent effecting data.
+    //
+    // These are required fields that are part of the final transaction, and are filled in
+    // by the Constructor when adding an input.
+    //
+    pub(crate) prevout_txid: [u8; 32],
+    pub(crate) prevout_index: u32,
+    pub(crate) sequence: u32,
+
+    /// The value of the coin being spent.
+    ///
+    /// This is required by every Signer, because the transparent sighash commits to the
+    /// amounts of all inputs.
+    pub(crate) value: u64,
+
+    /// The script constraining spending of the coin being spent.
+    ///
+    /// This is required by every Signer, because the transparent sighash commits to the
+    /// `scriptPubKey`s of all inputs.
+    pub(crate) script_pubkey: Vec<u8>,
+
+    /// The sighash type to be used for this input.
+    ///
+    /// Only `SIGHASH_ALL` is currently supported.
+    pub(crate) sighash_type: u8,
+
+    /// The `scriptSig` for this input.
+    ///
+    /// - This is set by the Spend Finalizer, once all required signatures are present.
+    /// - This is required by the Transaction Extractor.
+    pub(crate) script_sig: Option<Vec<u8>>,
+
+    /// A map from a pubkey to a signature created by it.
+    ///
+    /// - Each pubkey should appear in `script_pubkey`, or be the preimage of a pubkey
+    ///   hash that appears in it.
+    /// - These are set by the Signer for the inputs they are authorizing.
+    /// - These are cleared by the Spend Finalizer.
+    pub(crate) partial_signatures: BTreeMap<[u8; 33], Vec<u8>>,
+}
+
+/// Information about a transparent output within a transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    //
+    // Transparent effecting data.
+    //
+    // These are required fields that are part of the final transaction, and are filled in
+    // by the Constructor when adding an output.
+    //
+    pub(crate) value: u64,
+    pub(crate) script_pubkey: Vec<u8>,
+
+    /// The user-facing address to which this output is being sent, if any.
+    ///
+    /// - This is set by the Constructor for outputs that pay an address given by the
+    ///   user, and is `None` for change outputs.
+    /// - This may be used by Signers to display the recipient, and by the wallet that
+    ///   created the transaction to record it.
+    pub(crate) user_address: Option<String>,
+}
+
+impl Bundle {
+    /// Returns the transparent inputs of this bundle.
+    pub fn inputs(&self) -> &[Input] {
+        &self.inputs
+    }
+
+    /// Returns the transparent outputs of this bundle.
+    pub fn outputs(&self) -> &[Output] {
+        &self.outputs
+    }
+
+    pub(crate) fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let inputs = Vector::read(&mut reader, |r| Input::read(r))?;
+        let outputs = Vector::read(&mut reader, |r| Output::read(r))?;
+        Ok(Bundle { inputs, outputs })
+    }
+
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        Vector::write(&mut writer, &self.inputs, |w, input| input.write(w))?;
+        Vector::write(&mut writer, &self.outputs, |w, output| output.write(w))
+    }
+
+    /// Merges this bundle with another.
+    ///
+    /// Returns `None` if the bundles have conflicting data.
+    pub(crate) fn merge(mut self, other: Self) -> Option<Self> {
+        let Self { inputs, outputs } = other;
+
+        if self.inputs.len() != inputs.len() || self.outputs.len() != outputs.len() {
+            return None;
+        }
+
+        for (lhs, rhs) in self.inputs.iter_mut().zip(inputs) {
+            let Input {
+                prevout_txid,
+                prevout_index,
+                sequence,
+                value,
+                script_pubkey,
+                sighash_type,
+                script_sig,
+                partial_signatures,
+            } = rhs;
+
+            if lhs.prevout_txid != prevout_txid
+                || lhs.prevout_index != prevout_index
+                || lhs.sequence != sequence
+                || lhs.value != value
+                || lhs.script_pubkey != script_pubkey
+                || lhs.sighash_type != sighash_type
+                || !merge_optional(&mut lhs.script_sig, script_sig)
+            {
+                return None;
+            }
+
+            for (pubkey, sig) in partial_signatures {
+                match lhs.partial_signatures.get(&pubkey) {
+                    Some(existing) if existing != &sig => return None,
+                    _ => {
+                        lhs.partial_signatures.insert(pubkey, sig);
+                    }
+                }
+            }
+        }
+
+        for (lhs, rhs) in self.outputs.iter_mut().zip(outputs) {
+            let Output {
+                value,
+                script_pubkey,
+                user_address,
+            } = rhs;
+
+            if lhs.value != value
+                || lhs.script_pubkey != script_pubkey
+                || !merge_optional(&mut lhs.user_address, user_address)
+            {
+                return None;
+            }
+        }
+
+        Some(self)
+    }
+
+    pub(crate) fn to_vin<A: Authorization>(
+        &self,
+        script_sig: impl Fn(&Input) -> Result<A::ScriptSig, crate::roles::Error>,
+    ) -> Result<Vec<TxIn<A>>, crate::roles::Error> {
+        self.inputs
+            .iter()
+            .map(|input| {
+                Ok(TxIn {
+                    prevout: input.prevout(),
+                    script_sig: script_sig(input)?,
+                    sequence: input.sequence,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn to_vout(&self) -> Result<Vec<TxOut>, crate::roles::Error> {
+        self.outputs
+            .iter()
+            .map(|output| output.to_txout())
+            .collect()
+    }
+}
+
+impl Input {
+    /// Returns the outpoint of the coin being spent.
+    pub fn prevout(&self) -> OutPoint {
+        OutPoint::new(self.prevout_txid, self.prevout_index)
+    }
+
+    /// Returns the ID of the transaction that created the coin being spent.
+    pub fn prevout_txid(&self) -> TxId {
+        TxId::from_bytes(self.prevout_txid)
+    }
+
+    /// Returns the sequence number of this input.
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Returns the value of the coin being spent.
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    /// Returns the script constraining spending of the coin being spent.
+    pub fn script_pubkey(&self) -> Script {
+        Script(self.script_pubkey.clone())
+    }
+
+    /// Returns the sighash type to be used for this input.
+    pub fn sighash_type(&self) -> u8 {
+        self.sighash_type
+    }
+
+    /// Returns the `scriptSig` for this input, if it has been finalized.
+    pub fn script_sig(&self) -> Option<Script> {
+        self.script_sig.clone().map(Script)
+    }
+
+    /// Returns the signatures that have been created for this input, keyed by the
+    /// encoding of the public key that created them.
+    pub fn partial_signatures(&self) -> &BTreeMap<[u8; 33], Vec<u8>> {
+        &self.partial_signatures
+    }
+
+    pub(crate) fn coin(&self) -> Result<TxOut, crate::roles::Error> {
+        Ok(TxOut {
+            value: Zatoshis::from_u64(self.value).map_err(|_| crate::roles::Error::InvalidValue)?,
+            script_pubkey: self.script_pubkey(),
+        })
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let prevout_txid = read_array(&mut reader)?;
+        let prevout_index = reader.read_u32::<LittleEndian>()?;
+        let sequence = reader.read_u32::<LittleEndian>()?;
+        let value = reader.read_u64::<LittleEndian>()?;
+        let script_pubkey = read_bytes(&mut reader)?;
+        let sighash_type = reader.read_u8()?;
+        let script_sig = read_optional_bytes(&mut reader)?;
+        let signatures = Vector::read(&mut reader, |r| {
+            let pubkey = read_array(&mut *r)?;
+            let sig = read_bytes(r)?;
+            Ok((pubkey, sig))
+        })?;
+
+        let mut partial_signatures = BTreeMap::new();
+        for (pubkey, sig) in signatures {
+            if partial_signatures.insert(pubkey, sig).is_some() {
+                return Err(invalid_data("duplicate transparent partial signature"));
+            }
+        }
+
+        Ok(Input {
+            prevout_txid,
+            prevout_index,
+            sequence,
+            value,
+            script_pubkey,
+            sighash_type,
+            script_sig,
+            partial_signatures,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.prevout_txid)?;
+        writer.write_u32::<LittleEndian>(self.prevout_index)?;
+        writer.write_u32::<LittleEndian>(self.sequence)?;
+        writer.write_u64::<LittleEndian>(self.value)?;
+        write_bytes(&mut writer, &self.script_pubkey)?;
+        writer.write_u8(self.sighash_type)?;
+        write_optional_bytes(&mut writer, self.script_sig.as_deref())?;
+        Vector::write_sized(
+            &mut writer,
+            self.partial_signatures.iter(),
+            |w, (pubkey, sig)| {
+                w.write_all(&pubkey[..])?;
+                write_bytes(w, sig)
+            },
+        )
+    }
+}
+
+impl Output {
+    /// Returns the value of this output.
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    /// Returns the script constraining spending of this output.
+    pub fn script_pubkey(&self) -> Script {
+        Script(self.script_pubkey.clone())
+    }
+
+    /// Returns the user-facing address to which this output is being sent, if known.
+    pub fn user_address(&self) -> Option<&str> {
+        self.user_address.as_deref()
+    }
+
+    pub(crate) fn to_txout(&self) -> Result<TxOut, crate::roles::Error> {
+        Ok(TxOut {
+            value: Zatoshis::from_u64(self.value).map_err(|_| crate::roles::Error::InvalidValue)?,
+            script_pubkey: self.script_pubkey(),
+        })
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let value = reader.read_u64::<LittleEndian>()?;
+        let script_pubkey = read_bytes(&mut reader)?;
+        let user_address = read_optional_string(&mut reader)?;
+        Ok(Output {
+            value,
+            script_pubkey,
+            user_address,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u64::<LittleEndian>(self.value)?;
+        write_bytes(&mut writer, &self.script_pubkey)?;
+        write_optional_string(&mut writer, self.user_address.as_deref())
+    }
+}
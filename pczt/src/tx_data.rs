@@ -0,0 +1,164 @@
+//! Conversion of PCZTs into `zcash_primitives` transaction data, for computing digests.
+
+use zcash_primitives::{
+    legacy::Script,
+    transaction::{
+        components::{
+            amount::NonNegativeAmount,
+            transparent::{self, TxOut},
+        },
+        sighash::{signature_hash, SignableInput, TransparentAuthorizingContext},
+        txid::TxIdDigester,
+        Authorization, TransactionData, TxDigests, TxVersion,
+    },
+};
+
+use crate::{roles::Error, Pczt};
+
+/// [`Authorization`] marker type for a PCZT's effecting data.
+///
+/// This is only used to compute the transaction's txid digests and signature hashes,
+/// neither of which commit to authorizing data in v5 transactions.
+#[derive(Debug)]
+pub(crate) struct EffectsOnly;
+
+impl Authorization for EffectsOnly {
+    type TransparentAuth = TransparentEffectsOnly;
+    type SaplingAuth = SaplingEffectsOnly;
+    type OrchardAuth = OrchardEffectsOnly;
+
+    #[cfg(zcash_unstable = "zfuture")]
+    type TzeAuth = zcash_primitives::transaction::components::tze::Authorized;
+}
+
+#[derive(Debug)]
+pub(crate) struct TransparentEffectsOnly {
+    inputs: Vec<TxOut>,
+}
+
+impl transparent::Authorization for TransparentEffectsOnly {
+    type ScriptSig = ();
+}
+
+impl TransparentAuthorizingContext for TransparentEffectsOnly {
+    fn input_amounts(&self) -> Vec<NonNegativeAmount> {
+        self.inputs.iter().map(|input| input.value).collect()
+    }
+
+    fn input_scriptpubkeys(&self) -> Vec<Script> {
+        self.inputs
+            .iter()
+            .map(|input| input.script_pubkey.clone())
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SaplingEffectsOnly;
+
+impl sapling::bundle::Authorization for SaplingEffectsOnly {
+    type SpendProof = sapling::bundle::GrothProofBytes;
+    type OutputProof = sapling::bundle::GrothProofBytes;
+    type AuthSig = ();
+}
+
+#[derive(Debug)]
+pub(crate) struct OrchardEffectsOnly;
+
+impl orchard::bundle::Authorization for OrchardEffectsOnly {
+    type SpendAuth = ();
+}
+
+/// The effecting data of a PCZT, along with its txid digests.
+pub(crate) struct Effects {
+    pub(crate) tx_data: TransactionData<EffectsOnly>,
+    pub(crate) txid_parts: TxDigests<blake2b_simd::Hash>,
+}
+
+impl Effects {
+    pub(crate) fn new(pczt: &Pczt) -> Result<Self, Error> {
+        let tx_data = effects_only(pczt)?;
+        let txid_parts = tx_data.digest(TxIdDigester);
+        Ok(Effects {
+            tx_data,
+            txid_parts,
+        })
+    }
+
+    /// Returns the sighash that all shielded spend authorizations and binding signatures
+    /// sign.
+    pub(crate) fn shielded_sighash(&self) -> [u8; 32] {
+        *signature_hash(&self.tx_data, &SignableInput::Shielded, &self.txid_parts).as_ref()
+    }
+
+    /// Returns the sighash for the transparent input at the given index.
+    pub(crate) fn transparent_sighash(&self, pczt: &Pczt, index: usize) -> Result<[u8; 32], Error> {
+        let input = pczt
+            .transparent
+            .inputs
+            .get(index)
+            .ok_or(Error::InvalidIndex)?;
+        let script_pubkey = input.script_pubkey();
+        let value = input.coin()?.value;
+
+        Ok(*signature_hash(
+            &self.tx_data,
+            &SignableInput::Transparent {
+                hash_type: input.sighash_type,
+                index,
+                // For P2PKH, the script code is the `scriptPubKey`.
+                script_code: &script_pubkey,
+                script_pubkey: &script_pubkey,
+                value,
+            },
+            &self.txid_parts,
+        )
+        .as_ref())
+    }
+}
+
+fn effects_only(pczt: &Pczt) -> Result<TransactionData<EffectsOnly>, Error> {
+    let version = TxVersion::Zip225;
+    let consensus_branch_id = pczt.global.parsed_branch_id()?;
+
+    let transparent_bundle = {
+        let vin = pczt.transparent.to_vin(|_| Ok(()))?;
+        let vout = pczt.transparent.to_vout()?;
+        if vin.is_empty() && vout.is_empty() {
+            None
+        } else {
+            let inputs = pczt
+                .transparent
+                .inputs
+                .iter()
+                .map(|input| input.coin())
+                .collect::<Result<_, _>>()?;
+            Some(transparent::Bundle {
+                vin,
+                vout,
+                authorization: TransparentEffectsOnly { inputs },
+            })
+        }
+    };
+
+    let sapling_bundle = pczt.sapling.to_tx_data(
+        |_| Ok::<_, Error>(([0; 192], ())),
+        |_| Ok([0; 192]),
+        |_| Ok(SaplingEffectsOnly),
+    )?;
+
+    let orchard_bundle = pczt
+        .orchard
+        .to_tx_data(|_| Ok::<_, Error>(()), |_| Ok(OrchardEffectsOnly))?;
+
+    Ok(TransactionData::from_parts(
+        version,
+        consensus_branch_id,
+        pczt.global.lock_time,
+        pczt.global.parsed_expiry_height(),
+        transparent_bundle,
+        None,
+        sapling_bundle,
+        orchard_bundle,
+    ))
+}
@@ -56,11 +56,12 @@
 //! [`propose_shielding`]: crate::data_api::wallet::propose_shielding
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::Debug,
     hash::Hash,
     io,
     num::{NonZeroU32, TryFromIntError},
+    ops::Range,
 };
 
 use incrementalmerkletree::{frontier::Frontier, Retention};
@@ -81,7 +82,7 @@ use crate::{
     },
     proto::service::TreeState,
     wallet::{Note, NoteId, ReceivedNote, Recipient, WalletTransparentOutput, WalletTx},
-    ShieldedProtocol,
+    PoolType, ShieldedProtocol,
 };
 use zcash_primitives::{
     block::BlockHash,
@@ -95,12 +96,13 @@ use zcash_primitives::{
         Transaction, TxId,
     },
 };
+use zcash_protocol::value::{ZatBalance, Zatoshis};
 
 #[cfg(feature = "transparent-inputs")]
-use {
-    crate::wallet::TransparentAddressMetadata, std::ops::Range,
-    zcash_primitives::legacy::TransparentAddress,
-};
+use {crate::wallet::TransparentAddressMetadata, zcash_primitives::legacy::TransparentAddress};
+
+#[cfg(feature = "fiat")]
+use {crate::fiat::Currency, rust_decimal::Decimal, zcash_protocol::value::COIN};
 
 #[cfg(feature = "test-dependencies")]
 use ambassador::delegatable_trait;
@@ -355,6 +357,36 @@ pub enum AccountSource {
     Imported { purpose: AccountPurpose },
 }
 
+/// The ZIP 32 derivation of an account's spending keys.
+///
+/// For imported accounts, this may be recorded in order to identify the account to an
+/// external signer (such as a hardware wallet) that holds its spending keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Zip32Derivation {
+    seed_fingerprint: SeedFingerprint,
+    account_index: zip32::AccountId,
+}
+
+impl Zip32Derivation {
+    /// Constructs new derivation metadata from its constituent parts.
+    pub fn new(seed_fingerprint: SeedFingerprint, account_index: zip32::AccountId) -> Self {
+        Self {
+            seed_fingerprint,
+            account_index,
+        }
+    }
+
+    /// Returns the fingerprint of the seed from which the account's keys were derived.
+    pub fn seed_fingerprint(&self) -> &SeedFingerprint {
+        &self.seed_fingerprint
+    }
+
+    /// Returns the ZIP 32 account index at which the account's keys were derived.
+    pub fn account_index(&self) -> zip32::AccountId {
+        self.account_index
+    }
+}
+
 /// A set of capabilities that a client account must provide.
 pub trait Account {
     type AccountId: Copy;
@@ -386,8 +418,35 @@ pub trait Account {
     /// indication about whether an account can be used in a wallet context; for that, use
     /// [`Account::ufvk`].
     fn uivk(&self) -> UnifiedIncomingViewingKey;
+
+    /// Returns the human-readable name of the account, if one has been set.
+    fn name(&self) -> Option<&str>;
+
+    /// Returns an identifier for the device or key store that holds the account's spending
+    /// keys, such as the key fingerprint of a hardware wallet, if one has been recorded.
+    fn key_source(&self) -> Option<&str>;
+
+    /// Returns the ZIP 32 derivation of the account's spending keys, if known.
+    ///
+    /// For derived accounts this is determined by the account's [`AccountSource`]; imported
+    /// accounts may have a derivation recorded by [`WalletWrite::set_account_key_source`].
+    fn derivation(&self) -> Option<Zip32Derivation> {
+        match self.source() {
+            AccountSource::Derived {
+                seed_fingerprint,
+                account_index,
+            } => Some(Zip32Derivation::new(seed_fingerprint, account_index)),
+            AccountSource::Imported { .. } => None,
+        }
+    }
+
+    /// Returns the key/value metadata that has been attached to the account.
+    fn metadata(&self) -> &BTreeMap<String, String>;
 }
 
+#[cfg(any(test, feature = "test-dependencies"))]
+static NO_ACCOUNT_METADATA: BTreeMap<String, String> = BTreeMap::new();
+
 #[cfg(any(test, feature = "test-dependencies"))]
 impl<A: Copy> Account for (A, UnifiedFullViewingKey) {
     type AccountId = A;
@@ -409,6 +468,18 @@ impl<A: Copy> Account for (A, UnifiedFullViewingKey) {
     fn uivk(&self) -> UnifiedIncomingViewingKey {
         self.1.to_unified_incoming_viewing_key()
     }
+
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    fn key_source(&self) -> Option<&str> {
+        None
+    }
+
+    fn metadata(&self) -> &BTreeMap<String, String> {
+        &NO_ACCOUNT_METADATA
+    }
 }
 
 #[cfg(any(test, feature = "test-dependencies"))]
@@ -432,6 +503,18 @@ impl<A: Copy> Account for (A, UnifiedIncomingViewingKey) {
     fn uivk(&self) -> UnifiedIncomingViewingKey {
         self.1.clone()
     }
+
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    fn key_source(&self) -> Option<&str> {
+        None
+    }
+
+    fn metadata(&self) -> &BTreeMap<String, String> {
+        &NO_ACCOUNT_METADATA
+    }
 }
 
 /// A polymorphic ratio type, usually used for rational numbers.
@@ -699,6 +782,425 @@ pub enum TransactionStatus {
     Mined(BlockHeight),
 }
 
+/// The price of 1 ZEC in a fiat currency, as recorded by the wallet for a transaction.
+#[cfg(feature = "fiat")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FiatRate {
+    currency: Currency,
+    rate: Decimal,
+}
+
+#[cfg(feature = "fiat")]
+impl FiatRate {
+    /// Constructs a `FiatRate` from a currency and the price of 1 ZEC in that currency.
+    pub fn new(currency: Currency, rate: Decimal) -> Self {
+        Self { currency, rate }
+    }
+
+    /// Returns the currency in which the rate is denominated.
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Returns the price of 1 ZEC in the currency.
+    pub fn rate(&self) -> Decimal {
+        self.rate
+    }
+
+    /// Returns the value of the given amount in the currency.
+    pub fn value_of(&self, amount: ZatBalance) -> Decimal {
+        Decimal::from(i64::from(amount)) * self.rate / Decimal::from(COIN)
+    }
+}
+
+/// The direction of a transaction, from the perspective of a single account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionDirection {
+    /// The account spent at least one of its outputs in the transaction.
+    Sent,
+    /// The account did not spend any of its outputs in the transaction, but received at
+    /// least one output.
+    Received,
+}
+
+/// Criteria used to select the transactions returned by
+/// [`WalletRead::get_transaction_history`].
+///
+/// Each criterion that is set restricts the set of returned transactions; the default
+/// filter selects the complete transaction history of every account in the wallet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransactionHistoryFilter<AccountId> {
+    account_id: Option<AccountId>,
+    mined_heights: Option<Range<BlockHeight>>,
+    pool: Option<PoolType>,
+    direction: Option<TransactionDirection>,
+    has_memo: Option<bool>,
+}
+
+impl<AccountId> Default for TransactionHistoryFilter<AccountId> {
+    fn default() -> Self {
+        Self {
+            account_id: None,
+            mined_heights: None,
+            pool: None,
+            direction: None,
+            has_memo: None,
+        }
+    }
+}
+
+impl<AccountId> TransactionHistoryFilter<AccountId> {
+    /// Constructs a filter that selects the transaction history of every account in the
+    /// wallet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Selects only transactions involving the given account.
+    pub fn with_account(mut self, account_id: AccountId) -> Self {
+        self.account_id = Some(account_id);
+        self
+    }
+
+    /// Selects only transactions mined in the given range of block heights.
+    ///
+    /// Unmined transactions are excluded by this criterion.
+    pub fn with_mined_heights(mut self, heights: Range<BlockHeight>) -> Self {
+        self.mined_heights = Some(heights);
+        self
+    }
+
+    /// Selects only transactions in which the account received, spent, or sent an output
+    /// in the given pool.
+    pub fn with_pool(mut self, pool: PoolType) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Selects only transactions having the given direction relative to the account.
+    pub fn with_direction(mut self, direction: TransactionDirection) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    /// Selects only transactions that do (if `true`) or do not (if `false`) contain at
+    /// least one non-empty memo viewable by the account.
+    pub fn with_memo_presence(mut self, has_memo: bool) -> Self {
+        self.has_memo = Some(has_memo);
+        self
+    }
+
+    /// Returns the account to which the history is restricted, if any.
+    pub fn account_id(&self) -> Option<&AccountId> {
+        self.account_id.as_ref()
+    }
+
+    /// Returns the range of mined heights to which the history is restricted, if any.
+    pub fn mined_heights(&self) -> Option<&Range<BlockHeight>> {
+        self.mined_heights.as_ref()
+    }
+
+    /// Returns the pool to which the history is restricted, if any.
+    pub fn pool(&self) -> Option<PoolType> {
+        self.pool
+    }
+
+    /// Returns the transaction direction to which the history is restricted, if any.
+    pub fn direction(&self) -> Option<TransactionDirection> {
+        self.direction
+    }
+
+    /// Returns whether the history is restricted to transactions with (`Some(true)`) or
+    /// without (`Some(false)`) memos, if at all.
+    pub fn has_memo(&self) -> Option<bool> {
+        self.has_memo
+    }
+}
+
+/// An output of a transaction in the wallet's history.
+///
+/// This is either an output received by an account in the wallet, or an output sent from
+/// an account in the wallet to an external recipient.
+#[derive(Clone, Debug)]
+pub struct TransactionHistoryOutput<AccountId> {
+    pool: PoolType,
+    output_index: u32,
+    from_account_id: Option<AccountId>,
+    to_account_id: Option<AccountId>,
+    to_address: Option<String>,
+    value: Zatoshis,
+    is_change: bool,
+    memo: Option<MemoBytes>,
+}
+
+impl<AccountId> TransactionHistoryOutput<AccountId> {
+    /// Constructs a `TransactionHistoryOutput` from its parts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_parts(
+        pool: PoolType,
+        output_index: u32,
+        from_account_id: Option<AccountId>,
+        to_account_id: Option<AccountId>,
+        to_address: Option<String>,
+        value: Zatoshis,
+        is_change: bool,
+        memo: Option<MemoBytes>,
+    ) -> Self {
+        Self {
+            pool,
+            output_index,
+            from_account_id,
+            to_account_id,
+            to_address,
+            value,
+            is_change,
+            memo,
+        }
+    }
+
+    /// Returns the pool in which the output was created.
+    pub fn pool(&self) -> PoolType {
+        self.pool
+    }
+
+    /// Returns the index of the output within the transaction's bundle for its pool.
+    pub fn output_index(&self) -> u32 {
+        self.output_index
+    }
+
+    /// Returns the account that sent this output, if it was sent by the wallet.
+    pub fn from_account_id(&self) -> Option<&AccountId> {
+        self.from_account_id.as_ref()
+    }
+
+    /// Returns the account that received this output, if it was received by the wallet.
+    pub fn to_account_id(&self) -> Option<&AccountId> {
+        self.to_account_id.as_ref()
+    }
+
+    /// Returns the encoded address of the recipient, if the output was sent to an
+    /// external recipient.
+    pub fn to_address(&self) -> Option<&str> {
+        self.to_address.as_deref()
+    }
+
+    /// Returns the value of the output.
+    pub fn value(&self) -> Zatoshis {
+        self.value
+    }
+
+    /// Returns `true` if the output is change received by the sending account.
+    pub fn is_change(&self) -> bool {
+        self.is_change
+    }
+
+    /// Returns the memo associated with the output, if it is known.
+    pub fn memo(&self) -> Option<&MemoBytes> {
+        self.memo.as_ref()
+    }
+}
+
+/// A transaction in the history of an account, along with the outputs of the transaction
+/// that involve the account.
+#[derive(Clone, Debug)]
+pub struct TransactionHistoryEntry<AccountId> {
+    account_id: AccountId,
+    txid: TxId,
+    mined_height: Option<BlockHeight>,
+    block_time: Option<u32>,
+    expiry_height: Option<BlockHeight>,
+    account_value_delta: ZatBalance,
+    fee_paid: Option<Zatoshis>,
+    spent_note_count: usize,
+    has_change: bool,
+    sent_note_count: usize,
+    received_note_count: usize,
+    memo_count: usize,
+    expired_unmined: bool,
+    is_shielding: bool,
+    outputs: Vec<TransactionHistoryOutput<AccountId>>,
+    #[cfg(feature = "fiat")]
+    fiat_rates: Vec<FiatRate>,
+}
+
+impl<AccountId> TransactionHistoryEntry<AccountId> {
+    /// Constructs a `TransactionHistoryEntry` from its parts.
+    ///
+    /// See the documentation for each getter method below to determine how each method
+    /// argument should be prepared.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_parts(
+        account_id: AccountId,
+        txid: TxId,
+        mined_height: Option<BlockHeight>,
+        block_time: Option<u32>,
+        expiry_height: Option<BlockHeight>,
+        account_value_delta: ZatBalance,
+        fee_paid: Option<Zatoshis>,
+        spent_note_count: usize,
+        has_change: bool,
+        sent_note_count: usize,
+        received_note_count: usize,
+        memo_count: usize,
+        expired_unmined: bool,
+        is_shielding: bool,
+        outputs: Vec<TransactionHistoryOutput<AccountId>>,
+    ) -> Self {
+        Self {
+            account_id,
+            txid,
+            mined_height,
+            block_time,
+            expiry_height,
+            account_value_delta,
+            fee_paid,
+            spent_note_count,
+            has_change,
+            sent_note_count,
+            received_note_count,
+            memo_count,
+            expired_unmined,
+            is_shielding,
+            outputs,
+            #[cfg(feature = "fiat")]
+            fiat_rates: vec![],
+        }
+    }
+
+    /// Sets the exchange rates that were recorded for the transaction with
+    /// [`WalletWrite::set_transaction_fiat_rate`].
+    #[cfg(feature = "fiat")]
+    pub fn with_fiat_rates(mut self, fiat_rates: Vec<FiatRate>) -> Self {
+        self.fiat_rates = fiat_rates;
+        self
+    }
+
+    /// Returns the wallet-internal ID for the account that this transaction was received
+    /// by or sent from.
+    pub fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    /// Returns the transaction's ID.
+    pub fn txid(&self) -> TxId {
+        self.txid
+    }
+
+    /// Returns the height of the block in which the transaction was mined, or `None` if
+    /// the wallet has not yet observed the transaction to be mined.
+    pub fn mined_height(&self) -> Option<BlockHeight> {
+        self.mined_height
+    }
+
+    /// Returns the time of the block in which the transaction was mined, in seconds since
+    /// the UNIX epoch, if known.
+    pub fn block_time(&self) -> Option<u32> {
+        self.block_time
+    }
+
+    /// Returns the expiry height of the transaction, if known.
+    ///
+    /// - `None` means that the expiry height is unknown.
+    /// - `Some(0)` means that the transaction does not expire.
+    pub fn expiry_height(&self) -> Option<BlockHeight> {
+        self.expiry_height
+    }
+
+    /// Returns the net change in balance that this transaction caused to the account.
+    pub fn account_value_delta(&self) -> ZatBalance {
+        self.account_value_delta
+    }
+
+    /// Returns the fee paid by the transaction, if known.
+    pub fn fee_paid(&self) -> Option<Zatoshis> {
+        self.fee_paid
+    }
+
+    /// Returns the number of notes spent by the account in this transaction.
+    pub fn spent_note_count(&self) -> usize {
+        self.spent_note_count
+    }
+
+    /// Returns `true` if the account received a change note as part of this transaction.
+    pub fn has_change(&self) -> bool {
+        self.has_change
+    }
+
+    /// Returns the number of notes created in this transaction that were sent to a
+    /// wallet-external address.
+    pub fn sent_note_count(&self) -> usize {
+        self.sent_note_count
+    }
+
+    /// Returns the number of notes created in this transaction that were received by the
+    /// account, not counting change.
+    pub fn received_note_count(&self) -> usize {
+        self.received_note_count
+    }
+
+    /// Returns the number of non-empty memos viewable by the account in this transaction.
+    pub fn memo_count(&self) -> usize {
+        self.memo_count
+    }
+
+    /// Returns `true` if, from the wallet's current view of the chain, this transaction
+    /// expired before it was mined.
+    pub fn expired_unmined(&self) -> bool {
+        self.expired_unmined
+    }
+
+    /// Returns `true` if this is detectably a shielding transaction.
+    pub fn is_shielding(&self) -> bool {
+        self.is_shielding
+    }
+
+    /// Returns the direction of the transaction relative to the account.
+    pub fn direction(&self) -> TransactionDirection {
+        if self.spent_note_count > 0 {
+            TransactionDirection::Sent
+        } else {
+            TransactionDirection::Received
+        }
+    }
+
+    /// Returns the outputs of the transaction that were received by the account or sent
+    /// from it, ordered by pool and output index.
+    pub fn outputs(&self) -> &[TransactionHistoryOutput<AccountId>] {
+        &self.outputs
+    }
+
+    /// Returns the exchange rates that have been recorded for the transaction, at most one
+    /// per currency.
+    #[cfg(feature = "fiat")]
+    pub fn fiat_rates(&self) -> &[FiatRate] {
+        &self.fiat_rates
+    }
+
+    /// Returns the exchange rate recorded for the transaction in the given currency, if
+    /// any.
+    #[cfg(feature = "fiat")]
+    pub fn fiat_rate(&self, currency: Currency) -> Option<&FiatRate> {
+        self.fiat_rates.iter().find(|r| r.currency() == currency)
+    }
+
+    /// Returns the value of [`Self::account_value_delta`] in the given currency, if an
+    /// exchange rate in that currency has been recorded for the transaction.
+    #[cfg(feature = "fiat")]
+    pub fn fiat_value_delta(&self, currency: Currency) -> Option<Decimal> {
+        self.fiat_rate(currency)
+            .map(|rate| rate.value_of(self.account_value_delta))
+    }
+
+    /// Returns the value of [`Self::fee_paid`] in the given currency, if both the fee and
+    /// an exchange rate in that currency for the transaction are known.
+    #[cfg(feature = "fiat")]
+    pub fn fiat_fee_paid(&self, currency: Currency) -> Option<Decimal> {
+        self.fiat_rate(currency)
+            .zip(self.fee_paid)
+            .map(|(rate, fee)| rate.value_of(fee.into()))
+    }
+}
+
 impl<NoteRef> SpendableNotes<NoteRef> {
     /// Construct a new empty [`SpendableNotes`].
     pub fn empty() -> Self {
@@ -1451,6 +1953,21 @@ pub trait WalletRead {
     /// transaction data requests, such as when it is necessary to fill in purely-transparent
     /// transaction history by walking the chain backwards via transparent inputs.
     fn transaction_data_requests(&self) -> Result<Vec<TransactionDataRequest>, Self::Error>;
+
+    /// Returns a page of the wallet's transaction history, restricted to the transactions
+    /// selected by the given filter.
+    ///
+    /// Each entry describes the effect of a single transaction on a single account; a
+    /// transaction involving several accounts in the wallet has one entry per account.
+    /// Entries are ordered with unmined transactions first, followed by mined
+    /// transactions from the most recently mined to the least recently mined. At most
+    /// `limit` entries are returned, after skipping the first `offset` entries.
+    fn get_transaction_history(
+        &self,
+        filter: &TransactionHistoryFilter<Self::AccountId>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<TransactionHistoryEntry<Self::AccountId>>, Self::Error>;
 }
 
 /// Read-only operations required for testing light wallet functions.
@@ -2260,6 +2777,60 @@ pub trait WalletWrite: WalletRead {
         purpose: AccountPurpose,
     ) -> Result<Self::Account, Self::Error>;
 
+    /// Removes the specified account from the wallet, along with all of the data that the
+    /// wallet holds for it.
+    ///
+    /// This removes the account's viewing keys, the addresses that have been generated for
+    /// it (including any ephemeral addresses), the notes and transparent outputs it has
+    /// received, and the outputs it has sent. Positions in the note commitment trees that
+    /// were retained in order to spend the account's notes are no longer retained, so that
+    /// the corresponding tree data may be pruned.
+    ///
+    /// Transaction data is not removed, as it may be shared with other accounts in the
+    /// wallet. Outputs sent to this account by other accounts in the wallet are retained
+    /// if their recipient address is known.
+    ///
+    /// Returns an error if the account identifier does not correspond to a known account.
+    fn delete_account(&mut self, account: Self::AccountId) -> Result<(), Self::Error>;
+
+    /// Sets the human-readable name of the specified account, or clears it if `name` is
+    /// `None`.
+    ///
+    /// Returns an error if the account identifier does not correspond to a known account.
+    fn set_account_name(
+        &mut self,
+        account: Self::AccountId,
+        name: Option<&str>,
+    ) -> Result<(), Self::Error>;
+
+    /// Records the provenance of the specified account's spending keys.
+    ///
+    /// `key_source` identifies the device or key store that holds the spending keys, such as
+    /// the key fingerprint of a hardware wallet. `derivation` is the ZIP 32 derivation of the
+    /// keys, as used by an external signer; it may only be set for imported accounts, as the
+    /// derivation of a derived account is fixed by its [`AccountSource`]. Passing `None`
+    /// clears the corresponding value.
+    ///
+    /// Returns an error if the account identifier does not correspond to a known account, or
+    /// if a derivation is provided for a derived account that does not match its source.
+    fn set_account_key_source(
+        &mut self,
+        account: Self::AccountId,
+        key_source: Option<&str>,
+        derivation: Option<Zip32Derivation>,
+    ) -> Result<(), Self::Error>;
+
+    /// Sets the value of the metadata entry with the given key for the specified account, or
+    /// removes the entry if `value` is `None`.
+    ///
+    /// Returns an error if the account identifier does not correspond to a known account.
+    fn set_account_metadata(
+        &mut self,
+        account: Self::AccountId,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), Self::Error>;
+
     /// Generates and persists the next available diversified address, given the current
     /// addresses known to the wallet.
     ///
@@ -2377,6 +2948,25 @@ pub trait WalletWrite: WalletRead {
         _txid: TxId,
         _status: TransactionStatus,
     ) -> Result<(), Self::Error>;
+
+    /// Records the exchange rate between ZEC and a fiat currency at the time of the given
+    /// transaction, replacing any rate previously recorded for the transaction in that
+    /// currency.
+    ///
+    /// Recorded rates are returned with the transaction's entries in
+    /// [`WalletRead::get_transaction_history`]. Returns an error if the transaction is not
+    /// known to the wallet.
+    #[cfg(feature = "fiat")]
+    fn set_transaction_fiat_rate(
+        &mut self,
+        _txid: TxId,
+        _rate: FiatRate,
+    ) -> Result<(), Self::Error> {
+        // Default impl is required for feature-flagged trait methods to prevent
+        // breakage due to inadvertent activation of features by transitive dependencies
+        // of the implementing crate.
+        Ok(())
+    }
 }
 
 /// This trait describes a capability for manipulating wallet note commitment trees.
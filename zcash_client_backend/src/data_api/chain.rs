@@ -151,6 +151,7 @@
 //! # }
 //! ```
 
+use std::hash::Hash;
 use std::ops::Range;
 
 use incrementalmerkletree::frontier::Frontier;
@@ -161,7 +162,8 @@ use zcash_primitives::{
 };
 
 use crate::{
-    data_api::{NullifierQuery, WalletWrite},
+    data_api::{BlockMetadata, NullifierQuery, ScannedBlock, WalletWrite},
+    keys::UnifiedFullViewingKey,
     proto::compact_formats::CompactBlock,
     scanning::{scan_block_with_runners, BatchRunners, Nullifiers, ScanningKeys},
 };
@@ -603,15 +605,8 @@ where
     let account_ufvks = data_db
         .get_unified_full_viewing_keys()
         .map_err(Error::Wallet)?;
-    let scanning_keys = ScanningKeys::from_account_ufvks(account_ufvks);
-    let mut runners = BatchRunners::<_, (), ()>::for_keys(100, &scanning_keys);
 
-    block_source.with_blocks::<_, DbT::Error>(Some(from_height), Some(limit), |block| {
-        runners.add_block(params, block).map_err(|e| e.into())
-    })?;
-    runners.flush();
-
-    let mut prior_block_metadata = if from_height > BlockHeight::from(0) {
+    let prior_block_metadata = if from_height > BlockHeight::from(0) {
         data_db
             .block_metadata(from_height - 1)
             .map_err(Error::Wallet)?
@@ -620,7 +615,7 @@ where
     };
 
     // Get the nullifiers for the unspent notes we are tracking
-    let mut nullifiers = Nullifiers::new(
+    let nullifiers = Nullifiers::new(
         data_db
             .get_sapling_nullifiers(NullifierQuery::Unspent)
             .map_err(Error::Wallet)?,
@@ -630,9 +625,54 @@ where
             .map_err(Error::Wallet)?,
     );
 
+    let (scanned_blocks, scan_summary) = scan_block_range(
+        params,
+        block_source,
+        account_ufvks,
+        nullifiers,
+        prior_block_metadata,
+        from_height,
+        limit,
+    )?;
+
+    data_db
+        .put_blocks(from_state, scanned_blocks)
+        .map_err(Error::Wallet)?;
+    Ok(scan_summary)
+}
+
+/// Scans at most `limit` blocks from the provided block source, starting at `from_height`,
+/// for transactions that involve the given accounts, without reading from or writing to a
+/// wallet database.
+///
+/// `nullifiers` are the nullifiers of the unspent notes that the wallet is tracking, and
+/// `prior_block_metadata` describes the block at `from_height - 1`, if it is known.
+#[allow(clippy::type_complexity)]
+pub(crate) fn scan_block_range<ParamsT, AccountId, BlockSourceT, WalletErrT>(
+    params: &ParamsT,
+    block_source: &BlockSourceT,
+    account_ufvks: impl IntoIterator<Item = (AccountId, UnifiedFullViewingKey)>,
+    mut nullifiers: Nullifiers<AccountId>,
+    mut prior_block_metadata: Option<BlockMetadata>,
+    from_height: BlockHeight,
+    limit: usize,
+) -> Result<(Vec<ScannedBlock<AccountId>>, ScanSummary), Error<WalletErrT, BlockSourceT::Error>>
+where
+    ParamsT: consensus::Parameters + Send + 'static,
+    BlockSourceT: BlockSource,
+    AccountId: Copy + Eq + Hash + ConditionallySelectable + Default + Send + 'static,
+{
+    let scanning_keys = ScanningKeys::from_account_ufvks(account_ufvks);
+    let mut runners = BatchRunners::<_, (), ()>::for_keys(100, &scanning_keys);
+
+    block_source.with_blocks::<_, WalletErrT>(Some(from_height), Some(limit), |block| {
+        runners.add_block(params, block).map_err(|e| e.into())
+    })?;
+    runners.flush();
+
     let mut scanned_blocks = vec![];
     let mut scan_summary = ScanSummary::for_range(from_height..from_height);
-    block_source.with_blocks::<_, DbT::Error>(
+    block_source.with_blocks::<_, WalletErrT>(
         Some(from_height),
         Some(limit),
         |block: CompactBlock| {
@@ -692,10 +732,7 @@ where
         },
     )?;
 
-    data_db
-        .put_blocks(from_state, scanned_blocks)
-        .map_err(Error::Wallet)?;
-    Ok(scan_summary)
+    Ok((scanned_blocks, scan_summary))
 }
 
 #[cfg(feature = "test-dependencies")]
@@ -101,6 +101,19 @@ pub enum Error<DataSourceError, CommitmentTreeError, SelectionError, FeeError, C
     /// output.
     #[cfg(feature = "transparent-inputs")]
     PaysEphemeralTransparentAddress(String),
+
+    /// An error occurred while creating or finalizing a PCZT.
+    #[cfg(feature = "pczt")]
+    Pczt(pczt::roles::Error),
+
+    /// The PCZT was not created by this wallet, or lacks the wallet-specific information
+    /// that was added to it when it was created.
+    #[cfg(feature = "pczt")]
+    PcztNotRecognized,
+
+    /// A transaction signer failed to provide the authorization for a spend.
+    #[cfg(feature = "pczt")]
+    Signer(Box<dyn error::Error + Send + Sync + 'static>),
 }
 
 impl<DE, TE, SE, FE, CE, N> fmt::Display for Error<DE, TE, SE, FE, CE, N>
@@ -184,6 +197,12 @@ where
             Error::PaysEphemeralTransparentAddress(addr) => {
                 write!(f, "The wallet tried to pay to an ephemeral transparent address as a normal output: {}", addr)
             }
+            #[cfg(feature = "pczt")]
+            Error::Pczt(e) => write!(f, "An error occurred while processing a PCZT: {}", e),
+            #[cfg(feature = "pczt")]
+            Error::PcztNotRecognized => write!(f, "The PCZT was not created by this wallet"),
+            #[cfg(feature = "pczt")]
+            Error::Signer(e) => write!(f, "The transaction signer failed to authorize a spend: {}", e),
         }
     }
 }
@@ -204,6 +223,10 @@ where
             Error::NoteSelection(e) => Some(e),
             Error::Proposal(e) => Some(e),
             Error::Builder(e) => Some(e),
+            #[cfg(feature = "pczt")]
+            Error::Pczt(e) => Some(e),
+            #[cfg(feature = "pczt")]
+            Error::Signer(e) => Some(e.as_ref()),
             _ => None,
         }
     }
@@ -215,6 +238,13 @@ impl<DE, TE, SE, FE, CE, N> From<builder::Error<FE>> for Error<DE, TE, SE, FE, C
     }
 }
 
+#[cfg(feature = "pczt")]
+impl<DE, TE, SE, FE, CE, N> From<pczt::roles::Error> for Error<DE, TE, SE, FE, CE, N> {
+    fn from(e: pczt::roles::Error) -> Self {
+        Error::Pczt(e)
+    }
+}
+
 impl<DE, TE, SE, FE, CE, N> From<ProposalError> for Error<DE, TE, SE, FE, CE, N> {
     fn from(e: ProposalError) -> Self {
         Error::Proposal(e)
@@ -71,9 +71,9 @@ use super::{
     },
     Account, AccountBalance, AccountBirthday, AccountMeta, AccountPurpose, AccountSource,
     BlockMetadata, DecryptedTransaction, InputSource, NullifierQuery, ScannedBlock, SeedRelevance,
-    SentTransaction, SpendableNotes, TransactionDataRequest, TransactionStatus,
-    WalletCommitmentTrees, WalletRead, WalletSummary, WalletTest, WalletWrite,
-    SAPLING_SHARD_HEIGHT,
+    SentTransaction, SpendableNotes, TransactionDataRequest, TransactionHistoryEntry,
+    TransactionHistoryFilter, TransactionStatus, WalletCommitmentTrees, WalletRead, WalletSummary,
+    WalletTest, WalletWrite, Zip32Derivation, SAPLING_SHARD_HEIGHT,
 };
 use super::{error::Error, NoteFilter};
 
@@ -89,10 +89,19 @@ use {
     ::orchard::tree::MerkleHashOrchard, group::ff::PrimeField, pasta_curves::pallas,
 };
 
+#[cfg(feature = "sync")]
+use {
+    crate::{proto::service::TreeState, sync::source::memory::MemoryChainSource},
+    incrementalmerkletree::frontier::CommitmentTree,
+    zcash_primitives::merkle_tree::{write_commitment_tree, HashSer},
+};
+
 #[cfg(feature = "orchard")]
 pub mod orchard;
 pub mod pool;
 pub mod sapling;
+#[cfg(feature = "sync")]
+pub mod sync;
 #[cfg(feature = "transparent-inputs")]
 pub mod transparent;
 
@@ -327,6 +336,31 @@ impl CachedBlock {
         self.chain_state.block_height()
     }
 
+    /// Returns the tree state as of the end of this block, in the form served by a
+    /// [`ChainDataSource`].
+    ///
+    /// [`ChainDataSource`]: crate::sync::ChainDataSource
+    #[cfg(feature = "sync")]
+    pub fn tree_state(&self) -> TreeState {
+        fn encode<H: HashSer + Clone + incrementalmerkletree::Hashable, const DEPTH: u8>(
+            frontier: &incrementalmerkletree::frontier::Frontier<H, DEPTH>,
+        ) -> String {
+            let mut bytes = vec![];
+            write_commitment_tree(&CommitmentTree::from_frontier(frontier), &mut bytes)
+                .expect("writing to a Vec cannot fail");
+            hex::encode(bytes)
+        }
+
+        TreeState {
+            height: u32::from(self.height()).into(),
+            hash: self.chain_state.block_hash().to_string(),
+            sapling_tree: encode(self.chain_state.final_sapling_tree()),
+            #[cfg(feature = "orchard")]
+            orchard_tree: encode(self.chain_state.final_orchard_tree()),
+            ..Default::default()
+        }
+    }
+
     /// Returns the size of the Sapling note commitment tree as of the end of this block.
     pub fn sapling_end_size(&self) -> u32 {
         self.sapling_end_size
@@ -385,6 +419,22 @@ impl<A: Account> Account for TestAccount<A> {
     fn uivk(&self) -> zcash_keys::keys::UnifiedIncomingViewingKey {
         self.account.uivk()
     }
+
+    fn name(&self) -> Option<&str> {
+        self.account.name()
+    }
+
+    fn key_source(&self) -> Option<&str> {
+        self.account.key_source()
+    }
+
+    fn derivation(&self) -> Option<Zip32Derivation> {
+        self.account.derivation()
+    }
+
+    fn metadata(&self) -> &BTreeMap<String, String> {
+        self.account.metadata()
+    }
 }
 
 /// Trait method exposing the ability to reset the wallet within a test.
@@ -503,6 +553,36 @@ where
         self.cached_blocks.range(..height).last().map(|(_, b)| b)
     }
 
+    /// Returns a [`MemoryChainSource`] that serves the blocks generated by this
+    /// `TestState`, along with the tree states as of the end of each of those blocks and
+    /// of the block preceding them.
+    #[cfg(feature = "sync")]
+    pub fn chain_source(&self) -> MemoryChainSource {
+        let mut source = MemoryChainSource::new();
+        let mut first_block_height = None;
+        self.cache
+            .block_source()
+            .with_blocks::<_, Infallible>(None, None, |block| {
+                first_block_height.get_or_insert(block.height());
+                source.insert_block(block);
+                Ok(())
+            })
+            .unwrap();
+
+        for block in self.cached_blocks.values() {
+            source.insert_tree_state(block.tree_state());
+        }
+        // If the first block was generated without an initial chain state, it follows a
+        // block "before shielded time".
+        if let Some(height) =
+            first_block_height.filter(|height| self.cached_blocks.keys().next() == Some(height))
+        {
+            source.insert_tree_state(CachedBlock::none(height - 1).tree_state());
+        }
+
+        source
+    }
+
     fn cache_block(
         &mut self,
         prev_block: &CachedBlock,
@@ -2588,6 +2668,15 @@ impl WalletRead for MockWalletDb {
     fn transaction_data_requests(&self) -> Result<Vec<TransactionDataRequest>, Self::Error> {
         Ok(vec![])
     }
+
+    fn get_transaction_history(
+        &self,
+        _filter: &TransactionHistoryFilter<Self::AccountId>,
+        _offset: usize,
+        _limit: usize,
+    ) -> Result<Vec<TransactionHistoryEntry<Self::AccountId>>, Self::Error> {
+        Ok(vec![])
+    }
 }
 
 impl WalletWrite for MockWalletDb {
@@ -2622,6 +2711,36 @@ impl WalletWrite for MockWalletDb {
         todo!()
     }
 
+    fn delete_account(&mut self, _account: Self::AccountId) -> Result<(), Self::Error> {
+        Err(())
+    }
+
+    fn set_account_name(
+        &mut self,
+        _account: Self::AccountId,
+        _name: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        Err(())
+    }
+
+    fn set_account_key_source(
+        &mut self,
+        _account: Self::AccountId,
+        _key_source: Option<&str>,
+        _derivation: Option<Zip32Derivation>,
+    ) -> Result<(), Self::Error> {
+        Err(())
+    }
+
+    fn set_account_metadata(
+        &mut self,
+        _account: Self::AccountId,
+        _key: &str,
+        _value: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        Err(())
+    }
+
     fn get_next_available_address(
         &mut self,
         _account: Self::AccountId,
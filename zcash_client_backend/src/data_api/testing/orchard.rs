@@ -1,11 +1,11 @@
-use std::hash::Hash;
+use std::{collections::BTreeSet, hash::Hash};
 
 use ::orchard::{
     keys::{FullViewingKey, SpendingKey},
     note_encryption::OrchardDomain,
     tree::MerkleHashOrchard,
 };
-use incrementalmerkletree::{Hashable, Level};
+use incrementalmerkletree::{Hashable, Level, Position};
 use shardtree::error::ShardTreeError;
 
 use zcash_keys::{
@@ -100,6 +100,13 @@ impl ShieldedPoolTester for OrchardPoolTester {
             .put_orchard_subtree_roots(start_index, roots)
     }
 
+    fn marked_positions<Cache, DbT: WalletTest + WalletCommitmentTrees, P>(
+        st: &mut TestState<Cache, DbT, P>,
+    ) -> Result<BTreeSet<Position>, ShardTreeError<<DbT as WalletCommitmentTrees>::Error>> {
+        st.wallet_mut()
+            .with_orchard_tree_mut(|tree| tree.marked_positions())
+    }
+
     fn next_subtree_index<A: Hash + Eq>(s: &WalletSummary<A>) -> u64 {
         s.next_orchard_subtree_index()
     }
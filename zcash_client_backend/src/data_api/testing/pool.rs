@@ -1,5 +1,6 @@
 use std::{
     cmp::Eq,
+    collections::BTreeSet,
     convert::Infallible,
     hash::Hash,
     num::{NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize},
@@ -18,15 +19,15 @@ use zcash_primitives::{
     transaction::{
         components::amount::NonNegativeAmount,
         fees::zip317::{FeeRule as Zip317FeeRule, MARGINAL_FEE, MINIMUM_FEE},
-        Transaction,
+        Transaction, TxId,
     },
 };
 use zcash_protocol::{
     consensus::{self, BlockHeight, NetworkUpgrade, Parameters},
     local_consensus::LocalNetwork,
     memo::{Memo, MemoBytes},
-    value::Zatoshis,
-    ShieldedProtocol,
+    value::{ZatBalance, Zatoshis},
+    PoolType, ShieldedProtocol,
 };
 use zip32::Scope;
 use zip321::{Payment, TransactionRequest};
@@ -44,7 +45,8 @@ use crate::{
             decrypt_and_store_transaction, input_selection::GreedyInputSelector, TransferErrT,
         },
         Account as _, AccountBirthday, BoundedU8, DecryptedTransaction, InputSource, NoteFilter,
-        Ratio, WalletCommitmentTrees, WalletRead, WalletSummary, WalletTest, WalletWrite,
+        Ratio, TransactionDirection, TransactionHistoryFilter, WalletCommitmentTrees, WalletRead,
+        WalletSummary, WalletTest, WalletWrite,
     },
     decrypt_transaction,
     fees::{
@@ -78,12 +80,8 @@ use {
         },
     },
     zcash_proofs::prover::LocalTxProver,
-    zcash_protocol::value::ZatBalance,
 };
 
-#[cfg(feature = "orchard")]
-use crate::PoolType;
-
 /// Trait that exposes the pool-specific types and operations necessary to run the
 /// single-shielded-pool tests on a given pool.
 ///
@@ -139,6 +137,11 @@ pub trait ShieldedPoolTester {
         roots: &[CommitmentTreeRoot<Self::MerkleTreeHash>],
     ) -> Result<(), ShardTreeError<<DbT as WalletCommitmentTrees>::Error>>;
 
+    /// Returns the positions that are marked for witnessing in the note commitment tree.
+    fn marked_positions<Cache, DbT: WalletTest + WalletCommitmentTrees, P>(
+        st: &mut TestState<Cache, DbT, P>,
+    ) -> Result<BTreeSet<Position>, ShardTreeError<<DbT as WalletCommitmentTrees>::Error>>;
+
     fn next_subtree_index<A: Hash + Eq>(s: &WalletSummary<A>) -> u64;
 
     #[allow(clippy::type_complexity)]
@@ -316,6 +319,298 @@ pub fn send_single_step_proposed_transfer<T: ShieldedPoolTester>(
     );
 }
 
+/// Tests that a single-step proposal can be turned into a PCZT, proven and signed
+/// independently, and then extracted and stored in the wallet.
+///
+/// The PCZT is created with [`OvkPolicy::Discard`], so the stored payment and its memo
+/// can only have been recovered from the PCZT itself.
+#[cfg(feature = "pczt")]
+pub fn pczt_single_step<T: ShieldedPoolTester>(dsf: impl DataStoreFactory, cache: impl TestCache) {
+    use pczt::roles::{
+        combiner::Combiner, prover::Prover, signer::Signer, spend_finalizer::SpendFinalizer,
+        updater::Updater,
+    };
+
+    use crate::data_api::wallet::{
+        create_pczt_from_proposal, extract_and_store_transaction_from_pczt,
+    };
+
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(dsf)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+
+    let account = st.test_account().cloned().unwrap();
+    let dfvk = T::test_account_fvk(&st);
+
+    // Add funds to the wallet in a single note
+    let value = Zatoshis::const_from_u64(60000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(st.get_spendable_balance(account.id(), 1), value);
+
+    let to = T::sk_default_address(&T::sk(&[0xf5; 32]));
+    let memo = "Test PCZT memo".parse::<Memo>().unwrap();
+    let proposal = st
+        .propose_standard_transfer::<Infallible>(
+            account.id(),
+            StandardFeeRule::Zip317,
+            NonZeroU32::new(1).unwrap(),
+            &to,
+            Zatoshis::const_from_u64(10000),
+            Some(memo.clone().into()),
+            None,
+            T::SHIELDED_PROTOCOL,
+        )
+        .unwrap();
+
+    // Create the PCZT using only the account's viewing keys.
+    let network = *st.network();
+    let pczt = create_pczt_from_proposal::<_, _, Infallible, _, Infallible, _>(
+        st.wallet_mut(),
+        &network,
+        account.id(),
+        OvkPolicy::Discard,
+        &proposal,
+    )
+    .unwrap();
+    let sapling_spends = pczt.sapling().spends().len();
+    let orchard_actions = pczt.orchard().actions().len();
+
+    // Prove and sign independently.
+    let usk = account.usk();
+    let proven = {
+        let mut updater = Updater::new(pczt.clone());
+        for index in 0..sapling_spends {
+            updater
+                .set_sapling_proof_generation_key(index, usk.sapling().expsk.proof_generation_key())
+                .unwrap();
+        }
+
+        let prover = LocalTxProver::bundled();
+        let mut pczt_prover = Prover::new(updater.finish());
+        pczt_prover
+            .create_sapling_proofs(&prover, &prover, OsRng)
+            .unwrap();
+        pczt_prover
+            .create_orchard_proof(&orchard::circuit::ProvingKey::build(), OsRng)
+            .unwrap();
+        pczt_prover.finish()
+    };
+    let signed = {
+        let mut signer = Signer::new(pczt).unwrap();
+        for index in 0..sapling_spends {
+            signer
+                .sign_sapling(index, &usk.sapling().expsk.ask, OsRng)
+                .unwrap();
+        }
+        let orchard_ask = orchard::keys::SpendAuthorizingKey::from(usk.orchard());
+        for index in 0..orchard_actions {
+            // Dummy spends were already signed by the Constructor.
+            assert_matches!(
+                signer.sign_orchard(index, &orchard_ask, OsRng),
+                Ok(()) | Err(pczt::roles::Error::WrongSpendingKey)
+            );
+        }
+        signer.finish()
+    };
+
+    let pczt = SpendFinalizer::new(Combiner::new(vec![proven, signed]).combine().unwrap())
+        .finalize_spends()
+        .unwrap();
+    let txid =
+        extract_and_store_transaction_from_pczt::<_, Infallible>(st.wallet_mut(), pczt).unwrap();
+
+    // The transaction was stored. The payment was not encrypted to any OVK, so only the
+    // change is recoverable with the account's keys.
+    let tx = st
+        .wallet()
+        .get_transaction(txid)
+        .unwrap()
+        .expect("Extracted transaction was stored.");
+    let ufvks = [(account.id(), usk.to_unified_full_viewing_key())]
+        .into_iter()
+        .collect();
+    let d_tx = decrypt_transaction(st.network(), h + 1, &tx, &ufvks);
+    assert_eq!(T::decrypted_pool_outputs_count(&d_tx), 1);
+
+    // The payment and the change were stored as sent, along with the payment's memo and
+    // the fee, as they would have been by `create_proposed_transactions`.
+    let sent_note_ids = st
+        .wallet()
+        .get_sent_note_ids(&txid, T::SHIELDED_PROTOCOL)
+        .unwrap();
+    assert_eq!(sent_note_ids.len(), 2);
+    let sent_memos = sent_note_ids
+        .into_iter()
+        .map(|id| st.wallet().get_memo(id).unwrap())
+        .collect::<Vec<_>>();
+    assert!(sent_memos.contains(&Some(memo)));
+    assert!(sent_memos.contains(&Some(Memo::Empty)));
+    let tx_summary = st
+        .wallet()
+        .get_tx_history()
+        .unwrap()
+        .into_iter()
+        .find(|summary| summary.txid() == txid)
+        .unwrap();
+    assert_eq!(tx_summary.fee_paid(), Some(Zatoshis::const_from_u64(10000)));
+
+    // Once mined, the wallet's balance reflects the payment and the fee.
+    let (h, _) = st.generate_next_block_including(txid);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(
+        st.get_total_balance(account.id()),
+        (value - Zatoshis::const_from_u64(10000 + 10000)).unwrap()
+    );
+}
+
+/// Tests that a PCZT can be created for an account whose UFVK only has a component for
+/// the shielded pool that the proposal spends from and pays to.
+#[cfg(feature = "pczt")]
+pub fn pczt_single_step_sapling_only_ufvk(dsf: impl DataStoreFactory, cache: impl TestCache) {
+    use zcash_keys::keys::UnifiedFullViewingKey;
+
+    use super::sapling::SaplingPoolTester;
+    use crate::data_api::{wallet::create_pczt_from_proposal, AccountPurpose};
+
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(dsf)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+
+    // Import an account that only has a Sapling viewing key.
+    let birthday = st.test_account().unwrap().birthday().clone();
+    let usk =
+        UnifiedSpendingKey::from_seed(st.network(), &[0x42; 32], zip32::AccountId::ZERO).unwrap();
+    let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
+    let ufvk = UnifiedFullViewingKey::new(
+        #[cfg(feature = "transparent-inputs")]
+        None,
+        Some(dfvk.clone()),
+        #[cfg(feature = "orchard")]
+        None,
+    )
+    .unwrap();
+    let account = st
+        .wallet_mut()
+        .import_account_ufvk(&ufvk, &birthday, AccountPurpose::Spending)
+        .unwrap();
+
+    let value = Zatoshis::const_from_u64(60000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(st.get_spendable_balance(account.id(), 1), value);
+
+    let to = SaplingPoolTester::sk_default_address(&SaplingPoolTester::sk(&[0xf5; 32]));
+    let proposal = st
+        .propose_standard_transfer::<Infallible>(
+            account.id(),
+            StandardFeeRule::Zip317,
+            NonZeroU32::new(1).unwrap(),
+            &to,
+            Zatoshis::const_from_u64(10000),
+            None,
+            None,
+            ShieldedProtocol::Sapling,
+        )
+        .unwrap();
+
+    let network = *st.network();
+    for ovk_policy in [OvkPolicy::Sender, OvkPolicy::Discard] {
+        let pczt = create_pczt_from_proposal::<_, _, Infallible, _, Infallible, _>(
+            st.wallet_mut(),
+            &network,
+            account.id(),
+            ovk_policy,
+            &proposal,
+        )
+        .unwrap();
+        assert_eq!(pczt.sapling().spends().len(), 1);
+        assert!(pczt.transparent().inputs().is_empty());
+    }
+}
+
+#[cfg(feature = "pczt")]
+pub fn send_single_step_with_signer<T: ShieldedPoolTester>(
+    dsf: impl DataStoreFactory,
+    cache: impl TestCache,
+) {
+    use crate::data_api::wallet::{
+        create_proposed_transactions_with_signer, signer::UnifiedSpendingKeySigner,
+    };
+
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(dsf)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+
+    let account = st.test_account().cloned().unwrap();
+    let dfvk = T::test_account_fvk(&st);
+
+    // Add funds to the wallet in a single note
+    let value = Zatoshis::const_from_u64(60000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(st.get_spendable_balance(account.id(), 1), value);
+
+    let to = T::sk_default_address(&T::sk(&[0xf5; 32]));
+    let prover = LocalTxProver::bundled();
+    let usk = account.usk().clone();
+    let signer = UnifiedSpendingKeySigner::new(&usk);
+    let network = *st.network();
+
+    // Send twice, so that the second transaction spends the change note (which was
+    // received by the internal scope of the account).
+    for i in 1..=2u64 {
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &to,
+                Zatoshis::const_from_u64(10000),
+                None,
+                None,
+                T::SHIELDED_PROTOCOL,
+            )
+            .unwrap();
+
+        let txids =
+            create_proposed_transactions_with_signer::<_, _, Infallible, _, Infallible, _, _>(
+                st.wallet_mut(),
+                &network,
+                &prover,
+                &prover,
+                &signer,
+                account.id(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap();
+        assert_eq!(txids.len(), 1);
+
+        // The payment and the change were stored as sent notes.
+        assert_eq!(
+            st.wallet()
+                .get_sent_note_ids(txids.first(), T::SHIELDED_PROTOCOL)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let (h, _) = st.generate_next_block_including(*txids.first());
+        st.scan_cached_blocks(h, 1);
+        assert_eq!(
+            st.get_total_balance(account.id()),
+            (value - Zatoshis::const_from_u64(i * (10000 + 10000))).unwrap()
+        );
+    }
+}
+
 pub fn send_with_multiple_change_outputs<T: ShieldedPoolTester>(
     dsf: impl DataStoreFactory,
     cache: impl TestCache,
@@ -2877,6 +3172,290 @@ pub fn scan_cached_blocks_finds_received_notes<T: ShieldedPoolTester, DSF>(
     );
 }
 
+pub fn account_deletion<T: ShieldedPoolTester, DSF>(ds_factory: DSF, cache: impl TestCache)
+where
+    DSF: DataStoreFactory,
+    <DSF as DataStoreFactory>::AccountId: std::fmt::Debug,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(ds_factory)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+
+    let account = st.test_account().cloned().unwrap();
+    let dfvk = T::test_account_fvk(&st);
+
+    // Add a second account, which is not deleted.
+    let birthday = AccountBirthday::from_sapling_activation(st.network(), BlockHash([0; 32]));
+    let (other_id, other_usk) = st
+        .wallet_mut()
+        .create_account(&Secret::new(vec![1; 32]), &birthday)
+        .unwrap();
+    let other_dfvk = T::sk_to_fvk(T::usk_to_sk(&other_usk));
+
+    // Give the account a note, so that it has data in the wallet to be removed.
+    let value = NonNegativeAmount::const_from_u64(100000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(st.get_total_balance(account.id()), value);
+
+    // Sends a payment of the given amount with a memo, and mines the transaction.
+    let send = |st: &mut TestState<_, DSF::DataStore, _>,
+                from: DSF::AccountId,
+                usk: &UnifiedSpendingKey,
+                to: &Address,
+                amount: u64,
+                memo: &[u8]| {
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                from,
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                to,
+                NonNegativeAmount::const_from_u64(amount),
+                Some(MemoBytes::from_bytes(memo).unwrap()),
+                None,
+                T::SHIELDED_PROTOCOL,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                usk,
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+        let (h, _) = st.generate_next_block_including(txid);
+        st.scan_cached_blocks(h, 1);
+        txid
+    };
+    let memos = |st: &TestState<_, DSF::DataStore, _>, txid: &TxId| {
+        st.wallet()
+            .get_sent_note_ids(txid, T::SHIELDED_PROTOCOL)
+            .unwrap()
+            .into_iter()
+            .filter_map(|note_id| st.wallet().get_memo(note_id).unwrap())
+            .filter(|memo| *memo != Memo::Empty)
+            .collect::<Vec<_>>()
+    };
+
+    // The account pays an external recipient, and the two accounts pay each other.
+    let external = T::sk_default_address(&T::sk(&[0xf5; 32]));
+    let external_tx = send(
+        &mut st,
+        account.id(),
+        account.usk(),
+        &external,
+        10000,
+        b"external",
+    );
+    let to_other_tx = send(
+        &mut st,
+        account.id(),
+        account.usk(),
+        &T::fvk_default_address(&other_dfvk),
+        30000,
+        b"to other",
+    );
+    let from_other_tx = send(
+        &mut st,
+        other_id,
+        &other_usk,
+        &T::fvk_default_address(&dfvk),
+        10000,
+        b"from other",
+    );
+
+    let height = st.wallet().chain_height().unwrap().unwrap();
+    let spendable_positions = |st: &TestState<_, DSF::DataStore, _>, account_id| {
+        T::select_spendable_notes(st, account_id, value, height, &[])
+            .unwrap()
+            .iter()
+            .map(|note| note.note_commitment_tree_position())
+            .collect::<BTreeSet<_>>()
+    };
+    let account_positions = spendable_positions(&st, account.id());
+    let other_positions = spendable_positions(&st, other_id);
+    assert!(!account_positions.is_empty());
+    assert!(!other_positions.is_empty());
+    let marked = T::marked_positions(&mut st).unwrap();
+    assert!(marked.is_superset(&account_positions));
+    assert!(marked.is_superset(&other_positions));
+
+    let other_balance = st.get_total_balance(other_id);
+    let from_other_outputs = st.wallet().get_sent_outputs(&from_other_tx).unwrap().len();
+    assert!(from_other_outputs > 0);
+    assert_eq!(memos(&st, &external_tx).len(), 1);
+    assert_eq!(memos(&st, &from_other_tx).len(), 1);
+
+    st.wallet_mut().delete_account(account.id()).unwrap();
+
+    // The account is no longer known to the wallet, and holds no balance.
+    assert_eq!(st.wallet().get_account_ids().unwrap(), vec![other_id]);
+    assert!(st.wallet().get_account(account.id()).unwrap().is_none());
+    assert!(st
+        .get_wallet_summary(0)
+        .map_or(true, |s| s.account_balances().get(&account.id()).is_none()));
+
+    // The account's notes are no longer marked in the note commitment tree, but the other
+    // account's notes are.
+    let marked = T::marked_positions(&mut st).unwrap();
+    assert!(marked.is_disjoint(&account_positions));
+    assert!(marked.is_superset(&other_positions));
+
+    // The outputs that the account sent, and their memos, are removed. The outputs that
+    // the other account sent to it are retained, along with their memos.
+    assert!(st
+        .wallet()
+        .get_sent_outputs(&external_tx)
+        .unwrap()
+        .is_empty());
+    assert!(st
+        .wallet()
+        .get_sent_outputs(&to_other_tx)
+        .unwrap()
+        .is_empty());
+    assert!(memos(&st, &external_tx).is_empty());
+    assert_eq!(
+        st.wallet().get_sent_outputs(&from_other_tx).unwrap().len(),
+        from_other_outputs
+    );
+    assert_eq!(
+        memos(&st, &from_other_tx),
+        vec![Memo::from_bytes(b"from other").unwrap()]
+    );
+
+    // The other account is unaffected.
+    assert_eq!(st.get_total_balance(other_id), other_balance);
+
+    // Deleting the account a second time fails.
+    assert!(st.wallet_mut().delete_account(account.id()).is_err());
+
+    // The wallet can continue to scan blocks.
+    let (h, _, _) = st.generate_next_block(&other_dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(
+        st.get_total_balance(other_id),
+        (other_balance + value).unwrap()
+    );
+}
+
+pub fn transaction_history<T: ShieldedPoolTester, DSF>(ds_factory: DSF, cache: impl TestCache)
+where
+    DSF: DataStoreFactory,
+    <DSF as DataStoreFactory>::AccountId: std::fmt::Debug,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(ds_factory)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+
+    let account = st.test_account().cloned().unwrap();
+    let dfvk = T::test_account_fvk(&st);
+
+    // Receive a note, and then spend it to an external recipient.
+    let value = NonNegativeAmount::const_from_u64(50000);
+    let (received_height, _, nf) =
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(received_height, 1);
+
+    let not_our_key = T::sk_to_fvk(&T::sk(&[0xf5; 32]));
+    let to = T::fvk_default_address(&not_our_key);
+    let sent_value = NonNegativeAmount::const_from_u64(20000);
+    let (spent_height, _) = st.generate_next_block_spending(&dfvk, (nf, value), to, sent_value);
+    st.scan_cached_blocks(spent_height, 1);
+
+    let history = |st: &TestState<_, DSF::DataStore, _>, filter, offset, limit| {
+        st.wallet()
+            .get_transaction_history(&filter, offset, limit)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.mined_height().unwrap())
+            .collect::<Vec<_>>()
+    };
+    let all = TransactionHistoryFilter::new;
+
+    // The most recently mined transaction is returned first.
+    assert_eq!(
+        history(&st, all(), 0, 10),
+        vec![spent_height, received_height]
+    );
+    assert_eq!(
+        history(&st, all().with_account(account.id()), 0, 10),
+        vec![spent_height, received_height]
+    );
+
+    // Pagination
+    assert_eq!(history(&st, all(), 0, 1), vec![spent_height]);
+    assert_eq!(history(&st, all(), 1, 1), vec![received_height]);
+    assert_eq!(history(&st, all(), 2, 1), vec![]);
+    assert_eq!(history(&st, all(), 0, 0), vec![]);
+
+    // Filtering by height, pool, direction and memo presence
+    assert_eq!(
+        history(
+            &st,
+            all().with_mined_heights(received_height..spent_height),
+            0,
+            10
+        ),
+        vec![received_height]
+    );
+    assert_eq!(
+        history(
+            &st,
+            all().with_pool(PoolType::Shielded(T::SHIELDED_PROTOCOL)),
+            0,
+            10
+        ),
+        vec![spent_height, received_height]
+    );
+    assert_eq!(
+        history(&st, all().with_pool(PoolType::TRANSPARENT), 0, 10),
+        vec![]
+    );
+    assert_eq!(
+        history(&st, all().with_direction(TransactionDirection::Sent), 0, 10),
+        vec![spent_height]
+    );
+    assert_eq!(
+        history(
+            &st,
+            all().with_direction(TransactionDirection::Received),
+            0,
+            10
+        ),
+        vec![received_height]
+    );
+    assert_eq!(history(&st, all().with_memo_presence(true), 0, 10), vec![]);
+    assert_eq!(
+        history(&st, all().with_memo_presence(false), 0, 10),
+        vec![spent_height, received_height]
+    );
+
+    // Each entry describes the effect of the transaction on the account, along with the
+    // outputs that the account received.
+    let entries = st.wallet().get_transaction_history(&all(), 0, 10).unwrap();
+    assert_eq!(entries[0].account_id(), &account.id());
+    assert_eq!(entries[0].direction(), TransactionDirection::Sent);
+    assert_eq!(entries[0].spent_note_count(), 1);
+    assert_eq!(
+        entries[0].account_value_delta(),
+        -ZatBalance::from(sent_value)
+    );
+    assert_eq!(entries[1].direction(), TransactionDirection::Received);
+    assert_eq!(entries[1].account_value_delta(), ZatBalance::from(value));
+    assert_eq!(entries[1].outputs().len(), 1);
+    let output = &entries[1].outputs()[0];
+    assert_eq!(output.pool(), PoolType::Shielded(T::SHIELDED_PROTOCOL));
+    assert_eq!(output.to_account_id(), Some(&account.id()));
+    assert_eq!(output.to_address(), None);
+    assert_eq!(output.value(), value);
+    assert!(!output.is_change());
+}
+
 // TODO: This test can probably be entirely removed, as the following test duplicates it entirely.
 pub fn scan_cached_blocks_finds_change_notes<T: ShieldedPoolTester, DSF>(
     ds_factory: DSF,
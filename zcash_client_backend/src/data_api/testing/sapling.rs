@@ -1,6 +1,6 @@
-use std::hash::Hash;
+use std::{collections::BTreeSet, hash::Hash};
 
-use incrementalmerkletree::{Hashable, Level};
+use incrementalmerkletree::{Hashable, Level, Position};
 use sapling::{
     note_encryption::try_sapling_output_recovery,
     zip32::{DiversifiableFullViewingKey, ExtendedSpendingKey},
@@ -84,6 +84,13 @@ impl ShieldedPoolTester for SaplingPoolTester {
             .put_sapling_subtree_roots(start_index, roots)
     }
 
+    fn marked_positions<Cache, DbT: WalletTest + WalletCommitmentTrees, P>(
+        st: &mut TestState<Cache, DbT, P>,
+    ) -> Result<BTreeSet<Position>, ShardTreeError<<DbT as WalletCommitmentTrees>::Error>> {
+        st.wallet_mut()
+            .with_sapling_tree_mut(|tree| tree.marked_positions())
+    }
+
     fn next_subtree_index<A: Hash + Eq>(s: &WalletSummary<A>) -> u64 {
         s.next_sapling_subtree_index()
     }
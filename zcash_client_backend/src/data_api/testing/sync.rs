@@ -0,0 +1,956 @@
+//! Tests of the wallet synchronization flow in [`crate::sync`], run against a
+//! [`MemoryChainSource`] that serves the blocks generated by a [`TestState`].
+//!
+//! [`TestState`]: super::TestState
+
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::ops::Range;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures_util::{
+    stream::{self, BoxStream},
+    FutureExt, StreamExt,
+};
+use zcash_primitives::{
+    block::BlockHash,
+    consensus::BlockHeight,
+    transaction::{components::amount::NonNegativeAmount, TxId},
+};
+
+use crate::{
+    data_api::{
+        chain::{self, BlockCache, BlockSource},
+        scanning::ScanRange,
+        testing::{AddressType, DataStoreFactory, TestBuilder, TestCache},
+        Account as _, TransactionDataRequest, WalletCommitmentTrees, WalletRead,
+    },
+    proto::{
+        compact_formats::CompactBlock,
+        service::{GetAddressUtxosReply, RawTransaction, SubtreeRoot, TreeState},
+    },
+    sync::{
+        self, source::memory::MemoryChainSource, ChainDataSource, SyncEvent, SyncMonitor,
+        SyncOptions, TransactionDataOptions,
+    },
+    ShieldedProtocol,
+};
+
+use super::pool::ShieldedPoolTester;
+
+#[cfg(feature = "transparent-inputs")]
+use {
+    super::TestState,
+    crate::{
+        data_api::{InputSource, WalletWrite},
+        wallet::WalletTransparentOutput,
+    },
+    incrementalmerkletree::Hashable,
+    rand_core::OsRng,
+    zcash_primitives::{
+        legacy::TransparentAddress,
+        memo::MemoBytes,
+        transaction::{
+            builder::{BuildConfig, Builder},
+            components::{transparent::OutPoint, TxOut},
+        },
+    },
+};
+
+/// Builds a multi-threaded runtime on which to run the synchronization flow.
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_time()
+        .build()
+        .unwrap()
+}
+
+/// A [`ChainDataSource`] that serves data from a [`MemoryChainSource`].
+///
+/// - Requests for the transaction with ID `unavailable_txid` fail.
+/// - Requests for the transaction with ID `malformed_txid` return data that cannot be
+///   parsed as a transaction.
+/// - Requests for a block range starting at `failing_range_start` fail.
+/// - Requests for block ranges starting after `failing_range_start` never complete. The
+///   number of such requests that have been made is counted in `stalled`, and the number
+///   that are still in progress in `stalled_in_progress`.
+/// - If `mempool_stays_open` is set, the mempool stream does not end once the mempool's
+///   transactions have been streamed, as if no new block were mined.
+#[derive(Clone)]
+struct TestSource {
+    inner: MemoryChainSource,
+    unavailable_txid: Option<TxId>,
+    malformed_txid: Option<TxId>,
+    failing_range_start: Option<BlockHeight>,
+    mempool_stays_open: bool,
+    stalled: Arc<AtomicUsize>,
+    stalled_in_progress: Arc<AtomicUsize>,
+}
+
+impl TestSource {
+    fn new(inner: MemoryChainSource) -> Self {
+        TestSource {
+            inner,
+            unavailable_txid: None,
+            malformed_txid: None,
+            failing_range_start: None,
+            mempool_stays_open: false,
+            stalled: Arc::new(AtomicUsize::new(0)),
+            stalled_in_progress: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/// Counts a request that is in progress until it is dropped.
+struct InProgress(Arc<AtomicUsize>);
+
+impl InProgress {
+    fn new(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        InProgress(count.clone())
+    }
+}
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl ChainDataSource for TestSource {
+    type Error = String;
+
+    async fn get_latest_block(&mut self) -> Result<BlockHeight, Self::Error> {
+        self.inner
+            .get_latest_block()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get_block_range(
+        &mut self,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<CompactBlock>, Self::Error> {
+        match self.failing_range_start {
+            Some(start) if range.start == start => {
+                return Err(format!("Blocks from {} are unavailable", start));
+            }
+            Some(start) if range.start > start => {
+                self.stalled.fetch_add(1, Ordering::SeqCst);
+                let _in_progress = InProgress::new(&self.stalled_in_progress);
+                std::future::pending::<()>().await;
+            }
+            _ => (),
+        }
+        self.inner
+            .get_block_range(range)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get_tree_state(&mut self, height: BlockHeight) -> Result<TreeState, Self::Error> {
+        self.inner
+            .get_tree_state(height)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get_subtree_roots(
+        &mut self,
+        protocol: ShieldedProtocol,
+        start_index: u32,
+    ) -> Result<Vec<SubtreeRoot>, Self::Error> {
+        self.inner
+            .get_subtree_roots(protocol, start_index)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get_transaction(&mut self, txid: TxId) -> Result<Option<RawTransaction>, Self::Error> {
+        if self.unavailable_txid == Some(txid) {
+            return Err(format!("Transaction {} is unavailable", txid));
+        }
+        if self.malformed_txid == Some(txid) {
+            return Ok(Some(RawTransaction {
+                data: vec![0xff; 4],
+                ..Default::default()
+            }));
+        }
+        self.inner
+            .get_transaction(txid)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get_address_utxos(
+        &mut self,
+        addresses: Vec<String>,
+        start_height: BlockHeight,
+    ) -> Result<Vec<GetAddressUtxosReply>, Self::Error> {
+        self.inner
+            .get_address_utxos(addresses, start_height)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get_taddress_transactions(
+        &mut self,
+        address: String,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<RawTransaction>, Self::Error> {
+        self.inner
+            .get_taddress_transactions(address, range)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get_mempool_stream(
+        &mut self,
+    ) -> Result<BoxStream<'_, Result<RawTransaction, Self::Error>>, Self::Error> {
+        let mempool = self
+            .inner
+            .get_mempool_stream()
+            .await
+            .map_err(|e| e.to_string())?
+            .map(|tx| tx.map_err(|e| e.to_string()));
+        Ok(if self.mempool_stays_open {
+            mempool.chain(stream::pending()).boxed()
+        } else {
+            mempool.boxed()
+        })
+    }
+}
+
+/// A [`BlockCache`] that records the heights of the blocks inserted into it.
+///
+/// If `cancel_on_insert` is set, the monitor is cancelled once blocks have been inserted.
+struct RecordingCache<C> {
+    inner: C,
+    inserted: Mutex<BTreeSet<BlockHeight>>,
+    cancel_on_insert: Option<SyncMonitor>,
+}
+
+impl<C> RecordingCache<C> {
+    fn new(inner: C) -> Self {
+        RecordingCache {
+            inner,
+            inserted: Mutex::new(BTreeSet::new()),
+            cancel_on_insert: None,
+        }
+    }
+
+    fn inserted(&self) -> BTreeSet<BlockHeight> {
+        self.inserted.lock().unwrap().clone()
+    }
+}
+
+impl<C: BlockSource> BlockSource for RecordingCache<C> {
+    type Error = C::Error;
+
+    fn with_blocks<F, WalletErrT>(
+        &self,
+        from_height: Option<BlockHeight>,
+        limit: Option<usize>,
+        with_block: F,
+    ) -> Result<(), chain::error::Error<WalletErrT, Self::Error>>
+    where
+        F: FnMut(CompactBlock) -> Result<(), chain::error::Error<WalletErrT, Self::Error>>,
+    {
+        self.inner.with_blocks(from_height, limit, with_block)
+    }
+}
+
+#[async_trait]
+impl<C: BlockCache> BlockCache for RecordingCache<C>
+where
+    C::Error: Send,
+{
+    fn get_tip_height(
+        &self,
+        range: Option<&ScanRange>,
+    ) -> Result<Option<BlockHeight>, Self::Error> {
+        self.inner.get_tip_height(range)
+    }
+
+    async fn read(&self, range: &ScanRange) -> Result<Vec<CompactBlock>, Self::Error> {
+        self.inner.read(range).await
+    }
+
+    async fn insert(&self, compact_blocks: Vec<CompactBlock>) -> Result<(), Self::Error> {
+        self.inserted
+            .lock()
+            .unwrap()
+            .extend(compact_blocks.iter().map(|block| block.height()));
+        self.inner.insert(compact_blocks).await?;
+        if let Some(monitor) = &self.cancel_on_insert {
+            monitor.cancel();
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, range: ScanRange) -> Result<(), Self::Error> {
+        self.inner.delete(range).await
+    }
+}
+
+/// Tests that the spend of a note is detected when the batch of blocks that spends the
+/// note is scanned concurrently with the batch that receives it.
+pub fn run_detects_spends_across_concurrent_batches<T: ShieldedPoolTester, DSF>(
+    ds_factory: DSF,
+    cache: impl TestCache,
+    sync_cache: impl BlockCache<Error = impl std::error::Error + Send + Sync + 'static>,
+) where
+    DSF: DataStoreFactory,
+    DSF::DsError: std::error::Error + Send + Sync + 'static,
+    <DSF::DataStore as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(ds_factory)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+    let account_id = st.test_account().unwrap().id();
+    let fvk = T::test_account_fvk(&st);
+    let not_our_key = T::sk_to_fvk(&T::sk(&[0xf5; 32]));
+    let to = T::fvk_default_address(&not_our_key);
+
+    // With batches of 5 blocks, the first batch is downloaded and scanned on its own, and
+    // the next four batches are then scanned concurrently. The note received in the
+    // second batch is spent in the third.
+    let value = NonNegativeAmount::const_from_u64(50000);
+    let sent_value = NonNegativeAmount::const_from_u64(20000);
+    let mut received_nf = None;
+    let mut chain_end = st.sapling_activation_height();
+    for i in 0..25 {
+        let height = match (i, received_nf) {
+            (12, Some(nf)) => {
+                st.generate_next_block_spending(&fvk, (nf, value), to.clone(), sent_value)
+                    .0
+            }
+            _ => {
+                let (height, _, nf) =
+                    st.generate_next_block(&fvk, AddressType::DefaultExternal, value);
+                if i == 6 {
+                    received_nf = Some(nf);
+                }
+                height
+            }
+        };
+        chain_end = height + 1;
+    }
+
+    let mut source = TestSource::new(st.chain_source());
+    let options = SyncOptions {
+        batch_size: 5,
+        max_concurrent_scans: 4,
+        ..Default::default()
+    };
+
+    let network = *st.network();
+    runtime()
+        .block_on(sync::run_with_options(
+            &mut source,
+            &network,
+            &sync_cache,
+            st.wallet_mut(),
+            &options,
+            &SyncMonitor::default(),
+        ))
+        .unwrap();
+
+    assert_eq!(
+        st.wallet()
+            .block_fully_scanned()
+            .unwrap()
+            .map(|meta| meta.block_height()),
+        Some(chain_end - 1)
+    );
+    // 24 notes were received, one of which was spent with change.
+    assert_eq!(
+        st.get_total_balance(account_id),
+        ((value * 23u64).unwrap() + (value - sent_value).unwrap()).unwrap()
+    );
+}
+
+/// Tests that the downloads that are in progress when a sync fails are aborted.
+pub fn run_aborts_downloads_on_error<T: ShieldedPoolTester, DSF>(
+    ds_factory: DSF,
+    cache: impl TestCache,
+    sync_cache: impl BlockCache<Error = impl std::error::Error + Send + Sync + 'static>,
+) where
+    DSF: DataStoreFactory,
+    DSF::DsError: std::error::Error + Send + Sync + 'static,
+    <DSF::DataStore as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(ds_factory)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+    let fvk = T::test_account_fvk(&st);
+
+    let value = NonNegativeAmount::const_from_u64(50000);
+    for _ in 0..25 {
+        st.generate_next_block(&fvk, AddressType::DefaultExternal, value);
+    }
+
+    // The first batch is scanned, after which the next four batches are downloaded
+    // concurrently. The download of the second batch fails while the others are stalled.
+    let mut source = TestSource::new(st.chain_source());
+    source.failing_range_start = Some(st.sapling_activation_height() + 5);
+    let options = SyncOptions {
+        batch_size: 5,
+        ..Default::default()
+    };
+
+    let network = *st.network();
+    let rt = runtime();
+    let result = rt.block_on(sync::run_with_options(
+        &mut source,
+        &network,
+        &sync_cache,
+        st.wallet_mut(),
+        &options,
+        &SyncMonitor::default(),
+    ));
+    assert!(matches!(result, Err(sync::Error::Server(_))));
+    assert_eq!(
+        st.wallet()
+            .block_fully_scanned()
+            .unwrap()
+            .map(|meta| meta.block_height()),
+        Some(st.sapling_activation_height() + 4)
+    );
+
+    // The stalled downloads are aborted once the sync has returned.
+    rt.block_on(async {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while source.stalled_in_progress.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    });
+    assert!(source.stalled.load(Ordering::SeqCst) > 0);
+    assert_eq!(source.stalled_in_progress.load(Ordering::SeqCst), 0);
+}
+
+/// Tests that [`sync::run`] scans the chain up to its tip via the block cache, and picks up
+/// where it left off once the chain has grown.
+pub fn run_syncs_to_chain_tip<T: ShieldedPoolTester, DSF>(
+    ds_factory: DSF,
+    cache: impl TestCache,
+    sync_cache: impl BlockCache<Error = impl std::error::Error + Send + Sync + 'static>,
+) where
+    DSF: DataStoreFactory,
+    DSF::DsError: std::error::Error + Send + Sync + 'static,
+    <DSF::DataStore as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(ds_factory)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+    let account_id = st.test_account().unwrap().id();
+    let fvk = T::test_account_fvk(&st);
+    let network = *st.network();
+    let rt = runtime();
+    let sync_cache = RecordingCache::new(sync_cache);
+
+    let value = NonNegativeAmount::const_from_u64(50000);
+    let mut chain_end = st.sapling_activation_height();
+    let mut block_count = 0u64;
+    for new_blocks in [12, 5] {
+        for _ in 0..new_blocks {
+            let (height, _, _) = st.generate_next_block(&fvk, AddressType::DefaultExternal, value);
+            chain_end = height + 1;
+        }
+        block_count += new_blocks;
+
+        let mut source = st.chain_source();
+        rt.block_on(sync::run(
+            &mut source,
+            &network,
+            &sync_cache,
+            st.wallet_mut(),
+            5,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            st.wallet()
+                .block_fully_scanned()
+                .unwrap()
+                .map(|meta| meta.block_height()),
+            Some(chain_end - 1)
+        );
+        assert_eq!(
+            st.get_total_balance(account_id),
+            (value * block_count).unwrap()
+        );
+        // Every block was scanned from the block cache, and removed from it once stored.
+        let inserted = sync_cache.inserted();
+        assert!(
+            (u32::from(st.sapling_activation_height())..u32::from(chain_end))
+                .all(|height| inserted.contains(&BlockHeight::from(height)))
+        );
+        assert_eq!(sync_cache.get_tip_height(None).unwrap(), None);
+    }
+}
+
+/// Tests that [`sync::run_with_options`] reports its progress via its [`SyncMonitor`],
+/// and stops once the monitor is cancelled.
+pub fn run_with_options_reports_progress<T: ShieldedPoolTester, DSF>(
+    ds_factory: DSF,
+    cache: impl TestCache,
+    sync_cache: impl BlockCache<Error = impl std::error::Error + Send + Sync + 'static>,
+) where
+    DSF: DataStoreFactory,
+    DSF::DsError: std::error::Error + Send + Sync + 'static,
+    <DSF::DataStore as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(ds_factory)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+    let fvk = T::test_account_fvk(&st);
+    let network = *st.network();
+    let rt = runtime();
+
+    let value = NonNegativeAmount::const_from_u64(50000);
+    let mut chain_end = st.sapling_activation_height();
+    for _ in 0..25 {
+        let (height, _, _) = st.generate_next_block(&fvk, AddressType::DefaultExternal, value);
+        chain_end = height + 1;
+    }
+    let mut source = st.chain_source();
+    let options = SyncOptions {
+        batch_size: 10,
+        ..Default::default()
+    };
+
+    // A cancelled sync stops before scanning anything.
+    let (monitor, _) = SyncMonitor::new();
+    monitor.cancel();
+    let result = rt.block_on(sync::run_with_options(
+        &mut source,
+        &network,
+        &sync_cache,
+        st.wallet_mut(),
+        &options,
+        &monitor,
+    ));
+    assert!(matches!(result, Err(sync::Error::Cancelled)));
+    assert!(st.wallet().block_fully_scanned().unwrap().is_none());
+
+    let (monitor, mut events) = SyncMonitor::new();
+    rt.block_on(sync::run_with_options(
+        &mut source,
+        &network,
+        &sync_cache,
+        st.wallet_mut(),
+        &options,
+        &monitor,
+    ))
+    .unwrap();
+
+    let mut tip_updated = false;
+    let mut scanned = vec![];
+    while let Some(Some(event)) = events.next().now_or_never() {
+        match event {
+            SyncEvent::ChainTipUpdated(tip) => {
+                assert_eq!(tip, chain_end - 1);
+                tip_updated = true;
+            }
+            SyncEvent::RangeScanned(summary) => {
+                let range = summary.scanned_range();
+                assert!(range.end - range.start <= options.batch_size);
+                scanned.push(range);
+            }
+            _ => (),
+        }
+    }
+    assert!(tip_updated);
+    scanned.sort_by_key(|range| range.start);
+    assert_eq!(
+        scanned.first().map(|range| range.start),
+        Some(st.sapling_activation_height())
+    );
+    assert!(scanned.windows(2).all(|pair| pair[0].end == pair[1].start));
+    assert_eq!(scanned.last().map(|range| range.end), Some(chain_end));
+}
+
+/// Tests that a sync that is cancelled while batches are being scanned removes their
+/// blocks from the block cache.
+pub fn run_with_options_cancelled_clears_block_cache<T: ShieldedPoolTester, DSF>(
+    ds_factory: DSF,
+    cache: impl TestCache,
+    sync_cache: impl BlockCache<Error = impl std::error::Error + Send + Sync + 'static>,
+) where
+    DSF: DataStoreFactory,
+    DSF::DsError: std::error::Error + Send + Sync + 'static,
+    <DSF::DataStore as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(ds_factory)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+    let fvk = T::test_account_fvk(&st);
+    let network = *st.network();
+
+    let value = NonNegativeAmount::const_from_u64(50000);
+    for _ in 0..25 {
+        st.generate_next_block(&fvk, AddressType::DefaultExternal, value);
+    }
+    let mut source = st.chain_source();
+    let options = SyncOptions {
+        batch_size: 5,
+        ..Default::default()
+    };
+
+    // The sync is cancelled as soon as the first batch has been inserted into the block
+    // cache, and so before it has been stored.
+    let (monitor, _) = SyncMonitor::new();
+    let mut sync_cache = RecordingCache::new(sync_cache);
+    sync_cache.cancel_on_insert = Some(monitor.clone());
+    let result = runtime().block_on(sync::run_with_options(
+        &mut source,
+        &network,
+        &sync_cache,
+        st.wallet_mut(),
+        &options,
+        &monitor,
+    ));
+    assert!(matches!(result, Err(sync::Error::Cancelled)));
+    assert!(!sync_cache.inserted().is_empty());
+    assert!(st.wallet().block_fully_scanned().unwrap().is_none());
+    assert_eq!(sync_cache.get_tip_height(None).unwrap(), None);
+}
+
+/// Tests that [`sync::watch_mempool`] stores the mempool transactions that are relevant to
+/// the wallet once it is synced to the chain tip, and only then, skipping transactions that
+/// cannot be parsed; that [`sync::run_with_options`] watches the mempool when requested;
+/// and that watching the mempool stops once the sync is cancelled.
+#[cfg(feature = "transparent-inputs")]
+pub fn watch_mempool_stores_relevant_transactions<DSF>(
+    ds_factory: DSF,
+    cache: impl TestCache,
+    sync_cache: impl BlockCache<Error = impl std::error::Error + Send + Sync + 'static>,
+) where
+    DSF: DataStoreFactory,
+    DSF::DsError: std::error::Error + Send + Sync + 'static,
+    <DSF::DataStore as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(ds_factory)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+    let account = st.test_account().cloned().unwrap();
+    let dfvk = st.test_account_sapling().unwrap().clone();
+    let (taddr, taddr_index) = account.usk().default_transparent_address();
+    let tsk = account
+        .usk()
+        .transparent()
+        .derive_external_secret_key(taddr_index)
+        .unwrap();
+    let external_taddr = TransparentAddress::PublicKeyHash([7; 20]);
+    let network = *st.network();
+    let rt = runtime();
+
+    let value = NonNegativeAmount::const_from_u64(50000);
+    for _ in 0..5 {
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    }
+    let mut source = st.chain_source();
+    rt.block_on(sync::run(
+        &mut source,
+        &network,
+        &sync_cache,
+        st.wallet_mut(),
+        10,
+    ))
+    .unwrap();
+
+    // Give the wallet a mined transparent output.
+    let utxo_value = NonNegativeAmount::const_from_u64(40000);
+    let utxo = WalletTransparentOutput::from_parts(
+        OutPoint::new([1; 32], 0),
+        TxOut {
+            value: utxo_value,
+            script_pubkey: taddr.script(),
+        },
+        st.wallet().chain_height().unwrap(),
+    )
+    .unwrap();
+    st.wallet_mut()
+        .put_received_transparent_utxo(&utxo)
+        .unwrap();
+
+    // Builds a transaction that sends a transparent output of the given value at the
+    // wallet's default transparent address, minus the fee, to `to`, or to the wallet's
+    // default Sapling address. Returns the transaction's ID and encoding.
+    let mempool_height = st.wallet().chain_height().unwrap().unwrap() + 2;
+    let build = |outpoint: OutPoint, value: NonNegativeAmount, to: Option<&TransparentAddress>| {
+        let mut builder = Builder::new(
+            network,
+            mempool_height,
+            BuildConfig::Standard {
+                sapling_anchor: Some(sapling::Anchor::empty_tree()),
+                orchard_anchor: None,
+            },
+        );
+        builder
+            .add_transparent_input(
+                tsk,
+                outpoint,
+                TxOut {
+                    value,
+                    script_pubkey: taddr.script(),
+                },
+            )
+            .unwrap();
+        // The Sapling bundle is padded to two outputs, so it has two logical actions.
+        let fee = NonNegativeAmount::const_from_u64(if to.is_some() { 10000 } else { 15000 });
+        let amount = (value - fee).unwrap();
+        match to {
+            Some(to) => builder.add_transparent_output(to, amount).unwrap(),
+            None => builder
+                .add_sapling_output::<Infallible>(
+                    None,
+                    dfvk.default_address().1,
+                    amount,
+                    MemoBytes::empty(),
+                )
+                .unwrap(),
+        }
+        let result = builder.mock_build(OsRng).unwrap();
+        let tx = result.transaction();
+        let mut data = vec![];
+        tx.write(&mut data).unwrap();
+        (tx.txid(), data)
+    };
+    // Builds a transaction that spends one of the wallet's mined Sapling notes to
+    // `external_taddr`. Only the note's position matters for its nullifier, so the
+    // transaction is built against a fake Merkle path to the note.
+    let sapling_spend = {
+        let chain_height = st.wallet().chain_height().unwrap().unwrap();
+        let received = st
+            .wallet()
+            .select_spendable_notes(
+                account.id(),
+                value,
+                &[ShieldedProtocol::Sapling],
+                chain_height,
+                &[],
+            )
+            .unwrap()
+            .sapling()[0]
+            .clone();
+        let note = received.note().clone();
+        let merkle_path = sapling::MerklePath::from_parts(
+            vec![sapling::Node::empty_leaf(); sapling::NOTE_COMMITMENT_TREE_DEPTH.into()],
+            received.note_commitment_tree_position(),
+        )
+        .unwrap();
+        let anchor = merkle_path.root(sapling::Node::from_cmu(&note.cmu()));
+        let mut builder = Builder::new(
+            network,
+            mempool_height,
+            BuildConfig::Standard {
+                sapling_anchor: Some(anchor.into()),
+                orchard_anchor: None,
+            },
+        );
+        builder
+            .add_sapling_spend::<Infallible>(account.usk().sapling(), note, merkle_path)
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &external_taddr,
+                (value - NonNegativeAmount::const_from_u64(15000)).unwrap(),
+            )
+            .unwrap();
+        let result = builder.mock_build(OsRng).unwrap();
+        let tx = result.transaction();
+        let mut data = vec![];
+        tx.write(&mut data).unwrap();
+        (tx.txid(), data)
+    };
+    let shielded_receive = build(OutPoint::new([2; 32], 0), value, None);
+    let transparent_receive = build(OutPoint::new([3; 32], 0), value, Some(&taddr));
+    let transparent_spend = build(utxo.outpoint().clone(), utxo_value, Some(&external_taddr));
+    let unrelated = build(OutPoint::new([4; 32], 0), value, Some(&external_taddr));
+
+    let is_stored = |st: &TestState<_, DSF::DataStore, _>, (txid, _): &(TxId, Vec<u8>)| {
+        st.wallet().get_transaction(*txid).unwrap().is_some()
+    };
+    let mempool_source = |st: &TestState<_, DSF::DataStore, _>| {
+        let mut source = st.chain_source();
+        source.insert_mempool_transaction(TxId::from_bytes([9; 32]), vec![1, 2, 3]);
+        for (txid, data) in [
+            &shielded_receive,
+            &sapling_spend,
+            &transparent_receive,
+            &transparent_spend,
+            &unrelated,
+        ] {
+            source.insert_mempool_transaction(*txid, data.clone());
+        }
+        source
+    };
+
+    // The mempool is not watched while the wallet is behind the chain tip.
+    st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    let mut source = mempool_source(&st);
+    rt.block_on(sync::watch_mempool::<_, _, _, Infallible, Infallible>(
+        &mut source,
+        &network,
+        st.wallet_mut(),
+        &SyncMonitor::default(),
+    ))
+    .unwrap();
+    assert!(!is_stored(&st, &shielded_receive));
+    assert!(!is_stored(&st, &sapling_spend));
+
+    // Once synced to the chain tip, the sync watches the mempool if requested.
+    let options = SyncOptions {
+        watch_mempool: true,
+        ..Default::default()
+    };
+    rt.block_on(sync::run_with_options(
+        &mut source,
+        &network,
+        &sync_cache,
+        st.wallet_mut(),
+        &options,
+        &SyncMonitor::default(),
+    ))
+    .unwrap();
+    assert!(is_stored(&st, &shielded_receive));
+    assert!(is_stored(&st, &sapling_spend));
+    assert!(is_stored(&st, &transparent_receive));
+    assert!(is_stored(&st, &transparent_spend));
+    assert!(!is_stored(&st, &unrelated));
+
+    // Watching a mempool stream that stays open stops once the sync is cancelled.
+    let mut source = TestSource::new(mempool_source(&st));
+    source.mempool_stays_open = true;
+    let (monitor, _) = SyncMonitor::new();
+    let result = rt.block_on(async {
+        let canceller = monitor.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            sync::watch_mempool::<_, _, _, Infallible, Infallible>(
+                &mut source,
+                &network,
+                st.wallet_mut(),
+                &monitor,
+            ),
+        )
+        .await
+    });
+    assert!(matches!(result, Ok(Err(sync::Error::Cancelled))));
+}
+
+/// Tests that [`sync::process_transaction_data_requests`] reports the requests that it
+/// fails to service, either because the source fails or because it returns a transaction
+/// that cannot be parsed, and leaves them outstanding in the wallet.
+pub fn process_transaction_data_requests_reports_failures<T: ShieldedPoolTester, DSF>(
+    ds_factory: DSF,
+    cache: impl TestCache,
+) where
+    DSF: DataStoreFactory,
+    DSF::DsError: std::error::Error + Send + Sync + 'static,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(ds_factory)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+    let fvk = T::test_account_fvk(&st);
+
+    // Scanning blocks that contain wallet transactions queues requests for the full
+    // transactions.
+    let value = NonNegativeAmount::const_from_u64(50000);
+    let (height, _, _) = st.generate_next_block(&fvk, AddressType::DefaultExternal, value);
+    st.generate_next_block(&fvk, AddressType::DefaultExternal, value);
+    st.generate_next_block(&fvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(height, 3);
+
+    let requests = st.wallet().transaction_data_requests().unwrap();
+    let txids: Vec<_> = requests
+        .iter()
+        .filter_map(|request| match request {
+            TransactionDataRequest::Enhancement(txid) => Some(*txid),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(txids.len(), 3);
+
+    let mut source = TestSource::new(st.chain_source());
+    source.unavailable_txid = Some(txids[0]);
+    source.malformed_txid = Some(txids[1]);
+    let options = TransactionDataOptions {
+        max_retries: 1,
+        initial_retry_delay: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let (monitor, mut events) = SyncMonitor::new();
+    let network = *st.network();
+    let failed = runtime()
+        .block_on(sync::process_transaction_data_requests::<
+            _,
+            _,
+            _,
+            Infallible,
+            Infallible,
+        >(
+            &mut source, &network, st.wallet_mut(), &options, &monitor
+        ))
+        .unwrap();
+    let expected_failures: BTreeSet<_> = txids[..2]
+        .iter()
+        .map(|txid| TransactionDataRequest::Enhancement(*txid))
+        .collect();
+    assert_eq!(
+        failed.iter().cloned().collect::<BTreeSet<_>>(),
+        expected_failures
+    );
+
+    let mut retries = 0;
+    let mut failures = vec![];
+    let mut serviced = 0;
+    while let Some(Some(event)) = events.next().now_or_never() {
+        match event {
+            SyncEvent::RequestRetried { request, .. } => {
+                assert_eq!(request, TransactionDataRequest::Enhancement(txids[0]));
+                retries += 1;
+            }
+            SyncEvent::RequestFailed { request, .. } => failures.push(request),
+            SyncEvent::TransactionDataRequestsServiced(count) => serviced += count,
+            _ => (),
+        }
+    }
+    // Only the request that the source failed is retried.
+    assert_eq!(retries, 1);
+    assert_eq!(failures, failed);
+    assert_eq!(serviced, requests.len() - 2);
+
+    // The failed requests remain outstanding.
+    let remaining = st.wallet().transaction_data_requests().unwrap();
+    assert!(expected_failures
+        .iter()
+        .all(|request| remaining.contains(request)));
+}
@@ -85,6 +85,8 @@ use {
 };
 
 pub mod input_selection;
+#[cfg(feature = "pczt")]
+pub mod signer;
 use input_selection::{GreedyInputSelector, InputSelector, InputSelectorError};
 
 /// Scans a [`Transaction`] for any information that can be decrypted by the accounts in
@@ -150,6 +152,17 @@ pub type CreateErrT<DbT, InputsErrT, FeeRuleT, ChangeErrT, N> = Error<
     N,
 >;
 
+/// Errors that may be generated in extraction of transactions from PCZTs.
+#[cfg(feature = "pczt")]
+pub type ExtractErrT<DbT, N> = Error<
+    <DbT as WalletRead>::Error,
+    <DbT as WalletCommitmentTrees>::Error,
+    Infallible,
+    Infallible,
+    Infallible,
+    N,
+>;
+
 /// Errors that may be generated in the execution of proposals that may send shielded inputs.
 pub type TransferErrT<DbT, InputsT, ChangeT> = Error<
     <DbT as WalletRead>::Error,
@@ -633,7 +646,7 @@ where
 
     #[cfg(feature = "orchard")]
     for (orchard_note, merkle_path) in orchard_inputs.into_iter() {
-        builder.add_orchard_spend(usk.orchard(), *orchard_note, merkle_path.into())?;
+        builder.add_orchard_spend(usk.orchard(), *orchard_note, merkle_path)?;
     }
 
     #[cfg(feature = "transparent-inputs")]
@@ -1111,6 +1124,777 @@ where
     })
 }
 
+/// Constructs a partially-created transaction (PCZT) from the given single-step proposal.
+///
+/// Unlike [`create_proposed_transactions`], this does not require a spending key: the
+/// returned PCZT only contains the data derivable from the account's viewing keys. It
+/// can then be passed to separate Prover and Signer roles (which may run in other
+/// processes, or on other devices), and the fully-authorized result stored with
+/// [`extract_and_store_transaction_from_pczt`].
+///
+/// Parameters:
+/// * `wallet_db`: A read/write reference to the wallet database.
+/// * `params`: Consensus parameters.
+/// * `account_id`: The account whose funds are spent by the proposal. The account must
+///   have a unified full viewing key, with components for each pool that the proposal
+///   spends from or sends change to. If `ovk_policy` is [`OvkPolicy::Sender`], it must
+///   also have a component for each shielded pool that the proposal pays to.
+/// * `ovk_policy`: The policy to use for constructing outgoing viewing keys that
+///   can allow the sender to view the resulting notes on the blockchain.
+/// * `proposal`: The proposal for which to create the PCZT.
+///
+/// Returns [`Error::KeyNotRecognized`] if the account's unified full viewing key lacks a
+/// required component.
+///
+/// Multi-step proposals (such as those that pay a TEX address from shielded funds) are
+/// not supported, and [`Error::ProposalNotSupported`] is returned for them: later steps
+/// spend the outputs of earlier steps, which requires the earlier transactions to be fully
+/// authorized before the later transactions can be constructed. Use
+/// [`create_proposed_transactions`] for such proposals.
+#[cfg(feature = "pczt")]
+#[allow(clippy::type_complexity)]
+pub fn create_pczt_from_proposal<DbT, ParamsT, InputsErrT, FeeRuleT, ChangeErrT, N>(
+    wallet_db: &mut DbT,
+    params: &ParamsT,
+    account_id: <DbT as WalletRead>::AccountId,
+    ovk_policy: OvkPolicy,
+    proposal: &Proposal<FeeRuleT, N>,
+) -> Result<pczt::Pczt, CreateErrT<DbT, InputsErrT, FeeRuleT, ChangeErrT, N>>
+where
+    DbT: WalletWrite + WalletCommitmentTrees,
+    ParamsT: consensus::Parameters + Clone,
+    FeeRuleT: FeeRule,
+{
+    use pczt::roles::{constructor::Constructor, creator::Creator, updater::Updater};
+    use zcash_primitives::transaction::builder::DEFAULT_TX_EXPIRY_DELTA;
+    use zcash_protocol::consensus::BranchId;
+
+    if proposal.steps().len() != 1 {
+        return Err(Error::ProposalNotSupported);
+    }
+    let proposal_step = proposal.steps().first();
+
+    let account = wallet_db
+        .get_account(account_id)
+        .map_err(Error::DataSource)?
+        .ok_or(Error::KeyNotRecognized)?;
+    let ufvk = account.ufvk().ok_or(Error::KeyNotRecognized)?.clone();
+    // Each component of the UFVK is only required if the proposal uses the corresponding
+    // pool.
+    let sapling_dfvk = ufvk.sapling();
+    let orchard_fvk = ufvk.orchard();
+    let transparent_pubkey = ufvk.transparent();
+
+    let sapling_fvk = |scope| -> Result<
+        sapling::keys::FullViewingKey,
+        CreateErrT<DbT, InputsErrT, FeeRuleT, ChangeErrT, N>,
+    > {
+        let sapling_dfvk = sapling_dfvk.ok_or(Error::KeyNotRecognized)?;
+        Ok(match scope {
+            Scope::External => sapling_dfvk.fvk().clone(),
+            Scope::Internal => sapling::keys::FullViewingKey {
+                vk: sapling::ViewingKey {
+                    ak: sapling_dfvk.fvk().vk.ak.clone(),
+                    nk: sapling_dfvk.to_nk(Scope::Internal),
+                },
+                ovk: sapling_dfvk.to_ovk(Scope::Internal),
+            },
+        })
+    };
+
+    // Collect the shielded notes being spent, along with their witnesses at the anchor.
+    let (sapling_anchor, sapling_inputs, orchard_anchor, orchard_inputs) = match proposal_step
+        .shielded_inputs()
+    {
+        None => (
+            sapling::Anchor::empty_tree(),
+            vec![],
+            orchard::Anchor::empty_tree(),
+            vec![],
+        ),
+        Some(inputs) => {
+            let (sapling_anchor, sapling_inputs) = wallet_db
+                    .with_sapling_tree_mut::<_, _, CreateErrT<DbT, InputsErrT, FeeRuleT, ChangeErrT, N>>(
+                        |sapling_tree| {
+                            let anchor = sapling_tree
+                                .root_at_checkpoint_id(&inputs.anchor_height())?
+                                .ok_or(ProposalError::AnchorNotFound(inputs.anchor_height()))?
+                                .into();
+
+                            let notes = inputs
+                                .notes()
+                                .iter()
+                                .filter_map(|selected| match selected.note() {
+                                    Note::Sapling(note) => Some(
+                                        sapling_tree
+                                            .witness_at_checkpoint_id_caching(
+                                                selected.note_commitment_tree_position(),
+                                                &inputs.anchor_height(),
+                                            )
+                                            .and_then(|witness| {
+                                                witness.ok_or(ShardTreeError::Query(
+                                                    QueryError::CheckpointPruned,
+                                                ))
+                                            })
+                                            .map_err(Error::from)
+                                            .and_then(|merkle_path| {
+                                                Ok((
+                                                    sapling_fvk(selected.spending_key_scope())?,
+                                                    note.clone(),
+                                                    merkle_path,
+                                                ))
+                                            }),
+                                    ),
+                                    Note::Orchard(_) => None,
+                                })
+                                .collect::<Result<Vec<_>, _>>()?;
+
+                            Ok((anchor, notes))
+                        },
+                    )?;
+
+            let (orchard_anchor, orchard_inputs) = wallet_db
+                    .with_orchard_tree_mut::<_, _, CreateErrT<DbT, InputsErrT, FeeRuleT, ChangeErrT, N>>(
+                        |orchard_tree| {
+                            let anchor = orchard_tree
+                                .root_at_checkpoint_id(&inputs.anchor_height())?
+                                .ok_or(ProposalError::AnchorNotFound(inputs.anchor_height()))?
+                                .into();
+
+                            let notes = inputs
+                                .notes()
+                                .iter()
+                                .filter_map(|selected| match selected.note() {
+                                    Note::Orchard(note) => Some(
+                                        orchard_tree
+                                            .witness_at_checkpoint_id_caching(
+                                                selected.note_commitment_tree_position(),
+                                                &inputs.anchor_height(),
+                                            )
+                                            .and_then(|witness| {
+                                                witness.ok_or(ShardTreeError::Query(
+                                                    QueryError::CheckpointPruned,
+                                                ))
+                                            })
+                                            .map(|merkle_path| (note, merkle_path))
+                                            .map_err(Error::from),
+                                    ),
+                                    Note::Sapling(_) => None,
+                                })
+                                .collect::<Result<Vec<_>, _>>()?;
+
+                            Ok((anchor, notes))
+                        },
+                    )?;
+
+            (
+                sapling_anchor,
+                sapling_inputs,
+                orchard_anchor,
+                orchard_inputs,
+            )
+        }
+    };
+    let has_shielded_inputs = !(sapling_inputs.is_empty() && orchard_inputs.is_empty());
+
+    let pczt = Creator::new(
+        BranchId::for_height(params, proposal.min_target_height()),
+        proposal.min_target_height() + DEFAULT_TX_EXPIRY_DELTA,
+        sapling_anchor,
+        orchard_anchor,
+    )
+    .build()?;
+    let mut constructor = Constructor::new(pczt)?;
+
+    for (fvk, note, merkle_path) in sapling_inputs {
+        constructor.add_sapling_spend(&fvk, note, merkle_path)?;
+    }
+    for (note, merkle_path) in orchard_inputs {
+        constructor.add_orchard_spend(
+            orchard_fvk.ok_or(Error::KeyNotRecognized)?,
+            *note,
+            merkle_path,
+        )?;
+    }
+
+    for utxo in proposal_step.transparent_inputs() {
+        let address_metadata = wallet_db
+            .get_transparent_address_metadata(account_id, utxo.recipient_address())
+            .map_err(Error::DataSource)?
+            .ok_or(Error::AddressNotRecognized(*utxo.recipient_address()))?;
+        let pubkey = transparent_pubkey
+            .ok_or(Error::KeyNotRecognized)?
+            .derive_address_pubkey(address_metadata.scope(), address_metadata.address_index())
+            .expect("public key derivation should not fail");
+
+        constructor.add_transparent_input(pubkey, utxo.outpoint().clone(), utxo.txout().clone())?;
+    }
+
+    // Apply the outgoing viewing key policy. Under `OvkPolicy::Sender`, the OVK for each
+    // pool is derived from the account's key for that pool, and is `None` here (so that
+    // outputs to that pool are rejected) if the account has no such key.
+    let (sapling_external_ovk, orchard_external_ovk) = match &ovk_policy {
+        OvkPolicy::Sender => (
+            sapling_dfvk.map(|dfvk| Some(dfvk.to_ovk(Scope::External))),
+            orchard_fvk.map(|fvk| Some(fvk.to_ovk(orchard::keys::Scope::External))),
+        ),
+        OvkPolicy::Custom { sapling, orchard } => {
+            (Some(Some(*sapling)), Some(Some(orchard.clone())))
+        }
+        OvkPolicy::Discard => (Some(None), Some(None)),
+    };
+    let (sapling_internal_ovk, orchard_internal_ovk) = if proposal_step.is_shielding() {
+        let ovk = transparent_pubkey
+            .ok_or(Error::KeyNotRecognized)?
+            .internal_ovk()
+            .as_bytes();
+        (
+            Some(sapling::keys::OutgoingViewingKey(ovk)),
+            Some(orchard::keys::OutgoingViewingKey::from(ovk)),
+        )
+    } else {
+        (
+            sapling_dfvk.map(|dfvk| dfvk.to_ovk(Scope::Internal)),
+            orchard_fvk.map(|fvk| fvk.to_ovk(orchard::keys::Scope::Internal)),
+        )
+    };
+
+    for (&payment_index, output_pool) in proposal_step.payment_pools() {
+        let payment = proposal_step
+            .transaction_request()
+            .payments()
+            .get(&payment_index)
+            .expect(
+                "The mapping between payment index and payment is checked in step construction",
+            );
+        let memo = payment.memo().map_or_else(MemoBytes::empty, |m| m.clone());
+        // Recorded in the PCZT so that the payment can be stored as sent to this address.
+        let user_address = Some(payment.recipient_address().encode());
+
+        let mut add_transparent_output = |to: TransparentAddress| {
+            // Always reject sending to one of our known ephemeral addresses.
+            if wallet_db
+                .find_account_for_ephemeral_address(&to)
+                .map_err(Error::DataSource)?
+                .is_some()
+            {
+                return Err(Error::PaysEphemeralTransparentAddress(to.encode(params)));
+            }
+            if payment.memo().is_some() {
+                return Err(Error::MemoForbidden);
+            }
+            constructor.add_transparent_output(&to, payment.amount(), user_address.clone())?;
+            Ok(())
+        };
+
+        match payment
+            .recipient_address()
+            .clone()
+            .convert_if_network(params.network_type())?
+        {
+            Address::Unified(ua) => match output_pool {
+                PoolType::Shielded(ShieldedProtocol::Orchard) => {
+                    let to = *ua.orchard().expect("The mapping between payment pool and receiver is checked in step construction");
+                    constructor.add_orchard_output(
+                        orchard_external_ovk
+                            .clone()
+                            .ok_or(Error::KeyNotRecognized)?,
+                        to,
+                        payment.amount(),
+                        memo,
+                        user_address,
+                    )?;
+                }
+                PoolType::Shielded(ShieldedProtocol::Sapling) => {
+                    let to = *ua.sapling().expect("The mapping between payment pool and receiver is checked in step construction");
+                    constructor.add_sapling_output(
+                        sapling_external_ovk.ok_or(Error::KeyNotRecognized)?,
+                        to,
+                        payment.amount(),
+                        memo,
+                        user_address,
+                    )?;
+                }
+                PoolType::Transparent => {
+                    let to = *ua.transparent().expect("The mapping between payment pool and receiver is checked in step construction");
+                    add_transparent_output(to)?;
+                }
+            },
+            Address::Sapling(to) => {
+                constructor.add_sapling_output(
+                    sapling_external_ovk.ok_or(Error::KeyNotRecognized)?,
+                    to,
+                    payment.amount(),
+                    memo,
+                    user_address,
+                )?;
+            }
+            Address::Transparent(to) => {
+                add_transparent_output(to)?;
+            }
+            Address::Tex(data) => {
+                if has_shielded_inputs {
+                    return Err(ProposalError::PaysTexFromShielded.into());
+                }
+                add_transparent_output(TransparentAddress::PublicKeyHash(data))?;
+            }
+        }
+    }
+
+    for change_value in proposal_step.balance().proposed_change() {
+        let memo = change_value
+            .memo()
+            .map_or_else(MemoBytes::empty, |m| m.clone());
+        match change_value.output_pool() {
+            // Ephemeral outputs are only used by multi-step proposals.
+            _ if change_value.is_ephemeral() => return Err(Error::ProposalNotSupported),
+            PoolType::Shielded(ShieldedProtocol::Sapling) => {
+                constructor.add_sapling_output(
+                    Some(sapling_internal_ovk.ok_or(Error::KeyNotRecognized)?),
+                    sapling_dfvk
+                        .ok_or(Error::KeyNotRecognized)?
+                        .change_address()
+                        .1,
+                    change_value.value(),
+                    memo,
+                    None,
+                )?;
+            }
+            PoolType::Shielded(ShieldedProtocol::Orchard) => {
+                constructor.add_orchard_output(
+                    Some(
+                        orchard_internal_ovk
+                            .clone()
+                            .ok_or(Error::KeyNotRecognized)?,
+                    ),
+                    orchard_fvk
+                        .ok_or(Error::KeyNotRecognized)?
+                        .address_at(0u32, orchard::keys::Scope::Internal),
+                    change_value.value(),
+                    memo,
+                    None,
+                )?;
+            }
+            output_pool @ PoolType::Transparent => {
+                return Err(Error::UnsupportedChangeType(output_pool));
+            }
+        }
+    }
+
+    let mut updater = Updater::new(constructor.build(OsRng)?);
+    updater.set_proprietary(
+        PROPRIETARY_TARGET_HEIGHT.to_owned(),
+        u32::from(proposal.min_target_height())
+            .to_le_bytes()
+            .to_vec(),
+    );
+
+    Ok(updater.finish())
+}
+
+/// The proprietary PCZT field in which [`create_pczt_from_proposal`] records the target
+/// height of the proposal, as a little-endian `u32`.
+#[cfg(feature = "pczt")]
+const PROPRIETARY_TARGET_HEIGHT: &str = "zcash_client_backend:target_height";
+
+/// Extracts the fully-authorized transaction from the given PCZT, and stores it in the
+/// wallet database so that it can be broadcast.
+///
+/// The PCZT must have been created with [`create_pczt_from_proposal`], and then have
+/// had all of its proofs and signatures added (and its transparent spends finalized).
+/// The transaction is stored in the same way as by [`create_proposed_transactions`]:
+/// the recipient address, value and memo of each payment are recovered from the PCZT
+/// (regardless of the [`OvkPolicy`] it was created with), along with the account's
+/// change notes and the fee.
+///
+/// Returns [`Error::PcztNotRecognized`] if the PCZT lacks the information added by
+/// [`create_pczt_from_proposal`], or does not spend funds from an account in the wallet.
+///
+/// Returns the ID of the extracted transaction.
+#[cfg(feature = "pczt")]
+pub fn extract_and_store_transaction_from_pczt<DbT, N>(
+    wallet_db: &mut DbT,
+    pczt: pczt::Pczt,
+) -> Result<TxId, ExtractErrT<DbT, N>>
+where
+    DbT: WalletWrite + WalletCommitmentTrees,
+{
+    store_pczt_transaction(wallet_db, pczt)
+}
+
+/// Extracts the transaction from a fully-authorized PCZT that was created by
+/// [`create_pczt_from_proposal`], and stores it via
+/// [`WalletWrite::store_transactions_to_be_sent`].
+#[cfg(feature = "pczt")]
+#[allow(clippy::type_complexity)]
+fn store_pczt_transaction<DbT, SE, FE, CE, N>(
+    wallet_db: &mut DbT,
+    pczt: pczt::Pczt,
+) -> Result<
+    TxId,
+    Error<<DbT as WalletRead>::Error, <DbT as WalletCommitmentTrees>::Error, SE, FE, CE, N>,
+>
+where
+    DbT: WalletWrite + WalletCommitmentTrees,
+{
+    use pczt::roles::tx_extractor::TransactionExtractor;
+    use zcash_address::ZcashAddress;
+    use zcash_protocol::value::{BalanceError, ZatBalance};
+
+    let target_height = pczt
+        .global()
+        .proprietary()
+        .get(PROPRIETARY_TARGET_HEIGHT)
+        .and_then(|bytes| <[u8; 4]>::try_from(bytes.as_slice()).ok())
+        .map(|bytes| BlockHeight::from_u32(u32::from_le_bytes(bytes)))
+        .ok_or(Error::PcztNotRecognized)?;
+
+    let account_id = find_pczt_account(wallet_db, &pczt)?;
+    let ufvk = wallet_db
+        .get_account(account_id)
+        .map_err(Error::DataSource)?
+        .ok_or(Error::KeyNotRecognized)?
+        .ufvk()
+        .ok_or(Error::KeyNotRecognized)?
+        .clone();
+
+    let user_address = |address: Option<&str>, pool| {
+        address
+            .map(|address| {
+                ZcashAddress::try_from_encoded(address)
+                    .map(|address| Recipient::External(address, pool))
+                    .map_err(|_| Error::PcztNotRecognized)
+            })
+            .transpose()
+    };
+
+    // Outputs with a user-facing address are payments; the remaining outputs are either
+    // change (sent to the account's internal scope) or dummies, which are not recorded.
+    let mut outputs = vec![];
+    for (index, action) in pczt.orchard().actions().iter().enumerate() {
+        let (note, memo) = match action.recover_note_and_memo() {
+            Ok(recovered) => recovered,
+            Err(_) if action.output().user_address().is_none() => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let recipient = match user_address(action.output().user_address(), PoolType::ORCHARD)? {
+            Some(recipient) => recipient,
+            None if ufvk
+                .orchard()
+                .and_then(|fvk| fvk.scope_for_address(&note.recipient()))
+                == Some(orchard::keys::Scope::Internal) =>
+            {
+                Recipient::InternalAccount {
+                    receiving_account: account_id,
+                    external_address: None,
+                    note: Note::Orchard(note),
+                }
+            }
+            None => continue,
+        };
+        outputs.push(SentTransactionOutput::from_parts(
+            index,
+            recipient,
+            NonNegativeAmount::from_u64(note.value().inner())?,
+            Some(memo),
+        ));
+    }
+    for (index, output) in pczt.sapling().outputs().iter().enumerate() {
+        let (note, memo) = match output.recover_note_and_memo() {
+            Ok(recovered) => recovered,
+            Err(_) if output.user_address().is_none() => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let recipient = match user_address(output.user_address(), PoolType::SAPLING)? {
+            Some(recipient) => recipient,
+            None if ufvk.sapling().and_then(|dfvk| {
+                dfvk.diversified_change_address(*note.recipient().diversifier())
+            }) == Some(note.recipient()) =>
+            {
+                Recipient::InternalAccount {
+                    receiving_account: account_id,
+                    external_address: None,
+                    note: Note::Sapling(note.clone()),
+                }
+            }
+            None => continue,
+        };
+        outputs.push(SentTransactionOutput::from_parts(
+            index,
+            recipient,
+            NonNegativeAmount::from_u64(note.value().inner())?,
+            Some(memo),
+        ));
+    }
+    for (index, output) in pczt.transparent().outputs().iter().enumerate() {
+        if let Some(recipient) = user_address(output.user_address(), PoolType::TRANSPARENT)? {
+            outputs.push(SentTransactionOutput::from_parts(
+                index,
+                recipient,
+                NonNegativeAmount::from_u64(output.value())?,
+                None,
+            ));
+        }
+    }
+
+    // The fee is the transparent value that is not spent to transparent outputs, plus
+    // the net value leaving the shielded pools.
+    let fee_amount = pczt
+        .transparent()
+        .inputs()
+        .iter()
+        .map(|input| ZatBalance::from_u64(input.value()))
+        .chain(
+            pczt.transparent()
+                .outputs()
+                .iter()
+                .map(|output| ZatBalance::from_u64(output.value()).map(|value| -value)),
+        )
+        .chain([
+            ZatBalance::from_i64(pczt.sapling().value_sum()),
+            ZatBalance::from_i64(pczt.orchard().value_sum()),
+        ])
+        .sum::<Result<Option<ZatBalance>, _>>()?
+        .ok_or(BalanceError::Overflow)?;
+    let fee_amount = NonNegativeAmount::try_from(fee_amount)?;
+
+    let utxos_spent = pczt
+        .transparent()
+        .inputs()
+        .iter()
+        .map(|input| input.prevout())
+        .collect::<Vec<_>>();
+
+    let transaction = TransactionExtractor::new(pczt).extract(OsRng)?;
+    wallet_db
+        .store_transactions_to_be_sent(&[SentTransaction::new(
+            &transaction,
+            time::OffsetDateTime::now_utc(),
+            target_height,
+            account_id,
+            &outputs,
+            fee_amount,
+            &utxos_spent,
+        )])
+        .map_err(Error::DataSource)?;
+
+    Ok(transaction.txid())
+}
+
+/// Returns the account whose funds are spent by the given PCZT.
+///
+/// The account is identified by the nullifiers of the PCZT's shielded spends, or by the
+/// addresses of its transparent inputs.
+#[cfg(feature = "pczt")]
+#[allow(clippy::type_complexity)]
+fn find_pczt_account<DbT, SE, FE, CE, N>(
+    wallet_db: &DbT,
+    pczt: &pczt::Pczt,
+) -> Result<
+    <DbT as WalletRead>::AccountId,
+    Error<<DbT as WalletRead>::Error, <DbT as WalletCommitmentTrees>::Error, SE, FE, CE, N>,
+>
+where
+    DbT: WalletRead + WalletCommitmentTrees,
+{
+    use super::NullifierQuery;
+
+    let sapling_account = wallet_db
+        .get_sapling_nullifiers(NullifierQuery::Unspent)
+        .map_err(Error::DataSource)?
+        .into_iter()
+        .find(|(_, nf)| {
+            pczt.sapling()
+                .spends()
+                .iter()
+                .any(|spend| spend.nullifier() == nf.0)
+        });
+    if let Some((account_id, _)) = sapling_account {
+        return Ok(account_id);
+    }
+
+    // Dummy Orchard spends have random nullifiers, which will not match any of the
+    // wallet's notes.
+    let orchard_account = wallet_db
+        .get_orchard_nullifiers(NullifierQuery::Unspent)
+        .map_err(Error::DataSource)?
+        .into_iter()
+        .find(|(_, nf)| {
+            pczt.orchard()
+                .actions()
+                .iter()
+                .any(|action| action.spend().nullifier() == nf.to_bytes())
+        });
+    if let Some((account_id, _)) = orchard_account {
+        return Ok(account_id);
+    }
+
+    for account_id in wallet_db.get_account_ids().map_err(Error::DataSource)? {
+        for input in pczt.transparent().inputs() {
+            if let Some(address) = input.script_pubkey().address() {
+                if wallet_db
+                    .get_transparent_address_metadata(account_id, &address)
+                    .map_err(Error::DataSource)?
+                    .is_some()
+                {
+                    return Ok(account_id);
+                }
+            }
+        }
+    }
+
+    Err(Error::PcztNotRecognized)
+}
+
+/// Construct, prove, and sign a transaction using the inputs supplied by the given
+/// single-step proposal, authorizing its spends with the given [`TransactionSigner`], and
+/// persist it to the wallet database.
+///
+/// This is equivalent to [`create_proposed_transactions`], except that the account's
+/// spending keys are not required to be held in memory: the transaction is constructed
+/// as a PCZT with [`create_pczt_from_proposal`], and then proven and signed using the
+/// Sapling proof generation keys and spend authorization signatures obtained from
+/// `signer`. The Orchard proving key is built if the transaction has Orchard actions.
+/// The transaction is then stored as by [`extract_and_store_transaction_from_pczt`],
+/// which records the same sent outputs, memos and fee as [`create_proposed_transactions`].
+///
+/// Parameters:
+/// * `wallet_db`: A read/write reference to the wallet database.
+/// * `params`: Consensus parameters.
+/// * `spend_prover`: The [`sapling::SpendProver`] to use in constructing the shielded
+///   transaction.
+/// * `output_prover`: The [`sapling::OutputProver`] to use in constructing the shielded
+///   transaction.
+/// * `signer`: The source of spend authority for the account.
+/// * `account_id`: The account whose funds are spent by the proposal.
+/// * `ovk_policy`: The policy to use for constructing outgoing viewing keys that
+///   can allow the sender to view the resulting notes on the blockchain.
+/// * `proposal`: The proposal for which to create the transaction.
+///
+/// As with [`create_pczt_from_proposal`], multi-step proposals are not supported.
+///
+/// [`TransactionSigner`]: signer::TransactionSigner
+/// [`sapling::SpendProver`]: sapling::prover::SpendProver
+/// [`sapling::OutputProver`]: sapling::prover::OutputProver
+#[cfg(feature = "pczt")]
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub fn create_proposed_transactions_with_signer<
+    DbT,
+    ParamsT,
+    InputsErrT,
+    FeeRuleT,
+    ChangeErrT,
+    N,
+    S,
+>(
+    wallet_db: &mut DbT,
+    params: &ParamsT,
+    spend_prover: &impl SpendProver,
+    output_prover: &impl OutputProver,
+    signer: &S,
+    account_id: <DbT as WalletRead>::AccountId,
+    ovk_policy: OvkPolicy,
+    proposal: &Proposal<FeeRuleT, N>,
+) -> Result<NonEmpty<TxId>, CreateErrT<DbT, InputsErrT, FeeRuleT, ChangeErrT, N>>
+where
+    DbT: WalletWrite + WalletCommitmentTrees,
+    ParamsT: consensus::Parameters + Clone,
+    FeeRuleT: FeeRule,
+    S: signer::TransactionSigner,
+    S::Error: std::error::Error + Send + Sync + 'static,
+{
+    use pczt::roles::{
+        prover::Prover, signer::Signer, spend_finalizer::SpendFinalizer, updater::Updater,
+    };
+
+    let signer_error = |e: S::Error| Error::Signer(Box::new(e));
+
+    let pczt = create_pczt_from_proposal(wallet_db, params, account_id, ovk_policy, proposal)?;
+    let sapling_spends = pczt.sapling().spends().len();
+
+    // Sapling spends may be of notes received by either scope of the account; the Updater
+    // rejects the proof generation key for the wrong scope.
+    let mut updater = Updater::new(pczt);
+    let sapling_pgks = [Scope::External, Scope::Internal]
+        .into_iter()
+        .map(|scope| signer.sapling_proof_generation_key(scope))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(signer_error)?;
+    for index in 0..sapling_spends {
+        let mut result = Err(pczt::roles::Error::WrongSpendingKey);
+        for pgk in &sapling_pgks {
+            result = updater.set_sapling_proof_generation_key(index, pgk.clone());
+            if !matches!(result, Err(pczt::roles::Error::WrongSpendingKey)) {
+                break;
+            }
+        }
+        result?;
+    }
+
+    let mut prover = Prover::new(updater.finish());
+    prover.create_sapling_proofs(spend_prover, output_prover, OsRng)?;
+    if !prover.has_orchard_proof() {
+        prover.create_orchard_proof(&orchard::circuit::ProvingKey::build(), OsRng)?;
+    }
+    let pczt = prover.finish();
+
+    let ufvk = wallet_db
+        .get_account(account_id)
+        .map_err(Error::DataSource)?
+        .ok_or(Error::KeyNotRecognized)?
+        .ufvk()
+        .ok_or(Error::KeyNotRecognized)?
+        .clone();
+    let transparent_pubkey = ufvk.transparent();
+
+    let mut pczt_signer = Signer::new(pczt.clone())?;
+    let sighash = pczt_signer.shielded_sighash();
+    for (index, input) in pczt.transparent().inputs().iter().enumerate() {
+        let address = input
+            .script_pubkey()
+            .address()
+            .ok_or(Error::ProposalNotSupported)?;
+        let address_metadata = wallet_db
+            .get_transparent_address_metadata(account_id, &address)
+            .map_err(Error::DataSource)?
+            .ok_or(Error::AddressNotRecognized(address))?;
+        let pubkey = transparent_pubkey
+            .ok_or(Error::KeyNotRecognized)?
+            .derive_address_pubkey(address_metadata.scope(), address_metadata.address_index())
+            .expect("public key derivation should not fail");
+
+        let signature = signer
+            .sign_transparent(
+                address_metadata.scope(),
+                address_metadata.address_index(),
+                &pczt_signer.transparent_sighash(index)?,
+            )
+            .map_err(signer_error)?;
+        pczt_signer.append_transparent_signature(index, &pubkey, &signature)?;
+    }
+    for index in 0..sapling_spends {
+        let signature = signer
+            .sign_sapling(&pczt_signer.sapling_alpha(index)?, &sighash)
+            .map_err(signer_error)?;
+        pczt_signer.append_sapling_signature(index, signature)?;
+    }
+    for (index, action) in pczt.orchard().actions().iter().enumerate() {
+        // Dummy spends are signed by the Constructor.
+        if !action.spend().has_signature() {
+            let signature = signer
+                .sign_orchard(&pczt_signer.orchard_alpha(index)?, &sighash)
+                .map_err(signer_error)?;
+            pczt_signer.append_orchard_signature(index, signature)?;
+        }
+    }
+
+    let pczt = SpendFinalizer::new(pczt_signer.finish()).finalize_spends()?;
+    let txid = store_pczt_transaction(wallet_db, pczt)?;
+
+    Ok(NonEmpty::singleton(txid))
+}
+
 /// Constructs a transaction that consumes available transparent UTXOs belonging to the specified
 /// secret key, and sends them to the most-preferred receiver of the default internal address for
 /// the provided Unified Spending Key.
@@ -0,0 +1,162 @@
+//! Types for authorizing the spends of a transaction without holding the spending keys
+//! that control them.
+
+use std::error;
+use std::fmt;
+
+use orchard::primitives::redpallas;
+use rand_core::OsRng;
+use zcash_primitives::legacy::keys::{NonHardenedChildIndex, TransparentKeyScope};
+use zip32::Scope;
+
+use crate::keys::UnifiedSpendingKey;
+
+/// A source of spend authority for a single account.
+///
+/// A `TransactionSigner` is given the data required to authorize each spend of a
+/// transaction (the sighash that must be signed, along with the randomizer for shielded
+/// spends or the derivation path for transparent spends), and returns the corresponding
+/// signature. This allows transactions to be signed by hardware wallets, HSMs, or
+/// separate signing services, without the account's spending keys being held by the
+/// wallet.
+///
+/// [`UnifiedSpendingKeySigner`] is an implementation of this trait backed by a
+/// [`UnifiedSpendingKey`] held in memory.
+pub trait TransactionSigner {
+    /// The type of errors that may be produced by the signer, for example when a device
+    /// is disconnected or the user declines to authorize a spend.
+    ///
+    /// These errors are reported to the caller of [`create_proposed_transactions_with_signer`]
+    /// as [`Error::Signer`].
+    ///
+    /// [`create_proposed_transactions_with_signer`]: super::create_proposed_transactions_with_signer
+    /// [`Error::Signer`]: crate::data_api::error::Error::Signer
+    type Error;
+
+    /// Returns the Sapling proof generation key for the given scope of the account.
+    ///
+    /// This is required in order to create the proofs for Sapling spends, and does not
+    /// grant spend authority by itself.
+    fn sapling_proof_generation_key(
+        &self,
+        scope: Scope,
+    ) -> Result<sapling::ProofGenerationKey, Self::Error>;
+
+    /// Creates a Sapling spend authorization signature over `sighash`, using the
+    /// account's spend authorizing key randomized by `alpha`.
+    fn sign_sapling(
+        &self,
+        alpha: &jubjub::Scalar,
+        sighash: &[u8; 32],
+    ) -> Result<redjubjub::Signature<redjubjub::SpendAuth>, Self::Error>;
+
+    /// Creates an Orchard spend authorization signature over `sighash`, using the
+    /// account's spend authorizing key randomized by `alpha`.
+    fn sign_orchard(
+        &self,
+        alpha: &pasta_curves::pallas::Scalar,
+        sighash: &[u8; 32],
+    ) -> Result<redpallas::Signature<redpallas::SpendAuth>, Self::Error>;
+
+    /// Creates an ECDSA signature over `sighash`, using the account's transparent secret
+    /// key at the given BIP 44 scope and address index.
+    fn sign_transparent(
+        &self,
+        scope: TransparentKeyScope,
+        address_index: NonHardenedChildIndex,
+        sighash: &[u8; 32],
+    ) -> Result<secp256k1::ecdsa::Signature, Self::Error>;
+}
+
+/// Errors that can occur when signing with a [`UnifiedSpendingKeySigner`].
+#[derive(Debug)]
+pub enum SpendingKeySignerError {
+    /// The transparent secret key for a spend could not be derived.
+    TransparentKeyDerivation(bip32::Error),
+}
+
+impl fmt::Display for SpendingKeySignerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpendingKeySignerError::TransparentKeyDerivation(e) => {
+                write!(f, "Failed to derive transparent secret key: {}", e)
+            }
+        }
+    }
+}
+
+impl error::Error for SpendingKeySignerError {}
+
+/// A [`TransactionSigner`] backed by a [`UnifiedSpendingKey`].
+pub struct UnifiedSpendingKeySigner<'a> {
+    usk: &'a UnifiedSpendingKey,
+    secp: secp256k1::Secp256k1<secp256k1::SignOnly>,
+}
+
+impl<'a> UnifiedSpendingKeySigner<'a> {
+    /// Constructs a signer that authorizes spends with the given spending key.
+    pub fn new(usk: &'a UnifiedSpendingKey) -> Self {
+        Self {
+            usk,
+            secp: secp256k1::Secp256k1::signing_only(),
+        }
+    }
+}
+
+impl<'a> TransactionSigner for UnifiedSpendingKeySigner<'a> {
+    type Error = SpendingKeySignerError;
+
+    fn sapling_proof_generation_key(
+        &self,
+        scope: Scope,
+    ) -> Result<sapling::ProofGenerationKey, Self::Error> {
+        Ok(match scope {
+            Scope::External => self.usk.sapling().expsk.proof_generation_key(),
+            Scope::Internal => self
+                .usk
+                .sapling()
+                .derive_internal()
+                .expsk
+                .proof_generation_key(),
+        })
+    }
+
+    fn sign_sapling(
+        &self,
+        alpha: &jubjub::Scalar,
+        sighash: &[u8; 32],
+    ) -> Result<redjubjub::Signature<redjubjub::SpendAuth>, Self::Error> {
+        Ok(self
+            .usk
+            .sapling()
+            .expsk
+            .ask
+            .randomize(alpha)
+            .sign(OsRng, sighash))
+    }
+
+    fn sign_orchard(
+        &self,
+        alpha: &pasta_curves::pallas::Scalar,
+        sighash: &[u8; 32],
+    ) -> Result<redpallas::Signature<redpallas::SpendAuth>, Self::Error> {
+        Ok(orchard::keys::SpendAuthorizingKey::from(self.usk.orchard())
+            .randomize(alpha)
+            .sign(OsRng, sighash))
+    }
+
+    fn sign_transparent(
+        &self,
+        scope: TransparentKeyScope,
+        address_index: NonHardenedChildIndex,
+        sighash: &[u8; 32],
+    ) -> Result<secp256k1::ecdsa::Signature, Self::Error> {
+        let sk = self
+            .usk
+            .transparent()
+            .derive_secret_key(scope, address_index)
+            .map_err(SpendingKeySignerError::TransparentKeyDerivation)?;
+        let msg = secp256k1::Message::from_slice(sighash).expect("32 bytes");
+        Ok(self.secp.sign_ecdsa(&msg, &sk))
+    }
+}
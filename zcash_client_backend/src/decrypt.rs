@@ -105,6 +105,30 @@ pub fn decrypt_transaction<'a, P: consensus::Parameters, AccountId: Copy>(
     height: BlockHeight,
     tx: &'a Transaction,
     ufvks: &HashMap<AccountId, UnifiedFullViewingKey>,
+) -> DecryptedTransaction<'a, AccountId> {
+    decrypt_transaction_inner(params, height, Some(height), tx, ufvks)
+}
+
+/// Scans an unmined [`Transaction`], such as one in the mempool, for any information that
+/// can be decrypted by the set of [`UnifiedFullViewingKey`]s.
+///
+/// `mempool_height` is the height at which the transaction is expected to be mined; that
+/// is, one more than the height of the current chain tip.
+pub fn decrypt_unmined_transaction<'a, P: consensus::Parameters, AccountId: Copy>(
+    params: &P,
+    mempool_height: BlockHeight,
+    tx: &'a Transaction,
+    ufvks: &HashMap<AccountId, UnifiedFullViewingKey>,
+) -> DecryptedTransaction<'a, AccountId> {
+    decrypt_transaction_inner(params, mempool_height, None, tx, ufvks)
+}
+
+fn decrypt_transaction_inner<'a, P: consensus::Parameters, AccountId: Copy>(
+    params: &P,
+    height: BlockHeight,
+    mined_height: Option<BlockHeight>,
+    tx: &'a Transaction,
+    ufvks: &HashMap<AccountId, UnifiedFullViewingKey>,
 ) -> DecryptedTransaction<'a, AccountId> {
     let zip212_enforcement = zip212_enforcement(params, height);
     let sapling_bundle = tx.sapling_bundle();
@@ -214,7 +238,7 @@ pub fn decrypt_transaction<'a, P: consensus::Parameters, AccountId: Copy>(
         .collect();
 
     DecryptedTransaction::new(
-        Some(height),
+        mined_height,
         tx,
         sapling_outputs,
         #[cfg(feature = "orchard")]
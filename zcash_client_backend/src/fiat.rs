@@ -0,0 +1,56 @@
+//! Types for valuing ZEC in fiat currencies.
+
+use std::fmt;
+
+/// A currency that ZEC can be exchanged for.
+///
+/// Stablecoins pegged to a currency are treated as that currency; for example, the
+/// ZEC/USDT pair is used as a source of USD/ZEC data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Currency {
+    /// The United States dollar.
+    Usd,
+    /// The euro.
+    Eur,
+}
+
+impl Currency {
+    /// Returns the ISO 4217 code for this currency.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+        }
+    }
+
+    /// Returns the currency with the given ISO 4217 code, or `None` if the code is not
+    /// that of a supported currency.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "USD" => Some(Currency::Usd),
+            "EUR" => Some(Currency::Eur),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Currency;
+
+    #[test]
+    fn code_round_trip() {
+        for currency in [Currency::Usd, Currency::Eur] {
+            assert_eq!(Currency::from_code(currency.code()), Some(currency));
+        }
+        assert_eq!(Currency::from_code("usd"), None);
+        assert_eq!(Currency::from_code("ZEC"), None);
+    }
+}
@@ -77,13 +77,18 @@ pub use zip321;
 #[cfg(feature = "sync")]
 pub mod sync;
 
+#[cfg(feature = "fiat")]
+pub mod fiat;
+
 #[cfg(feature = "unstable-serialization")]
 pub mod serialization;
 
 #[cfg(feature = "tor")]
 pub mod tor;
 
-pub use decrypt::{decrypt_transaction, DecryptedOutput, TransferType};
+pub use decrypt::{
+    decrypt_transaction, decrypt_unmined_transaction, DecryptedOutput, TransferType,
+};
 pub use zcash_protocol::{PoolType, ShieldedProtocol};
 
 #[cfg(test)]
@@ -1,74 +1,204 @@
 //! Implementation of the synchronization flow described in the crate root.
 //!
-//! This is currently a simple implementation that does not yet implement a few features:
-//!
-//! - Block batches are not downloaded in parallel with scanning.
-//! - Transactions are not enhanced once detected (that is, after an output is detected in
-//!   a transaction, the full transaction is not downloaded and scanned).
-//! - There is no mechanism for notifying the caller of progress updates.
-//! - There is no mechanism for interrupting the synchronization flow, other than ending
-//!   the process.
+//! The progress of the synchronization flow can be observed, and the flow cancelled, via
+//! a [`SyncMonitor`].
 
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
 use std::fmt;
+use std::hash::Hash;
+use std::io;
+use std::time::Duration;
 
-use futures_util::TryStreamExt;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use prost::Message;
 use shardtree::error::ShardTreeError;
 use subtle::ConditionallySelectable;
-use tonic::{
-    body::BoxBody,
-    client::GrpcService,
-    codegen::{Body, Bytes, StdError},
-};
-use tracing::{debug, info};
+use tokio::{runtime::RuntimeFlavor, task::JoinHandle};
+use tracing::{debug, info, warn};
 use zcash_primitives::{
-    consensus::{BlockHeight, Parameters},
+    consensus::{BlockHeight, BranchId, NetworkUpgrade, Parameters},
     merkle_tree::HashSer,
+    transaction::{Transaction, TxId},
 };
 
 use crate::{
     data_api::{
         chain::{
-            error::Error as ChainError, scan_cached_blocks, BlockCache, ChainState,
-            CommitmentTreeRoot,
+            error::Error as ChainError, scan_block_range, scan_cached_blocks, BlockCache,
+            BlockSource, ChainState, CommitmentTreeRoot, ScanSummary,
         },
         scanning::{ScanPriority, ScanRange},
-        WalletCommitmentTrees, WalletRead, WalletWrite,
+        wallet::decrypt_and_store_transaction,
+        BlockMetadata, InputSource, NullifierQuery, ScannedBlock, TransactionDataRequest,
+        TransactionStatus, WalletCommitmentTrees, WalletRead, WalletWrite,
     },
-    proto::service::{self, compact_tx_streamer_client::CompactTxStreamerClient, BlockId},
-    scanning::ScanError,
+    decrypt_unmined_transaction,
+    keys::UnifiedFullViewingKey,
+    proto::{compact_formats::CompactBlock, service::RawTransaction},
+    scanning::{Nullifiers, ScanError},
+    ShieldedProtocol,
 };
 
+mod monitor;
+pub use monitor::{SyncEvent, SyncEvents, SyncMonitor};
+
+pub mod source;
+pub use source::ChainDataSource;
+
 #[cfg(feature = "orchard")]
-use orchard::tree::MerkleHashOrchard;
+use {
+    crate::{data_api::DecryptedTransaction, TransferType},
+    orchard::tree::MerkleHashOrchard,
+};
 
 #[cfg(feature = "transparent-inputs")]
 use {
     crate::{encoding::AddressCodec, wallet::WalletTransparentOutput},
     zcash_primitives::{
-        legacy::Script,
+        legacy::{Script, TransparentAddress},
         transaction::components::transparent::{OutPoint, TxOut},
     },
-    zcash_protocol::{consensus::NetworkUpgrade, value::Zatoshis},
+    zcash_protocol::value::Zatoshis,
 };
 
 /// Scans the chain until the wallet is up-to-date.
+///
+/// This is equivalent to calling [`run_with_options`] with the default [`SyncOptions`]
+/// and the given `batch_size`, and a default [`SyncMonitor`].
 pub async fn run<P, ChT, CaT, DbT>(
-    client: &mut CompactTxStreamerClient<ChT>,
+    client: &mut ChT,
     params: &P,
     db_cache: &CaT,
     db_data: &mut DbT,
     batch_size: u32,
-) -> Result<(), Error<CaT::Error, <DbT as WalletRead>::Error, <DbT as WalletCommitmentTrees>::Error>>
+) -> Result<
+    (),
+    Error<
+        CaT::Error,
+        <DbT as WalletRead>::Error,
+        <DbT as WalletCommitmentTrees>::Error,
+        ChT::Error,
+    >,
+>
 where
     P: Parameters + Send + 'static,
-    ChT: GrpcService<BoxBody>,
-    ChT::Error: Into<StdError>,
-    ChT::ResponseBody: Body<Data = Bytes> + Send + 'static,
-    <ChT::ResponseBody as Body>::Error: Into<StdError> + Send,
+    ChT: ChainDataSource + Clone + 'static,
+    ChT::Error: fmt::Display + Send + 'static,
     CaT: BlockCache,
     CaT::Error: std::error::Error + Send + Sync + 'static,
-    DbT: WalletWrite + WalletCommitmentTrees,
-    DbT::AccountId: ConditionallySelectable + Default + Send + 'static,
+    DbT: WalletWrite + WalletCommitmentTrees + InputSource<Error = <DbT as WalletRead>::Error>,
+    <DbT as WalletRead>::AccountId: ConditionallySelectable + Default + Send + 'static,
+    <DbT as WalletRead>::Error: std::error::Error + Send + Sync + 'static,
+    <DbT as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
+{
+    let options = SyncOptions {
+        batch_size,
+        ..Default::default()
+    };
+    run_with_options(
+        client,
+        params,
+        db_cache,
+        db_data,
+        &options,
+        &SyncMonitor::default(),
+    )
+    .await
+}
+
+/// Options controlling the behaviour of [`run_with_options`].
+#[derive(Clone, Copy, Debug)]
+pub struct SyncOptions {
+    /// The maximum number of blocks that are downloaded and scanned as a single batch.
+    pub batch_size: u32,
+    /// The maximum number of batches that may be downloaded concurrently.
+    pub max_concurrent_downloads: usize,
+    /// The maximum number of downloaded batches that may be scanned concurrently, each on
+    /// a separate thread of Tokio's blocking thread pool.
+    pub max_concurrent_scans: usize,
+    /// An approximate bound on the total encoded size, in bytes, of the blocks that are
+    /// being downloaded, and are held in memory until they are inserted into the block
+    /// cache.
+    ///
+    /// The size of future batches is estimated from the batches downloaded so far, and
+    /// fewer batches are downloaded concurrently if necessary to stay within this bound.
+    /// At least one batch is always downloaded, regardless of its size.
+    pub max_cached_bytes: usize,
+    /// Options for servicing the wallet's transaction data requests once scanning is
+    /// complete.
+    pub transaction_data: TransactionDataOptions,
+    /// Whether to watch the mempool via [`watch_mempool`] once the wallet is synced to the
+    /// chain tip, until a new block is mined.
+    ///
+    /// Calling [`run_with_options`] in a loop with this enabled keeps the wallet up to
+    /// date with both the chain and the mempool.
+    pub watch_mempool: bool,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            batch_size: 1000,
+            max_concurrent_downloads: 4,
+            max_concurrent_scans: 2,
+            max_cached_bytes: 64 * 1024 * 1024,
+            transaction_data: TransactionDataOptions::default(),
+            watch_mempool: false,
+        }
+    }
+}
+
+/// Scans the chain until the wallet is up-to-date.
+///
+/// Block batches are downloaded and scanned concurrently, up to the limits given in
+/// `options`. Each batch is downloaded by a task spawned on the current Tokio runtime,
+/// inserted into `db_cache`, and then scanned from the cache on the runtime's blocking
+/// thread pool; trial decryption within each batch is performed in parallel on the global
+/// `rayon` thread pool. The blocks of each batch are deleted from `db_cache` once the batch
+/// has been stored in the wallet, or discarded. Scanned batches are always
+/// stored in the wallet in the order suggested by the wallet, so that higher-priority
+/// ranges are committed first; if storing a batch changes the suggested ranges, any
+/// batches that have been downloaded or scanned but not yet stored are discarded.
+///
+/// A batch that is scanned before the batches preceding it have been stored cannot detect
+/// spends of the notes that they contain. Such spends are detected when the batch is
+/// stored, in which case the batch is scanned again.
+///
+/// Once scanning is complete, the wallet's outstanding transaction data requests are
+/// serviced; see [`process_transaction_data_requests`]. Requests that cannot be serviced
+/// because the chain data source fails are reported as [`SyncEvent::RequestFailed`]
+/// events, and are left for a later sync.
+///
+/// If [`SyncOptions::watch_mempool`] is set, the mempool is then watched until a new block
+/// is mined.
+///
+/// Progress is reported as [`SyncEvent`]s via `monitor`. If `monitor` is cancelled, this
+/// returns [`Error::Cancelled`] at the next opportunity.
+pub async fn run_with_options<P, ChT, CaT, DbT>(
+    client: &mut ChT,
+    params: &P,
+    db_cache: &CaT,
+    db_data: &mut DbT,
+    options: &SyncOptions,
+    monitor: &SyncMonitor,
+) -> Result<
+    (),
+    Error<
+        CaT::Error,
+        <DbT as WalletRead>::Error,
+        <DbT as WalletCommitmentTrees>::Error,
+        ChT::Error,
+    >,
+>
+where
+    P: Parameters + Send + 'static,
+    ChT: ChainDataSource + Clone + 'static,
+    ChT::Error: fmt::Display + Send + 'static,
+    CaT: BlockCache,
+    CaT::Error: std::error::Error + Send + Sync + 'static,
+    DbT: WalletWrite + WalletCommitmentTrees + InputSource<Error = <DbT as WalletRead>::Error>,
+    <DbT as WalletRead>::AccountId: ConditionallySelectable + Default + Send + 'static,
     <DbT as WalletRead>::Error: std::error::Error + Send + Sync + 'static,
     <DbT as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
 {
@@ -78,48 +208,60 @@ where
         .map_err(Error::Wallet)?
         .unwrap_or_else(|| params.activation_height(NetworkUpgrade::Sapling).unwrap());
 
-    // 1) Download note commitment tree data from lightwalletd
+    // 1) Download note commitment tree data from the chain data source
     // 2) Pass the commitment tree data to the database.
-    update_subtree_roots(client, db_data).await?;
+    update_subtree_roots(client, db_data, monitor).await?;
 
     while running(
         client,
         params,
         db_cache,
         db_data,
-        batch_size,
+        options,
+        monitor,
         #[cfg(feature = "transparent-inputs")]
         wallet_birthday,
     )
     .await?
     {}
 
+    // 8) Download the full transactions that the wallet has requested, and query the
+    //    status of transactions that were not detected by scanning.
+    process_transaction_data_requests(client, params, db_data, &options.transaction_data, monitor)
+        .await?;
+
+    // 9) Watch the mempool for transactions that are relevant to the wallet.
+    if options.watch_mempool {
+        watch_mempool(client, params, db_data, monitor).await?;
+    }
+
     Ok(())
 }
 
 async fn running<P, ChT, CaT, DbT, TrErr>(
-    client: &mut CompactTxStreamerClient<ChT>,
+    client: &mut ChT,
     params: &P,
     db_cache: &CaT,
     db_data: &mut DbT,
-    batch_size: u32,
+    options: &SyncOptions,
+    monitor: &SyncMonitor,
     #[cfg(feature = "transparent-inputs")] wallet_birthday: BlockHeight,
-) -> Result<bool, Error<CaT::Error, <DbT as WalletRead>::Error, TrErr>>
+) -> Result<bool, Error<CaT::Error, <DbT as WalletRead>::Error, TrErr, ChT::Error>>
 where
     P: Parameters + Send + 'static,
-    ChT: GrpcService<BoxBody>,
-    ChT::Error: Into<StdError>,
-    ChT::ResponseBody: Body<Data = Bytes> + Send + 'static,
-    <ChT::ResponseBody as Body>::Error: Into<StdError> + Send,
+    ChT: ChainDataSource + Clone + 'static,
+    ChT::Error: Send + 'static,
     CaT: BlockCache,
     CaT::Error: std::error::Error + Send + Sync + 'static,
     DbT: WalletWrite,
     DbT::AccountId: ConditionallySelectable + Default + Send + 'static,
     DbT::Error: std::error::Error + Send + Sync + 'static,
 {
-    // 3) Download chain tip metadata from lightwalletd
+    ensure_not_cancelled(monitor)?;
+
+    // 3) Download chain tip metadata from the chain data source
     // 4) Notify the wallet of the updated chain tip.
-    update_chain_tip(client, db_data).await?;
+    update_chain_tip(client, db_data, monitor).await?;
 
     // Refresh UTXOs for the accounts in the wallet. We do this before we perform
     // any shielded scanning, to ensure that we discover any UTXOs between the old
@@ -135,7 +277,7 @@ where
             "Refreshing UTXOs for {:?} from height {}",
             account_id, start_height,
         );
-        refresh_utxos(params, client, db_data, account_id, start_height).await?;
+        refresh_utxos(params, client, db_data, account_id, start_height, monitor).await?;
     }
 
     // 5) Get the suggested scan ranges from the wallet database
@@ -163,7 +305,8 @@ where
                 // indicate the wallet's chain tip is out of sync with blockchain
                 // history.
                 let scan_ranges_updated =
-                    scan_blocks(params, db_cache, db_data, &chain_state, scan_range).await?;
+                    scan_blocks(params, db_cache, db_data, &chain_state, scan_range, monitor)
+                        .await?;
 
                 // Delete the now-scanned blocks, because keeping the entire chain
                 // in CompactBlock files on disk is horrendous for the filesystem.
@@ -188,13 +331,19 @@ where
         }
     }
 
-    // 7) Loop over the remaining suggested scan ranges, retrieving the requested data
-    //    and calling `scan_cached_blocks` on each range.
+    info!("Waiting for cached blocks to be deleted...");
+    for deletion in block_deletions {
+        deletion.await.map_err(Error::Cache)?;
+    }
+
+    // 7) Loop over the remaining suggested scan ranges, downloading and scanning each
+    //    batch, and storing the scanned blocks in the wallet.
     let scan_ranges = db_data.suggest_scan_ranges().map_err(Error::Wallet)?;
     debug!("Suggested ranges: {:?}", scan_ranges);
-    for scan_range in scan_ranges.into_iter().flat_map(|r| {
+    let batch_size = options.batch_size;
+    let batches = scan_ranges.into_iter().flat_map(|r| {
         // Limit the number of blocks we download and scan at any one time.
-        (0..).scan(r, |acc, _| {
+        (0..).scan(r, move |acc, _| {
             if acc.is_empty() {
                 None
             } else if let Some((cur, next)) = acc.split_at(acc.block_range().start + batch_size) {
@@ -207,186 +356,615 @@ where
                 Some(cur)
             }
         })
-    }) {
-        // Download the blocks in `scan_range` into the block source.
-        download_blocks(client, db_cache, &scan_range).await?;
+    });
+
+    // The ranges of the batches that are being scanned, in the order in which they will be
+    // stored. Their blocks are in the block cache, unless they could not be downloaded.
+    let mut cached_ranges = VecDeque::new();
+    let ranges_updated = scan_batches(
+        client,
+        params,
+        db_cache,
+        db_data,
+        options,
+        monitor,
+        batches,
+        &mut cached_ranges,
+    )
+    .await;
+
+    // However scanning ended, the batches that were still being scanned will not be stored,
+    // and their ranges may no longer be suggested, so their blocks are removed from the
+    // block cache. A failure to remove them is only reported if scanning succeeded.
+    for scan_range in cached_ranges {
+        if let Err(e) = db_cache.delete(scan_range).await {
+            if ranges_updated.is_ok() {
+                return Err(Error::Cache(e));
+            }
+            warn!(
+                "Failed to remove unscanned blocks from the block cache: {}",
+                e
+            );
+        }
+    }
+
+    ranges_updated
+}
+
+/// Downloads, scans and stores the given batches, returning whether the suggested scan
+/// ranges have been updated.
+///
+/// Each batch is downloaded by a separate task, and the tasks complete in any order, but
+/// their blocks are inserted into the block cache, scanned, and stored in the order of the
+/// given batches. Any tasks that are still pending when this function returns are aborted.
+///
+/// The ranges of the batches whose blocks have been inserted into the block cache but not
+/// yet stored are left in `cached_ranges` when this returns, for the caller to remove. The
+/// range of a batch that could not be stored is not, so that its blocks are kept.
+#[allow(clippy::too_many_arguments)]
+async fn scan_batches<P, ChT, CaT, DbT, TrErr>(
+    client: &ChT,
+    params: &P,
+    db_cache: &CaT,
+    db_data: &mut DbT,
+    options: &SyncOptions,
+    monitor: &SyncMonitor,
+    mut batches: impl Iterator<Item = ScanRange>,
+    cached_ranges: &mut VecDeque<ScanRange>,
+) -> Result<bool, Error<CaT::Error, <DbT as WalletRead>::Error, TrErr, ChT::Error>>
+where
+    P: Parameters + Send + 'static,
+    ChT: ChainDataSource + Clone + 'static,
+    ChT::Error: Send + 'static,
+    CaT: BlockCache,
+    CaT::Error: std::error::Error + Send + Sync + 'static,
+    DbT: WalletWrite,
+    DbT::AccountId: ConditionallySelectable + Default + Send + 'static,
+    DbT::Error: std::error::Error + Send + Sync + 'static,
+{
+    let ufvks = db_data
+        .get_unified_full_viewing_keys()
+        .map_err(Error::Wallet)?;
+    let max_scans = options.max_concurrent_scans.max(1);
+    let mut downloads = TaskQueue::new();
+    let mut scans = TaskQueue::new();
+    let mut downloaded_bytes = 0;
+    let mut downloaded_batches = 0;
+    loop {
+        ensure_not_cancelled(monitor)?;
 
-        let chain_state = download_chain_state(client, scan_range.block_range().start - 1).await?;
+        // Estimate how many batches fit within the memory bound from the batches seen so
+        // far. Until a batch has been downloaded, we have no estimate, so we only
+        // download one.
+        let max_downloads = if downloaded_batches == 0 {
+            1
+        } else {
+            let batch_bytes = (downloaded_bytes / downloaded_batches).max(1);
+            (options.max_cached_bytes / batch_bytes)
+                .clamp(1, options.max_concurrent_downloads.max(1))
+        };
+        while downloads.len() < max_downloads {
+            match batches.next() {
+                Some(scan_range) => {
+                    downloads.push(tokio::spawn(fetch_batch(client.clone(), scan_range)))
+                }
+                None => break,
+            }
+        }
 
-        // Scan the downloaded blocks.
-        let scan_ranges_updated =
-            scan_blocks(params, db_cache, db_data, &chain_state, &scan_range).await?;
+        // Store the oldest scanned batch as soon as it is ready. Until then, move the
+        // oldest downloaded batch into the block cache and start scanning it, unless
+        // enough batches are already being scanned.
+        let next = tokio::select! {
+            biased;
+            Some(scanned) = scans.next() => Next::Store(scanned),
+            Some(fetched) = downloads.next(), if scans.len() < max_scans => Next::Scan(fetched),
+            else => return Ok(false),
+        };
 
-        // Delete the now-scanned blocks.
-        block_deletions.push(db_cache.delete(scan_range));
+        match next {
+            Next::Store(scanned) => {
+                let scan_range = cached_ranges
+                    .pop_front()
+                    .expect("every batch being scanned has a range");
+                let scan_ranges_updated = match scanned {
+                    Ok(batch) => store_batch(params, db_cache, db_data, batch, monitor).await,
+                    Err(e) => Err(e.into()),
+                }?;
 
-        if scan_ranges_updated {
-            // The suggested scan ranges have been updated (either due to a continuity
-            // error or because a higher priority range has been added).
-            info!("Waiting for cached blocks to be deleted...");
-            for deletion in block_deletions {
-                deletion.await.map_err(Error::Cache)?;
+                // Delete the now-scanned blocks, because keeping the entire chain in the
+                // block cache is horrendous for the filesystem. If the batch could not be
+                // stored, the blocks are kept so that they can be scanned again.
+                db_cache.delete(scan_range).await.map_err(Error::Cache)?;
+
+                if scan_ranges_updated {
+                    // The suggested scan ranges have been updated (either due to a
+                    // continuity error or because a higher priority range has been
+                    // added).
+                    return Ok(true);
+                }
+            }
+            Next::Scan((scan_range, fetched)) => {
+                cached_ranges.push_back(scan_range.clone());
+                match fetched {
+                    Ok((compact_blocks, chain_state)) => {
+                        downloaded_bytes += compact_blocks
+                            .iter()
+                            .map(|block| block.encoded_len())
+                            .sum::<usize>();
+                        downloaded_batches += 1;
+
+                        db_cache
+                            .insert(compact_blocks)
+                            .await
+                            .map_err(Error::Cache)?;
+                        let blocks = db_cache.read(&scan_range).await.map_err(Error::Cache)?;
+                        scans.push(scan_cached_batch(
+                            params.clone(),
+                            scan_range,
+                            blocks,
+                            chain_state,
+                            ufvks.clone(),
+                            unspent_nullifiers(db_data).map_err(Error::Wallet)?,
+                        ));
+                    }
+                    // The error is reported once the preceding batches have been stored.
+                    Err(e) => scans.push(tokio::spawn(std::future::ready(Err(e)))),
+                }
             }
-            return Ok(true);
         }
     }
+}
 
-    info!("Waiting for cached blocks to be deleted...");
-    for deletion in block_deletions {
-        deletion.await.map_err(Error::Cache)?;
+/// A queue of spawned tasks, which are awaited in the order in which they were spawned.
+///
+/// The tasks that remain in the queue when it is dropped are aborted, so that they do not
+/// outlive the sync that spawned them, whichever way it exits.
+struct TaskQueue<T>(VecDeque<JoinHandle<T>>);
+
+impl<T> TaskQueue<T> {
+    fn new() -> Self {
+        TaskQueue(VecDeque::new())
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn push(&mut self, task: JoinHandle<T>) {
+        self.0.push_back(task);
+    }
+
+    /// Waits for the oldest task in the queue to complete, and returns its output.
+    ///
+    /// The task remains in the queue until it has completed, so that it is aborted if this
+    /// future is dropped.
+    async fn next(&mut self) -> Option<T> {
+        let output = self.0.front_mut()?.await;
+        self.0.pop_front();
+        Some(output.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic())))
+    }
+}
+
+impl<T> Drop for TaskQueue<T> {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+fn ensure_not_cancelled<CaErr, DbErr, TrErr, SrcErr>(
+    monitor: &SyncMonitor,
+) -> Result<(), Error<CaErr, DbErr, TrErr, SrcErr>> {
+    if monitor.is_cancelled() {
+        Err(Error::Cancelled)
+    } else {
+        Ok(())
     }
-    Ok(false)
 }
 
 async fn update_subtree_roots<ChT, DbT, CaErr, DbErr>(
-    client: &mut CompactTxStreamerClient<ChT>,
+    client: &mut ChT,
     db_data: &mut DbT,
-) -> Result<(), Error<CaErr, DbErr, <DbT as WalletCommitmentTrees>::Error>>
+    monitor: &SyncMonitor,
+) -> Result<(), Error<CaErr, DbErr, <DbT as WalletCommitmentTrees>::Error, ChT::Error>>
 where
-    ChT: GrpcService<BoxBody>,
-    ChT::Error: Into<StdError>,
-    ChT::ResponseBody: Body<Data = Bytes> + Send + 'static,
-    <ChT::ResponseBody as Body>::Error: Into<StdError> + Send,
+    ChT: ChainDataSource,
+    ChT::Error: Send,
     DbT: WalletCommitmentTrees,
     <DbT as WalletCommitmentTrees>::Error: std::error::Error + Send + Sync + 'static,
 {
-    let mut request = service::GetSubtreeRootsArg::default();
-    request.set_shielded_protocol(service::ShieldedProtocol::Sapling);
-    // Hack to work around a bug in the initial lightwalletd implementation.
-    request.max_entries = 65536;
-
     let sapling_roots: Vec<CommitmentTreeRoot<sapling::Node>> = client
-        .get_subtree_roots(request)
-        .await?
-        .into_inner()
-        .and_then(|root| async move {
-            let root_hash = sapling::Node::read(&root.root_hash[..])?;
+        .get_subtree_roots(ShieldedProtocol::Sapling, 0)
+        .await
+        .map_err(Error::Server)?
+        .into_iter()
+        .map(|root| {
+            let root_hash =
+                sapling::Node::read(&root.root_hash[..]).map_err(|_| Error::MisbehavingServer)?;
             Ok(CommitmentTreeRoot::from_parts(
                 BlockHeight::from_u32(root.completing_block_height as u32),
                 root_hash,
             ))
         })
-        .try_collect()
-        .await?;
+        .collect::<Result<_, Error<_, _, _, ChT::Error>>>()?;
 
     info!("Sapling tree has {} subtrees", sapling_roots.len());
     db_data
         .put_sapling_subtree_roots(0, &sapling_roots)
         .map_err(Error::WalletTrees)?;
+    monitor.emit(SyncEvent::SubtreeRootsUpdated {
+        protocol: ShieldedProtocol::Sapling,
+        subtree_count: sapling_roots.len(),
+    });
 
     #[cfg(feature = "orchard")]
     {
-        let mut request = service::GetSubtreeRootsArg::default();
-        request.set_shielded_protocol(service::ShieldedProtocol::Orchard);
-        // Hack to work around a bug in the initial lightwalletd implementation.
-        request.max_entries = 65536;
         let orchard_roots: Vec<CommitmentTreeRoot<MerkleHashOrchard>> = client
-            .get_subtree_roots(request)
-            .await?
-            .into_inner()
-            .and_then(|root| async move {
-                let root_hash = MerkleHashOrchard::read(&root.root_hash[..])?;
+            .get_subtree_roots(ShieldedProtocol::Orchard, 0)
+            .await
+            .map_err(Error::Server)?
+            .into_iter()
+            .map(|root| {
+                let root_hash = MerkleHashOrchard::read(&root.root_hash[..])
+                    .map_err(|_| Error::MisbehavingServer)?;
                 Ok(CommitmentTreeRoot::from_parts(
                     BlockHeight::from_u32(root.completing_block_height as u32),
                     root_hash,
                 ))
             })
-            .try_collect()
-            .await?;
+            .collect::<Result<_, Error<_, _, _, ChT::Error>>>()?;
 
         info!("Orchard tree has {} subtrees", orchard_roots.len());
         db_data
             .put_orchard_subtree_roots(0, &orchard_roots)
             .map_err(Error::WalletTrees)?;
+        monitor.emit(SyncEvent::SubtreeRootsUpdated {
+            protocol: ShieldedProtocol::Orchard,
+            subtree_count: orchard_roots.len(),
+        });
     }
 
     Ok(())
 }
 
 async fn update_chain_tip<ChT, DbT, CaErr, TrErr>(
-    client: &mut CompactTxStreamerClient<ChT>,
+    client: &mut ChT,
     db_data: &mut DbT,
-) -> Result<(), Error<CaErr, <DbT as WalletRead>::Error, TrErr>>
+    monitor: &SyncMonitor,
+) -> Result<(), Error<CaErr, <DbT as WalletRead>::Error, TrErr, ChT::Error>>
 where
-    ChT: GrpcService<BoxBody>,
-    ChT::Error: Into<StdError>,
-    ChT::ResponseBody: Body<Data = Bytes> + Send + 'static,
-    <ChT::ResponseBody as Body>::Error: Into<StdError> + Send,
+    ChT: ChainDataSource,
+    ChT::Error: Send,
     DbT: WalletWrite,
     DbT::Error: std::error::Error + Send + Sync + 'static,
 {
-    let tip_height: BlockHeight = client
-        .get_latest_block(service::ChainSpec::default())
-        .await?
-        .get_ref()
-        .height
-        .try_into()
-        .map_err(|_| Error::MisbehavingServer)?;
+    let tip_height = client.get_latest_block().await.map_err(Error::Server)?;
 
     info!("Latest block height is {}", tip_height);
     db_data
         .update_chain_tip(tip_height)
         .map_err(Error::Wallet)?;
+    monitor.emit(SyncEvent::ChainTipUpdated(tip_height));
 
     Ok(())
 }
 
+/// The next step of the scanning pipeline in [`running`].
+enum Next<AccountId, SrcErr> {
+    /// Store a batch that has been scanned.
+    Store(Result<ScannedBatch<AccountId>, BatchError<SrcErr>>),
+    /// Cache and scan a batch that has been downloaded.
+    Scan(FetchedBatch<SrcErr>),
+}
+
+/// The blocks in a scan range, along with the chain state as of the start of the range.
+type FetchedBatch<SrcErr> = (
+    ScanRange,
+    Result<(Vec<CompactBlock>, ChainState), BatchError<SrcErr>>,
+);
+
+/// Downloads the blocks in `scan_range`, along with the tree state as of the start of the
+/// range.
+async fn fetch_batch<ChT>(mut client: ChT, scan_range: ScanRange) -> FetchedBatch<ChT::Error>
+where
+    ChT: ChainDataSource,
+    ChT::Error: Send,
+{
+    info!("Fetching {}", scan_range);
+    let fetched = async {
+        let compact_blocks = client
+            .get_block_range(scan_range.block_range().clone())
+            .await
+            .map_err(BatchError::Server)?;
+        let chain_state = client
+            .get_tree_state(scan_range.block_range().start - 1)
+            .await
+            .map_err(BatchError::Server)?
+            .to_chain_state()
+            .map_err(|_| BatchError::MisbehavingServer)?;
+        Ok((compact_blocks, chain_state))
+    }
+    .await;
+    (scan_range, fetched)
+}
+
+/// A batch of blocks that has been scanned from the block cache, but not yet stored in
+/// the wallet.
+struct ScannedBatch<AccountId> {
+    scan_range: ScanRange,
+    /// The chain state as of the start of the batch, according to the chain data source.
+    chain_state: ChainState,
+    scanned: Result<(Vec<ScannedBlock<AccountId>>, ScanSummary), ScanError>,
+}
+
+/// The errors that can occur while downloading a batch of blocks.
+enum BatchError<SrcErr> {
+    Server(SrcErr),
+    MisbehavingServer,
+}
+
+impl<CaErr, DbErr, TrErr, SrcErr> From<BatchError<SrcErr>> for Error<CaErr, DbErr, TrErr, SrcErr> {
+    fn from(e: BatchError<SrcErr>) -> Self {
+        match e {
+            BatchError::Server(e) => Error::Server(e),
+            BatchError::MisbehavingServer => Error::MisbehavingServer,
+        }
+    }
+}
+
+/// Scans the given blocks, which were read from the block cache, for transactions
+/// involving the given accounts, without storing them in the wallet.
+///
+/// The scan runs on Tokio's blocking thread pool.
+fn scan_cached_batch<P, AccountId, SrcErr>(
+    params: P,
+    scan_range: ScanRange,
+    blocks: Vec<CompactBlock>,
+    chain_state: ChainState,
+    ufvks: HashMap<AccountId, UnifiedFullViewingKey>,
+    nullifiers: Nullifiers<AccountId>,
+) -> JoinHandle<Result<ScannedBatch<AccountId>, BatchError<SrcErr>>>
+where
+    P: Parameters + Send + 'static,
+    AccountId: Copy + Eq + Hash + ConditionallySelectable + Default + Send + 'static,
+    SrcErr: Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        info!("Scanning {}", scan_range);
+        let scanned = scan_batch(
+            &params,
+            &BatchBlocks(blocks),
+            &chain_state,
+            &scan_range,
+            ufvks,
+            nullifiers,
+        );
+        Ok(ScannedBatch {
+            scan_range,
+            chain_state,
+            scanned,
+        })
+    })
+}
+
+/// Scans the given blocks, which follow `chain_state`, for transactions involving the
+/// given accounts.
+#[allow(clippy::type_complexity)]
+fn scan_batch<P, AccountId>(
+    params: &P,
+    blocks: &BatchBlocks,
+    chain_state: &ChainState,
+    scan_range: &ScanRange,
+    ufvks: HashMap<AccountId, UnifiedFullViewingKey>,
+    nullifiers: Nullifiers<AccountId>,
+) -> Result<(Vec<ScannedBlock<AccountId>>, ScanSummary), ScanError>
+where
+    P: Parameters + Send + 'static,
+    AccountId: Copy + Eq + Hash + ConditionallySelectable + Default + Send + 'static,
+{
+    let prior_block_metadata = BlockMetadata::from_parts(
+        chain_state.block_height(),
+        chain_state.block_hash(),
+        chain_state.final_sapling_tree().tree_size().try_into().ok(),
+        #[cfg(feature = "orchard")]
+        chain_state.final_orchard_tree().tree_size().try_into().ok(),
+    );
+
+    scan_block_range::<_, _, _, Infallible>(
+        params,
+        blocks,
+        ufvks,
+        nullifiers,
+        Some(prior_block_metadata),
+        scan_range.block_range().start,
+        scan_range.len(),
+    )
+    .map_err(|e| match e {
+        ChainError::Scan(e) => e,
+        ChainError::Wallet(e) => match e {},
+        ChainError::BlockSource(e) => match e {},
+    })
+}
+
+/// Returns the nullifiers of the unspent notes that the wallet is tracking.
+fn unspent_nullifiers<DbT: WalletRead>(
+    db_data: &DbT,
+) -> Result<Nullifiers<DbT::AccountId>, DbT::Error> {
+    Ok(Nullifiers::new(
+        db_data.get_sapling_nullifiers(NullifierQuery::Unspent)?,
+        #[cfg(feature = "orchard")]
+        db_data.get_orchard_nullifiers(NullifierQuery::Unspent)?,
+    ))
+}
+
+/// Returns whether the given blocks spend any of the given notes without the spends having
+/// been detected by scanning. This happens when the notes were stored in the wallet after
+/// the blocks were scanned.
+fn has_undetected_spends<AccountId>(
+    blocks: &[ScannedBlock<AccountId>],
+    nullifiers: &Nullifiers<AccountId>,
+) -> bool {
+    // The nullifier maps of the scanned blocks contain the nullifiers that were not
+    // detected as spends of the wallet's notes.
+    let sapling_nullifiers: BTreeSet<_> = nullifiers.sapling().iter().map(|(_, nf)| nf).collect();
+    let spends_sapling = blocks.iter().any(|block| {
+        block
+            .sapling()
+            .nullifier_map()
+            .iter()
+            .flat_map(|(_, _, nfs)| nfs)
+            .any(|nf| sapling_nullifiers.contains(nf))
+    });
+
+    #[cfg(feature = "orchard")]
+    let spends_orchard = {
+        let orchard_nullifiers: BTreeSet<_> =
+            nullifiers.orchard().iter().map(|(_, nf)| nf).collect();
+        blocks.iter().any(|block| {
+            block
+                .orchard()
+                .nullifier_map()
+                .iter()
+                .flat_map(|(_, _, nfs)| nfs)
+                .any(|nf| orchard_nullifiers.contains(nf))
+        })
+    };
+    #[cfg(not(feature = "orchard"))]
+    let spends_orchard = false;
+
+    spends_sapling || spends_orchard
+}
+
+/// Stores a scanned batch of blocks in the wallet, once it has been checked against the
+/// blocks that the wallet has already scanned.
+///
+/// If the batch spends notes that were stored in the wallet after it was scanned, it is
+/// scanned again from the block cache so that the spends are detected.
+///
+/// Returns `true` if storing these blocks materially changed the suggested scan ranges.
+async fn store_batch<P, CaT, DbT, TrErr, SrcErr>(
+    params: &P,
+    db_cache: &CaT,
+    db_data: &mut DbT,
+    batch: ScannedBatch<DbT::AccountId>,
+    monitor: &SyncMonitor,
+) -> Result<bool, Error<CaT::Error, <DbT as WalletRead>::Error, TrErr, SrcErr>>
+where
+    P: Parameters + Send + 'static,
+    CaT: BlockCache,
+    CaT::Error: std::error::Error + Send + Sync + 'static,
+    DbT: WalletWrite,
+    DbT::AccountId: ConditionallySelectable + Default + Send + 'static,
+    DbT::Error: std::error::Error + Send + Sync + 'static,
+{
+    let ScannedBatch {
+        scan_range,
+        chain_state,
+        scanned,
+    } = batch;
+
+    // The batch was scanned from the chain state provided by the chain data source, which
+    // must match the wallet's view of the chain if it has scanned the preceding block.
+    let wallet_hash = db_data
+        .block_metadata(chain_state.block_height())
+        .map_err(Error::Wallet)?
+        .map(|meta| meta.block_hash());
+    let result = match scanned {
+        Ok(_) if wallet_hash.map_or(false, |hash| hash != chain_state.block_hash()) => {
+            Err(ChainError::Scan(ScanError::PrevHashMismatch {
+                at_height: scan_range.block_range().start,
+            }))
+        }
+        Ok((scanned_blocks, scan_summary)) => {
+            let nullifiers = unspent_nullifiers(db_data).map_err(Error::Wallet)?;
+            if has_undetected_spends(&scanned_blocks, &nullifiers) {
+                debug!("Rescanning {} to detect spends of new notes", scan_range);
+                return scan_blocks(
+                    params,
+                    db_cache,
+                    db_data,
+                    &chain_state,
+                    &scan_range,
+                    monitor,
+                )
+                .await;
+            }
+            db_data
+                .put_blocks(&chain_state, scanned_blocks)
+                .map(|_| scan_summary)
+                .map_err(ChainError::Wallet)
+        }
+        Err(e) => Err(ChainError::Scan(e)),
+    };
+
+    handle_scan_result(db_cache, db_data, &scan_range, result, monitor).await
+}
+
+/// The blocks of a cached batch, as a [`BlockSource`] from which they can be scanned.
+struct BatchBlocks(Vec<CompactBlock>);
+
+impl BlockSource for BatchBlocks {
+    type Error = Infallible;
+
+    fn with_blocks<F, WalletErrT>(
+        &self,
+        from_height: Option<BlockHeight>,
+        limit: Option<usize>,
+        mut with_block: F,
+    ) -> Result<(), ChainError<WalletErrT, Self::Error>>
+    where
+        F: FnMut(CompactBlock) -> Result<(), ChainError<WalletErrT, Self::Error>>,
+    {
+        for block in self
+            .0
+            .iter()
+            .filter(|block| from_height.map_or(true, |from_height| block.height() >= from_height))
+            .take(limit.unwrap_or(usize::MAX))
+        {
+            with_block(block.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// Downloads the blocks in `scan_range` into the block cache, overwriting any existing
+/// blocks in the range.
 async fn download_blocks<ChT, CaT, DbErr, TrErr>(
-    client: &mut CompactTxStreamerClient<ChT>,
+    client: &mut ChT,
     db_cache: &CaT,
     scan_range: &ScanRange,
-) -> Result<(), Error<CaT::Error, DbErr, TrErr>>
+) -> Result<(), Error<CaT::Error, DbErr, TrErr, ChT::Error>>
 where
-    ChT: GrpcService<BoxBody>,
-    ChT::Error: Into<StdError>,
-    ChT::ResponseBody: Body<Data = Bytes> + Send + 'static,
-    <ChT::ResponseBody as Body>::Error: Into<StdError> + Send,
+    ChT: ChainDataSource,
+    ChT::Error: Send,
     CaT: BlockCache,
     CaT::Error: std::error::Error + Send + Sync + 'static,
 {
     info!("Fetching {}", scan_range);
-    let mut start = service::BlockId::default();
-    start.height = scan_range.block_range().start.into();
-    let mut end = service::BlockId::default();
-    end.height = (scan_range.block_range().end - 1).into();
-    let range = service::BlockRange {
-        start: Some(start),
-        end: Some(end),
-    };
     let compact_blocks = client
-        .get_block_range(range)
-        .await?
-        .into_inner()
-        .try_collect::<Vec<_>>()
-        .await?;
-
-    db_cache
-        .insert(compact_blocks)
+        .get_block_range(scan_range.block_range().clone())
         .await
-        .map_err(Error::Cache)?;
+        .map_err(Error::Server)?;
 
-    Ok(())
+    db_cache.insert(compact_blocks).await.map_err(Error::Cache)
 }
 
 async fn download_chain_state<ChT, CaErr, DbErr, TrErr>(
-    client: &mut CompactTxStreamerClient<ChT>,
+    client: &mut ChT,
     block_height: BlockHeight,
-) -> Result<ChainState, Error<CaErr, DbErr, TrErr>>
+) -> Result<ChainState, Error<CaErr, DbErr, TrErr, ChT::Error>>
 where
-    ChT: GrpcService<BoxBody>,
-    ChT::Error: Into<StdError>,
-    ChT::ResponseBody: Body<Data = Bytes> + Send + 'static,
-    <ChT::ResponseBody as Body>::Error: Into<StdError> + Send,
+    ChT: ChainDataSource,
+    ChT::Error: Send,
 {
     let tree_state = client
-        .get_tree_state(BlockId {
-            height: block_height.into(),
-            hash: vec![],
-        })
-        .await?;
+        .get_tree_state(block_height)
+        .await
+        .map_err(Error::Server)?;
 
     tree_state
-        .into_inner()
         .to_chain_state()
         .map_err(|_| Error::MisbehavingServer)
 }
@@ -395,13 +973,14 @@ where
 /// chain tip is out of sync with blockchain history.
 ///
 /// Returns `true` if scanning these blocks materially changed the suggested scan ranges.
-async fn scan_blocks<P, CaT, DbT, TrErr>(
+async fn scan_blocks<P, CaT, DbT, TrErr, SrcErr>(
     params: &P,
     db_cache: &CaT,
     db_data: &mut DbT,
     initial_chain_state: &ChainState,
     scan_range: &ScanRange,
-) -> Result<bool, Error<CaT::Error, <DbT as WalletRead>::Error, TrErr>>
+    monitor: &SyncMonitor,
+) -> Result<bool, Error<CaT::Error, <DbT as WalletRead>::Error, TrErr, SrcErr>>
 where
     P: Parameters + Send + 'static,
     CaT: BlockCache,
@@ -411,15 +990,37 @@ where
     DbT::Error: std::error::Error + Send + Sync + 'static,
 {
     info!("Scanning {}", scan_range);
-    let scan_result = scan_cached_blocks(
-        params,
-        db_cache,
-        db_data,
-        scan_range.block_range().start,
-        initial_chain_state,
-        scan_range.len(),
-    );
+    let scan_result = block_in_place(|| {
+        scan_cached_blocks(
+            params,
+            db_cache,
+            db_data,
+            scan_range.block_range().start,
+            initial_chain_state,
+            scan_range.len(),
+        )
+    });
+
+    handle_scan_result(db_cache, db_data, scan_range, scan_result, monitor).await
+}
 
+/// Handles the result of scanning `scan_range` and storing the scanned blocks in the
+/// wallet, rewinding the wallet if the blocks do not continue its view of the chain.
+///
+/// Returns `true` if scanning these blocks materially changed the suggested scan ranges.
+async fn handle_scan_result<CaT, DbT, TrErr, SrcErr>(
+    db_cache: &CaT,
+    db_data: &mut DbT,
+    scan_range: &ScanRange,
+    scan_result: Result<ScanSummary, ChainError<<DbT as WalletRead>::Error, CaT::Error>>,
+    monitor: &SyncMonitor,
+) -> Result<bool, Error<CaT::Error, <DbT as WalletRead>::Error, TrErr, SrcErr>>
+where
+    CaT: BlockCache,
+    CaT::Error: std::error::Error + Send + Sync + 'static,
+    DbT: WalletWrite,
+    DbT::Error: std::error::Error + Send + Sync + 'static,
+{
     match scan_result {
         Err(ChainError::Scan(err)) if err.is_continuity_error() => {
             // Pick a height to rewind to, which must be at least one block before the
@@ -447,11 +1048,14 @@ where
                 .truncate(rewind_height)
                 .await
                 .map_err(Error::Cache)?;
+            monitor.emit(SyncEvent::Rewound(rewind_height));
 
             // The database was truncated, invalidating prior suggested ranges.
             Ok(true)
         }
-        Ok(_) => {
+        Ok(summary) => {
+            monitor.emit(SyncEvent::RangeScanned(summary));
+
             // If scanning these blocks caused a suggested range to be added that has a
             // higher priority than the current range, invalidate the current ranges.
             let latest_ranges = db_data.suggest_scan_ranges().map_err(Error::Wallet)?;
@@ -466,6 +1070,20 @@ where
     }
 }
 
+/// Runs the given blocking operation.
+///
+/// On a multi-threaded Tokio runtime, this uses [`tokio::task::block_in_place`], so that
+/// other tasks (such as block downloads) can continue to run while `f` blocks the current
+/// thread. Otherwise, `f` is run directly.
+fn block_in_place<T>(f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
 /// Refreshes the given account's view of UTXOs that exist starting at the given height.
 ///
 /// ## Note about UTXO tracking
@@ -494,83 +1112,582 @@ where
 #[cfg(feature = "transparent-inputs")]
 async fn refresh_utxos<P, ChT, DbT, CaErr, TrErr>(
     params: &P,
-    client: &mut CompactTxStreamerClient<ChT>,
+    client: &mut ChT,
     db_data: &mut DbT,
     account_id: DbT::AccountId,
     start_height: BlockHeight,
-) -> Result<(), Error<CaErr, <DbT as WalletRead>::Error, TrErr>>
+    monitor: &SyncMonitor,
+) -> Result<(), Error<CaErr, <DbT as WalletRead>::Error, TrErr, ChT::Error>>
 where
     P: Parameters + Send + 'static,
-    ChT: GrpcService<BoxBody>,
-    ChT::Error: Into<StdError>,
-    ChT::ResponseBody: Body<Data = Bytes> + Send + 'static,
-    <ChT::ResponseBody as Body>::Error: Into<StdError> + Send,
+    ChT: ChainDataSource,
+    ChT::Error: Send,
     DbT: WalletWrite,
     DbT::Error: std::error::Error + Send + Sync + 'static,
 {
-    let request = service::GetAddressUtxosArg {
-        addresses: db_data
-            .get_transparent_receivers(account_id)
-            .map_err(Error::Wallet)?
-            .into_keys()
-            .map(|addr| addr.encode(params))
-            .collect(),
-        start_height: start_height.into(),
-        max_entries: 0,
-    };
+    let addresses: Vec<String> = db_data
+        .get_transparent_receivers(account_id)
+        .map_err(Error::Wallet)?
+        .into_keys()
+        .map(|addr| addr.encode(params))
+        .collect();
 
-    if request.addresses.is_empty() {
+    if addresses.is_empty() {
         info!("{:?} has no transparent receivers", account_id);
     } else {
-        client
-            .get_address_utxos_stream(request)
-            .await?
-            .into_inner()
-            .map_err(Error::Server)
-            .and_then(|reply| async move {
-                WalletTransparentOutput::from_parts(
-                    OutPoint::new(
-                        reply.txid[..]
-                            .try_into()
-                            .map_err(|_| Error::MisbehavingServer)?,
-                        reply
-                            .index
-                            .try_into()
-                            .map_err(|_| Error::MisbehavingServer)?,
-                    ),
-                    TxOut {
-                        value: Zatoshis::from_nonnegative_i64(reply.value_zat)
-                            .map_err(|_| Error::MisbehavingServer)?,
-                        script_pubkey: Script(reply.script),
-                    },
-                    Some(
-                        BlockHeight::try_from(reply.height)
-                            .map_err(|_| Error::MisbehavingServer)?,
-                    ),
+        let utxos = client
+            .get_address_utxos(addresses, start_height)
+            .await
+            .map_err(Error::Server)?;
+        let utxo_count = utxos.len();
+        for reply in utxos {
+            let output = WalletTransparentOutput::from_parts(
+                OutPoint::new(
+                    reply.txid[..]
+                        .try_into()
+                        .map_err(|_| Error::MisbehavingServer)?,
+                    reply
+                        .index
+                        .try_into()
+                        .map_err(|_| Error::MisbehavingServer)?,
+                ),
+                TxOut {
+                    value: Zatoshis::from_nonnegative_i64(reply.value_zat)
+                        .map_err(|_| Error::MisbehavingServer)?,
+                    script_pubkey: Script(reply.script),
+                },
+                Some(BlockHeight::try_from(reply.height).map_err(|_| Error::MisbehavingServer)?),
+            )
+            .ok_or(Error::MisbehavingServer)?;
+            db_data
+                .put_received_transparent_utxo(&output)
+                .map_err(Error::Wallet)?;
+        }
+        monitor.emit(SyncEvent::UtxosRefreshed {
+            start_height,
+            utxo_count,
+        });
+    }
+
+    Ok(())
+}
+
+/// Watches the mempool for transactions that are relevant to the wallet, until a new block
+/// is mined.
+///
+/// Each transaction in the mempool is trial-decrypted with the wallet's viewing keys, and
+/// is stored in the wallet as an unmined transaction if it contains outputs that the
+/// wallet can decrypt, or spends shielded notes that the wallet is tracking. With the
+/// `transparent-inputs` feature, transactions that send funds to the wallet's transparent
+/// receivers, or spend the wallet's mined transparent outputs, are also stored. This
+/// allows incoming payments to be shown to the user before they are confirmed.
+///
+/// Spends of notes and transparent outputs that were themselves received in the mempool
+/// are detected for Orchard notes only. The nullifier of a Sapling note depends on the
+/// note's position in the commitment tree, which is not known until the note is mined,
+/// and the wallet does not treat unmined transparent outputs as spendable.
+///
+/// Transactions that cannot be parsed are logged and skipped.
+///
+/// The chain data source closes the mempool stream when a new block is mined, at which
+/// point this function returns. Callers that want to keep the wallet up to date should
+/// then scan the new block, before watching the mempool again. If `monitor` is cancelled
+/// while the mempool is being watched, this returns [`Error::Cancelled`] instead.
+/// [`run_with_options`] scans new blocks and watches the mempool again when
+/// [`SyncOptions::watch_mempool`] is set:
+///
+/// ```ignore
+/// let options = SyncOptions {
+///     watch_mempool: true,
+///     ..Default::default()
+/// };
+/// loop {
+///     run_with_options(&mut client, &params, &db_cache, &mut db_data, &options, &monitor)
+///         .await?;
+/// }
+/// ```
+///
+/// Returns immediately if the wallet has not yet scanned every block up to the chain tip
+/// reported by the chain data source, as the wallet could not detect spends of notes
+/// received in the blocks that it has yet to scan.
+pub async fn watch_mempool<P, ChT, DbT, CaErr, TrErr>(
+    client: &mut ChT,
+    params: &P,
+    db_data: &mut DbT,
+    monitor: &SyncMonitor,
+) -> Result<(), Error<CaErr, <DbT as WalletRead>::Error, TrErr, ChT::Error>>
+where
+    P: Parameters,
+    ChT: ChainDataSource,
+    ChT::Error: Send,
+    DbT: WalletWrite + InputSource<Error = <DbT as WalletRead>::Error>,
+    <DbT as WalletRead>::Error: std::error::Error + Send + Sync + 'static,
+{
+    ensure_not_cancelled(monitor)?;
+    let tip_height = client.get_latest_block().await.map_err(Error::Server)?;
+    match db_data.block_fully_scanned().map_err(Error::Wallet)? {
+        Some(meta) if meta.block_height() >= tip_height => (),
+        _ => {
+            info!(
+                "Not watching the mempool, as the wallet is not synced to height {}",
+                tip_height
+            );
+            return Ok(());
+        }
+    }
+    let mempool_height = tip_height + 1;
+    let branch_id = BranchId::for_height(params, mempool_height);
+    let ufvks = db_data
+        .get_unified_full_viewing_keys()
+        .map_err(Error::Wallet)?;
+    #[allow(unused_mut)]
+    let mut wallet_spends = WalletSpends::load(db_data, &ufvks).map_err(Error::Wallet)?;
+
+    let mut mempool = client.get_mempool_stream().await.map_err(Error::Server)?;
+    info!("Watching the mempool at height {}", mempool_height);
+
+    loop {
+        let raw_tx = tokio::select! {
+            raw_tx = mempool.try_next() => raw_tx.map_err(Error::Server)?,
+            () = monitor.cancelled() => return Err(Error::Cancelled),
+        };
+        let raw_tx = match raw_tx {
+            Some(raw_tx) => raw_tx,
+            None => break,
+        };
+        let tx = match Transaction::read(&raw_tx.data[..], branch_id) {
+            Ok(tx) => tx,
+            Err(e) => {
+                warn!(
+                    "Skipping a mempool transaction that could not be parsed: {}",
+                    e
+                );
+                continue;
+            }
+        };
+
+        let involves_wallet = wallet_spends
+            .involves(db_data, &tx)
+            .map_err(Error::Wallet)?;
+        let d_tx = decrypt_unmined_transaction(params, mempool_height, &tx, &ufvks);
+        #[cfg(feature = "orchard")]
+        let has_wallet_outputs =
+            !(d_tx.sapling_outputs().is_empty() && d_tx.orchard_outputs().is_empty());
+        #[cfg(not(feature = "orchard"))]
+        let has_wallet_outputs = !d_tx.sapling_outputs().is_empty();
+
+        if has_wallet_outputs || involves_wallet {
+            debug!("Storing mempool transaction {}", tx.txid());
+            #[cfg(feature = "orchard")]
+            wallet_spends.insert_received_orchard_notes(&d_tx, &ufvks);
+            db_data.store_decrypted_tx(d_tx).map_err(Error::Wallet)?;
+        }
+    }
+
+    info!("Mempool stream closed; a new block has been mined");
+    Ok(())
+}
+
+/// The wallet data used by [`watch_mempool`] to detect transactions that spend the
+/// wallet's funds, or send funds to its transparent receivers.
+struct WalletSpends {
+    sapling_nullifiers: BTreeSet<sapling::Nullifier>,
+    #[cfg(feature = "orchard")]
+    orchard_nullifiers: BTreeSet<orchard::note::Nullifier>,
+    #[cfg(feature = "transparent-inputs")]
+    transparent_receivers: HashSet<TransparentAddress>,
+}
+
+impl WalletSpends {
+    fn load<DbT: WalletRead>(
+        db_data: &DbT,
+        #[allow(unused_variables)] ufvks: &HashMap<DbT::AccountId, UnifiedFullViewingKey>,
+    ) -> Result<Self, DbT::Error> {
+        let sapling_nullifiers = db_data
+            .get_sapling_nullifiers(NullifierQuery::Unspent)?
+            .into_iter()
+            .map(|(_, nf)| nf)
+            .collect();
+        #[cfg(feature = "orchard")]
+        let orchard_nullifiers = db_data
+            .get_orchard_nullifiers(NullifierQuery::Unspent)?
+            .into_iter()
+            .map(|(_, nf)| nf)
+            .collect();
+        #[cfg(feature = "transparent-inputs")]
+        let transparent_receivers = {
+            let mut receivers = HashSet::new();
+            for account_id in ufvks.keys() {
+                receivers.extend(db_data.get_transparent_receivers(*account_id)?.into_keys());
+            }
+            receivers
+        };
+
+        Ok(WalletSpends {
+            sapling_nullifiers,
+            #[cfg(feature = "orchard")]
+            orchard_nullifiers,
+            #[cfg(feature = "transparent-inputs")]
+            transparent_receivers,
+        })
+    }
+
+    /// Returns whether the given transaction spends the wallet's notes or transparent
+    /// outputs, or sends funds to the wallet's transparent receivers.
+    fn involves<DbT: InputSource>(
+        &self,
+        #[allow(unused_variables)] db_data: &DbT,
+        tx: &Transaction,
+    ) -> Result<bool, DbT::Error> {
+        let spends_sapling = tx.sapling_bundle().map_or(false, |bundle| {
+            bundle
+                .shielded_spends()
+                .iter()
+                .any(|spend| self.sapling_nullifiers.contains(spend.nullifier()))
+        });
+        #[cfg(feature = "orchard")]
+        let spends_orchard = tx.orchard_bundle().map_or(false, |bundle| {
+            bundle
+                .actions()
+                .iter()
+                .any(|action| self.orchard_nullifiers.contains(action.nullifier()))
+        });
+        #[cfg(not(feature = "orchard"))]
+        let spends_orchard = false;
+        if spends_sapling || spends_orchard {
+            return Ok(true);
+        }
+
+        #[cfg(feature = "transparent-inputs")]
+        if let Some(bundle) = tx.transparent_bundle() {
+            if bundle.vout.iter().any(|txout| {
+                txout.recipient_address().map_or(false, |address| {
+                    self.transparent_receivers.contains(&address)
+                })
+            }) {
+                return Ok(true);
+            }
+            for txin in &bundle.vin {
+                if db_data
+                    .get_unspent_transparent_output(&txin.prevout)?
+                    .is_some()
+                {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Tracks the nullifiers of the Orchard notes that the wallet receives in the given
+    /// transaction, so that spends of them in the mempool are detected.
+    #[cfg(feature = "orchard")]
+    fn insert_received_orchard_notes<AccountId: Eq + Hash>(
+        &mut self,
+        d_tx: &DecryptedTransaction<AccountId>,
+        ufvks: &HashMap<AccountId, UnifiedFullViewingKey>,
+    ) {
+        for output in d_tx.orchard_outputs() {
+            if output.transfer_type() == TransferType::Outgoing {
+                continue;
+            }
+            if let Some(fvk) = ufvks.get(output.account()).and_then(|ufvk| ufvk.orchard()) {
+                self.orchard_nullifiers.insert(output.note().nullifier(fvk));
+            }
+        }
+    }
+}
+
+/// Options controlling how [`process_transaction_data_requests`] communicates with the
+/// chain data source.
+#[derive(Clone, Copy, Debug)]
+pub struct TransactionDataOptions {
+    /// The maximum number of requests to the chain data source that may be in flight at
+    /// once.
+    pub max_concurrent_requests: usize,
+    /// The maximum number of times that a failed request to the chain data source is
+    /// retried.
+    pub max_retries: u32,
+    /// The delay before the first retry of a failed request. The delay doubles with each
+    /// subsequent retry.
+    pub initial_retry_delay: Duration,
+}
+
+impl Default for TransactionDataOptions {
+    fn default() -> Self {
+        TransactionDataOptions {
+            max_concurrent_requests: 8,
+            max_retries: 3,
+            initial_retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Services the wallet's outstanding [`TransactionDataRequest`]s.
+///
+/// - For [`TransactionDataRequest::GetStatus`], the status of the transaction is queried
+///   and provided to [`WalletWrite::set_transaction_status`].
+/// - For [`TransactionDataRequest::Enhancement`], the full transaction is downloaded and
+///   provided to [`decrypt_and_store_transaction`], so that its memos and transparent
+///   components are stored in the wallet.
+/// - For `TransactionDataRequest::SpendsFromAddress`, the transactions involving the
+///   address are downloaded and provided to [`decrypt_and_store_transaction`].
+///
+/// Servicing requests can cause the wallet to create new ones (for example, to walk
+/// backwards through transparent transaction history), so this function repeats until
+/// no new requests are returned. Each request is serviced at most once per call.
+///
+/// Requests to the chain data source are made concurrently, with failed requests retried
+/// after an exponentially increasing delay. If a request still fails after the configured
+/// number of retries, or a transaction returned for it cannot be parsed, a
+/// [`SyncEvent::RequestFailed`] event is emitted, and the request is left outstanding in the
+/// wallet for a later call to service. The requests that failed in this way are returned.
+///
+/// Progress is reported as [`SyncEvent`]s via `monitor`. If `monitor` is cancelled, this
+/// returns [`Error::Cancelled`] at the next opportunity; requests that have not yet been
+/// serviced are left for a later call.
+pub async fn process_transaction_data_requests<P, ChT, DbT, CaErr, TrErr>(
+    client: &mut ChT,
+    params: &P,
+    db_data: &mut DbT,
+    options: &TransactionDataOptions,
+    monitor: &SyncMonitor,
+) -> Result<Vec<TransactionDataRequest>, Error<CaErr, <DbT as WalletRead>::Error, TrErr, ChT::Error>>
+where
+    P: Parameters,
+    ChT: ChainDataSource + Clone,
+    ChT::Error: fmt::Display + Send,
+    DbT: WalletWrite,
+    DbT::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut serviced = HashSet::new();
+    let mut failed = vec![];
+    loop {
+        ensure_not_cancelled(monitor)?;
+
+        let requests: Vec<_> = db_data
+            .transaction_data_requests()
+            .map_err(Error::Wallet)?
+            .into_iter()
+            .filter(|request| serviced.insert(request.clone()))
+            .collect();
+        if requests.is_empty() {
+            return Ok(failed);
+        }
+        let request_count = requests.len();
+        let failed_count = failed.len();
+        info!("Servicing {} transaction data requests", request_count);
+
+        let tip_height = db_data.chain_height().map_err(Error::Wallet)?;
+        let mut responses = stream::iter(requests)
+            .map(|request| {
+                fetch_with_retries(
+                    client.clone(),
+                    params,
+                    request,
+                    tip_height,
+                    options,
+                    monitor,
                 )
-                .ok_or(Error::MisbehavingServer)
-            })
-            .try_for_each(|output| {
-                let res = db_data.put_received_transparent_utxo(&output).map(|_| ());
-                async move { res.map_err(Error::Wallet) }
             })
-            .await?;
+            .buffer_unordered(options.max_concurrent_requests.max(1));
+
+        while let Some((request, response)) = responses.next().await {
+            ensure_not_cancelled(monitor)?;
+            match response {
+                Ok(TransactionData::Status(txid, raw_tx)) => {
+                    let status = match raw_tx {
+                        None => TransactionStatus::TxidNotRecognized,
+                        Some(raw_tx) => mined_height(&raw_tx)
+                            .map_or(TransactionStatus::NotInMainChain, TransactionStatus::Mined),
+                    };
+                    db_data
+                        .set_transaction_status(txid, status)
+                        .map_err(Error::Wallet)?;
+                }
+                Ok(TransactionData::Transactions(txid, raw_txs)) => {
+                    if raw_txs.is_empty() {
+                        if let Some(txid) = txid {
+                            db_data
+                                .set_transaction_status(txid, TransactionStatus::TxidNotRecognized)
+                                .map_err(Error::Wallet)?;
+                        }
+                    }
+                    // A transaction that cannot be parsed fails the request, as if it
+                    // could not be fetched; the request's other transactions are stored.
+                    let mut parse_error = None;
+                    for raw_tx in raw_txs {
+                        if let Err(e) = store_raw_transaction(params, db_data, &raw_tx)? {
+                            parse_error.get_or_insert(e);
+                        }
+                    }
+                    if let Some(e) = parse_error {
+                        warn!("Failed to parse a transaction for {:?}: {}", request, e);
+                        monitor.emit(SyncEvent::RequestFailed {
+                            request: request.clone(),
+                            error: format!("Failed to parse transaction: {}", e),
+                        });
+                        failed.push(request);
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to service {:?}: {}", request, e);
+                    monitor.emit(SyncEvent::RequestFailed {
+                        request: request.clone(),
+                        error: e.to_string(),
+                    });
+                    failed.push(request);
+                }
+            }
+        }
+        monitor.emit(SyncEvent::TransactionDataRequestsServiced(
+            request_count - (failed.len() - failed_count),
+        ));
     }
+}
 
-    Ok(())
+/// The data obtained from the chain data source for a [`TransactionDataRequest`].
+enum TransactionData {
+    /// The transaction with the given ID, if the source knows of it.
+    Status(TxId, Option<RawTransaction>),
+    /// Transactions to be stored in the wallet. If they were requested by ID, the ID is
+    /// included.
+    Transactions(Option<TxId>, Vec<RawTransaction>),
+}
+
+async fn fetch_with_retries<P: Parameters, ChT: ChainDataSource>(
+    mut client: ChT,
+    params: &P,
+    request: TransactionDataRequest,
+    tip_height: Option<BlockHeight>,
+    options: &TransactionDataOptions,
+    monitor: &SyncMonitor,
+) -> (TransactionDataRequest, Result<TransactionData, ChT::Error>)
+where
+    ChT::Error: fmt::Display + Send,
+{
+    let mut delay = options.initial_retry_delay;
+    let mut retries = 0;
+    loop {
+        match fetch_transaction_data(&mut client, params, &request, tip_height).await {
+            Err(e) if retries < options.max_retries => {
+                debug!("Retrying {:?} in {:?} after error: {}", request, delay, e);
+                retries += 1;
+                monitor.emit(SyncEvent::RequestRetried {
+                    request: request.clone(),
+                    attempt: retries,
+                    delay,
+                    error: e.to_string(),
+                });
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            response => return (request, response),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "transparent-inputs"), allow(unused_variables))]
+async fn fetch_transaction_data<P: Parameters, ChT: ChainDataSource>(
+    client: &mut ChT,
+    params: &P,
+    request: &TransactionDataRequest,
+    tip_height: Option<BlockHeight>,
+) -> Result<TransactionData, ChT::Error>
+where
+    ChT::Error: Send,
+{
+    match request {
+        TransactionDataRequest::GetStatus(txid) => client
+            .get_transaction(*txid)
+            .await
+            .map(|raw_tx| TransactionData::Status(*txid, raw_tx)),
+        TransactionDataRequest::Enhancement(txid) => client
+            .get_transaction(*txid)
+            .await
+            .map(|raw_tx| TransactionData::Transactions(Some(*txid), raw_tx.into_iter().collect())),
+        #[cfg(feature = "transparent-inputs")]
+        TransactionDataRequest::SpendsFromAddress {
+            address,
+            block_range_start,
+            block_range_end,
+        } => {
+            let end = match (block_range_end, tip_height) {
+                (Some(end), _) => *end,
+                (None, Some(tip_height)) => tip_height + 1,
+                (None, None) => *block_range_start,
+            };
+            if end <= *block_range_start {
+                return Ok(TransactionData::Transactions(None, vec![]));
+            }
+            client
+                .get_taddress_transactions(address.encode(params), *block_range_start..end)
+                .await
+                .map(|raw_txs| TransactionData::Transactions(None, raw_txs))
+        }
+    }
+}
+
+/// Returns the height at which the given transaction was mined, or `None` if it is in the
+/// mempool or is not in the main chain.
+fn mined_height(raw_tx: &RawTransaction) -> Option<BlockHeight> {
+    // `lightwalletd` reports a height of 0 for mempool transactions, and passes through
+    // the full node's height of -1 for transactions that are not in the main chain.
+    match raw_tx.height {
+        0 | u64::MAX => None,
+        height => u32::try_from(height).ok().map(BlockHeight::from_u32),
+    }
+}
+
+/// Parses the given transaction and stores it in the wallet.
+///
+/// Returns `Ok(Err(_))` without modifying the wallet if the transaction cannot be parsed.
+fn store_raw_transaction<P, DbT, CaErr, TrErr, SrcErr>(
+    params: &P,
+    db_data: &mut DbT,
+    raw_tx: &RawTransaction,
+) -> Result<io::Result<()>, Error<CaErr, <DbT as WalletRead>::Error, TrErr, SrcErr>>
+where
+    P: Parameters,
+    DbT: WalletWrite,
+{
+    let mined_height = mined_height(raw_tx);
+    let branch_height = match mined_height {
+        Some(height) => height,
+        None => db_data
+            .chain_height()
+            .map_err(Error::Wallet)?
+            .map(|height| height + 1)
+            .or_else(|| params.activation_height(NetworkUpgrade::Sapling))
+            .expect("Sapling activation height must be known."),
+    };
+    let tx = match Transaction::read(
+        &raw_tx.data[..],
+        BranchId::for_height(params, branch_height),
+    ) {
+        Ok(tx) => tx,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    debug!("Storing transaction {}", tx.txid());
+    decrypt_and_store_transaction(params, db_data, &tx, mined_height).map_err(Error::Wallet)?;
+    Ok(Ok(()))
 }
 
 /// Errors that can occur while syncing.
 #[derive(Debug)]
-pub enum Error<CaErr, DbErr, TrErr> {
+pub enum Error<CaErr, DbErr, TrErr, SrcErr = tonic::Status> {
     /// An error while interacting with a [`BlockCache`].
     Cache(CaErr),
-    /// The lightwalletd server returned invalid information, and is misbehaving.
+    /// The sync was cancelled via its [`SyncMonitor`].
+    Cancelled,
+    /// The chain data source returned invalid information, and is misbehaving.
     MisbehavingServer,
     /// An error while scanning blocks.
     Scan(ScanError),
-    /// An error while communicating with the lightwalletd server.
-    Server(tonic::Status),
+    /// An error while communicating with the chain data source.
+    Server(SrcErr),
     /// An error while interacting with a wallet database via [`WalletRead`] or
     /// [`WalletWrite`].
     Wallet(DbErr),
@@ -578,22 +1695,22 @@ pub enum Error<CaErr, DbErr, TrErr> {
     WalletTrees(ShardTreeError<TrErr>),
 }
 
-impl<CaErr, DbErr, TrErr> fmt::Display for Error<CaErr, DbErr, TrErr>
+impl<CaErr, DbErr, TrErr, SrcErr> fmt::Display for Error<CaErr, DbErr, TrErr, SrcErr>
 where
     CaErr: fmt::Display,
     DbErr: fmt::Display,
     TrErr: fmt::Display,
+    SrcErr: fmt::Display,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Cache(e) => write!(f, "Error while interacting with block cache: {}", e),
-            Error::MisbehavingServer => write!(f, "lightwalletd server is misbehaving"),
+            Error::Cancelled => write!(f, "Sync was cancelled"),
+            Error::MisbehavingServer => write!(f, "Chain data source is misbehaving"),
             Error::Scan(e) => write!(f, "Error while scanning blocks: {}", e),
-            Error::Server(e) => write!(
-                f,
-                "Error while communicating with lightwalletd server: {}",
-                e
-            ),
+            Error::Server(e) => {
+                write!(f, "Error while communicating with chain data source: {}", e)
+            }
             Error::Wallet(e) => write!(f, "Error while interacting with wallet database: {}", e),
             Error::WalletTrees(e) => write!(
                 f,
@@ -604,15 +1721,18 @@ where
     }
 }
 
-impl<CaErr, DbErr, TrErr> std::error::Error for Error<CaErr, DbErr, TrErr>
+impl<CaErr, DbErr, TrErr, SrcErr> std::error::Error for Error<CaErr, DbErr, TrErr, SrcErr>
 where
     CaErr: std::error::Error,
     DbErr: std::error::Error,
     TrErr: std::error::Error,
+    SrcErr: std::error::Error,
 {
 }
 
-impl<CaErr, DbErr, TrErr> From<ChainError<DbErr, CaErr>> for Error<CaErr, DbErr, TrErr> {
+impl<CaErr, DbErr, TrErr, SrcErr> From<ChainError<DbErr, CaErr>>
+    for Error<CaErr, DbErr, TrErr, SrcErr>
+{
     fn from(e: ChainError<DbErr, CaErr>) -> Self {
         match e {
             ChainError::Wallet(e) => Error::Wallet(e),
@@ -621,9 +1741,3 @@ impl<CaErr, DbErr, TrErr> From<ChainError<DbErr, CaErr>> for Error<CaErr, DbErr,
         }
     }
 }
-
-impl<CaErr, DbErr, TrErr> From<tonic::Status> for Error<CaErr, DbErr, TrErr> {
-    fn from(status: tonic::Status) -> Self {
-        Error::Server(status)
-    }
-}
@@ -0,0 +1,176 @@
+//! Observation and cancellation of a running sync.
+
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::Stream;
+use tokio::sync::{mpsc, Notify};
+use zcash_primitives::consensus::BlockHeight;
+
+use crate::{
+    data_api::{chain::ScanSummary, TransactionDataRequest},
+    ShieldedProtocol,
+};
+
+/// An event describing the progress of a sync.
+#[derive(Clone, Debug)]
+pub enum SyncEvent {
+    /// The roots of the complete subtrees of a note commitment tree were provided to the
+    /// wallet.
+    SubtreeRootsUpdated {
+        protocol: ShieldedProtocol,
+        subtree_count: usize,
+    },
+    /// The wallet was notified of the chain tip at the given height.
+    ChainTipUpdated(BlockHeight),
+    /// The unspent transparent outputs received by an account at or above the given
+    /// height were stored in the wallet.
+    UtxosRefreshed {
+        start_height: BlockHeight,
+        utxo_count: usize,
+    },
+    /// A range of blocks was scanned.
+    RangeScanned(ScanSummary),
+    /// A chain reorg was detected, and the wallet was rewound to the given height.
+    Rewound(BlockHeight),
+    /// A request to the chain data source failed, and will be retried after the given
+    /// delay.
+    RequestRetried {
+        request: TransactionDataRequest,
+        attempt: u32,
+        delay: Duration,
+        error: String,
+    },
+    /// A request to the chain data source failed after it was retried the maximum number
+    /// of times. The request remains outstanding in the wallet.
+    RequestFailed {
+        request: TransactionDataRequest,
+        error: String,
+    },
+    /// The given number of transaction data requests were serviced.
+    TransactionDataRequestsServiced(usize),
+}
+
+/// A handle for observing and cancelling a sync.
+///
+/// Cancellation is cooperative: the sync checks for it between steps (for example,
+/// before scanning each batch of blocks), and then returns [`Error::Cancelled`] after
+/// leaving the wallet and block cache in a consistent state.
+///
+/// The default monitor does not emit events, and is never cancelled unless
+/// [`SyncMonitor::cancel`] is called on it or one of its clones.
+///
+/// [`Error::Cancelled`]: super::Error::Cancelled
+#[derive(Clone, Debug, Default)]
+pub struct SyncMonitor {
+    events: Option<mpsc::UnboundedSender<SyncEvent>>,
+    cancellation: Arc<Cancellation>,
+}
+
+/// The cancellation state shared by a [`SyncMonitor`] and its clones.
+#[derive(Debug, Default)]
+struct Cancellation {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl SyncMonitor {
+    /// Constructs a monitor, along with the stream of events that it emits.
+    ///
+    /// The stream ends once the monitor and all of its clones have been dropped. Events
+    /// are buffered until they are read, so the stream does not slow down the sync.
+    pub fn new() -> (Self, SyncEvents) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let monitor = SyncMonitor {
+            events: Some(sender),
+            cancellation: Arc::new(Cancellation::default()),
+        };
+        (monitor, SyncEvents(receiver))
+    }
+
+    /// Requests that the sync stop as soon as possible.
+    pub fn cancel(&self) {
+        self.cancellation.cancelled.store(true, Ordering::Relaxed);
+        self.cancellation.notify.notify_waiters();
+    }
+
+    /// Returns whether [`SyncMonitor::cancel`] has been called on this monitor or any of
+    /// its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Waits until [`SyncMonitor::cancel`] has been called on this monitor or any of its
+    /// clones, for use by steps of the sync that may wait indefinitely.
+    pub(crate) async fn cancelled(&self) {
+        // The future is notified of calls to `cancel` from the time it is created, so a
+        // call between it being created and awaited is not missed.
+        let notified = self.cancellation.notify.notified();
+        if !self.is_cancelled() {
+            notified.await;
+        }
+    }
+
+    pub(crate) fn emit(&self, event: SyncEvent) {
+        if let Some(events) = &self.events {
+            // The caller may have dropped the stream if it is not interested in events.
+            let _ = events.send(event);
+        }
+    }
+}
+
+/// The stream of events emitted by a [`SyncMonitor`].
+#[derive(Debug)]
+pub struct SyncEvents(mpsc::UnboundedReceiver<SyncEvent>);
+
+impl Stream for SyncEvents {
+    type Item = SyncEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{FutureExt, StreamExt};
+    use zcash_primitives::consensus::BlockHeight;
+
+    use super::{SyncEvent, SyncMonitor};
+
+    #[test]
+    fn events_and_cancellation() {
+        let (monitor, mut events) = SyncMonitor::new();
+        let clone = monitor.clone();
+
+        monitor.emit(SyncEvent::ChainTipUpdated(BlockHeight::from_u32(10)));
+        clone.emit(SyncEvent::Rewound(BlockHeight::from_u32(5)));
+        assert!(matches!(
+            events.next().now_or_never(),
+            Some(Some(SyncEvent::ChainTipUpdated(h))) if h == BlockHeight::from_u32(10)
+        ));
+        assert!(matches!(
+            events.next().now_or_never(),
+            Some(Some(SyncEvent::Rewound(_)))
+        ));
+        assert!(events.next().now_or_never().is_none());
+
+        assert!(!monitor.is_cancelled());
+        clone.cancel();
+        assert!(monitor.is_cancelled());
+
+        drop(monitor);
+        drop(clone);
+        assert!(matches!(events.next().now_or_never(), Some(None)));
+
+        // A default monitor discards events.
+        let monitor = SyncMonitor::default();
+        monitor.emit(SyncEvent::TransactionDataRequestsServiced(1));
+        assert!(!monitor.is_cancelled());
+    }
+}
@@ -0,0 +1,103 @@
+//! Sources of chain data for wallet synchronization.
+//!
+//! [`sync::run`] obtains the chain data it needs through the [`ChainDataSource`] trait,
+//! which is implemented for:
+//!
+//! - [`CompactTxStreamerClient`], for syncing from a `lightwalletd` server over gRPC.
+//! - [`jsonrpc::JsonRpcSource`], for syncing directly from a `zcashd` or `zebrad` node over
+//!   its JSON-RPC interface (behind the `sync-jsonrpc` feature flag).
+//! - [`memory::MemoryChainSource`], an in-memory fixture for deterministic tests (behind
+//!   the `test-dependencies` feature flag).
+//!
+//! [`sync::run`]: super::run
+//! [`CompactTxStreamerClient`]: crate::proto::service::compact_tx_streamer_client::CompactTxStreamerClient
+
+use std::ops::Range;
+
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use zcash_primitives::{consensus::BlockHeight, transaction::TxId};
+
+use crate::{
+    proto::{
+        compact_formats::CompactBlock,
+        service::{GetAddressUtxosReply, RawTransaction, SubtreeRoot, TreeState},
+    },
+    ShieldedProtocol,
+};
+
+mod lightwalletd;
+
+#[cfg(feature = "sync-jsonrpc")]
+pub mod jsonrpc;
+
+#[cfg(any(test, feature = "test-dependencies"))]
+pub mod memory;
+
+/// A source of the chain data required to synchronize a wallet.
+///
+/// The data is exchanged using the types of the `lightwalletd` gRPC protocol, which
+/// implementations for other sources convert to.
+#[async_trait]
+pub trait ChainDataSource: Send
+where
+    Self::Error: Send,
+{
+    /// The type of errors produced by this source.
+    type Error;
+
+    /// Returns the height of the latest block in the best chain known to this source.
+    async fn get_latest_block(&mut self) -> Result<BlockHeight, Self::Error>;
+
+    /// Returns the compact blocks in the given range of heights, in order.
+    async fn get_block_range(
+        &mut self,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<CompactBlock>, Self::Error>;
+
+    /// Returns the state of the note commitment trees as of the end of the block at the
+    /// given height.
+    async fn get_tree_state(&mut self, height: BlockHeight) -> Result<TreeState, Self::Error>;
+
+    /// Returns the roots of the complete subtrees of the note commitment tree for the given
+    /// protocol, starting with the subtree at `start_index`.
+    async fn get_subtree_roots(
+        &mut self,
+        protocol: ShieldedProtocol,
+        start_index: u32,
+    ) -> Result<Vec<SubtreeRoot>, Self::Error>;
+
+    /// Returns the transaction with the given ID, or `None` if it is not known to this
+    /// source.
+    ///
+    /// The height of the returned transaction is 0 if it is in the mempool.
+    async fn get_transaction(&mut self, txid: TxId) -> Result<Option<RawTransaction>, Self::Error>;
+
+    /// Returns the unspent transparent outputs received by the given addresses in blocks
+    /// at or above `start_height`, ordered by height.
+    ///
+    /// The addresses are provided in their string encoding.
+    async fn get_address_utxos(
+        &mut self,
+        addresses: Vec<String>,
+        start_height: BlockHeight,
+    ) -> Result<Vec<GetAddressUtxosReply>, Self::Error>;
+
+    /// Returns the transactions mined in the given range of heights that send funds to, or
+    /// spend funds from, the given transparent address, ordered by height.
+    ///
+    /// The address is provided in its string encoding.
+    async fn get_taddress_transactions(
+        &mut self,
+        address: String,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<RawTransaction>, Self::Error>;
+
+    /// Returns a stream of the transactions in the mempool.
+    ///
+    /// The stream yields the transactions currently in the mempool, followed by any that
+    /// enter it, and ends when a new block is mined.
+    async fn get_mempool_stream(
+        &mut self,
+    ) -> Result<BoxStream<'_, Result<RawTransaction, Self::Error>>, Self::Error>;
+}
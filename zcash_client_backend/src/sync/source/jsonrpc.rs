@@ -0,0 +1,1147 @@
+//! [`ChainDataSource`] implementation for `zcashd` and `zebrad` full nodes.
+//!
+//! This allows a wallet to be synced directly from a full node that the wallet's owner
+//! controls, without going through a `lightwalletd` server. Full blocks are downloaded
+//! over the node's JSON-RPC interface and converted into compact blocks locally.
+//!
+//! The node must have the RPC methods used by `lightwalletd` available; in particular,
+//! `zcashd` must be run with `-lightwalletd` (which enables `-insightexplorer`), so that
+//! `getaddressutxos` and `getaddresstxids` are supported.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::{Buf, Bytes},
+    client::conn::{self, http1::SendRequest},
+    http::uri::Scheme,
+    Request, Uri,
+};
+use hyper_util::rt::TokioIo;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tracing::{debug, error};
+use zcash_primitives::{
+    block::Block,
+    consensus::{BlockHeight, BranchId, NetworkType, Parameters},
+    transaction::{Transaction, TxId},
+};
+
+use super::ChainDataSource;
+use crate::{
+    proto::{
+        compact_formats::{
+            ChainMetadata, CompactBlock, CompactSaplingOutput, CompactSaplingSpend, CompactTx,
+        },
+        service::{GetAddressUtxosReply, RawTransaction, SubtreeRoot, TreeState},
+    },
+    ShieldedProtocol,
+};
+
+#[cfg(feature = "orchard")]
+use crate::proto::compact_formats::CompactOrchardAction;
+
+/// The JSON-RPC error code returned by `zcashd` and `zebrad` for unknown transactions.
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+
+/// The maximum number of blocks that are requested in a single batch of RPC calls.
+const MAX_BATCH_BLOCKS: usize = 100;
+
+/// Errors that can occur while communicating with a full node.
+#[derive(Debug)]
+pub enum Error {
+    /// The URL of the node is not a plain-HTTP URL.
+    UnsupportedUrl,
+    /// An I/O error occurred while connecting to the node.
+    Io(io::Error),
+    /// An HTTP error occurred while communicating with the node.
+    Http(hyper::Error),
+    /// The response from the node could not be parsed.
+    Json(serde_json::Error),
+    /// The node returned an error for an RPC call.
+    Rpc {
+        /// The JSON-RPC error code.
+        code: i64,
+        /// The error message.
+        message: String,
+    },
+    /// The node returned data that could not be parsed.
+    InvalidData(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnsupportedUrl => write!(f, "Full node URL must be an http:// URL"),
+            Error::Io(e) => write!(f, "Error while connecting to full node: {}", e),
+            Error::Http(e) => write!(f, "HTTP error while communicating with full node: {}", e),
+            Error::Json(e) => write!(f, "Invalid JSON-RPC response from full node: {}", e),
+            Error::Rpc { code, message } => {
+                write!(f, "Full node returned RPC error {}: {}", code, message)
+            }
+            Error::InvalidData(e) => write!(f, "Full node returned invalid data: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Http(e) => Some(e),
+            Error::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+/// A [`ChainDataSource`] backed by the JSON-RPC interface of a `zcashd` or `zebrad` node.
+///
+/// The source keeps its connection to the node open between calls. Each clone of a
+/// source opens its own connection, so that clones can make calls concurrently.
+pub struct JsonRpcSource<P> {
+    params: P,
+    url: Uri,
+    authorization: Option<String>,
+    mempool_poll_interval: Duration,
+    next_id: u64,
+    connection: Option<SendRequest<Full<Bytes>>>,
+}
+
+impl<P: Clone> Clone for JsonRpcSource<P> {
+    fn clone(&self) -> Self {
+        JsonRpcSource {
+            params: self.params.clone(),
+            url: self.url.clone(),
+            authorization: self.authorization.clone(),
+            mempool_poll_interval: self.mempool_poll_interval,
+            next_id: self.next_id,
+            connection: None,
+        }
+    }
+}
+
+impl<P: Parameters + Send> JsonRpcSource<P> {
+    /// Constructs a source for the node with the given RPC URL, such as
+    /// `http://127.0.0.1:8232`.
+    ///
+    /// Only plain HTTP is supported, so the node should be on the local machine or
+    /// reached over a trusted network.
+    pub fn new(params: P, url: Uri) -> Self {
+        JsonRpcSource {
+            params,
+            url,
+            authorization: None,
+            mempool_poll_interval: Duration::from_secs(5),
+            next_id: 0,
+            connection: None,
+        }
+    }
+
+    /// Sets the username and password used to authenticate to the node.
+    pub fn with_basic_auth(mut self, username: &str, password: &str) -> Self {
+        self.authorization = Some(format!(
+            "Basic {}",
+            STANDARD.encode(format!("{}:{}", username, password))
+        ));
+        self
+    }
+
+    /// Sets how often the node's mempool is polled for new transactions while a stream
+    /// returned by [`ChainDataSource::get_mempool_stream`] is open.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn with_mempool_poll_interval(mut self, interval: Duration) -> Self {
+        self.mempool_poll_interval = interval;
+        self
+    }
+
+    async fn call<T: DeserializeOwned>(&mut self, method: &str, params: Value) -> Result<T, Error> {
+        debug!("Calling {} on full node", method);
+        let request = self.request(method, params);
+        parse_response(self.send(&request).await?)
+    }
+
+    /// Makes the given calls in a single JSON-RPC batch request, returning their results
+    /// in the same order as the calls.
+    ///
+    /// Returns an error if any of the calls fails.
+    async fn call_batch<'a>(
+        &mut self,
+        calls: impl IntoIterator<Item = (&'a str, Value)>,
+    ) -> Result<Vec<Value>, Error> {
+        let first_id = self.next_id;
+        let requests: Vec<_> = calls
+            .into_iter()
+            .map(|(method, params)| self.request(method, params))
+            .collect();
+        if requests.is_empty() {
+            return Ok(vec![]);
+        }
+        debug!("Sending a batch of {} calls to full node", requests.len());
+
+        let responses = match self.send(&Value::Array(requests)).await? {
+            Value::Array(responses) => responses,
+            // A node that fails to process a batch as a whole returns a single error.
+            response => {
+                parse_response::<Value>(response)?;
+                return Err(Error::InvalidData(
+                    "Expected an array of responses to a batch request".to_string(),
+                ));
+            }
+        };
+
+        // Responses to a batch request may be returned in any order.
+        let mut results = vec![None; (self.next_id - first_id) as usize];
+        for response in responses {
+            let index = response
+                .get("id")
+                .and_then(Value::as_u64)
+                .and_then(|id| id.checked_sub(first_id))
+                .and_then(|index| usize::try_from(index).ok())
+                .filter(|index| *index < results.len())
+                .ok_or_else(|| Error::InvalidData("Unexpected JSON-RPC response ID".to_string()))?;
+            results[index] = Some(parse_response(response)?);
+        }
+        results
+            .into_iter()
+            .map(|result| {
+                result.ok_or_else(|| Error::InvalidData("Missing JSON-RPC response".to_string()))
+            })
+            .collect()
+    }
+
+    /// Constructs a JSON-RPC request with a fresh ID.
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        json!({
+            "jsonrpc": "1.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        })
+    }
+
+    /// Sends a JSON-RPC request to the node, and returns the response.
+    ///
+    /// The connection to the node is reused if it is still open. If sending a request
+    /// over a reused connection fails, the request is retried once over a new
+    /// connection, as the node may have closed an idle connection.
+    async fn send(&mut self, body: &Value) -> Result<Value, Error> {
+        let reused = self.connection.is_some();
+        match self.send_once(body).await {
+            Err(Error::Http(e)) if reused => {
+                debug!("Reconnecting to full node after error: {}", e);
+                self.send_once(body).await
+            }
+            result => result,
+        }
+    }
+
+    async fn send_once(&mut self, body: &Value) -> Result<Value, Error> {
+        let mut sender = match self.connection.take() {
+            Some(mut sender) => match sender.ready().await {
+                Ok(()) => sender,
+                Err(_) => connect(&self.url).await?,
+            },
+            None => connect(&self.url).await?,
+        };
+
+        let mut request = Request::post(self.url.clone())
+            .header(
+                hyper::header::HOST,
+                self.url
+                    .authority()
+                    .expect("Checked on connection")
+                    .as_str(),
+            )
+            .header(hyper::header::CONTENT_TYPE, "application/json");
+        if let Some(authorization) = &self.authorization {
+            request = request.header(hyper::header::AUTHORIZATION, authorization);
+        }
+        let request = request
+            .body(Full::new(Bytes::from(body.to_string())))
+            .map_err(|e| Error::InvalidData(e.to_string()))?;
+
+        // Full nodes return RPC errors with a non-success HTTP status code, so we parse
+        // the body regardless of the status.
+        let response = sender.send_request(request).await?;
+        let body = response.into_body().collect().await?.aggregate();
+        self.connection = Some(sender);
+        Ok(serde_json::from_reader(body.reader())?)
+    }
+
+    /// Returns the final state of a pool's note commitment tree as of the block that a
+    /// `z_gettreestate` response is for.
+    ///
+    /// If the node omitted the state because the tree has not changed since an earlier
+    /// block, the state is requested for that block instead, as `lightwalletd` does.
+    async fn final_state(
+        &mut self,
+        mut state: PoolTreeState,
+        pool: fn(TreeStateInfo) -> PoolTreeState,
+    ) -> Result<String, Error> {
+        loop {
+            match state.final_state() {
+                FinalState::Known(final_state) => return Ok(final_state),
+                FinalState::SameAsBlock(hash) => {
+                    let info: TreeStateInfo = self.call("z_gettreestate", json!([hash])).await?;
+                    state = pool(info);
+                }
+            }
+        }
+    }
+
+    async fn get_best_block_hash(&mut self) -> Result<String, Error> {
+        self.call("getbestblockhash", json!([])).await
+    }
+}
+
+#[async_trait]
+impl<P: Parameters + Send> ChainDataSource for JsonRpcSource<P> {
+    type Error = Error;
+
+    async fn get_latest_block(&mut self) -> Result<BlockHeight, Self::Error> {
+        self.call("getblockcount", json!([]))
+            .await
+            .map(BlockHeight::from_u32)
+    }
+
+    async fn get_block_range(
+        &mut self,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<CompactBlock>, Self::Error> {
+        let heights: Vec<_> = (u32::from(range.start)..u32::from(range.end))
+            .map(BlockHeight::from_u32)
+            .collect();
+
+        // Each block is requested twice in the same batch: once for its encoding, and
+        // once for the sizes of the note commitment trees, which the encoding lacks.
+        let mut blocks = Vec::with_capacity(heights.len());
+        for heights in heights.chunks(MAX_BATCH_BLOCKS) {
+            let mut results = self
+                .call_batch(heights.iter().flat_map(|height| {
+                    [
+                        ("getblock", json!([height.to_string(), 0])),
+                        ("getblock", json!([height.to_string(), 1])),
+                    ]
+                }))
+                .await?
+                .into_iter();
+            for &height in heights {
+                let (raw, info) = results
+                    .next()
+                    .zip(results.next())
+                    .expect("Checked by call_batch");
+                let block = Block::read(
+                    &decode_hex(&serde_json::from_value::<String>(raw)?)?[..],
+                    BranchId::for_height(&self.params, height),
+                )
+                .map_err(|e| Error::InvalidData(e.to_string()))?;
+                blocks.push(compact_block(
+                    &block,
+                    height,
+                    serde_json::from_value(info)?,
+                )?);
+            }
+        }
+        Ok(blocks)
+    }
+
+    async fn get_tree_state(&mut self, height: BlockHeight) -> Result<TreeState, Self::Error> {
+        let network = match self.params.network_type() {
+            NetworkType::Main => "main",
+            NetworkType::Test => "test",
+            NetworkType::Regtest => "regtest",
+        };
+        let info: TreeStateInfo = self
+            .call("z_gettreestate", json!([height.to_string()]))
+            .await?;
+        let sapling_tree = self.final_state(info.sapling, |info| info.sapling).await?;
+        let orchard_tree = self.final_state(info.orchard, |info| info.orchard).await?;
+        Ok(TreeState {
+            network: network.to_string(),
+            height: info.height,
+            hash: info.hash,
+            time: info.time,
+            sapling_tree,
+            orchard_tree,
+        })
+    }
+
+    async fn get_subtree_roots(
+        &mut self,
+        protocol: ShieldedProtocol,
+        start_index: u32,
+    ) -> Result<Vec<SubtreeRoot>, Self::Error> {
+        let pool = match protocol {
+            ShieldedProtocol::Sapling => "sapling",
+            ShieldedProtocol::Orchard => "orchard",
+        };
+        let subtrees: SubtreesInfo = self
+            .call("z_getsubtreesbyindex", json!([pool, start_index]))
+            .await?;
+        subtrees.into_subtree_roots()
+    }
+
+    async fn get_transaction(&mut self, txid: TxId) -> Result<Option<RawTransaction>, Self::Error> {
+        match self
+            .call::<TransactionInfo>("getrawtransaction", json!([txid.to_string(), 1]))
+            .await
+        {
+            Ok(tx) => tx.into_raw_transaction().map(Some),
+            Err(Error::Rpc { code, .. }) if code == RPC_INVALID_ADDRESS_OR_KEY => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_address_utxos(
+        &mut self,
+        addresses: Vec<String>,
+        start_height: BlockHeight,
+    ) -> Result<Vec<GetAddressUtxosReply>, Self::Error> {
+        let utxos: Vec<UtxoInfo> = self
+            .call("getaddressutxos", json!([{ "addresses": addresses }]))
+            .await?;
+        utxos_since(utxos, start_height)
+    }
+
+    async fn get_taddress_transactions(
+        &mut self,
+        address: String,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<RawTransaction>, Self::Error> {
+        // The range of `getaddresstxids` is inclusive.
+        let txids: Vec<String> = self
+            .call(
+                "getaddresstxids",
+                json!([{
+                    "addresses": [address],
+                    "start": u32::from(range.start),
+                    "end": u32::from(range.end - 1),
+                }]),
+            )
+            .await?;
+
+        self.call_batch(
+            txids
+                .into_iter()
+                .map(|txid| ("getrawtransaction", json!([txid, 1]))),
+        )
+        .await?
+        .into_iter()
+        .map(|tx| serde_json::from_value::<TransactionInfo>(tx)?.into_raw_transaction())
+        .collect()
+    }
+
+    async fn get_mempool_stream(
+        &mut self,
+    ) -> Result<BoxStream<'_, Result<RawTransaction, Self::Error>>, Self::Error> {
+        let tip = self.get_best_block_hash().await?;
+
+        struct State<'a, P> {
+            source: &'a mut JsonRpcSource<P>,
+            tip: String,
+            seen: HashSet<String>,
+            pending: VecDeque<String>,
+        }
+
+        let state = State {
+            source: self,
+            tip,
+            seen: HashSet::new(),
+            pending: VecDeque::new(),
+        };
+
+        // Poll the mempool until a new block is mined, yielding each transaction the first
+        // time it is seen. The stream ends after the first error.
+        Ok(stream::unfold(Some(state), |state| async move {
+            let mut state = state?;
+            loop {
+                let step: Result<Option<RawTransaction>, Error> = async {
+                    if let Some(txid) = state.pending.pop_front() {
+                        // The transaction may have been mined or evicted since we learned
+                        // of it.
+                        return match state
+                            .source
+                            .call::<TransactionInfo>("getrawtransaction", json!([txid, 1]))
+                            .await
+                        {
+                            Ok(tx) => tx.into_raw_transaction().map(Some),
+                            Err(Error::Rpc { code, .. }) if code == RPC_INVALID_ADDRESS_OR_KEY => {
+                                Ok(None)
+                            }
+                            Err(e) => Err(e),
+                        };
+                    }
+
+                    let txids: Vec<String> = state.source.call("getrawmempool", json!([])).await?;
+                    let mut new_txids: Vec<_> = txids
+                        .into_iter()
+                        .filter(|txid| state.seen.insert(txid.clone()))
+                        .collect();
+                    if new_txids.is_empty() {
+                        tokio::time::sleep(state.source.mempool_poll_interval).await;
+                    }
+                    state.pending.extend(new_txids.drain(..));
+                    Ok(None)
+                }
+                .await;
+
+                match step {
+                    Ok(Some(tx)) => return Some((Ok(tx), Some(state))),
+                    Ok(None) if state.pending.is_empty() => {
+                        // Before polling the mempool again, end the stream if a new block
+                        // has been mined.
+                        match state.source.get_best_block_hash().await {
+                            Ok(tip) if tip == state.tip => (),
+                            Ok(_) => return None,
+                            Err(e) => return Some((Err(e), None)),
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(e) => return Some((Err(e), None)),
+                }
+            }
+        })
+        .boxed())
+    }
+}
+
+/// Opens an HTTP connection to the node with the given URL.
+async fn connect(url: &Uri) -> Result<SendRequest<Full<Bytes>>, Error> {
+    if url.scheme() != Some(&Scheme::HTTP) || url.authority().is_none() {
+        return Err(Error::UnsupportedUrl);
+    }
+    let host = url.host().ok_or(Error::UnsupportedUrl)?;
+    let port = url.port_u16().unwrap_or(80);
+
+    debug!("Connecting to full node at {}", url);
+    let stream = TcpStream::connect((host, port)).await?;
+    let (sender, connection) = conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("Connection failed: {}", e);
+        }
+    });
+    Ok(sender)
+}
+
+/// Extracts the result of a JSON-RPC call from the response.
+fn parse_response<T: DeserializeOwned>(response: Value) -> Result<T, Error> {
+    #[derive(Deserialize)]
+    struct RpcError {
+        code: i64,
+        message: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Response {
+        result: Option<Value>,
+        error: Option<RpcError>,
+    }
+
+    let response: Response = serde_json::from_value(response)?;
+    match response.error {
+        Some(RpcError { code, message }) => Err(Error::Rpc { code, message }),
+        None => Ok(serde_json::from_value(
+            response.result.unwrap_or(Value::Null),
+        )?),
+    }
+}
+
+fn decode_hex(data: &str) -> Result<Vec<u8>, Error> {
+    hex::decode(data).map_err(|e| Error::InvalidData(e.to_string()))
+}
+
+/// Decodes a byte-reversed hex encoding, as used by full nodes for hashes.
+fn decode_reversed_hex(data: &str) -> Result<Vec<u8>, Error> {
+    let mut bytes = decode_hex(data)?;
+    bytes.reverse();
+    Ok(bytes)
+}
+
+#[derive(Deserialize)]
+struct TreeSize {
+    size: u32,
+}
+
+/// The sizes of the note commitment trees as of the end of a block.
+///
+/// A pool's tree is omitted if the pool is not active at the block's height.
+#[derive(Deserialize)]
+struct BlockTrees {
+    sapling: Option<TreeSize>,
+    orchard: Option<TreeSize>,
+}
+
+/// The subset of the verbose `getblock` response that is not contained in the block.
+#[derive(Deserialize)]
+struct BlockInfo {
+    hash: String,
+    trees: BlockTrees,
+}
+
+#[derive(Deserialize)]
+struct Commitments {
+    #[serde(rename = "finalState")]
+    final_state: Option<String>,
+}
+
+/// The state of a pool's note commitment tree in the `z_gettreestate` response.
+#[derive(Default, Deserialize)]
+struct PoolTreeState {
+    #[serde(rename = "skipHash")]
+    skip_hash: Option<String>,
+    commitments: Option<Commitments>,
+}
+
+enum FinalState {
+    /// The final state of the tree, which is empty if the pool is not active.
+    Known(String),
+    /// The tree has not changed since the block with the given hash.
+    SameAsBlock(String),
+}
+
+impl PoolTreeState {
+    fn final_state(self) -> FinalState {
+        match (
+            self.commitments
+                .and_then(|commitments| commitments.final_state),
+            self.skip_hash,
+        ) {
+            (Some(final_state), _) => FinalState::Known(final_state),
+            (None, Some(hash)) => FinalState::SameAsBlock(hash),
+            (None, None) => FinalState::Known(String::new()),
+        }
+    }
+}
+
+/// The `z_gettreestate` response.
+#[derive(Deserialize)]
+struct TreeStateInfo {
+    hash: String,
+    height: u64,
+    time: u32,
+    #[serde(default)]
+    sapling: PoolTreeState,
+    #[serde(default)]
+    orchard: PoolTreeState,
+}
+
+#[derive(Deserialize)]
+struct Subtree {
+    root: String,
+    end_height: u64,
+}
+
+/// The `z_getsubtreesbyindex` response.
+#[derive(Deserialize)]
+struct SubtreesInfo {
+    subtrees: Vec<Subtree>,
+}
+
+impl SubtreesInfo {
+    fn into_subtree_roots(self) -> Result<Vec<SubtreeRoot>, Error> {
+        self.subtrees
+            .into_iter()
+            .map(|subtree| {
+                Ok(SubtreeRoot {
+                    root_hash: decode_hex(&subtree.root)?,
+                    completing_block_hash: vec![],
+                    completing_block_height: subtree.end_height,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The verbose `getrawtransaction` response.
+#[derive(Deserialize)]
+struct TransactionInfo {
+    hex: String,
+    height: Option<i64>,
+}
+
+impl TransactionInfo {
+    fn into_raw_transaction(self) -> Result<RawTransaction, Error> {
+        Ok(RawTransaction {
+            data: decode_hex(&self.hex)?,
+            // Mempool transactions have no height, and transactions that are not in the
+            // main chain have a height of -1.
+            height: self
+                .height
+                .and_then(|height| u64::try_from(height).ok())
+                .unwrap_or(0),
+        })
+    }
+}
+
+/// An element of the `getaddressutxos` response.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UtxoInfo {
+    address: String,
+    txid: String,
+    output_index: i32,
+    script: String,
+    satoshis: i64,
+    height: u64,
+}
+
+fn utxos_since(
+    utxos: Vec<UtxoInfo>,
+    start_height: BlockHeight,
+) -> Result<Vec<GetAddressUtxosReply>, Error> {
+    let mut utxos = utxos
+        .into_iter()
+        .filter(|utxo| utxo.height >= u64::from(start_height))
+        .map(|utxo| {
+            Ok(GetAddressUtxosReply {
+                address: utxo.address,
+                txid: decode_reversed_hex(&utxo.txid)?,
+                index: utxo.output_index,
+                script: decode_hex(&utxo.script)?,
+                value_zat: utxo.satoshis,
+                height: utxo.height,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    utxos.sort_by_key(|utxo| utxo.height);
+    Ok(utxos)
+}
+
+/// Converts a full block into a compact block, in the same way as `lightwalletd`.
+///
+/// Returns an error if `info` is for a different block, which can happen if the chain
+/// was reorganized while the block was being requested.
+fn compact_block(
+    block: &Block,
+    height: BlockHeight,
+    info: BlockInfo,
+) -> Result<CompactBlock, Error> {
+    if block.hash().to_string() != info.hash {
+        return Err(Error::InvalidData(format!(
+            "Block at height {} changed while it was being requested",
+            height
+        )));
+    }
+    let chain_metadata = ChainMetadata {
+        sapling_commitment_tree_size: info.trees.sapling.map_or(0, |tree| tree.size),
+        orchard_commitment_tree_size: info.trees.orchard.map_or(0, |tree| tree.size),
+    };
+
+    Ok(CompactBlock {
+        proto_version: 1,
+        height: height.into(),
+        hash: block.hash().0.to_vec(),
+        prev_hash: block.header().prev_block.0.to_vec(),
+        time: block.header().time,
+        header: vec![],
+        vtx: block
+            .transactions()
+            .iter()
+            .enumerate()
+            .filter_map(|(index, tx)| compact_tx(index, tx))
+            .collect(),
+        chain_metadata: Some(chain_metadata),
+    })
+}
+
+/// Converts a transaction into a compact transaction, or returns `None` if the
+/// transaction has no shielded components.
+fn compact_tx(index: usize, tx: &Transaction) -> Option<CompactTx> {
+    let (spends, outputs) = tx.sapling_bundle().map_or((vec![], vec![]), |bundle| {
+        (
+            bundle
+                .shielded_spends()
+                .iter()
+                .map(CompactSaplingSpend::from)
+                .collect(),
+            bundle
+                .shielded_outputs()
+                .iter()
+                .map(CompactSaplingOutput::from)
+                .collect(),
+        )
+    });
+    #[cfg(feature = "orchard")]
+    let actions = tx.orchard_bundle().map_or(vec![], |bundle| {
+        bundle
+            .actions()
+            .iter()
+            .map(CompactOrchardAction::from)
+            .collect()
+    });
+    #[cfg(not(feature = "orchard"))]
+    let actions = vec![];
+
+    if spends.is_empty() && outputs.is_empty() && actions.is_empty() {
+        None
+    } else {
+        Some(CompactTx {
+            index: index as u64,
+            hash: tx.txid().as_ref().to_vec(),
+            fee: 0,
+            spends,
+            outputs,
+            actions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use http_body_util::{BodyExt, Full};
+    use hyper::{
+        body::{Bytes, Incoming},
+        server::conn::http1,
+        service::service_fn,
+        Request, Response, Uri,
+    };
+    use hyper_util::rt::TokioIo;
+    use serde_json::{json, Value};
+    use tokio::{net::TcpListener, runtime::Runtime};
+    use zcash_primitives::{
+        block::{Block, BlockHash, BlockHeaderData},
+        consensus::{BlockHeight, Network},
+    };
+
+    use super::{
+        parse_response, utxos_since, Error, FinalState, JsonRpcSource, SubtreesInfo,
+        TransactionInfo, TreeStateInfo,
+    };
+    use crate::sync::ChainDataSource;
+
+    /// A mock full node, which answers each JSON-RPC call, including the calls in batch
+    /// requests, with the result of `respond`.
+    struct MockNode {
+        url: Uri,
+        connections: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl MockNode {
+        fn start(rt: &Runtime, respond: fn(&str, &Value) -> Value) -> Self {
+            let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap())
+                .parse()
+                .unwrap();
+            let connections = Arc::new(AtomicUsize::new(0));
+            let requests = Arc::new(AtomicUsize::new(0));
+
+            let answer = move |call: &Value| {
+                json!({
+                    "result": respond(call["method"].as_str().unwrap(), &call["params"]),
+                    "error": null,
+                    "id": call["id"],
+                })
+            };
+            let (node_connections, node_requests) = (connections.clone(), requests.clone());
+            rt.spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    node_connections.fetch_add(1, Ordering::SeqCst);
+                    let requests = node_requests.clone();
+                    let service = service_fn(move |request: Request<Incoming>| {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        async move {
+                            let body = request.into_body().collect().await?.to_bytes();
+                            let response = match serde_json::from_slice(&body).unwrap() {
+                                Value::Array(calls) => calls.iter().map(answer).collect(),
+                                call => answer(&call),
+                            };
+                            Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(
+                                response.to_string(),
+                            ))))
+                        }
+                    });
+                    tokio::spawn(
+                        http1::Builder::new().serve_connection(TokioIo::new(stream), service),
+                    );
+                }
+            });
+
+            MockNode {
+                url,
+                connections,
+                requests,
+            }
+        }
+
+        fn source(&self) -> JsonRpcSource<Network> {
+            JsonRpcSource::new(Network::TestNetwork, self.url.clone())
+        }
+    }
+
+    /// Returns a block with no transactions, which is at the given height in the chains
+    /// served by the mock nodes in these tests.
+    fn test_block(height: u32) -> Block {
+        let header = BlockHeaderData {
+            version: 4,
+            prev_block: BlockHash([height as u8 - 1; 32]),
+            merkle_root: [0; 32],
+            final_sapling_root: [0; 32],
+            time: height,
+            bits: 0,
+            nonce: [0; 32],
+            solution: vec![],
+        }
+        .freeze()
+        .unwrap();
+        Block::from_parts(header, vec![])
+    }
+
+    /// Answers `getblock` calls for the blocks returned by [`test_block`].
+    fn getblock(params: &Value, trees: Value) -> Value {
+        let height = params[0].as_str().unwrap().parse().unwrap();
+        let block = test_block(height);
+        if params[1] == 0 {
+            let mut raw = vec![];
+            block.write(&mut raw).unwrap();
+            Value::String(hex::encode(raw))
+        } else {
+            json!({
+                "hash": block.hash().to_string(),
+                "height": height,
+                "trees": trees,
+            })
+        }
+    }
+
+    #[test]
+    fn rpc_errors() {
+        let result: Result<u32, _> = parse_response(json!({
+            "result": 2_000_000,
+            "error": null,
+            "id": 0,
+        }));
+        assert_eq!(result.unwrap(), 2_000_000);
+
+        let result: Result<u32, _> = parse_response(json!({
+            "result": null,
+            "error": { "code": -5, "message": "No such mempool or blockchain transaction" },
+            "id": 0,
+        }));
+        assert!(matches!(result, Err(Error::Rpc { code: -5, .. })));
+    }
+
+    #[test]
+    fn tree_state() {
+        let info: TreeStateInfo = serde_json::from_value(json!({
+            "hash": "0000000001b1f7ac0df6dc7fa2c6ce1f2d5d1b21e00c4bdb1d48fe0ad37b4c79",
+            "height": 1_000_000,
+            "time": 1_600_000_000,
+            "sapling": {
+                "commitments": { "finalRoot": "00", "finalState": "01ab" },
+            },
+            "sprout": { "commitments": { "finalState": "ff" } },
+        }))
+        .unwrap();
+        assert_eq!(info.height, 1_000_000);
+        assert!(matches!(info.sapling.final_state(), FinalState::Known(state) if state == "01ab"));
+        assert!(matches!(info.orchard.final_state(), FinalState::Known(state) if state.is_empty()));
+
+        let info: TreeStateInfo = serde_json::from_value(json!({
+            "hash": "0000000001b1f7ac0df6dc7fa2c6ce1f2d5d1b21e00c4bdb1d48fe0ad37b4c79",
+            "height": 1_000_000,
+            "time": 1_600_000_000,
+            "sapling": { "skipHash": "ab01" },
+        }))
+        .unwrap();
+        assert!(
+            matches!(info.sapling.final_state(), FinalState::SameAsBlock(hash) if hash == "ab01")
+        );
+    }
+
+    #[test]
+    fn tree_state_follows_skip_hash() {
+        let rt = Runtime::new().unwrap();
+        let node = MockNode::start(&rt, |method, params| {
+            assert_eq!(method, "z_gettreestate");
+            match params[0].as_str().unwrap() {
+                "1000" => json!({
+                    "hash": "03",
+                    "height": 1000,
+                    "time": 1_600_000_000,
+                    "sapling": { "skipHash": "02" },
+                    "orchard": { "commitments": { "finalState": "0001" } },
+                }),
+                "02" => json!({
+                    "hash": "02",
+                    "height": 990,
+                    "time": 1_500_000_000,
+                    "sapling": { "skipHash": "01" },
+                }),
+                "01" => json!({
+                    "hash": "01",
+                    "height": 900,
+                    "time": 1_400_000_000,
+                    "sapling": { "commitments": { "finalState": "01ab" } },
+                }),
+                _ => panic!("Unexpected block"),
+            }
+        });
+
+        let state = rt
+            .block_on(node.source().get_tree_state(BlockHeight::from_u32(1000)))
+            .unwrap();
+        assert_eq!(state.network, "test");
+        assert_eq!(state.height, 1000);
+        assert_eq!(state.hash, "03");
+        assert_eq!(state.time, 1_600_000_000);
+        assert_eq!(state.sapling_tree, "01ab");
+        assert_eq!(state.orchard_tree, "0001");
+    }
+
+    #[test]
+    fn block_range_is_batched_over_one_connection() {
+        let rt = Runtime::new().unwrap();
+        let node = MockNode::start(&rt, |method, params| match method {
+            "getblock" => getblock(params, json!({ "sapling": { "size": 42 } })),
+            "getblockcount" => json!(12),
+            _ => panic!("Unexpected method"),
+        });
+
+        let mut source = node.source();
+        let blocks = rt
+            .block_on(source.get_block_range(BlockHeight::from_u32(10)..BlockHeight::from_u32(13)))
+            .unwrap();
+        assert_eq!(
+            blocks.iter().map(|block| block.height).collect::<Vec<_>>(),
+            vec![10, 11, 12]
+        );
+        for block in &blocks {
+            assert_eq!(
+                block.hash,
+                test_block(block.height as u32).hash().0.to_vec()
+            );
+            let chain_metadata = block.chain_metadata.as_ref().unwrap();
+            assert_eq!(chain_metadata.sapling_commitment_tree_size, 42);
+            assert_eq!(chain_metadata.orchard_commitment_tree_size, 0);
+        }
+
+        assert_eq!(
+            rt.block_on(source.get_latest_block()).unwrap(),
+            BlockHeight::from_u32(12)
+        );
+        assert_eq!(node.connections.load(Ordering::SeqCst), 1);
+        assert_eq!(node.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn block_without_trees_is_an_error() {
+        let rt = Runtime::new().unwrap();
+        let node = MockNode::start(&rt, |_, params| getblock(params, Value::Null));
+
+        let result = rt.block_on(
+            node.source()
+                .get_block_range(BlockHeight::from_u32(10)..BlockHeight::from_u32(11)),
+        );
+        assert!(matches!(result, Err(Error::Json(_))));
+    }
+
+    #[test]
+    fn subtree_roots() {
+        let info: SubtreesInfo = serde_json::from_value(json!({
+            "pool": "sapling",
+            "start_index": 1,
+            "subtrees": [
+                { "root": "0102", "end_height": 1_000 },
+                { "root": "0304", "end_height": 2_000 },
+            ],
+        }))
+        .unwrap();
+        let roots = info.into_subtree_roots().unwrap();
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].root_hash, vec![1, 2]);
+        assert_eq!(roots[1].completing_block_height, 2_000);
+    }
+
+    #[test]
+    fn transactions() {
+        let mined: TransactionInfo =
+            serde_json::from_value(json!({ "hex": "0102", "height": 123 })).unwrap();
+        let mined = mined.into_raw_transaction().unwrap();
+        assert_eq!(mined.data, vec![1, 2]);
+        assert_eq!(mined.height, 123);
+
+        for info in [json!({ "hex": "03" }), json!({ "hex": "03", "height": -1 })] {
+            let tx: TransactionInfo = serde_json::from_value(info).unwrap();
+            assert_eq!(tx.into_raw_transaction().unwrap().height, 0);
+        }
+    }
+
+    #[test]
+    fn utxos() {
+        let utxos = serde_json::from_value(json!([
+            {
+                "address": "t1a",
+                "txid": "0100000000000000000000000000000000000000000000000000000000000002",
+                "outputIndex": 1,
+                "script": "76a9",
+                "satoshis": 5000,
+                "height": 30,
+            },
+            {
+                "address": "t1b",
+                "txid": "0300000000000000000000000000000000000000000000000000000000000004",
+                "outputIndex": 0,
+                "script": "76a9",
+                "satoshis": 7000,
+                "height": 20,
+            },
+            {
+                "address": "t1a",
+                "txid": "0500000000000000000000000000000000000000000000000000000000000006",
+                "outputIndex": 0,
+                "script": "76a9",
+                "satoshis": 1000,
+                "height": 10,
+            },
+        ]))
+        .unwrap();
+        let utxos = utxos_since(utxos, BlockHeight::from_u32(15)).unwrap();
+        assert_eq!(
+            utxos.iter().map(|u| u.height).collect::<Vec<_>>(),
+            vec![20, 30]
+        );
+        // Transaction IDs are converted from the byte-reversed display order.
+        assert_eq!(utxos[0].txid[0], 4);
+        assert_eq!(utxos[0].txid[31], 3);
+        assert_eq!(utxos[1].index, 1);
+        assert_eq!(utxos[1].script, vec![0x76, 0xa9]);
+    }
+}
@@ -0,0 +1,164 @@
+//! [`ChainDataSource`] implementation for `lightwalletd` servers.
+
+use std::ops::Range;
+
+use async_trait::async_trait;
+use futures_util::{
+    stream::{BoxStream, StreamExt},
+    TryStreamExt,
+};
+use tonic::{
+    body::BoxBody,
+    client::GrpcService,
+    codegen::{Body, Bytes, StdError},
+    Code,
+};
+use zcash_primitives::{consensus::BlockHeight, transaction::TxId};
+
+use super::ChainDataSource;
+use crate::{
+    proto::{
+        compact_formats::CompactBlock,
+        service::{
+            self, compact_tx_streamer_client::CompactTxStreamerClient, GetAddressUtxosReply,
+            RawTransaction, SubtreeRoot, TreeState,
+        },
+    },
+    ShieldedProtocol,
+};
+
+#[async_trait]
+impl<ChT> ChainDataSource for CompactTxStreamerClient<ChT>
+where
+    ChT: GrpcService<BoxBody> + Send,
+    ChT::Error: Into<StdError>,
+    ChT::Future: Send,
+    ChT::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <ChT::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    type Error = tonic::Status;
+
+    async fn get_latest_block(&mut self) -> Result<BlockHeight, Self::Error> {
+        self.get_latest_block(service::ChainSpec::default())
+            .await?
+            .get_ref()
+            .height
+            .try_into()
+            .map_err(|_| tonic::Status::out_of_range("Latest block height is out of range"))
+    }
+
+    async fn get_block_range(
+        &mut self,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<CompactBlock>, Self::Error> {
+        let mut start = service::BlockId::default();
+        start.height = range.start.into();
+        let mut end = service::BlockId::default();
+        end.height = (range.end - 1).into();
+        let range = service::BlockRange {
+            start: Some(start),
+            end: Some(end),
+        };
+        self.get_block_range(range)
+            .await?
+            .into_inner()
+            .try_collect()
+            .await
+    }
+
+    async fn get_tree_state(&mut self, height: BlockHeight) -> Result<TreeState, Self::Error> {
+        self.get_tree_state(service::BlockId {
+            height: height.into(),
+            hash: vec![],
+        })
+        .await
+        .map(|response| response.into_inner())
+    }
+
+    async fn get_subtree_roots(
+        &mut self,
+        protocol: ShieldedProtocol,
+        start_index: u32,
+    ) -> Result<Vec<SubtreeRoot>, Self::Error> {
+        let mut request = service::GetSubtreeRootsArg {
+            start_index,
+            ..Default::default()
+        };
+        request.set_shielded_protocol(match protocol {
+            ShieldedProtocol::Sapling => service::ShieldedProtocol::Sapling,
+            ShieldedProtocol::Orchard => service::ShieldedProtocol::Orchard,
+        });
+        // Hack to work around a bug in the initial lightwalletd implementation.
+        request.max_entries = 65536;
+
+        self.get_subtree_roots(request)
+            .await?
+            .into_inner()
+            .try_collect()
+            .await
+    }
+
+    async fn get_transaction(&mut self, txid: TxId) -> Result<Option<RawTransaction>, Self::Error> {
+        let request = service::TxFilter {
+            hash: txid.as_ref().to_vec(),
+            ..Default::default()
+        };
+        match self.get_transaction(request).await {
+            Ok(response) => Ok(Some(response.into_inner())),
+            Err(status) if status.code() == Code::NotFound => Ok(None),
+            Err(status) => Err(status),
+        }
+    }
+
+    async fn get_address_utxos(
+        &mut self,
+        addresses: Vec<String>,
+        start_height: BlockHeight,
+    ) -> Result<Vec<GetAddressUtxosReply>, Self::Error> {
+        let request = service::GetAddressUtxosArg {
+            addresses,
+            start_height: start_height.into(),
+            max_entries: 0,
+        };
+        self.get_address_utxos_stream(request)
+            .await?
+            .into_inner()
+            .try_collect()
+            .await
+    }
+
+    async fn get_taddress_transactions(
+        &mut self,
+        address: String,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<RawTransaction>, Self::Error> {
+        let request = service::TransparentAddressBlockFilter {
+            address,
+            range: Some(service::BlockRange {
+                start: Some(service::BlockId {
+                    height: range.start.into(),
+                    hash: vec![],
+                }),
+                end: Some(service::BlockId {
+                    height: (range.end - 1).into(),
+                    hash: vec![],
+                }),
+            }),
+        };
+        self.get_taddress_txids(request)
+            .await?
+            .into_inner()
+            .try_collect()
+            .await
+    }
+
+    async fn get_mempool_stream(
+        &mut self,
+    ) -> Result<BoxStream<'_, Result<RawTransaction, Self::Error>>, Self::Error> {
+        Ok(self
+            .get_mempool_stream(service::Empty {})
+            .await?
+            .into_inner()
+            .boxed())
+    }
+}
@@ -0,0 +1,354 @@
+//! An in-memory [`ChainDataSource`], for use in tests.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+
+use async_trait::async_trait;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use zcash_primitives::{consensus::BlockHeight, transaction::TxId};
+
+use super::ChainDataSource;
+use crate::{
+    proto::{
+        compact_formats::CompactBlock,
+        service::{GetAddressUtxosReply, RawTransaction, SubtreeRoot, TreeState},
+    },
+    ShieldedProtocol,
+};
+
+/// Errors that can be produced by a [`MemoryChainSource`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The source has no blocks.
+    NoBlocks,
+    /// The block at the given height is not present in the source.
+    MissingBlock(BlockHeight),
+    /// The tree state at the given height is not present in the source.
+    MissingTreeState(BlockHeight),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoBlocks => write!(f, "The chain data source has no blocks"),
+            Error::MissingBlock(height) => write!(f, "Block at height {} is missing", height),
+            Error::MissingTreeState(height) => {
+                write!(f, "Tree state at height {} is missing", height)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A [`ChainDataSource`] that serves chain data from memory.
+///
+/// This can be used to write deterministic tests of wallet synchronization.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryChainSource {
+    blocks: BTreeMap<BlockHeight, CompactBlock>,
+    tree_states: BTreeMap<BlockHeight, TreeState>,
+    sapling_subtree_roots: Vec<SubtreeRoot>,
+    orchard_subtree_roots: Vec<SubtreeRoot>,
+    transactions: HashMap<TxId, RawTransaction>,
+    utxos: Vec<GetAddressUtxosReply>,
+    address_transactions: Vec<(String, TxId)>,
+    mempool: Vec<RawTransaction>,
+}
+
+impl MemoryChainSource {
+    /// Constructs an empty source.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a block to the chain, replacing any existing block at the same height.
+    pub fn insert_block(&mut self, block: CompactBlock) {
+        self.blocks.insert(block.height(), block);
+    }
+
+    /// Removes all blocks, and their tree states, above the given height.
+    pub fn truncate(&mut self, height: BlockHeight) {
+        self.blocks.split_off(&(height + 1));
+        self.tree_states.split_off(&(height + 1));
+    }
+
+    /// Sets the tree state as of the end of the block at the tree state's height.
+    pub fn insert_tree_state(&mut self, tree_state: TreeState) {
+        self.tree_states
+            .insert(BlockHeight::from_u32(tree_state.height as u32), tree_state);
+    }
+
+    /// Appends the root of the next complete subtree for the given protocol.
+    pub fn push_subtree_root(&mut self, protocol: ShieldedProtocol, root: SubtreeRoot) {
+        match protocol {
+            ShieldedProtocol::Sapling => self.sapling_subtree_roots.push(root),
+            ShieldedProtocol::Orchard => self.orchard_subtree_roots.push(root),
+        }
+    }
+
+    /// Adds a full transaction.
+    pub fn insert_transaction(&mut self, txid: TxId, tx: RawTransaction) {
+        self.transactions.insert(txid, tx);
+    }
+
+    /// Adds an unspent transparent output.
+    pub fn insert_utxo(&mut self, utxo: GetAddressUtxosReply) {
+        self.utxos.push(utxo);
+    }
+
+    /// Adds a mined transaction that sends funds to, or spends funds from, the given
+    /// transparent address.
+    ///
+    /// The transaction is also made available via [`ChainDataSource::get_transaction`].
+    pub fn insert_address_transaction(&mut self, address: String, txid: TxId, tx: RawTransaction) {
+        self.transactions.insert(txid, tx);
+        self.address_transactions.push((address, txid));
+    }
+
+    /// Adds a transaction to the mempool.
+    ///
+    /// The transaction is also made available via [`ChainDataSource::get_transaction`].
+    pub fn insert_mempool_transaction(&mut self, txid: TxId, data: Vec<u8>) {
+        let tx = RawTransaction { data, height: 0 };
+        self.transactions.insert(txid, tx.clone());
+        self.mempool.push(tx);
+    }
+
+    /// Removes all transactions from the mempool.
+    pub fn clear_mempool(&mut self) {
+        self.mempool.clear();
+    }
+}
+
+#[async_trait]
+impl ChainDataSource for MemoryChainSource {
+    type Error = Error;
+
+    async fn get_latest_block(&mut self) -> Result<BlockHeight, Self::Error> {
+        self.blocks
+            .keys()
+            .next_back()
+            .copied()
+            .ok_or(Error::NoBlocks)
+    }
+
+    async fn get_block_range(
+        &mut self,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<CompactBlock>, Self::Error> {
+        (u32::from(range.start)..u32::from(range.end))
+            .map(BlockHeight::from_u32)
+            .map(|height| {
+                self.blocks
+                    .get(&height)
+                    .cloned()
+                    .ok_or(Error::MissingBlock(height))
+            })
+            .collect()
+    }
+
+    async fn get_tree_state(&mut self, height: BlockHeight) -> Result<TreeState, Self::Error> {
+        self.tree_states
+            .get(&height)
+            .cloned()
+            .ok_or(Error::MissingTreeState(height))
+    }
+
+    async fn get_subtree_roots(
+        &mut self,
+        protocol: ShieldedProtocol,
+        start_index: u32,
+    ) -> Result<Vec<SubtreeRoot>, Self::Error> {
+        let roots = match protocol {
+            ShieldedProtocol::Sapling => &self.sapling_subtree_roots,
+            ShieldedProtocol::Orchard => &self.orchard_subtree_roots,
+        };
+        Ok(roots.iter().skip(start_index as usize).cloned().collect())
+    }
+
+    async fn get_transaction(&mut self, txid: TxId) -> Result<Option<RawTransaction>, Self::Error> {
+        Ok(self.transactions.get(&txid).cloned())
+    }
+
+    async fn get_address_utxos(
+        &mut self,
+        addresses: Vec<String>,
+        start_height: BlockHeight,
+    ) -> Result<Vec<GetAddressUtxosReply>, Self::Error> {
+        let mut utxos: Vec<_> = self
+            .utxos
+            .iter()
+            .filter(|utxo| {
+                addresses.contains(&utxo.address) && utxo.height >= u64::from(start_height)
+            })
+            .cloned()
+            .collect();
+        utxos.sort_by_key(|utxo| utxo.height);
+        Ok(utxos)
+    }
+
+    async fn get_taddress_transactions(
+        &mut self,
+        address: String,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<RawTransaction>, Self::Error> {
+        let mut txs: Vec<_> = self
+            .address_transactions
+            .iter()
+            .filter(|(a, _)| a == &address)
+            .filter_map(|(_, txid)| self.transactions.get(txid))
+            .filter(|tx| (u64::from(range.start)..u64::from(range.end)).contains(&tx.height))
+            .cloned()
+            .collect();
+        txs.sort_by_key(|tx| tx.height);
+        Ok(txs)
+    }
+
+    async fn get_mempool_stream(
+        &mut self,
+    ) -> Result<BoxStream<'_, Result<RawTransaction, Self::Error>>, Self::Error> {
+        Ok(stream::iter(self.mempool.clone().into_iter().map(Ok)).boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+    use zcash_primitives::{consensus::BlockHeight, transaction::TxId};
+
+    use super::{Error, MemoryChainSource};
+    use crate::{
+        proto::{
+            compact_formats::CompactBlock,
+            service::{GetAddressUtxosReply, RawTransaction, SubtreeRoot, TreeState},
+        },
+        sync::ChainDataSource,
+        ShieldedProtocol,
+    };
+
+    fn block(height: u32) -> CompactBlock {
+        CompactBlock {
+            height: height.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn serves_inserted_data() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(serves_inserted_data_async());
+    }
+
+    async fn serves_inserted_data_async() {
+        let mut source = MemoryChainSource::new();
+        assert_eq!(source.get_latest_block().await, Err(Error::NoBlocks));
+
+        for height in 10..20 {
+            source.insert_block(block(height));
+            source.insert_tree_state(TreeState {
+                height: height.into(),
+                ..Default::default()
+            });
+        }
+        let h = BlockHeight::from_u32;
+        assert_eq!(source.get_latest_block().await, Ok(h(19)));
+
+        let blocks = source.get_block_range(h(12)..h(15)).await.unwrap();
+        assert_eq!(
+            blocks.iter().map(|b| b.height).collect::<Vec<_>>(),
+            vec![12, 13, 14]
+        );
+        assert_eq!(
+            source.get_block_range(h(18)..h(21)).await,
+            Err(Error::MissingBlock(h(20)))
+        );
+
+        source.truncate(h(15));
+        assert_eq!(source.get_latest_block().await, Ok(h(15)));
+        assert_eq!(
+            source.get_tree_state(h(16)).await,
+            Err(Error::MissingTreeState(h(16)))
+        );
+        assert_eq!(source.get_tree_state(h(15)).await.unwrap().height, 15);
+
+        for i in 0..3u64 {
+            source.push_subtree_root(
+                ShieldedProtocol::Sapling,
+                SubtreeRoot {
+                    completing_block_height: 10 + i,
+                    ..Default::default()
+                },
+            );
+        }
+        let roots = source
+            .get_subtree_roots(ShieldedProtocol::Sapling, 1)
+            .await
+            .unwrap();
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].completing_block_height, 11);
+        assert!(source
+            .get_subtree_roots(ShieldedProtocol::Orchard, 0)
+            .await
+            .unwrap()
+            .is_empty());
+
+        for (address, height) in [("a", 14), ("b", 12), ("a", 11)] {
+            source.insert_utxo(GetAddressUtxosReply {
+                address: address.to_string(),
+                height,
+                ..Default::default()
+            });
+        }
+        let utxos = source
+            .get_address_utxos(vec!["a".to_string()], h(11))
+            .await
+            .unwrap();
+        assert_eq!(
+            utxos.iter().map(|u| u.height).collect::<Vec<_>>(),
+            vec![11, 14]
+        );
+
+        let txid = TxId::from_bytes([1; 32]);
+        assert_eq!(source.get_transaction(txid).await, Ok(None));
+        source.insert_mempool_transaction(txid, vec![1, 2, 3]);
+        assert_eq!(
+            source.get_transaction(txid).await.unwrap().unwrap().height,
+            0
+        );
+        let mempool: Vec<_> = source
+            .get_mempool_stream()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(mempool.len(), 1);
+        assert_eq!(mempool[0].data, vec![1, 2, 3]);
+
+        for (i, height) in [(2u8, 13), (3, 11), (4, 18)] {
+            source.insert_address_transaction(
+                "a".to_string(),
+                TxId::from_bytes([i; 32]),
+                RawTransaction {
+                    data: vec![i],
+                    height,
+                },
+            );
+        }
+        let txs = source
+            .get_taddress_transactions("a".to_string(), h(11)..h(18))
+            .await
+            .unwrap();
+        assert_eq!(
+            txs.iter().map(|tx| tx.height).collect::<Vec<_>>(),
+            vec![11, 13]
+        );
+        assert!(source
+            .get_taddress_transactions("b".to_string(), h(0)..h(20))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -11,6 +11,9 @@ mod grpc;
 
 pub mod http;
 
+#[cfg(feature = "lightwalletd-tonic-tls-webpki-roots")]
+pub mod isolation;
+
 /// A Tor client that exposes capabilities designed for Zcash wallets.
 #[derive(Clone)]
 pub struct Client {
@@ -79,6 +82,8 @@ pub enum Error {
     #[cfg(feature = "lightwalletd-tonic-tls-webpki-roots")]
     /// An error occurred while using gRPC-over-Tor.
     Grpc(self::grpc::GrpcError),
+    /// An error occurred while querying an exchange for ZEC data.
+    Exchange(self::http::cryptex::ExchangeError),
     /// An error occurred while using HTTP-over-Tor.
     Http(self::http::HttpError),
     /// An IO error occurred while interacting with the filesystem.
@@ -93,6 +98,7 @@ impl fmt::Display for Error {
             Error::MissingTorDirectory => write!(f, "Tor directory is missing"),
             #[cfg(feature = "lightwalletd-tonic-tls-webpki-roots")]
             Error::Grpc(e) => write!(f, "gRPC-over-Tor error: {}", e),
+            Error::Exchange(e) => write!(f, "Exchange error: {}", e),
             Error::Http(e) => write!(f, "HTTP-over-Tor error: {}", e),
             Error::Io(e) => write!(f, "IO error: {}", e),
             Error::Tor(e) => write!(f, "Tor error: {}", e),
@@ -106,6 +112,7 @@ impl std::error::Error for Error {
             Error::MissingTorDirectory => None,
             #[cfg(feature = "lightwalletd-tonic-tls-webpki-roots")]
             Error::Grpc(e) => Some(e),
+            Error::Exchange(e) => Some(e),
             Error::Http(e) => Some(e),
             Error::Io(e) => Some(e),
             Error::Tor(e) => Some(e),
@@ -120,6 +127,12 @@ impl From<self::grpc::GrpcError> for Error {
     }
 }
 
+impl From<self::http::cryptex::ExchangeError> for Error {
+    fn from(e: self::http::cryptex::ExchangeError) -> Self {
+        Error::Exchange(e)
+    }
+}
+
 impl From<self::http::HttpError> for Error {
     fn from(e: self::http::HttpError) -> Self {
         Error::Http(e)
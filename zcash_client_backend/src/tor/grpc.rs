@@ -81,12 +81,20 @@ impl Service<Uri> for HttpTcpConnector {
 pub enum GrpcError {
     /// A [`tonic`] error.
     Tonic(tonic::transport::Error),
+    /// The server returned an error in response to a request.
+    Status(tonic::Status),
+    /// The server rejected a transaction that was sent to it.
+    TransactionRejected { code: i32, message: String },
 }
 
 impl fmt::Display for GrpcError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             GrpcError::Tonic(e) => write!(f, "Hyper error: {}", e),
+            GrpcError::Status(e) => write!(f, "gRPC status: {}", e),
+            GrpcError::TransactionRejected { code, message } => {
+                write!(f, "Transaction rejected ({}): {}", code, message)
+            }
         }
     }
 }
@@ -95,6 +103,8 @@ impl std::error::Error for GrpcError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             GrpcError::Tonic(e) => Some(e),
+            GrpcError::Status(e) => Some(e),
+            GrpcError::TransactionRejected { .. } => None,
         }
     }
 }
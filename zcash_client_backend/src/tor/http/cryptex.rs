@@ -1,41 +1,162 @@
 //! Cryptocurrency exchange rate APIs.
 
-use futures_util::{future::join_all, join};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::{future::join_all, join, stream, StreamExt};
 use rand::{seq::IteratorRandom, thread_rng};
 use rust_decimal::Decimal;
 use tracing::{error, trace};
 
 use crate::tor::{Client, Error};
 
+pub use crate::fiat::Currency;
+
 mod binance;
+mod bitfinex;
 mod coinbase;
 mod gate_io;
 mod gemini;
+mod kraken;
 mod ku_coin;
 mod mexc;
 
 /// Exchanges for which we know how to query data over Tor.
 pub mod exchanges {
     pub use super::binance::Binance;
+    pub use super::bitfinex::Bitfinex;
     pub use super::coinbase::Coinbase;
     pub use super::gate_io::GateIo;
     pub use super::gemini::Gemini;
+    pub use super::kraken::Kraken;
     pub use super::ku_coin::KuCoin;
     pub use super::mexc::Mexc;
 }
 
+/// The length of an hour, in seconds.
+const HOUR: u64 = 60 * 60;
+
+/// The maximum number of historical rates that [`Client::fill_price_history`] queries
+/// concurrently.
+const MAX_CONCURRENT_HISTORY_QUERIES: usize = 8;
+
+/// Returns the start of the hour containing the given time, in seconds since the UNIX
+/// epoch.
+fn hour_start(time: SystemTime) -> u64 {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    secs - secs % HOUR
+}
+
+/// Returns an error if `currency` is not one of the `supported` currencies.
+fn ensure_supported(currency: Currency, supported: &[Currency]) -> Result<(), Error> {
+    if supported.contains(&currency) {
+        Ok(())
+    } else {
+        Err(ExchangeError::UnsupportedCurrency(currency).into())
+    }
+}
+
 /// An exchange that can be queried for ZEC data.
 #[trait_variant::make(Exchange: Send)]
 #[dynosaur::dynosaur(DynExchange = dyn Exchange)]
 #[dynosaur::dynosaur(DynLocalExchange = dyn LocalExchange)]
 pub trait LocalExchange {
-    /// Queries data about the USD/ZEC pair.
+    /// Queries data about the ZEC pair with the given currency.
+    ///
+    /// The returned bid and ask data must be denominated in `currency`, i.e. the latest
+    /// bid and ask for 1 ZEC.
     ///
-    /// The returned bid and ask data must be denominated in USD, i.e. the latest bid and
-    /// ask for 1 ZEC.
-    async fn query_zec_to_usd(&self, client: &Client) -> Result<ExchangeData, Error>;
+    /// Returns [`ExchangeError::UnsupportedCurrency`] if the exchange does not have a
+    /// market for the pair.
+    async fn query_zec_to(
+        &self,
+        client: &Client,
+        currency: Currency,
+    ) -> Result<ExchangeData, Error>;
+
+    /// Queries the price of 1 ZEC in the given currency during the hour containing
+    /// `time`.
+    ///
+    /// Returns [`ExchangeError::UnsupportedCurrency`] if the exchange does not have a
+    /// market for the pair, and [`ExchangeError::NoHistoricalData`] if the exchange does
+    /// not provide hourly prices for `time`. Exchanges must not substitute prices of a
+    /// coarser granularity.
+    async fn query_historical_zec_rate(
+        &self,
+        client: &Client,
+        currency: Currency,
+        time: SystemTime,
+    ) -> Result<Decimal, Error>;
+}
+
+/// Errors that can occur while querying exchanges for ZEC data.
+#[derive(Debug)]
+pub enum ExchangeError {
+    /// The exchange does not have a market for ZEC in the given currency.
+    UnsupportedCurrency(Currency),
+    /// The exchange does not provide hourly historical prices for the requested time.
+    NoHistoricalData,
+    /// The exchange did not return the requested data.
+    MissingData,
+    /// None of the queried exchanges support the requested data.
+    NoSources,
+    /// Fewer of the queried exchanges support the requested data than the configured
+    /// minimum (see [`ExchangesBuilder::with_min_sources`]).
+    TooFewSources {
+        /// The number of exchanges that support the requested data.
+        supported: usize,
+        /// The minimum number of exchanges that must support the requested data.
+        required: usize,
+    },
+}
+
+impl ExchangeError {
+    /// Returns `true` if the error indicates that the exchange cannot provide the
+    /// requested data at all, rather than that the query failed.
+    fn is_unsupported(&self) -> bool {
+        matches!(
+            self,
+            ExchangeError::UnsupportedCurrency(_) | ExchangeError::NoHistoricalData
+        )
+    }
 }
 
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::UnsupportedCurrency(currency) => {
+                write!(f, "Exchange has no {}/ZEC market", currency)
+            }
+            ExchangeError::NoHistoricalData => {
+                write!(
+                    f,
+                    "Exchange does not provide hourly prices for the requested time"
+                )
+            }
+            ExchangeError::MissingData => write!(f, "Exchange did not return the requested data"),
+            ExchangeError::NoSources => {
+                write!(f, "No exchange supports the requested data")
+            }
+            ExchangeError::TooFewSources {
+                supported,
+                required,
+            } => write!(
+                f,
+                "Only {} exchanges support the requested data, but {} are required",
+                supported, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExchangeError {}
+
 /// Data queried from an [`Exchange`].
 #[derive(Debug)]
 pub struct ExchangeData {
@@ -58,18 +179,26 @@ impl ExchangeData {
 pub struct Exchanges {
     trusted: Box<DynExchange<'static>>,
     others: Vec<Box<DynExchange<'static>>>,
+    min_sources: usize,
 }
 
 impl Exchanges {
-    /// Unauthenticated connections to all known exchanges with USD/ZEC pairs.
+    /// Unauthenticated connections to all known exchanges.
     ///
     /// Gemini is treated as a "trusted" data source due to being a NYDFS-regulated
-    /// exchange.
+    /// exchange. A minimum of three successful responses are required, and queries that
+    /// fewer than three of the exchanges support fail with
+    /// [`ExchangeError::TooFewSources`]. Currently, only Kraken has a EUR/ZEC market, and
+    /// hourly historical rates are provided by Binance, Bitfinex, Coinbase and KuCoin, as
+    /// well as by Kraken for the last 30 days. Use [`Exchanges::builder`] with
+    /// [`ExchangesBuilder::with_min_sources`] to accept rates from fewer exchanges.
     pub fn unauthenticated_known_with_gemini_trusted() -> Self {
         Self::builder(exchanges::Gemini::unauthenticated())
             .with(exchanges::Binance::unauthenticated())
+            .with(exchanges::Bitfinex::unauthenticated())
             .with(exchanges::Coinbase::unauthenticated())
             .with(exchanges::GateIo::unauthenticated())
+            .with(exchanges::Kraken::unauthenticated())
             .with(exchanges::KuCoin::unauthenticated())
             .with(exchanges::Mexc::unauthenticated())
             .build()
@@ -92,9 +221,14 @@ impl Exchanges {
 /// against transient network failures or adversarial market manipulation on individual
 /// sources.
 ///
-/// The number of times [`ExchangesBuilder::with`] is called will affect the behaviour of
-/// the final [`Exchanges`]:
-/// - With no additional sources, the trusted [`Exchange`] is used on its own.
+/// By default, a query must be supported by at least three of the sources, or it fails
+/// with [`ExchangeError::TooFewSources`]. Sources that cannot provide the data for a
+/// particular query (for example, because they have no market for the requested
+/// currency) are left out when counting the sources for that query.
+/// [`ExchangesBuilder::with_min_sources`] can be used to lower this minimum, in which
+/// case the number of sources supporting a query will affect the behaviour of the final
+/// [`Exchanges`]:
+/// - With only the trusted [`Exchange`], it is used on its own.
 /// - With one additional source, the trusted [`Exchange`] is used preferentially,
 ///   with the additional source as a backup if the trusted source cannot be queried.
 /// - With two or more additional sources, a minimum of three successful responses are
@@ -110,6 +244,7 @@ impl ExchangesBuilder {
         Self(Exchanges {
             trusted: DynExchange::boxed(trusted),
             others: vec![],
+            min_sources: 3,
         })
     }
 
@@ -119,6 +254,17 @@ impl ExchangesBuilder {
         self
     }
 
+    /// Sets the minimum number of sources (including the trusted source) that must
+    /// support a query for it to be answered.
+    ///
+    /// Queries that fewer sources support fail with [`ExchangeError::TooFewSources`],
+    /// instead of relying on those few sources. Defaults to 3; set this to 1 to accept
+    /// rates from a single source.
+    pub fn with_min_sources(mut self, min_sources: usize) -> Self {
+        self.0.min_sources = min_sources;
+        self
+    }
+
     /// Builds the [`Exchanges`].
     pub fn build(self) -> Exchanges {
         self.0
@@ -128,71 +274,389 @@ impl ExchangesBuilder {
 impl Client {
     /// Fetches the latest USD/ZEC exchange rate, derived from the given exchanges.
     ///
-    /// Returns:
-    /// - `Ok(rate)` if at least one exchange request succeeds.
-    /// - `Err(_)` if none of the exchange queries succeed.
+    /// See [`Self::get_latest_zec_rate`] for how the rate is derived, and when it is
+    /// refused.
     pub async fn get_latest_zec_to_usd_rate(
         &self,
         exchanges: &Exchanges,
+    ) -> Result<Decimal, Error> {
+        self.get_latest_zec_rate(exchanges, Currency::Usd).await
+    }
+
+    /// Fetches the latest exchange rate between ZEC and the given currency, derived from
+    /// the given exchanges.
+    ///
+    /// Returns:
+    /// - `Ok(rate)` with the median of the rates reported by the exchanges that answered.
+    /// - `Err(_)` wrapping [`ExchangeError::NoSources`] if none of the exchanges support
+    ///   the currency, or [`ExchangeError::TooFewSources`] if fewer of them do than the
+    ///   minimum set with [`ExchangesBuilder::with_min_sources`].
+    /// - `Err(_)` with one of the request errors if at least three exchanges support the
+    ///   currency but fewer than three of their requests succeed, or if every request
+    ///   fails.
+    pub async fn get_latest_zec_rate(
+        &self,
+        exchanges: &Exchanges,
+        currency: Currency,
     ) -> Result<Decimal, Error> {
         // Fetch the data in parallel.
         let res = join!(
-            exchanges.trusted.query_zec_to_usd(self),
-            join_all(exchanges.others.iter().map(|e| e.query_zec_to_usd(self)))
+            exchanges.trusted.query_zec_to(self, currency),
+            join_all(
+                exchanges
+                    .others
+                    .iter()
+                    .map(|e| e.query_zec_to(self, currency))
+            )
         );
         trace!(?res, "Data results");
         let (trusted_res, other_res) = res;
 
-        // Split into successful queries and errors.
-        let mut rates: Vec<Decimal> = vec![];
-        let mut errors = vec![];
-        for res in other_res {
+        aggregate_rates(
+            trusted_res.map(|d| d.exchange_rate()),
+            other_res
+                .into_iter()
+                .map(|res| res.map(|d| d.exchange_rate()))
+                .collect(),
+            exchanges.min_sources,
+        )
+    }
+
+    /// Fetches the price of 1 ZEC in the given currency during the hour containing
+    /// `time`, derived from the given exchanges.
+    ///
+    /// The rate is derived from the exchanges' hourly rates, and refused when too few
+    /// exchanges support the query or answer it, in the same way as for
+    /// [`Self::get_latest_zec_rate`].
+    pub async fn get_historical_zec_rate(
+        &self,
+        exchanges: &Exchanges,
+        currency: Currency,
+        time: SystemTime,
+    ) -> Result<Decimal, Error> {
+        // Fetch the data in parallel.
+        let res = join!(
+            exchanges
+                .trusted
+                .query_historical_zec_rate(self, currency, time),
+            join_all(
+                exchanges
+                    .others
+                    .iter()
+                    .map(|e| e.query_historical_zec_rate(self, currency, time))
+            )
+        );
+        trace!(?res, "Historical data results");
+        let (trusted_res, other_res) = res;
+
+        aggregate_rates(trusted_res, other_res, exchanges.min_sources)
+    }
+
+    /// Fills in `history` with the exchange rate between ZEC and the given currency at
+    /// each of the given times (for example, the block times of the wallet's
+    /// transactions), derived from the given exchanges.
+    ///
+    /// Only the hours for which `history` does not already have a rate are queried, with
+    /// up to eight queries in flight at a time. If any query fails, one of the errors is
+    /// returned; the rates that were fetched successfully are kept in `history`, so this
+    /// method can be called again to retry.
+    pub async fn fill_price_history(
+        &self,
+        exchanges: &Exchanges,
+        history: &mut PriceHistory,
+        currency: Currency,
+        times: impl IntoIterator<Item = SystemTime>,
+    ) -> Result<(), Error> {
+        let mut hours = times
+            .into_iter()
+            .filter(|time| history.get(currency, *time).is_none())
+            .map(hour_start)
+            .collect::<Vec<_>>();
+        hours.sort_unstable();
+        hours.dedup();
+
+        let mut results = stream::iter(hours)
+            .map(|hour| async move {
+                let time = UNIX_EPOCH + Duration::from_secs(hour);
+                let res = self
+                    .get_historical_zec_rate(exchanges, currency, time)
+                    .await;
+                (time, res)
+            })
+            .buffer_unordered(MAX_CONCURRENT_HISTORY_QUERIES);
+
+        let mut error = None;
+        while let Some((time, res)) = results.next().await {
             match res {
-                Ok(d) => rates.push(d.exchange_rate()),
-                Err(e) => errors.push(e),
+                Ok(rate) => history.insert(currency, time, rate),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
             }
         }
+        error.map_or(Ok(()), Err)
+    }
+}
 
-        // "Never go to sea with two chronometers; take one or three."
-        // Randomly drop one rate if necessary to have an odd number of rates, as long as
-        // we have either at least three rates, or fewer than three sources.
-        if exchanges.others.len() >= 2 && rates.len() + usize::from(trusted_res.is_ok()) < 3 {
-            error!("Too many exchange requests failed");
-            return Err(errors
-                .into_iter()
-                .next()
-                .expect("At least one request failed"));
+/// Derives an exchange rate from the rates reported by a trusted source and a set of
+/// additional sources.
+///
+/// Sources that cannot provide the data are ignored, and the query is refused if fewer
+/// than `min_sources` sources remain. The median of the remaining rates is used, so that
+/// a minority of sources reporting outlying rates cannot move the result.
+fn aggregate_rates(
+    trusted_res: Result<Decimal, Error>,
+    other_res: Vec<Result<Decimal, Error>>,
+    min_sources: usize,
+) -> Result<Decimal, Error> {
+    let is_unsupported =
+        |res: &Result<Decimal, Error>| matches!(res, Err(Error::Exchange(e)) if e.is_unsupported());
+
+    let trusted_res = Some(trusted_res).filter(|res| !is_unsupported(res));
+    let other_res = other_res
+        .into_iter()
+        .filter(|res| !is_unsupported(res))
+        .collect::<Vec<_>>();
+    if trusted_res.is_none() && other_res.is_empty() {
+        error!("No exchanges support the requested data");
+        return Err(ExchangeError::NoSources.into());
+    }
+    let supported = usize::from(trusted_res.is_some()) + other_res.len();
+    if supported < min_sources {
+        error!(
+            supported,
+            min_sources, "Too few exchanges support the requested data"
+        );
+        return Err(ExchangeError::TooFewSources {
+            supported,
+            required: min_sources,
         }
-        let evict_random = |s: &mut Vec<Decimal>| {
-            if let Some(index) = (0..s.len()).choose(&mut thread_rng()) {
-                s.remove(index);
+        .into());
+    }
+
+    // Split into successful queries and errors.
+    let mut rates: Vec<Decimal> = vec![];
+    let mut errors = vec![];
+    for res in other_res {
+        match res {
+            Ok(rate) => rates.push(rate),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    // "Never go to sea with two chronometers; take one or three."
+    // Randomly drop one rate if necessary to have an odd number of rates, as long as
+    // we have either at least three rates, or fewer than three sources support the query.
+    if supported >= 3 && rates.len() + usize::from(matches!(trusted_res, Some(Ok(_)))) < 3 {
+        error!("Too many exchange requests failed");
+        return Err(errors
+            .into_iter()
+            .next()
+            .expect("At least one request failed"));
+    }
+    let evict_random = |s: &mut Vec<Decimal>| {
+        if let Some(index) = (0..s.len()).choose(&mut thread_rng()) {
+            s.remove(index);
+        }
+    };
+    match trusted_res {
+        Some(Ok(trusted)) => {
+            if rates.len() % 2 != 0 {
+                evict_random(&mut rates);
             }
-        };
-        match trusted_res {
-            Ok(trusted) => {
-                if rates.len() % 2 != 0 {
-                    evict_random(&mut rates);
-                }
-                rates.push(trusted.exchange_rate());
+            rates.push(trusted);
+        }
+        Some(Err(e)) => {
+            if rates.len() % 2 == 0 {
+                evict_random(&mut rates);
             }
-            Err(e) => {
-                if rates.len() % 2 == 0 {
-                    evict_random(&mut rates);
-                }
-                errors.push(e);
+            errors.push(e);
+        }
+        None => {
+            if rates.len() % 2 == 0 {
+                evict_random(&mut rates);
             }
         }
+    }
+
+    // If all of the requests failed, log all errors and return one of them.
+    if rates.is_empty() {
+        error!("All exchange requests failed");
+        Err(errors.into_iter().next().expect("All requests failed"))
+    } else {
+        // We have an odd number of rates; take the median.
+        assert!(rates.len() % 2 != 0);
+        rates.sort();
+        let median = rates.len() / 2;
+        Ok(rates[median])
+    }
+}
+
+/// A local cache of historical exchange rates between ZEC and other currencies.
+///
+/// Rates are stored at a granularity of one hour: a rate inserted for a given time is
+/// returned for every time within the same hour.
+#[derive(Clone, Debug, Default)]
+pub struct PriceHistory {
+    rates: BTreeMap<(Currency, u64), Decimal>,
+}
+
+impl PriceHistory {
+    /// Constructs an empty price history.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached price of 1 ZEC in the given currency during the hour
+    /// containing `time`, if known.
+    pub fn get(&self, currency: Currency, time: SystemTime) -> Option<Decimal> {
+        self.rates.get(&(currency, hour_start(time))).copied()
+    }
+
+    /// Caches the price of 1 ZEC in the given currency during the hour containing
+    /// `time`, replacing any previously cached price.
+    pub fn insert(&mut self, currency: Currency, time: SystemTime, rate: Decimal) {
+        self.rates.insert((currency, hour_start(time)), rate);
+    }
+
+    /// Returns an iterator over the cached prices, along with the currency and the start
+    /// of the hour for which each was cached.
+    ///
+    /// This can be used to persist the cache, which can later be restored with
+    /// [`PriceHistory::insert`].
+    pub fn iter(&self) -> impl Iterator<Item = (Currency, SystemTime, Decimal)> + '_ {
+        self.rates.iter().map(|(&(currency, hour), &rate)| {
+            (currency, UNIX_EPOCH + Duration::from_secs(hour), rate)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use rust_decimal::Decimal;
 
-        // If all of the requests failed, log all errors and return one of them.
-        if rates.is_empty() {
-            error!("All exchange requests failed");
-            Err(errors.into_iter().next().expect("All requests failed"))
-        } else {
-            // We have an odd number of rates; take the median.
-            assert!(rates.len() % 2 != 0);
-            rates.sort();
-            let median = rates.len() / 2;
-            Ok(rates[median])
+    use super::{aggregate_rates, Currency, ExchangeError, PriceHistory};
+    use crate::tor::Error;
+
+    fn failed() -> Result<Decimal, Error> {
+        Err(ExchangeError::MissingData.into())
+    }
+
+    fn unsupported() -> Result<Decimal, Error> {
+        Err(ExchangeError::UnsupportedCurrency(Currency::Eur).into())
+    }
+
+    #[test]
+    fn aggregate_rates_rejects_outliers() {
+        // Whichever additional rate is evicted, the outlier cannot be the median.
+        for _ in 0..10 {
+            let rate = aggregate_rates(
+                Ok(Decimal::from(10)),
+                vec![
+                    Ok(Decimal::from(11)),
+                    Ok(Decimal::from(1000)),
+                    Ok(Decimal::from(12)),
+                    unsupported(),
+                ],
+                1,
+            )
+            .unwrap();
+            assert!(rate == Decimal::from(11) || rate == Decimal::from(12));
         }
     }
+
+    #[test]
+    fn aggregate_rates_ignores_unsupported_sources() {
+        // The trusted source doesn't support the query, and only one other does.
+        assert_eq!(
+            aggregate_rates(
+                unsupported(),
+                vec![Ok(Decimal::from(7)), unsupported(), unsupported()],
+                1,
+            )
+            .unwrap(),
+            Decimal::from(7),
+        );
+
+        // Only two other sources support the query, so their rates are used even though
+        // that is fewer than three responses.
+        let rate = aggregate_rates(
+            unsupported(),
+            vec![Ok(Decimal::from(7)), unsupported(), Ok(Decimal::from(8))],
+            1,
+        )
+        .unwrap();
+        assert!(rate == Decimal::from(7) || rate == Decimal::from(8));
+
+        assert!(matches!(
+            aggregate_rates(unsupported(), vec![unsupported()], 1),
+            Err(Error::Exchange(ExchangeError::NoSources)),
+        ));
+    }
+
+    #[test]
+    fn aggregate_rates_requires_three_responses() {
+        assert!(matches!(
+            aggregate_rates(
+                failed(),
+                vec![Ok(Decimal::from(7)), Ok(Decimal::from(8)), failed()],
+                1,
+            ),
+            Err(Error::Exchange(ExchangeError::MissingData)),
+        ));
+    }
+
+    #[test]
+    fn aggregate_rates_requires_min_sources() {
+        // Only two sources support the query, so it is refused even though both succeed.
+        assert!(matches!(
+            aggregate_rates(
+                Ok(Decimal::from(7)),
+                vec![Ok(Decimal::from(8)), unsupported(), unsupported()],
+                3,
+            ),
+            Err(Error::Exchange(ExchangeError::TooFewSources {
+                supported: 2,
+                required: 3,
+            })),
+        ));
+        assert_eq!(
+            aggregate_rates(
+                Ok(Decimal::from(7)),
+                vec![Ok(Decimal::from(8)), Ok(Decimal::from(9)), unsupported()],
+                3,
+            )
+            .unwrap(),
+            Decimal::from(8),
+        );
+    }
+
+    #[test]
+    fn price_history() {
+        let mut history = PriceHistory::new();
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_123);
+        history.insert(Currency::Usd, time, Decimal::from(30));
+
+        // Rates are cached per hour, and per currency.
+        assert_eq!(
+            history.get(Currency::Usd, time + Duration::from_secs(60)),
+            Some(Decimal::from(30)),
+        );
+        assert_eq!(history.get(Currency::Eur, time), None);
+        assert_eq!(
+            history.get(Currency::Usd, time + Duration::from_secs(3600)),
+            None
+        );
+
+        assert_eq!(
+            history.iter().collect::<Vec<_>>(),
+            vec![(
+                Currency::Usd,
+                UNIX_EPOCH + Duration::from_secs(1_699_999_200),
+                Decimal::from(30),
+            )],
+        );
+    }
 }
@@ -1,7 +1,10 @@
+use std::time::SystemTime;
+
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
-use super::{Exchange, ExchangeData};
+use super::super::HttpError;
+use super::{ensure_supported, hour_start, Currency, Exchange, ExchangeData, ExchangeError, HOUR};
 use crate::tor::{Client, Error};
 
 /// Querier for the Binance exchange.
@@ -44,7 +47,13 @@ struct BinanceData {
 }
 
 impl Exchange for Binance {
-    async fn query_zec_to_usd(&self, client: &Client) -> Result<ExchangeData, Error> {
+    async fn query_zec_to(
+        &self,
+        client: &Client,
+        currency: Currency,
+    ) -> Result<ExchangeData, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
         // API documentation:
         // https://binance-docs.github.io/apidocs/spot/en/#24hr-ticker-price-change-statistics
         let res = client
@@ -60,4 +69,37 @@ impl Exchange for Binance {
             ask: data.askPrice,
         })
     }
+
+    async fn query_historical_zec_rate(
+        &self,
+        client: &Client,
+        currency: Currency,
+        time: SystemTime,
+    ) -> Result<Decimal, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
+        // API documentation:
+        // https://binance-docs.github.io/apidocs/spot/en/#kline-candlestick-data
+        let start_ms = hour_start(time) * 1000;
+        let res = client
+            .get_json::<Vec<Vec<serde_json::Value>>>(
+                format!(
+                    "https://api.binance.com/api/v3/klines?symbol=ZECUSDT&interval=1h&startTime={}&endTime={}&limit=1",
+                    start_ms,
+                    start_ms + HOUR * 1000 - 1,
+                )
+                .parse()
+                .unwrap(),
+            )
+            .await?;
+
+        // Each kline is `[open time, open, high, low, close, ...]`.
+        let close = res
+            .into_body()
+            .into_iter()
+            .next()
+            .and_then(|kline| kline.into_iter().nth(4))
+            .ok_or(ExchangeError::MissingData)?;
+        Ok(serde_json::from_value(close).map_err(HttpError::Json)?)
+    }
 }
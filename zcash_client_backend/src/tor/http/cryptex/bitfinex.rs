@@ -0,0 +1,79 @@
+use std::time::SystemTime;
+
+use rust_decimal::Decimal;
+
+use super::{ensure_supported, hour_start, Currency, Exchange, ExchangeData, ExchangeError, HOUR};
+use crate::tor::{Client, Error};
+
+/// Querier for the Bitfinex exchange.
+pub struct Bitfinex {
+    _private: (),
+}
+
+impl Bitfinex {
+    /// Prepares for unauthenticated connections to Bitfinex.
+    pub fn unauthenticated() -> Self {
+        Self { _private: () }
+    }
+}
+
+impl Exchange for Bitfinex {
+    async fn query_zec_to(
+        &self,
+        client: &Client,
+        currency: Currency,
+    ) -> Result<ExchangeData, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
+        // API documentation:
+        // https://docs.bitfinex.com/reference/rest-public-ticker
+        let res = client
+            .get_json::<Vec<Decimal>>(
+                "https://api-pub.bitfinex.com/v2/ticker/tZECUSD"
+                    .parse()
+                    .unwrap(),
+            )
+            .await?;
+
+        // The ticker is `[bid, bid size, ask, ask size, ...]`.
+        let data = res.into_body();
+        match (data.first(), data.get(2)) {
+            (Some(bid), Some(ask)) => Ok(ExchangeData {
+                bid: *bid,
+                ask: *ask,
+            }),
+            _ => Err(ExchangeError::MissingData.into()),
+        }
+    }
+
+    async fn query_historical_zec_rate(
+        &self,
+        client: &Client,
+        currency: Currency,
+        time: SystemTime,
+    ) -> Result<Decimal, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
+        // API documentation:
+        // https://docs.bitfinex.com/reference/rest-public-candles
+        let start_ms = hour_start(time) * 1000;
+        let res = client
+            .get_json::<Vec<Vec<Decimal>>>(
+                format!(
+                    "https://api-pub.bitfinex.com/v2/candles/trade:1h:tZECUSD/hist?start={}&end={}&limit=1&sort=1",
+                    start_ms,
+                    start_ms + HOUR * 1000 - 1,
+                )
+                .parse()
+                .unwrap(),
+            )
+            .await?;
+
+        // Each candle is `[time, open, close, high, low, volume]`.
+        res.into_body()
+            .into_iter()
+            .next()
+            .and_then(|candle| candle.get(2).copied())
+            .ok_or_else(|| ExchangeError::MissingData.into())
+    }
+}
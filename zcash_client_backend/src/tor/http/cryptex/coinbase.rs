@@ -1,7 +1,9 @@
+use std::time::SystemTime;
+
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
-use super::{Exchange, ExchangeData};
+use super::{ensure_supported, hour_start, Currency, Exchange, ExchangeData, ExchangeError, HOUR};
 use crate::tor::{Client, Error};
 
 /// Querier for the Coinbase exchange.
@@ -32,7 +34,13 @@ struct CoinbaseData {
 
 impl Exchange for Coinbase {
     #[allow(dead_code)]
-    async fn query_zec_to_usd(&self, client: &Client) -> Result<ExchangeData, Error> {
+    async fn query_zec_to(
+        &self,
+        client: &Client,
+        currency: Currency,
+    ) -> Result<ExchangeData, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
         // API documentation:
         // https://docs.cdp.coinbase.com/exchange/reference/exchangerestapi_getproductticker
         let res = client
@@ -48,4 +56,54 @@ impl Exchange for Coinbase {
             ask: data.ask,
         })
     }
+
+    async fn query_historical_zec_rate(
+        &self,
+        client: &Client,
+        currency: Currency,
+        time: SystemTime,
+    ) -> Result<Decimal, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
+        // API documentation:
+        // https://docs.cdp.coinbase.com/exchange/reference/exchangerestapi_getproductcandles
+        let start = hour_start(time);
+        let res = client
+            .get_json::<Vec<Vec<Decimal>>>(
+                format!(
+                    "https://api.exchange.coinbase.com/products/ZEC-USD/candles?granularity={}&start={}&end={}",
+                    HOUR,
+                    iso8601(start)?,
+                    iso8601(start + HOUR - 1)?,
+                )
+                .parse()
+                .unwrap(),
+            )
+            .await?;
+
+        // Each candle is `[time, low, high, open, close, volume]`.
+        res.into_body()
+            .into_iter()
+            .find(|candle| candle.first() == Some(&Decimal::from(start)))
+            .and_then(|candle| candle.get(4).copied())
+            .ok_or_else(|| ExchangeError::MissingData.into())
+    }
+}
+
+/// Formats the given time, in seconds since the UNIX epoch, as an ISO 8601 timestamp in
+/// UTC.
+fn iso8601(secs: u64) -> Result<String, Error> {
+    let time = i64::try_from(secs)
+        .ok()
+        .and_then(|secs| time::OffsetDateTime::from_unix_timestamp(secs).ok())
+        .ok_or(ExchangeError::NoHistoricalData)?;
+    Ok(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+    ))
 }
@@ -1,8 +1,10 @@
+use std::time::SystemTime;
+
 use hyper::StatusCode;
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
-use super::{Exchange, ExchangeData};
+use super::{ensure_supported, Currency, Exchange, ExchangeData, ExchangeError};
 use crate::tor::{Client, Error};
 
 /// Querier for the Gate.io exchange.
@@ -32,7 +34,13 @@ struct GateIoData {
 }
 
 impl Exchange for GateIo {
-    async fn query_zec_to_usd(&self, client: &Client) -> Result<ExchangeData, Error> {
+    async fn query_zec_to(
+        &self,
+        client: &Client,
+        currency: Currency,
+    ) -> Result<ExchangeData, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
         // API documentation:
         // https://www.gate.io/docs/developers/apiv4/#retrieve-ticker-information
         let res = client
@@ -51,4 +59,13 @@ impl Exchange for GateIo {
             ask: data.lowest_ask,
         })
     }
+
+    async fn query_historical_zec_rate(
+        &self,
+        _client: &Client,
+        _currency: Currency,
+        _time: SystemTime,
+    ) -> Result<Decimal, Error> {
+        Err(ExchangeError::NoHistoricalData.into())
+    }
 }
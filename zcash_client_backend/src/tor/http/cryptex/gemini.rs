@@ -1,7 +1,9 @@
+use std::time::SystemTime;
+
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
-use super::{Exchange, ExchangeData};
+use super::{ensure_supported, Currency, Exchange, ExchangeData, ExchangeError};
 use crate::tor::{Client, Error};
 
 /// Querier for the Gemini exchange.
@@ -30,7 +32,13 @@ struct GeminiData {
 }
 
 impl Exchange for Gemini {
-    async fn query_zec_to_usd(&self, client: &Client) -> Result<ExchangeData, Error> {
+    async fn query_zec_to(
+        &self,
+        client: &Client,
+        currency: Currency,
+    ) -> Result<ExchangeData, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
         // API documentation:
         // https://docs.gemini.com/rest-api/#ticker-v2
         let res = client
@@ -42,4 +50,13 @@ impl Exchange for Gemini {
             ask: data.ask,
         })
     }
+
+    async fn query_historical_zec_rate(
+        &self,
+        _client: &Client,
+        _currency: Currency,
+        _time: SystemTime,
+    ) -> Result<Decimal, Error> {
+        Err(ExchangeError::NoHistoricalData.into())
+    }
 }
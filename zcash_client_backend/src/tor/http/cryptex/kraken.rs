@@ -0,0 +1,152 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use super::super::HttpError;
+use super::{hour_start, Currency, Exchange, ExchangeData, ExchangeError, HOUR};
+use crate::tor::{Client, Error};
+
+/// Querier for the Kraken exchange.
+pub struct Kraken {
+    _private: (),
+}
+
+impl Kraken {
+    /// Prepares for unauthenticated connections to Kraken.
+    pub fn unauthenticated() -> Self {
+        Self { _private: () }
+    }
+}
+
+/// The number of most recent candles of each interval that Kraken returns.
+const MAX_CANDLES: u64 = 720;
+
+/// Returns the Kraken name for the ZEC pair with the given currency.
+fn pair(currency: Currency) -> &'static str {
+    match currency {
+        Currency::Usd => "ZECUSD",
+        Currency::Eur => "ZECEUR",
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct KrakenResponse<T> {
+    error: Vec<String>,
+    result: Option<T>,
+}
+
+impl<T> KrakenResponse<T> {
+    fn into_result(self) -> Result<T, Error> {
+        match self.result {
+            Some(result) if self.error.is_empty() => Ok(result),
+            _ => Err(ExchangeError::MissingData.into()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+struct KrakenTicker {
+    /// `[price, whole lot volume, lot volume]`
+    a: Vec<Decimal>,
+    /// `[price, whole lot volume, lot volume]`
+    b: Vec<Decimal>,
+    /// `[price, lot volume]`
+    c: Vec<Decimal>,
+}
+
+/// `[time, open, high, low, close, vwap, volume, count]`
+type KrakenCandle = (
+    u64,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    Decimal,
+    u64,
+);
+
+impl Exchange for Kraken {
+    async fn query_zec_to(
+        &self,
+        client: &Client,
+        currency: Currency,
+    ) -> Result<ExchangeData, Error> {
+        // API documentation:
+        // https://docs.kraken.com/api/docs/rest-api/get-ticker-information
+        let res = client
+            .get_json::<KrakenResponse<HashMap<String, KrakenTicker>>>(
+                format!(
+                    "https://api.kraken.com/0/public/Ticker?pair={}",
+                    pair(currency)
+                )
+                .parse()
+                .unwrap(),
+            )
+            .await?;
+
+        // The result is keyed by Kraken's internal name for the pair.
+        let data = res
+            .into_body()
+            .into_result()?
+            .into_values()
+            .next()
+            .ok_or(ExchangeError::MissingData)?;
+        match (data.b.first(), data.a.first()) {
+            (Some(bid), Some(ask)) => Ok(ExchangeData {
+                bid: *bid,
+                ask: *ask,
+            }),
+            _ => Err(ExchangeError::MissingData.into()),
+        }
+    }
+
+    async fn query_historical_zec_rate(
+        &self,
+        client: &Client,
+        currency: Currency,
+        time: SystemTime,
+    ) -> Result<Decimal, Error> {
+        let start = hour_start(time);
+
+        // Kraken only returns the most recent 720 candles of each interval. We don't fall
+        // back to daily candles for older times, because a daily close is not a price
+        // during the requested hour.
+        if start + MAX_CANDLES * HOUR <= hour_start(SystemTime::now()) {
+            return Err(ExchangeError::NoHistoricalData.into());
+        }
+
+        // API documentation:
+        // https://docs.kraken.com/api/docs/rest-api/get-ohlc-data
+        let res = client
+            .get_json::<KrakenResponse<HashMap<String, serde_json::Value>>>(
+                format!(
+                    "https://api.kraken.com/0/public/OHLC?pair={}&interval={}&since={}",
+                    pair(currency),
+                    HOUR / 60,
+                    start.saturating_sub(1),
+                )
+                .parse()
+                .unwrap(),
+            )
+            .await?;
+
+        // Alongside the candles, the result contains a `last` field with the ID of the
+        // most recent candle.
+        let candles = res
+            .into_body()
+            .into_result()?
+            .into_iter()
+            .find(|(key, _)| key != "last")
+            .ok_or(ExchangeError::MissingData)?
+            .1;
+        serde_json::from_value::<Vec<KrakenCandle>>(candles)
+            .map_err(HttpError::Json)?
+            .into_iter()
+            .find(|candle| candle.0 == start)
+            .map(|candle| candle.4)
+            .ok_or_else(|| ExchangeError::MissingData.into())
+    }
+}
@@ -1,7 +1,9 @@
+use std::time::SystemTime;
+
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
-use super::{Exchange, ExchangeData};
+use super::{ensure_supported, hour_start, Currency, Exchange, ExchangeData, ExchangeError, HOUR};
 use crate::tor::{Client, Error};
 
 /// Querier for the KuCoin exchange.
@@ -40,17 +42,23 @@ struct KuCoinData {
 
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
-struct KuCoinResponse {
+struct KuCoinResponse<T> {
     code: String,
-    data: KuCoinData,
+    data: T,
 }
 
 impl Exchange for KuCoin {
-    async fn query_zec_to_usd(&self, client: &Client) -> Result<ExchangeData, Error> {
+    async fn query_zec_to(
+        &self,
+        client: &Client,
+        currency: Currency,
+    ) -> Result<ExchangeData, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
         // API documentation:
         // https://www.kucoin.com/docs/rest/spot-trading/market-data/get-24hr-stats
         let res = client
-            .get_json::<KuCoinResponse>(
+            .get_json::<KuCoinResponse<KuCoinData>>(
                 "https://api.kucoin.com/api/v1/market/stats?symbol=ZEC-USDT"
                     .parse()
                     .unwrap(),
@@ -62,4 +70,37 @@ impl Exchange for KuCoin {
             ask: data.sell,
         })
     }
+
+    async fn query_historical_zec_rate(
+        &self,
+        client: &Client,
+        currency: Currency,
+        time: SystemTime,
+    ) -> Result<Decimal, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
+        // API documentation:
+        // https://www.kucoin.com/docs/rest/spot-trading/market-data/get-klines
+        let start = hour_start(time);
+        let res = client
+            .get_json::<KuCoinResponse<Vec<Vec<String>>>>(
+                format!(
+                    "https://api.kucoin.com/api/v1/market/candles?type=1hour&symbol=ZEC-USDT&startAt={}&endAt={}",
+                    start,
+                    start + HOUR - 1,
+                )
+                .parse()
+                .unwrap(),
+            )
+            .await?;
+
+        // Each candle is `[time, open, close, high, low, volume, turnover]`, with every
+        // field encoded as a string.
+        res.into_body()
+            .data
+            .into_iter()
+            .find(|candle| candle.first() == Some(&start.to_string()))
+            .and_then(|candle| candle.get(2).and_then(|close| close.parse().ok()))
+            .ok_or_else(|| ExchangeError::MissingData.into())
+    }
 }
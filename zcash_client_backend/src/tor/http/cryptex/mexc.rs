@@ -1,7 +1,9 @@
+use std::time::SystemTime;
+
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
-use super::{Exchange, ExchangeData};
+use super::{ensure_supported, Currency, Exchange, ExchangeData, ExchangeError};
 use crate::tor::{Client, Error};
 
 /// Querier for the MEXC exchange.
@@ -39,7 +41,13 @@ struct MexcData {
 }
 
 impl Exchange for Mexc {
-    async fn query_zec_to_usd(&self, client: &Client) -> Result<ExchangeData, Error> {
+    async fn query_zec_to(
+        &self,
+        client: &Client,
+        currency: Currency,
+    ) -> Result<ExchangeData, Error> {
+        ensure_supported(currency, &[Currency::Usd])?;
+
         // API documentation:
         // https://mexcdevelop.github.io/apidocs/spot_v3_en/#24hr-ticker-price-change-statistics
         let res = client
@@ -55,4 +63,13 @@ impl Exchange for Mexc {
             ask: data.askPrice,
         })
     }
+
+    async fn query_historical_zec_rate(
+        &self,
+        _client: &Client,
+        _currency: Currency,
+        _time: SystemTime,
+    ) -> Result<Decimal, Error> {
+        Err(ExchangeError::NoHistoricalData.into())
+    }
 }
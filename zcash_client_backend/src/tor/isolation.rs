@@ -0,0 +1,564 @@
+//! `lightwalletd` requests that are each sent over their own isolated Tor circuit.
+//!
+//! Broadcasting a transaction, or fetching the full data of a transaction that the
+//! wallet has detected, reveals to the server which transactions the wallet is
+//! interested in. If several of these requests were sent over the same circuit, the
+//! server could link them to one another, and thereby to a single wallet. The types in
+//! this module prevent this by opening a new connection for every request, over a
+//! circuit that is never shared with any other connection.
+//!
+//! With the `sync` feature flag, `IsolatedChainSource` applies this to the transactions
+//! that `sync::run` fetches, while sending all other requests to an existing
+//! `ChainDataSource`.
+
+use std::future::Future;
+
+use futures_util::{stream, StreamExt};
+use tonic::{
+    transport::{Channel, Uri},
+    Code,
+};
+use tracing::debug;
+use zcash_primitives::transaction::{Transaction, TxId};
+
+use super::{grpc::GrpcError, Client, Error};
+use crate::proto::service::{
+    self, compact_tx_streamer_client::CompactTxStreamerClient, RawTransaction,
+};
+
+#[cfg(feature = "sync")]
+use {
+    crate::{
+        proto::{
+            compact_formats::CompactBlock,
+            service::{GetAddressUtxosReply, SubtreeRoot, TreeState},
+        },
+        sync::ChainDataSource,
+        ShieldedProtocol,
+    },
+    async_trait::async_trait,
+    futures_util::stream::BoxStream,
+    std::{fmt, ops::Range, sync::Arc},
+    zcash_primitives::consensus::BlockHeight,
+};
+
+/// A transport that can open connections to a `lightwalletd` server, each over a
+/// circuit that is isolated from all other connections.
+pub trait Connector: Send + Sync {
+    /// The type of connection returned by [`Connector::connect_isolated`].
+    type Connection: TransactionService;
+
+    /// Opens a new connection to the server.
+    ///
+    /// Implementations must ensure that the returned connection does not share a
+    /// circuit with any connection previously returned by this method.
+    fn connect_isolated(&self) -> impl Future<Output = Result<Self::Connection, Error>> + Send;
+}
+
+/// The `lightwalletd` requests that [`IsolatedRequests`] sends over isolated
+/// connections.
+pub trait TransactionService: Send {
+    /// Submits a transaction to the server for broadcast to the network.
+    fn send_transaction(
+        &mut self,
+        tx: RawTransaction,
+    ) -> impl Future<Output = Result<service::SendResponse, tonic::Status>> + Send;
+
+    /// Fetches the transaction with the given ID, or returns `None` if the server does
+    /// not know of it.
+    fn get_transaction(
+        &mut self,
+        txid: TxId,
+    ) -> impl Future<Output = Result<Option<RawTransaction>, tonic::Status>> + Send;
+}
+
+impl TransactionService for CompactTxStreamerClient<Channel> {
+    async fn send_transaction(
+        &mut self,
+        tx: RawTransaction,
+    ) -> Result<service::SendResponse, tonic::Status> {
+        CompactTxStreamerClient::send_transaction(self, tx)
+            .await
+            .map(|response| response.into_inner())
+    }
+
+    async fn get_transaction(
+        &mut self,
+        txid: TxId,
+    ) -> Result<Option<RawTransaction>, tonic::Status> {
+        let request = service::TxFilter {
+            hash: txid.as_ref().to_vec(),
+            ..Default::default()
+        };
+        match CompactTxStreamerClient::get_transaction(self, request).await {
+            Ok(response) => Ok(Some(response.into_inner())),
+            Err(status) if status.code() == Code::NotFound => Ok(None),
+            Err(status) => Err(status),
+        }
+    }
+}
+
+/// A [`Connector`] that connects to a `lightwalletd` server over Tor.
+///
+/// Each connection is made with a new [`Client::isolated_client`] handle.
+#[derive(Clone)]
+pub struct TorConnector {
+    client: Client,
+    endpoint: Uri,
+}
+
+impl TorConnector {
+    /// Constructs a connector to the `lightwalletd` server at the given endpoint.
+    pub fn new(client: Client, endpoint: Uri) -> Self {
+        TorConnector { client, endpoint }
+    }
+}
+
+impl Connector for TorConnector {
+    type Connection = CompactTxStreamerClient<Channel>;
+
+    async fn connect_isolated(&self) -> Result<Self::Connection, Error> {
+        self.client
+            .isolated_client()
+            .connect_to_lightwalletd(self.endpoint.clone())
+            .await
+    }
+}
+
+/// Sends each transaction-related request to a `lightwalletd` server over its own
+/// isolated connection.
+///
+/// This is intended for broadcasting transactions, and for servicing
+/// [`TransactionDataRequest::Enhancement`] requests.
+///
+/// [`TransactionDataRequest::Enhancement`]: crate::data_api::TransactionDataRequest::Enhancement
+pub struct IsolatedRequests<C> {
+    connector: C,
+}
+
+impl<C: Connector> IsolatedRequests<C> {
+    /// Constructs a request sender that opens its connections with the given connector.
+    pub fn new(connector: C) -> Self {
+        IsolatedRequests { connector }
+    }
+
+    /// Submits the given transaction to the server for broadcast to the network.
+    ///
+    /// Returns an error if the server rejects the transaction.
+    pub async fn send_transaction(&self, tx: &Transaction) -> Result<(), Error> {
+        let mut tx_data = vec![];
+        tx.write(&mut tx_data)?;
+        self.send_raw_transaction(tx_data).await
+    }
+
+    /// Submits the given encoded transaction to the server for broadcast to the network.
+    ///
+    /// Returns an error if the server rejects the transaction.
+    pub async fn send_raw_transaction(&self, tx_data: Vec<u8>) -> Result<(), Error> {
+        let mut conn = self.connector.connect_isolated().await?;
+        debug!("Sending transaction over an isolated connection");
+
+        let response = conn
+            .send_transaction(RawTransaction {
+                data: tx_data,
+                height: 0,
+            })
+            .await
+            .map_err(GrpcError::Status)?;
+
+        if response.error_code == 0 {
+            Ok(())
+        } else {
+            Err(GrpcError::TransactionRejected {
+                code: response.error_code,
+                message: response.error_message,
+            }
+            .into())
+        }
+    }
+
+    /// Fetches the transaction with the given ID, or returns `None` if the server does
+    /// not know of it.
+    pub async fn get_transaction(&self, txid: TxId) -> Result<Option<RawTransaction>, Error> {
+        let mut conn = self.connector.connect_isolated().await?;
+        debug!("Fetching transaction over an isolated connection");
+
+        Ok(conn
+            .get_transaction(txid)
+            .await
+            .map_err(GrpcError::Status)?)
+    }
+
+    /// Fetches each of the given transactions over its own isolated connection, with at
+    /// most `max_concurrent_requests` requests in flight at a time.
+    ///
+    /// The results are returned in the order in which the requests complete.
+    pub async fn get_transactions(
+        &self,
+        txids: impl IntoIterator<Item = TxId>,
+        max_concurrent_requests: usize,
+    ) -> Vec<(TxId, Result<Option<RawTransaction>, Error>)> {
+        stream::iter(txids)
+            .map(|txid| async move { (txid, self.get_transaction(txid).await) })
+            .buffer_unordered(max_concurrent_requests.max(1))
+            .collect()
+            .await
+    }
+}
+
+/// A [`ChainDataSource`] that fetches each transaction over its own isolated connection,
+/// and sends all other requests to the wrapped source.
+///
+/// This allows [`sync::run`] to service [`TransactionDataRequest::Enhancement`] requests
+/// without the server being able to link the fetched transactions to one another.
+///
+/// [`ChainDataSource`]: crate::sync::ChainDataSource
+/// [`sync::run`]: crate::sync::run
+/// [`TransactionDataRequest::Enhancement`]: crate::data_api::TransactionDataRequest::Enhancement
+#[cfg(feature = "sync")]
+pub struct IsolatedChainSource<S, C> {
+    source: S,
+    requests: Arc<IsolatedRequests<C>>,
+}
+
+#[cfg(feature = "sync")]
+impl<S, C> IsolatedChainSource<S, C> {
+    /// Constructs a chain data source that sends requests for transactions with
+    /// `requests`, and all other requests to `source`.
+    pub fn new(source: S, requests: IsolatedRequests<C>) -> Self {
+        IsolatedChainSource {
+            source,
+            requests: Arc::new(requests),
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<S: Clone, C> Clone for IsolatedChainSource<S, C> {
+    fn clone(&self) -> Self {
+        IsolatedChainSource {
+            source: self.source.clone(),
+            requests: self.requests.clone(),
+        }
+    }
+}
+
+/// Errors produced by an [`IsolatedChainSource`].
+#[cfg(feature = "sync")]
+#[derive(Debug)]
+pub enum IsolatedSourceError<E> {
+    /// An error produced by the wrapped chain data source.
+    Source(E),
+    /// An error occurred while fetching a transaction over an isolated connection.
+    Isolated(Error),
+}
+
+#[cfg(feature = "sync")]
+impl<E: fmt::Display> fmt::Display for IsolatedSourceError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IsolatedSourceError::Source(e) => write!(f, "{}", e),
+            IsolatedSourceError::Isolated(e) => {
+                write!(
+                    f,
+                    "Error fetching transaction over isolated connection: {}",
+                    e
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<E: std::error::Error + 'static> std::error::Error for IsolatedSourceError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IsolatedSourceError::Source(e) => Some(e),
+            IsolatedSourceError::Isolated(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+#[async_trait]
+impl<S, C> ChainDataSource for IsolatedChainSource<S, C>
+where
+    S: ChainDataSource,
+    S::Error: Send,
+    C: Connector,
+{
+    type Error = IsolatedSourceError<S::Error>;
+
+    async fn get_latest_block(&mut self) -> Result<BlockHeight, Self::Error> {
+        self.source
+            .get_latest_block()
+            .await
+            .map_err(IsolatedSourceError::Source)
+    }
+
+    async fn get_block_range(
+        &mut self,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<CompactBlock>, Self::Error> {
+        self.source
+            .get_block_range(range)
+            .await
+            .map_err(IsolatedSourceError::Source)
+    }
+
+    async fn get_tree_state(&mut self, height: BlockHeight) -> Result<TreeState, Self::Error> {
+        self.source
+            .get_tree_state(height)
+            .await
+            .map_err(IsolatedSourceError::Source)
+    }
+
+    async fn get_subtree_roots(
+        &mut self,
+        protocol: ShieldedProtocol,
+        start_index: u32,
+    ) -> Result<Vec<SubtreeRoot>, Self::Error> {
+        self.source
+            .get_subtree_roots(protocol, start_index)
+            .await
+            .map_err(IsolatedSourceError::Source)
+    }
+
+    async fn get_transaction(&mut self, txid: TxId) -> Result<Option<RawTransaction>, Self::Error> {
+        self.requests
+            .get_transaction(txid)
+            .await
+            .map_err(IsolatedSourceError::Isolated)
+    }
+
+    async fn get_address_utxos(
+        &mut self,
+        addresses: Vec<String>,
+        start_height: BlockHeight,
+    ) -> Result<Vec<GetAddressUtxosReply>, Self::Error> {
+        self.source
+            .get_address_utxos(addresses, start_height)
+            .await
+            .map_err(IsolatedSourceError::Source)
+    }
+
+    async fn get_taddress_transactions(
+        &mut self,
+        address: String,
+        range: Range<BlockHeight>,
+    ) -> Result<Vec<RawTransaction>, Self::Error> {
+        self.source
+            .get_taddress_transactions(address, range)
+            .await
+            .map_err(IsolatedSourceError::Source)
+    }
+
+    async fn get_mempool_stream(
+        &mut self,
+    ) -> Result<BoxStream<'_, Result<RawTransaction, Self::Error>>, Self::Error> {
+        Ok(self
+            .source
+            .get_mempool_stream()
+            .await
+            .map_err(IsolatedSourceError::Source)?
+            .map(|res| res.map_err(IsolatedSourceError::Source))
+            .boxed())
+    }
+}
+
+impl Client {
+    /// Returns a request sender that sends each transaction-related request to the
+    /// `lightwalletd` server at the given endpoint over its own isolated circuit.
+    pub fn isolated_requests(&self, endpoint: Uri) -> IsolatedRequests<TorConnector> {
+        IsolatedRequests::new(TorConnector::new(self.clone(), endpoint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use tokio::runtime::Runtime;
+    use zcash_primitives::transaction::TxId;
+
+    use super::{Connector, IsolatedRequests, TransactionService};
+    use crate::{
+        proto::service::{RawTransaction, SendResponse},
+        tor::{grpc::GrpcError, Error},
+    };
+
+    #[cfg(feature = "sync")]
+    use {
+        super::{IsolatedChainSource, IsolatedSourceError},
+        crate::{
+            proto::service::TreeState,
+            sync::{source::memory::MemoryChainSource, ChainDataSource},
+        },
+    };
+
+    #[derive(Debug, PartialEq)]
+    enum Request {
+        Send(Vec<u8>),
+        Get(TxId),
+    }
+
+    /// A transport that records which connection each request was sent over.
+    #[derive(Default)]
+    struct MockConnector {
+        connections: AtomicUsize,
+        requests: Arc<Mutex<Vec<(usize, Request)>>>,
+    }
+
+    struct MockConnection {
+        id: usize,
+        requests: Arc<Mutex<Vec<(usize, Request)>>>,
+    }
+
+    impl Connector for MockConnector {
+        type Connection = MockConnection;
+
+        async fn connect_isolated(&self) -> Result<Self::Connection, Error> {
+            Ok(MockConnection {
+                id: self.connections.fetch_add(1, Ordering::SeqCst),
+                requests: self.requests.clone(),
+            })
+        }
+    }
+
+    impl TransactionService for MockConnection {
+        async fn send_transaction(
+            &mut self,
+            tx: RawTransaction,
+        ) -> Result<SendResponse, tonic::Status> {
+            let rejected = tx.data.is_empty();
+            self.requests
+                .lock()
+                .unwrap()
+                .push((self.id, Request::Send(tx.data)));
+            Ok(if rejected {
+                SendResponse {
+                    error_code: -22,
+                    error_message: "empty transaction".into(),
+                }
+            } else {
+                SendResponse::default()
+            })
+        }
+
+        async fn get_transaction(
+            &mut self,
+            txid: TxId,
+        ) -> Result<Option<RawTransaction>, tonic::Status> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((self.id, Request::Get(txid)));
+            Ok((txid.as_ref()[0] != 0).then(|| RawTransaction {
+                data: txid.as_ref().to_vec(),
+                height: 1,
+            }))
+        }
+    }
+
+    #[test]
+    fn each_request_uses_its_own_connection() {
+        let rt = Runtime::new().unwrap();
+        let requests = IsolatedRequests::new(MockConnector::default());
+
+        let txids = (0..4)
+            .map(|i| TxId::from_bytes([i; 32]))
+            .collect::<Vec<_>>();
+
+        rt.block_on(async {
+            requests.send_raw_transaction(vec![1, 2, 3]).await.unwrap();
+            requests.send_raw_transaction(vec![4, 5, 6]).await.unwrap();
+
+            // Unknown transactions are not an error.
+            assert!(requests.get_transaction(txids[0]).await.unwrap().is_none());
+
+            let fetched = requests.get_transactions(txids[1..].to_vec(), 2).await;
+            assert_eq!(fetched.len(), 3);
+            for (txid, result) in fetched {
+                assert_eq!(result.unwrap().unwrap().data, txid.as_ref().to_vec());
+            }
+        });
+
+        let log = requests.connector.requests.lock().unwrap();
+        assert_eq!(log.len(), 6);
+        assert_eq!(log[0].1, Request::Send(vec![1, 2, 3]));
+        assert_eq!(log[1].1, Request::Send(vec![4, 5, 6]));
+        assert_eq!(log[2].1, Request::Get(txids[0]));
+        assert_eq!(requests.connector.connections.load(Ordering::SeqCst), 6);
+
+        // No two requests were sent over the same connection.
+        let ids = log.iter().map(|(id, _)| *id).collect::<HashSet<_>>();
+        assert_eq!(ids.len(), log.len());
+    }
+
+    #[test]
+    fn rejected_transaction() {
+        let rt = Runtime::new().unwrap();
+        let requests = IsolatedRequests::new(MockConnector::default());
+
+        let res = rt.block_on(requests.send_raw_transaction(vec![]));
+        assert!(matches!(
+            res,
+            Err(Error::Grpc(GrpcError::TransactionRejected {
+                code: -22,
+                ..
+            }))
+        ));
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn chain_source_fetches_transactions_in_isolation() {
+        let rt = Runtime::new().unwrap();
+        let txid = TxId::from_bytes([1; 32]);
+
+        let mut inner = MemoryChainSource::new();
+        inner.insert_transaction(
+            txid,
+            RawTransaction {
+                data: vec![9],
+                height: 5,
+            },
+        );
+        inner.insert_tree_state(TreeState {
+            height: 7,
+            ..Default::default()
+        });
+        let mut source =
+            IsolatedChainSource::new(inner, IsolatedRequests::new(MockConnector::default()));
+
+        rt.block_on(async {
+            // Transactions are fetched over isolated connections, rather than from the
+            // wrapped source.
+            let tx = source.get_transaction(txid).await.unwrap().unwrap();
+            assert_eq!(tx.data, txid.as_ref().to_vec());
+            assert!(source
+                .get_transaction(TxId::from_bytes([0; 32]))
+                .await
+                .unwrap()
+                .is_none());
+
+            // All other requests are sent to the wrapped source.
+            assert_eq!(source.get_tree_state(7.into()).await.unwrap().height, 7);
+            assert!(matches!(
+                source.get_latest_block().await,
+                Err(IsolatedSourceError::Source(_))
+            ));
+        });
+
+        assert_eq!(
+            source.requests.connector.connections.load(Ordering::SeqCst),
+            2
+        );
+    }
+}
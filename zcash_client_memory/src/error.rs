@@ -0,0 +1,261 @@
+//! Error types for problems that may arise when reading or storing wallet data in memory.
+
+use std::convert::Infallible;
+use std::error;
+use std::fmt;
+
+use shardtree::error::ShardTreeError;
+use zcash_keys::keys::AddressGenerationError;
+use zcash_primitives::zip32;
+use zcash_primitives::{
+    consensus::BlockHeight,
+    transaction::{components::amount::BalanceError, TxId},
+};
+use zcash_protocol::PoolType;
+
+use crate::AccountId;
+
+#[cfg(feature = "transparent-inputs")]
+use zcash_primitives::legacy::TransparentAddress;
+
+/// The primary error type for the in-memory wallet backend.
+#[derive(Debug)]
+pub enum Error {
+    /// Stored wallet data is internally inconsistent, or a snapshot could not be decoded.
+    CorruptedData(String),
+
+    /// An error occurred reading or writing a wallet snapshot.
+    Io(std::io::Error),
+
+    /// A received memo cannot be interpreted as a UTF-8 string.
+    InvalidMemo(zcash_primitives::memo::Error),
+
+    /// An error produced in legacy transparent address derivation
+    #[cfg(feature = "transparent-inputs")]
+    TransparentDerivation(bip32::Error),
+
+    /// An attempt to update block data would overwrite the current hash for a block with a
+    /// different hash. This indicates that a required rewind was not performed.
+    BlockConflict(BlockHeight),
+
+    /// A range of blocks provided to the wallet as a unit was non-sequential
+    NonSequentialBlocks,
+
+    /// A transaction locator being added to the nullifier map conflicts with an existing
+    /// one. This indicates that a reorg has occurred and that a rewind is required.
+    NullifierMapConflict(BlockHeight, u16),
+
+    /// A requested rewind would violate invariants of the wallet. The payload returned with
+    /// this error is (safe rewind height, requested height). If no safe rewind height can be
+    /// determined, the safe rewind height member will be `None`.
+    RequestedRewindInvalid {
+        safe_rewind_height: Option<BlockHeight>,
+        requested_height: BlockHeight,
+    },
+
+    /// An error occurred in generating a Zcash address.
+    AddressGeneration(AddressGenerationError),
+
+    /// The account for which information was requested does not belong to the wallet.
+    AccountUnknown,
+
+    /// The account being added collides with an existing account in the wallet with the given ID.
+    /// The collision can be on the seed and ZIP-32 account index, or a shared FVK component.
+    AccountCollision(AccountId),
+
+    /// The account was imported, and ZIP-32 derivation information is not known for it.
+    UnknownZip32Derivation,
+
+    /// The ZIP-32 derivation provided for a derived account does not match the derivation
+    /// of the account.
+    Zip32DerivationMismatch,
+
+    /// An error occurred deriving a spending key from a seed and a ZIP-32 account index.
+    KeyDerivationError(zip32::AccountId),
+
+    /// An error occurred while processing an account due to a failure in deriving the account's keys.
+    BadAccountData(String),
+
+    /// A caller attempted to construct a new account with an invalid account identifier.
+    AccountIdOutOfRange,
+
+    /// The address associated with a record being inserted was not recognized as
+    /// belonging to the wallet.
+    #[cfg(feature = "transparent-inputs")]
+    AddressNotRecognized(TransparentAddress),
+
+    /// An error occurred in inserting data into or accessing data from one of the wallet's note
+    /// commitment trees.
+    CommitmentTree(ShardTreeError<Infallible>),
+
+    /// The height of the chain was not available; a call to [`WalletWrite::update_chain_tip`] is
+    /// required before the requested operation can succeed.
+    ///
+    /// [`WalletWrite::update_chain_tip`]:
+    /// zcash_client_backend::data_api::WalletWrite::update_chain_tip
+    ChainHeightUnknown,
+
+    /// An error occurred in computing wallet balance
+    BalanceError(BalanceError),
+
+    /// The proposal cannot be constructed until transactions with previously reserved
+    /// ephemeral address outputs have been mined. The parameters are the account id and
+    /// the index that could not safely be reserved.
+    #[cfg(feature = "transparent-inputs")]
+    ReachedGapLimit(AccountId, u32),
+
+    /// An ephemeral address would be reused. The parameters are the address in string
+    /// form, and the txid of the earliest transaction in which it is known to have been
+    /// used.
+    #[cfg(feature = "transparent-inputs")]
+    EphemeralAddressReuse(String, TxId),
+
+    /// The transaction with the given ID is not known to the wallet.
+    TransactionUnknown(TxId),
+
+    /// The wallet was asked to operate on a value pool that it was not compiled to support.
+    UnsupportedPoolType(PoolType),
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self {
+            Error::Io(e) => Some(e),
+            Error::InvalidMemo(e) => Some(e),
+            Error::BalanceError(e) => Some(e),
+            Error::AddressGeneration(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            Error::CorruptedData(reason) => write!(f, "Wallet data is corrupted: {}", reason),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::InvalidMemo(e) => write!(f, "{}", e),
+            #[cfg(feature = "transparent-inputs")]
+            Error::TransparentDerivation(e) => write!(f, "{:?}", e),
+            Error::BlockConflict(h) => write!(
+                f,
+                "A block hash conflict occurred at height {}; rewind required.",
+                u32::from(*h)
+            ),
+            Error::NonSequentialBlocks => write!(
+                f,
+                "`put_blocks` requires that the provided block range be sequential"
+            ),
+            Error::NullifierMapConflict(h, tx_index) => write!(
+                f,
+                "The transaction at index {} in block {} conflicts with an existing entry in the nullifier map; rewind required.",
+                tx_index, h
+            ),
+            Error::RequestedRewindInvalid {
+                safe_rewind_height,
+                requested_height,
+            } => write!(
+                f,
+                "A rewind for your wallet may only target height {} or greater; the requested height was {}.",
+                safe_rewind_height.map_or("<unavailable>".to_owned(), |h0| format!("{}", h0)),
+                requested_height
+            ),
+            Error::AddressGeneration(e) => write!(f, "{}", e),
+            Error::AccountUnknown => write!(
+                f,
+                "The account with the given ID does not belong to this wallet."
+            ),
+            Error::AccountCollision(id) => write!(
+                f,
+                "An account corresponding to the data provided already exists in the wallet with internal identifier {}.",
+                id.0
+            ),
+            Error::UnknownZip32Derivation => write!(
+                f,
+                "ZIP-32 derivation information is not known for this account."
+            ),
+            Error::Zip32DerivationMismatch => write!(
+                f,
+                "The ZIP-32 derivation provided does not match the derivation of the account."
+            ),
+            Error::KeyDerivationError(acct_id) => write!(
+                f,
+                "Key derivation failed for account {}",
+                u32::from(*acct_id)
+            ),
+            Error::BadAccountData(e) => write!(f, "Failed to add account: {}", e),
+            Error::AccountIdOutOfRange => write!(
+                f,
+                "Wallet account identifiers must be less than 0x7FFFFFFF."
+            ),
+            #[cfg(feature = "transparent-inputs")]
+            Error::AddressNotRecognized(_) => write!(
+                f,
+                "The address associated with a received txo is not identifiable as belonging to the wallet."
+            ),
+            Error::CommitmentTree(err) => write!(
+                f,
+                "An error occurred accessing or updating note commitment tree data: {}.",
+                err
+            ),
+            Error::ChainHeightUnknown => write!(
+                f,
+                "Chain height unknown; please call `update_chain_tip`"
+            ),
+            Error::BalanceError(e) => write!(f, "Balance error: {}", e),
+            #[cfg(feature = "transparent-inputs")]
+            Error::ReachedGapLimit(account_id, bad_index) => write!(
+                f,
+                "The proposal cannot be constructed until transactions with previously reserved ephemeral address outputs have been mined. \
+                 The ephemeral address in account {account_id:?} at index {bad_index} could not be safely reserved.",
+            ),
+            #[cfg(feature = "transparent-inputs")]
+            Error::EphemeralAddressReuse(address_str, txid) => write!(
+                f,
+                "The ephemeral address {address_str} previously used in txid {txid} would be reused."
+            ),
+            Error::TransactionUnknown(txid) => write!(
+                f,
+                "The transaction {txid} is not known to the wallet."
+            ),
+            Error::UnsupportedPoolType(t) => write!(f, "Pool type is not currently supported: {}", t),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "transparent-inputs")]
+impl From<bip32::Error> for Error {
+    fn from(e: bip32::Error) -> Self {
+        Error::TransparentDerivation(e)
+    }
+}
+
+impl From<zcash_primitives::memo::Error> for Error {
+    fn from(e: zcash_primitives::memo::Error) -> Self {
+        Error::InvalidMemo(e)
+    }
+}
+
+impl From<ShardTreeError<Infallible>> for Error {
+    fn from(e: ShardTreeError<Infallible>) -> Self {
+        Error::CommitmentTree(e)
+    }
+}
+
+impl From<BalanceError> for Error {
+    fn from(e: BalanceError) -> Self {
+        Error::BalanceError(e)
+    }
+}
+
+impl From<AddressGenerationError> for Error {
+    fn from(e: AddressGenerationError) -> Self {
+        Error::AddressGeneration(e)
+    }
+}
@@ -0,0 +1,1554 @@
+//! *An in-memory Zcash light client wallet backend.*
+//!
+//! `zcash_client_memory` contains an implementation of the [`WalletRead`], [`WalletWrite`],
+//! [`InputSource`] and [`WalletCommitmentTrees`] traits from the [`zcash_client_backend`]
+//! crate that holds all wallet state in memory. It is intended for use in contexts where a
+//! persistent database is unavailable or undesirable, such as in tests, in browsers, or in
+//! short-lived processes.
+//!
+//! # Design
+//!
+//! The entire state of the wallet is held by a [`MemoryWalletDb`] value. Operations that
+//! modify the wallet are applied atomically: if an operation fails part of the way through,
+//! the wallet is restored to the state it was in before the operation began.
+//!
+//! The state of the wallet can be serialized to a byte vector using
+//! [`MemoryWalletDb::to_bytes`], and restored using [`MemoryWalletDb::from_bytes`]. The
+//! snapshot format is versioned, and is not intended to be compatible with any other wallet
+//! backend.
+//!
+//! ## Feature flags
+#![doc = document_features::document_features!()]
+//!
+//! [`WalletRead`]: zcash_client_backend::data_api::WalletRead
+//! [`WalletWrite`]: zcash_client_backend::data_api::WalletWrite
+//! [`InputSource`]: zcash_client_backend::data_api::InputSource
+//! [`WalletCommitmentTrees`]: zcash_client_backend::data_api::WalletCommitmentTrees
+
+#![cfg_attr(docsrs, feature(doc_cfg))]
+#![cfg_attr(docsrs, feature(doc_auto_cfg))]
+// Catch documentation errors caused by code changes.
+#![deny(rustdoc::broken_intra_doc_links)]
+
+use incrementalmerkletree::{Marking, Position, Retention};
+use nonempty::NonEmpty;
+use secrecy::{ExposeSecret, SecretVec};
+use shardtree::{error::ShardTreeError, ShardTree};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::Infallible,
+    fmt,
+    num::NonZeroU32,
+    ops::Range,
+};
+use subtle::ConditionallySelectable;
+use tracing::{debug, trace};
+
+use zcash_client_backend::{
+    address::UnifiedAddress,
+    data_api::{
+        self,
+        chain::{ChainState, CommitmentTreeRoot},
+        scanning::ScanRange,
+        Account as _, AccountBirthday, AccountMeta, AccountPurpose, AccountSource, BlockMetadata,
+        DecryptedTransaction, InputSource, NoteFilter, NullifierQuery, ScannedBlock, SeedRelevance,
+        SentTransaction, SpendableNotes, TransactionDataRequest, TransactionHistoryEntry,
+        TransactionHistoryFilter, WalletCommitmentTrees, WalletRead, WalletSummary, WalletWrite,
+        Zip32Derivation, SAPLING_SHARD_HEIGHT,
+    },
+    keys::{
+        AddressGenerationError, UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey,
+    },
+    wallet::{Note, NoteId, ReceivedNote, WalletTransparentOutput},
+    ShieldedProtocol,
+};
+use zcash_primitives::{
+    block::BlockHash,
+    consensus::{self, BlockHeight},
+    memo::Memo,
+    transaction::{components::amount::NonNegativeAmount, Transaction, TxId},
+    zip32::{self, DiversifierIndex},
+};
+use zcash_protocol::PoolType;
+use zip32::fingerprint::SeedFingerprint;
+
+use crate::{
+    error::Error,
+    wallet::{
+        commitment_tree::{put_shard_roots, MemoryShardStore},
+        Account, BlockRecord, ReceivedNoteRecord, SentOutputRecord, TransactionRecord, TxQueryType,
+    },
+};
+
+#[cfg(feature = "orchard")]
+use {
+    incrementalmerkletree::frontier::Frontier,
+    shardtree::store::{Checkpoint, ShardStore},
+    zcash_client_backend::data_api::ORCHARD_SHARD_HEIGHT,
+};
+
+#[cfg(feature = "transparent-inputs")]
+use {
+    crate::wallet::transparent::{EphemeralAddressRecord, TransparentOutputRecord},
+    zcash_client_backend::wallet::TransparentAddressMetadata,
+    zcash_primitives::{legacy::TransparentAddress, transaction::components::OutPoint},
+};
+
+#[cfg(any(test, feature = "test-dependencies"))]
+use {
+    zcash_client_backend::data_api::{testing::TransactionSummary, OutputOfSentTx, WalletTest},
+    zcash_keys::address::Address,
+};
+
+pub mod error;
+mod serialization;
+pub mod wallet;
+
+#[cfg(test)]
+mod testing;
+
+/// The maximum number of blocks the wallet is allowed to rewind. This is
+/// consistent with the bound in zcashd, and allows block data deeper than
+/// this delta from the chain tip to be pruned.
+pub(crate) const PRUNING_DEPTH: u32 = 100;
+
+/// The number of blocks to verify ahead when the chain tip is updated.
+pub(crate) const VERIFY_LOOKAHEAD: u32 = 10;
+
+#[cfg(not(feature = "orchard"))]
+pub(crate) const UA_ORCHARD: bool = false;
+#[cfg(feature = "orchard")]
+pub(crate) const UA_ORCHARD: bool = true;
+
+#[cfg(not(feature = "transparent-inputs"))]
+pub(crate) const UA_TRANSPARENT: bool = false;
+#[cfg(feature = "transparent-inputs")]
+pub(crate) const UA_TRANSPARENT: bool = true;
+
+pub(crate) const DEFAULT_UA_REQUEST: UnifiedAddressRequest =
+    UnifiedAddressRequest::unsafe_new(UA_ORCHARD, true, UA_TRANSPARENT);
+
+/// The ID type for accounts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct AccountId(u32);
+
+impl AccountId {
+    /// Constructs an `AccountId` from a bare `u32` value. The resulting identifier is not
+    /// guaranteed to correspond to any account stored in the wallet.
+    pub fn from_u32(value: u32) -> Self {
+        AccountId(value)
+    }
+
+    /// Unwraps the raw identifier value from its typesafe wrapper.
+    ///
+    /// Note that account identifiers are not guaranteed to be stable; if a wallet is restored from
+    /// seed, the account identifiers of the restored wallet are not likely to correspond to the
+    /// identifiers for the same accounts in another wallet created or restored from the same seed.
+    /// These unwrapped identifier values should therefore be treated as ephemeral.
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+impl ConditionallySelectable for AccountId {
+    fn conditional_select(a: &Self, b: &Self, choice: subtle::Choice) -> Self {
+        AccountId(ConditionallySelectable::conditional_select(
+            &a.0, &b.0, choice,
+        ))
+    }
+}
+
+/// An opaque type for received note identifiers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ReceivedNoteId(pub(crate) ShieldedProtocol, pub(crate) u64);
+
+impl fmt::Display for ReceivedNoteId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReceivedNoteId(protocol, id) => write!(f, "Received {:?} Note: {}", protocol, id),
+        }
+    }
+}
+
+/// A reference to a transparent output received by the wallet.
+#[cfg(feature = "transparent-inputs")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtxoId(pub OutPoint);
+
+/// A reference to a transparent output received by the wallet.
+///
+/// Transparent outputs cannot be received by the wallet unless the `transparent-inputs`
+/// feature is enabled, so this type is uninhabited.
+#[cfg(not(feature = "transparent-inputs"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UtxoId {}
+
+/// An in-memory wallet.
+///
+/// All wallet state is held in memory; use [`MemoryWalletDb::to_bytes`] and
+/// [`MemoryWalletDb::from_bytes`] to persist and restore it.
+#[derive(Clone)]
+pub struct MemoryWalletDb<P> {
+    pub(crate) params: P,
+    pub(crate) accounts: BTreeMap<AccountId, Account>,
+    /// The addresses generated for each account, keyed by diversifier index.
+    pub(crate) addresses: BTreeMap<AccountId, BTreeMap<u128, UnifiedAddress>>,
+    pub(crate) blocks: BTreeMap<BlockHeight, BlockRecord>,
+    pub(crate) transactions: BTreeMap<TxId, TransactionRecord>,
+    pub(crate) received_notes: BTreeMap<u64, ReceivedNoteRecord>,
+    /// Pairs of (received note id, spending transaction).
+    pub(crate) received_note_spends: BTreeSet<(u64, TxId)>,
+    /// Sent outputs, keyed by (transaction, output pool, output index).
+    pub(crate) sent_outputs: BTreeMap<(TxId, PoolType, u32), SentOutputRecord>,
+    pub(crate) scan_queue: Vec<ScanRange>,
+    /// Transaction locators for the nullifier map, keyed by (block height, transaction index).
+    pub(crate) tx_locators: BTreeMap<(BlockHeight, u16), TxId>,
+    /// The nullifier map, from (protocol, nullifier) to transaction locator.
+    pub(crate) nullifiers: BTreeMap<(ShieldedProtocol, [u8; 32]), (BlockHeight, u16)>,
+    /// Transactions for which additional data must be requested, along with the
+    /// transaction (if any) that depends upon them.
+    pub(crate) tx_retrieval_queue: BTreeMap<TxId, (TxQueryType, Option<TxId>)>,
+    pub(crate) sapling_tree: MemoryShardStore<sapling::Node, BlockHeight>,
+    #[cfg(feature = "orchard")]
+    pub(crate) orchard_tree: MemoryShardStore<orchard::tree::MerkleHashOrchard, BlockHeight>,
+    #[cfg(feature = "transparent-inputs")]
+    pub(crate) transparent_outputs: BTreeMap<OutPoint, TransparentOutputRecord>,
+    /// Pairs of (received transparent output, spending transaction).
+    #[cfg(feature = "transparent-inputs")]
+    pub(crate) transparent_output_spends: BTreeSet<(OutPoint, TxId)>,
+    /// Spends of transparent outputs that were detected before the outputs themselves.
+    #[cfg(feature = "transparent-inputs")]
+    pub(crate) transparent_spend_map: BTreeSet<(TxId, OutPoint)>,
+    /// Outputs whose spends must be searched for, along with their receiving addresses.
+    #[cfg(feature = "transparent-inputs")]
+    pub(crate) transparent_spend_search_queue: BTreeMap<OutPoint, TransparentAddress>,
+    #[cfg(feature = "transparent-inputs")]
+    pub(crate) ephemeral_addresses: BTreeMap<(AccountId, u32), EphemeralAddressRecord>,
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Constructs a new empty wallet for the given network.
+    pub fn new(params: P) -> Self {
+        MemoryWalletDb {
+            params,
+            accounts: BTreeMap::new(),
+            addresses: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            transactions: BTreeMap::new(),
+            received_notes: BTreeMap::new(),
+            received_note_spends: BTreeSet::new(),
+            sent_outputs: BTreeMap::new(),
+            scan_queue: vec![],
+            tx_locators: BTreeMap::new(),
+            nullifiers: BTreeMap::new(),
+            tx_retrieval_queue: BTreeMap::new(),
+            sapling_tree: MemoryShardStore::empty(),
+            #[cfg(feature = "orchard")]
+            orchard_tree: MemoryShardStore::empty(),
+            #[cfg(feature = "transparent-inputs")]
+            transparent_outputs: BTreeMap::new(),
+            #[cfg(feature = "transparent-inputs")]
+            transparent_output_spends: BTreeSet::new(),
+            #[cfg(feature = "transparent-inputs")]
+            transparent_spend_map: BTreeSet::new(),
+            #[cfg(feature = "transparent-inputs")]
+            transparent_spend_search_queue: BTreeMap::new(),
+            #[cfg(feature = "transparent-inputs")]
+            ephemeral_addresses: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the network parameters of the wallet.
+    pub fn params(&self) -> &P {
+        &self.params
+    }
+}
+
+impl<P: consensus::Parameters + Clone> MemoryWalletDb<P> {
+    /// Applies the given operation to the wallet. If the operation returns an error, any
+    /// changes it made to the wallet are discarded.
+    pub fn transactionally<F, A, E>(&mut self, f: F) -> Result<A, E>
+    where
+        F: FnOnce(&mut Self) -> Result<A, E>,
+    {
+        let backup = self.clone();
+        let result = f(self);
+        if result.is_err() {
+            *self = backup;
+        }
+        result
+    }
+}
+
+impl<P: consensus::Parameters> InputSource for MemoryWalletDb<P> {
+    type Error = Error;
+    type NoteRef = ReceivedNoteId;
+    type AccountId = AccountId;
+
+    fn get_spendable_note(
+        &self,
+        txid: &TxId,
+        protocol: ShieldedProtocol,
+        index: u32,
+    ) -> Result<Option<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
+        #[cfg(not(feature = "orchard"))]
+        if protocol == ShieldedProtocol::Orchard {
+            return Err(Error::UnsupportedPoolType(PoolType::ORCHARD));
+        }
+
+        self.get_spendable_note_inner(txid, protocol, index)
+    }
+
+    fn select_spendable_notes(
+        &self,
+        account: AccountId,
+        target_value: NonNegativeAmount,
+        sources: &[ShieldedProtocol],
+        anchor_height: BlockHeight,
+        exclude: &[Self::NoteRef],
+    ) -> Result<SpendableNotes<Self::NoteRef>, Self::Error> {
+        let select = |protocol| {
+            if sources.contains(&protocol) {
+                self.select_spendable_notes_inner(
+                    protocol,
+                    account,
+                    target_value,
+                    anchor_height,
+                    exclude,
+                )
+            } else {
+                Ok(vec![])
+            }
+        };
+
+        Ok(SpendableNotes::new(
+            select(ShieldedProtocol::Sapling)?
+                .into_iter()
+                .map(|n| {
+                    n.map_note(|note| match note {
+                        Note::Sapling(n) => n,
+                        #[cfg(feature = "orchard")]
+                        Note::Orchard(_) => unreachable!("selected notes are Sapling notes"),
+                    })
+                })
+                .collect(),
+            #[cfg(feature = "orchard")]
+            select(ShieldedProtocol::Orchard)?
+                .into_iter()
+                .map(|n| {
+                    n.map_note(|note| match note {
+                        Note::Orchard(n) => n,
+                        Note::Sapling(_) => unreachable!("selected notes are Orchard notes"),
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_unspent_transparent_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<WalletTransparentOutput>, Self::Error> {
+        self.get_wallet_transparent_output(outpoint, false)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_spendable_transparent_outputs(
+        &self,
+        address: &TransparentAddress,
+        target_height: BlockHeight,
+        min_confirmations: u32,
+    ) -> Result<Vec<WalletTransparentOutput>, Self::Error> {
+        self.get_spendable_transparent_outputs_inner(address, target_height, min_confirmations)
+    }
+
+    /// Returns metadata for the spendable notes in the wallet.
+    fn get_account_metadata(
+        &self,
+        account_id: Self::AccountId,
+        selector: &NoteFilter,
+        exclude: &[Self::NoteRef],
+    ) -> Result<AccountMeta, Self::Error> {
+        let chain_tip_height = self.chain_tip_height().ok_or(Error::ChainHeightUnknown)?;
+
+        let sapling_pool_meta = self.spendable_notes_meta(
+            ShieldedProtocol::Sapling,
+            chain_tip_height,
+            account_id,
+            selector,
+            exclude,
+        )?;
+
+        #[cfg(feature = "orchard")]
+        let orchard_pool_meta = self.spendable_notes_meta(
+            ShieldedProtocol::Orchard,
+            chain_tip_height,
+            account_id,
+            selector,
+            exclude,
+        )?;
+        #[cfg(not(feature = "orchard"))]
+        let orchard_pool_meta = None;
+
+        Ok(AccountMeta::new(sapling_pool_meta, orchard_pool_meta))
+    }
+}
+
+impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
+    type Error = Error;
+    type AccountId = AccountId;
+    type Account = Account;
+
+    fn get_account_ids(&self) -> Result<Vec<AccountId>, Self::Error> {
+        Ok(self.accounts.keys().copied().collect())
+    }
+
+    fn get_account(
+        &self,
+        account_id: Self::AccountId,
+    ) -> Result<Option<Self::Account>, Self::Error> {
+        Ok(self.accounts.get(&account_id).cloned())
+    }
+
+    fn get_derived_account(
+        &self,
+        seed: &SeedFingerprint,
+        account_id: zip32::AccountId,
+    ) -> Result<Option<Self::Account>, Self::Error> {
+        Ok(self.get_derived_account_inner(seed, account_id))
+    }
+
+    fn validate_seed(
+        &self,
+        account_id: Self::AccountId,
+        seed: &SecretVec<u8>,
+    ) -> Result<bool, Self::Error> {
+        if let Some(account) = self.accounts.get(&account_id) {
+            if let AccountSource::Derived {
+                seed_fingerprint,
+                account_index,
+            } = account.source()
+            {
+                wallet::seed_matches_derived_account(
+                    &self.params,
+                    seed,
+                    &seed_fingerprint,
+                    account_index,
+                    &account.uivk(),
+                )
+            } else {
+                Err(Error::UnknownZip32Derivation)
+            }
+        } else {
+            // Missing account is documented to return false.
+            Ok(false)
+        }
+    }
+
+    fn seed_relevance_to_derived_accounts(
+        &self,
+        seed: &SecretVec<u8>,
+    ) -> Result<SeedRelevance<Self::AccountId>, Self::Error> {
+        let mut has_derived = false;
+        let mut relevant_account_ids = vec![];
+
+        for (account_id, account) in &self.accounts {
+            // If the account is imported, the seed _might_ be relevant, but the only
+            // way we could determine that is by brute-forcing the ZIP 32 account
+            // index space, which we're not going to do. The method name indicates to
+            // the caller that we only check derived accounts.
+            if let AccountSource::Derived {
+                seed_fingerprint,
+                account_index,
+            } = account.source()
+            {
+                has_derived = true;
+
+                if wallet::seed_matches_derived_account(
+                    &self.params,
+                    seed,
+                    &seed_fingerprint,
+                    account_index,
+                    &account.uivk(),
+                )? {
+                    // The seed is relevant to this account.
+                    relevant_account_ids.push(*account_id);
+                }
+            }
+        }
+
+        Ok(
+            if let Some(account_ids) = NonEmpty::from_vec(relevant_account_ids) {
+                SeedRelevance::Relevant { account_ids }
+            } else if has_derived {
+                SeedRelevance::NotRelevant
+            } else if !self.accounts.is_empty() {
+                SeedRelevance::NoDerivedAccounts
+            } else {
+                SeedRelevance::NoAccounts
+            },
+        )
+    }
+
+    fn get_account_for_ufvk(
+        &self,
+        ufvk: &UnifiedFullViewingKey,
+    ) -> Result<Option<Self::Account>, Self::Error> {
+        self.get_account_for_ufvk_inner(ufvk)
+    }
+
+    fn get_current_address(
+        &self,
+        account: AccountId,
+    ) -> Result<Option<UnifiedAddress>, Self::Error> {
+        Ok(self
+            .get_current_address_inner(account)
+            .map(|(addr, _)| addr))
+    }
+
+    fn get_account_birthday(&self, account: AccountId) -> Result<BlockHeight, Self::Error> {
+        self.accounts
+            .get(&account)
+            .map(|a| a.birthday_height)
+            .ok_or(Error::AccountUnknown)
+    }
+
+    fn get_wallet_birthday(&self) -> Result<Option<BlockHeight>, Self::Error> {
+        Ok(self.wallet_birthday())
+    }
+
+    fn get_wallet_summary(
+        &self,
+        min_confirmations: u32,
+    ) -> Result<Option<WalletSummary<Self::AccountId>>, Self::Error> {
+        self.get_wallet_summary_inner(min_confirmations)
+    }
+
+    fn chain_height(&self) -> Result<Option<BlockHeight>, Self::Error> {
+        Ok(self.chain_tip_height())
+    }
+
+    fn get_block_hash(&self, block_height: BlockHeight) -> Result<Option<BlockHash>, Self::Error> {
+        Ok(self.blocks.get(&block_height).map(|b| b.hash))
+    }
+
+    fn block_metadata(&self, height: BlockHeight) -> Result<Option<BlockMetadata>, Self::Error> {
+        Ok(self.block_metadata_inner(height))
+    }
+
+    fn block_fully_scanned(&self) -> Result<Option<BlockMetadata>, Self::Error> {
+        Ok(self.block_fully_scanned_inner())
+    }
+
+    fn get_max_height_hash(&self) -> Result<Option<(BlockHeight, BlockHash)>, Self::Error> {
+        Ok(self.blocks.iter().next_back().map(|(h, b)| (*h, b.hash)))
+    }
+
+    fn block_max_scanned(&self) -> Result<Option<BlockMetadata>, Self::Error> {
+        Ok(self
+            .blocks
+            .keys()
+            .next_back()
+            .and_then(|h| self.block_metadata_inner(*h)))
+    }
+
+    fn suggest_scan_ranges(&self) -> Result<Vec<ScanRange>, Self::Error> {
+        Ok(self.suggest_scan_ranges_inner(data_api::scanning::ScanPriority::Historic))
+    }
+
+    fn get_target_and_anchor_heights(
+        &self,
+        min_confirmations: NonZeroU32,
+    ) -> Result<Option<(BlockHeight, BlockHeight)>, Self::Error> {
+        Ok(self.get_target_and_anchor_heights_inner(min_confirmations))
+    }
+
+    fn get_tx_height(&self, txid: TxId) -> Result<Option<BlockHeight>, Self::Error> {
+        Ok(self.transactions.get(&txid).and_then(|tx| tx.block))
+    }
+
+    fn get_unified_full_viewing_keys(
+        &self,
+    ) -> Result<HashMap<AccountId, UnifiedFullViewingKey>, Self::Error> {
+        Ok(self
+            .accounts
+            .iter()
+            .map(|(id, account)| (*id, account.ufvk.clone()))
+            .collect())
+    }
+
+    fn get_memo(&self, note_id: NoteId) -> Result<Option<Memo>, Self::Error> {
+        let sent_memo = self.get_sent_memo(note_id)?;
+        if sent_memo.is_some() {
+            Ok(sent_memo)
+        } else {
+            self.get_received_memo(note_id)
+        }
+    }
+
+    fn get_transaction(&self, txid: TxId) -> Result<Option<Transaction>, Self::Error> {
+        self.get_transaction_inner(txid)
+    }
+
+    fn get_sapling_nullifiers(
+        &self,
+        query: NullifierQuery,
+    ) -> Result<Vec<(AccountId, sapling::Nullifier)>, Self::Error> {
+        Ok(self
+            .get_nullifiers(ShieldedProtocol::Sapling, query)
+            .into_iter()
+            .map(|(account_id, nf)| (account_id, sapling::Nullifier(nf)))
+            .collect())
+    }
+
+    #[cfg(feature = "orchard")]
+    fn get_orchard_nullifiers(
+        &self,
+        query: NullifierQuery,
+    ) -> Result<Vec<(AccountId, orchard::note::Nullifier)>, Self::Error> {
+        self.get_nullifiers(ShieldedProtocol::Orchard, query)
+            .into_iter()
+            .map(|(account_id, nf)| {
+                Option::from(orchard::note::Nullifier::from_bytes(&nf))
+                    .map(|nf| (account_id, nf))
+                    .ok_or_else(|| Error::CorruptedData("Invalid Orchard nullifier".to_owned()))
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_transparent_receivers(
+        &self,
+        account: AccountId,
+    ) -> Result<HashMap<TransparentAddress, Option<TransparentAddressMetadata>>, Self::Error> {
+        self.get_transparent_receivers_inner(account)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_transparent_balances(
+        &self,
+        account: AccountId,
+        max_height: BlockHeight,
+    ) -> Result<HashMap<TransparentAddress, NonNegativeAmount>, Self::Error> {
+        self.get_transparent_balances_inner(account, max_height)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_transparent_address_metadata(
+        &self,
+        account: Self::AccountId,
+        address: &TransparentAddress,
+    ) -> Result<Option<TransparentAddressMetadata>, Self::Error> {
+        self.get_transparent_address_metadata_inner(account, address)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_known_ephemeral_addresses(
+        &self,
+        account: Self::AccountId,
+        index_range: Option<Range<u32>>,
+    ) -> Result<Vec<(TransparentAddress, TransparentAddressMetadata)>, Self::Error> {
+        Ok(self.get_known_ephemeral_addresses_inner(account, index_range))
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn find_account_for_ephemeral_address(
+        &self,
+        address: &TransparentAddress,
+    ) -> Result<Option<Self::AccountId>, Self::Error> {
+        Ok(self
+            .find_ephemeral_address(address)
+            .map(|(account_id, _)| account_id))
+    }
+
+    fn transaction_data_requests(&self) -> Result<Vec<TransactionDataRequest>, Self::Error> {
+        let iter = self
+            .tx_retrieval_queue
+            .iter()
+            .map(|(txid, (query_type, _))| match query_type {
+                TxQueryType::Status => TransactionDataRequest::GetStatus(*txid),
+                TxQueryType::Enhancement => TransactionDataRequest::Enhancement(*txid),
+            });
+
+        #[cfg(feature = "transparent-inputs")]
+        let iter = iter.chain(self.transparent_transaction_data_requests());
+
+        Ok(iter.collect())
+    }
+
+    fn get_transaction_history(
+        &self,
+        filter: &TransactionHistoryFilter<Self::AccountId>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<TransactionHistoryEntry<Self::AccountId>>, Self::Error> {
+        self.get_transaction_history_inner(filter, offset, limit)
+    }
+}
+
+#[cfg(any(test, feature = "test-dependencies"))]
+impl<P: consensus::Parameters> WalletTest for MemoryWalletDb<P> {
+    fn get_tx_history(
+        &self,
+    ) -> Result<Vec<TransactionSummary<<Self as WalletRead>::AccountId>>, <Self as WalletRead>::Error>
+    {
+        self.get_tx_history_inner()
+    }
+
+    fn get_sent_note_ids(
+        &self,
+        txid: &TxId,
+        protocol: ShieldedProtocol,
+    ) -> Result<Vec<NoteId>, <Self as WalletRead>::Error> {
+        Ok(self
+            .sent_outputs
+            .keys()
+            .filter(|(tx, pool, _)| tx == txid && *pool == PoolType::Shielded(protocol))
+            .map(|(_, _, output_index)| NoteId::new(*txid, protocol, *output_index as u16))
+            .collect())
+    }
+
+    fn get_sent_outputs(
+        &self,
+        txid: &TxId,
+    ) -> Result<Vec<OutputOfSentTx>, <Self as WalletRead>::Error> {
+        #[cfg(feature = "transparent-inputs")]
+        let ephemeral_addresses = self
+            .ephemeral_addresses
+            .iter()
+            .filter(|(_, e)| e.used_in.as_ref() == Some(txid))
+            .filter_map(|((_, index), e)| {
+                e.address
+                    .as_ref()
+                    .map(|addr| (Address::Transparent(*addr), *index))
+            })
+            .collect::<Vec<_>>();
+        #[cfg(not(feature = "transparent-inputs"))]
+        let ephemeral_addresses: Vec<(Address, u32)> = vec![];
+
+        let mut sends = self
+            .sent_outputs
+            .iter()
+            .filter(|((tx, _, _), _)| tx == txid)
+            .flat_map(|(_, output)| {
+                let to_address = output
+                    .to_address
+                    .as_ref()
+                    .and_then(|s| Address::decode(&self.params, s));
+                let ephemeral = if ephemeral_addresses.is_empty() {
+                    vec![None]
+                } else {
+                    ephemeral_addresses.iter().cloned().map(Some).collect()
+                };
+                ephemeral
+                    .into_iter()
+                    .map(move |e| (output.value, to_address.clone(), e))
+            })
+            .collect::<Vec<_>>();
+        sends.sort_by_key(|(value, _, _)| *value);
+
+        Ok(sends
+            .into_iter()
+            .map(|(value, to_address, ephemeral_address)| {
+                OutputOfSentTx::from_parts(value, to_address, ephemeral_address)
+            })
+            .collect())
+    }
+
+    fn get_checkpoint_history(
+        &self,
+        protocol: &ShieldedProtocol,
+    ) -> Result<
+        Vec<(BlockHeight, Option<incrementalmerkletree::Position>)>,
+        <Self as WalletRead>::Error,
+    > {
+        let checkpoints = match protocol {
+            ShieldedProtocol::Sapling => &self.sapling_tree.checkpoints,
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => &self.orchard_tree.checkpoints,
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => {
+                return Err(Error::UnsupportedPoolType(PoolType::ORCHARD));
+            }
+        };
+
+        Ok(checkpoints
+            .iter()
+            .map(|(height, checkpoint)| (*height, checkpoint.position()))
+            .collect())
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_transparent_output(
+        &self,
+        outpoint: &OutPoint,
+        allow_unspendable: bool,
+    ) -> Result<Option<WalletTransparentOutput>, <Self as InputSource>::Error> {
+        self.get_wallet_transparent_output(outpoint, allow_unspendable)
+    }
+
+    fn get_notes(
+        &self,
+        protocol: ShieldedProtocol,
+    ) -> Result<Vec<ReceivedNote<Self::NoteRef, Note>>, <Self as InputSource>::Error> {
+        Ok(self
+            .received_notes
+            .values()
+            .filter(|n| {
+                n.note.protocol() == protocol
+                    && self
+                        .transactions
+                        .get(&n.txid)
+                        .map_or(false, |tx| tx.block.is_some())
+                    && n.nf.is_some()
+                    && n.commitment_tree_position.is_some()
+            })
+            .map(|n| {
+                self.get_spendable_note(&n.txid, protocol, n.output_index)
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect())
+    }
+}
+
+impl<P: consensus::Parameters + Clone> WalletWrite for MemoryWalletDb<P> {
+    type UtxoRef = UtxoId;
+
+    fn create_account(
+        &mut self,
+        seed: &SecretVec<u8>,
+        birthday: &AccountBirthday,
+    ) -> Result<(AccountId, UnifiedSpendingKey), Self::Error> {
+        self.transactionally(|wdb| {
+            let seed_fingerprint =
+                SeedFingerprint::from_seed(seed.expose_secret()).ok_or_else(|| {
+                    Error::BadAccountData(
+                        "Seed must be between 32 and 252 bytes in length.".to_owned(),
+                    )
+                })?;
+            let account_index = wdb
+                .max_zip32_account_index(&seed_fingerprint)
+                .map(|a| a.next().ok_or(Error::AccountIdOutOfRange))
+                .transpose()?
+                .unwrap_or(zip32::AccountId::ZERO);
+
+            let usk =
+                UnifiedSpendingKey::from_seed(&wdb.params, seed.expose_secret(), account_index)
+                    .map_err(|_| Error::KeyDerivationError(account_index))?;
+            let ufvk = usk.to_unified_full_viewing_key();
+
+            let account = wdb.add_account(
+                AccountSource::Derived {
+                    seed_fingerprint,
+                    account_index,
+                },
+                ufvk,
+                birthday,
+            )?;
+
+            Ok((account.id(), usk))
+        })
+    }
+
+    fn import_account_hd(
+        &mut self,
+        seed: &SecretVec<u8>,
+        account_index: zip32::AccountId,
+        birthday: &AccountBirthday,
+    ) -> Result<(Self::Account, UnifiedSpendingKey), Self::Error> {
+        self.transactionally(|wdb| {
+            let seed_fingerprint =
+                SeedFingerprint::from_seed(seed.expose_secret()).ok_or_else(|| {
+                    Error::BadAccountData(
+                        "Seed must be between 32 and 252 bytes in length.".to_owned(),
+                    )
+                })?;
+
+            let usk =
+                UnifiedSpendingKey::from_seed(&wdb.params, seed.expose_secret(), account_index)
+                    .map_err(|_| Error::KeyDerivationError(account_index))?;
+            let ufvk = usk.to_unified_full_viewing_key();
+
+            let account = wdb.add_account(
+                AccountSource::Derived {
+                    seed_fingerprint,
+                    account_index,
+                },
+                ufvk,
+                birthday,
+            )?;
+
+            Ok((account, usk))
+        })
+    }
+
+    fn import_account_ufvk(
+        &mut self,
+        ufvk: &UnifiedFullViewingKey,
+        birthday: &AccountBirthday,
+        purpose: AccountPurpose,
+    ) -> Result<Self::Account, Self::Error> {
+        self.transactionally(|wdb| {
+            wdb.add_account(AccountSource::Imported { purpose }, ufvk.clone(), birthday)
+        })
+    }
+
+    fn delete_account(&mut self, account: AccountId) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| wdb.delete_account_inner(account))
+    }
+
+    fn set_account_name(
+        &mut self,
+        account: AccountId,
+        name: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let account = self
+            .accounts
+            .get_mut(&account)
+            .ok_or(Error::AccountUnknown)?;
+        account.name = name.map(|name| name.to_owned());
+        Ok(())
+    }
+
+    fn set_account_key_source(
+        &mut self,
+        account: AccountId,
+        key_source: Option<&str>,
+        derivation: Option<Zip32Derivation>,
+    ) -> Result<(), Self::Error> {
+        let account = self
+            .accounts
+            .get_mut(&account)
+            .ok_or(Error::AccountUnknown)?;
+        match account.kind {
+            // The derivation of a derived account is fixed by its source.
+            AccountSource::Derived {
+                seed_fingerprint,
+                account_index,
+            } => {
+                if derivation.map_or(false, |d| {
+                    d != Zip32Derivation::new(seed_fingerprint, account_index)
+                }) {
+                    return Err(Error::Zip32DerivationMismatch);
+                }
+            }
+            AccountSource::Imported { .. } => account.imported_derivation = derivation,
+        }
+        account.key_source = key_source.map(|key_source| key_source.to_owned());
+        Ok(())
+    }
+
+    fn set_account_metadata(
+        &mut self,
+        account: AccountId,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        let account = self
+            .accounts
+            .get_mut(&account)
+            .ok_or(Error::AccountUnknown)?;
+        match value {
+            Some(value) => account.metadata.insert(key.to_owned(), value.to_owned()),
+            None => account.metadata.remove(key),
+        };
+        Ok(())
+    }
+
+    fn get_next_available_address(
+        &mut self,
+        account: AccountId,
+        request: UnifiedAddressRequest,
+    ) -> Result<Option<UnifiedAddress>, Self::Error> {
+        self.transactionally(|wdb| match wdb.accounts.get(&account) {
+            Some(acct) => {
+                let ufvk = acct.ufvk.clone();
+                let search_from = match wdb.get_current_address_inner(account) {
+                    Some((_, mut last_diversifier_index)) => {
+                        last_diversifier_index
+                            .increment()
+                            .map_err(|_| AddressGenerationError::DiversifierSpaceExhausted)?;
+                        last_diversifier_index
+                    }
+                    None => DiversifierIndex::default(),
+                };
+
+                let (addr, diversifier_index) = ufvk.find_address(search_from, request)?;
+                wdb.insert_address(account, diversifier_index, addr.clone());
+
+                Ok(Some(addr))
+            }
+            None => Ok(None),
+        })
+    }
+
+    fn update_chain_tip(&mut self, tip_height: BlockHeight) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| wdb.update_chain_tip_inner(tip_height))
+    }
+
+    #[tracing::instrument(skip_all, fields(height = blocks.first().map(|b| u32::from(b.height())), count = blocks.len()))]
+    #[allow(clippy::type_complexity)]
+    fn put_blocks(
+        &mut self,
+        from_state: &ChainState,
+        blocks: Vec<ScannedBlock<Self::AccountId>>,
+    ) -> Result<(), Self::Error> {
+        struct BlockPositions {
+            height: BlockHeight,
+            sapling_start_position: Position,
+            #[cfg(feature = "orchard")]
+            orchard_start_position: Position,
+        }
+
+        if blocks.is_empty() {
+            return Ok(());
+        }
+
+        self.transactionally(|wdb| {
+            let initial_block = blocks.first().expect("blocks is known to be nonempty");
+            assert!(from_state.block_height() + 1 == initial_block.height());
+
+            let start_positions = BlockPositions {
+                height: initial_block.height(),
+                sapling_start_position: Position::from(
+                    u64::from(initial_block.sapling().final_tree_size())
+                        - u64::try_from(initial_block.sapling().commitments().len()).unwrap(),
+                ),
+                #[cfg(feature = "orchard")]
+                orchard_start_position: Position::from(
+                    u64::from(initial_block.orchard().final_tree_size())
+                        - u64::try_from(initial_block.orchard().commitments().len()).unwrap(),
+                ),
+            };
+
+            let mut sapling_commitments = vec![];
+            #[cfg(feature = "orchard")]
+            let mut orchard_commitments = vec![];
+            let mut last_scanned_height = None;
+            let mut note_positions = vec![];
+            for block in blocks.into_iter() {
+                if last_scanned_height
+                    .iter()
+                    .any(|prev| block.height() != *prev + 1)
+                {
+                    return Err(Error::NonSequentialBlocks);
+                }
+
+                // Insert the block into the wallet.
+                wdb.put_block(
+                    block.height(),
+                    block.block_hash(),
+                    block.block_time(),
+                    block.sapling().final_tree_size(),
+                    block.sapling().commitments().len().try_into().unwrap(),
+                    #[cfg(feature = "orchard")]
+                    block.orchard().final_tree_size(),
+                    #[cfg(feature = "orchard")]
+                    block.orchard().commitments().len().try_into().unwrap(),
+                )?;
+
+                for tx in block.transactions() {
+                    wdb.put_tx_meta(tx.txid(), tx.block_index() as u16, block.height());
+                    wdb.queue_tx_retrieval(std::iter::once(tx.txid()), None);
+
+                    // Mark notes as spent and remove them from the scanning cache
+                    for spend in tx.sapling_spends() {
+                        wdb.mark_note_spent(ShieldedProtocol::Sapling, &spend.nf().0, tx.txid());
+                    }
+                    #[cfg(feature = "orchard")]
+                    for spend in tx.orchard_spends() {
+                        wdb.mark_note_spent(
+                            ShieldedProtocol::Orchard,
+                            &spend.nf().to_bytes(),
+                            tx.txid(),
+                        );
+                    }
+
+                    for output in tx.sapling_outputs() {
+                        // Check whether this note was spent in a later block range that
+                        // we previously scanned.
+                        let spent_in = output.nf().and_then(|nf| {
+                            wdb.query_nullifier_map(ShieldedProtocol::Sapling, &nf.0)
+                        });
+
+                        wdb.put_received_note(output, tx.txid(), spent_in)?;
+                    }
+                    #[cfg(feature = "orchard")]
+                    for output in tx.orchard_outputs() {
+                        // Check whether this note was spent in a later block range that
+                        // we previously scanned.
+                        let spent_in = output.nf().and_then(|nf| {
+                            wdb.query_nullifier_map(ShieldedProtocol::Orchard, &nf.to_bytes())
+                        });
+
+                        wdb.put_received_note(output, tx.txid(), spent_in)?;
+                    }
+                }
+
+                // Insert the new nullifiers from this block into the nullifier map.
+                wdb.insert_nullifier_map(
+                    block.height(),
+                    ShieldedProtocol::Sapling,
+                    &block
+                        .sapling()
+                        .nullifier_map()
+                        .iter()
+                        .map(|(txid, idx, nfs)| (*txid, *idx, nfs.iter().map(|nf| nf.0).collect()))
+                        .collect::<Vec<_>>(),
+                )?;
+                #[cfg(feature = "orchard")]
+                wdb.insert_nullifier_map(
+                    block.height(),
+                    ShieldedProtocol::Orchard,
+                    &block
+                        .orchard()
+                        .nullifier_map()
+                        .iter()
+                        .map(|(txid, idx, nfs)| {
+                            (*txid, *idx, nfs.iter().map(|nf| nf.to_bytes()).collect())
+                        })
+                        .collect::<Vec<_>>(),
+                )?;
+
+                note_positions.extend(block.transactions().iter().flat_map(|wtx| {
+                    let iter = wtx.sapling_outputs().iter().map(|out| {
+                        (
+                            ShieldedProtocol::Sapling,
+                            out.note_commitment_tree_position(),
+                        )
+                    });
+                    #[cfg(feature = "orchard")]
+                    let iter = iter.chain(wtx.orchard_outputs().iter().map(|out| {
+                        (
+                            ShieldedProtocol::Orchard,
+                            out.note_commitment_tree_position(),
+                        )
+                    }));
+
+                    iter
+                }));
+
+                last_scanned_height = Some(block.height());
+                let block_commitments = block.into_commitments();
+                trace!(
+                    "Sapling commitments for {:?}: {:?}",
+                    last_scanned_height,
+                    block_commitments
+                        .sapling
+                        .iter()
+                        .map(|(_, r)| *r)
+                        .collect::<Vec<_>>()
+                );
+                #[cfg(feature = "orchard")]
+                trace!(
+                    "Orchard commitments for {:?}: {:?}",
+                    last_scanned_height,
+                    block_commitments
+                        .orchard
+                        .iter()
+                        .map(|(_, r)| *r)
+                        .collect::<Vec<_>>()
+                );
+
+                sapling_commitments.extend(block_commitments.sapling.into_iter());
+                #[cfg(feature = "orchard")]
+                orchard_commitments.extend(block_commitments.orchard.into_iter());
+            }
+
+            // Prune the nullifier map of entries we no longer need.
+            if let Some(meta) = wdb.block_fully_scanned_inner() {
+                wdb.prune_nullifier_map(meta.block_height().saturating_sub(PRUNING_DEPTH));
+            }
+
+            // We will have a start position and a last scanned height in all cases where
+            // `blocks` is non-empty.
+            if let Some(last_scanned_height) = last_scanned_height {
+                // Create subtrees from the note commitments.
+                const CHUNK_SIZE: usize = 1024;
+                let sapling_subtrees = sapling_commitments
+                    .chunks(CHUNK_SIZE)
+                    .enumerate()
+                    .filter_map(|(i, chunk)| {
+                        let start =
+                            start_positions.sapling_start_position + (i * CHUNK_SIZE) as u64;
+                        let end = start + chunk.len() as u64;
+
+                        shardtree::LocatedTree::from_iter(
+                            start..end,
+                            SAPLING_SHARD_HEIGHT.into(),
+                            chunk.iter().cloned(),
+                        )
+                    })
+                    .map(|res| (res.subtree, res.checkpoints))
+                    .collect::<Vec<_>>();
+
+                #[cfg(feature = "orchard")]
+                let orchard_subtrees = orchard_commitments
+                    .chunks(CHUNK_SIZE)
+                    .enumerate()
+                    .filter_map(|(i, chunk)| {
+                        let start =
+                            start_positions.orchard_start_position + (i * CHUNK_SIZE) as u64;
+                        let end = start + chunk.len() as u64;
+
+                        shardtree::LocatedTree::from_iter(
+                            start..end,
+                            ORCHARD_SHARD_HEIGHT.into(),
+                            chunk.iter().cloned(),
+                        )
+                    })
+                    .map(|res| (res.subtree, res.checkpoints))
+                    .collect::<Vec<_>>();
+
+                // Collect the complete set of Sapling checkpoints
+                #[cfg(feature = "orchard")]
+                let sapling_checkpoint_positions: BTreeMap<BlockHeight, Position> =
+                    sapling_subtrees
+                        .iter()
+                        .flat_map(|(_, checkpoints)| checkpoints.iter())
+                        .map(|(k, v)| (*k, *v))
+                        .collect();
+
+                #[cfg(feature = "orchard")]
+                let orchard_checkpoint_positions: BTreeMap<BlockHeight, Position> =
+                    orchard_subtrees
+                        .iter()
+                        .flat_map(|(_, checkpoints)| checkpoints.iter())
+                        .map(|(k, v)| (*k, *v))
+                        .collect();
+
+                #[cfg(feature = "orchard")]
+                fn ensure_checkpoints<
+                    'a,
+                    H,
+                    I: Iterator<Item = &'a BlockHeight>,
+                    const DEPTH: u8,
+                >(
+                    // An iterator of checkpoints heights for which we wish to ensure that
+                    // checkpoints exists.
+                    ensure_heights: I,
+                    // The map of checkpoint positions from which we will draw note commitment tree
+                    // position information for the newly created checkpoints.
+                    existing_checkpoint_positions: &BTreeMap<BlockHeight, Position>,
+                    // The frontier whose position will be used for an inserted checkpoint when
+                    // there is no preceding checkpoint in existing_checkpoint_positions.
+                    state_final_tree: &Frontier<H, DEPTH>,
+                ) -> Vec<(BlockHeight, Checkpoint)> {
+                    ensure_heights
+                        .flat_map(|ensure_height| {
+                            existing_checkpoint_positions
+                                .range::<BlockHeight, _>(..=*ensure_height)
+                                .last()
+                                .map_or_else(
+                                    || {
+                                        Some((
+                                            *ensure_height,
+                                            state_final_tree
+                                                .value()
+                                                .map_or_else(Checkpoint::tree_empty, |t| {
+                                                    Checkpoint::at_position(t.position())
+                                                }),
+                                        ))
+                                    },
+                                    |(existing_checkpoint_height, position)| {
+                                        if *existing_checkpoint_height < *ensure_height {
+                                            Some((
+                                                *ensure_height,
+                                                Checkpoint::at_position(*position),
+                                            ))
+                                        } else {
+                                            // The checkpoint already exists, so we don't need to
+                                            // do anything.
+                                            None
+                                        }
+                                    },
+                                )
+                                .into_iter()
+                        })
+                        .collect::<Vec<_>>()
+                }
+
+                #[cfg(feature = "orchard")]
+                let (missing_sapling_checkpoints, missing_orchard_checkpoints) = (
+                    ensure_checkpoints(
+                        orchard_checkpoint_positions.keys(),
+                        &sapling_checkpoint_positions,
+                        from_state.final_sapling_tree(),
+                    ),
+                    ensure_checkpoints(
+                        sapling_checkpoint_positions.keys(),
+                        &orchard_checkpoint_positions,
+                        from_state.final_orchard_tree(),
+                    ),
+                );
+
+                // Update the Sapling note commitment tree with all newly read note commitments
+                {
+                    let mut sapling_subtrees_iter = sapling_subtrees.into_iter();
+                    wdb.with_sapling_tree_mut::<_, _, Error>(|sapling_tree| {
+                        debug!(
+                            "Sapling initial tree size at {:?}: {:?}",
+                            from_state.block_height(),
+                            from_state.final_sapling_tree().tree_size()
+                        );
+                        // We insert the frontier with `Checkpoint` retention because we need to be
+                        // able to truncate the tree back to this point.
+                        sapling_tree.insert_frontier(
+                            from_state.final_sapling_tree().clone(),
+                            Retention::Checkpoint {
+                                id: from_state.block_height(),
+                                marking: Marking::Reference,
+                            },
+                        )?;
+
+                        for (tree, checkpoints) in &mut sapling_subtrees_iter {
+                            sapling_tree.insert_tree(tree, checkpoints)?;
+                        }
+
+                        // Ensure we have a Sapling checkpoint for each checkpointed Orchard block height.
+                        // We skip all checkpoints below the minimum retained checkpoint in the
+                        // Sapling tree, because branches below this height may be pruned.
+                        #[cfg(feature = "orchard")]
+                        {
+                            let min_checkpoint_height = sapling_tree
+                                .store()
+                                .min_checkpoint_id()
+                                .map_err(ShardTreeError::Storage)?
+                                .expect(
+                                    "At least one checkpoint was inserted (by insert_frontier)",
+                                );
+
+                            for (height, checkpoint) in &missing_sapling_checkpoints {
+                                if *height > min_checkpoint_height {
+                                    sapling_tree
+                                        .store_mut()
+                                        .add_checkpoint(*height, checkpoint.clone())
+                                        .map_err(ShardTreeError::Storage)?;
+                                }
+                            }
+                        }
+
+                        Ok(())
+                    })?;
+                }
+
+                // Update the Orchard note commitment tree with all newly read note commitments
+                #[cfg(feature = "orchard")]
+                {
+                    let mut orchard_subtrees = orchard_subtrees.into_iter();
+                    wdb.with_orchard_tree_mut::<_, _, Error>(|orchard_tree| {
+                        debug!(
+                            "Orchard initial tree size at {:?}: {:?}",
+                            from_state.block_height(),
+                            from_state.final_orchard_tree().tree_size()
+                        );
+                        // We insert the frontier with `Checkpoint` retention because we need to be
+                        // able to truncate the tree back to this point.
+                        orchard_tree.insert_frontier(
+                            from_state.final_orchard_tree().clone(),
+                            Retention::Checkpoint {
+                                id: from_state.block_height(),
+                                marking: Marking::Reference,
+                            },
+                        )?;
+
+                        for (tree, checkpoints) in &mut orchard_subtrees {
+                            orchard_tree.insert_tree(tree, checkpoints)?;
+                        }
+
+                        // Ensure we have an Orchard checkpoint for each checkpointed Sapling block height.
+                        // We skip all checkpoints below the minimum retained checkpoint in the
+                        // Orchard tree, because branches below this height may be pruned.
+                        {
+                            let min_checkpoint_height = orchard_tree
+                                .store()
+                                .min_checkpoint_id()
+                                .map_err(ShardTreeError::Storage)?
+                                .expect(
+                                    "At least one checkpoint was inserted (by insert_frontier)",
+                                );
+
+                            for (height, checkpoint) in &missing_orchard_checkpoints {
+                                if *height > min_checkpoint_height {
+                                    debug!(
+                                        "Adding missing Orchard checkpoint for height: {:?}: {:?}",
+                                        height,
+                                        checkpoint.position()
+                                    );
+                                    orchard_tree
+                                        .store_mut()
+                                        .add_checkpoint(*height, checkpoint.clone())
+                                        .map_err(ShardTreeError::Storage)?;
+                                }
+                            }
+                        }
+                        Ok(())
+                    })?;
+                }
+
+                wdb.scan_complete(
+                    Range {
+                        start: start_positions.height,
+                        end: last_scanned_height + 1,
+                    },
+                    &note_positions,
+                )?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn put_received_transparent_utxo(
+        &mut self,
+        _output: &WalletTransparentOutput,
+    ) -> Result<Self::UtxoRef, Self::Error> {
+        #[cfg(feature = "transparent-inputs")]
+        return self.transactionally(|wdb| wdb.put_received_transparent_utxo_inner(_output));
+
+        #[cfg(not(feature = "transparent-inputs"))]
+        panic!(
+            "The wallet must be compiled with the transparent-inputs feature to use this method."
+        );
+    }
+
+    fn store_decrypted_tx(
+        &mut self,
+        d_tx: DecryptedTransaction<AccountId>,
+    ) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| wdb.store_decrypted_tx_inner(d_tx))
+    }
+
+    fn store_transactions_to_be_sent(
+        &mut self,
+        transactions: &[SentTransaction<AccountId>],
+    ) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| {
+            for sent_tx in transactions {
+                wdb.store_transaction_to_be_sent(sent_tx)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn truncate_to_height(&mut self, max_height: BlockHeight) -> Result<BlockHeight, Self::Error> {
+        self.transactionally(|wdb| wdb.truncate_to_height_inner(max_height))
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn reserve_next_n_ephemeral_addresses(
+        &mut self,
+        account_id: Self::AccountId,
+        n: usize,
+    ) -> Result<Vec<(TransparentAddress, TransparentAddressMetadata)>, Self::Error> {
+        self.transactionally(|wdb| wdb.reserve_next_n_ephemeral_addresses_inner(account_id, n))
+    }
+
+    fn set_transaction_status(
+        &mut self,
+        txid: TxId,
+        status: data_api::TransactionStatus,
+    ) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| {
+            wdb.set_transaction_status_inner(txid, status);
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "fiat")]
+    fn set_transaction_fiat_rate(
+        &mut self,
+        txid: TxId,
+        rate: data_api::FiatRate,
+    ) -> Result<(), Self::Error> {
+        let tx = self
+            .transactions
+            .get_mut(&txid)
+            .ok_or(Error::TransactionUnknown(txid))?;
+        tx.fiat_rates.insert(rate.currency(), rate.rate());
+        Ok(())
+    }
+}
+
+impl<P: consensus::Parameters> WalletCommitmentTrees for MemoryWalletDb<P> {
+    type Error = Infallible;
+    type SaplingShardStore<'a> = &'a mut MemoryShardStore<sapling::Node, BlockHeight>;
+
+    fn with_sapling_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
+    where
+        for<'a> F: FnMut(
+            &'a mut ShardTree<
+                Self::SaplingShardStore<'a>,
+                { sapling::NOTE_COMMITMENT_TREE_DEPTH },
+                SAPLING_SHARD_HEIGHT,
+            >,
+        ) -> Result<A, E>,
+        E: From<ShardTreeError<Self::Error>>,
+    {
+        let backup = self.sapling_tree.clone();
+        let result = {
+            let mut shardtree =
+                ShardTree::new(&mut self.sapling_tree, PRUNING_DEPTH.try_into().unwrap());
+            callback(&mut shardtree)
+        };
+        if result.is_err() {
+            self.sapling_tree = backup;
+        }
+        result
+    }
+
+    fn put_sapling_subtree_roots(
+        &mut self,
+        start_index: u64,
+        roots: &[CommitmentTreeRoot<sapling::Node>],
+    ) -> Result<(), ShardTreeError<Self::Error>> {
+        put_shard_roots::<_, { sapling::NOTE_COMMITMENT_TREE_DEPTH }, SAPLING_SHARD_HEIGHT>(
+            &mut self.sapling_tree,
+            start_index,
+            roots,
+        )
+    }
+
+    #[cfg(feature = "orchard")]
+    type OrchardShardStore<'a> =
+        &'a mut MemoryShardStore<orchard::tree::MerkleHashOrchard, BlockHeight>;
+
+    #[cfg(feature = "orchard")]
+    fn with_orchard_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
+    where
+        for<'a> F: FnMut(
+            &'a mut ShardTree<
+                Self::OrchardShardStore<'a>,
+                { ORCHARD_SHARD_HEIGHT * 2 },
+                ORCHARD_SHARD_HEIGHT,
+            >,
+        ) -> Result<A, E>,
+        E: From<ShardTreeError<Self::Error>>,
+    {
+        let backup = self.orchard_tree.clone();
+        let result = {
+            let mut shardtree =
+                ShardTree::new(&mut self.orchard_tree, PRUNING_DEPTH.try_into().unwrap());
+            callback(&mut shardtree)
+        };
+        if result.is_err() {
+            self.orchard_tree = backup;
+        }
+        result
+    }
+
+    #[cfg(feature = "orchard")]
+    fn put_orchard_subtree_roots(
+        &mut self,
+        start_index: u64,
+        roots: &[CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>],
+    ) -> Result<(), ShardTreeError<Self::Error>> {
+        put_shard_roots::<_, { ORCHARD_SHARD_HEIGHT * 2 }, ORCHARD_SHARD_HEIGHT>(
+            &mut self.orchard_tree,
+            start_index,
+            roots,
+        )
+    }
+}
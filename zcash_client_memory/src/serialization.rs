@@ -0,0 +1,1078 @@
+//! Serialization of wallet state to and from a versioned binary snapshot format.
+//!
+//! The snapshot format is specific to this crate. All integers are little-endian, and all
+//! variable-length sequences are prefixed by their length encoded as a `CompactSize`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use incrementalmerkletree::{Address as TreeAddress, Level, Position};
+use shardtree::{
+    store::{Checkpoint, TreeState},
+    LocatedTree,
+};
+use zcash_client_backend::{
+    data_api::{
+        scanning::{ScanPriority, ScanRange},
+        AccountPurpose, AccountSource, Zip32Derivation,
+    },
+    serialization::shardtree::{read_shard, write_shard},
+    wallet::Note,
+};
+use zcash_encoding::CompactSize;
+use zcash_keys::{
+    address::{Address, UnifiedAddress},
+    keys::UnifiedFullViewingKey,
+};
+use zcash_primitives::{
+    block::BlockHash, memo::MemoBytes, merkle_tree::HashSer,
+    transaction::components::amount::NonNegativeAmount, transaction::TxId,
+};
+use zcash_protocol::{
+    consensus::{self, BlockHeight},
+    PoolType, ShieldedProtocol,
+};
+use zip32::{fingerprint::SeedFingerprint, Scope};
+
+use crate::{
+    error::Error,
+    wallet::{
+        commitment_tree::MemoryShardStore, Account, BlockRecord, ReceivedNoteRecord,
+        SentOutputRecord, TransactionRecord, TxQueryType,
+    },
+    AccountId, MemoryWalletDb,
+};
+
+#[cfg(feature = "fiat")]
+use {rust_decimal::Decimal, zcash_client_backend::fiat::Currency};
+
+#[cfg(feature = "transparent-inputs")]
+use {
+    crate::wallet::transparent::{EphemeralAddressRecord, TransparentOutputRecord},
+    zcash_primitives::{
+        legacy::TransparentAddress,
+        transaction::components::{OutPoint, TxOut},
+    },
+};
+
+/// The initial version of the snapshot format.
+const SER_V1: u8 = 1;
+/// The current version of the snapshot format, which adds account names, key provenance
+/// and key/value account metadata.
+const SER_V2: u8 = 2;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Serializes the complete state of the wallet to a byte vector.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![];
+        self.write(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Restores a wallet from a snapshot produced by [`MemoryWalletDb::to_bytes`].
+    ///
+    /// Returns an error if the snapshot is malformed, or contains data that cannot be
+    /// represented with the features enabled for this crate.
+    pub fn from_bytes(params: P, bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = bytes;
+        let wallet = Self::read(params, &mut reader)?;
+        if !reader.is_empty() {
+            return Err(Error::CorruptedData(
+                "Unexpected trailing data in wallet snapshot".to_owned(),
+            ));
+        }
+        Ok(wallet)
+    }
+
+    fn write<W: Write>(&self, mut w: W) -> Result<(), Error> {
+        w.write_u8(SER_V2)?;
+
+        write_seq(&mut w, self.accounts.values(), |w, account| {
+            write_account(w, &self.params, account)
+        })?;
+        write_seq(&mut w, self.addresses.iter(), |w, (account_id, addrs)| {
+            write_account_id(w, *account_id)?;
+            write_seq(w, addrs.iter(), |w, (di, ua)| {
+                w.write_u128::<LittleEndian>(*di)?;
+                write_string(w, &ua.encode(&self.params))
+            })
+        })?;
+        write_seq(&mut w, self.blocks.iter(), |w, (height, block)| {
+            write_height(w, *height)?;
+            write_block(w, block)
+        })?;
+        write_seq(&mut w, self.transactions.iter(), |w, (txid, tx)| {
+            txid.write(&mut *w)?;
+            write_transaction(w, tx)
+        })?;
+        write_seq(&mut w, self.received_notes.iter(), |w, (id, note)| {
+            w.write_u64::<LittleEndian>(*id)?;
+            write_received_note(w, note)
+        })?;
+        write_seq(&mut w, self.received_note_spends.iter(), |w, (id, txid)| {
+            w.write_u64::<LittleEndian>(*id)?;
+            Ok(txid.write(w)?)
+        })?;
+        write_seq(
+            &mut w,
+            self.sent_outputs.iter(),
+            |w, ((txid, pool, output_index), output)| {
+                txid.write(&mut *w)?;
+                write_pool_type(w, *pool)?;
+                w.write_u32::<LittleEndian>(*output_index)?;
+                write_sent_output(w, output)
+            },
+        )?;
+        write_seq(&mut w, self.scan_queue.iter(), |w, range| {
+            write_height(w, range.block_range().start)?;
+            write_height(w, range.block_range().end)?;
+            w.write_u8(scan_priority_code(range.priority()))?;
+            Ok(())
+        })?;
+        write_seq(
+            &mut w,
+            self.tx_locators.iter(),
+            |w, ((height, index), txid)| {
+                write_height(w, *height)?;
+                w.write_u16::<LittleEndian>(*index)?;
+                Ok(txid.write(w)?)
+            },
+        )?;
+        write_seq(
+            &mut w,
+            self.nullifiers.iter(),
+            |w, ((protocol, nf), (height, index))| {
+                write_pool_type(w, PoolType::Shielded(*protocol))?;
+                w.write_all(nf)?;
+                write_height(w, *height)?;
+                w.write_u16::<LittleEndian>(*index)?;
+                Ok(())
+            },
+        )?;
+        write_seq(
+            &mut w,
+            self.tx_retrieval_queue.iter(),
+            |w, (txid, (query_type, dependent))| {
+                txid.write(&mut *w)?;
+                w.write_u8(match query_type {
+                    TxQueryType::Status => 0,
+                    TxQueryType::Enhancement => 1,
+                })?;
+                write_opt(w, dependent.as_ref(), |w, txid| Ok(txid.write(w)?))
+            },
+        )?;
+
+        write_tree(&mut w, &self.sapling_tree)?;
+
+        #[cfg(feature = "orchard")]
+        write_opt(&mut w, Some(&self.orchard_tree), |w, tree| {
+            write_tree(w, tree)
+        })?;
+        #[cfg(not(feature = "orchard"))]
+        w.write_u8(0)?;
+
+        #[cfg(feature = "transparent-inputs")]
+        write_opt(&mut w, Some(self), |w, wdb| wdb.write_transparent(w))?;
+        #[cfg(not(feature = "transparent-inputs"))]
+        w.write_u8(0)?;
+
+        Ok(())
+    }
+
+    fn read<R: Read>(params: P, mut r: R) -> Result<Self, Error> {
+        let version = r.read_u8()?;
+        if version != SER_V1 && version != SER_V2 {
+            return Err(Error::CorruptedData(format!(
+                "Unrecognized wallet snapshot version: {}",
+                version
+            )));
+        }
+
+        let mut wallet = MemoryWalletDb::new(params);
+
+        wallet.accounts = read_seq(&mut r, |r| {
+            let account = read_account(r, &wallet.params, version)?;
+            Ok((account.account_id, account))
+        })?;
+        wallet.addresses = read_seq(&mut r, |r| {
+            let account_id = read_account_id(r)?;
+            let addrs = read_seq(r, |r| {
+                let di = r.read_u128::<LittleEndian>()?;
+                let ua = read_unified_address(r, &wallet.params)?;
+                Ok((di, ua))
+            })?;
+            Ok((account_id, addrs))
+        })?;
+        wallet.blocks = read_seq(&mut r, |r| Ok((read_height(r)?, read_block(r)?)))?;
+        wallet.transactions =
+            read_seq(&mut r, |r| Ok((TxId::read(&mut *r)?, read_transaction(r)?)))?;
+        wallet.received_notes = read_seq(&mut r, |r| {
+            Ok((r.read_u64::<LittleEndian>()?, read_received_note(r)?))
+        })?;
+        wallet.received_note_spends = read_seq(&mut r, |r| {
+            Ok((r.read_u64::<LittleEndian>()?, TxId::read(r)?))
+        })?;
+        wallet.sent_outputs = read_seq(&mut r, |r| {
+            let txid = TxId::read(&mut *r)?;
+            let pool = read_pool_type(r)?;
+            let output_index = r.read_u32::<LittleEndian>()?;
+            Ok(((txid, pool, output_index), read_sent_output(r)?))
+        })?;
+        wallet.scan_queue = read_seq(&mut r, |r| {
+            let start = read_height(r)?;
+            let end = read_height(r)?;
+            let priority = read_scan_priority(r)?;
+            Ok(ScanRange::from_parts(start..end, priority))
+        })?;
+        wallet.tx_locators = read_seq(&mut r, |r| {
+            let height = read_height(r)?;
+            let index = r.read_u16::<LittleEndian>()?;
+            Ok(((height, index), TxId::read(r)?))
+        })?;
+        wallet.nullifiers = read_seq(&mut r, |r| {
+            let protocol = match read_pool_type(r)? {
+                PoolType::Shielded(protocol) => protocol,
+                PoolType::Transparent => {
+                    return Err(Error::CorruptedData(
+                        "Nullifier map entry has a transparent pool type".to_owned(),
+                    ))
+                }
+            };
+            let nf = read_array(r)?;
+            let height = read_height(r)?;
+            let index = r.read_u16::<LittleEndian>()?;
+            Ok(((protocol, nf), (height, index)))
+        })?;
+        wallet.tx_retrieval_queue = read_seq(&mut r, |r| {
+            let txid = TxId::read(&mut *r)?;
+            let query_type = match r.read_u8()? {
+                0 => TxQueryType::Status,
+                1 => TxQueryType::Enhancement,
+                other => {
+                    return Err(Error::CorruptedData(format!(
+                        "Unrecognized transaction query type: {}",
+                        other
+                    )))
+                }
+            };
+            let dependent = read_opt(r, |r| Ok(TxId::read(r)?))?;
+            Ok((txid, (query_type, dependent)))
+        })?;
+
+        wallet.sapling_tree = read_tree(&mut r)?;
+
+        #[cfg(feature = "orchard")]
+        if let Some(tree) = read_opt(&mut r, |r| read_tree(r))? {
+            wallet.orchard_tree = tree;
+        }
+        #[cfg(not(feature = "orchard"))]
+        if r.read_u8()? != 0 {
+            return Err(Error::UnsupportedPoolType(PoolType::ORCHARD));
+        }
+
+        #[cfg(feature = "transparent-inputs")]
+        read_opt(&mut r, |r| wallet.read_transparent(r))?;
+        #[cfg(not(feature = "transparent-inputs"))]
+        if r.read_u8()? != 0 {
+            return Err(Error::UnsupportedPoolType(PoolType::TRANSPARENT));
+        }
+
+        Ok(wallet)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn write_transparent<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        write_seq(
+            w,
+            self.transparent_outputs.iter(),
+            |w, (outpoint, output)| {
+                outpoint.write(&mut *w)?;
+                write_transparent_output(w, output)
+            },
+        )?;
+        write_seq(
+            w,
+            self.transparent_output_spends.iter(),
+            |w, (outpoint, txid)| {
+                outpoint.write(&mut *w)?;
+                Ok(txid.write(w)?)
+            },
+        )?;
+        write_seq(
+            w,
+            self.transparent_spend_map.iter(),
+            |w, (txid, outpoint)| {
+                txid.write(&mut *w)?;
+                Ok(outpoint.write(w)?)
+            },
+        )?;
+        write_seq(
+            w,
+            self.transparent_spend_search_queue.iter(),
+            |w, (outpoint, address)| {
+                outpoint.write(&mut *w)?;
+                write_transparent_address(w, address)
+            },
+        )?;
+        write_seq(
+            w,
+            self.ephemeral_addresses.iter(),
+            |w, ((account_id, index), record)| {
+                write_account_id(w, *account_id)?;
+                w.write_u32::<LittleEndian>(*index)?;
+                write_opt(w, record.address.as_ref(), write_transparent_address)?;
+                write_opt(w, record.used_in.as_ref(), |w, txid| Ok(txid.write(w)?))?;
+                write_opt(w, record.seen_in.as_ref(), |w, txid| Ok(txid.write(w)?))
+            },
+        )
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn read_transparent<R: Read>(&mut self, r: &mut R) -> Result<(), Error> {
+        self.transparent_outputs = read_seq(r, |r| {
+            let outpoint = OutPoint::read(&mut *r)?;
+            Ok((outpoint, read_transparent_output(r)?))
+        })?;
+        self.transparent_output_spends = read_seq(r, |r| {
+            let outpoint = OutPoint::read(&mut *r)?;
+            Ok((outpoint, TxId::read(r)?))
+        })?;
+        self.transparent_spend_map = read_seq(r, |r| {
+            let txid = TxId::read(&mut *r)?;
+            Ok((txid, OutPoint::read(r)?))
+        })?;
+        self.transparent_spend_search_queue = read_seq(r, |r| {
+            let outpoint = OutPoint::read(&mut *r)?;
+            Ok((outpoint, read_transparent_address(r)?))
+        })?;
+        self.ephemeral_addresses = read_seq(r, |r| {
+            let account_id = read_account_id(r)?;
+            let index = r.read_u32::<LittleEndian>()?;
+            let record = EphemeralAddressRecord {
+                address: read_opt(r, read_transparent_address)?,
+                used_in: read_opt(r, |r| Ok(TxId::read(r)?))?,
+                seen_in: read_opt(r, |r| Ok(TxId::read(r)?))?,
+            };
+            Ok(((account_id, index), record))
+        })?;
+        Ok(())
+    }
+}
+
+fn write_seq<W: Write, T>(
+    w: &mut W,
+    items: impl ExactSizeIterator<Item = T>,
+    mut f: impl FnMut(&mut W, T) -> Result<(), Error>,
+) -> Result<(), Error> {
+    CompactSize::write(&mut *w, items.len())?;
+    for item in items {
+        f(w, item)?;
+    }
+    Ok(())
+}
+
+fn read_seq<R: Read, T, O: FromIterator<T>>(
+    r: &mut R,
+    mut f: impl FnMut(&mut R) -> Result<T, Error>,
+) -> Result<O, Error> {
+    let count: usize = CompactSize::read_t(&mut *r)?;
+    (0..count).map(|_| f(r)).collect()
+}
+
+fn write_opt<W: Write, T>(
+    w: &mut W,
+    value: Option<T>,
+    f: impl FnOnce(&mut W, T) -> Result<(), Error>,
+) -> Result<(), Error> {
+    match value {
+        None => Ok(w.write_u8(0)?),
+        Some(value) => {
+            w.write_u8(1)?;
+            f(w, value)
+        }
+    }
+}
+
+fn read_opt<R: Read, T>(
+    r: &mut R,
+    f: impl FnOnce(&mut R) -> Result<T, Error>,
+) -> Result<Option<T>, Error> {
+    match r.read_u8()? {
+        0 => Ok(None),
+        1 => f(r).map(Some),
+        _ => Err(Error::CorruptedData("Non-canonical Option<T>".to_owned())),
+    }
+}
+
+fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> Result<(), Error> {
+    CompactSize::write(&mut *w, bytes.len())?;
+    Ok(w.write_all(bytes)?)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> Result<Vec<u8>, Error> {
+    let len: usize = CompactSize::read_t(&mut *r)?;
+    let mut bytes = vec![0; len];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> Result<[u8; N], Error> {
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn write_string<W: Write>(w: &mut W, s: &str) -> Result<(), Error> {
+    write_bytes(w, s.as_bytes())
+}
+
+fn read_string<R: Read>(r: &mut R) -> Result<String, Error> {
+    String::from_utf8(read_bytes(r)?)
+        .map_err(|e| Error::CorruptedData(format!("Invalid UTF-8 string: {}", e)))
+}
+
+fn write_height<W: Write>(w: &mut W, height: BlockHeight) -> Result<(), Error> {
+    Ok(w.write_u32::<LittleEndian>(height.into())?)
+}
+
+fn read_height<R: Read>(r: &mut R) -> Result<BlockHeight, Error> {
+    Ok(BlockHeight::from(r.read_u32::<LittleEndian>()?))
+}
+
+fn write_account_id<W: Write>(w: &mut W, account_id: AccountId) -> Result<(), Error> {
+    Ok(w.write_u32::<LittleEndian>(account_id.0)?)
+}
+
+fn read_account_id<R: Read>(r: &mut R) -> Result<AccountId, Error> {
+    Ok(AccountId(r.read_u32::<LittleEndian>()?))
+}
+
+fn write_amount<W: Write>(w: &mut W, value: NonNegativeAmount) -> Result<(), Error> {
+    Ok(w.write_u64::<LittleEndian>(value.into())?)
+}
+
+fn read_amount<R: Read>(r: &mut R) -> Result<NonNegativeAmount, Error> {
+    Ok(NonNegativeAmount::from_u64(r.read_u64::<LittleEndian>()?)?)
+}
+
+fn write_memo<W: Write>(w: &mut W, memo: Option<&MemoBytes>) -> Result<(), Error> {
+    write_opt(w, memo, |w, memo| write_bytes(w, memo.as_slice()))
+}
+
+fn read_memo<R: Read>(r: &mut R) -> Result<Option<MemoBytes>, Error> {
+    read_opt(r, |r| Ok(MemoBytes::from_bytes(&read_bytes(r)?)?))
+}
+
+fn write_pool_type<W: Write>(w: &mut W, pool: PoolType) -> Result<(), Error> {
+    Ok(w.write_u8(match pool {
+        PoolType::Transparent => 0,
+        PoolType::Shielded(ShieldedProtocol::Sapling) => 1,
+        PoolType::Shielded(ShieldedProtocol::Orchard) => 2,
+    })?)
+}
+
+fn read_pool_type<R: Read>(r: &mut R) -> Result<PoolType, Error> {
+    match r.read_u8()? {
+        0 => Ok(PoolType::TRANSPARENT),
+        1 => Ok(PoolType::SAPLING),
+        2 => Ok(PoolType::ORCHARD),
+        other => Err(Error::CorruptedData(format!(
+            "Unrecognized pool type: {}",
+            other
+        ))),
+    }
+}
+
+fn scan_priority_code(priority: ScanPriority) -> u8 {
+    match priority {
+        ScanPriority::Ignored => 0,
+        ScanPriority::Scanned => 1,
+        ScanPriority::Historic => 2,
+        ScanPriority::OpenAdjacent => 3,
+        ScanPriority::FoundNote => 4,
+        ScanPriority::ChainTip => 5,
+        ScanPriority::Verify => 6,
+    }
+}
+
+fn read_scan_priority<R: Read>(r: &mut R) -> Result<ScanPriority, Error> {
+    match r.read_u8()? {
+        0 => Ok(ScanPriority::Ignored),
+        1 => Ok(ScanPriority::Scanned),
+        2 => Ok(ScanPriority::Historic),
+        3 => Ok(ScanPriority::OpenAdjacent),
+        4 => Ok(ScanPriority::FoundNote),
+        5 => Ok(ScanPriority::ChainTip),
+        6 => Ok(ScanPriority::Verify),
+        other => Err(Error::CorruptedData(format!(
+            "Unrecognized scan priority: {}",
+            other
+        ))),
+    }
+}
+
+fn write_account<W: Write, P: consensus::Parameters>(
+    w: &mut W,
+    params: &P,
+    account: &Account,
+) -> Result<(), Error> {
+    write_account_id(w, account.account_id)?;
+    match account.kind {
+        AccountSource::Derived {
+            seed_fingerprint,
+            account_index,
+        } => {
+            w.write_u8(0)?;
+            w.write_all(&seed_fingerprint.to_bytes())?;
+            w.write_u32::<LittleEndian>(account_index.into())?;
+        }
+        AccountSource::Imported { purpose } => {
+            w.write_u8(1)?;
+            w.write_u8(match purpose {
+                AccountPurpose::Spending => 0,
+                AccountPurpose::ViewOnly => 1,
+            })?;
+        }
+    }
+    write_string(w, &account.ufvk.encode(params))?;
+    write_height(w, account.birthday_height)?;
+    w.write_u64::<LittleEndian>(account.birthday_sapling_tree_size)?;
+    write_opt(w, account.birthday_orchard_tree_size, |w, size| {
+        Ok(w.write_u64::<LittleEndian>(size)?)
+    })?;
+    write_opt(w, account.recover_until_height, write_height)?;
+    write_opt(w, account.name.as_deref(), write_string)?;
+    write_opt(w, account.key_source.as_deref(), write_string)?;
+    write_opt(w, account.imported_derivation, |w, derivation| {
+        w.write_all(&derivation.seed_fingerprint().to_bytes())?;
+        Ok(w.write_u32::<LittleEndian>(derivation.account_index().into())?)
+    })?;
+    write_seq(w, account.metadata.iter(), |w, (key, value)| {
+        write_string(w, key)?;
+        write_string(w, value)
+    })
+}
+
+fn read_account<R: Read, P: consensus::Parameters>(
+    r: &mut R,
+    params: &P,
+    version: u8,
+) -> Result<Account, Error> {
+    let account_id = read_account_id(r)?;
+    let kind = match r.read_u8()? {
+        0 => {
+            let seed_fingerprint = SeedFingerprint::from_bytes(read_array(r)?);
+            let account_index = zip32::AccountId::try_from(r.read_u32::<LittleEndian>()?)
+                .map_err(|_| Error::AccountIdOutOfRange)?;
+            AccountSource::Derived {
+                seed_fingerprint,
+                account_index,
+            }
+        }
+        1 => AccountSource::Imported {
+            purpose: match r.read_u8()? {
+                0 => AccountPurpose::Spending,
+                1 => AccountPurpose::ViewOnly,
+                other => {
+                    return Err(Error::CorruptedData(format!(
+                        "Unrecognized account purpose: {}",
+                        other
+                    )))
+                }
+            },
+        },
+        other => {
+            return Err(Error::CorruptedData(format!(
+                "Unrecognized account source: {}",
+                other
+            )))
+        }
+    };
+    let ufvk = UnifiedFullViewingKey::decode(params, &read_string(r)?)
+        .map_err(|e| Error::CorruptedData(format!("Failure to decode UFVK: {}", e)))?;
+
+    let birthday_height = read_height(r)?;
+    let birthday_sapling_tree_size = r.read_u64::<LittleEndian>()?;
+    let birthday_orchard_tree_size = read_opt(r, |r| Ok(r.read_u64::<LittleEndian>()?))?;
+    let recover_until_height = read_opt(r, read_height)?;
+
+    let (name, key_source, imported_derivation, metadata) = if version >= SER_V2 {
+        (
+            read_opt(r, read_string)?,
+            read_opt(r, read_string)?,
+            read_opt(r, |r| {
+                let seed_fingerprint = SeedFingerprint::from_bytes(read_array(r)?);
+                let account_index = zip32::AccountId::try_from(r.read_u32::<LittleEndian>()?)
+                    .map_err(|_| Error::AccountIdOutOfRange)?;
+                Ok(Zip32Derivation::new(seed_fingerprint, account_index))
+            })?,
+            read_seq(r, |r| Ok((read_string(r)?, read_string(r)?)))?,
+        )
+    } else {
+        (None, None, None, BTreeMap::new())
+    };
+
+    Ok(Account {
+        account_id,
+        kind,
+        ufvk,
+        birthday_height,
+        birthday_sapling_tree_size,
+        birthday_orchard_tree_size,
+        recover_until_height,
+        name,
+        key_source,
+        imported_derivation,
+        metadata,
+    })
+}
+
+fn read_unified_address<R: Read, P: consensus::Parameters>(
+    r: &mut R,
+    params: &P,
+) -> Result<UnifiedAddress, Error> {
+    let encoded = read_string(r)?;
+    match Address::decode(params, &encoded) {
+        Some(Address::Unified(ua)) => Ok(ua),
+        _ => Err(Error::CorruptedData(format!(
+            "Unable to decode Unified Address: {}",
+            encoded
+        ))),
+    }
+}
+
+fn write_block<W: Write>(w: &mut W, block: &BlockRecord) -> Result<(), Error> {
+    w.write_all(&block.hash.0)?;
+    w.write_u32::<LittleEndian>(block.time)?;
+    w.write_u32::<LittleEndian>(block.sapling_commitment_tree_size)?;
+    w.write_u32::<LittleEndian>(block.sapling_output_count)?;
+    write_opt(w, block.orchard_commitment_tree_size, |w, size| {
+        Ok(w.write_u32::<LittleEndian>(size)?)
+    })?;
+    write_opt(w, block.orchard_action_count, |w, count| {
+        Ok(w.write_u32::<LittleEndian>(count)?)
+    })
+}
+
+fn read_block<R: Read>(r: &mut R) -> Result<BlockRecord, Error> {
+    Ok(BlockRecord {
+        hash: BlockHash(read_array(r)?),
+        time: r.read_u32::<LittleEndian>()?,
+        sapling_commitment_tree_size: r.read_u32::<LittleEndian>()?,
+        sapling_output_count: r.read_u32::<LittleEndian>()?,
+        orchard_commitment_tree_size: read_opt(r, |r| Ok(r.read_u32::<LittleEndian>()?))?,
+        orchard_action_count: read_opt(r, |r| Ok(r.read_u32::<LittleEndian>()?))?,
+    })
+}
+
+fn write_transaction<W: Write>(w: &mut W, tx: &TransactionRecord) -> Result<(), Error> {
+    write_opt(w, tx.block, write_height)?;
+    write_opt(w, tx.mined_height, write_height)?;
+    write_opt(w, tx.tx_index, |w, index| {
+        Ok(w.write_u16::<LittleEndian>(index)?)
+    })?;
+    write_opt(w, tx.expiry_height, write_height)?;
+    write_opt(w, tx.raw.as_deref(), write_bytes)?;
+    write_opt(w, tx.fee, write_amount)?;
+    write_opt(w, tx.target_height, write_height)?;
+    #[cfg(feature = "fiat")]
+    write_seq(w, tx.fiat_rates.iter(), |w, (currency, rate)| {
+        write_string(w, currency.code())?;
+        Ok(w.write_all(&rate.serialize())?)
+    })?;
+    #[cfg(not(feature = "fiat"))]
+    w.write_u8(0)?;
+    Ok(())
+}
+
+fn read_transaction<R: Read>(r: &mut R) -> Result<TransactionRecord, Error> {
+    let tx = TransactionRecord {
+        block: read_opt(r, read_height)?,
+        mined_height: read_opt(r, read_height)?,
+        tx_index: read_opt(r, |r| Ok(r.read_u16::<LittleEndian>()?))?,
+        expiry_height: read_opt(r, read_height)?,
+        raw: read_opt(r, read_bytes)?,
+        fee: read_opt(r, read_amount)?,
+        target_height: read_opt(r, read_height)?,
+        #[cfg(feature = "fiat")]
+        fiat_rates: read_seq(r, |r| {
+            let code = read_string(r)?;
+            let currency = Currency::from_code(&code)
+                .ok_or_else(|| Error::CorruptedData(format!("Unknown fiat currency: {}", code)))?;
+            Ok((currency, Decimal::deserialize(read_array(r)?)))
+        })?,
+    };
+    #[cfg(not(feature = "fiat"))]
+    if r.read_u8()? != 0 {
+        return Err(Error::CorruptedData(
+            "Fiat exchange rates require the `fiat` feature".to_owned(),
+        ));
+    }
+    Ok(tx)
+}
+
+fn write_note<W: Write>(w: &mut W, note: &Note) -> Result<(), Error> {
+    match note {
+        Note::Sapling(note) => {
+            w.write_u8(0)?;
+            w.write_all(&note.recipient().to_bytes())?;
+            w.write_u64::<LittleEndian>(note.value().inner())?;
+            match note.rseed() {
+                sapling::Rseed::BeforeZip212(rcm) => {
+                    w.write_u8(0)?;
+                    w.write_all(&rcm.to_bytes())?;
+                }
+                sapling::Rseed::AfterZip212(rseed) => {
+                    w.write_u8(1)?;
+                    w.write_all(rseed)?;
+                }
+            }
+        }
+        #[cfg(feature = "orchard")]
+        Note::Orchard(note) => {
+            w.write_u8(1)?;
+            w.write_all(&note.recipient().to_raw_address_bytes())?;
+            w.write_u64::<LittleEndian>(note.value().inner())?;
+            w.write_all(&note.rho().to_bytes())?;
+            w.write_all(note.rseed().as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_note<R: Read>(r: &mut R) -> Result<Note, Error> {
+    match r.read_u8()? {
+        0 => {
+            let recipient =
+                sapling::PaymentAddress::from_bytes(&read_array(r)?).ok_or_else(|| {
+                    Error::CorruptedData("Invalid Sapling payment address".to_owned())
+                })?;
+            let value = sapling::value::NoteValue::from_raw(r.read_u64::<LittleEndian>()?);
+            let rseed = match r.read_u8()? {
+                0 => sapling::Rseed::BeforeZip212(
+                    Option::from(jubjub::Fr::from_bytes(&read_array(r)?)).ok_or_else(|| {
+                        Error::CorruptedData("Invalid Sapling note commitment trapdoor".to_owned())
+                    })?,
+                ),
+                1 => sapling::Rseed::AfterZip212(read_array(r)?),
+                other => {
+                    return Err(Error::CorruptedData(format!(
+                        "Unrecognized Sapling rseed type: {}",
+                        other
+                    )))
+                }
+            };
+            Ok(Note::Sapling(sapling::Note::from_parts(
+                recipient, value, rseed,
+            )))
+        }
+        #[cfg(feature = "orchard")]
+        1 => {
+            let recipient = Option::from(orchard::Address::from_raw_address_bytes(&read_array(r)?))
+                .ok_or_else(|| Error::CorruptedData("Invalid Orchard address".to_owned()))?;
+            let value = orchard::value::NoteValue::from_raw(r.read_u64::<LittleEndian>()?);
+            let rho = Option::from(orchard::note::Rho::from_bytes(&read_array(r)?))
+                .ok_or_else(|| Error::CorruptedData("Invalid Orchard rho".to_owned()))?;
+            let rseed = Option::from(orchard::note::RandomSeed::from_bytes(read_array(r)?, &rho))
+                .ok_or_else(|| Error::CorruptedData("Invalid Orchard rseed".to_owned()))?;
+            Option::from(orchard::Note::from_parts(recipient, value, rho, rseed))
+                .map(Note::Orchard)
+                .ok_or_else(|| Error::CorruptedData("Invalid Orchard note".to_owned()))
+        }
+        #[cfg(not(feature = "orchard"))]
+        1 => Err(Error::UnsupportedPoolType(PoolType::ORCHARD)),
+        other => Err(Error::CorruptedData(format!(
+            "Unrecognized note type: {}",
+            other
+        ))),
+    }
+}
+
+fn write_received_note<W: Write>(w: &mut W, note: &ReceivedNoteRecord) -> Result<(), Error> {
+    note.txid.write(&mut *w)?;
+    w.write_u32::<LittleEndian>(note.output_index)?;
+    write_account_id(w, note.account_id)?;
+    write_note(w, &note.note)?;
+    write_opt(w, note.nf.as_ref(), |w, nf| Ok(w.write_all(nf)?))?;
+    w.write_u8(u8::from(note.is_change))?;
+    write_memo(w, note.memo.as_ref())?;
+    write_opt(w, note.commitment_tree_position, |w, position| {
+        Ok(w.write_u64::<LittleEndian>(position.into())?)
+    })?;
+    write_opt(w, note.recipient_key_scope, |w, scope| {
+        Ok(w.write_u8(match scope {
+            Scope::External => 0,
+            Scope::Internal => 1,
+        })?)
+    })
+}
+
+fn read_received_note<R: Read>(r: &mut R) -> Result<ReceivedNoteRecord, Error> {
+    Ok(ReceivedNoteRecord {
+        txid: TxId::read(&mut *r)?,
+        output_index: r.read_u32::<LittleEndian>()?,
+        account_id: read_account_id(r)?,
+        note: read_note(r)?,
+        nf: read_opt(r, read_array)?,
+        is_change: read_bool(r)?,
+        memo: read_memo(r)?,
+        commitment_tree_position: read_opt(r, |r| {
+            Ok(Position::from(r.read_u64::<LittleEndian>()?))
+        })?,
+        recipient_key_scope: read_opt(r, |r| match r.read_u8()? {
+            0 => Ok(Scope::External),
+            1 => Ok(Scope::Internal),
+            other => Err(Error::CorruptedData(format!(
+                "Unrecognized key scope: {}",
+                other
+            ))),
+        })?,
+    })
+}
+
+fn read_bool<R: Read>(r: &mut R) -> Result<bool, Error> {
+    match r.read_u8()? {
+        0 => Ok(false),
+        1 => Ok(true),
+        other => Err(Error::CorruptedData(format!(
+            "Non-canonical boolean value: {}",
+            other
+        ))),
+    }
+}
+
+fn write_sent_output<W: Write>(w: &mut W, output: &SentOutputRecord) -> Result<(), Error> {
+    write_account_id(w, output.from_account_id)?;
+    write_opt(w, output.to_address.as_deref(), write_string)?;
+    write_opt(w, output.to_account_id, write_account_id)?;
+    write_amount(w, output.value)?;
+    write_memo(w, output.memo.as_ref())
+}
+
+fn read_sent_output<R: Read>(r: &mut R) -> Result<SentOutputRecord, Error> {
+    Ok(SentOutputRecord {
+        from_account_id: read_account_id(r)?,
+        to_address: read_opt(r, read_string)?,
+        to_account_id: read_opt(r, read_account_id)?,
+        value: read_amount(r)?,
+        memo: read_memo(r)?,
+    })
+}
+
+fn write_tree<W: Write, H: HashSer>(
+    w: &mut W,
+    tree: &MemoryShardStore<H, BlockHeight>,
+) -> Result<(), Error> {
+    write_seq(w, tree.shards.iter(), |w, (index, shard)| {
+        w.write_u64::<LittleEndian>(*index)?;
+        w.write_u8(shard.root_addr().level().into())?;
+        w.write_u64::<LittleEndian>(shard.root_addr().index())?;
+        Ok(write_shard(w, shard.root())?)
+    })?;
+    write_seq(w, tree.shard_end_heights.iter(), |w, (index, height)| {
+        w.write_u64::<LittleEndian>(*index)?;
+        write_height(w, *height)
+    })?;
+    write_seq(w, tree.checkpoints.iter(), |w, (height, checkpoint)| {
+        write_height(w, *height)?;
+        match checkpoint.tree_state() {
+            TreeState::Empty => w.write_u8(0)?,
+            TreeState::AtPosition(position) => {
+                w.write_u8(1)?;
+                w.write_u64::<LittleEndian>(position.into())?;
+            }
+        }
+        write_seq(w, checkpoint.marks_removed().iter(), |w, position| {
+            Ok(w.write_u64::<LittleEndian>((*position).into())?)
+        })
+    })?;
+    Ok(write_shard(w, &tree.cap)?)
+}
+
+fn read_tree<R: Read, H: HashSer>(r: &mut R) -> Result<MemoryShardStore<H, BlockHeight>, Error> {
+    let mut tree = MemoryShardStore::empty();
+    tree.shards = read_seq(r, |r| {
+        let index = r.read_u64::<LittleEndian>()?;
+        let root_addr =
+            TreeAddress::from_parts(Level::from(r.read_u8()?), r.read_u64::<LittleEndian>()?);
+        let shard = read_shard(&mut *r)?;
+        Ok((index, LocatedTree::from_parts(root_addr, shard)))
+    })?;
+    tree.shard_end_heights = read_seq(r, |r| Ok((r.read_u64::<LittleEndian>()?, read_height(r)?)))?;
+    tree.checkpoints = read_seq(r, |r| {
+        let height = read_height(r)?;
+        let tree_state = match r.read_u8()? {
+            0 => TreeState::Empty,
+            1 => TreeState::AtPosition(Position::from(r.read_u64::<LittleEndian>()?)),
+            other => {
+                return Err(Error::CorruptedData(format!(
+                    "Unrecognized checkpoint tree state: {}",
+                    other
+                )))
+            }
+        };
+        let marks_removed: BTreeSet<Position> =
+            read_seq(r, |r| Ok(Position::from(r.read_u64::<LittleEndian>()?)))?;
+        Ok((height, Checkpoint::from_parts(tree_state, marks_removed)))
+    })?;
+    tree.cap = read_shard(r)?;
+    Ok(tree)
+}
+
+#[cfg(feature = "transparent-inputs")]
+fn write_transparent_address<W: Write>(
+    w: &mut W,
+    address: &TransparentAddress,
+) -> Result<(), Error> {
+    match address {
+        TransparentAddress::PublicKeyHash(hash) => {
+            w.write_u8(0)?;
+            w.write_all(hash)?;
+        }
+        TransparentAddress::ScriptHash(hash) => {
+            w.write_u8(1)?;
+            w.write_all(hash)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "transparent-inputs")]
+fn read_transparent_address<R: Read>(r: &mut R) -> Result<TransparentAddress, Error> {
+    match r.read_u8()? {
+        0 => Ok(TransparentAddress::PublicKeyHash(read_array(r)?)),
+        1 => Ok(TransparentAddress::ScriptHash(read_array(r)?)),
+        other => Err(Error::CorruptedData(format!(
+            "Unrecognized transparent address type: {}",
+            other
+        ))),
+    }
+}
+
+#[cfg(feature = "transparent-inputs")]
+fn write_transparent_output<W: Write>(
+    w: &mut W,
+    output: &TransparentOutputRecord,
+) -> Result<(), Error> {
+    write_account_id(w, output.account_id)?;
+    write_transparent_address(w, &output.address)?;
+    output.txout.write(&mut *w)?;
+    write_opt(w, output.max_observed_unspent_height, write_height)
+}
+
+#[cfg(feature = "transparent-inputs")]
+fn read_transparent_output<R: Read>(r: &mut R) -> Result<TransparentOutputRecord, Error> {
+    Ok(TransparentOutputRecord {
+        account_id: read_account_id(r)?,
+        address: read_transparent_address(r)?,
+        txout: TxOut::read(r)?,
+        max_observed_unspent_height: read_opt(r, read_height)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{
+        testing::{pool::ShieldedPoolTester, sapling::SaplingPoolTester, AddressType, TestBuilder},
+        Account as _, WalletRead, WalletWrite,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use crate::{
+        testing::{BlockCache, MemoryWalletDbFactory},
+        MemoryWalletDb,
+    };
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(MemoryWalletDbFactory)
+            .with_block_cache(BlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = SaplingPoolTester::test_account_fvk(&st);
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 1);
+
+        st.wallet_mut()
+            .set_account_name(account.id(), Some("Savings"))
+            .unwrap();
+        st.wallet_mut()
+            .set_account_key_source(account.id(), Some("keystone"), None)
+            .unwrap();
+        st.wallet_mut()
+            .set_account_metadata(account.id(), "color", Some("blue"))
+            .unwrap();
+
+        let network = *st.network();
+        let bytes = st.wallet().to_bytes().unwrap();
+        let restored = MemoryWalletDb::from_bytes(network, &bytes).unwrap();
+
+        assert_eq!(restored.to_bytes().unwrap(), bytes);
+        assert_eq!(restored.get_account_ids().unwrap(), vec![account.id()]);
+        let restored_account = restored.get_account(account.id()).unwrap().unwrap();
+        assert_eq!(restored_account.name(), Some("Savings"));
+        assert_eq!(restored_account.key_source(), Some("keystone"));
+        assert_eq!(restored_account.derivation(), account.derivation());
+        assert_eq!(restored_account.metadata()["color"], "blue");
+        assert_eq!(restored.chain_height().unwrap(), Some(h));
+        assert_eq!(
+            restored
+                .get_wallet_summary(0)
+                .unwrap()
+                .unwrap()
+                .account_balances()[&account.id()]
+                .total(),
+            value
+        );
+
+        // Truncated and trailing data are both rejected.
+        assert!(MemoryWalletDb::from_bytes(network, &bytes[..bytes.len() - 1]).is_err());
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(MemoryWalletDb::from_bytes(network, &extended).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "fiat")]
+    fn snapshot_fiat_rates() {
+        use rust_decimal::Decimal;
+        use zcash_client_backend::{
+            data_api::{FiatRate, TransactionHistoryFilter},
+            fiat::Currency,
+        };
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(MemoryWalletDbFactory)
+            .with_block_cache(BlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        let dfvk = SaplingPoolTester::test_account_fvk(&st);
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 1);
+
+        let filter = TransactionHistoryFilter::new();
+        let txid = st.wallet().get_transaction_history(&filter, 0, 10).unwrap()[0].txid();
+        let rate = FiatRate::new(Currency::Eur, Decimal::new(3700, 2));
+        st.wallet_mut()
+            .set_transaction_fiat_rate(txid, rate.clone())
+            .unwrap();
+
+        let network = *st.network();
+        let bytes = st.wallet().to_bytes().unwrap();
+        let restored = MemoryWalletDb::from_bytes(network, &bytes).unwrap();
+
+        let history = restored.get_transaction_history(&filter, 0, 10).unwrap();
+        assert_eq!(history[0].fiat_rates(), &[rate]);
+        assert_eq!(
+            history[0].fiat_value_delta(Currency::Eur),
+            Some(Decimal::new(185, 4))
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+
+use zcash_client_backend::{
+    data_api::{
+        chain::{error::Error as ChainError, BlockSource},
+        testing::{DataStoreFactory, NoteCommitments, Reset, TestCache, TestState},
+    },
+    proto::compact_formats::CompactBlock,
+};
+use zcash_protocol::{consensus::BlockHeight, local_consensus::LocalNetwork};
+
+use crate::{error::Error, AccountId, MemoryWalletDb};
+
+pub(crate) mod pool;
+
+/// A block cache that holds compact blocks in memory.
+#[derive(Default)]
+pub(crate) struct BlockCache(BTreeMap<BlockHeight, CompactBlock>);
+
+impl BlockCache {
+    pub(crate) fn new() -> Self {
+        BlockCache(BTreeMap::new())
+    }
+}
+
+impl BlockSource for BlockCache {
+    type Error = Infallible;
+
+    fn with_blocks<F, WalletErrT>(
+        &self,
+        from_height: Option<BlockHeight>,
+        limit: Option<usize>,
+        mut with_block: F,
+    ) -> Result<(), ChainError<WalletErrT, Self::Error>>
+    where
+        F: FnMut(CompactBlock) -> Result<(), ChainError<WalletErrT, Self::Error>>,
+    {
+        for cb in self
+            .0
+            .range(from_height.unwrap_or_else(|| BlockHeight::from(0))..)
+            .map(|(_, cb)| cb)
+            .take(limit.unwrap_or(usize::MAX))
+        {
+            with_block(cb.clone())?;
+        }
+        Ok(())
+    }
+}
+
+impl TestCache for BlockCache {
+    type BsError = Infallible;
+    type BlockSource = BlockCache;
+    type InsertResult = NoteCommitments;
+
+    fn block_source(&self) -> &Self::BlockSource {
+        self
+    }
+
+    fn insert(&mut self, cb: &CompactBlock) -> Self::InsertResult {
+        let res = NoteCommitments::from_compact_block(cb);
+        self.0.insert(cb.height(), cb.clone());
+        res
+    }
+
+    fn truncate_to_height(&mut self, height: BlockHeight) {
+        self.0.retain(|h, _| *h <= height);
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct MemoryWalletDbFactory;
+
+impl DataStoreFactory for MemoryWalletDbFactory {
+    type Error = ();
+    type AccountId = AccountId;
+    type Account = crate::wallet::Account;
+    type DsError = Error;
+    type DataStore = MemoryWalletDb<LocalNetwork>;
+
+    fn new_data_store(&self, network: LocalNetwork) -> Result<Self::DataStore, Self::Error> {
+        Ok(MemoryWalletDb::new(network))
+    }
+}
+
+impl Reset for MemoryWalletDb<LocalNetwork> {
+    type Handle = ();
+
+    fn reset<C>(st: &mut TestState<C, Self, LocalNetwork>) -> Self::Handle {
+        let network = *st.network();
+        let _ = std::mem::replace(st.wallet_mut(), MemoryWalletDb::new(network));
+    }
+}
@@ -0,0 +1,274 @@
+//! Test logic involving a single shielded pool.
+//!
+//! Generalised for sharing across the Sapling and Orchard implementations.
+
+use zcash_client_backend::data_api::testing::pool::ShieldedPoolTester;
+
+use crate::testing::{BlockCache, MemoryWalletDbFactory};
+
+pub(crate) fn send_single_step_proposed_transfer<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::send_single_step_proposed_transfer::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "pczt")]
+pub(crate) fn pczt_single_step<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::pczt_single_step::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "pczt")]
+pub(crate) fn pczt_single_step_sapling_only_ufvk() {
+    zcash_client_backend::data_api::testing::pool::pczt_single_step_sapling_only_ufvk(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "pczt")]
+pub(crate) fn send_single_step_with_signer<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::send_single_step_with_signer::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn send_with_multiple_change_outputs<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::send_with_multiple_change_outputs::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "transparent-inputs")]
+pub(crate) fn send_multi_step_proposed_transfer<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::send_multi_step_proposed_transfer::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+        |e, account_id, expected_bad_index| {
+            matches!(
+                e,
+                crate::error::Error::ReachedGapLimit(acct, bad_index)
+                if acct == &account_id && bad_index == &expected_bad_index)
+        },
+    )
+}
+
+#[cfg(feature = "transparent-inputs")]
+pub(crate) fn proposal_fails_if_not_all_ephemeral_outputs_consumed<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::proposal_fails_if_not_all_ephemeral_outputs_consumed::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn create_to_address_fails_on_incorrect_usk<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::create_to_address_fails_on_incorrect_usk::<T, _>(
+        MemoryWalletDbFactory,
+    )
+}
+
+pub(crate) fn proposal_fails_with_no_blocks<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::proposal_fails_with_no_blocks::<T, _>(
+        MemoryWalletDbFactory,
+    )
+}
+
+pub(crate) fn spend_fails_on_unverified_notes<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::spend_fails_on_unverified_notes::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn spend_fails_on_locked_notes<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::spend_fails_on_locked_notes::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn ovk_policy_prevents_recovery_from_chain<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::ovk_policy_prevents_recovery_from_chain::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn spend_succeeds_to_t_addr_zero_change<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::spend_succeeds_to_t_addr_zero_change::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn change_note_spends_succeed<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::change_note_spends_succeed::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn external_address_change_spends_detected_in_restore_from_seed<
+    T: ShieldedPoolTester,
+>() {
+    zcash_client_backend::data_api::testing::pool::external_address_change_spends_detected_in_restore_from_seed::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn zip317_spend<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::zip317_spend::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "transparent-inputs")]
+pub(crate) fn shield_transparent<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::shield_transparent::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn birthday_in_anchor_shard<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::birthday_in_anchor_shard::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn checkpoint_gaps<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::checkpoint_gaps::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "orchard")]
+pub(crate) fn pool_crossing_required<P0: ShieldedPoolTester, P1: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::pool_crossing_required::<P0, P1>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "orchard")]
+pub(crate) fn fully_funded_fully_private<P0: ShieldedPoolTester, P1: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::fully_funded_fully_private::<P0, P1>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(all(feature = "orchard", feature = "transparent-inputs"))]
+pub(crate) fn fully_funded_send_to_t<P0: ShieldedPoolTester, P1: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::fully_funded_send_to_t::<P0, P1>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "orchard")]
+pub(crate) fn multi_pool_checkpoint<P0: ShieldedPoolTester, P1: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::multi_pool_checkpoint::<P0, P1>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "orchard")]
+pub(crate) fn multi_pool_checkpoints_with_pruning<
+    P0: ShieldedPoolTester,
+    P1: ShieldedPoolTester,
+>() {
+    zcash_client_backend::data_api::testing::pool::multi_pool_checkpoints_with_pruning::<P0, P1>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn valid_chain_states<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::valid_chain_states::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "orchard")]
+pub(crate) fn invalid_chain_cache_disconnected<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::invalid_chain_cache_disconnected::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn data_db_truncation<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::data_db_truncation::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn reorg_to_checkpoint<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::reorg_to_checkpoint::<T, _, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn scan_cached_blocks_allows_blocks_out_of_order<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::scan_cached_blocks_allows_blocks_out_of_order::<T>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn scan_cached_blocks_finds_received_notes<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::scan_cached_blocks_finds_received_notes::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn account_deletion<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::account_deletion::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn transaction_history<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::transaction_history::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+// TODO: This test can probably be entirely removed, as the following test duplicates it entirely.
+pub(crate) fn scan_cached_blocks_finds_change_notes<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::scan_cached_blocks_finds_change_notes::<T, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn scan_cached_blocks_detects_spends_out_of_order<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::scan_cached_blocks_detects_spends_out_of_order::<
+        T,
+        _,
+    >(MemoryWalletDbFactory, BlockCache::new())
+}
+
+pub(crate) fn metadata_queries_exclude_unwanted_notes<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::metadata_queries_exclude_unwanted_notes::<T, _, _>(
+        MemoryWalletDbFactory,
+        BlockCache::new(),
+    )
+}
@@ -0,0 +1,1711 @@
+//! Functions for querying and updating the state of the in-memory wallet.
+
+use std::collections::{BTreeMap, HashSet};
+use std::num::NonZeroU32;
+
+use incrementalmerkletree::{Marking, Position, Retention};
+use secrecy::{ExposeSecret, SecretVec};
+use shardtree::ShardTree;
+use tracing::{debug, warn};
+use zip32::fingerprint::SeedFingerprint;
+
+use zcash_address::ZcashAddress;
+use zcash_client_backend::{
+    data_api::{
+        scanning::{ScanPriority, ScanRange},
+        Account as _, AccountBirthday, AccountSource, BlockMetadata, DecryptedTransaction,
+        SentTransaction, SentTransactionOutput, TransactionStatus, Zip32Derivation,
+    },
+    keys::UnifiedFullViewingKey,
+    wallet::{Note, NoteId, Recipient},
+    DecryptedOutput, TransferType,
+};
+use zcash_keys::{
+    address::{Address, Receiver, UnifiedAddress},
+    keys::{
+        AddressGenerationError, UnifiedAddressRequest, UnifiedIncomingViewingKey,
+        UnifiedSpendingKey,
+    },
+};
+use zcash_primitives::{
+    block::BlockHash,
+    consensus::{self, BlockHeight, BranchId, NetworkUpgrade},
+    memo::{Memo, MemoBytes},
+    transaction::{
+        components::{amount::NonNegativeAmount, OutPoint},
+        Transaction, TransactionData, TxId,
+    },
+};
+use zcash_protocol::{PoolType, ShieldedProtocol};
+use zip32::{DiversifierIndex, Scope};
+
+use crate::{
+    error::Error, AccountId, MemoryWalletDb, DEFAULT_UA_REQUEST, PRUNING_DEPTH, VERIFY_LOOKAHEAD,
+};
+
+#[cfg(feature = "transparent-inputs")]
+use zcash_primitives::transaction::components::TxOut;
+
+#[cfg(feature = "fiat")]
+use {rust_decimal::Decimal, zcash_client_backend::fiat::Currency};
+
+pub mod commitment_tree;
+pub(crate) mod history;
+pub(crate) mod notes;
+pub(crate) mod scanning;
+pub(crate) mod summary;
+#[cfg(feature = "transparent-inputs")]
+pub(crate) mod transparent;
+
+/// An account stored in the wallet.
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub(crate) account_id: AccountId,
+    pub(crate) kind: AccountSource,
+    pub(crate) ufvk: UnifiedFullViewingKey,
+    pub(crate) birthday_height: BlockHeight,
+    pub(crate) birthday_sapling_tree_size: u64,
+    pub(crate) birthday_orchard_tree_size: Option<u64>,
+    pub(crate) recover_until_height: Option<BlockHeight>,
+    pub(crate) name: Option<String>,
+    pub(crate) key_source: Option<String>,
+    /// The ZIP 32 derivation recorded for an imported account, if any.
+    pub(crate) imported_derivation: Option<Zip32Derivation>,
+    pub(crate) metadata: BTreeMap<String, String>,
+}
+
+impl Account {
+    /// Returns the default Unified Address for the account,
+    /// along with the diversifier index that generated it.
+    ///
+    /// The diversifier index may be non-zero if the Unified Address includes a Sapling
+    /// receiver, and there was no valid Sapling receiver at diversifier index zero.
+    pub(crate) fn default_address(
+        &self,
+        request: UnifiedAddressRequest,
+    ) -> Result<(UnifiedAddress, DiversifierIndex), AddressGenerationError> {
+        self.uivk().default_address(request)
+    }
+}
+
+impl zcash_client_backend::data_api::Account for Account {
+    type AccountId = AccountId;
+
+    fn id(&self) -> AccountId {
+        self.account_id
+    }
+
+    fn source(&self) -> AccountSource {
+        self.kind
+    }
+
+    fn ufvk(&self) -> Option<&UnifiedFullViewingKey> {
+        Some(&self.ufvk)
+    }
+
+    fn uivk(&self) -> UnifiedIncomingViewingKey {
+        self.ufvk.to_unified_incoming_viewing_key()
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn key_source(&self) -> Option<&str> {
+        self.key_source.as_deref()
+    }
+
+    fn derivation(&self) -> Option<Zip32Derivation> {
+        match self.kind {
+            AccountSource::Derived {
+                seed_fingerprint,
+                account_index,
+            } => Some(Zip32Derivation::new(seed_fingerprint, account_index)),
+            AccountSource::Imported { .. } => self.imported_derivation,
+        }
+    }
+
+    fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+}
+
+/// Metadata about a block that has been scanned by the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BlockRecord {
+    pub(crate) hash: BlockHash,
+    pub(crate) time: u32,
+    pub(crate) sapling_commitment_tree_size: u32,
+    pub(crate) sapling_output_count: u32,
+    pub(crate) orchard_commitment_tree_size: Option<u32>,
+    pub(crate) orchard_action_count: Option<u32>,
+}
+
+/// What the wallet knows about a transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct TransactionRecord {
+    /// The height of the scanned block containing this transaction, if any.
+    pub(crate) block: Option<BlockHeight>,
+    /// The height at which the transaction is known to have been mined, if any. This may be
+    /// set even if the block at that height has not been scanned.
+    pub(crate) mined_height: Option<BlockHeight>,
+    pub(crate) tx_index: Option<u16>,
+    /// The expiry height of the transaction, if the transaction data is known. An expiry
+    /// height of zero indicates that the transaction does not expire.
+    pub(crate) expiry_height: Option<BlockHeight>,
+    pub(crate) raw: Option<Vec<u8>>,
+    pub(crate) fee: Option<NonNegativeAmount>,
+    pub(crate) target_height: Option<BlockHeight>,
+    /// Exchange rates between ZEC and fiat currencies, keyed by currency.
+    #[cfg(feature = "fiat")]
+    pub(crate) fiat_rates: BTreeMap<Currency, Decimal>,
+}
+
+impl TransactionRecord {
+    /// Returns whether a spend in this transaction should be treated as effective as of the
+    /// given height: that is, whether the transaction is mined, will not expire, or has not
+    /// yet expired at that height.
+    pub(crate) fn is_unexpired_at(&self, height: BlockHeight) -> bool {
+        self.block.is_some() || self.expiry_height.map_or(true, |h| h > height)
+    }
+}
+
+/// A shielded note received by the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ReceivedNoteRecord {
+    pub(crate) txid: TxId,
+    pub(crate) output_index: u32,
+    pub(crate) account_id: AccountId,
+    pub(crate) note: Note,
+    pub(crate) nf: Option<[u8; 32]>,
+    pub(crate) is_change: bool,
+    pub(crate) memo: Option<MemoBytes>,
+    pub(crate) commitment_tree_position: Option<Position>,
+    pub(crate) recipient_key_scope: Option<Scope>,
+}
+
+/// An output of a transaction created by (or detected as having been funded by) the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SentOutputRecord {
+    pub(crate) from_account_id: AccountId,
+    /// The encoded recipient address, if the recipient is not an account in the wallet or
+    /// the address it was sent to is known.
+    pub(crate) to_address: Option<String>,
+    pub(crate) to_account_id: Option<AccountId>,
+    pub(crate) value: NonNegativeAmount,
+    pub(crate) memo: Option<MemoBytes>,
+}
+
+/// The kind of data that must be requested for a transaction in the retrieval queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TxQueryType {
+    Status,
+    Enhancement,
+}
+
+pub(crate) fn seed_matches_derived_account<P: consensus::Parameters>(
+    params: &P,
+    seed: &SecretVec<u8>,
+    seed_fingerprint: &SeedFingerprint,
+    account_index: zip32::AccountId,
+    uivk: &UnifiedIncomingViewingKey,
+) -> Result<bool, Error> {
+    let seed_fingerprint_match =
+        &SeedFingerprint::from_seed(seed.expose_secret()).ok_or_else(|| {
+            Error::BadAccountData("Seed must be between 32 and 252 bytes in length.".to_owned())
+        })? == seed_fingerprint;
+
+    // Keys are not comparable with `Eq`, but addresses are, so we derive what should
+    // be equivalent addresses for each key and use those to check for key equality.
+    let uivk_match =
+        match UnifiedSpendingKey::from_seed(params, &seed.expose_secret()[..], account_index) {
+            // If we can't derive a USK from the given seed with the account's ZIP 32
+            // account index, then we immediately know the UIVK won't match because wallet
+            // accounts are required to have a known UIVK.
+            Err(_) => false,
+            Ok(usk) => {
+                UnifiedAddressRequest::all().map_or(Ok::<_, Error>(false), |ua_request| {
+                    Ok(usk
+                        .to_unified_full_viewing_key()
+                        .default_address(ua_request)?
+                        == uivk.default_address(ua_request)?)
+                })?
+            }
+        };
+
+    if seed_fingerprint_match != uivk_match {
+        // If these mismatch, it suggests wallet corruption.
+        Err(Error::CorruptedData(format!(
+            "Seed fingerprint match: {seed_fingerprint_match}, uivk match: {uivk_match}"
+        )))
+    } else {
+        Ok(seed_fingerprint_match && uivk_match)
+    }
+}
+
+/// Returns the (Orchard, Sapling, transparent) components of the given UFVK in their
+/// serialized forms, for use in detecting collisions between accounts.
+fn ufvk_items(ufvk: &UnifiedFullViewingKey) -> [Option<Vec<u8>>; 3] {
+    #[cfg(feature = "orchard")]
+    let orchard_item = ufvk.orchard().map(|k| k.to_bytes().to_vec());
+    #[cfg(not(feature = "orchard"))]
+    let orchard_item: Option<Vec<u8>> = None;
+
+    let sapling_item = ufvk.sapling().map(|k| k.to_bytes().to_vec());
+
+    #[cfg(feature = "transparent-inputs")]
+    let transparent_item = ufvk.transparent().map(|k| k.serialize());
+    #[cfg(not(feature = "transparent-inputs"))]
+    let transparent_item: Option<Vec<u8>> = None;
+
+    [orchard_item, sapling_item, transparent_item]
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the minimum birthday height for accounts in the wallet.
+    pub(crate) fn wallet_birthday(&self) -> Option<BlockHeight> {
+        self.accounts.values().map(|a| a.birthday_height).min()
+    }
+
+    /// Returns the maximum recover-until height for accounts in the wallet.
+    pub(crate) fn recover_until_height(&self) -> Option<BlockHeight> {
+        self.accounts
+            .values()
+            .filter_map(|a| a.recover_until_height)
+            .max()
+    }
+
+    /// Returns the highest used account index for a given seed.
+    pub(crate) fn max_zip32_account_index(
+        &self,
+        seed_fingerprint: &SeedFingerprint,
+    ) -> Option<zip32::AccountId> {
+        self.accounts
+            .values()
+            .filter_map(|a| match a.kind {
+                AccountSource::Derived {
+                    seed_fingerprint: fp,
+                    account_index,
+                } if &fp == seed_fingerprint => Some(account_index),
+                _ => None,
+            })
+            .max_by_key(|account_index| u32::from(*account_index))
+    }
+
+    /// Returns the account corresponding to a given [`SeedFingerprint`] and
+    /// [`zip32::AccountId`], if any.
+    pub(crate) fn get_derived_account_inner(
+        &self,
+        seed: &SeedFingerprint,
+        account_index: zip32::AccountId,
+    ) -> Option<Account> {
+        self.accounts
+            .values()
+            .find(|a| {
+                matches!(a.kind, AccountSource::Derived {
+                    seed_fingerprint,
+                    account_index: idx,
+                } if &seed_fingerprint == seed && idx == account_index)
+            })
+            .cloned()
+    }
+
+    /// Returns the account that shares any component of the given [`UnifiedFullViewingKey`],
+    /// if any.
+    pub(crate) fn get_account_for_ufvk_inner(
+        &self,
+        ufvk: &UnifiedFullViewingKey,
+    ) -> Result<Option<Account>, Error> {
+        let items = ufvk_items(ufvk);
+        let accounts = self
+            .accounts
+            .values()
+            .filter(|a| {
+                ufvk_items(&a.ufvk)
+                    .iter()
+                    .zip(items.iter())
+                    .any(|(a, b)| a.is_some() && a == b)
+            })
+            .collect::<Vec<_>>();
+
+        if accounts.len() > 1 {
+            Err(Error::CorruptedData(
+                "Mutiple account records matched the provided UFVK".to_owned(),
+            ))
+        } else {
+            Ok(accounts.into_iter().next().cloned())
+        }
+    }
+
+    pub(crate) fn add_account(
+        &mut self,
+        kind: AccountSource,
+        ufvk: UnifiedFullViewingKey,
+        birthday: &AccountBirthday,
+    ) -> Result<Account, Error> {
+        // Check whether any component of this UFVK collides with an existing imported or derived FVK.
+        if let Some(existing_account) = self.get_account_for_ufvk_inner(&ufvk)? {
+            return Err(Error::AccountCollision(existing_account.id()));
+        }
+        if let AccountSource::Derived {
+            seed_fingerprint,
+            account_index,
+        } = kind
+        {
+            if let Some(existing_account) =
+                self.get_derived_account_inner(&seed_fingerprint, account_index)
+            {
+                return Err(Error::AccountCollision(existing_account.id()));
+            }
+        }
+
+        let account_id = self
+            .accounts
+            .keys()
+            .next_back()
+            .map_or(AccountId(0), |id| AccountId(id.0 + 1));
+
+        let account = Account {
+            account_id,
+            kind,
+            ufvk,
+            birthday_height: birthday.height(),
+            birthday_sapling_tree_size: birthday.sapling_frontier().tree_size(),
+            #[cfg(feature = "orchard")]
+            birthday_orchard_tree_size: Some(birthday.orchard_frontier().tree_size()),
+            #[cfg(not(feature = "orchard"))]
+            birthday_orchard_tree_size: None,
+            recover_until_height: birthday.recover_until(),
+            name: None,
+            key_source: None,
+            imported_derivation: None,
+            metadata: BTreeMap::new(),
+        };
+        self.accounts.insert(account_id, account.clone());
+
+        // If a birthday frontier is available, insert it into the note commitment tree. If the
+        // birthday frontier is the empty frontier, we don't need to do anything.
+        if let Some(frontier) = birthday.sapling_frontier().value() {
+            debug!("Inserting Sapling frontier into ShardTree: {:?}", frontier);
+            let mut shard_tree: ShardTree<
+                _,
+                { sapling::NOTE_COMMITMENT_TREE_DEPTH },
+                { zcash_client_backend::data_api::SAPLING_SHARD_HEIGHT },
+            > = ShardTree::new(&mut self.sapling_tree, PRUNING_DEPTH.try_into().unwrap());
+            shard_tree.insert_frontier_nodes(
+                frontier.clone(),
+                Retention::Checkpoint {
+                    // This subtraction is safe, because all leaves in the tree appear in blocks,
+                    // and `frontier` is the tree state at the end of the block preceding the
+                    // birthday height.
+                    id: birthday.height() - 1,
+                    marking: Marking::Reference,
+                },
+            )?;
+        }
+
+        #[cfg(feature = "orchard")]
+        if let Some(frontier) = birthday.orchard_frontier().value() {
+            debug!("Inserting Orchard frontier into ShardTree: {:?}", frontier);
+            let mut shard_tree: ShardTree<
+                _,
+                { orchard::NOTE_COMMITMENT_TREE_DEPTH as u8 },
+                { zcash_client_backend::data_api::ORCHARD_SHARD_HEIGHT },
+            > = ShardTree::new(&mut self.orchard_tree, PRUNING_DEPTH.try_into().unwrap());
+            shard_tree.insert_frontier_nodes(
+                frontier.clone(),
+                Retention::Checkpoint {
+                    // This subtraction is safe, because all leaves in the tree appear in blocks,
+                    // and `frontier` is the tree state at the end of the block preceding the
+                    // birthday height.
+                    id: birthday.height() - 1,
+                    marking: Marking::Reference,
+                },
+            )?;
+        }
+
+        // The ignored range always starts at Sapling activation
+        let sapling_activation_height = self
+            .params
+            .activation_height(NetworkUpgrade::Sapling)
+            .expect("Sapling activation height must be available.");
+
+        // Add the ignored range up to the birthday height.
+        if sapling_activation_height < birthday.height() {
+            let ignored_range = sapling_activation_height..birthday.height();
+
+            self.replace_queue_entries(
+                &ignored_range,
+                Some(ScanRange::from_parts(
+                    ignored_range.clone(),
+                    ScanPriority::Ignored,
+                ))
+                .into_iter(),
+                false,
+            );
+        };
+
+        // Rewrite the scan ranges from the birthday height up to the chain tip so that we'll
+        // ensure we re-scan to find any notes that might belong to the newly added account.
+        if let Some(t) = self.chain_tip_height() {
+            let rescan_range = birthday.height()..(t + 1);
+
+            self.replace_queue_entries(
+                &rescan_range,
+                Some(ScanRange::from_parts(
+                    rescan_range.clone(),
+                    ScanPriority::Historic,
+                ))
+                .into_iter(),
+                true, // force rescan
+            );
+        }
+
+        // Always derive the default Unified Address for the account. If the account's viewing
+        // key has fewer components than the wallet supports (most likely due to this being an
+        // imported viewing key), derive an address containing the common subset of receivers.
+        let ua_request = account
+            .uivk()
+            .to_address_request()
+            .and_then(|ua_request| ua_request.intersect(&DEFAULT_UA_REQUEST))
+            .ok_or(Error::AddressGeneration(
+                AddressGenerationError::ShieldedReceiverRequired,
+            ))?;
+        let (address, d_idx) = account.default_address(ua_request)?;
+        self.insert_address(account_id, d_idx, address);
+
+        // Initialize the ephemeral addresses for the account.
+        #[cfg(feature = "transparent-inputs")]
+        self.init_ephemeral_addresses(account_id)?;
+
+        Ok(account)
+    }
+
+    /// Returns the most recently generated address for the given account, along with the
+    /// diversifier index that generated it.
+    pub(crate) fn get_current_address_inner(
+        &self,
+        account_id: AccountId,
+    ) -> Option<(UnifiedAddress, DiversifierIndex)> {
+        self.addresses
+            .get(&account_id)
+            .and_then(|addrs| addrs.iter().next_back())
+            .and_then(|(idx, ua)| {
+                DiversifierIndex::try_from(*idx)
+                    .ok()
+                    .map(|d_idx| (ua.clone(), d_idx))
+            })
+    }
+
+    /// Adds the given address and diversifier index to the wallet's addresses.
+    pub(crate) fn insert_address(
+        &mut self,
+        account_id: AccountId,
+        diversifier_index: DiversifierIndex,
+        address: UnifiedAddress,
+    ) {
+        self.addresses
+            .entry(account_id)
+            .or_default()
+            .insert(u128::from(diversifier_index), address);
+    }
+
+    /// Returns the height of the chain tip, as known from the scan queue.
+    pub(crate) fn chain_tip_height(&self) -> Option<BlockHeight> {
+        // Scan ranges are end-exclusive, so we subtract 1 to obtain the height of the last
+        // known chain tip.
+        self.scan_queue
+            .iter()
+            .map(|r| r.block_range().end)
+            .max()
+            .map(|h| h.saturating_sub(1))
+    }
+
+    pub(crate) fn get_target_and_anchor_heights_inner(
+        &self,
+        min_confirmations: NonZeroU32,
+    ) -> Option<(BlockHeight, BlockHeight)> {
+        let chain_tip_height = self.chain_tip_height()?;
+        let max_checkpoint_height = BlockHeight::from(
+            u32::from(chain_tip_height).saturating_sub(u32::from(min_confirmations) - 1),
+        );
+
+        let sapling_anchor_height = self
+            .sapling_tree
+            .checkpoints
+            .range(..=max_checkpoint_height)
+            .next_back()
+            .map(|(h, _)| *h);
+
+        #[cfg(feature = "orchard")]
+        let orchard_anchor_height = self
+            .orchard_tree
+            .checkpoints
+            .range(..=max_checkpoint_height)
+            .next_back()
+            .map(|(h, _)| *h);
+        #[cfg(not(feature = "orchard"))]
+        let orchard_anchor_height: Option<BlockHeight> = None;
+
+        let anchor_height = sapling_anchor_height
+            .zip(orchard_anchor_height)
+            .map(|(s, o)| std::cmp::min(s, o))
+            .or(sapling_anchor_height)
+            .or(orchard_anchor_height);
+
+        anchor_height.map(|h| (chain_tip_height + 1, h))
+    }
+
+    pub(crate) fn block_metadata_inner(&self, block_height: BlockHeight) -> Option<BlockMetadata> {
+        self.blocks.get(&block_height).map(|block| {
+            BlockMetadata::from_parts(
+                block_height,
+                block.hash,
+                Some(block.sapling_commitment_tree_size),
+                #[cfg(feature = "orchard")]
+                if self
+                    .params
+                    .activation_height(NetworkUpgrade::Nu5)
+                    .iter()
+                    .any(|nu5_activation| &block_height >= nu5_activation)
+                {
+                    block.orchard_commitment_tree_size
+                } else {
+                    Some(0)
+                },
+            )
+        })
+    }
+
+    pub(crate) fn block_fully_scanned_inner(&self) -> Option<BlockMetadata> {
+        let birthday_height = self.wallet_birthday()?;
+
+        // The fully-scanned height is the last height that falls within the first range in
+        // the scan queue with priority "Scanned". If the start of that range is greater than
+        // the birthday height, then there is an unscanned range between the wallet birthday
+        // and that range, so there is no fully scanned height.
+        let fully_scanned_height = self
+            .scan_queue
+            .iter()
+            .filter(|r| r.priority() == ScanPriority::Scanned)
+            .min_by_key(|r| r.block_range().start)
+            .filter(|r| r.block_range().start <= birthday_height)
+            // Scan ranges are end-exclusive.
+            .map(|r| r.block_range().end - 1)?;
+
+        self.block_metadata_inner(fully_scanned_height)
+    }
+
+    /// Inserts information about a scanned block into the wallet.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn put_block(
+        &mut self,
+        block_height: BlockHeight,
+        block_hash: BlockHash,
+        block_time: u32,
+        sapling_commitment_tree_size: u32,
+        sapling_output_count: u32,
+        #[cfg(feature = "orchard")] orchard_commitment_tree_size: u32,
+        #[cfg(feature = "orchard")] orchard_action_count: u32,
+    ) -> Result<(), Error> {
+        // Ensure that in the case of an upsert, we don't overwrite block data
+        // with information for a block with a different hash.
+        if let Some(existing) = self.blocks.get(&block_height) {
+            if existing.hash != block_hash {
+                return Err(Error::BlockConflict(block_height));
+            }
+        }
+
+        #[cfg(not(feature = "orchard"))]
+        let (orchard_commitment_tree_size, orchard_action_count): (
+            Option<u32>,
+            Option<u32>,
+        ) = (None, None);
+        #[cfg(feature = "orchard")]
+        let (orchard_commitment_tree_size, orchard_action_count) = (
+            Some(orchard_commitment_tree_size),
+            Some(orchard_action_count),
+        );
+
+        self.blocks.insert(
+            block_height,
+            BlockRecord {
+                hash: block_hash,
+                time: block_time,
+                sapling_commitment_tree_size,
+                sapling_output_count,
+                orchard_commitment_tree_size,
+                orchard_action_count,
+            },
+        );
+
+        // If we now have a block corresponding to a received transparent output that had not
+        // been scanned at the time the UTXO was discovered, update the associated transaction
+        // record to refer to that block.
+        for tx in self.transactions.values_mut() {
+            if tx.mined_height == Some(block_height) {
+                tx.block = Some(block_height);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that a transaction was mined at the given index within the block at the
+    /// given height.
+    pub(crate) fn put_tx_meta(&mut self, txid: TxId, tx_index: u16, height: BlockHeight) {
+        let tx = self.transactions.entry(txid).or_default();
+        tx.block = Some(height);
+        tx.mined_height = Some(height);
+        tx.tx_index = Some(tx_index);
+    }
+
+    /// Stores the full data of a transaction.
+    pub(crate) fn put_tx_data(
+        &mut self,
+        tx: &Transaction,
+        fee: Option<NonNegativeAmount>,
+        target_height: Option<BlockHeight>,
+    ) -> Result<(), Error> {
+        let mut raw_tx = vec![];
+        tx.write(&mut raw_tx)?;
+
+        match self.transactions.get_mut(&tx.txid()) {
+            Some(record) => {
+                record.expiry_height = Some(tx.expiry_height());
+                record.raw = Some(raw_tx);
+                record.fee = fee.or(record.fee);
+            }
+            None => {
+                self.transactions.insert(
+                    tx.txid(),
+                    TransactionRecord {
+                        expiry_height: Some(tx.expiry_height()),
+                        raw: Some(raw_tx),
+                        fee,
+                        target_height,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the given transactions to the retrieval queue, if doing so would not be
+    /// redundant.
+    pub(crate) fn queue_tx_retrieval(
+        &mut self,
+        txids: impl Iterator<Item = TxId>,
+        dependent_tx: Option<TxId>,
+    ) {
+        for txid in txids {
+            let query_type = if self
+                .transactions
+                .get(&txid)
+                .map_or(false, |tx| tx.raw.is_some())
+            {
+                TxQueryType::Status
+            } else {
+                TxQueryType::Enhancement
+            };
+
+            let entry = self
+                .tx_retrieval_queue
+                .entry(txid)
+                .or_insert((query_type, dependent_tx));
+            entry.0 = query_type;
+            entry.1 = dependent_tx.or(entry.1);
+        }
+    }
+
+    pub(crate) fn notify_tx_retrieved(&mut self, txid: TxId) {
+        self.tx_retrieval_queue.remove(&txid);
+    }
+
+    pub(crate) fn set_transaction_status_inner(&mut self, txid: TxId, status: TransactionStatus) {
+        // It is safe to unconditionally remove the request from the retrieval queue below,
+        // because we already have all the data we need about this transaction; see the
+        // equivalent logic in the SQLite backend for details.
+        match status {
+            TransactionStatus::TxidNotRecognized | TransactionStatus::NotInMainChain => {
+                // If the transaction is now expired, remove it from the retrieval queue.
+                if let Some(chain_tip) = self.chain_tip_height() {
+                    let expired = self.transactions.get(&txid).map_or(false, |tx| {
+                        tx.expiry_height
+                            .map_or(false, |h| h < chain_tip.saturating_sub(VERIFY_LOOKAHEAD))
+                    });
+                    if expired {
+                        self.notify_tx_retrieved(txid);
+                    }
+                }
+            }
+            TransactionStatus::Mined(height) => {
+                // The transaction has been mined, so we can set its mined height, associate it
+                // with the appropriate block, and remove it from the retrieval queue.
+                let block_known = self.blocks.contains_key(&height);
+                if let Some(tx) = self.transactions.get_mut(&txid) {
+                    tx.mined_height = Some(height);
+                    if block_known {
+                        tx.block = Some(height);
+                    }
+                }
+
+                self.notify_tx_retrieved(txid);
+            }
+        }
+    }
+
+    /// Looks up a transaction by its [`TxId`].
+    pub(crate) fn get_transaction_inner(&self, txid: TxId) -> Result<Option<Transaction>, Error> {
+        let tx = match self.transactions.get(&txid) {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let tx_bytes = match &tx.raw {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+
+        // We need to provide a consensus branch ID so that pre-v5 `Transaction` structs
+        // (which don't commit directly to one) can store it internally.
+        // - If the transaction is mined, we use the block height to get the correct one.
+        // - If the transaction is unmined and has a cached non-zero expiry height, we use
+        //   that (relying on the invariant that a transaction can't be mined across a network
+        //   upgrade boundary, so the expiry height must be in the same epoch).
+        // - Otherwise, we use a placeholder for the initial transaction parse (as the
+        //   consensus branch ID is not used there), and then either use its non-zero expiry
+        //   height or return an error.
+        if let Some(height) = tx
+            .block
+            .or_else(|| tx.expiry_height.filter(|h| h > &BlockHeight::from(0)))
+        {
+            Ok(Some(Transaction::read(
+                &tx_bytes[..],
+                BranchId::for_height(&self.params, height),
+            )?))
+        } else {
+            let tx_data = Transaction::read(&tx_bytes[..], BranchId::Sprout)?.into_data();
+
+            let expiry_height = tx_data.expiry_height();
+            if expiry_height > BlockHeight::from(0) {
+                Ok(Some(
+                    TransactionData::from_parts(
+                        tx_data.version(),
+                        BranchId::for_height(&self.params, expiry_height),
+                        tx_data.lock_time(),
+                        expiry_height,
+                        tx_data.transparent_bundle().cloned(),
+                        tx_data.sprout_bundle().cloned(),
+                        tx_data.sapling_bundle().cloned(),
+                        tx_data.orchard_bundle().cloned(),
+                    )
+                    .freeze()?,
+                ))
+            } else {
+                Err(Error::CorruptedData(
+                    "Consensus branch ID not known, cannot parse this transaction until it is mined"
+                        .to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns the memo for a sent note, if the sent note is known to the wallet.
+    pub(crate) fn get_sent_memo(&self, note_id: NoteId) -> Result<Option<Memo>, Error> {
+        self.sent_outputs
+            .get(&(
+                *note_id.txid(),
+                PoolType::Shielded(note_id.protocol()),
+                u32::from(note_id.output_index()),
+            ))
+            .and_then(|output| output.memo.clone())
+            .map(|memo| Memo::try_from(memo).map_err(Error::from))
+            .transpose()
+    }
+
+    /// Returns the memo for a received note, if the note is known to the wallet.
+    pub(crate) fn get_received_memo(&self, note_id: NoteId) -> Result<Option<Memo>, Error> {
+        #[cfg(not(feature = "orchard"))]
+        if note_id.protocol() == ShieldedProtocol::Orchard {
+            return Err(Error::UnsupportedPoolType(PoolType::ORCHARD));
+        }
+
+        self.received_notes
+            .values()
+            .find(|n| {
+                &n.txid == note_id.txid()
+                    && n.note.protocol() == note_id.protocol()
+                    && n.output_index == u32::from(note_id.output_index())
+            })
+            .and_then(|n| n.memo.clone())
+            .map(|memo| Memo::try_from(memo).map_err(Error::from))
+            .transpose()
+    }
+
+    /// Returns the set of accounts that funded the given transaction, as determined by the
+    /// wallet's received notes and outputs spent in the transaction.
+    pub(crate) fn get_funding_accounts(&self, tx: &Transaction) -> HashSet<AccountId> {
+        let mut funding_accounts = HashSet::new();
+        #[cfg(feature = "transparent-inputs")]
+        funding_accounts.extend(
+            self.detect_transparent_spending_accounts(
+                tx.transparent_bundle()
+                    .iter()
+                    .flat_map(|bundle| bundle.vin.iter().map(|txin| &txin.prevout)),
+            ),
+        );
+
+        funding_accounts.extend(self.detect_spending_accounts(
+            ShieldedProtocol::Sapling,
+            tx.sapling_bundle().iter().flat_map(|bundle| {
+                bundle
+                    .shielded_spends()
+                    .iter()
+                    .map(|spend| spend.nullifier().0)
+            }),
+        ));
+
+        #[cfg(feature = "orchard")]
+        funding_accounts.extend(self.detect_spending_accounts(
+            ShieldedProtocol::Orchard,
+            tx.orchard_bundle().iter().flat_map(|bundle| {
+                bundle
+                    .actions()
+                    .iter()
+                    .map(|action| action.nullifier().to_bytes())
+            }),
+        ));
+
+        funding_accounts
+    }
+
+    /// Returns the most likely wallet address that corresponds to the protocol-level receiver
+    /// of a note or UTXO.
+    pub(crate) fn select_receiving_address(
+        &self,
+        account: AccountId,
+        receiver: &Receiver,
+    ) -> Option<ZcashAddress> {
+        match receiver {
+            #[cfg(feature = "transparent-inputs")]
+            Receiver::Transparent(taddr) => self
+                .addresses
+                .values()
+                .flat_map(|addrs| addrs.values())
+                .find(|ua| ua.transparent() == Some(taddr))
+                .map(|ua| Address::from(ua.clone()).to_zcash_address(&self.params)),
+            receiver => self
+                .addresses
+                .get(&account)
+                .into_iter()
+                .flat_map(|addrs| addrs.values())
+                .map(|ua| Address::from(ua.clone()).to_zcash_address(&self.params))
+                .find(|addr| receiver.corresponds(addr)),
+        }
+    }
+
+    pub(crate) fn store_decrypted_tx_inner(
+        &mut self,
+        d_tx: DecryptedTransaction<AccountId>,
+    ) -> Result<(), Error> {
+        let txid = d_tx.tx().txid();
+        self.put_tx_data(d_tx.tx(), None, None)?;
+        if let Some(height) = d_tx.mined_height() {
+            self.set_transaction_status_inner(txid, TransactionStatus::Mined(height));
+        }
+
+        let funding_accounts = self.get_funding_accounts(d_tx.tx());
+
+        // TODO(#1305): Correctly track accounts that fund each transaction output.
+        let funding_account = funding_accounts.iter().next().copied();
+        if funding_accounts.len() > 1 {
+            warn!(
+                "More than one wallet account detected as funding transaction {:?}, selecting {:?}",
+                txid,
+                funding_account.unwrap()
+            )
+        }
+
+        // A flag used to determine whether it is necessary to query for transactions that
+        // provided transparent inputs to this transaction, in order to be able to correctly
+        // recover transparent transaction history.
+        #[cfg(feature = "transparent-inputs")]
+        let mut tx_has_wallet_outputs = false;
+
+        for output in d_tx.sapling_outputs() {
+            #[cfg(feature = "transparent-inputs")]
+            {
+                tx_has_wallet_outputs = true;
+            }
+            self.put_decrypted_output(
+                txid,
+                output,
+                Note::Sapling(output.note().clone()),
+                Receiver::Sapling(output.note().recipient()),
+                funding_account,
+            )?;
+        }
+
+        #[cfg(feature = "orchard")]
+        for output in d_tx.orchard_outputs() {
+            #[cfg(feature = "transparent-inputs")]
+            {
+                tx_has_wallet_outputs = true;
+            }
+            self.put_decrypted_output(
+                txid,
+                output,
+                Note::Orchard(*output.note()),
+                Receiver::Orchard(output.note().recipient()),
+                funding_account,
+            )?;
+        }
+
+        // If any of the utxos spent in the transaction are ours, mark them as spent.
+        #[cfg(feature = "transparent-inputs")]
+        for txin in d_tx
+            .tx()
+            .transparent_bundle()
+            .iter()
+            .flat_map(|b| b.vin.iter())
+        {
+            self.mark_transparent_utxo_spent(txid, &txin.prevout);
+        }
+
+        // This `if` is just an optimization for cases where we would do nothing in the loop.
+        if funding_account.is_some() || cfg!(feature = "transparent-inputs") {
+            for (output_index, txout) in d_tx
+                .tx()
+                .transparent_bundle()
+                .iter()
+                .flat_map(|b| b.vout.iter())
+                .enumerate()
+            {
+                if let Some(address) = txout.recipient_address() {
+                    debug!(
+                        "{:?} output {} has recipient {}",
+                        txid,
+                        output_index,
+                        Address::Transparent(address).encode(&self.params)
+                    );
+
+                    // The transaction is not necessarily mined yet, but we want to record
+                    // that an output to the address was seen in this tx anyway. This will
+                    // advance the gap regardless of whether it is mined, but an output in
+                    // an unmined transaction won't advance the range of safe indices.
+                    #[cfg(feature = "transparent-inputs")]
+                    self.mark_ephemeral_address_as_seen(&address, txid)?;
+
+                    // If the output belongs to the wallet, add it to the wallet's
+                    // transparent outputs.
+                    #[cfg(feature = "transparent-inputs")]
+                    if let Some(account_id) = self.find_account_for_transparent_address(&address) {
+                        debug!(
+                            "{:?} output {} belongs to account {:?}",
+                            txid, output_index, account_id
+                        );
+                        let outpoint =
+                            OutPoint::new(txid.into(), u32::try_from(output_index).unwrap());
+                        self.put_transparent_output(
+                            &outpoint,
+                            txout,
+                            d_tx.mined_height(),
+                            &address,
+                            account_id,
+                            false,
+                        )?;
+
+                        // Since the wallet created the transparent output, we need to ensure
+                        // that any transparent inputs belonging to the wallet will be
+                        // discovered.
+                        tx_has_wallet_outputs = true;
+
+                        // When we receive transparent funds (particularly as ephemeral outputs
+                        // in transaction pairs sending to a ZIP 320 address) it becomes
+                        // possible that the spend of these outputs is not then later detected
+                        // if the transaction that spends them is purely transparent. This is
+                        // especially a problem in wallet recovery.
+                        self.queue_transparent_spend_detection(address, outpoint);
+                    } else {
+                        debug!(
+                            "Address {} is not recognized as belonging to any of our accounts.",
+                            Address::Transparent(address).encode(&self.params)
+                        );
+                    }
+
+                    // If a transaction we observe contains spends from our wallet, we will
+                    // store its transparent outputs in the same way they would be stored by
+                    // create_spend_to_address.
+                    if let Some(account_id) = funding_account {
+                        let receiver = Receiver::Transparent(address);
+
+                        let recipient_addr = self
+                            .select_receiving_address(account_id, &receiver)
+                            .unwrap_or_else(|| {
+                                receiver.to_zcash_address(self.params.network_type())
+                            });
+
+                        let recipient = Recipient::External(recipient_addr, PoolType::TRANSPARENT);
+
+                        self.put_sent_output(
+                            account_id,
+                            txid,
+                            output_index,
+                            &recipient,
+                            txout.value,
+                            None,
+                        );
+
+                        // Even though we know the funding account, we don't know that we have
+                        // information for all of the transparent inputs to the transaction.
+                        #[cfg(feature = "transparent-inputs")]
+                        {
+                            tx_has_wallet_outputs = true;
+                        }
+                    }
+                } else {
+                    warn!(
+                        "Unable to determine recipient address for tx {:?} output {}",
+                        txid, output_index
+                    );
+                }
+            }
+        }
+
+        // If the transaction has outputs that belong to the wallet as well as transparent
+        // inputs, we may need to download the transactions corresponding to the transparent
+        // prevout references to determine whether the transaction was created (at least in
+        // part) by this wallet.
+        #[cfg(feature = "transparent-inputs")]
+        if tx_has_wallet_outputs {
+            if let Some(b) = d_tx.tx().transparent_bundle() {
+                // queue the transparent inputs for enhancement
+                self.queue_tx_retrieval(b.vin.iter().map(|txin| *txin.prevout.txid()), Some(txid));
+            }
+        }
+
+        self.notify_tx_retrieved(txid);
+
+        // If the decrypted transaction is unmined and has no shielded components, add it to
+        // the queue for status retrieval.
+        #[cfg(feature = "transparent-inputs")]
+        {
+            let detectable_via_scanning = d_tx.tx().sapling_bundle().is_some();
+            #[cfg(feature = "orchard")]
+            let detectable_via_scanning =
+                detectable_via_scanning | d_tx.tx().orchard_bundle().is_some();
+
+            if d_tx.mined_height().is_none() && !detectable_via_scanning {
+                self.queue_tx_retrieval(std::iter::once(txid), None);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records a shielded output decrypted from a transaction, as a received note and/or
+    /// as a sent output as appropriate for its transfer type.
+    fn put_decrypted_output<N>(
+        &mut self,
+        txid: TxId,
+        output: &DecryptedOutput<N, AccountId>,
+        note: Note,
+        receiver: Receiver,
+        funding_account: Option<AccountId>,
+    ) -> Result<(), Error>
+    where
+        DecryptedOutput<N, AccountId>: notes::ReceivedOutput,
+    {
+        let pool = PoolType::Shielded(note.protocol());
+        let value = note.value();
+        match output.transfer_type() {
+            TransferType::Outgoing => {
+                let recipient = {
+                    let wallet_address = self
+                        .select_receiving_address(*output.account(), &receiver)
+                        .unwrap_or_else(|| receiver.to_zcash_address(self.params.network_type()));
+
+                    Recipient::External(wallet_address, pool)
+                };
+
+                self.put_sent_output(
+                    *output.account(),
+                    txid,
+                    output.index(),
+                    &recipient,
+                    value,
+                    Some(output.memo()),
+                );
+            }
+            TransferType::WalletInternal => {
+                self.put_received_note(output, txid, None)?;
+
+                let recipient = Recipient::InternalAccount {
+                    receiving_account: *output.account(),
+                    external_address: None,
+                    note,
+                };
+
+                self.put_sent_output(
+                    *output.account(),
+                    txid,
+                    output.index(),
+                    &recipient,
+                    value,
+                    Some(output.memo()),
+                );
+            }
+            TransferType::Incoming => {
+                self.put_received_note(output, txid, None)?;
+
+                if let Some(account_id) = funding_account {
+                    // Even if the recipient address is external, record the send as internal.
+                    let recipient = Recipient::InternalAccount {
+                        receiving_account: *output.account(),
+                        external_address: Some(
+                            self.select_receiving_address(*output.account(), &receiver)
+                                .unwrap_or_else(|| {
+                                    receiver.to_zcash_address(self.params.network_type())
+                                }),
+                        ),
+                        note,
+                    };
+
+                    self.put_sent_output(
+                        account_id,
+                        txid,
+                        output.index(),
+                        &recipient,
+                        value,
+                        Some(output.memo()),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn store_transaction_to_be_sent(
+        &mut self,
+        sent_tx: &SentTransaction<AccountId>,
+    ) -> Result<(), Error> {
+        let txid = sent_tx.tx().txid();
+        self.put_tx_data(
+            sent_tx.tx(),
+            Some(sent_tx.fee_amount()),
+            Some(sent_tx.target_height()),
+        )?;
+
+        let mut detectable_via_scanning = false;
+
+        // Mark notes as spent.
+        //
+        // This locks the notes so they aren't selected again by a subsequent call to
+        // create_spend_to_address() before this transaction has been mined (at which point the
+        // notes get re-marked as spent).
+        if let Some(bundle) = sent_tx.tx().sapling_bundle() {
+            detectable_via_scanning = true;
+            for spend in bundle.shielded_spends() {
+                self.mark_note_spent(ShieldedProtocol::Sapling, &spend.nullifier().0, txid);
+            }
+        }
+        if let Some(_bundle) = sent_tx.tx().orchard_bundle() {
+            #[cfg(feature = "orchard")]
+            {
+                detectable_via_scanning = true;
+                for action in _bundle.actions() {
+                    self.mark_note_spent(
+                        ShieldedProtocol::Orchard,
+                        &action.nullifier().to_bytes(),
+                        txid,
+                    );
+                }
+            }
+
+            #[cfg(not(feature = "orchard"))]
+            panic!("Sent a transaction with Orchard Actions without `orchard` enabled?");
+        }
+
+        #[cfg(feature = "transparent-inputs")]
+        for utxo_outpoint in sent_tx.utxos_spent() {
+            self.mark_transparent_utxo_spent(txid, utxo_outpoint);
+        }
+
+        for output in sent_tx.outputs() {
+            self.insert_sent_output(txid, *sent_tx.account_id(), output);
+
+            match output.recipient() {
+                Recipient::InternalAccount {
+                    receiving_account,
+                    note: Note::Sapling(note),
+                    ..
+                } => {
+                    self.put_received_note(
+                        &DecryptedOutput::new(
+                            output.output_index(),
+                            note.clone(),
+                            *receiving_account,
+                            output
+                                .memo()
+                                .map_or_else(MemoBytes::empty, |memo| memo.clone()),
+                            TransferType::WalletInternal,
+                        ),
+                        txid,
+                        None,
+                    )?;
+                }
+                #[cfg(feature = "orchard")]
+                Recipient::InternalAccount {
+                    receiving_account,
+                    note: Note::Orchard(note),
+                    ..
+                } => {
+                    self.put_received_note(
+                        &DecryptedOutput::new(
+                            output.output_index(),
+                            *note,
+                            *receiving_account,
+                            output
+                                .memo()
+                                .map_or_else(MemoBytes::empty, |memo| memo.clone()),
+                            TransferType::WalletInternal,
+                        ),
+                        txid,
+                        None,
+                    )?;
+                }
+                #[cfg(feature = "transparent-inputs")]
+                Recipient::EphemeralTransparent {
+                    receiving_account,
+                    ephemeral_address,
+                    outpoint_metadata,
+                } => {
+                    self.put_transparent_output(
+                        outpoint_metadata,
+                        &TxOut {
+                            value: output.value(),
+                            script_pubkey: ephemeral_address.script(),
+                        },
+                        None,
+                        ephemeral_address,
+                        *receiving_account,
+                        true,
+                    )?;
+                    self.mark_ephemeral_address_as_used(ephemeral_address, txid)?;
+                }
+                _ => {}
+            }
+        }
+
+        // Add the transaction to the set to be queried for transaction status. This is only
+        // necessary at present for fully transparent transactions, because any transaction
+        // with a shielded component will be detected via ordinary chain scanning and/or
+        // nullifier checking.
+        if !detectable_via_scanning {
+            self.queue_tx_retrieval(std::iter::once(txid), None);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the encoded address, the receiving account, and the output pool for the
+    /// given recipient.
+    fn recipient_params(
+        &self,
+        to: &Recipient<AccountId, Note, OutPoint>,
+    ) -> (Option<String>, Option<AccountId>, PoolType) {
+        match to {
+            Recipient::External(addr, pool) => (Some(addr.encode()), None, *pool),
+            Recipient::EphemeralTransparent {
+                receiving_account,
+                ephemeral_address,
+                ..
+            } => (
+                Some(Address::Transparent(*ephemeral_address).encode(&self.params)),
+                Some(*receiving_account),
+                PoolType::TRANSPARENT,
+            ),
+            Recipient::InternalAccount {
+                receiving_account,
+                external_address,
+                note,
+            } => (
+                external_address.as_ref().map(|a| a.encode()),
+                Some(*receiving_account),
+                PoolType::Shielded(note.protocol()),
+            ),
+        }
+    }
+
+    /// Marks notes received in the given transaction as change, if they were received by an
+    /// account that also sent outputs in that transaction.
+    fn flag_previously_received_change(&mut self, txid: TxId) {
+        let from_accounts = self
+            .sent_outputs
+            .iter()
+            .filter(|((tx, _, _), _)| tx == &txid)
+            .map(|(_, output)| output.from_account_id)
+            .collect::<HashSet<_>>();
+
+        for note in self.received_notes.values_mut() {
+            if note.txid == txid
+                && from_accounts.contains(&note.account_id)
+                && note.recipient_key_scope == Some(Scope::Internal)
+            {
+                note.is_change = true;
+            }
+        }
+    }
+
+    /// Records information about a transaction output that the wallet created.
+    pub(crate) fn insert_sent_output(
+        &mut self,
+        txid: TxId,
+        from_account: AccountId,
+        output: &SentTransactionOutput<AccountId>,
+    ) {
+        let (to_address, to_account_id, pool_type) = self.recipient_params(output.recipient());
+        self.sent_outputs.insert(
+            (
+                txid,
+                pool_type,
+                u32::try_from(output.output_index()).unwrap(),
+            ),
+            SentOutputRecord {
+                from_account_id: from_account,
+                to_address,
+                to_account_id,
+                value: output.value(),
+                memo: output.memo().cloned(),
+            },
+        );
+        self.flag_previously_received_change(txid);
+    }
+
+    /// Records information about a transaction output that the wallet created, from the
+    /// constituent properties of that output.
+    pub(crate) fn put_sent_output(
+        &mut self,
+        from_account: AccountId,
+        txid: TxId,
+        output_index: usize,
+        recipient: &Recipient<AccountId, Note, OutPoint>,
+        value: NonNegativeAmount,
+        memo: Option<&MemoBytes>,
+    ) {
+        let (to_address, to_account_id, pool_type) = self.recipient_params(recipient);
+        let key = (txid, pool_type, u32::try_from(output_index).unwrap());
+        match self.sent_outputs.get_mut(&key) {
+            Some(existing) => {
+                existing.from_account_id = from_account;
+                existing.to_address = existing.to_address.take().or(to_address);
+                existing.to_account_id = existing.to_account_id.or(to_account_id);
+                existing.value = value;
+                existing.memo = memo.cloned().or(existing.memo.take());
+            }
+            None => {
+                self.sent_outputs.insert(
+                    key,
+                    SentOutputRecord {
+                        from_account_id: from_account,
+                        to_address,
+                        to_account_id,
+                        value,
+                        memo: memo.cloned(),
+                    },
+                );
+            }
+        }
+        self.flag_previously_received_change(txid);
+    }
+
+    /// Truncates the wallet to at most the given height, returning the height to which the
+    /// wallet was truncated.
+    pub(crate) fn truncate_to_height_inner(
+        &mut self,
+        max_height: BlockHeight,
+    ) -> Result<BlockHeight, Error> {
+        // Determine a checkpoint to which we can rewind, if any.
+        let has_checkpoint = |h: &BlockHeight| {
+            #[cfg(feature = "orchard")]
+            let orchard = self.orchard_tree.checkpoints.contains_key(h);
+            #[cfg(not(feature = "orchard"))]
+            let orchard = true;
+
+            self.sapling_tree.checkpoints.contains_key(h) && orchard
+        };
+
+        let truncation_height = match self
+            .blocks
+            .range(..=max_height)
+            .rev()
+            .map(|(h, _)| *h)
+            .find(has_checkpoint)
+        {
+            Some(h) => h,
+            None => {
+                // If we don't have a checkpoint at a height less than or equal to the
+                // requested truncation height, find the minimum height to which it's possible
+                // for us to truncate so that we can report it to the caller.
+                let min_truncation_height = self
+                    .sapling_tree
+                    .checkpoints
+                    .keys()
+                    .copied()
+                    .find(has_checkpoint);
+
+                return Err(Error::RequestedRewindInvalid {
+                    safe_rewind_height: min_truncation_height,
+                    requested_height: max_height,
+                });
+            }
+        };
+
+        let last_scanned_height = self.blocks.keys().next_back().copied().unwrap_or_else(|| {
+            self.params
+                .activation_height(NetworkUpgrade::Sapling)
+                .expect("Sapling activation height must be available.")
+                - 1
+        });
+
+        // Delete from the scanning queue any range with a start height greater than the
+        // truncation height, and then truncate any remaining range by setting the end
+        // equal to the truncation height + 1. This sets our view of the chain tip back
+        // to the retained height.
+        let new_end_height = truncation_height + 1;
+        self.scan_queue = self
+            .scan_queue
+            .iter()
+            .filter(|r| r.block_range().start < new_end_height)
+            .map(|r| {
+                if r.block_range().end > new_end_height {
+                    ScanRange::from_parts(r.block_range().start..new_end_height, r.priority())
+                } else {
+                    r.clone()
+                }
+            })
+            .collect();
+
+        // Mark transparent utxos as un-mined. Since the TXO is now not mined, it would ideally
+        // be considered to have been returned to the mempool; it _might_ be spendable in this
+        // state, but we must also clear its max observed unspent height because the
+        // transaction may be rendered entirely invalid by a reorg that alters anchor(s) used
+        // in constructing shielded spends in the transaction.
+        #[cfg(feature = "transparent-inputs")]
+        for (outpoint, output) in self.transparent_outputs.iter_mut() {
+            if output
+                .max_observed_unspent_height
+                .map_or(false, |h| h > truncation_height)
+            {
+                let mined_height = self
+                    .transactions
+                    .get(outpoint.txid())
+                    .and_then(|tx| tx.mined_height);
+                output.max_observed_unspent_height =
+                    if mined_height.map_or(false, |h| h <= truncation_height) {
+                        Some(truncation_height)
+                    } else {
+                        None
+                    };
+            }
+        }
+
+        // Un-mine transactions. This must be done outside of the last_scanned_height check
+        // because transaction entries may be created as a consequence of receiving
+        // transparent TXOs.
+        for tx in self.transactions.values_mut() {
+            if tx.mined_height.map_or(false, |h| h > truncation_height) {
+                tx.block = None;
+                tx.mined_height = None;
+                tx.tx_index = None;
+            }
+        }
+
+        // If we're removing scanned blocks, we need to truncate the note commitment tree and
+        // remove affected block records.
+        if truncation_height < last_scanned_height {
+            // Truncate the note commitment trees
+            ShardTree::<
+                _,
+                { sapling::NOTE_COMMITMENT_TREE_DEPTH },
+                { zcash_client_backend::data_api::SAPLING_SHARD_HEIGHT },
+            >::new(&mut self.sapling_tree, PRUNING_DEPTH.try_into().unwrap())
+            .truncate_to_checkpoint(&truncation_height)?;
+            #[cfg(feature = "orchard")]
+            ShardTree::<
+                _,
+                { orchard::NOTE_COMMITMENT_TREE_DEPTH as u8 },
+                { zcash_client_backend::data_api::ORCHARD_SHARD_HEIGHT },
+            >::new(&mut self.orchard_tree, PRUNING_DEPTH.try_into().unwrap())
+            .truncate_to_checkpoint(&truncation_height)?;
+
+            // Do not delete sent outputs or received notes; these can contain data that is not
+            // recoverable from the chain. Balance APIs must ensure that un-mined received
+            // notes do not count towards spendability or transaction balance.
+
+            // Now that they aren't depended on, delete un-mined blocks.
+            self.blocks.split_off(&new_end_height);
+
+            // Delete from the nullifier map any entries with a locator referencing a block
+            // height greater than the truncation height.
+            self.tx_locators.split_off(&(new_end_height, 0));
+            self.nullifiers.retain(|_, (h, _)| *h <= truncation_height);
+        }
+
+        Ok(truncation_height)
+    }
+
+    /// Removes the given account, along with all of the data that the wallet holds for it
+    /// other than transaction data, and un-marks the account's note commitment tree
+    /// positions so that they may be pruned.
+    pub(crate) fn delete_account_inner(&mut self, account_id: AccountId) -> Result<(), Error> {
+        self.accounts
+            .remove(&account_id)
+            .ok_or(Error::AccountUnknown)?;
+        self.addresses.remove(&account_id);
+
+        let note_ids = self
+            .received_notes
+            .iter()
+            .filter(|(_, n)| n.account_id == account_id)
+            .map(|(id, _)| *id)
+            .collect::<HashSet<_>>();
+        let mut sapling_positions = vec![];
+        #[cfg(feature = "orchard")]
+        let mut orchard_positions = vec![];
+        for id in &note_ids {
+            let note = self.received_notes.remove(id).expect("note id is present");
+            match (note.note.protocol(), note.commitment_tree_position) {
+                (ShieldedProtocol::Sapling, Some(position)) => sapling_positions.push(position),
+                #[cfg(feature = "orchard")]
+                (ShieldedProtocol::Orchard, Some(position)) => orchard_positions.push(position),
+                _ => {}
+            }
+        }
+        self.received_note_spends
+            .retain(|(id, _)| !note_ids.contains(id));
+
+        let mut sapling_tree =
+            ShardTree::<
+                _,
+                { sapling::NOTE_COMMITMENT_TREE_DEPTH },
+                { zcash_client_backend::data_api::SAPLING_SHARD_HEIGHT },
+            >::new(&mut self.sapling_tree, PRUNING_DEPTH.try_into().unwrap());
+        for position in sapling_positions {
+            sapling_tree.remove_mark(position, None)?;
+        }
+        #[cfg(feature = "orchard")]
+        {
+            let mut orchard_tree =
+                ShardTree::<
+                    _,
+                    { orchard::NOTE_COMMITMENT_TREE_DEPTH as u8 },
+                    { zcash_client_backend::data_api::ORCHARD_SHARD_HEIGHT },
+                >::new(&mut self.orchard_tree, PRUNING_DEPTH.try_into().unwrap());
+            for position in orchard_positions {
+                orchard_tree.remove_mark(position, None)?;
+            }
+        }
+
+        // Outputs sent by this account are removed entirely. Outputs that this account
+        // received from other accounts in the wallet are retained as ordinary outgoing
+        // outputs if we know the address they were sent to.
+        self.sent_outputs.retain(|_, output| {
+            output.from_account_id != account_id
+                && (output.to_account_id != Some(account_id) || output.to_address.is_some())
+        });
+        for output in self.sent_outputs.values_mut() {
+            if output.to_account_id == Some(account_id) {
+                output.to_account_id = None;
+            }
+        }
+
+        #[cfg(feature = "transparent-inputs")]
+        {
+            let outpoints = self
+                .transparent_outputs
+                .iter()
+                .filter(|(_, o)| o.account_id == account_id)
+                .map(|(outpoint, _)| outpoint.clone())
+                .collect::<std::collections::BTreeSet<_>>();
+            self.transparent_outputs
+                .retain(|outpoint, _| !outpoints.contains(outpoint));
+            self.transparent_output_spends
+                .retain(|(outpoint, _)| !outpoints.contains(outpoint));
+            self.transparent_spend_search_queue
+                .retain(|outpoint, _| !outpoints.contains(outpoint));
+            self.ephemeral_addresses
+                .retain(|(id, _), _| *id != account_id);
+        }
+
+        Ok(())
+    }
+
+    /// Inserts the given entries into the nullifier map.
+    ///
+    /// Returns an error if the new entries conflict with existing ones. This indicates either
+    /// corrupted data, or that a reorg has occurred and the caller needs to repair the wallet
+    /// state with [`WalletWrite::truncate_to_height`].
+    ///
+    /// [`WalletWrite::truncate_to_height`]: zcash_client_backend::data_api::WalletWrite::truncate_to_height
+    pub(crate) fn insert_nullifier_map(
+        &mut self,
+        block_height: BlockHeight,
+        spend_pool: ShieldedProtocol,
+        new_entries: &[(TxId, u16, Vec<[u8; 32]>)],
+    ) -> Result<(), Error> {
+        for (txid, tx_index, nullifiers) in new_entries {
+            let locator = (block_height, *tx_index);
+            let existing = self
+                .tx_locators
+                .iter()
+                .filter(|(loc, t)| **loc == locator || *t == txid)
+                .collect::<Vec<_>>();
+
+            match existing.as_slice() {
+                // If the locator doesn't exist, insert it.
+                [] => {
+                    self.tx_locators.insert(locator, *txid);
+                }
+                // If the locator matches the one being inserted, do nothing.
+                [(loc, t)] if **loc == locator && *t == txid => (),
+                // Otherwise, the locator being inserted conflicts with an existing one.
+                _ => return Err(Error::NullifierMapConflict(block_height, *tx_index)),
+            }
+
+            for nf in nullifiers {
+                self.nullifiers.insert((spend_pool, *nf), locator);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the transaction in which the given nullifier is revealed, if any.
+    pub(crate) fn query_nullifier_map(
+        &mut self,
+        spend_pool: ShieldedProtocol,
+        nf: &[u8; 32],
+    ) -> Option<TxId> {
+        let (height, index) = *self.nullifiers.get(&(spend_pool, *nf))?;
+        let txid = *self.tx_locators.get(&(height, index))?;
+
+        // Find or create a corresponding transaction record. Usually a record will have been
+        // created during the same scan that the locator was added to the nullifier map, but
+        // it would not happen if the transaction in question spent the note with no change
+        // or explicit in-wallet recipient.
+        self.put_tx_meta(txid, index, height);
+
+        Some(txid)
+    }
+
+    /// Deletes from the nullifier map any entries with a locator referencing a block height
+    /// lower than the pruning height.
+    pub(crate) fn prune_nullifier_map(&mut self, block_height: BlockHeight) {
+        self.tx_locators = self.tx_locators.split_off(&(block_height, 0));
+        self.nullifiers.retain(|_, (h, _)| *h >= block_height);
+    }
+}
@@ -0,0 +1,335 @@
+//! An in-memory [`ShardStore`] for the wallet's note commitment trees.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use incrementalmerkletree::{Address, Hashable, Level, Position, Retention};
+use shardtree::{
+    error::ShardTreeError,
+    store::{Checkpoint, ShardStore},
+    LocatedPrunableTree, LocatedTree, Node, PrunableTree, RetentionFlags, Tree,
+};
+use zcash_client_backend::data_api::chain::CommitmentTreeRoot;
+use zcash_protocol::consensus::BlockHeight;
+
+/// An implementation of [`ShardStore`] that stores all state in memory.
+///
+/// Unlike [`shardtree::store::memory::MemoryShardStore`], this store only holds the shards
+/// that have actually been written, can be cloned (so that the wallet can be restored to
+/// a prior state if an operation fails part of the way through), and records the end
+/// height of each complete subtree alongside its shard.
+#[derive(Clone, Debug)]
+pub struct MemoryShardStore<H, C: Ord> {
+    pub(crate) shards: BTreeMap<u64, LocatedPrunableTree<H>>,
+    pub(crate) shard_end_heights: BTreeMap<u64, BlockHeight>,
+    pub(crate) checkpoints: BTreeMap<C, Checkpoint>,
+    pub(crate) cap: PrunableTree<H>,
+}
+
+impl<H, C: Ord> MemoryShardStore<H, C> {
+    /// Constructs a new empty `MemoryShardStore`.
+    pub fn empty() -> Self {
+        Self {
+            shards: BTreeMap::new(),
+            shard_end_heights: BTreeMap::new(),
+            checkpoints: BTreeMap::new(),
+            cap: PrunableTree::empty(),
+        }
+    }
+
+    /// Returns the indices of all shards for which the store has either shard data or a
+    /// known subtree end height, in ascending order.
+    pub(crate) fn shard_indices(&self) -> BTreeSet<u64> {
+        self.shards
+            .keys()
+            .chain(self.shard_end_heights.keys())
+            .copied()
+            .collect()
+    }
+
+    /// Returns the block height at which the subtree with the given index was completed,
+    /// if known.
+    pub(crate) fn shard_end_height(&self, shard_index: u64) -> Option<BlockHeight> {
+        self.shard_end_heights.get(&shard_index).copied()
+    }
+
+    /// Returns the greatest block height at which any known subtree was completed.
+    pub(crate) fn max_shard_end_height(&self) -> Option<BlockHeight> {
+        self.shard_end_heights.values().max().copied()
+    }
+}
+
+impl<H: Clone, C: Clone + Ord> ShardStore for MemoryShardStore<H, C> {
+    type H = H;
+    type CheckpointId = C;
+    type Error = Infallible;
+
+    fn get_shard(
+        &self,
+        shard_root: Address,
+    ) -> Result<Option<LocatedPrunableTree<H>>, Self::Error> {
+        Ok(self.shards.get(&shard_root.index()).cloned())
+    }
+
+    fn last_shard(&self) -> Result<Option<LocatedPrunableTree<H>>, Self::Error> {
+        Ok(self.shards.values().last().cloned())
+    }
+
+    fn put_shard(&mut self, subtree: LocatedPrunableTree<H>) -> Result<(), Self::Error> {
+        self.shards.insert(subtree.root_addr().index(), subtree);
+        Ok(())
+    }
+
+    fn get_shard_roots(&self) -> Result<Vec<Address>, Self::Error> {
+        Ok(self.shards.values().map(|s| s.root_addr()).collect())
+    }
+
+    fn truncate_shards(&mut self, shard_index: u64) -> Result<(), Self::Error> {
+        self.shards.split_off(&shard_index);
+        self.shard_end_heights.split_off(&shard_index);
+        Ok(())
+    }
+
+    fn get_cap(&self) -> Result<PrunableTree<H>, Self::Error> {
+        Ok(self.cap.clone())
+    }
+
+    fn put_cap(&mut self, cap: PrunableTree<H>) -> Result<(), Self::Error> {
+        self.cap = cap;
+        Ok(())
+    }
+
+    fn add_checkpoint(
+        &mut self,
+        checkpoint_id: C,
+        checkpoint: Checkpoint,
+    ) -> Result<(), Self::Error> {
+        self.checkpoints.insert(checkpoint_id, checkpoint);
+        Ok(())
+    }
+
+    fn checkpoint_count(&self) -> Result<usize, Self::Error> {
+        Ok(self.checkpoints.len())
+    }
+
+    fn get_checkpoint(
+        &self,
+        checkpoint_id: &Self::CheckpointId,
+    ) -> Result<Option<Checkpoint>, Self::Error> {
+        Ok(self.checkpoints.get(checkpoint_id).cloned())
+    }
+
+    fn get_checkpoint_at_depth(
+        &self,
+        checkpoint_depth: usize,
+    ) -> Result<Option<(C, Checkpoint)>, Self::Error> {
+        Ok(self
+            .checkpoints
+            .iter()
+            .rev()
+            .nth(checkpoint_depth)
+            .map(|(id, c)| (id.clone(), c.clone())))
+    }
+
+    fn min_checkpoint_id(&self) -> Result<Option<C>, Self::Error> {
+        Ok(self.checkpoints.keys().next().cloned())
+    }
+
+    fn max_checkpoint_id(&self) -> Result<Option<C>, Self::Error> {
+        Ok(self.checkpoints.keys().last().cloned())
+    }
+
+    fn with_checkpoints<F>(&mut self, limit: usize, mut callback: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&C, &Checkpoint) -> Result<(), Self::Error>,
+    {
+        for (cid, checkpoint) in self.checkpoints.iter().take(limit) {
+            callback(cid, checkpoint)?
+        }
+
+        Ok(())
+    }
+
+    fn for_each_checkpoint<F>(&self, limit: usize, mut callback: F) -> Result<(), Self::Error>
+    where
+        F: FnMut(&C, &Checkpoint) -> Result<(), Self::Error>,
+    {
+        for (cid, checkpoint) in self.checkpoints.iter().take(limit) {
+            callback(cid, checkpoint)?
+        }
+
+        Ok(())
+    }
+
+    fn update_checkpoint_with<F>(
+        &mut self,
+        checkpoint_id: &C,
+        update: F,
+    ) -> Result<bool, Self::Error>
+    where
+        F: Fn(&mut Checkpoint) -> Result<(), Self::Error>,
+    {
+        if let Some(c) = self.checkpoints.get_mut(checkpoint_id) {
+            update(c)?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn remove_checkpoint(&mut self, checkpoint_id: &C) -> Result<(), Self::Error> {
+        self.checkpoints.remove(checkpoint_id);
+        Ok(())
+    }
+
+    fn truncate_checkpoints_retaining(
+        &mut self,
+        checkpoint_id: &Self::CheckpointId,
+    ) -> Result<(), Self::Error> {
+        let mut rest = self.checkpoints.split_off(checkpoint_id);
+        if let Some(c) = rest.remove(checkpoint_id) {
+            self.checkpoints.insert(
+                checkpoint_id.clone(),
+                Checkpoint::from_parts(c.tree_state(), BTreeSet::new()),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Applies `f` to each hash in the given tree, preserving its structure.
+fn map_hashes<H, G>(tree: &PrunableTree<H>, f: &impl Fn(&H) -> G) -> PrunableTree<G> {
+    match &**tree {
+        Node::Parent { ann, left, right } => Tree::parent(
+            ann.as_ref().map(|h| Arc::new(f(h))),
+            map_hashes(left, f),
+            map_hashes(right, f),
+        ),
+        Node::Leaf { value: (h, flags) } => Tree::leaf((f(h), *flags)),
+        Node::Nil => Tree::empty(),
+    }
+}
+
+/// Adds the given subtree roots to the store's cap, and records the end height of each
+/// subtree alongside its shard.
+pub(crate) fn put_shard_roots<H: Hashable + Clone + Eq, const DEPTH: u8, const SHARD_HEIGHT: u8>(
+    store: &mut MemoryShardStore<H, BlockHeight>,
+    start_index: u64,
+    roots: &[CommitmentTreeRoot<H>],
+) -> Result<(), ShardTreeError<Infallible>> {
+    if roots.is_empty() {
+        // nothing to do
+        return Ok(());
+    }
+
+    // We treat the cap as a tree with `DEPTH - SHARD_HEIGHT` levels, so that we can make a
+    // batch insertion of root data using `Position::from(start_index)` as the starting position
+    // and treating the roots as level-0 leaves.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct LevelShifter<H, const SHARD_HEIGHT: u8>(H);
+    impl<H: Hashable, const SHARD_HEIGHT: u8> Hashable for LevelShifter<H, SHARD_HEIGHT> {
+        fn empty_leaf() -> Self {
+            Self(H::empty_root(SHARD_HEIGHT.into()))
+        }
+
+        fn combine(level: Level, a: &Self, b: &Self) -> Self {
+            Self(H::combine(level + SHARD_HEIGHT, &a.0, &b.0))
+        }
+
+        fn empty_root(level: Level) -> Self
+        where
+            Self: Sized,
+        {
+            Self(H::empty_root(level + SHARD_HEIGHT))
+        }
+    }
+
+    let cap = LocatedTree::from_parts(
+        Address::from_parts((DEPTH - SHARD_HEIGHT).into(), 0),
+        map_hashes(&store.cap, &|h: &H| {
+            LevelShifter::<H, SHARD_HEIGHT>(h.clone())
+        }),
+    );
+
+    let cap_result = cap
+        .batch_insert::<(), _>(
+            Position::from(start_index),
+            roots
+                .iter()
+                .map(|r| (LevelShifter(r.root_hash().clone()), Retention::Reference)),
+        )
+        .map_err(ShardTreeError::Insert)?
+        .expect("slice of inserted roots was verified to be nonempty");
+
+    store.cap = map_hashes(cap_result.subtree.root(), &|h| h.0.clone());
+
+    for (root, i) in roots.iter().zip(0u64..) {
+        let shard_index = start_index + i;
+        let shard = match store.shards.remove(&shard_index) {
+            Some(shard) => shard.reannotate_root(Some(Arc::new(root.root_hash().clone()))),
+            None => LocatedTree::from_parts(
+                Address::from_parts(SHARD_HEIGHT.into(), shard_index),
+                PrunableTree::leaf((root.root_hash().clone(), RetentionFlags::EPHEMERAL)),
+            ),
+        };
+        store.shards.insert(shard_index, shard);
+        store
+            .shard_end_heights
+            .insert(shard_index, root.subtree_end_height());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use incrementalmerkletree::{Marking, Position, Retention};
+    use shardtree::{store::ShardStore, ShardTree};
+    use zcash_protocol::consensus::BlockHeight;
+
+    use super::MemoryShardStore;
+
+    #[test]
+    fn store_is_sparse_and_truncates_end_heights() {
+        let mut tree: ShardTree<MemoryShardStore<String, BlockHeight>, 6, 3> =
+            ShardTree::new(MemoryShardStore::empty(), 10);
+
+        // Insert a root for the third shard without any data for the first two.
+        tree.insert(
+            incrementalmerkletree::Address::from_parts(3.into(), 2),
+            "abcdefgh".to_string(),
+        )
+        .unwrap();
+        tree.store_mut()
+            .shard_end_heights
+            .insert(2, BlockHeight::from(20));
+        assert_eq!(
+            tree.store().get_shard_roots().unwrap(),
+            vec![incrementalmerkletree::Address::from_parts(3.into(), 2)]
+        );
+
+        tree.batch_insert(
+            Position::from(0),
+            ('a'..='c').map(|c| {
+                (
+                    c.to_string(),
+                    Retention::Checkpoint {
+                        id: BlockHeight::from(u32::from(c)),
+                        marking: Marking::None,
+                    },
+                )
+            }),
+        )
+        .unwrap();
+        assert_eq!(tree.store().shard_indices().len(), 2);
+
+        // A clone is unaffected by subsequent modification of the original.
+        let snapshot = tree.store().clone();
+        tree.store_mut().truncate_shards(1).unwrap();
+        assert_eq!(tree.store().shard_indices().len(), 1);
+        assert_eq!(tree.store().max_shard_end_height(), None);
+        assert_eq!(snapshot.shard_indices().len(), 2);
+        assert_eq!(snapshot.shard_end_height(2), Some(BlockHeight::from(20)));
+    }
+}
@@ -0,0 +1,376 @@
+//! Functions for summarizing the wallet's transaction history.
+
+use std::collections::BTreeMap;
+
+use zcash_client_backend::data_api::{
+    TransactionDirection, TransactionHistoryEntry, TransactionHistoryFilter,
+    TransactionHistoryOutput,
+};
+use zcash_primitives::{
+    consensus,
+    memo::MemoBytes,
+    transaction::{components::amount::NonNegativeAmount, TxId},
+};
+use zcash_protocol::{value::ZatBalance, PoolType};
+
+use crate::{error::Error, AccountId, MemoryWalletDb};
+
+#[cfg(feature = "fiat")]
+use zcash_client_backend::data_api::FiatRate;
+
+#[cfg(any(test, feature = "test-dependencies"))]
+use zcash_client_backend::data_api::testing::TransactionSummary;
+
+/// The net effect of a transaction on a single account in the wallet.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TxHistoryRow {
+    pub(crate) account_balance_delta: i64,
+    pub(crate) has_change: bool,
+    pub(crate) sent_note_count: usize,
+    pub(crate) received_note_count: usize,
+    pub(crate) memo_count: usize,
+    pub(crate) spent_note_count: usize,
+    /// The number of wallet-spent and wallet-received outputs that are inconsistent with the
+    /// transaction being a shielding transaction.
+    does_not_match_shielding: usize,
+}
+
+impl TxHistoryRow {
+    /// Returns whether the transaction looks like a shielding transaction from the
+    /// perspective of this account.
+    pub(crate) fn is_shielding(&self) -> bool {
+        // All of the wallet-spent and wallet-received notes are consistent with a
+        // shielding transaction.
+        self.does_not_match_shielding == 0
+            // The transaction contains at least one wallet-spent output.
+            && self.spent_note_count > 0
+            // The transaction contains at least one wallet-received note.
+            && self.received_note_count + usize::from(self.has_change) > 0
+            // We do not know about any external outputs of the transaction.
+            && self.sent_note_count == 0
+    }
+}
+
+/// An output received by the wallet, as seen by the transaction history.
+struct ReceivedOutputView {
+    account_id: AccountId,
+    txid: TxId,
+    pool: PoolType,
+    output_index: u32,
+    value: NonNegativeAmount,
+    is_change: bool,
+    memo: Option<MemoBytes>,
+}
+
+fn memo_present(memo: &Option<MemoBytes>) -> bool {
+    memo.as_ref().map_or(false, |m| m != &MemoBytes::empty())
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns all of the outputs received by the wallet, along with the transactions (if
+    /// any) in which each has been spent.
+    fn received_outputs(&self) -> Vec<(ReceivedOutputView, Vec<TxId>)> {
+        let mut result = self
+            .received_notes
+            .iter()
+            .map(|(id, n)| {
+                (
+                    ReceivedOutputView {
+                        account_id: n.account_id,
+                        txid: n.txid,
+                        pool: PoolType::Shielded(n.note.protocol()),
+                        output_index: n.output_index,
+                        value: n.note.value(),
+                        is_change: n.is_change,
+                        memo: n.memo.clone(),
+                    },
+                    self.received_note_spends
+                        .iter()
+                        .filter(|(note_id, _)| note_id == id)
+                        .map(|(_, txid)| *txid)
+                        .collect(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        #[cfg(feature = "transparent-inputs")]
+        result.extend(self.transparent_outputs.iter().map(|(outpoint, o)| {
+            (
+                ReceivedOutputView {
+                    account_id: o.account_id,
+                    txid: *outpoint.txid(),
+                    pool: PoolType::TRANSPARENT,
+                    output_index: outpoint.n(),
+                    value: o.txout.value,
+                    is_change: false,
+                    memo: None,
+                },
+                self.transparent_output_spends
+                    .iter()
+                    .filter(|(op, _)| op == outpoint)
+                    .map(|(_, txid)| *txid)
+                    .collect(),
+            )
+        }));
+
+        result.sort_by_key(|(o, _)| (o.txid, o.pool, o.output_index));
+        result
+    }
+
+    /// Computes the net effect of each transaction on each account in the wallet.
+    pub(crate) fn tx_history_rows(
+        &self,
+    ) -> Result<BTreeMap<(AccountId, TxId), TxHistoryRow>, Error> {
+        let received = self.received_outputs();
+        let mut rows: BTreeMap<(AccountId, TxId), TxHistoryRow> = BTreeMap::new();
+
+        let to_i64 = |v: NonNegativeAmount| {
+            i64::try_from(u64::from(v))
+                .map_err(|_| Error::CorruptedData(format!("Output value out of range: {:?}", v)))
+        };
+
+        for (output, spends) in &received {
+            // Outputs received in this transaction
+            if self.transactions.contains_key(&output.txid) {
+                let row = rows.entry((output.account_id, output.txid)).or_default();
+                row.account_balance_delta += to_i64(output.value)?;
+                row.has_change |= output.is_change;
+                row.received_note_count += usize::from(!output.is_change);
+                row.memo_count += usize::from(memo_present(&output.memo));
+                // The wallet cannot receive transparent outputs in shielding transactions.
+                row.does_not_match_shielding += usize::from(output.pool == PoolType::TRANSPARENT);
+            }
+
+            // Outputs spent in this transaction
+            for spend_txid in spends {
+                if self.transactions.contains_key(spend_txid) {
+                    let row = rows.entry((output.account_id, *spend_txid)).or_default();
+                    row.account_balance_delta -= to_i64(output.value)?;
+                    row.spent_note_count += 1;
+                    // The wallet cannot spend shielded outputs in shielding transactions.
+                    row.does_not_match_shielding +=
+                        usize::from(output.pool != PoolType::TRANSPARENT);
+                }
+            }
+        }
+
+        // Count the outputs that the wallet created in each transaction, not counting change.
+        for ((txid, pool, output_index), sent) in &self.sent_outputs {
+            let received_output = received.iter().find(|(o, _)| {
+                &o.txid == txid && &o.pool == pool && &o.output_index == output_index
+            });
+            if received_output.map_or(false, |(o, _)| o.is_change) {
+                continue;
+            }
+            if let Some(row) = rows.get_mut(&(sent.from_account_id, *txid)) {
+                row.sent_note_count += 1;
+                if received_output.is_none() && memo_present(&sent.memo) {
+                    row.memo_count += 1;
+                }
+            }
+        }
+
+        Ok(rows)
+    }
+
+    /// Returns whether the given transaction expired without having been mined.
+    fn is_expired_unmined(&self, txid: &TxId) -> bool {
+        let max_height = self.blocks.keys().next_back().copied();
+        self.transactions.get(txid).map_or(false, |tx| {
+            let mined_block_known = tx
+                .mined_height
+                .map_or(false, |h| self.blocks.contains_key(&h));
+            !mined_block_known
+                && tx
+                    .expiry_height
+                    .zip(max_height)
+                    .map_or(false, |(e, max)| u32::from(e) >= 1 && e <= max)
+        })
+    }
+
+    #[cfg(any(test, feature = "test-dependencies"))]
+    pub(crate) fn get_tx_history_inner(&self) -> Result<Vec<TransactionSummary<AccountId>>, Error> {
+        let mut history = self
+            .tx_history_rows()?
+            .into_iter()
+            .map(|((account_id, txid), row)| {
+                let tx = self.transactions.get(&txid);
+                Ok(TransactionSummary::from_parts(
+                    account_id,
+                    txid,
+                    tx.and_then(|tx| tx.expiry_height),
+                    tx.and_then(|tx| tx.mined_height),
+                    ZatBalance::from_i64(row.account_balance_delta).map_err(|_| {
+                        Error::CorruptedData("Account balance delta out of range".to_owned())
+                    })?,
+                    tx.and_then(|tx| tx.fee),
+                    row.spent_note_count,
+                    row.has_change,
+                    row.sent_note_count,
+                    row.received_note_count,
+                    row.memo_count,
+                    self.is_expired_unmined(&txid),
+                    row.is_shielding(),
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        history.sort_by(|a, b| {
+            // Order by mined height and transaction index, descending, with unmined
+            // transactions last.
+            let key = |s: &TransactionSummary<AccountId>| {
+                (
+                    s.mined_height(),
+                    self.transactions.get(&s.txid()).and_then(|tx| tx.tx_index),
+                )
+            };
+            key(b).cmp(&key(a))
+        });
+
+        Ok(history)
+    }
+
+    pub(crate) fn get_transaction_history_inner(
+        &self,
+        filter: &TransactionHistoryFilter<AccountId>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<TransactionHistoryEntry<AccountId>>, Error> {
+        let received = self.received_outputs();
+
+        // The outputs of each transaction: all outputs received by the wallet, plus the
+        // outputs sent from the wallet to external recipients.
+        let mut outputs: BTreeMap<TxId, Vec<TransactionHistoryOutput<AccountId>>> = BTreeMap::new();
+        for (output, _) in &received {
+            if self.transactions.contains_key(&output.txid) {
+                let sent = self
+                    .sent_outputs
+                    .get(&(output.txid, output.pool, output.output_index));
+                outputs
+                    .entry(output.txid)
+                    .or_default()
+                    .push(TransactionHistoryOutput::from_parts(
+                        output.pool,
+                        output.output_index,
+                        sent.map(|s| s.from_account_id),
+                        Some(output.account_id),
+                        None,
+                        output.value,
+                        output.is_change,
+                        output.memo.clone(),
+                    ));
+            }
+        }
+        for ((txid, pool, output_index), sent) in &self.sent_outputs {
+            let is_received = received.iter().any(|(o, _)| {
+                &o.txid == txid && &o.pool == pool && &o.output_index == output_index
+            });
+            if !is_received && self.transactions.contains_key(txid) {
+                outputs
+                    .entry(*txid)
+                    .or_default()
+                    .push(TransactionHistoryOutput::from_parts(
+                        *pool,
+                        *output_index,
+                        Some(sent.from_account_id),
+                        None,
+                        sent.to_address.clone(),
+                        sent.value,
+                        false,
+                        sent.memo.clone(),
+                    ));
+            }
+        }
+
+        let mut history = vec![];
+        for ((account_id, txid), row) in self.tx_history_rows()? {
+            let tx = self.transactions.get(&txid);
+            let mined_height = tx.and_then(|tx| tx.mined_height);
+
+            if filter.account_id().map_or(false, |a| a != &account_id)
+                || filter
+                    .mined_heights()
+                    .map_or(false, |r| !mined_height.map_or(false, |h| r.contains(&h)))
+                || filter.direction().map_or(false, |d| {
+                    (d == TransactionDirection::Sent) != (row.spent_note_count > 0)
+                })
+                || filter
+                    .has_memo()
+                    .map_or(false, |m| m != (row.memo_count > 0))
+            {
+                continue;
+            }
+
+            let mut tx_outputs = outputs
+                .get(&txid)
+                .into_iter()
+                .flatten()
+                .filter(|o| {
+                    o.from_account_id() == Some(&account_id)
+                        || o.to_account_id() == Some(&account_id)
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            tx_outputs.sort_by_key(|o| (o.pool(), o.output_index()));
+
+            if let Some(pool) = filter.pool() {
+                let received_or_sent = tx_outputs.iter().any(|o| o.pool() == pool);
+                let spent = received.iter().any(|(o, spends)| {
+                    o.account_id == account_id && o.pool == pool && spends.contains(&txid)
+                });
+                if !(received_or_sent || spent) {
+                    continue;
+                }
+            }
+
+            let entry = TransactionHistoryEntry::from_parts(
+                account_id,
+                txid,
+                mined_height,
+                mined_height
+                    .and_then(|h| self.blocks.get(&h))
+                    .map(|b| b.time),
+                tx.and_then(|tx| tx.expiry_height),
+                ZatBalance::from_i64(row.account_balance_delta).map_err(|_| {
+                    Error::CorruptedData("Account balance delta out of range".to_owned())
+                })?,
+                tx.and_then(|tx| tx.fee),
+                row.spent_note_count,
+                row.has_change,
+                row.sent_note_count,
+                row.received_note_count,
+                row.memo_count,
+                self.is_expired_unmined(&txid),
+                row.is_shielding(),
+                tx_outputs,
+            );
+            #[cfg(feature = "fiat")]
+            let entry = entry.with_fiat_rates(tx.map_or_else(Vec::new, |tx| {
+                tx.fiat_rates
+                    .iter()
+                    .map(|(currency, rate)| FiatRate::new(*currency, *rate))
+                    .collect()
+            }));
+
+            history.push((
+                (
+                    mined_height.is_some(),
+                    std::cmp::Reverse(mined_height),
+                    std::cmp::Reverse(tx.and_then(|tx| tx.tx_index)),
+                    account_id,
+                    txid,
+                ),
+                entry,
+            ));
+        }
+
+        history.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(history
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .skip(offset)
+            .take(limit)
+            .collect())
+    }
+}
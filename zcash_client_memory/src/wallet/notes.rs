@@ -0,0 +1,900 @@
+//! Functions for storing, selecting and querying received shielded notes.
+
+use std::collections::HashSet;
+use std::num::NonZeroU64;
+
+use incrementalmerkletree::Position;
+use zcash_client_backend::{
+    data_api::{NoteFilter, NullifierQuery, PoolMeta},
+    wallet::{Note, ReceivedNote, WalletSaplingOutput},
+    DecryptedOutput, TransferType,
+};
+use zcash_primitives::{
+    consensus::{self, BlockHeight},
+    memo::MemoBytes,
+    transaction::{
+        components::amount::{BalanceError, NonNegativeAmount},
+        TxId,
+    },
+};
+use zcash_protocol::ShieldedProtocol;
+use zip32::Scope;
+
+use crate::{error::Error, AccountId, MemoryWalletDb, ReceivedNoteId};
+
+use super::{ReceivedNoteRecord, TransactionRecord};
+
+#[cfg(feature = "orchard")]
+use zcash_client_backend::wallet::WalletOrchardOutput;
+
+/// This trait provides a generalization over shielded output representations.
+pub(crate) trait ReceivedOutput {
+    fn index(&self) -> usize;
+    fn account_id(&self) -> AccountId;
+    fn note(&self) -> Note;
+    fn memo(&self) -> Option<&MemoBytes>;
+    fn is_change(&self) -> bool;
+    fn nullifier(&self) -> Option<[u8; 32]>;
+    fn note_commitment_tree_position(&self) -> Option<Position>;
+    fn recipient_key_scope(&self) -> Option<Scope>;
+}
+
+impl ReceivedOutput for WalletSaplingOutput<AccountId> {
+    fn index(&self) -> usize {
+        self.index()
+    }
+    fn account_id(&self) -> AccountId {
+        *WalletSaplingOutput::account_id(self)
+    }
+    fn note(&self) -> Note {
+        Note::Sapling(WalletSaplingOutput::note(self).clone())
+    }
+    fn memo(&self) -> Option<&MemoBytes> {
+        None
+    }
+    fn is_change(&self) -> bool {
+        WalletSaplingOutput::is_change(self)
+    }
+    fn nullifier(&self) -> Option<[u8; 32]> {
+        self.nf().map(|nf| nf.0)
+    }
+    fn note_commitment_tree_position(&self) -> Option<Position> {
+        Some(WalletSaplingOutput::note_commitment_tree_position(self))
+    }
+    fn recipient_key_scope(&self) -> Option<Scope> {
+        WalletSaplingOutput::recipient_key_scope(self)
+    }
+}
+
+impl ReceivedOutput for DecryptedOutput<sapling::Note, AccountId> {
+    fn index(&self) -> usize {
+        self.index()
+    }
+    fn account_id(&self) -> AccountId {
+        *self.account()
+    }
+    fn note(&self) -> Note {
+        Note::Sapling(DecryptedOutput::note(self).clone())
+    }
+    fn memo(&self) -> Option<&MemoBytes> {
+        Some(DecryptedOutput::memo(self))
+    }
+    fn is_change(&self) -> bool {
+        self.transfer_type() == TransferType::WalletInternal
+    }
+    fn nullifier(&self) -> Option<[u8; 32]> {
+        None
+    }
+    fn note_commitment_tree_position(&self) -> Option<Position> {
+        None
+    }
+    fn recipient_key_scope(&self) -> Option<Scope> {
+        if self.transfer_type() == TransferType::WalletInternal {
+            Some(Scope::Internal)
+        } else {
+            Some(Scope::External)
+        }
+    }
+}
+
+#[cfg(feature = "orchard")]
+impl ReceivedOutput for WalletOrchardOutput<AccountId> {
+    fn index(&self) -> usize {
+        self.index()
+    }
+    fn account_id(&self) -> AccountId {
+        *WalletOrchardOutput::account_id(self)
+    }
+    fn note(&self) -> Note {
+        Note::Orchard(*WalletOrchardOutput::note(self))
+    }
+    fn memo(&self) -> Option<&MemoBytes> {
+        None
+    }
+    fn is_change(&self) -> bool {
+        WalletOrchardOutput::is_change(self)
+    }
+    fn nullifier(&self) -> Option<[u8; 32]> {
+        self.nf().map(|nf| nf.to_bytes())
+    }
+    fn note_commitment_tree_position(&self) -> Option<Position> {
+        Some(WalletOrchardOutput::note_commitment_tree_position(self))
+    }
+    fn recipient_key_scope(&self) -> Option<Scope> {
+        WalletOrchardOutput::recipient_key_scope(self)
+    }
+}
+
+#[cfg(feature = "orchard")]
+impl ReceivedOutput for DecryptedOutput<orchard::note::Note, AccountId> {
+    fn index(&self) -> usize {
+        self.index()
+    }
+    fn account_id(&self) -> AccountId {
+        *self.account()
+    }
+    fn note(&self) -> Note {
+        Note::Orchard(*DecryptedOutput::note(self))
+    }
+    fn memo(&self) -> Option<&MemoBytes> {
+        Some(DecryptedOutput::memo(self))
+    }
+    fn is_change(&self) -> bool {
+        self.transfer_type() == TransferType::WalletInternal
+    }
+    fn nullifier(&self) -> Option<[u8; 32]> {
+        None
+    }
+    fn note_commitment_tree_position(&self) -> Option<Position> {
+        None
+    }
+    fn recipient_key_scope(&self) -> Option<Scope> {
+        if self.transfer_type() == TransferType::WalletInternal {
+            Some(Scope::Internal)
+        } else {
+            Some(Scope::External)
+        }
+    }
+}
+
+/// Returns the sum of the given values, or an error if the sum overflows.
+pub(crate) fn sum_values(
+    mut values: impl Iterator<Item = NonNegativeAmount>,
+) -> Result<NonNegativeAmount, BalanceError> {
+    values.try_fold(NonNegativeAmount::ZERO, |acc, v| {
+        (acc + v).ok_or(BalanceError::Overflow)
+    })
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns whether the note with the given identifier has been spent by a transaction
+    /// satisfying the given predicate.
+    pub(crate) fn is_note_spent_by(
+        &self,
+        note_id: u64,
+        spending_tx: impl Fn(&TransactionRecord) -> bool,
+    ) -> bool {
+        self.received_note_spends
+            .range(
+                (note_id, TxId::from_bytes([0u8; 32]))..=(note_id, TxId::from_bytes([0xffu8; 32])),
+            )
+            .any(|(_, txid)| self.transactions.get(txid).map_or(false, &spending_tx))
+    }
+
+    /// Returns the transaction record for the transaction in which the given note was
+    /// received.
+    fn receiving_tx(&self, note: &ReceivedNoteRecord) -> Option<&TransactionRecord> {
+        self.transactions.get(&note.txid)
+    }
+
+    /// Converts a note record to a [`ReceivedNote`], if the record has all of the data
+    /// required to spend the note.
+    fn to_received_note(
+        id: u64,
+        note: &ReceivedNoteRecord,
+    ) -> Option<ReceivedNote<ReceivedNoteId, Note>> {
+        Some(ReceivedNote::from_parts(
+            ReceivedNoteId(note.note.protocol(), id),
+            note.txid,
+            u16::try_from(note.output_index).ok()?,
+            note.note.clone(),
+            note.recipient_key_scope?,
+            note.commitment_tree_position?,
+        ))
+    }
+
+    pub(crate) fn get_spendable_note_inner(
+        &self,
+        txid: &TxId,
+        protocol: ShieldedProtocol,
+        index: u32,
+    ) -> Result<Option<ReceivedNote<ReceivedNoteId, Note>>, Error> {
+        Ok(self
+            .received_notes
+            .iter()
+            .filter(|(_, n)| {
+                &n.txid == txid && n.note.protocol() == protocol && n.output_index == index
+            })
+            .filter(|(_, n)| self.receiving_tx(n).map_or(false, |tx| tx.block.is_some()))
+            .filter(|(_, n)| n.nf.is_some())
+            .find(|(id, _)| {
+                !self.is_note_spent_by(**id, |stx| {
+                    // the spending tx is mined, or will not expire
+                    stx.block.is_some() || stx.expiry_height.is_none()
+                })
+            })
+            .and_then(|(id, n)| Self::to_received_note(*id, n)))
+    }
+
+    /// Returns whether an unscanned range exists that contains the anchor height, and would
+    /// therefore prevent the construction of witnesses for notes at that anchor height.
+    fn unscanned_tip_exists(&self, protocol: ShieldedProtocol, anchor_height: BlockHeight) -> bool {
+        self.unscanned_shard_ranges(protocol).iter().any(|r| {
+            r.range.block_range().start <= anchor_height
+                && r.subtree_start_height <= anchor_height
+                && anchor_height <= r.subtree_end_height.unwrap_or(anchor_height)
+        })
+    }
+
+    pub(crate) fn select_spendable_notes_inner(
+        &self,
+        protocol: ShieldedProtocol,
+        account: AccountId,
+        target_value: NonNegativeAmount,
+        anchor_height: BlockHeight,
+        exclude: &[ReceivedNoteId],
+    ) -> Result<Vec<ReceivedNote<ReceivedNoteId, Note>>, Error> {
+        if self.wallet_birthday().is_none() {
+            // The wallet has no accounts, and therefore no notes.
+            return Ok(vec![]);
+        }
+
+        // If the anchor height is contained within an unscanned range, we cannot construct
+        // witnesses for any notes, and so cannot select any.
+        if self.unscanned_tip_exists(protocol, anchor_height) {
+            return Ok(vec![]);
+        }
+
+        // Notes in shards that have not yet been fully scanned cannot be witnessed.
+        let unscanned_positions = self
+            .unscanned_shard_ranges(protocol)
+            .into_iter()
+            .filter(|r| r.range.block_range().start <= anchor_height)
+            .map(|r| r.start_position..r.end_position_exclusive)
+            .collect::<Vec<_>>();
+
+        let excluded = exclude
+            .iter()
+            .filter(|ReceivedNoteId(p, _)| *p == protocol)
+            .map(|ReceivedNoteId(_, id)| *id)
+            .collect::<HashSet<_>>();
+
+        let eligible = self
+            .received_notes
+            .iter()
+            .filter(|(id, n)| {
+                n.note.protocol() == protocol
+                    && n.account_id == account
+                    // FIXME #1316, allow selection of dust inputs
+                    && n.note.value() > NonNegativeAmount::const_from_u64(5000)
+                    && n.recipient_key_scope.is_some()
+                    && n.nf.is_some()
+                    && !excluded.contains(id)
+            })
+            .filter(|(_, n)| {
+                self.receiving_tx(n)
+                    .and_then(|tx| tx.block)
+                    .map_or(false, |h| h <= anchor_height)
+            })
+            .filter(|(_, n)| {
+                n.commitment_tree_position.map_or(false, |pos| {
+                    !unscanned_positions.iter().any(|r| r.contains(&pos))
+                })
+            })
+            .filter(|(id, _)| {
+                !self.is_note_spent_by(**id, |stx| stx.is_unexpired_at(anchor_height))
+            });
+
+        // Select notes in order of insertion until the target value is reached.
+        let mut selected = vec![];
+        let mut so_far = NonNegativeAmount::ZERO;
+        for (id, note) in eligible {
+            so_far = (so_far + note.note.value()).ok_or(BalanceError::Overflow)?;
+            selected.extend(Self::to_received_note(*id, note));
+            if so_far >= target_value {
+                break;
+            }
+        }
+
+        Ok(selected)
+    }
+
+    /// Returns the value of the unspent shielded notes belonging to the given account, in
+    /// transactions that have been mined.
+    fn unspent_shielded_balance(
+        &self,
+        account: AccountId,
+        chain_tip_height: BlockHeight,
+    ) -> Result<Option<NonNegativeAmount>, Error> {
+        let mut values = self
+            .received_notes
+            .iter()
+            .filter(|(_, n)| n.account_id == account)
+            .filter(|(_, n)| {
+                self.receiving_tx(n)
+                    .map_or(false, |tx| tx.mined_height.is_some())
+            })
+            .filter(|(id, _)| {
+                !self.is_note_spent_by(**id, |stx| stx.is_unexpired_at(chain_tip_height))
+            })
+            .map(|(_, n)| n.note.value())
+            .peekable();
+
+        if values.peek().is_none() {
+            Ok(None)
+        } else {
+            Ok(Some(sum_values(values)?))
+        }
+    }
+
+    /// Evaluates the provided note filter conditions against the wallet in order to determine
+    /// the minimum value of notes to be produced by note splitting.
+    fn min_note_value(
+        &self,
+        account: AccountId,
+        filter: &NoteFilter,
+        chain_tip_height: BlockHeight,
+    ) -> Result<Option<NonNegativeAmount>, Error> {
+        match filter {
+            NoteFilter::ExceedsMinValue(v) => Ok(Some(*v)),
+            NoteFilter::ExceedsPriorSendPercentile(n) => {
+                let mut values = self
+                    .sent_outputs
+                    .iter()
+                    .filter(|(_, o)| o.from_account_id == account)
+                    // only count mined transactions
+                    .filter(|((txid, _, _), _)| {
+                        self.transactions
+                            .get(txid)
+                            .map_or(false, |tx| tx.mined_height.is_some())
+                    })
+                    // exclude change and account-internal sends
+                    .filter(|(_, o)| o.to_account_id != Some(o.from_account_id))
+                    .map(|(_, o)| o.value)
+                    .collect::<Vec<_>>();
+                values.sort();
+
+                // Divide the sorted values into (at most) 10 buckets of near-equal size, with
+                // any remainder distributed one apiece to the leading buckets.
+                let bucket_count = std::cmp::min(values.len(), 10);
+                let mut bucket_maxima = Vec::with_capacity(bucket_count);
+                let mut end = 0;
+                for i in 0..bucket_count {
+                    end +=
+                        values.len() / bucket_count + usize::from(i < values.len() % bucket_count);
+                    bucket_maxima.push(values[end - 1]);
+                }
+
+                // Pick a bucket index by scaling the requested percentile to the number of buckets
+                let i = (bucket_maxima.len() * usize::from(*n) / 100).saturating_sub(1);
+                Ok(bucket_maxima.get(i).copied())
+            }
+            NoteFilter::ExceedsBalancePercentage(p) => {
+                match self.unspent_shielded_balance(account, chain_tip_height)? {
+                    None => Ok(None),
+                    Some(b) => {
+                        let numerator = (b * u64::from(p.value())).ok_or(BalanceError::Overflow)?;
+                        Ok(Some(
+                            numerator / NonZeroU64::new(100).expect("Constant is nonzero."),
+                        ))
+                    }
+                }
+            }
+            NoteFilter::Combine(a, b) => {
+                // All the existing note selectors set lower bounds on note value, so the "and"
+                // operation is just taking the maximum of the two lower bounds.
+                let a_min_value = self.min_note_value(account, a.as_ref(), chain_tip_height)?;
+                let b_min_value = self.min_note_value(account, b.as_ref(), chain_tip_height)?;
+                Ok(a_min_value
+                    .zip(b_min_value)
+                    .map(|(av, bv)| std::cmp::max(av, bv))
+                    .or(a_min_value)
+                    .or(b_min_value))
+            }
+            NoteFilter::Attempt {
+                condition,
+                fallback,
+            } => {
+                let cond = self.min_note_value(account, condition.as_ref(), chain_tip_height)?;
+                if cond.is_none() {
+                    self.min_note_value(account, fallback, chain_tip_height)
+                } else {
+                    Ok(cond)
+                }
+            }
+        }
+    }
+
+    pub(crate) fn spendable_notes_meta(
+        &self,
+        protocol: ShieldedProtocol,
+        chain_tip_height: BlockHeight,
+        account: AccountId,
+        filter: &NoteFilter,
+        exclude: &[ReceivedNoteId],
+    ) -> Result<Option<PoolMeta>, Error> {
+        let excluded = exclude
+            .iter()
+            .filter(|ReceivedNoteId(p, _)| *p == protocol)
+            .map(|ReceivedNoteId(_, id)| *id)
+            .collect::<HashSet<_>>();
+
+        if let Some(min_value) = self.min_note_value(account, filter, chain_tip_height)? {
+            let values = self
+                .received_notes
+                .iter()
+                .filter(|(id, n)| {
+                    n.note.protocol() == protocol
+                        && n.account_id == account
+                        && n.note.value() >= min_value
+                        && !excluded.contains(id)
+                })
+                .filter(|(_, n)| {
+                    self.receiving_tx(n)
+                        .map_or(false, |tx| tx.mined_height.is_some())
+                })
+                .filter(|(id, _)| {
+                    !self.is_note_spent_by(**id, |stx| stx.is_unexpired_at(chain_tip_height))
+                })
+                .map(|(_, n)| n.note.value())
+                .collect::<Vec<_>>();
+
+            Ok(Some(PoolMeta::new(
+                values.len(),
+                sum_values(values.into_iter())?,
+            )))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the nullifiers for the notes that this wallet is tracking, along with their
+    /// associated account IDs, that match the given query.
+    pub(crate) fn get_nullifiers(
+        &self,
+        protocol: ShieldedProtocol,
+        query: NullifierQuery,
+    ) -> Vec<(AccountId, [u8; 32])> {
+        self.received_notes
+            .iter()
+            .filter(|(_, n)| n.note.protocol() == protocol)
+            .filter(|(id, n)| match query {
+                NullifierQuery::Unspent => {
+                    self.receiving_tx(n).map_or(false, |tx| tx.block.is_some())
+                        && !self.is_note_spent_by(**id, |stx| {
+                            // the spending tx is mined, or will not expire
+                            stx.block.is_some() || stx.expiry_height.is_none()
+                        })
+                }
+                NullifierQuery::All => true,
+            })
+            .filter_map(|(_, n)| n.nf.map(|nf| (n.account_id, nf)))
+            .collect()
+    }
+
+    /// Returns the set of accounts that own the notes with the given nullifiers.
+    pub(crate) fn detect_spending_accounts(
+        &self,
+        protocol: ShieldedProtocol,
+        nfs: impl Iterator<Item = [u8; 32]>,
+    ) -> HashSet<AccountId> {
+        let nfs = nfs.collect::<HashSet<_>>();
+        self.received_notes
+            .values()
+            .filter(|n| n.note.protocol() == protocol)
+            .filter(|n| n.nf.map_or(false, |nf| nfs.contains(&nf)))
+            .map(|n| n.account_id)
+            .collect()
+    }
+
+    /// Marks the note with the given nullifier as having been spent in the given transaction,
+    /// if such a note is known to the wallet.
+    pub(crate) fn mark_note_spent(
+        &mut self,
+        protocol: ShieldedProtocol,
+        nf: &[u8; 32],
+        txid: TxId,
+    ) {
+        let spent = self
+            .received_notes
+            .iter()
+            .filter(|(_, n)| n.note.protocol() == protocol && n.nf.as_ref() == Some(nf))
+            .map(|(id, _)| (*id, txid))
+            .collect::<Vec<_>>();
+        self.received_note_spends.extend(spent);
+    }
+
+    /// Records the given received note in the wallet, merging it with any existing record
+    /// for the same output.
+    pub(crate) fn put_received_note<T: ReceivedOutput>(
+        &mut self,
+        output: &T,
+        txid: TxId,
+        spent_in: Option<TxId>,
+    ) -> Result<(), Error> {
+        let note = output.note();
+        let output_index = u32::try_from(output.index()).map_err(|_| {
+            Error::CorruptedData(format!("Output index {} out of range", output.index()))
+        })?;
+
+        let existing = self.received_notes.iter_mut().find(|(_, n)| {
+            n.txid == txid && n.output_index == output_index && n.note.protocol() == note.protocol()
+        });
+
+        let note_id = match existing {
+            Some((id, record)) => {
+                record.account_id = output.account_id();
+                record.note = note;
+                record.nf = output.nullifier().or(record.nf);
+                record.is_change = output.is_change() || record.is_change;
+                record.memo = output.memo().cloned().or(record.memo.take());
+                record.commitment_tree_position = output
+                    .note_commitment_tree_position()
+                    .or(record.commitment_tree_position);
+                record.recipient_key_scope = output.recipient_key_scope();
+                *id
+            }
+            None => {
+                let id = self
+                    .received_notes
+                    .keys()
+                    .next_back()
+                    .map_or(0, |id| id + 1);
+                self.received_notes.insert(
+                    id,
+                    ReceivedNoteRecord {
+                        txid,
+                        output_index,
+                        account_id: output.account_id(),
+                        note,
+                        nf: output.nullifier(),
+                        is_change: output.is_change(),
+                        memo: output.memo().cloned(),
+                        commitment_tree_position: output.note_commitment_tree_position(),
+                        recipient_key_scope: output.recipient_key_scope(),
+                    },
+                );
+                id
+            }
+        };
+
+        if let Some(spent_in) = spent_in {
+            self.received_note_spends.insert((note_id, spent_in));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod sapling {
+        use zcash_client_backend::data_api::testing::sapling::SaplingPoolTester;
+
+        use crate::testing;
+
+        #[cfg(feature = "orchard")]
+        use zcash_client_backend::data_api::testing::orchard::OrchardPoolTester;
+
+        #[test]
+        fn send_single_step_proposed_transfer() {
+            testing::pool::send_single_step_proposed_transfer::<SaplingPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "pczt")]
+        fn pczt_single_step() {
+            testing::pool::pczt_single_step::<SaplingPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "pczt")]
+        fn pczt_single_step_sapling_only_ufvk() {
+            testing::pool::pczt_single_step_sapling_only_ufvk()
+        }
+
+        #[test]
+        #[cfg(feature = "pczt")]
+        fn send_single_step_with_signer() {
+            testing::pool::send_single_step_with_signer::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn send_with_multiple_change_outputs() {
+            testing::pool::send_with_multiple_change_outputs::<SaplingPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "transparent-inputs")]
+        fn send_multi_step_proposed_transfer() {
+            testing::pool::send_multi_step_proposed_transfer::<SaplingPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "transparent-inputs")]
+        fn proposal_fails_if_not_all_ephemeral_outputs_consumed() {
+            testing::pool::proposal_fails_if_not_all_ephemeral_outputs_consumed::<SaplingPoolTester>(
+            )
+        }
+
+        #[test]
+        fn create_to_address_fails_on_incorrect_usk() {
+            testing::pool::create_to_address_fails_on_incorrect_usk::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn proposal_fails_with_no_blocks() {
+            testing::pool::proposal_fails_with_no_blocks::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn spend_fails_on_unverified_notes() {
+            testing::pool::spend_fails_on_unverified_notes::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn spend_fails_on_locked_notes() {
+            testing::pool::spend_fails_on_locked_notes::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn ovk_policy_prevents_recovery_from_chain() {
+            testing::pool::ovk_policy_prevents_recovery_from_chain::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn spend_succeeds_to_t_addr_zero_change() {
+            testing::pool::spend_succeeds_to_t_addr_zero_change::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn change_note_spends_succeed() {
+            testing::pool::change_note_spends_succeed::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn external_address_change_spends_detected_in_restore_from_seed() {
+            testing::pool::external_address_change_spends_detected_in_restore_from_seed::<
+                SaplingPoolTester,
+            >()
+        }
+
+        #[test]
+        #[ignore] // FIXME: #1316 This requires support for dust outputs; see the README.
+        fn zip317_spend() {
+            testing::pool::zip317_spend::<SaplingPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "transparent-inputs")]
+        fn shield_transparent() {
+            testing::pool::shield_transparent::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn birthday_in_anchor_shard() {
+            testing::pool::birthday_in_anchor_shard::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn checkpoint_gaps() {
+            testing::pool::checkpoint_gaps::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn scan_cached_blocks_detects_spends_out_of_order() {
+            testing::pool::scan_cached_blocks_detects_spends_out_of_order::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn metadata_queries_exclude_unwanted_notes() {
+            testing::pool::metadata_queries_exclude_unwanted_notes::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn account_deletion() {
+            testing::pool::account_deletion::<SaplingPoolTester>()
+        }
+
+        #[test]
+        fn transaction_history() {
+            testing::pool::transaction_history::<SaplingPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "orchard")]
+        fn pool_crossing_required() {
+            testing::pool::pool_crossing_required::<SaplingPoolTester, OrchardPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "orchard")]
+        fn fully_funded_fully_private() {
+            testing::pool::fully_funded_fully_private::<SaplingPoolTester, OrchardPoolTester>()
+        }
+
+        #[test]
+        #[cfg(all(feature = "orchard", feature = "transparent-inputs"))]
+        fn fully_funded_send_to_t() {
+            testing::pool::fully_funded_send_to_t::<SaplingPoolTester, OrchardPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "orchard")]
+        fn multi_pool_checkpoint() {
+            testing::pool::multi_pool_checkpoint::<SaplingPoolTester, OrchardPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "orchard")]
+        fn multi_pool_checkpoints_with_pruning() {
+            testing::pool::multi_pool_checkpoints_with_pruning::<SaplingPoolTester, OrchardPoolTester>(
+            )
+        }
+    }
+
+    #[cfg(feature = "orchard")]
+    mod orchard {
+        use zcash_client_backend::data_api::testing::{
+            orchard::OrchardPoolTester, sapling::SaplingPoolTester,
+        };
+
+        use crate::testing;
+
+        #[test]
+        fn send_single_step_proposed_transfer() {
+            testing::pool::send_single_step_proposed_transfer::<OrchardPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "pczt")]
+        fn pczt_single_step() {
+            testing::pool::pczt_single_step::<OrchardPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "pczt")]
+        fn send_single_step_with_signer() {
+            testing::pool::send_single_step_with_signer::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn send_with_multiple_change_outputs() {
+            testing::pool::send_with_multiple_change_outputs::<OrchardPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "transparent-inputs")]
+        fn send_multi_step_proposed_transfer() {
+            testing::pool::send_multi_step_proposed_transfer::<OrchardPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "transparent-inputs")]
+        fn proposal_fails_if_not_all_ephemeral_outputs_consumed() {
+            testing::pool::proposal_fails_if_not_all_ephemeral_outputs_consumed::<OrchardPoolTester>(
+            )
+        }
+
+        #[test]
+        fn create_to_address_fails_on_incorrect_usk() {
+            testing::pool::create_to_address_fails_on_incorrect_usk::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn proposal_fails_with_no_blocks() {
+            testing::pool::proposal_fails_with_no_blocks::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn spend_fails_on_unverified_notes() {
+            testing::pool::spend_fails_on_unverified_notes::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn spend_fails_on_locked_notes() {
+            testing::pool::spend_fails_on_locked_notes::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn ovk_policy_prevents_recovery_from_chain() {
+            testing::pool::ovk_policy_prevents_recovery_from_chain::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn spend_succeeds_to_t_addr_zero_change() {
+            testing::pool::spend_succeeds_to_t_addr_zero_change::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn change_note_spends_succeed() {
+            testing::pool::change_note_spends_succeed::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn external_address_change_spends_detected_in_restore_from_seed() {
+            testing::pool::external_address_change_spends_detected_in_restore_from_seed::<
+                OrchardPoolTester,
+            >()
+        }
+
+        #[test]
+        #[ignore] // FIXME: #1316 This requires support for dust outputs; see the README.
+        fn zip317_spend() {
+            testing::pool::zip317_spend::<OrchardPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "transparent-inputs")]
+        fn shield_transparent() {
+            testing::pool::shield_transparent::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn birthday_in_anchor_shard() {
+            testing::pool::birthday_in_anchor_shard::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn checkpoint_gaps() {
+            testing::pool::checkpoint_gaps::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn scan_cached_blocks_detects_spends_out_of_order() {
+            testing::pool::scan_cached_blocks_detects_spends_out_of_order::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn metadata_queries_exclude_unwanted_notes() {
+            testing::pool::metadata_queries_exclude_unwanted_notes::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn account_deletion() {
+            testing::pool::account_deletion::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn transaction_history() {
+            testing::pool::transaction_history::<OrchardPoolTester>()
+        }
+
+        #[test]
+        fn pool_crossing_required() {
+            testing::pool::pool_crossing_required::<OrchardPoolTester, SaplingPoolTester>()
+        }
+
+        #[test]
+        fn fully_funded_fully_private() {
+            testing::pool::fully_funded_fully_private::<OrchardPoolTester, SaplingPoolTester>()
+        }
+
+        #[test]
+        #[cfg(feature = "transparent-inputs")]
+        fn fully_funded_send_to_t() {
+            testing::pool::fully_funded_send_to_t::<OrchardPoolTester, SaplingPoolTester>()
+        }
+
+        #[test]
+        fn multi_pool_checkpoint() {
+            testing::pool::multi_pool_checkpoint::<OrchardPoolTester, SaplingPoolTester>()
+        }
+
+        #[test]
+        fn multi_pool_checkpoints_with_pruning() {
+            testing::pool::multi_pool_checkpoints_with_pruning::<OrchardPoolTester, SaplingPoolTester>(
+            )
+        }
+    }
+}
@@ -0,0 +1,531 @@
+//! Functions for maintaining the wallet's scan queue.
+
+use std::cmp::{max, min};
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use incrementalmerkletree::{Address, Position};
+use tracing::{debug, trace};
+use zcash_client_backend::data_api::{
+    scanning::{spanning_tree::SpanningTree, ScanPriority, ScanRange},
+    SAPLING_SHARD_HEIGHT,
+};
+use zcash_primitives::consensus::{self, BlockHeight, NetworkUpgrade};
+use zcash_protocol::ShieldedProtocol;
+
+use crate::{error::Error, MemoryWalletDb, PRUNING_DEPTH, VERIFY_LOOKAHEAD};
+
+#[cfg(feature = "orchard")]
+use zcash_client_backend::data_api::ORCHARD_SHARD_HEIGHT;
+
+#[cfg(not(feature = "orchard"))]
+use zcash_protocol::PoolType;
+
+/// The intersection of a note commitment tree shard with a range in the scan queue.
+#[derive(Debug, Clone)]
+pub(crate) struct ShardScanRange {
+    pub(crate) start_position: Position,
+    pub(crate) end_position_exclusive: Position,
+    pub(crate) subtree_start_height: BlockHeight,
+    pub(crate) subtree_end_height: Option<BlockHeight>,
+    pub(crate) range: ScanRange,
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    pub(crate) fn suggest_scan_ranges_inner(&self, min_priority: ScanPriority) -> Vec<ScanRange> {
+        let mut ranges = self
+            .scan_queue
+            .iter()
+            .filter(|r| r.priority() >= min_priority)
+            .cloned()
+            .collect::<Vec<_>>();
+        ranges.sort_by(|a, b| {
+            b.priority()
+                .cmp(&a.priority())
+                .then_with(|| b.block_range().end.cmp(&a.block_range().end))
+        });
+        ranges
+    }
+
+    /// Replaces the scan queue entries that overlap or are adjacent to the query range with
+    /// the union of those entries and the provided new entries.
+    pub(crate) fn replace_queue_entries(
+        &mut self,
+        query_range: &Range<BlockHeight>,
+        entries: impl Iterator<Item = ScanRange>,
+        force_rescans: bool,
+    ) {
+        // Ignore ranges that do not overlap and are not adjacent to the query range.
+        let mut overlapping = self
+            .scan_queue
+            .iter()
+            .filter(|r| {
+                !(r.block_range().start > query_range.end
+                    || query_range.start > r.block_range().end)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        overlapping.sort_by_key(|r| r.block_range().end);
+
+        // Iterate over the ranges in the scan queue that overlap the range that we have
+        // identified as needing to be fully scanned. For each such range add it to the
+        // spanning tree (these should all be nonoverlapping ranges, but we might coalesce
+        // some in the process).
+        let mut to_create: Option<SpanningTree> = None;
+        let mut to_delete_ends = BTreeSet::new();
+        for entry in overlapping {
+            to_delete_ends.insert(entry.block_range().end);
+            to_create = if let Some(cur) = to_create {
+                Some(cur.insert(entry, force_rescans))
+            } else {
+                Some(SpanningTree::Leaf(entry))
+            };
+        }
+
+        // Update the tree that we read from the queue, or if we didn't find any ranges
+        // start with the scanned range.
+        for entry in entries {
+            to_create = if let Some(cur) = to_create {
+                Some(cur.insert(entry, force_rescans))
+            } else {
+                Some(SpanningTree::Leaf(entry))
+            };
+        }
+
+        if let Some(tree) = to_create {
+            self.scan_queue
+                .retain(|r| !to_delete_ends.contains(&r.block_range().end));
+
+            for entry in tree.into_vec() {
+                trace!("Inserting queue entry {}", entry);
+                if !entry.is_empty() {
+                    self.scan_queue.push(entry);
+                }
+            }
+            self.scan_queue.sort_by_key(|r| r.block_range().start);
+        }
+    }
+
+    /// Returns the intersections of the note commitment tree shards for the given protocol
+    /// with the ranges of the scan queue.
+    pub(crate) fn shard_scan_ranges(&self, protocol: ShieldedProtocol) -> Vec<ShardScanRange> {
+        let (shard_indices, shard_end, activation) = match protocol {
+            ShieldedProtocol::Sapling => (
+                self.sapling_tree.shard_indices(),
+                self.sapling_tree.shard_end_heights.clone(),
+                self.params.activation_height(NetworkUpgrade::Sapling),
+            ),
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => (
+                self.orchard_tree.shard_indices(),
+                self.orchard_tree.shard_end_heights.clone(),
+                self.params.activation_height(NetworkUpgrade::Nu5),
+            ),
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => return vec![],
+        };
+        let activation = activation.expect("Shielded protocol activation height must be known.");
+
+        let mut result = vec![];
+        for shard_index in shard_indices {
+            let subtree_start_height = shard_index
+                .checked_sub(1)
+                .and_then(|prev| shard_end.get(&prev).copied())
+                .unwrap_or(activation);
+            let subtree_end_height = shard_end.get(&shard_index).copied();
+
+            // Join with scan ranges that overlap with the subtree's involved blocks.
+            for r in self.scan_queue.iter().filter(|r| {
+                subtree_start_height < r.block_range().end
+                    && subtree_end_height.map_or(true, |end| r.block_range().start <= end)
+            }) {
+                result.push(ShardScanRange {
+                    start_position: Position::from(shard_index << 16),
+                    end_position_exclusive: Position::from((shard_index + 1) << 16),
+                    subtree_start_height,
+                    subtree_end_height,
+                    range: r.clone(),
+                });
+            }
+        }
+
+        result
+    }
+
+    /// Returns the shard scan ranges for the given protocol that have not yet been scanned
+    /// and that lie (at least in part) above the wallet birthday.
+    pub(crate) fn unscanned_shard_ranges(&self, protocol: ShieldedProtocol) -> Vec<ShardScanRange> {
+        match self.wallet_birthday() {
+            None => vec![],
+            Some(birthday) => self
+                .shard_scan_ranges(protocol)
+                .into_iter()
+                .filter(|r| {
+                    r.range.priority() > ScanPriority::Scanned
+                        && r.range.block_range().end > birthday
+                })
+                .collect(),
+        }
+    }
+
+    /// Extends the given range to cover the full extent of the note commitment tree subtrees
+    /// having the given indices.
+    fn extend_range(
+        &self,
+        protocol: ShieldedProtocol,
+        range: &Range<BlockHeight>,
+        required_subtree_indices: BTreeSet<u64>,
+        fallback_start_height: Option<BlockHeight>,
+        birthday_height: Option<BlockHeight>,
+    ) -> Option<Range<BlockHeight>> {
+        let shard_end = |index: u64| match protocol {
+            ShieldedProtocol::Sapling => self.sapling_tree.shard_end_height(index),
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => self.orchard_tree.shard_end_height(index),
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => None,
+        };
+
+        // we'll either have both min and max bounds, or we'll have neither
+        let subtree_index_bounds = required_subtree_indices
+            .iter()
+            .min()
+            .zip(required_subtree_indices.iter().max());
+
+        // If no notes belonging to the wallet were found, we don't need to extend the scanning
+        // range suggestions to include the associated subtrees, and our bounds are just the
+        // scanned range. Otherwise, ensure that all shard ranges starting from the wallet
+        // birthday are included.
+        subtree_index_bounds.map(|(min_idx, max_idx)| {
+            let range_min = if *min_idx > 0 {
+                // get the block height of the end of the previous shard
+                shard_end(*min_idx - 1)
+            } else {
+                // our lower bound is going to be the fallback height
+                fallback_start_height
+            };
+
+            // bound the minimum to the wallet birthday
+            let range_min = range_min.map(|h| birthday_height.map_or(h, |b| max(b, h)));
+
+            // Get the block height for the end of the current shard, and make it an
+            // exclusive end bound.
+            let range_max = shard_end(*max_idx).map(|end| end + 1);
+
+            Range {
+                start: range.start.min(range_min.unwrap_or(range.start)),
+                end: range.end.max(range_max.unwrap_or(range.end)),
+            }
+        })
+    }
+
+    /// Updates the scan queue to reflect the completion of scanning of the given range.
+    pub(crate) fn scan_complete(
+        &mut self,
+        range: Range<BlockHeight>,
+        wallet_note_positions: &[(ShieldedProtocol, Position)],
+    ) -> Result<(), Error> {
+        let wallet_birthday = self.wallet_birthday();
+
+        // Determine the range of block heights for which we will be updating the scan queue.
+        let extended_range = {
+            // If notes have been detected in the scan, we need to extend any adjacent
+            // un-scanned ranges starting from the wallet birthday to include the blocks needed
+            // to complete the note commitment tree subtrees containing the positions of the
+            // discovered notes. We will query by subtree index to find these bounds.
+            let mut required_sapling_subtrees = BTreeSet::new();
+            #[cfg(feature = "orchard")]
+            let mut required_orchard_subtrees = BTreeSet::new();
+            for (protocol, position) in wallet_note_positions {
+                match protocol {
+                    ShieldedProtocol::Sapling => {
+                        required_sapling_subtrees.insert(
+                            Address::above_position(SAPLING_SHARD_HEIGHT.into(), *position).index(),
+                        );
+                    }
+                    ShieldedProtocol::Orchard => {
+                        #[cfg(feature = "orchard")]
+                        required_orchard_subtrees.insert(
+                            Address::above_position(ORCHARD_SHARD_HEIGHT.into(), *position).index(),
+                        );
+
+                        #[cfg(not(feature = "orchard"))]
+                        return Err(Error::UnsupportedPoolType(PoolType::Shielded(*protocol)));
+                    }
+                }
+            }
+
+            let extended_range = self.extend_range(
+                ShieldedProtocol::Sapling,
+                &range,
+                required_sapling_subtrees,
+                self.params.activation_height(NetworkUpgrade::Sapling),
+                wallet_birthday,
+            );
+
+            #[cfg(feature = "orchard")]
+            let extended_range = self
+                .extend_range(
+                    ShieldedProtocol::Orchard,
+                    extended_range.as_ref().unwrap_or(&range),
+                    required_orchard_subtrees,
+                    self.params.activation_height(NetworkUpgrade::Nu5),
+                    wallet_birthday,
+                )
+                .or(extended_range);
+
+            #[allow(clippy::let_and_return)]
+            extended_range
+        };
+
+        let query_range = extended_range.clone().unwrap_or_else(|| range.clone());
+
+        let scanned = ScanRange::from_parts(range.clone(), ScanPriority::Scanned);
+
+        // If any of the extended range actually extends beyond the scanned range, we need to
+        // scan that extension in order to make the found note(s) spendable. We need to avoid
+        // creating empty ranges here, as that acts as an optimization barrier preventing
+        // `SpanningTree` from merging non-empty scanned ranges on either side.
+        let extended_before = extended_range
+            .as_ref()
+            .map(|extended| {
+                ScanRange::from_parts(extended.start..range.start, ScanPriority::FoundNote)
+            })
+            .filter(|range| !range.is_empty());
+        let extended_after = extended_range
+            .map(|extended| ScanRange::from_parts(range.end..extended.end, ScanPriority::FoundNote))
+            .filter(|range| !range.is_empty());
+
+        let replacement = Some(scanned)
+            .into_iter()
+            .chain(extended_before)
+            .chain(extended_after);
+
+        self.replace_queue_entries(&query_range, replacement, false);
+
+        Ok(())
+    }
+
+    /// Updates the scan queue to reflect a new chain tip.
+    pub(crate) fn update_chain_tip_inner(&mut self, new_tip: BlockHeight) -> Result<(), Error> {
+        // If the caller provided a chain tip that is before Sapling activation, do nothing.
+        let sapling_activation = match self.params.activation_height(NetworkUpgrade::Sapling) {
+            Some(h) if h <= new_tip => h,
+            _ => return Ok(()),
+        };
+
+        let max_scanned = self.blocks.keys().next_back().copied();
+        let wallet_birthday = self.wallet_birthday();
+
+        // If the chain tip is below the prior max scanned height, then the caller has caught
+        // the chain in the middle of a reorg. Do nothing; the caller will continue using the
+        // old scan ranges and will encounter an error or discontinuity that triggers the
+        // appropriate rewind.
+        match max_scanned {
+            Some(h) if new_tip < h => return Ok(()),
+            _ => (),
+        };
+
+        // `ScanRange` uses an exclusive upper bound.
+        let chain_end = new_tip + 1;
+
+        // The minimum of the maximum shard end heights gives the start of a height range that
+        // covers the last incomplete shard of both the Sapling and Orchard pools.
+        let sapling_shard_tip = self.sapling_tree.max_shard_end_height();
+        #[cfg(feature = "orchard")]
+        let orchard_shard_tip = self.orchard_tree.max_shard_end_height();
+
+        #[cfg(feature = "orchard")]
+        let min_shard_tip = match (sapling_shard_tip, orchard_shard_tip) {
+            (None, None) => None,
+            (None, Some(o)) => Some(o),
+            (Some(s), None) => Some(s),
+            (Some(s), Some(o)) => Some(min(s, o)),
+        };
+        #[cfg(not(feature = "orchard"))]
+        let min_shard_tip = sapling_shard_tip;
+
+        // Create a scanning range for the fragment of the last shard leading up to new tip.
+        // We set a lower bound at the wallet birthday (if known), because account creation
+        // requires specifying a tree frontier that ensures we don't need tree information
+        // prior to the birthday.
+        let tip_shard_entry = min_shard_tip.filter(|h| h < &chain_end).map(|h| {
+            let min_to_scan = wallet_birthday.filter(|b| b > &h).unwrap_or(h);
+            ScanRange::from_parts(min_to_scan..chain_end, ScanPriority::ChainTip)
+        });
+
+        // Create scan ranges to either validate potentially invalid blocks at the wallet's
+        // view of the chain tip, or connect the prior tip to the new tip.
+        let tip_entry = max_scanned.map_or_else(
+            || {
+                // No blocks have been scanned, so we need to anchor the start of the new scan
+                // range to something else.
+                wallet_birthday.map_or_else(
+                    // We don't have a wallet birthday, which means we have no accounts yet.
+                    // We can therefore ignore all blocks up to the chain tip.
+                    || ScanRange::from_parts(sapling_activation..chain_end, ScanPriority::Ignored),
+                    // We have a wallet birthday, so mark all blocks between that and the
+                    // chain tip as `Historic` (performing wallet recovery).
+                    |wallet_birthday| {
+                        ScanRange::from_parts(wallet_birthday..chain_end, ScanPriority::Historic)
+                    },
+                )
+            },
+            |max_scanned| {
+                // The scan range starts at the block after the max scanned height.
+                let min_unscanned = max_scanned + 1;
+
+                // If we don't have shard metadata, this means we're doing linear scanning, so
+                // create a scan range from the prior tip to the current tip with `Historic`
+                // priority.
+                if tip_shard_entry.is_none() {
+                    ScanRange::from_parts(min_unscanned..chain_end, ScanPriority::Historic)
+                } else {
+                    // Determine the height to which we expect new blocks retrieved from the
+                    // block source to be stable and not subject to being reorg'ed.
+                    let stable_height = new_tip.saturating_sub(PRUNING_DEPTH);
+
+                    // If the wallet's max scanned height is above the stable height,
+                    // prioritize the range between it and the new tip as `ChainTip`.
+                    if max_scanned > stable_height {
+                        // We are in the steady-state case, where a wallet is close to the
+                        // chain tip and just needs to catch up.
+                        //
+                        // This overlaps the `tip_shard_entry` range and so will be coalesced
+                        // with it.
+                        ScanRange::from_parts(min_unscanned..chain_end, ScanPriority::ChainTip)
+                    } else {
+                        // The max scanned height is considered stable relative to the chain
+                        // tip, but may not be stable relative to the prior chain tip. To
+                        // confirm its stability, prioritize the `VERIFY_LOOKAHEAD` blocks
+                        // above the max scanned height as `Verify`, limited to at most the
+                        // stable region.
+                        ScanRange::from_parts(
+                            min_unscanned..min(stable_height + 1, min_unscanned + VERIFY_LOOKAHEAD),
+                            ScanPriority::Verify,
+                        )
+                    }
+                }
+            },
+        );
+        if let Some(entry) = &tip_shard_entry {
+            debug!("{} will update latest shard", entry);
+        }
+        debug!("{} will connect prior scanned state to new tip", tip_entry);
+
+        let query_range = match tip_shard_entry.as_ref() {
+            Some(se) => Range {
+                start: min(se.block_range().start, tip_entry.block_range().start),
+                end: max(se.block_range().end, tip_entry.block_range().end),
+            },
+            None => tip_entry.block_range().clone(),
+        };
+
+        self.replace_queue_entries(
+            &query_range,
+            tip_shard_entry.into_iter().chain(Some(tip_entry)),
+            false,
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::testing::sapling::SaplingPoolTester;
+
+    use crate::testing;
+
+    #[cfg(feature = "orchard")]
+    use zcash_client_backend::data_api::testing::orchard::OrchardPoolTester;
+
+    #[test]
+    fn valid_chain_states_sapling() {
+        testing::pool::valid_chain_states::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn valid_chain_states_orchard() {
+        testing::pool::valid_chain_states::<OrchardPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn invalid_chain_cache_disconnected_sapling() {
+        testing::pool::invalid_chain_cache_disconnected::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn invalid_chain_cache_disconnected_orchard() {
+        testing::pool::invalid_chain_cache_disconnected::<OrchardPoolTester>()
+    }
+
+    #[test]
+    fn data_db_truncation_sapling() {
+        testing::pool::data_db_truncation::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn data_db_truncation_orchard() {
+        testing::pool::data_db_truncation::<OrchardPoolTester>()
+    }
+
+    #[test]
+    fn reorg_to_checkpoint_sapling() {
+        testing::pool::reorg_to_checkpoint::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn reorg_to_checkpoint_orchard() {
+        testing::pool::reorg_to_checkpoint::<OrchardPoolTester>()
+    }
+
+    #[test]
+    fn scan_cached_blocks_allows_blocks_out_of_order_sapling() {
+        testing::pool::scan_cached_blocks_allows_blocks_out_of_order::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn scan_cached_blocks_allows_blocks_out_of_order_orchard() {
+        testing::pool::scan_cached_blocks_allows_blocks_out_of_order::<OrchardPoolTester>()
+    }
+
+    #[test]
+    fn scan_cached_blocks_finds_received_notes_sapling() {
+        testing::pool::scan_cached_blocks_finds_received_notes::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn scan_cached_blocks_finds_received_notes_orchard() {
+        testing::pool::scan_cached_blocks_finds_received_notes::<OrchardPoolTester>()
+    }
+
+    #[test]
+    fn scan_cached_blocks_finds_change_notes_sapling() {
+        testing::pool::scan_cached_blocks_finds_change_notes::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn scan_cached_blocks_finds_change_notes_orchard() {
+        testing::pool::scan_cached_blocks_finds_change_notes::<OrchardPoolTester>()
+    }
+
+    #[test]
+    fn scan_cached_blocks_detects_spends_out_of_order_sapling() {
+        testing::pool::scan_cached_blocks_detects_spends_out_of_order::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn scan_cached_blocks_detects_spends_out_of_order_orchard() {
+        testing::pool::scan_cached_blocks_detects_spends_out_of_order::<OrchardPoolTester>()
+    }
+}
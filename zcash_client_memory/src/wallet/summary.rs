@@ -0,0 +1,518 @@
+//! Functions for computing wallet balances and scan progress.
+
+use std::collections::HashMap;
+
+use zcash_client_backend::data_api::{
+    scanning::ScanPriority, AccountBalance, Progress, Ratio, WalletSummary,
+};
+use zcash_primitives::{
+    consensus::{self, BlockHeight, NetworkUpgrade},
+    transaction::components::amount::NonNegativeAmount,
+};
+use zcash_protocol::ShieldedProtocol;
+
+use crate::{error::Error, AccountId, MemoryWalletDb};
+
+use super::BlockRecord;
+
+/// Returns the note commitment tree size and output count recorded for the given protocol
+/// in the given block.
+fn block_tree_stats(block: &BlockRecord, protocol: ShieldedProtocol) -> Option<(u64, u64)> {
+    match protocol {
+        ShieldedProtocol::Sapling => Some((
+            u64::from(block.sapling_commitment_tree_size),
+            u64::from(block.sapling_output_count),
+        )),
+        ShieldedProtocol::Orchard => block
+            .orchard_commitment_tree_size
+            .zip(block.orchard_action_count)
+            .map(|(size, count)| (u64::from(size), u64::from(count))),
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the end heights of the note commitment tree shards for the given protocol.
+    fn shard_end_heights(
+        &self,
+        protocol: ShieldedProtocol,
+    ) -> std::collections::BTreeMap<u64, BlockHeight> {
+        match protocol {
+            ShieldedProtocol::Sapling => self.sapling_tree.shard_end_heights.clone(),
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => self.orchard_tree.shard_end_heights.clone(),
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => Default::default(),
+        }
+    }
+
+    /// Returns the number of outputs of the given protocol in scanned blocks within the
+    /// given height bounds, or `None` if there are no such blocks.
+    fn scanned_output_count(
+        &self,
+        protocol: ShieldedProtocol,
+        range: impl std::ops::RangeBounds<BlockHeight>,
+    ) -> Option<u64> {
+        self.blocks
+            .range(range)
+            .filter_map(|(_, b)| block_tree_stats(b, protocol).map(|(_, count)| count))
+            .fold(None, |acc, n| Some(acc.unwrap_or(0) + n))
+    }
+
+    /// Estimates the size of the note commitment tree at the chain tip by linear
+    /// extrapolation from the available data closest to the chain tip.
+    ///
+    /// If we have scanned blocks within the incomplete subtree, and we know the tree size for
+    /// the end of the most recent scanned range, then we extrapolate from the start of the
+    /// incomplete subtree. Otherwise, we extrapolate from the block-width of the last complete
+    /// subtree. This avoids having a sharp discontinuity in the progress percentages shown to
+    /// users, and gets more accurate the closer to the chain tip we have scanned.
+    fn estimate_tree_size(
+        &self,
+        protocol: ShieldedProtocol,
+        pool_activation_height: BlockHeight,
+        chain_tip_height: BlockHeight,
+    ) -> Option<u64> {
+        // Get the tree size at the last scanned height, if known.
+        let last_scanned = self
+            .blocks
+            .keys()
+            .next_back()
+            .and_then(|h| self.block_metadata_inner(*h))
+            .and_then(|last_scanned| {
+                match protocol {
+                    ShieldedProtocol::Sapling => last_scanned.sapling_tree_size(),
+                    #[cfg(feature = "orchard")]
+                    ShieldedProtocol::Orchard => last_scanned.orchard_tree_size(),
+                    #[cfg(not(feature = "orchard"))]
+                    ShieldedProtocol::Orchard => None,
+                }
+                .map(|tree_size| (last_scanned.block_height(), u64::from(tree_size)))
+            });
+
+        // Get the last completed subtree.
+        let shard_end_heights = self.shard_end_heights(protocol);
+        let last_completed_subtree = shard_end_heights.iter().next_back();
+
+        if let Some((last_completed_subtree_index, last_completed_subtree_end)) =
+            last_completed_subtree
+        {
+            let notes_in_complete_subtrees = (last_completed_subtree_index + 1) << 16;
+
+            // If we know the tree size at the last scanned height, and that
+            // height is within the incomplete subtree, extrapolate.
+            let tip_tree_size = last_scanned.and_then(|(last_scanned, last_scanned_tree_size)| {
+                (last_scanned > *last_completed_subtree_end)
+                    .then(|| {
+                        let scanned_notes = last_scanned_tree_size - notes_in_complete_subtrees;
+                        let scanned_range = u64::from(last_scanned - *last_completed_subtree_end);
+                        let unscanned_range = u64::from(chain_tip_height - last_scanned);
+
+                        (scanned_notes * unscanned_range)
+                            .checked_div(scanned_range)
+                            .map(|extrapolated_unscanned_notes| {
+                                last_scanned_tree_size + extrapolated_unscanned_notes
+                            })
+                    })
+                    .flatten()
+            });
+
+            if let Some(tree_size) = tip_tree_size {
+                Some(tree_size)
+            } else if let Some(second_to_last_completed_subtree_end) = last_completed_subtree_index
+                .checked_sub(1)
+                .and_then(|idx| shard_end_heights.get(&idx))
+            {
+                let subtree_notes = 1 << 16;
+                let subtree_range =
+                    u64::from(*last_completed_subtree_end - *second_to_last_completed_subtree_end);
+                let unscanned_range = u64::from(chain_tip_height - *last_completed_subtree_end);
+
+                (subtree_notes * unscanned_range)
+                    .checked_div(subtree_range)
+                    .map(|extrapolated_incomplete_subtree_notes| {
+                        notes_in_complete_subtrees + extrapolated_incomplete_subtree_notes
+                    })
+            } else {
+                // There's only one completed subtree; its start height must
+                // be the activation height for this shielded protocol.
+                let subtree_notes = 1 << 16;
+
+                let subtree_range = u64::from(*last_completed_subtree_end - pool_activation_height);
+                let unscanned_range = u64::from(chain_tip_height - *last_completed_subtree_end);
+
+                (subtree_notes * unscanned_range)
+                    .checked_div(subtree_range)
+                    .map(|extrapolated_incomplete_subtree_notes| {
+                        subtree_notes + extrapolated_incomplete_subtree_notes
+                    })
+            }
+        } else {
+            // If there are no completed subtrees, but we have scanned some blocks, we can still
+            // interpolate based upon the tree size as of the last scanned block. Here, since we
+            // don't have any subtree data to draw on, we will interpolate based on the number
+            // of blocks since the pool activation height
+            last_scanned.and_then(|(last_scanned_height, last_scanned_tree_size)| {
+                let subtree_range = u64::from(last_scanned_height - pool_activation_height);
+                let unscanned_range = u64::from(chain_tip_height - last_scanned_height);
+
+                (last_scanned_tree_size * unscanned_range)
+                    .checked_div(subtree_range)
+                    .map(|extrapolated_incomplete_subtree_notes| {
+                        last_scanned_tree_size + extrapolated_incomplete_subtree_notes
+                    })
+            })
+        }
+    }
+
+    /// Returns the approximate size of the note commitment tree as of the start of the block
+    /// at the given height.
+    fn tree_size_near(&self, protocol: ShieldedProtocol, as_of: BlockHeight) -> Option<u64> {
+        let size_from_blocks = self
+            .blocks
+            .range(..=as_of)
+            .filter_map(|(_, b)| block_tree_stats(b, protocol).map(|(size, count)| size - count))
+            .max();
+
+        size_from_blocks.or_else(|| {
+            let shard_end_heights = self.shard_end_heights(protocol);
+            let shard_indices = match protocol {
+                ShieldedProtocol::Sapling => self.sapling_tree.shard_indices(),
+                #[cfg(feature = "orchard")]
+                ShieldedProtocol::Orchard => self.orchard_tree.shard_indices(),
+                #[cfg(not(feature = "orchard"))]
+                ShieldedProtocol::Orchard => Default::default(),
+            };
+            shard_indices
+                .into_iter()
+                .find(|idx| shard_end_heights.get(idx).map_or(true, |end| end >= &as_of))
+                .map(|min_idx| min_idx << 16)
+        })
+    }
+
+    fn subtree_scan_progress(
+        &self,
+        protocol: ShieldedProtocol,
+        birthday_height: BlockHeight,
+        recover_until_height: Option<BlockHeight>,
+        fully_scanned_height: Option<BlockHeight>,
+        chain_tip_height: BlockHeight,
+    ) -> Option<Progress> {
+        let pool_activation_height = match protocol {
+            ShieldedProtocol::Sapling => self
+                .params
+                .activation_height(NetworkUpgrade::Sapling)
+                .expect("Sapling activation height must be available."),
+            ShieldedProtocol::Orchard => self
+                .params
+                .activation_height(NetworkUpgrade::Nu5)
+                .expect("NU5 activation height must be available."),
+        };
+
+        let scan_start = recover_until_height.unwrap_or(birthday_height);
+
+        if fully_scanned_height == Some(chain_tip_height) {
+            // Compute the total blocks scanned since the wallet birthday on either side of
+            // the recover-until height.
+            let recover = match recover_until_height {
+                Some(end_height) => self
+                    .scanned_output_count(protocol, birthday_height..end_height)
+                    .map(|n| Ratio::new(n, n)),
+                // If none of the wallet's accounts have a recover-until height, then there
+                // is no recovery phase for the wallet, and therefore the denominator in the
+                // resulting ratio (the number of notes in the recovery range) is zero.
+                None => Some(Ratio::new(0, 0)),
+            };
+
+            let scan = self
+                .scanned_output_count(protocol, scan_start..)
+                .map(|n| Ratio::new(n, n));
+
+            scan.map(|scan| Progress::new(scan, recover))
+        } else {
+            // Get the starting note commitment tree size from the wallet birthday, or failing
+            // that from the blocks table.
+            let birthday_size = self
+                .accounts
+                .values()
+                .find(|a| a.birthday_height == birthday_height)
+                .and_then(|a| match protocol {
+                    ShieldedProtocol::Sapling => Some(a.birthday_sapling_tree_size),
+                    ShieldedProtocol::Orchard => a.birthday_orchard_tree_size,
+                })
+                // If we don't have an explicit birthday tree size, find something nearby.
+                .or_else(|| self.tree_size_near(protocol, birthday_height));
+
+            // Get the note commitment tree size as of the start of the recover-until height.
+            // The outer option indicates whether or not we have recover-until height
+            // information; the inner option indicates whether or not we were able to obtain a
+            // tree size given the recover-until height.
+            let recover_until_size: Option<Option<u64>> =
+                recover_until_height.map(|h| self.tree_size_near(protocol, h));
+
+            // Count the total outputs scanned so far on the birthday side of the
+            // recover-until height.
+            let recovered_count = recover_until_height
+                .map(|end_height| self.scanned_output_count(protocol, birthday_height..end_height));
+
+            // If we've scanned the block at the chain tip, we know how many notes are
+            // currently in the tree.
+            let tip_tree_size = match self
+                .blocks
+                .get(&chain_tip_height)
+                .and_then(|b| block_tree_stats(b, protocol))
+            {
+                Some((tree_size, _)) => Some(tree_size),
+                None => self.estimate_tree_size(protocol, pool_activation_height, chain_tip_height),
+            };
+
+            let recover = recovered_count
+                .zip(recover_until_size)
+                .map(|(recovered, end_size)| {
+                    birthday_size.zip(end_size).map(|(start_size, end_size)| {
+                        Ratio::new(recovered.unwrap_or(0), end_size - start_size)
+                    })
+                })
+                // If none of the wallet's accounts have a recover-until height, then there
+                // is no recovery phase for the wallet, and therefore the denominator in the
+                // resulting ratio (the number of notes in the recovery range) is zero.
+                .unwrap_or_else(|| Some(Ratio::new(0, 0)));
+
+            let scan = {
+                // Count the total outputs scanned so far on the chain tip side of the
+                // recover-until height.
+                let scanned_count = self.scanned_output_count(protocol, scan_start..);
+
+                recover_until_size
+                    .unwrap_or(birthday_size)
+                    .zip(tip_tree_size)
+                    .map(|(start_size, tip_tree_size)| {
+                        Ratio::new(scanned_count.unwrap_or(0), tip_tree_size - start_size)
+                    })
+            };
+
+            scan.map(|scan| Progress::new(scan, recover))
+        }
+    }
+
+    /// Adds the balances of the shielded notes of the given protocol to the given account
+    /// balances, as of the given summary height.
+    fn count_notes(
+        &self,
+        protocol: ShieldedProtocol,
+        summary_height: BlockHeight,
+        account_balances: &mut HashMap<AccountId, AccountBalance>,
+    ) -> Result<(), Error> {
+        // If the shard containing the summary height contains any unscanned ranges that start
+        // below or including that height, none of our shielded balance is currently spendable.
+        let any_spendable = !self.unscanned_shard_ranges(protocol).iter().any(|r| {
+            r.subtree_start_height <= summary_height
+                && summary_height <= r.subtree_end_height.unwrap_or(summary_height)
+                && r.range.block_range().start <= summary_height
+        });
+
+        let shard_scan_ranges = self.shard_scan_ranges(protocol);
+
+        for (id, note) in self
+            .received_notes
+            .iter()
+            .filter(|(_, n)| n.note.protocol() == protocol)
+        {
+            let received_tx = match self.transactions.get(&note.txid) {
+                Some(tx) => tx,
+                None => continue,
+            };
+
+            // Skip notes received in transactions that have expired without being mined.
+            if !(received_tx.block.is_some()
+                || received_tx
+                    .expiry_height
+                    .map_or(true, |h| h >= summary_height))
+            {
+                continue;
+            }
+
+            // Skip notes that have been spent.
+            if self.is_note_spent_by(*id, |stx| stx.is_unexpired_at(summary_height)) {
+                continue;
+            }
+
+            let value = note.note.value();
+
+            // If the note is not positioned, or lies in a shard with no known scan ranges,
+            // the note will not be spendable, so we assign the scan priority to `ChainTip` as
+            // a priority that is greater than `Scanned`.
+            let max_priority = note
+                .commitment_tree_position
+                .and_then(|pos| {
+                    shard_scan_ranges
+                        .iter()
+                        .filter(|r| r.start_position <= pos && pos < r.end_position_exclusive)
+                        .map(|r| r.range.priority())
+                        .max()
+                })
+                .unwrap_or(ScanPriority::ChainTip);
+
+            let received_height = received_tx.block;
+
+            let is_spendable = any_spendable
+                && received_height.iter().any(|h| h <= &summary_height)
+                && max_priority <= ScanPriority::Scanned;
+
+            let is_pending_change =
+                note.is_change && received_height.iter().all(|h| h > &summary_height);
+
+            let (spendable_value, change_pending_confirmation, value_pending_spendability) = {
+                let zero = NonNegativeAmount::ZERO;
+                if is_spendable {
+                    (value, zero, zero)
+                } else if is_pending_change {
+                    (zero, value, zero)
+                } else {
+                    (zero, zero, value)
+                }
+            };
+
+            if let Some(balances) = account_balances.get_mut(&note.account_id) {
+                let update = |bal: &mut zcash_client_backend::data_api::Balance| {
+                    bal.add_spendable_value(spendable_value)?;
+                    bal.add_pending_change_value(change_pending_confirmation)?;
+                    bal.add_pending_spendable_value(value_pending_spendability)?;
+                    Ok::<_, Error>(())
+                };
+                match protocol {
+                    ShieldedProtocol::Sapling => balances.with_sapling_balance_mut(update)?,
+                    #[cfg(feature = "orchard")]
+                    ShieldedProtocol::Orchard => balances.with_orchard_balance_mut(update)?,
+                    #[cfg(not(feature = "orchard"))]
+                    ShieldedProtocol::Orchard => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index of the second-to-last shard root in the given note commitment tree,
+    /// or zero if the tree has fewer than two shards.
+    fn next_subtree_index(&self, protocol: ShieldedProtocol) -> u64 {
+        let shard_indices = match protocol {
+            ShieldedProtocol::Sapling => self.sapling_tree.shard_indices(),
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => self.orchard_tree.shard_indices(),
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => Default::default(),
+        };
+
+        // The last shard will be incomplete, and we want the next range to overlap with
+        // the last complete shard, so return the index of the second-to-last shard root.
+        shard_indices.iter().rev().nth(1).copied().unwrap_or(0)
+    }
+
+    /// Returns the balances of the wallet's accounts, along with the wallet's scan progress,
+    /// as of the chain tip.
+    ///
+    /// `min_confirmations` can be 0, but that case is currently treated identically to
+    /// `min_confirmations == 1` for shielded notes.
+    pub(crate) fn get_wallet_summary_inner(
+        &self,
+        min_confirmations: u32,
+    ) -> Result<Option<WalletSummary<AccountId>>, Error> {
+        let chain_tip_height = match self.chain_tip_height() {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+
+        let birthday_height = match self.wallet_birthday() {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+
+        let recover_until_height = self.recover_until_height();
+
+        let fully_scanned_height = self.block_fully_scanned_inner().map(|m| m.block_height());
+        let summary_height =
+            (chain_tip_height + 1).saturating_sub(std::cmp::max(min_confirmations, 1));
+
+        let sapling_progress = self.subtree_scan_progress(
+            ShieldedProtocol::Sapling,
+            birthday_height,
+            recover_until_height,
+            fully_scanned_height,
+            chain_tip_height,
+        );
+
+        #[cfg(feature = "orchard")]
+        let orchard_progress = self.subtree_scan_progress(
+            ShieldedProtocol::Orchard,
+            birthday_height,
+            recover_until_height,
+            fully_scanned_height,
+            chain_tip_height,
+        );
+        #[cfg(not(feature = "orchard"))]
+        let orchard_progress: Option<Progress> = None;
+
+        // Treat Sapling and Orchard outputs as having the same cost to scan.
+        let progress = sapling_progress
+            .as_ref()
+            .zip(orchard_progress.as_ref())
+            .map(|(s, o)| {
+                Progress::new(
+                    Ratio::new(
+                        s.scan().numerator() + o.scan().numerator(),
+                        s.scan().denominator() + o.scan().denominator(),
+                    ),
+                    s.recovery()
+                        .zip(o.recovery())
+                        .map(|(s, o)| {
+                            Ratio::new(
+                                s.numerator() + o.numerator(),
+                                s.denominator() + o.denominator(),
+                            )
+                        })
+                        .or_else(|| s.recovery())
+                        .or_else(|| o.recovery()),
+                )
+            })
+            .or(sapling_progress)
+            .or(orchard_progress);
+
+        let progress = match progress {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        let mut account_balances = self
+            .accounts
+            .keys()
+            .map(|id| (*id, AccountBalance::ZERO))
+            .collect::<HashMap<_, _>>();
+
+        #[cfg(feature = "orchard")]
+        self.count_notes(
+            ShieldedProtocol::Orchard,
+            summary_height,
+            &mut account_balances,
+        )?;
+        self.count_notes(
+            ShieldedProtocol::Sapling,
+            summary_height,
+            &mut account_balances,
+        )?;
+
+        #[cfg(feature = "transparent-inputs")]
+        self.add_transparent_account_balances(chain_tip_height + 1, &mut account_balances)?;
+
+        let summary = WalletSummary::new(
+            account_balances,
+            chain_tip_height,
+            fully_scanned_height.unwrap_or(birthday_height - 1),
+            progress,
+            self.next_subtree_index(ShieldedProtocol::Sapling),
+            #[cfg(feature = "orchard")]
+            self.next_subtree_index(ShieldedProtocol::Orchard),
+        );
+
+        Ok(Some(summary))
+    }
+}
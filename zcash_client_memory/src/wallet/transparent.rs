@@ -0,0 +1,549 @@
+//! Functions for transparent input support in the wallet.
+
+use std::collections::{HashMap, HashSet};
+
+use zcash_client_backend::{
+    data_api::{Account as _, AccountBalance, TransactionDataRequest},
+    wallet::{TransparentAddressMetadata, WalletTransparentOutput},
+};
+use zcash_primitives::{
+    legacy::{
+        keys::{IncomingViewingKey, NonHardenedChildIndex},
+        TransparentAddress,
+    },
+    transaction::{
+        builder::DEFAULT_TX_EXPIRY_DELTA,
+        components::{amount::NonNegativeAmount, OutPoint, TxOut},
+        TxId,
+    },
+};
+use zcash_protocol::consensus::{self, BlockHeight};
+use zip32::{DiversifierIndex, Scope};
+
+use crate::{error::Error, AccountId, MemoryWalletDb, UtxoId};
+
+use super::{notes::sum_values, TransactionRecord};
+
+pub(crate) mod ephemeral;
+
+pub use ephemeral::EphemeralAddressRecord;
+
+/// A transparent output received by the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransparentOutputRecord {
+    pub(crate) account_id: AccountId,
+    pub(crate) address: TransparentAddress,
+    pub(crate) txout: TxOut,
+    /// The maximum block height at which this output is known to have been unspent.
+    pub(crate) max_observed_unspent_height: Option<BlockHeight>,
+}
+
+/// Returns the [`NonHardenedChildIndex`] corresponding to a diversifier index.
+fn address_index_from_diversifier_index(
+    diversifier_index: u128,
+) -> Result<NonHardenedChildIndex, Error> {
+    let di = DiversifierIndex::try_from(diversifier_index).map_err(|_| {
+        Error::CorruptedData("Diversifier index is not an 11-byte value".to_owned())
+    })?;
+    NonHardenedChildIndex::from_index(u32::try_from(di).map_err(|_| {
+        Error::CorruptedData("Unable to get diversifier for transparent address.".to_string())
+    })?)
+    .ok_or_else(|| {
+        Error::CorruptedData("Unexpected hardened index for transparent address.".to_string())
+    })
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the set of accounts that own the transparent outputs spent by the given
+    /// prevouts.
+    pub(crate) fn detect_transparent_spending_accounts<'a>(
+        &self,
+        spent: impl Iterator<Item = &'a OutPoint>,
+    ) -> HashSet<AccountId> {
+        spent
+            .filter_map(|prevout| self.transparent_outputs.get(prevout))
+            .map(|o| o.account_id)
+            .collect()
+    }
+
+    /// Returns the legacy transparent address (at BIP 44 address index 0) for the given
+    /// account, if the account has a transparent component.
+    pub(crate) fn get_legacy_transparent_address(
+        &self,
+        account_id: AccountId,
+    ) -> Option<(TransparentAddress, NonHardenedChildIndex)> {
+        self.accounts.get(&account_id).and_then(|account| {
+            account
+                .uivk()
+                .transparent()
+                .as_ref()
+                .map(|tivk| tivk.default_address())
+        })
+    }
+
+    pub(crate) fn get_transparent_receivers_inner(
+        &self,
+        account: AccountId,
+    ) -> Result<HashMap<TransparentAddress, Option<TransparentAddressMetadata>>, Error> {
+        let mut ret: HashMap<TransparentAddress, Option<TransparentAddressMetadata>> =
+            HashMap::new();
+
+        for (di, ua) in self.addresses.get(&account).into_iter().flatten() {
+            if let Some(taddr) = ua.transparent() {
+                let address_index = address_index_from_diversifier_index(*di)?;
+                let metadata =
+                    TransparentAddressMetadata::new(Scope::External.into(), address_index);
+                ret.insert(*taddr, Some(metadata));
+            }
+        }
+
+        if let Some((taddr, address_index)) = self.get_legacy_transparent_address(account) {
+            let metadata = TransparentAddressMetadata::new(Scope::External.into(), address_index);
+            ret.insert(taddr, Some(metadata));
+        }
+
+        Ok(ret)
+    }
+
+    pub(crate) fn get_transparent_address_metadata_inner(
+        &self,
+        account_id: AccountId,
+        address: &TransparentAddress,
+    ) -> Result<Option<TransparentAddressMetadata>, Error> {
+        if let Some(di) = self
+            .addresses
+            .get(&account_id)
+            .into_iter()
+            .flatten()
+            .find(|(_, ua)| ua.transparent() == Some(address))
+            .map(|(di, _)| *di)
+        {
+            let address_index = address_index_from_diversifier_index(di)?;
+            let metadata = TransparentAddressMetadata::new(Scope::External.into(), address_index);
+            return Ok(Some(metadata));
+        }
+
+        if let Some((legacy_taddr, address_index)) = self.get_legacy_transparent_address(account_id)
+        {
+            if &legacy_taddr == address {
+                let metadata =
+                    TransparentAddressMetadata::new(Scope::External.into(), address_index);
+                return Ok(Some(metadata));
+            }
+        }
+
+        // Search known ephemeral addresses.
+        if let Some((_, address_index)) = self
+            .find_ephemeral_address(address)
+            .filter(|(account, _)| account == &account_id)
+        {
+            return Ok(Some(ephemeral::metadata(address_index)));
+        }
+
+        Ok(None)
+    }
+
+    /// Attempts to determine the account that received the given transparent output.
+    ///
+    /// The following three locations in the wallet's key tree are searched:
+    /// - Transparent receivers that have been generated as part of a Unified Address.
+    /// - Transparent ephemeral addresses that have been reserved or are within
+    ///   the gap limit from the last reserved address.
+    /// - "Legacy transparent addresses" (at BIP 44 address index 0 within an account).
+    ///
+    /// Returns `None` if the transparent output's recipient address is not in any of the
+    /// above locations. This means the wallet considers the output "not interesting".
+    pub(crate) fn find_account_for_transparent_address(
+        &self,
+        address: &TransparentAddress,
+    ) -> Option<AccountId> {
+        self.addresses
+            .iter()
+            .find(|(_, addrs)| addrs.values().any(|ua| ua.transparent() == Some(address)))
+            .map(|(account_id, _)| *account_id)
+            .or_else(|| {
+                self.find_ephemeral_address(address)
+                    .map(|(account_id, _)| account_id)
+            })
+            .or_else(|| {
+                self.accounts.keys().copied().find(|account_id| {
+                    self.get_legacy_transparent_address(*account_id)
+                        .map_or(false, |(legacy_taddr, _)| &legacy_taddr == address)
+                })
+            })
+    }
+
+    fn to_wallet_transparent_output(
+        &self,
+        outpoint: &OutPoint,
+        output: &TransparentOutputRecord,
+    ) -> Result<WalletTransparentOutput, Error> {
+        WalletTransparentOutput::from_parts(
+            outpoint.clone(),
+            output.txout.clone(),
+            self.transactions
+                .get(outpoint.txid())
+                .and_then(|tx| tx.mined_height),
+        )
+        .ok_or_else(|| {
+            Error::CorruptedData(
+                "Txout script_pubkey value did not correspond to a P2PKH or P2SH address"
+                    .to_string(),
+            )
+        })
+    }
+
+    /// Returns whether the given transparent output has been spent by a transaction
+    /// satisfying the given predicate.
+    fn is_transparent_output_spent_by(
+        &self,
+        outpoint: &OutPoint,
+        spending_tx: impl Fn(&TransactionRecord) -> bool,
+    ) -> bool {
+        self.transparent_output_spends
+            .iter()
+            .filter(|(op, _)| op == outpoint)
+            .any(|(_, txid)| self.transactions.get(txid).map_or(false, &spending_tx))
+    }
+
+    /// Returns the given output if it is known to the wallet, and (unless
+    /// `allow_unspendable` is set) if it can be used to fund a new transaction that is
+    /// targeting at least `chain_tip_height + 1`.
+    pub(crate) fn get_wallet_transparent_output(
+        &self,
+        outpoint: &OutPoint,
+        allow_unspendable: bool,
+    ) -> Result<Option<WalletTransparentOutput>, Error> {
+        let mempool_height = self.chain_tip_height().map(|h| h + 1);
+
+        let output = match self.transparent_outputs.get(outpoint) {
+            Some(output) => output,
+            None => return Ok(None),
+        };
+        let tx = match self.transactions.get(outpoint.txid()) {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+
+        // The transaction that created the output must be mined, and the output unspent.
+        let spendable = tx.mined_height.is_some()
+            && !self.is_transparent_output_spent_by(outpoint, |stx| {
+                stx.mined_height.is_some() // the spending tx is mined
+                    || stx.expiry_height == Some(BlockHeight::from(0)) // the spending tx will not expire
+                    || stx
+                        .expiry_height
+                        .zip(mempool_height)
+                        .map_or(false, |(e, m)| e >= m) // the spending tx has not yet expired
+            });
+
+        if allow_unspendable || spendable {
+            self.to_wallet_transparent_output(outpoint, output)
+                .map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the list of spendable transparent outputs received by this wallet at
+    /// `address` such that, at height `target_height`:
+    /// * the transaction that produced the output had or will have at least
+    ///   `min_confirmations` confirmations; and
+    /// * the output is unspent as of the current chain tip.
+    pub(crate) fn get_spendable_transparent_outputs_inner(
+        &self,
+        address: &TransparentAddress,
+        target_height: BlockHeight,
+        min_confirmations: u32,
+    ) -> Result<Vec<WalletTransparentOutput>, Error> {
+        let confirmed_height = target_height - min_confirmations;
+
+        self.transparent_outputs
+            .iter()
+            .filter(|(_, o)| &o.address == address)
+            .filter(|(outpoint, _)| {
+                self.transactions
+                    .get(outpoint.txid())
+                    .and_then(|tx| tx.mined_height)
+                    .map_or(false, |h| h <= confirmed_height)
+            })
+            .filter(|(outpoint, _)| {
+                // We are intentionally conservative and exclude outputs that are potentially
+                // spent as of the target height, even if they might actually be spendable due
+                // to expiry of the spending transaction as of the chain tip.
+                !self.is_transparent_output_spent_by(outpoint, |stx| {
+                    stx.mined_height.is_some()
+                        || stx.expiry_height == Some(BlockHeight::from(0))
+                        || stx.expiry_height.map_or(false, |e| e >= target_height)
+                })
+            })
+            .map(|(outpoint, o)| self.to_wallet_transparent_output(outpoint, o))
+            .collect()
+    }
+
+    /// Returns whether the given output is unspent, and was created by a transaction that
+    /// is mined at or below `mined_height`, or is unexpired as of `unexpired_height`.
+    fn transparent_output_balance_filter(
+        &self,
+        outpoint: &OutPoint,
+        is_mined: impl Fn(BlockHeight) -> bool,
+        unexpired_height: Option<BlockHeight>,
+        spend_expiry_height: BlockHeight,
+    ) -> bool {
+        let created = self.transactions.get(outpoint.txid()).map_or(false, |tx| {
+            tx.mined_height.map_or(false, &is_mined)
+                || unexpired_height.map_or(false, |h| {
+                    tx.expiry_height == Some(BlockHeight::from(0))
+                        || tx.expiry_height.map_or(false, |e| e >= h)
+                })
+        });
+
+        created
+            && !self.is_transparent_output_spent_by(outpoint, |stx| {
+                stx.mined_height.is_some()
+                    || stx.expiry_height == Some(BlockHeight::from(0))
+                    || stx
+                        .expiry_height
+                        .map_or(false, |e| e >= spend_expiry_height)
+            })
+    }
+
+    /// Returns a mapping from each transparent receiver associated with the specified account
+    /// to its not-yet-shielded UTXO balance, including only the effects of transactions mined
+    /// at a block height less than or equal to `summary_height`.
+    pub(crate) fn get_transparent_balances_inner(
+        &self,
+        account: AccountId,
+        summary_height: BlockHeight,
+    ) -> Result<HashMap<TransparentAddress, NonNegativeAmount>, Error> {
+        let chain_tip_height = self.chain_tip_height().ok_or(Error::ChainHeightUnknown)?;
+
+        let mut res: HashMap<TransparentAddress, NonNegativeAmount> = HashMap::new();
+        for (outpoint, output) in self
+            .transparent_outputs
+            .iter()
+            .filter(|(_, o)| o.account_id == account)
+        {
+            if self.transparent_output_balance_filter(
+                outpoint,
+                |h| h <= summary_height,
+                // include zero-conf funds that are not expired if the caller has requested it
+                Some(summary_height).filter(|h| h > &chain_tip_height),
+                std::cmp::min(summary_height, chain_tip_height + 1),
+            ) {
+                let balance = res.entry(output.address).or_insert(NonNegativeAmount::ZERO);
+                *balance = sum_values([*balance, output.txout.value].into_iter())?;
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Adds the unshielded balances of the wallet's accounts to the given account balances.
+    pub(crate) fn add_transparent_account_balances(
+        &self,
+        mempool_height: BlockHeight,
+        account_balances: &mut HashMap<AccountId, AccountBalance>,
+    ) -> Result<(), Error> {
+        for (outpoint, output) in self.transparent_outputs.iter() {
+            if self.transparent_output_balance_filter(
+                outpoint,
+                |h| h < mempool_height,
+                Some(mempool_height),
+                mempool_height,
+            ) {
+                account_balances
+                    .entry(output.account_id)
+                    .or_insert(AccountBalance::ZERO)
+                    .add_unshielded_value(output.txout.value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Marks the given UTXO as having been spent.
+    ///
+    /// Returns `true` if the UTXO was known to the wallet.
+    pub(crate) fn mark_transparent_utxo_spent(
+        &mut self,
+        spent_in_tx: TxId,
+        outpoint: &OutPoint,
+    ) -> bool {
+        let known = self.transparent_outputs.contains_key(outpoint);
+        if known {
+            self.transparent_output_spends
+                .insert((outpoint.clone(), spent_in_tx));
+        } else {
+            // We don't actually have the output yet, so we have to record the output as spent
+            // so that when we eventually detect the output, we can create the spend record.
+            self.transparent_spend_map
+                .insert((spent_in_tx, outpoint.clone()));
+        }
+
+        // Since we know that the output is spent, we no longer need to search for
+        // it to find out if it has been spent.
+        self.transparent_spend_search_queue.remove(outpoint);
+
+        known
+    }
+
+    /// Adds the given received UTXO to the wallet.
+    pub(crate) fn put_received_transparent_utxo_inner(
+        &mut self,
+        output: &WalletTransparentOutput,
+    ) -> Result<UtxoId, Error> {
+        let address = output.recipient_address();
+        if let Some(receiving_account) = self.find_account_for_transparent_address(address) {
+            self.put_transparent_output(
+                output.outpoint(),
+                output.txout(),
+                output.mined_height(),
+                address,
+                receiving_account,
+                true,
+            )
+        } else {
+            // The UTXO was not for any of our transparent addresses.
+            Err(Error::AddressNotRecognized(*address))
+        }
+    }
+
+    /// Returns the [`TransactionDataRequest`]s required to find the transactions that spend
+    /// the outputs in the transparent spend search queue.
+    pub(crate) fn transparent_transaction_data_requests(&self) -> Vec<TransactionDataRequest> {
+        // `lightwalletd` will return an error for `GetTaddressTxids` requests having an end
+        // height greater than the current chain tip height, so we take the chain tip height
+        // into account here in order to make this pothole easier for clients of the API to
+        // avoid.
+        let chain_tip_height = self.chain_tip_height();
+
+        // We cannot construct address-based transaction data requests for the case where we
+        // cannot determine the height at which to begin, so we require that either the target
+        // height or mined height be set.
+        self.transparent_spend_search_queue
+            .iter()
+            .filter_map(|(outpoint, address)| {
+                let tx = self.transactions.get(outpoint.txid())?;
+                let block_range_start = tx.target_height.or(tx.mined_height)?;
+                let max_end_height = block_range_start + DEFAULT_TX_EXPIRY_DELTA + 1;
+
+                Some(TransactionDataRequest::SpendsFromAddress {
+                    address: *address,
+                    block_range_start,
+                    block_range_end: Some(
+                        chain_tip_height
+                            .map_or(max_end_height, |h| std::cmp::min(h + 1, max_end_height)),
+                    ),
+                })
+            })
+            .collect()
+    }
+
+    /// Adds a transparent output relevant to this wallet to the wallet.
+    ///
+    /// `output_height` may be None if this is an ephemeral output from a
+    /// transaction we created, that we do not yet know to have been mined.
+    pub(crate) fn put_transparent_output(
+        &mut self,
+        outpoint: &OutPoint,
+        txout: &TxOut,
+        output_height: Option<BlockHeight>,
+        address: &TransparentAddress,
+        receiving_account: AccountId,
+        known_unspent: bool,
+    ) -> Result<UtxoId, Error> {
+        // Check whether we have a record for the block at the output height; if not, the
+        // transaction will be updated with its mined height when the associated block is
+        // scanned.
+        let block = output_height.filter(|h| self.blocks.contains_key(h));
+
+        let tx = self.transactions.entry(*outpoint.txid()).or_default();
+        tx.block = tx.block.or(block);
+        tx.mined_height = output_height;
+
+        let spent_height = self
+            .transparent_output_spends
+            .iter()
+            .filter(|(op, _)| op == outpoint)
+            .find_map(|(_, txid)| self.transactions.get(txid).map(|tx| tx.mined_height))
+            .flatten();
+
+        // The max observed unspent height is either the spending transaction's mined height
+        // - 1, or the current chain tip height if the UTXO was received via a path that
+        // confirmed that it was unspent, such as by querying the UTXO set of the network.
+        let max_observed_unspent = match spent_height {
+            Some(h) => Some(h - 1),
+            None => {
+                if known_unspent {
+                    self.chain_tip_height()
+                } else {
+                    None
+                }
+            }
+        };
+
+        let existing_max_unspent = self
+            .transparent_outputs
+            .get(outpoint)
+            .and_then(|o| o.max_observed_unspent_height);
+        self.transparent_outputs.insert(
+            outpoint.clone(),
+            TransparentOutputRecord {
+                account_id: receiving_account,
+                address: *address,
+                txout: txout.clone(),
+                max_observed_unspent_height: max_observed_unspent.or(existing_max_unspent),
+            },
+        );
+
+        // If we have a record of the output already having been spent, then mark it as spent
+        // using the stored reference to the spending transaction.
+        let spending_txid = self
+            .transparent_spend_map
+            .iter()
+            .filter(|(_, op)| op == outpoint)
+            .filter_map(|(txid, _)| self.transactions.get(txid).map(|tx| (txid, tx.block)))
+            .min_by_key(|(_, block)| (block.is_none(), *block))
+            .map(|(txid, _)| *txid);
+
+        if let Some(spending_txid) = spending_txid {
+            self.mark_transparent_utxo_spent(spending_txid, outpoint);
+        }
+
+        Ok(UtxoId(outpoint.clone()))
+    }
+
+    /// Adds a request to retrieve transactions involving the specified address to the
+    /// transparent spend search queue. Note that such requests are _not_ for data related to
+    /// the transaction that created the output, but instead a request to find where the UTXO
+    /// with the given outpoint is spent.
+    pub(crate) fn queue_transparent_spend_detection(
+        &mut self,
+        receiving_address: TransparentAddress,
+        outpoint: OutPoint,
+    ) {
+        self.transparent_spend_search_queue
+            .entry(outpoint)
+            .or_insert(receiving_address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{BlockCache, MemoryWalletDbFactory};
+
+    #[test]
+    fn put_received_transparent_utxo() {
+        zcash_client_backend::data_api::testing::transparent::put_received_transparent_utxo(
+            MemoryWalletDbFactory,
+        );
+    }
+
+    #[test]
+    fn transparent_balance_across_shielding() {
+        zcash_client_backend::data_api::testing::transparent::transparent_balance_across_shielding(
+            MemoryWalletDbFactory,
+            BlockCache::new(),
+        );
+    }
+}
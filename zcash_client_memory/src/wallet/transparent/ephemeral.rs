@@ -0,0 +1,341 @@
+//! Functions for wallet support of ephemeral transparent addresses.
+
+use std::cmp::{max, min};
+use std::ops::Range;
+
+use zcash_client_backend::{
+    data_api::GAP_LIMIT, keys::AddressGenerationError, wallet::TransparentAddressMetadata,
+};
+use zcash_keys::encoding::AddressCodec;
+use zcash_primitives::{
+    legacy::{
+        keys::{EphemeralIvk, NonHardenedChildIndex, TransparentKeyScope},
+        TransparentAddress,
+    },
+    transaction::TxId,
+};
+use zcash_protocol::consensus;
+
+use crate::{error::Error, AccountId, MemoryWalletDb};
+
+/// An ephemeral transparent address that has been reserved, or is within the gap limit
+/// of the last reserved address, for an account.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EphemeralAddressRecord {
+    /// The address, or `None` if the account cannot derive ephemeral addresses.
+    pub(crate) address: Option<TransparentAddress>,
+    /// The transaction that this wallet created to send to the address.
+    pub(crate) used_in: Option<TxId>,
+    /// The earliest-mined transaction that has been observed to send to the address.
+    pub(crate) seen_in: Option<TxId>,
+}
+
+// Returns `TransparentAddressMetadata` in the ephemeral scope for the
+// given address index.
+pub(crate) fn metadata(address_index: NonHardenedChildIndex) -> TransparentAddressMetadata {
+    TransparentAddressMetadata::new(TransparentKeyScope::EPHEMERAL, address_index)
+}
+
+/// Utility function to return an `Range<u32>` that starts at `i`
+/// and is of length up to `n`. The range is truncated if necessary
+/// so that it contains no elements beyond the maximum valid address
+/// index, `(1 << 31) - 1`.
+pub(crate) fn range_from(i: u32, n: u32) -> Range<u32> {
+    let first = min(1 << 31, i);
+    let last = min(1 << 31, i.saturating_add(n));
+    first..last
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    fn ephemeral_addresses_for(
+        &self,
+        account_id: AccountId,
+    ) -> impl DoubleEndedIterator<Item = (u32, &EphemeralAddressRecord)> {
+        self.ephemeral_addresses
+            .range((account_id, 0)..=(account_id, u32::MAX))
+            .map(|((_, index), record)| (*index, record))
+    }
+
+    /// Returns the first unstored ephemeral address index in the given account.
+    fn first_unstored_ephemeral_index(&self, account_id: AccountId) -> u32 {
+        self.ephemeral_addresses_for(account_id)
+            .next_back()
+            .map_or(0, |(i, _)| i.checked_add(1).unwrap())
+    }
+
+    /// Returns the first unreserved ephemeral address index in the given account.
+    fn first_unreserved_ephemeral_index(&self, account_id: AccountId) -> Result<u32, Error> {
+        self.first_unstored_ephemeral_index(account_id)
+            .checked_sub(GAP_LIMIT)
+            .ok_or_else(|| {
+                Error::CorruptedData("ephemeral addresses have not been initialized".to_owned())
+            })
+    }
+
+    /// Returns the first ephemeral address index in the given account that
+    /// would violate the gap invariant if used.
+    fn first_unsafe_ephemeral_index(&self, account_id: AccountId) -> u32 {
+        // Addresses observed to have been mined in a transaction that we currently see as
+        // unmined are excluded. This is conservative in terms of avoiding violation of the
+        // gap invariant: it can only cause us to get to the end of the gap sooner.
+        let first_unmined_index = self
+            .ephemeral_addresses_for(account_id)
+            .rev()
+            .find(|(_, record)| {
+                record
+                    .seen_in
+                    .and_then(|txid| self.transactions.get(&txid))
+                    .map_or(false, |tx| tx.mined_height.is_some())
+            })
+            .map_or(0, |(i, _)| i.checked_add(1).unwrap());
+
+        min(1 << 31, first_unmined_index.checked_add(GAP_LIMIT).unwrap())
+    }
+
+    /// Returns the ephemeral transparent IVK for a given account ID.
+    fn get_ephemeral_ivk(&self, account_id: AccountId) -> Result<Option<EphemeralIvk>, Error> {
+        let account = self
+            .accounts
+            .get(&account_id)
+            .ok_or(Error::AccountUnknown)?;
+
+        Ok(account
+            .ufvk
+            .transparent()
+            .map(|t| t.derive_ephemeral_ivk())
+            .transpose()?)
+    }
+
+    /// Returns a vector of ephemeral transparent addresses associated with the given
+    /// account controlled by this wallet, along with their metadata. The result includes
+    /// reserved addresses, and addresses for `GAP_LIMIT` additional indices (capped to
+    /// the maximum index).
+    ///
+    /// If `index_range` is some `Range`, it limits the result to addresses with indices
+    /// in that range.
+    pub(crate) fn get_known_ephemeral_addresses_inner(
+        &self,
+        account_id: AccountId,
+        index_range: Option<Range<u32>>,
+    ) -> Vec<(TransparentAddress, TransparentAddressMetadata)> {
+        let index_range = index_range.unwrap_or(0..(1 << 31));
+        let index_range = index_range.start..min(1 << 31, index_range.end);
+
+        self.ephemeral_addresses_for(account_id)
+            .filter(|(i, _)| index_range.contains(i))
+            .filter_map(|(i, record)| {
+                let address_index = NonHardenedChildIndex::from_index(i)?;
+                record.address.map(|a| (a, metadata(address_index)))
+            })
+            .collect()
+    }
+
+    /// If this is a known ephemeral address in any account, returns its account id and
+    /// address index.
+    pub(crate) fn find_ephemeral_address(
+        &self,
+        address: &TransparentAddress,
+    ) -> Option<(AccountId, NonHardenedChildIndex)> {
+        self.ephemeral_addresses
+            .iter()
+            .find(|(_, record)| record.address.as_ref() == Some(address))
+            .and_then(|((account_id, i), _)| {
+                NonHardenedChildIndex::from_index(*i).map(|i| (*account_id, i))
+            })
+    }
+
+    /// Returns a vector with the next `n` previously unreserved ephemeral addresses for
+    /// the given account.
+    ///
+    /// # Errors
+    ///
+    /// * `Error::AccountUnknown`, if there is no account with the given id.
+    /// * `Error::ReachedGapLimit`, if it is not possible to reserve `n` addresses
+    ///   within the gap limit after the last address in this account that is known to have
+    ///   an output in a mined transaction.
+    /// * `Error::AddressGeneration(AddressGenerationError::DiversifierSpaceExhausted)`,
+    ///   if the limit on transparent address indices has been reached.
+    pub(crate) fn reserve_next_n_ephemeral_addresses_inner(
+        &mut self,
+        account_id: AccountId,
+        n: usize,
+    ) -> Result<Vec<(TransparentAddress, TransparentAddressMetadata)>, Error> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        if !self.accounts.contains_key(&account_id) {
+            return Err(Error::AccountUnknown);
+        }
+
+        let first_unreserved = self.first_unreserved_ephemeral_index(account_id)?;
+        let first_unsafe = self.first_unsafe_ephemeral_index(account_id);
+        let allocation = range_from(
+            first_unreserved,
+            u32::try_from(n).map_err(|_| AddressGenerationError::DiversifierSpaceExhausted)?,
+        );
+
+        if allocation.len() < n {
+            return Err(AddressGenerationError::DiversifierSpaceExhausted.into());
+        }
+        if allocation.end > first_unsafe {
+            return Err(Error::ReachedGapLimit(
+                account_id,
+                max(first_unreserved, first_unsafe),
+            ));
+        }
+        self.reserve_ephemeral_addresses_until(account_id, allocation.end)?;
+        Ok(self.get_known_ephemeral_addresses_inner(account_id, Some(allocation)))
+    }
+
+    /// Initializes the ephemeral addresses for an account. This must be called when
+    /// creating an account.
+    pub(crate) fn init_ephemeral_addresses(&mut self, account_id: AccountId) -> Result<(), Error> {
+        self.reserve_ephemeral_addresses_until(account_id, 0)
+    }
+
+    /// Extends the range of stored addresses in an account if necessary so that the index
+    /// of the next address to reserve will be *at least* `next_to_reserve`. If it would
+    /// already have been at least `next_to_reserve`, then do nothing.
+    ///
+    /// Note that this is called from `init_ephemeral_addresses` to set up the initial
+    /// stored addresses, and should not be called from anywhere else.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the precondition `next_to_reserve <= (1 << 31)` does not hold.
+    fn reserve_ephemeral_addresses_until(
+        &mut self,
+        account_id: AccountId,
+        next_to_reserve: u32,
+    ) -> Result<(), Error> {
+        assert!(next_to_reserve <= 1 << 31);
+
+        if let Some(ephemeral_ivk) = self.get_ephemeral_ivk(account_id)? {
+            let first_unstored = self.first_unstored_ephemeral_index(account_id);
+            let range_to_store = first_unstored..(next_to_reserve.checked_add(GAP_LIMIT).unwrap());
+            if range_to_store.is_empty() {
+                return Ok(());
+            }
+
+            // `range_to_store` might contain indices that are not valid transparent child
+            // indices, for which we store no address. Storing them anyway maintains the
+            // invariant that the last `GAP_LIMIT` stored indices are unreserved.
+            for raw_index in range_to_store {
+                let address = NonHardenedChildIndex::from_index(raw_index)
+                    .map(|i| ephemeral_ivk.derive_ephemeral_address(i))
+                    .transpose()?;
+                self.ephemeral_addresses.insert(
+                    (account_id, raw_index),
+                    EphemeralAddressRecord {
+                        address,
+                        used_in: None,
+                        seen_in: None,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the account and index of the given ephemeral address, if it is known,
+    /// checking that it has not already been used or seen in a transaction.
+    fn ephemeral_address_reuse_check(
+        &self,
+        address: &TransparentAddress,
+    ) -> Result<Option<(AccountId, u32)>, Error> {
+        let found = self
+            .ephemeral_addresses
+            .iter()
+            .find(|(_, record)| record.address.as_ref() == Some(address));
+
+        match found {
+            Some((_, record)) => match record.used_in.or(record.seen_in) {
+                Some(txid) => Err(Error::EphemeralAddressReuse(
+                    address.encode(&self.params),
+                    txid,
+                )),
+                None => Ok(found.map(|(k, _)| *k)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// If `address` is one of our ephemeral addresses, mark it as having an output
+    /// in a transaction that we have just created. This has no effect if `address` is
+    /// not one of our ephemeral addresses.
+    ///
+    /// Returns an `Error::EphemeralAddressReuse` error if the address was already used.
+    pub(crate) fn mark_ephemeral_address_as_used(
+        &mut self,
+        ephemeral_address: &TransparentAddress,
+        txid: TxId,
+    ) -> Result<(), Error> {
+        if let Some(key) = self.ephemeral_address_reuse_check(ephemeral_address)? {
+            // We update both `used_in` and `seen_in` here, because a used address has
+            // necessarily been seen in a transaction. We will not treat this as extending
+            // the range of addresses that are safe to reserve unless and until the
+            // transaction is observed as mined.
+            if let Some(record) = self.ephemeral_addresses.get_mut(&key) {
+                record.used_in = Some(txid);
+                record.seen_in = Some(txid);
+            }
+
+            // Maintain the invariant that the last `GAP_LIMIT` addresses are unused and
+            // unseen.
+            let (account_id, address_index) = key;
+            let next_to_reserve = address_index.checked_add(1).expect("valid address index");
+            self.reserve_ephemeral_addresses_until(account_id, next_to_reserve)?;
+        }
+        Ok(())
+    }
+
+    /// If `address` is one of our ephemeral addresses, mark it as having an output
+    /// in the given mined transaction (which may or may not be a transaction we sent).
+    ///
+    /// This call has no effect if `address` is not one of our ephemeral addresses.
+    pub(crate) fn mark_ephemeral_address_as_seen(
+        &mut self,
+        address: &TransparentAddress,
+        txid: TxId,
+    ) -> Result<(), Error> {
+        let key = match self
+            .ephemeral_addresses
+            .iter()
+            .find(|(_, record)| record.address.as_ref() == Some(address))
+        {
+            Some((key, _)) => *key,
+            None => return Ok(()),
+        };
+
+        // Figure out which transaction was mined earlier: `txid`, or any existing tx
+        // referenced by `seen_in` for the given address. Prefer the existing reference in
+        // case of a tie or if both transactions are unmined. This slightly reduces the
+        // chance of unnecessarily reaching the gap limit too early in some corner cases
+        // (because the earlier transaction is less likely to be unmined).
+        let mined_order = |txid: &TxId| {
+            let tx = self.transactions.get(txid);
+            let mined_height = tx.and_then(|tx| tx.mined_height);
+            let tx_index = tx.and_then(|tx| tx.tx_index);
+            (
+                mined_height.is_none(),
+                mined_height,
+                tx_index.is_none(),
+                tx_index,
+            )
+        };
+        let earlier = match self.ephemeral_addresses[&key].seen_in {
+            Some(existing) if mined_order(&existing) <= mined_order(&txid) => existing,
+            _ => txid,
+        };
+
+        if let Some(record) = self.ephemeral_addresses.get_mut(&key) {
+            record.seen_in = Some(earlier);
+        }
+
+        // Maintain the invariant that the last `GAP_LIMIT` addresses are unused and unseen.
+        let (account_id, address_index) = key;
+        let next_to_reserve = address_index.checked_add(1).expect("valid address index");
+        self.reserve_ephemeral_addresses_until(account_id, next_to_reserve)
+    }
+}
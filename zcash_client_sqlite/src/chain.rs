@@ -18,6 +18,12 @@ use {
     std::path::{Path, PathBuf},
 };
 
+#[cfg(feature = "sync")]
+use {rusqlite::Connection as SyncConnection, std::ops::Range};
+
+#[cfg(all(feature = "sync", feature = "unstable"))]
+use std::{fs, io, io::Write};
+
 pub mod init;
 pub mod migrations;
 
@@ -40,8 +46,8 @@ where
     }
 
     // Fetch the CompactBlocks we need to scan
-    let mut stmt_blocks = block_source
-        .0
+    let conn = block_source.conn();
+    let mut stmt_blocks = conn
         .prepare(
             "SELECT height, data FROM compactblocks
             WHERE height >= ?
@@ -93,6 +99,61 @@ where
     Ok(())
 }
 
+/// Returns the height of the highest block in the cache database within the given range,
+/// or in the entire cache if `range` is `None`.
+#[cfg(feature = "sync")]
+pub(crate) fn blockdb_get_tip_height(
+    conn: &SyncConnection,
+    range: Option<&Range<BlockHeight>>,
+) -> Result<Option<BlockHeight>, rusqlite::Error> {
+    let (start, end) = range.map_or((0, u32::MAX), |range| {
+        (u32::from(range.start), u32::from(range.end))
+    });
+    conn.query_row(
+        "SELECT MAX(height) FROM compactblocks WHERE height >= ? AND height < ?",
+        params![start, end],
+        |row| {
+            // `SELECT MAX(_)` will always return a row, but it will return `null` if no
+            // blocks are in the range.
+            let h: Option<u32> = row.get(0)?;
+            Ok(h.map(BlockHeight::from))
+        },
+    )
+}
+
+/// Inserts a batch of blocks into the cache database, replacing any existing blocks at
+/// the same heights.
+#[cfg(feature = "sync")]
+pub(crate) fn blockdb_insert(
+    conn: &SyncConnection,
+    blocks: &[CompactBlock],
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt_insert = tx.prepare(
+            "INSERT INTO compactblocks (height, data) VALUES (?, ?)
+            ON CONFLICT (height) DO UPDATE SET data = excluded.data",
+        )?;
+        for block in blocks {
+            stmt_insert.execute(params![u32::from(block.height()), block.encode_to_vec()])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Deletes the blocks in the given range from the cache database.
+#[cfg(feature = "sync")]
+pub(crate) fn blockdb_delete(
+    conn: &SyncConnection,
+    range: &Range<BlockHeight>,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM compactblocks WHERE height >= ? AND height < ?",
+        params![u32::from(range.start), u32::from(range.end)],
+    )?;
+    Ok(())
+}
+
 /// Data structure representing a row in the block metadata database.
 #[cfg(feature = "unstable")]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -226,6 +287,110 @@ pub(crate) fn blockmetadb_find_block(
     .optional()
 }
 
+/// Returns the height of the highest block in the metadata database within the given
+/// range, or in the entire database if `range` is `None`.
+#[cfg(all(feature = "sync", feature = "unstable"))]
+pub(crate) fn blockmetadb_get_tip_height(
+    conn: &Connection,
+    range: Option<&Range<BlockHeight>>,
+) -> Result<Option<BlockHeight>, rusqlite::Error> {
+    let (start, end) = range.map_or((0, u32::MAX), |range| {
+        (u32::from(range.start), u32::from(range.end))
+    });
+    conn.query_row(
+        "SELECT MAX(height) FROM compactblocks_meta WHERE height >= ? AND height < ?",
+        params![start, end],
+        |row| {
+            let h: Option<u32> = row.get(0)?;
+            Ok(h.map(BlockHeight::from))
+        },
+    )
+}
+
+/// Removes the file at the given path, if it exists.
+#[cfg(all(feature = "sync", feature = "unstable"))]
+fn remove_block_file(path: &Path) -> Result<(), io::Error> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Writes a batch of blocks to the filesystem-backed block cache, and inserts their
+/// metadata into the metadata database.
+///
+/// Any existing blocks at the same heights are replaced, and their files removed.
+#[cfg(all(feature = "sync", feature = "unstable"))]
+pub(crate) fn fsblockdb_insert(
+    cache: &FsBlockDb,
+    blocks: &[CompactBlock],
+) -> Result<(), FsBlockDbError> {
+    let conn = cache.conn();
+    let mut block_meta = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let meta = BlockMeta {
+            height: block.height(),
+            block_hash: block.hash(),
+            block_time: block.time,
+            sapling_outputs_count: block.vtx.iter().map(|tx| tx.outputs.len() as u32).sum(),
+            orchard_actions_count: block.vtx.iter().map(|tx| tx.actions.len() as u32).sum(),
+        };
+
+        // Block files are named by hash, so a block being replaced by one with a
+        // different hash would otherwise leave its file behind.
+        if let Some(existing) = blockmetadb_find_block(&conn, meta.height)? {
+            if existing.block_hash != meta.block_hash {
+                remove_block_file(&existing.block_file_path(&cache.blocks_dir))?;
+            }
+        }
+
+        File::create(meta.block_file_path(&cache.blocks_dir))?.write_all(&block.encode_to_vec())?;
+        block_meta.push(meta);
+    }
+
+    Ok(blockmetadb_insert(&conn, &block_meta)?)
+}
+
+/// Deletes the blocks in the given range from the filesystem-backed block cache.
+///
+/// The metadata for the blocks is removed before their files, so that the metadata
+/// database never refers to a missing file.
+#[cfg(all(feature = "sync", feature = "unstable"))]
+pub(crate) fn fsblockdb_delete(
+    cache: &FsBlockDb,
+    range: &Range<BlockHeight>,
+) -> Result<(), FsBlockDbError> {
+    let conn = cache.conn();
+    let heights = params![u32::from(range.start), u32::from(range.end)];
+    let block_paths = conn
+        .prepare(
+            "SELECT height, blockhash, time, sapling_outputs_count, orchard_actions_count
+             FROM compactblocks_meta
+             WHERE height >= ? AND height < ?",
+        )?
+        .query_map(heights, |row| {
+            Ok(BlockMeta {
+                height: BlockHeight::from_u32(row.get(0)?),
+                block_hash: BlockHash::from_slice(&row.get::<_, Vec<_>>(1)?),
+                block_time: row.get(2)?,
+                sapling_outputs_count: row.get(3)?,
+                orchard_actions_count: row.get(4)?,
+            })
+        })?
+        .map(|row| row.map(|meta| meta.block_file_path(&cache.blocks_dir)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    conn.execute(
+        "DELETE FROM compactblocks_meta WHERE height >= ? AND height < ?",
+        heights,
+    )?;
+    for block_path in block_paths {
+        remove_block_file(&block_path)?;
+    }
+
+    Ok(())
+}
+
 /// Implements a traversal of `limit` blocks of the filesystem-backed
 /// block cache.
 ///
@@ -247,8 +412,8 @@ where
     }
 
     // Fetch the CompactBlocks we need to scan
-    let mut stmt_blocks = cache
-        .conn
+    let conn = cache.conn();
+    let mut stmt_blocks = conn
         .prepare(
             "SELECT height, blockhash, time, sapling_outputs_count, orchard_actions_count
              FROM compactblocks_meta
@@ -325,6 +490,18 @@ mod tests {
 
     use crate::testing;
 
+    #[cfg(feature = "sync")]
+    use {
+        zcash_client_backend::{
+            data_api::{
+                chain::BlockCache,
+                scanning::{ScanPriority, ScanRange},
+            },
+            proto::compact_formats::CompactBlock,
+        },
+        zcash_primitives::consensus::BlockHeight,
+    };
+
     #[cfg(feature = "orchard")]
     use zcash_client_backend::data_api::testing::orchard::OrchardPoolTester;
 
@@ -416,4 +593,158 @@ mod tests {
     fn scan_cached_blocks_detects_spends_out_of_order_orchard() {
         testing::pool::scan_cached_blocks_detects_spends_out_of_order::<OrchardPoolTester>()
     }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    fn sync_run_syncs_to_chain_tip_sapling() {
+        testing::sync::run_syncs_to_chain_tip::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(all(feature = "sync", feature = "orchard"))]
+    fn sync_run_syncs_to_chain_tip_orchard() {
+        testing::sync::run_syncs_to_chain_tip::<OrchardPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    fn sync_run_with_options_reports_progress_sapling() {
+        testing::sync::run_with_options_reports_progress::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(all(feature = "sync", feature = "orchard"))]
+    fn sync_run_with_options_reports_progress_orchard() {
+        testing::sync::run_with_options_reports_progress::<OrchardPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    fn sync_run_with_options_cancelled_clears_block_cache_sapling() {
+        testing::sync::run_with_options_cancelled_clears_block_cache::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(all(feature = "sync", feature = "orchard"))]
+    fn sync_run_with_options_cancelled_clears_block_cache_orchard() {
+        testing::sync::run_with_options_cancelled_clears_block_cache::<OrchardPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    fn sync_process_transaction_data_requests_reports_failures_sapling() {
+        testing::sync::process_transaction_data_requests_reports_failures::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(all(feature = "sync", feature = "orchard"))]
+    fn sync_process_transaction_data_requests_reports_failures_orchard() {
+        testing::sync::process_transaction_data_requests_reports_failures::<OrchardPoolTester>()
+    }
+
+    #[test]
+    #[cfg(all(feature = "sync", feature = "transparent-inputs"))]
+    fn sync_watch_mempool_stores_relevant_transactions() {
+        testing::sync::watch_mempool_stores_relevant_transactions()
+    }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    fn sync_detects_spends_across_concurrent_batches_sapling() {
+        testing::sync::run_detects_spends_across_concurrent_batches::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(all(feature = "sync", feature = "orchard"))]
+    fn sync_detects_spends_across_concurrent_batches_orchard() {
+        testing::sync::run_detects_spends_across_concurrent_batches::<OrchardPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    fn sync_aborts_downloads_on_error_sapling() {
+        testing::sync::run_aborts_downloads_on_error::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(all(feature = "sync", feature = "orchard"))]
+    fn sync_aborts_downloads_on_error_orchard() {
+        testing::sync::run_aborts_downloads_on_error::<OrchardPoolTester>()
+    }
+
+    #[cfg(feature = "sync")]
+    fn block_cache_operations<C: BlockCache>(cache: &C)
+    where
+        C::Error: std::fmt::Debug + Send,
+    {
+        let h = BlockHeight::from_u32;
+        let range = |start, end| ScanRange::from_parts(h(start)..h(end), ScanPriority::Historic);
+        let block = |height: u32, tag: u8| CompactBlock {
+            height: height.into(),
+            hash: vec![tag; 32],
+            ..Default::default()
+        };
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            assert_eq!(cache.get_tip_height(None).unwrap(), None);
+
+            // Insert two non-contiguous runs of blocks.
+            let blocks: Vec<_> = (10..15).chain(16..18).map(|h| block(h, 0)).collect();
+            cache.insert(blocks).await.unwrap();
+            assert_eq!(cache.get_tip_height(None).unwrap(), Some(h(17)));
+            assert_eq!(
+                cache.get_tip_height(Some(&range(10, 16))).unwrap(),
+                Some(h(14))
+            );
+
+            // Reads stop at the first missing block.
+            let read = cache.read(&range(12, 18)).await.unwrap();
+            assert_eq!(
+                read.iter().map(|b| b.height).collect::<Vec<_>>(),
+                vec![12, 13, 14]
+            );
+            assert!(cache.read(&range(15, 18)).await.is_err());
+            assert!(cache.read(&range(15, 15)).await.unwrap().is_empty());
+
+            // Inserting a block at an existing height replaces it.
+            cache.insert(vec![block(13, 1)]).await.unwrap();
+            let read = cache.read(&range(13, 14)).await.unwrap();
+            assert_eq!(read, vec![block(13, 1)]);
+
+            cache.delete(range(11, 14)).await.unwrap();
+            assert_eq!(cache.get_tip_height(Some(&range(11, 14))).unwrap(), None);
+            assert_eq!(
+                cache.read(&range(10, 12)).await.unwrap(),
+                vec![block(10, 0)]
+            );
+
+            cache.truncate(h(14)).await.unwrap();
+            assert_eq!(cache.get_tip_height(None).unwrap(), Some(h(14)));
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    fn block_db_cache() {
+        let cache_file = tempfile::NamedTempFile::new().unwrap();
+        let db_cache = crate::BlockDb::for_path(cache_file.path()).unwrap();
+        crate::chain::init::init_cache_database(&db_cache).unwrap();
+        block_cache_operations(&db_cache);
+    }
+
+    #[test]
+    #[cfg(all(feature = "sync", feature = "unstable"))]
+    fn fs_block_db_cache() {
+        let fsblockdb_root = tempfile::tempdir().unwrap();
+        let mut db_meta = crate::FsBlockDb::for_path(&fsblockdb_root).unwrap();
+        crate::chain::init::init_blockmeta_db(&mut db_meta).unwrap();
+        block_cache_operations(&db_meta);
+
+        // Only the files of the remaining blocks (10 and 14) are left on disk.
+        let block_files = std::fs::read_dir(fsblockdb_root.path().join("blocks"))
+            .unwrap()
+            .count();
+        assert_eq!(block_files, 2);
+    }
 }
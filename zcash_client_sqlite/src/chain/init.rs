@@ -7,6 +7,7 @@ use {
     crate::FsBlockDb,
     schemerz::{Migrator, MigratorError},
     schemerz_rusqlite::RusqliteAdapter,
+    std::sync::PoisonError,
 };
 
 /// Sets up the internal structure of the cache database.
@@ -25,7 +26,7 @@ use {
 /// init_cache_database(&db).unwrap();
 /// ```
 pub fn init_cache_database(db_cache: &BlockDb) -> Result<(), rusqlite::Error> {
-    db_cache.0.execute(
+    db_cache.conn().execute(
         "CREATE TABLE IF NOT EXISTS compactblocks (
             height INTEGER PRIMARY KEY,
             data BLOB NOT NULL
@@ -58,7 +59,10 @@ pub fn init_cache_database(db_cache: &BlockDb) -> Result<(), rusqlite::Error> {
 pub fn init_blockmeta_db(
     db: &mut FsBlockDb,
 ) -> Result<(), MigratorError<uuid::Uuid, rusqlite::Error>> {
-    let adapter = RusqliteAdapter::new(&mut db.conn, Some("schemer_migrations".to_string()));
+    let adapter = RusqliteAdapter::new(
+        db.conn.get_mut().unwrap_or_else(PoisonError::into_inner),
+        Some("schemer_migrations".to_string()),
+    );
     adapter.init().expect("Migrations table setup succeeds.");
 
     let mut migrator = Migrator::new(adapter);
@@ -9,7 +9,10 @@ use zcash_client_backend::data_api::NoteFilter;
 use zcash_client_backend::PoolType;
 use zcash_keys::keys::AddressGenerationError;
 use zcash_primitives::zip32;
-use zcash_primitives::{consensus::BlockHeight, transaction::components::amount::BalanceError};
+use zcash_primitives::{
+    consensus::BlockHeight,
+    transaction::{components::amount::BalanceError, TxId},
+};
 
 use crate::wallet::commitment_tree;
 use crate::AccountId;
@@ -17,7 +20,7 @@ use crate::AccountId;
 #[cfg(feature = "transparent-inputs")]
 use {
     zcash_client_backend::encoding::TransparentCodecError,
-    zcash_primitives::{legacy::TransparentAddress, transaction::TxId},
+    zcash_primitives::legacy::TransparentAddress,
 };
 
 /// The primary error type for the SQLite wallet backend.
@@ -84,6 +87,10 @@ pub enum SqliteClientError {
     /// The account was imported, and ZIP-32 derivation information is not known for it.
     UnknownZip32Derivation,
 
+    /// The ZIP-32 derivation provided for a derived account does not match the derivation
+    /// of the account.
+    Zip32DerivationMismatch,
+
     /// An error occurred deriving a spending key from a seed and a ZIP-32 account index.
     KeyDerivationError(zip32::AccountId),
 
@@ -136,6 +143,9 @@ pub enum SqliteClientError {
     /// used.
     #[cfg(feature = "transparent-inputs")]
     EphemeralAddressReuse(String, TxId),
+
+    /// The transaction with the given ID is not known to the wallet.
+    TransactionUnknown(TxId),
 }
 
 impl error::Error for SqliteClientError {
@@ -179,6 +189,7 @@ impl fmt::Display for SqliteClientError {
             SqliteClientError::AddressGeneration(e) => write!(f, "{}", e),
             SqliteClientError::AccountUnknown => write!(f, "The account with the given ID does not belong to this wallet."),
             SqliteClientError::UnknownZip32Derivation => write!(f, "ZIP-32 derivation information is not known for this account."),
+            SqliteClientError::Zip32DerivationMismatch => write!(f, "The ZIP-32 derivation provided does not match the derivation of the account."),
             SqliteClientError::KeyDerivationError(acct_id) => write!(f, "Key derivation failed for account {}", u32::from(*acct_id)),
             SqliteClientError::BadAccountData(e) => write!(f, "Failed to add account: {}", e),
             SqliteClientError::AccountIdDiscontinuity => write!(f, "Wallet account identifiers must be sequential."),
@@ -199,6 +210,7 @@ impl fmt::Display for SqliteClientError {
             ),
             #[cfg(feature = "transparent-inputs")]
             SqliteClientError::EphemeralAddressReuse(address_str, txid) => write!(f, "The ephemeral address {address_str} previously used in txid {txid} would be reused."),
+            SqliteClientError::TransactionUnknown(txid) => write!(f, "The transaction {txid} is not known to the wallet."),
         }
     }
 }
@@ -38,8 +38,14 @@ use rusqlite::{self, Connection};
 use secrecy::{ExposeSecret, SecretVec};
 use shardtree::{error::ShardTreeError, ShardTree};
 use std::{
-    borrow::Borrow, collections::HashMap, convert::AsRef, fmt, num::NonZeroU32, ops::Range,
+    borrow::Borrow,
+    collections::HashMap,
+    convert::AsRef,
+    fmt,
+    num::NonZeroU32,
+    ops::Range,
     path::Path,
+    sync::{Mutex, MutexGuard, PoisonError},
 };
 use subtle::ConditionallySelectable;
 use tracing::{debug, trace, warn};
@@ -52,8 +58,9 @@ use zcash_client_backend::{
         scanning::{ScanPriority, ScanRange},
         Account, AccountBirthday, AccountMeta, AccountPurpose, AccountSource, BlockMetadata,
         DecryptedTransaction, InputSource, NoteFilter, NullifierQuery, ScannedBlock, SeedRelevance,
-        SentTransaction, SpendableNotes, TransactionDataRequest, WalletCommitmentTrees, WalletRead,
-        WalletSummary, WalletWrite, SAPLING_SHARD_HEIGHT,
+        SentTransaction, SpendableNotes, TransactionDataRequest, TransactionHistoryEntry,
+        TransactionHistoryFilter, WalletCommitmentTrees, WalletRead, WalletSummary, WalletWrite,
+        Zip32Derivation, SAPLING_SHARD_HEIGHT,
     },
     keys::{
         AddressGenerationError, UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey,
@@ -72,7 +79,10 @@ use zcash_primitives::{
 };
 use zip32::fingerprint::SeedFingerprint;
 
-use crate::{error::SqliteClientError, wallet::commitment_tree::SqliteShardStore};
+use crate::{
+    error::SqliteClientError,
+    wallet::{commitment_tree::SqliteShardStore, memos::MemoMatch},
+};
 
 #[cfg(any(test, feature = "test-dependencies", not(feature = "orchard")))]
 use zcash_protocol::PoolType;
@@ -92,6 +102,12 @@ use {
     zcash_primitives::{legacy::TransparentAddress, transaction::components::OutPoint},
 };
 
+#[cfg(feature = "sync")]
+use {
+    async_trait::async_trait, std::convert::Infallible,
+    zcash_client_backend::data_api::chain::BlockCache,
+};
+
 #[cfg(feature = "multicore")]
 use maybe_rayon::{
     prelude::{IndexedParallelIterator, ParallelIterator},
@@ -145,7 +161,6 @@ pub(crate) const VERIFY_LOOKAHEAD: u32 = 10;
 
 pub(crate) const SAPLING_TABLES_PREFIX: &str = "sapling";
 
-#[cfg(feature = "orchard")]
 pub(crate) const ORCHARD_TABLES_PREFIX: &str = "orchard";
 
 #[cfg(not(feature = "orchard"))]
@@ -252,6 +267,22 @@ impl<P: consensus::Parameters + Clone> WalletDb<Connection, P> {
     }
 }
 
+impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> WalletDb<C, P> {
+    /// Returns the outputs of wallet transactions having text memos that contain the given
+    /// phrase, ordered by relevance. At most `limit` matches are returned.
+    ///
+    /// The memos of both received and sent outputs are searched. The query is matched
+    /// case-insensitively against whole words: for example, the query `"inv-0042"` will
+    /// match the memo `"Payment for INV-0042"` but not `"Payment for INV-00421"`.
+    pub fn search_memos(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<MemoMatch>, SqliteClientError> {
+        wallet::memos::search_memos(self.conn.borrow(), query, limit)
+    }
+}
+
 impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> InputSource for WalletDb<C, P> {
     type Error = SqliteClientError;
     type NoteRef = ReceivedNoteId;
@@ -668,6 +699,15 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> WalletRead for W
 
         Ok(iter.collect())
     }
+
+    fn get_transaction_history(
+        &self,
+        filter: &TransactionHistoryFilter<Self::AccountId>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<TransactionHistoryEntry<Self::AccountId>>, Self::Error> {
+        wallet::get_transaction_history(self.conn.borrow(), filter, offset, limit)
+    }
 }
 
 #[cfg(any(test, feature = "test-dependencies"))]
@@ -893,6 +933,64 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
         })
     }
 
+    fn delete_account(&mut self, account: AccountId) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| {
+            let sapling_positions =
+                wallet::get_received_note_positions(wdb.conn.0, SAPLING_TABLES_PREFIX, account)?;
+            #[cfg(feature = "orchard")]
+            let orchard_positions =
+                wallet::get_received_note_positions(wdb.conn.0, ORCHARD_TABLES_PREFIX, account)?;
+
+            wallet::delete_account(wdb.conn.0, account)?;
+
+            // Un-mark the account's note commitment tree positions so that the corresponding
+            // tree data may be pruned.
+            wdb.with_sapling_tree_mut::<_, _, Self::Error>(|tree| {
+                for position in &sapling_positions {
+                    tree.remove_mark(*position, None)?;
+                }
+                Ok(())
+            })?;
+            #[cfg(feature = "orchard")]
+            wdb.with_orchard_tree_mut::<_, _, Self::Error>(|tree| {
+                for position in &orchard_positions {
+                    tree.remove_mark(*position, None)?;
+                }
+                Ok(())
+            })?;
+
+            Ok(())
+        })
+    }
+
+    fn set_account_name(
+        &mut self,
+        account: AccountId,
+        name: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| wallet::set_account_name(wdb.conn.0, account, name))
+    }
+
+    fn set_account_key_source(
+        &mut self,
+        account: AccountId,
+        key_source: Option<&str>,
+        derivation: Option<Zip32Derivation>,
+    ) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| {
+            wallet::set_account_key_source(wdb.conn.0, account, key_source, derivation)
+        })
+    }
+
+    fn set_account_metadata(
+        &mut self,
+        account: AccountId,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| wallet::set_account_metadata(wdb.conn.0, account, key, value))
+    }
+
     fn get_next_available_address(
         &mut self,
         account: AccountId,
@@ -1427,6 +1525,15 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
     ) -> Result<(), Self::Error> {
         self.transactionally(|wdb| wallet::set_transaction_status(wdb.conn.0, txid, status))
     }
+
+    #[cfg(feature = "fiat")]
+    fn set_transaction_fiat_rate(
+        &mut self,
+        txid: TxId,
+        rate: data_api::FiatRate,
+    ) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| wallet::set_transaction_fiat_rate(wdb.conn.0, txid, &rate))
+    }
 }
 
 impl<P: consensus::Parameters> WalletCommitmentTrees for WalletDb<rusqlite::Connection, P> {
@@ -1622,12 +1729,17 @@ impl<'conn, P: consensus::Parameters> WalletCommitmentTrees for WalletDb<SqlTran
 }
 
 /// A handle for the SQLite block source.
-pub struct BlockDb(Connection);
+pub struct BlockDb(Mutex<Connection>);
 
 impl BlockDb {
     /// Opens a connection to the wallet database stored at the specified path.
     pub fn for_path<P: AsRef<Path>>(path: P) -> Result<Self, rusqlite::Error> {
-        Connection::open(path).map(BlockDb)
+        Connection::open(path).map(|conn| BlockDb(Mutex::new(conn)))
+    }
+
+    pub(crate) fn conn(&self) -> MutexGuard<'_, Connection> {
+        // A panic while the connection is in use cannot leave it in an invalid state.
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
     }
 }
 
@@ -1647,6 +1759,66 @@ impl BlockSource for BlockDb {
     }
 }
 
+#[cfg(feature = "sync")]
+#[async_trait]
+impl BlockCache for BlockDb {
+    fn get_tip_height(
+        &self,
+        range: Option<&ScanRange>,
+    ) -> Result<Option<BlockHeight>, Self::Error> {
+        Ok(chain::blockdb_get_tip_height(
+            &self.conn(),
+            range.map(|range| range.block_range()),
+        )?)
+    }
+
+    async fn read(&self, range: &ScanRange) -> Result<Vec<CompactBlock>, Self::Error> {
+        read_contiguous_blocks(self, range)
+    }
+
+    async fn insert(&self, compact_blocks: Vec<CompactBlock>) -> Result<(), Self::Error> {
+        Ok(chain::blockdb_insert(&self.conn(), &compact_blocks)?)
+    }
+
+    async fn delete(&self, range: ScanRange) -> Result<(), Self::Error> {
+        Ok(chain::blockdb_delete(&self.conn(), range.block_range())?)
+    }
+}
+
+/// Reads the longest contiguous run of blocks from the start of `range` out of the given
+/// block source.
+#[cfg(feature = "sync")]
+fn read_contiguous_blocks<BsT: BlockSource>(
+    block_source: &BsT,
+    range: &ScanRange,
+) -> Result<Vec<CompactBlock>, BsT::Error> {
+    if range.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut blocks: Vec<CompactBlock> = vec![];
+    block_source
+        .with_blocks::<_, Infallible>(
+            Some(range.block_range().start),
+            Some(range.len()),
+            |block| {
+                let expected_height = range.block_range().start + blocks.len() as u32;
+                if block.height() == expected_height {
+                    blocks.push(block);
+                }
+                Ok(())
+            },
+        )
+        .map_err(|e| match e {
+            data_api::chain::error::Error::BlockSource(e) => e,
+            data_api::chain::error::Error::Wallet(e) => match e {},
+            data_api::chain::error::Error::Scan(_) => {
+                unreachable!("Reading blocks does not scan them")
+            }
+        })?;
+    Ok(blocks)
+}
+
 /// A block source that reads block data from disk and block metadata from a SQLite database.
 ///
 /// This block source expects each compact block to be stored on disk in the `blocks` subdirectory
@@ -1687,7 +1859,7 @@ impl BlockSource for BlockDb {
 /// order; this assumption is likely to be weakened and/or removed in a future update.
 #[cfg(feature = "unstable")]
 pub struct FsBlockDb {
-    conn: Connection,
+    conn: Mutex<Connection>,
     blocks_dir: PathBuf,
 }
 
@@ -1747,7 +1919,7 @@ impl FsBlockDb {
             let blocks_dir = fsblockdb_root.as_ref().join("blocks");
             fs::create_dir_all(&blocks_dir)?;
             Ok(FsBlockDb {
-                conn: Connection::open(db_path).map_err(FsBlockDbError::Db)?,
+                conn: Mutex::new(Connection::open(db_path).map_err(FsBlockDbError::Db)?),
                 blocks_dir,
             })
         } else {
@@ -1757,9 +1929,14 @@ impl FsBlockDb {
         }
     }
 
+    pub(crate) fn conn(&self) -> MutexGuard<'_, Connection> {
+        // A panic while the connection is in use cannot leave it in an invalid state.
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
     /// Returns the maximum height of blocks known to the block metadata database.
     pub fn get_max_cached_height(&self) -> Result<Option<BlockHeight>, FsBlockDbError> {
-        Ok(chain::blockmetadb_get_max_cached_height(&self.conn)?)
+        Ok(chain::blockmetadb_get_max_cached_height(&self.conn())?)
     }
 
     /// Adds a set of block metadata entries to the metadata database, overwriting any
@@ -1785,13 +1962,13 @@ impl FsBlockDb {
             }
         }
 
-        Ok(chain::blockmetadb_insert(&self.conn, block_meta)?)
+        Ok(chain::blockmetadb_insert(&self.conn(), block_meta)?)
     }
 
     /// Returns the metadata for the block with the given height, if it exists in the
     /// database.
     pub fn find_block(&self, height: BlockHeight) -> Result<Option<BlockMeta>, FsBlockDbError> {
-        Ok(chain::blockmetadb_find_block(&self.conn, height)?)
+        Ok(chain::blockmetadb_find_block(&self.conn(), height)?)
     }
 
     /// Rewinds the BlockMeta Db to the `block_height` provided.
@@ -1804,7 +1981,7 @@ impl FsBlockDb {
     /// does nothing.
     pub fn truncate_to_height(&self, block_height: BlockHeight) -> Result<(), FsBlockDbError> {
         Ok(chain::blockmetadb_truncate_to_height(
-            &self.conn,
+            &self.conn(),
             block_height,
         )?)
     }
@@ -1827,6 +2004,32 @@ impl BlockSource for FsBlockDb {
     }
 }
 
+#[cfg(all(feature = "sync", feature = "unstable"))]
+#[async_trait]
+impl BlockCache for FsBlockDb {
+    fn get_tip_height(
+        &self,
+        range: Option<&ScanRange>,
+    ) -> Result<Option<BlockHeight>, Self::Error> {
+        Ok(chain::blockmetadb_get_tip_height(
+            &self.conn(),
+            range.map(|range| range.block_range()),
+        )?)
+    }
+
+    async fn read(&self, range: &ScanRange) -> Result<Vec<CompactBlock>, Self::Error> {
+        read_contiguous_blocks(self, range)
+    }
+
+    async fn insert(&self, compact_blocks: Vec<CompactBlock>) -> Result<(), Self::Error> {
+        chain::fsblockdb_insert(self, &compact_blocks)
+    }
+
+    async fn delete(&self, range: ScanRange) -> Result<(), Self::Error> {
+        chain::fsblockdb_delete(self, range.block_range())
+    }
+}
+
 #[cfg(feature = "unstable")]
 impl std::fmt::Display for FsBlockDbError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -1885,12 +2088,13 @@ extern crate assert_matches;
 
 #[cfg(test)]
 mod tests {
+    use rusqlite::named_params;
     use secrecy::{ExposeSecret, Secret, SecretVec};
     use zcash_client_backend::data_api::{
         chain::ChainState,
         testing::{TestBuilder, TestState},
         Account, AccountBirthday, AccountPurpose, AccountSource, WalletRead, WalletTest,
-        WalletWrite,
+        WalletWrite, Zip32Derivation,
     };
     use zcash_keys::keys::{UnifiedFullViewingKey, UnifiedSpendingKey};
     use zcash_primitives::block::BlockHash;
@@ -2138,6 +2342,141 @@ mod tests {
         );
     }
 
+    #[test]
+    fn account_name_metadata_and_key_source() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory::default())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let derived = st.test_account().cloned().unwrap();
+        let derivation = derived.derivation().unwrap();
+
+        let birthday = AccountBirthday::from_parts(
+            ChainState::empty(st.network().sapling.unwrap() - 1, BlockHash([0; 32])),
+            None,
+        );
+        let usk = UnifiedSpendingKey::from_seed(st.network(), &[1u8; 32], zip32::AccountId::ZERO)
+            .unwrap();
+        let imported = st
+            .wallet_mut()
+            .import_account_ufvk(
+                &usk.to_unified_full_viewing_key(),
+                &birthday,
+                AccountPurpose::ViewOnly,
+            )
+            .unwrap();
+        assert_eq!(imported.name(), None);
+        assert_eq!(imported.derivation(), None);
+
+        // Names can be set and cleared.
+        st.wallet_mut()
+            .set_account_name(derived.id(), Some("Savings"))
+            .unwrap();
+        assert_eq!(
+            st.wallet()
+                .get_account(derived.id())
+                .unwrap()
+                .unwrap()
+                .name(),
+            Some("Savings")
+        );
+        st.wallet_mut()
+            .set_account_name(derived.id(), None)
+            .unwrap();
+        assert_eq!(
+            st.wallet()
+                .get_account(derived.id())
+                .unwrap()
+                .unwrap()
+                .name(),
+            None
+        );
+
+        // Metadata entries can be inserted, replaced and removed.
+        st.wallet_mut()
+            .set_account_metadata(derived.id(), "color", Some("red"))
+            .unwrap();
+        st.wallet_mut()
+            .set_account_metadata(derived.id(), "color", Some("blue"))
+            .unwrap();
+        st.wallet_mut()
+            .set_account_metadata(derived.id(), "icon", Some("piggy-bank"))
+            .unwrap();
+        st.wallet_mut()
+            .set_account_metadata(derived.id(), "icon", None)
+            .unwrap();
+        let account = st.wallet().get_account(derived.id()).unwrap().unwrap();
+        assert_eq!(account.metadata().len(), 1);
+        assert_eq!(account.metadata()["color"], "blue");
+        assert!(st
+            .wallet()
+            .get_account(imported.id())
+            .unwrap()
+            .unwrap()
+            .metadata()
+            .is_empty());
+
+        // A derivation can be recorded for an imported account, but the derivation of a
+        // derived account is fixed.
+        st.wallet_mut()
+            .set_account_key_source(imported.id(), Some("ledger:0a1b2c3d"), Some(derivation))
+            .unwrap();
+        let account = st.wallet().get_account(imported.id()).unwrap().unwrap();
+        assert_eq!(account.key_source(), Some("ledger:0a1b2c3d"));
+        assert_eq!(account.derivation(), Some(derivation));
+
+        st.wallet_mut()
+            .set_account_key_source(derived.id(), Some("keystone"), Some(derivation))
+            .unwrap();
+        assert_eq!(
+            st.wallet()
+                .get_account(derived.id())
+                .unwrap()
+                .unwrap()
+                .key_source(),
+            Some("keystone")
+        );
+        let other_derivation = Zip32Derivation::new(
+            *derivation.seed_fingerprint(),
+            derivation.account_index().next().unwrap(),
+        );
+        assert_matches!(
+            st.wallet_mut()
+                .set_account_key_source(derived.id(), None, Some(other_derivation)),
+            Err(SqliteClientError::Zip32DerivationMismatch)
+        );
+
+        // Unknown accounts are rejected.
+        let unknown = AccountId(99);
+        assert_matches!(
+            st.wallet_mut().set_account_name(unknown, Some("Nope")),
+            Err(SqliteClientError::AccountUnknown)
+        );
+        assert_matches!(
+            st.wallet_mut().set_account_metadata(unknown, "color", None),
+            Err(SqliteClientError::AccountUnknown)
+        );
+        assert_matches!(
+            st.wallet_mut().set_account_key_source(unknown, None, None),
+            Err(SqliteClientError::AccountUnknown)
+        );
+
+        // Deleting an account removes its metadata.
+        st.wallet_mut().delete_account(derived.id()).unwrap();
+        let metadata_rows: i64 = st
+            .wallet()
+            .conn()
+            .query_row(
+                "SELECT COUNT(*) FROM account_metadata WHERE account_id = :account_id",
+                named_params![":account_id": derived.id().0],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(metadata_rows, 0);
+        assert!(st.wallet().get_account(derived.id()).unwrap().is_none());
+        assert!(st.wallet().get_account(imported.id()).unwrap().is_some());
+    }
+
     #[test]
     pub(crate) fn create_account_then_conflicts() {
         let mut st = TestBuilder::new()
@@ -23,6 +23,8 @@ use {
 
 pub(crate) mod db;
 pub(crate) mod pool;
+#[cfg(feature = "sync")]
+pub(crate) mod sync;
 
 pub(crate) struct BlockCache {
     _cache_file: NamedTempFile,
@@ -55,7 +57,7 @@ impl TestCache for BlockCache {
         let cb_bytes = cb.encode_to_vec();
         let res = NoteCommitments::from_compact_block(cb);
         self.db_cache
-            .0
+            .conn()
             .execute(
                 "INSERT INTO compactblocks (height, data) VALUES (?, ?)",
                 params![u32::from(cb.height()), cb_bytes,],
@@ -66,7 +68,7 @@ impl TestCache for BlockCache {
 
     fn truncate_to_height(&mut self, height: zcash_protocol::consensus::BlockHeight) {
         self.db_cache
-            .0
+            .conn()
             .execute(
                 "DELETE FROM compactblocks WHERE height > ?",
                 params![u32::from(height)],
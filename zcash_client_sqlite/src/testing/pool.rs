@@ -36,6 +36,30 @@ pub(crate) fn send_single_step_proposed_transfer<T: ShieldedPoolTester>() {
     )
 }
 
+#[cfg(feature = "pczt")]
+pub(crate) fn pczt_single_step<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::pczt_single_step::<T>(
+        TestDbFactory::default(),
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "pczt")]
+pub(crate) fn pczt_single_step_sapling_only_ufvk() {
+    zcash_client_backend::data_api::testing::pool::pczt_single_step_sapling_only_ufvk(
+        TestDbFactory::default(),
+        BlockCache::new(),
+    )
+}
+
+#[cfg(feature = "pczt")]
+pub(crate) fn send_single_step_with_signer<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::send_single_step_with_signer::<T>(
+        TestDbFactory::default(),
+        BlockCache::new(),
+    )
+}
+
 pub(crate) fn send_with_multiple_change_outputs<T: ShieldedPoolTester>() {
     zcash_client_backend::data_api::testing::pool::send_with_multiple_change_outputs::<T>(
         TestDbFactory::default(),
@@ -240,6 +264,20 @@ pub(crate) fn scan_cached_blocks_finds_received_notes<T: ShieldedPoolTester>() {
     )
 }
 
+pub(crate) fn account_deletion<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::account_deletion::<T, _>(
+        TestDbFactory::default(),
+        BlockCache::new(),
+    )
+}
+
+pub(crate) fn transaction_history<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::transaction_history::<T, _>(
+        TestDbFactory::default(),
+        BlockCache::new(),
+    )
+}
+
 // TODO: This test can probably be entirely removed, as the following test duplicates it entirely.
 pub(crate) fn scan_cached_blocks_finds_change_notes<T: ShieldedPoolTester>() {
     zcash_client_backend::data_api::testing::pool::scan_cached_blocks_finds_change_notes::<T, _>(
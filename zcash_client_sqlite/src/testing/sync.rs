@@ -0,0 +1,83 @@
+//! Tests of the wallet synchronization flow in `zcash_client_backend::sync`.
+
+use crate::{
+    chain::init::init_cache_database,
+    testing::{db::TestDbFactory, BlockCache},
+    BlockDb,
+};
+use tempfile::NamedTempFile;
+use zcash_client_backend::data_api::testing::pool::ShieldedPoolTester;
+
+/// Runs `f` with a fresh block cache for use by the synchronization flow.
+fn with_sync_cache(f: impl FnOnce(BlockDb)) {
+    let cache_file = NamedTempFile::new().unwrap();
+    let db_cache = BlockDb::for_path(cache_file.path()).unwrap();
+    init_cache_database(&db_cache).unwrap();
+    f(db_cache)
+}
+
+pub(crate) fn run_detects_spends_across_concurrent_batches<T: ShieldedPoolTester>() {
+    with_sync_cache(|sync_cache| {
+        zcash_client_backend::data_api::testing::sync::run_detects_spends_across_concurrent_batches::<
+            T,
+            _,
+        >(TestDbFactory::default(), BlockCache::new(), sync_cache)
+    })
+}
+
+pub(crate) fn run_aborts_downloads_on_error<T: ShieldedPoolTester>() {
+    with_sync_cache(|sync_cache| {
+        zcash_client_backend::data_api::testing::sync::run_aborts_downloads_on_error::<T, _>(
+            TestDbFactory::default(),
+            BlockCache::new(),
+            sync_cache,
+        )
+    })
+}
+
+pub(crate) fn run_syncs_to_chain_tip<T: ShieldedPoolTester>() {
+    with_sync_cache(|sync_cache| {
+        zcash_client_backend::data_api::testing::sync::run_syncs_to_chain_tip::<T, _>(
+            TestDbFactory::default(),
+            BlockCache::new(),
+            sync_cache,
+        )
+    })
+}
+
+pub(crate) fn run_with_options_reports_progress<T: ShieldedPoolTester>() {
+    with_sync_cache(|sync_cache| {
+        zcash_client_backend::data_api::testing::sync::run_with_options_reports_progress::<T, _>(
+            TestDbFactory::default(),
+            BlockCache::new(),
+            sync_cache,
+        )
+    })
+}
+
+pub(crate) fn run_with_options_cancelled_clears_block_cache<T: ShieldedPoolTester>() {
+    with_sync_cache(|sync_cache| {
+        zcash_client_backend::data_api::testing::sync::run_with_options_cancelled_clears_block_cache::<
+            T,
+            _,
+        >(TestDbFactory::default(), BlockCache::new(), sync_cache)
+    })
+}
+
+#[cfg(feature = "transparent-inputs")]
+pub(crate) fn watch_mempool_stores_relevant_transactions() {
+    with_sync_cache(|sync_cache| {
+        zcash_client_backend::data_api::testing::sync::watch_mempool_stores_relevant_transactions(
+            TestDbFactory::default(),
+            BlockCache::new(),
+            sync_cache,
+        )
+    })
+}
+
+pub(crate) fn process_transaction_data_requests_reports_failures<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::sync::process_transaction_data_requests_reports_failures::<T, _>(
+        TestDbFactory::default(),
+        BlockCache::new(),
+    )
+}
@@ -64,17 +64,19 @@
 //!   wallet.
 //! - `memo` the shielded memo associated with the output, if any.
 
-use incrementalmerkletree::{Marking, Retention};
+use incrementalmerkletree::{Marking, Position, Retention};
 
 use rusqlite::{self, named_params, params, OptionalExtension};
 use secrecy::{ExposeSecret, SecretVec};
 use shardtree::{error::ShardTreeError, store::ShardStore, ShardTree};
 use zcash_client_backend::data_api::{
-    AccountPurpose, DecryptedTransaction, Progress, TransactionDataRequest, TransactionStatus,
+    AccountPurpose, DecryptedTransaction, Progress, TransactionDataRequest, TransactionDirection,
+    TransactionHistoryEntry, TransactionHistoryFilter, TransactionHistoryOutput, TransactionStatus,
+    Zip32Derivation,
 };
 use zip32::fingerprint::SeedFingerprint;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::io::{self, Cursor};
 use std::num::NonZeroU32;
@@ -111,28 +113,38 @@ use zcash_primitives::{
         Transaction, TransactionData, TxId,
     },
 };
+use zcash_protocol::value::{ZatBalance, Zatoshis};
 use zip32::{self, DiversifierIndex, Scope};
 
 use crate::{
     error::SqliteClientError,
     wallet::commitment_tree::{get_max_checkpointed_height, SqliteShardStore},
     AccountId, SqlTransaction, TransferType, WalletCommitmentTrees, WalletDb, DEFAULT_UA_REQUEST,
-    PRUNING_DEPTH, SAPLING_TABLES_PREFIX,
+    ORCHARD_TABLES_PREFIX, PRUNING_DEPTH, SAPLING_TABLES_PREFIX,
 };
 use crate::{TxRef, VERIFY_LOOKAHEAD};
 
 #[cfg(feature = "transparent-inputs")]
 use zcash_primitives::transaction::components::TxOut;
 
+#[cfg(feature = "fiat")]
+use {
+    rusqlite::types::Value,
+    rust_decimal::Decimal,
+    std::rc::Rc,
+    zcash_client_backend::{data_api::FiatRate, fiat::Currency},
+};
+
 use self::scanning::{parse_priority_code, priority_code, replace_queue_entries};
 
 #[cfg(feature = "orchard")]
-use {crate::ORCHARD_TABLES_PREFIX, zcash_client_backend::data_api::ORCHARD_SHARD_HEIGHT};
+use zcash_client_backend::data_api::ORCHARD_SHARD_HEIGHT;
 
 pub mod commitment_tree;
 pub(crate) mod common;
 mod db;
 pub mod init;
+pub mod memos;
 #[cfg(feature = "orchard")]
 pub(crate) mod orchard;
 pub(crate) mod sapling;
@@ -202,6 +214,70 @@ pub struct Account {
     account_id: AccountId,
     kind: AccountSource,
     viewing_key: ViewingKey,
+    details: AccountDetails,
+}
+
+/// The user-facing details of an account: its name, the provenance of its keys, and the
+/// key/value metadata attached to it.
+#[derive(Debug, Clone, Default)]
+struct AccountDetails {
+    name: Option<String>,
+    key_source: Option<String>,
+    /// The ZIP 32 derivation recorded for an imported account, if any.
+    imported_derivation: Option<Zip32Derivation>,
+    metadata: BTreeMap<String, String>,
+}
+
+impl AccountDetails {
+    /// Returns the columns of the `accounts` table that are read by [`Self::from_row`], for
+    /// use in a `SELECT` clause.
+    ///
+    /// These columns are added by the `account_metadata` migration. If the database has not
+    /// yet been migrated to include them, `NULL` is selected in their place.
+    fn columns(conn: &rusqlite::Connection) -> Result<&'static str, SqliteClientError> {
+        Ok(if table_exists(conn, "account_metadata")? {
+            "name, key_source, key_derivation_seed_fingerprint, key_derivation_account_index"
+        } else {
+            "NULL AS name, NULL AS key_source, \
+             NULL AS key_derivation_seed_fingerprint, NULL AS key_derivation_account_index"
+        })
+    }
+
+    /// Reads the details of the given account from a row of the `accounts` table.
+    ///
+    /// The row must include the columns returned by [`Self::columns`].
+    fn from_row(
+        conn: &rusqlite::Connection,
+        account_id: AccountId,
+        row: &rusqlite::Row,
+    ) -> Result<Self, SqliteClientError> {
+        let imported_derivation = match (
+            row.get::<_, Option<[u8; 32]>>("key_derivation_seed_fingerprint")?,
+            row.get::<_, Option<u32>>("key_derivation_account_index")?,
+        ) {
+            (Some(seed_fp), Some(account_index)) => Some(Zip32Derivation::new(
+                SeedFingerprint::from_bytes(seed_fp),
+                zip32::AccountId::try_from(account_index).map_err(|_| {
+                    SqliteClientError::CorruptedData(
+                        "ZIP-32 account ID from wallet DB is out of range.".to_string(),
+                    )
+                })?,
+            )),
+            (None, None) => None,
+            _ => {
+                return Err(SqliteClientError::CorruptedData(
+                    "Incomplete key derivation recorded for imported account".to_string(),
+                ))
+            }
+        };
+
+        Ok(AccountDetails {
+            name: row.get("name")?,
+            key_source: row.get("key_source")?,
+            imported_derivation,
+            metadata: get_account_metadata(conn, account_id)?,
+        })
+    }
 }
 
 impl Account {
@@ -236,6 +312,28 @@ impl zcash_client_backend::data_api::Account for Account {
     fn uivk(&self) -> UnifiedIncomingViewingKey {
         self.viewing_key.uivk()
     }
+
+    fn name(&self) -> Option<&str> {
+        self.details.name.as_deref()
+    }
+
+    fn key_source(&self) -> Option<&str> {
+        self.details.key_source.as_deref()
+    }
+
+    fn derivation(&self) -> Option<Zip32Derivation> {
+        match self.kind {
+            AccountSource::Derived {
+                seed_fingerprint,
+                account_index,
+            } => Some(Zip32Derivation::new(seed_fingerprint, account_index)),
+            AccountSource::Imported { .. } => self.details.imported_derivation,
+        }
+    }
+
+    fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.details.metadata
+    }
 }
 
 impl ViewingKey {
@@ -308,6 +406,34 @@ pub(crate) fn pool_code(pool_type: PoolType) -> i64 {
     }
 }
 
+pub(crate) fn parse_pool_code(code: i64) -> Result<PoolType, SqliteClientError> {
+    match code {
+        0 => Ok(PoolType::Transparent),
+        2 => Ok(PoolType::Shielded(ShieldedProtocol::Sapling)),
+        3 => Ok(PoolType::Shielded(ShieldedProtocol::Orchard)),
+        _ => Err(SqliteClientError::CorruptedData(format!(
+            "Invalid pool code: {}",
+            code
+        ))),
+    }
+}
+
+/// Returns whether a table with the given name exists in the wallet database.
+///
+/// Wallet operations are also performed against databases that have only been migrated to
+/// an earlier schema version (for example, by migrations that reuse wallet functions, and
+/// by tests of those migrations). Functions that touch tables added by later migrations use
+/// this to skip the work that cannot yet be done.
+pub(crate) fn table_exists(
+    conn: &rusqlite::Connection,
+    name: &str,
+) -> Result<bool, rusqlite::Error> {
+    conn.prepare_cached(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = :name)",
+    )?
+    .query_row(named_params![":name": name], |row| row.get(0))
+}
+
 pub(crate) fn scope_code(scope: Scope) -> i64 {
     match scope {
         Scope::External => 0i64,
@@ -463,6 +589,7 @@ pub(crate) fn add_account<P: consensus::Parameters>(
         account_id,
         kind,
         viewing_key,
+        details: AccountDetails::default(),
     };
 
     // If a birthday frontier is available, insert it into the note commitment tree. If the
@@ -704,13 +831,15 @@ pub(crate) fn get_account_for_ufvk<P: consensus::Parameters>(
     #[cfg(not(feature = "transparent-inputs"))]
     let transparent_item: Option<Vec<u8>> = None;
 
-    let mut stmt = conn.prepare(
-        "SELECT id, account_kind, hd_seed_fingerprint, hd_account_index, ufvk, has_spend_key
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, account_kind, hd_seed_fingerprint, hd_account_index, ufvk, has_spend_key,
+            {}
         FROM accounts
         WHERE orchard_fvk_item_cache = :orchard_fvk_item_cache
            OR sapling_fvk_item_cache = :sapling_fvk_item_cache
            OR p2pkh_fvk_item_cache = :p2pkh_fvk_item_cache",
-    )?;
+        AccountDetails::columns(conn)?
+    ))?;
 
     let accounts = stmt
         .query_and_then::<_, SqliteClientError, _, _>(
@@ -744,6 +873,7 @@ pub(crate) fn get_account_for_ufvk<P: consensus::Parameters>(
                     account_id,
                     kind,
                     viewing_key,
+                    details: AccountDetails::from_row(conn, account_id, row)?,
                 })
             },
         )?
@@ -766,12 +896,14 @@ pub(crate) fn get_derived_account<P: consensus::Parameters>(
     seed: &SeedFingerprint,
     account_index: zip32::AccountId,
 ) -> Result<Option<Account>, SqliteClientError> {
-    let mut stmt = conn.prepare(
-        "SELECT id, ufvk
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, ufvk,
+            {}
         FROM accounts
         WHERE hd_seed_fingerprint = :hd_seed_fingerprint
           AND hd_account_index = :account_id",
-    )?;
+        AccountDetails::columns(conn)?
+    ))?;
 
     let mut accounts = stmt.query_and_then::<_, SqliteClientError, _, _>(
         named_params![
@@ -799,6 +931,7 @@ pub(crate) fn get_derived_account<P: consensus::Parameters>(
                     account_index,
                 },
                 viewing_key: ViewingKey::Full(Box::new(ufvk)),
+                details: AccountDetails::from_row(conn, account_id, row)?,
             })
         },
     )?;
@@ -1834,13 +1967,15 @@ pub(crate) fn get_account<P: Parameters>(
     params: &P,
     account_id: AccountId,
 ) -> Result<Option<Account>, SqliteClientError> {
-    let mut sql = conn.prepare_cached(
+    let mut sql = conn.prepare_cached(&format!(
         r#"
-        SELECT account_kind, hd_seed_fingerprint, hd_account_index, ufvk, uivk, has_spend_key
+        SELECT account_kind, hd_seed_fingerprint, hd_account_index, ufvk, uivk, has_spend_key,
+            {}
         FROM accounts
         WHERE id = :account_id
         "#,
-    )?;
+        AccountDetails::columns(conn)?
+    ))?;
 
     let mut result = sql.query(named_params![":account_id": account_id.0])?;
     let row = result.next()?;
@@ -1871,6 +2006,7 @@ pub(crate) fn get_account<P: Parameters>(
                 account_id,
                 kind,
                 viewing_key,
+                details: AccountDetails::from_row(conn, account_id, row)?,
             }))
         }
         None => Ok(None),
@@ -2341,6 +2477,233 @@ pub(crate) fn set_transaction_status(
     Ok(())
 }
 
+/// Records the exchange rate between ZEC and a fiat currency for the given transaction,
+/// replacing any rate previously recorded for the transaction in that currency.
+#[cfg(feature = "fiat")]
+pub(crate) fn set_transaction_fiat_rate(
+    conn: &rusqlite::Transaction,
+    txid: TxId,
+    rate: &FiatRate,
+) -> Result<(), SqliteClientError> {
+    let inserted = conn.execute(
+        "INSERT INTO transaction_fiat_rates (transaction_id, currency, rate)
+         SELECT id_tx, :currency, :rate
+         FROM transactions
+         WHERE txid = :txid
+         ON CONFLICT (transaction_id, currency) DO UPDATE
+         SET rate = :rate",
+        named_params![
+            ":txid": txid.as_ref(),
+            ":currency": rate.currency().code(),
+            ":rate": rate.rate().to_string(),
+        ],
+    )?;
+
+    if inserted == 0 {
+        Err(SqliteClientError::TransactionUnknown(txid))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns a page of the wallet's transaction history, restricted to the transactions
+/// selected by the given filter.
+pub(crate) fn get_transaction_history(
+    conn: &rusqlite::Connection,
+    filter: &TransactionHistoryFilter<AccountId>,
+    offset: usize,
+    limit: usize,
+) -> Result<Vec<TransactionHistoryEntry<AccountId>>, SqliteClientError> {
+    // The page of transactions is selected first, and then joined with the outputs of each
+    // transaction that involve its account, so that the whole page is read with a single
+    // query. The rows for each entry are adjacent, in output order.
+    let mut stmt = conn.prepare_cached(
+        "WITH page AS (
+            SELECT t.account_id, t.txid, t.mined_height, t.tx_index, t.block_time,
+                   t.expiry_height, t.account_balance_delta, t.fee_paid, t.spent_note_count,
+                   t.has_change, t.sent_note_count, t.received_note_count, t.memo_count,
+                   t.expired_unmined, t.is_shielding
+            FROM v_transactions t
+            WHERE (:account_id IS NULL OR t.account_id = :account_id)
+            AND (
+                :min_height IS NULL
+                OR (t.mined_height >= :min_height AND t.mined_height < :max_height)
+            )
+            AND (:is_sent IS NULL OR (t.spent_note_count > 0) = :is_sent)
+            AND (:has_memo IS NULL OR (t.memo_count > 0) = :has_memo)
+            AND (
+                :pool IS NULL
+                -- outputs received or sent by the account in the pool
+                OR EXISTS (
+                    SELECT 1 FROM v_tx_outputs o
+                    WHERE o.txid = t.txid
+                    AND o.output_pool = :pool
+                    AND (o.from_account_id = t.account_id OR o.to_account_id = t.account_id)
+                )
+                -- outputs in the pool spent by the account
+                OR EXISTS (
+                    SELECT 1 FROM v_received_outputs ro
+                    JOIN v_received_output_spends ros
+                         ON ros.pool = ro.pool
+                         AND ros.received_output_id = ro.id_within_pool_table
+                    JOIN transactions ON transactions.id_tx = ros.transaction_id
+                    WHERE transactions.txid = t.txid
+                    AND ro.pool = :pool
+                    AND ro.account_id = t.account_id
+                )
+            )
+            ORDER BY t.mined_height IS NOT NULL, t.mined_height DESC, t.tx_index DESC,
+                     t.account_id, t.txid
+            LIMIT :limit OFFSET :offset
+         )
+         SELECT page.*,
+                o.output_pool, o.output_index, o.from_account_id, o.to_account_id,
+                o.to_address, o.value, o.is_change, o.memo
+         FROM page
+         LEFT JOIN v_tx_outputs o
+              ON o.txid = page.txid
+              AND (o.from_account_id = page.account_id OR o.to_account_id = page.account_id)
+         ORDER BY page.mined_height IS NOT NULL, page.mined_height DESC, page.tx_index DESC,
+                  page.account_id, page.txid, o.output_pool, o.output_index",
+    )?;
+
+    let mut rows = stmt.query(named_params![
+        ":account_id": filter.account_id().map(|a| a.0),
+        ":min_height": filter.mined_heights().map(|r| u32::from(r.start)),
+        ":max_height": filter.mined_heights().map(|r| u32::from(r.end)),
+        ":is_sent": filter.direction().map(|d| d == TransactionDirection::Sent),
+        ":has_memo": filter.has_memo(),
+        ":pool": filter.pool().map(pool_code),
+        ":limit": i64::try_from(limit).unwrap_or(i64::MAX),
+        ":offset": i64::try_from(offset).unwrap_or(i64::MAX),
+    ])?;
+
+    // Each entry is constructed once all of its outputs have been read.
+    let mut pending: Vec<(AccountId, TxId, _, Vec<_>)> = vec![];
+    while let Some(row) = rows.next()? {
+        let account_id = AccountId(row.get("account_id")?);
+        let txid = TxId::from_bytes(row.get("txid")?);
+        if !matches!(pending.last(), Some((a, t, _, _)) if (*a, *t) == (account_id, txid)) {
+            let mined_height = row
+                .get::<_, Option<u32>>("mined_height")?
+                .map(BlockHeight::from);
+            let block_time = row.get("block_time")?;
+            let expiry_height = row
+                .get::<_, Option<u32>>("expiry_height")?
+                .map(BlockHeight::from);
+            let account_value_delta = ZatBalance::from_i64(row.get("account_balance_delta")?)?;
+            let fee_paid = row
+                .get::<_, Option<i64>>("fee_paid")?
+                .map(Zatoshis::from_nonnegative_i64)
+                .transpose()?;
+            let spent_note_count = row.get("spent_note_count")?;
+            let has_change = row.get("has_change")?;
+            let sent_note_count = row.get("sent_note_count")?;
+            let received_note_count = row.get("received_note_count")?;
+            let memo_count = row.get("memo_count")?;
+            let expired_unmined = row.get("expired_unmined")?;
+            let is_shielding = row.get("is_shielding")?;
+            let make_entry = move |outputs| {
+                TransactionHistoryEntry::from_parts(
+                    account_id,
+                    txid,
+                    mined_height,
+                    block_time,
+                    expiry_height,
+                    account_value_delta,
+                    fee_paid,
+                    spent_note_count,
+                    has_change,
+                    sent_note_count,
+                    received_note_count,
+                    memo_count,
+                    expired_unmined,
+                    is_shielding,
+                    outputs,
+                )
+            };
+            pending.push((account_id, txid, make_entry, vec![]));
+        }
+
+        // An entry with no outputs involving its account has a single row, with no output.
+        if let Some(output_pool) = row.get::<_, Option<i64>>("output_pool")? {
+            let (_, _, _, outputs) = pending.last_mut().expect("pushed above");
+            outputs.push(TransactionHistoryOutput::from_parts(
+                parse_pool_code(output_pool)?,
+                row.get("output_index")?,
+                row.get::<_, Option<u32>>("from_account_id")?.map(AccountId),
+                row.get::<_, Option<u32>>("to_account_id")?.map(AccountId),
+                row.get("to_address")?,
+                Zatoshis::from_nonnegative_i64(row.get("value")?)?,
+                row.get("is_change")?,
+                row.get::<_, Option<Vec<u8>>>("memo")?
+                    .map(|b| MemoBytes::from_bytes(&b))
+                    .transpose()?,
+            ));
+        }
+    }
+    let entries = pending
+        .into_iter()
+        .map(|(_, txid, make_entry, outputs)| (txid, make_entry(outputs)));
+
+    // The exchange rates of the whole page are read at once. A transaction may appear once
+    // per account, so its rates are cloned into each of its entries.
+    #[cfg(feature = "fiat")]
+    let entries = {
+        let entries = entries.collect::<Vec<_>>();
+        let fiat_rates = get_transaction_fiat_rates(conn, entries.iter().map(|(t, _)| t))?;
+        entries.into_iter().map(move |(txid, entry)| {
+            let rates = fiat_rates.get(&txid).cloned().unwrap_or_default();
+            (txid, entry.with_fiat_rates(rates))
+        })
+    };
+
+    Ok(entries.map(|(_, entry)| entry).collect())
+}
+
+/// Returns the exchange rates that have been recorded for each of the given transactions,
+/// in currency order. Transactions with no recorded rates are omitted.
+#[cfg(feature = "fiat")]
+fn get_transaction_fiat_rates<'a>(
+    conn: &rusqlite::Connection,
+    txids: impl Iterator<Item = &'a TxId>,
+) -> Result<HashMap<TxId, Vec<FiatRate>>, SqliteClientError> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT t.txid, r.currency, r.rate
+         FROM transaction_fiat_rates r
+         JOIN transactions t ON t.id_tx = r.transaction_id
+         WHERE t.txid IN rarray(:txids)
+         ORDER BY t.txid, r.currency",
+    )?;
+
+    let txids_ptr = Rc::new(
+        txids
+            .map(|txid| Value::Blob(txid.as_ref().to_vec()))
+            .collect::<Vec<_>>(),
+    );
+    let mut rates: HashMap<TxId, Vec<FiatRate>> = HashMap::new();
+    let mut rows = stmt.query(named_params![":txids": txids_ptr])?;
+    while let Some(row) = rows.next()? {
+        let txid = TxId::from_bytes(row.get("txid")?);
+        let code: String = row.get("currency")?;
+        let currency = Currency::from_code(&code).ok_or_else(|| {
+            SqliteClientError::CorruptedData(format!("Unknown fiat currency: {}", code))
+        })?;
+        let rate = row
+            .get::<_, String>("rate")?
+            .parse::<Decimal>()
+            .map_err(|e| {
+                SqliteClientError::CorruptedData(format!("Invalid fiat exchange rate: {}", e))
+            })?;
+        rates
+            .entry(txid)
+            .or_default()
+            .push(FiatRate::new(currency, rate));
+    }
+
+    Ok(rates)
+}
+
 /// Truncates the database to at most the given height.
 ///
 /// If the requested height is greater than or equal to the height of the last scanned
@@ -2520,6 +2883,258 @@ pub(crate) fn get_account_ids(
     Ok(result)
 }
 
+/// Returns an error if the given account does not exist in the wallet.
+fn ensure_account_exists(
+    conn: &rusqlite::Connection,
+    account: AccountId,
+) -> Result<(), SqliteClientError> {
+    conn.query_row(
+        "SELECT id FROM accounts WHERE id = :account_id",
+        named_params![":account_id": account.0],
+        |_| Ok(()),
+    )
+    .optional()?
+    .ok_or(SqliteClientError::AccountUnknown)
+}
+
+/// Sets or clears the name of the given account.
+pub(crate) fn set_account_name(
+    conn: &rusqlite::Transaction,
+    account: AccountId,
+    name: Option<&str>,
+) -> Result<(), SqliteClientError> {
+    let updated = conn.execute(
+        "UPDATE accounts SET name = :name WHERE id = :account_id",
+        named_params![":name": name, ":account_id": account.0],
+    )?;
+
+    if updated == 0 {
+        Err(SqliteClientError::AccountUnknown)
+    } else {
+        Ok(())
+    }
+}
+
+/// Records the provenance of the given account's spending keys.
+///
+/// The derivation of a derived account is given by its `hd_seed_fingerprint` and
+/// `hd_account_index` columns, so a derivation is only stored for imported accounts.
+pub(crate) fn set_account_key_source(
+    conn: &rusqlite::Transaction,
+    account: AccountId,
+    key_source: Option<&str>,
+    derivation: Option<Zip32Derivation>,
+) -> Result<(), SqliteClientError> {
+    let (hd_seed_fingerprint, hd_account_index) = conn
+        .query_row(
+            "SELECT hd_seed_fingerprint, hd_account_index FROM accounts WHERE id = :account_id",
+            named_params![":account_id": account.0],
+            |row| {
+                Ok((
+                    row.get::<_, Option<[u8; 32]>>(0)?,
+                    row.get::<_, Option<u32>>(1)?,
+                ))
+            },
+        )
+        .optional()?
+        .ok_or(SqliteClientError::AccountUnknown)?;
+
+    let imported_derivation = match (hd_seed_fingerprint, hd_account_index) {
+        (Some(seed_fp), Some(account_index)) => {
+            if derivation.map_or(false, |d| {
+                d.seed_fingerprint().to_bytes() != seed_fp
+                    || u32::from(d.account_index()) != account_index
+            }) {
+                return Err(SqliteClientError::Zip32DerivationMismatch);
+            }
+            None
+        }
+        _ => derivation,
+    };
+
+    conn.execute(
+        "UPDATE accounts
+         SET key_source = :key_source,
+             key_derivation_seed_fingerprint = :seed_fingerprint,
+             key_derivation_account_index = :account_index
+         WHERE id = :account_id",
+        named_params![
+            ":key_source": key_source,
+            ":seed_fingerprint": imported_derivation.map(|d| d.seed_fingerprint().to_bytes()),
+            ":account_index": imported_derivation.map(|d| u32::from(d.account_index())),
+            ":account_id": account.0,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Sets the value of the metadata entry with the given key for the given account, or removes
+/// the entry if `value` is `None`.
+pub(crate) fn set_account_metadata(
+    conn: &rusqlite::Transaction,
+    account: AccountId,
+    key: &str,
+    value: Option<&str>,
+) -> Result<(), SqliteClientError> {
+    ensure_account_exists(conn, account)?;
+
+    match value {
+        Some(value) => conn.execute(
+            "INSERT INTO account_metadata (account_id, key, value)
+             VALUES (:account_id, :key, :value)
+             ON CONFLICT (account_id, key) DO UPDATE
+             SET value = :value",
+            named_params![":account_id": account.0, ":key": key, ":value": value],
+        )?,
+        None => conn.execute(
+            "DELETE FROM account_metadata WHERE account_id = :account_id AND key = :key",
+            named_params![":account_id": account.0, ":key": key],
+        )?,
+    };
+
+    Ok(())
+}
+
+/// Returns the key/value metadata attached to the given account.
+///
+/// Returns an empty map if the database has not yet been migrated to support account
+/// metadata.
+pub(crate) fn get_account_metadata(
+    conn: &rusqlite::Connection,
+    account: AccountId,
+) -> Result<BTreeMap<String, String>, SqliteClientError> {
+    if !table_exists(conn, "account_metadata")? {
+        return Ok(BTreeMap::new());
+    }
+
+    let mut stmt = conn
+        .prepare_cached("SELECT key, value FROM account_metadata WHERE account_id = :account_id")?;
+
+    let metadata = stmt
+        .query_map(named_params![":account_id": account.0], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+    Ok(metadata)
+}
+
+/// Returns the note commitment tree positions of the notes received by the given account in
+/// the shielded pool corresponding to `table_prefix`.
+pub(crate) fn get_received_note_positions(
+    conn: &rusqlite::Connection,
+    table_prefix: &'static str,
+    account: AccountId,
+) -> Result<Vec<Position>, SqliteClientError> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT commitment_tree_position
+         FROM {table_prefix}_received_notes
+         WHERE account_id = :account_id
+         AND commitment_tree_position IS NOT NULL"
+    ))?;
+
+    let positions = stmt
+        .query_and_then(named_params![":account_id": account.0], |row| {
+            row.get::<_, u64>(0)
+                .map(Position::from)
+                .map_err(SqliteClientError::from)
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(positions)
+}
+
+/// Deletes the given account, along with every record in the wallet that refers to it.
+///
+/// Transactions are not deleted, as they may be shared with other accounts in the wallet.
+/// Sent outputs received by the account are retained (with their `to_account_id` cleared)
+/// if their recipient address is known. The caller is responsible for removing the marks
+/// from the account's note commitment tree positions; see [`get_received_note_positions`].
+pub(crate) fn delete_account(
+    conn: &rusqlite::Transaction,
+    account: AccountId,
+) -> Result<(), SqliteClientError> {
+    ensure_account_exists(conn, account)?;
+
+    let delete_received_notes = |table_prefix: &'static str| -> Result<(), rusqlite::Error> {
+        conn.execute(
+            &format!(
+                "DELETE FROM {table_prefix}_received_note_spends
+                 WHERE {table_prefix}_received_note_id IN (
+                    SELECT id FROM {table_prefix}_received_notes
+                    WHERE account_id = :account_id
+                 )"
+            ),
+            named_params![":account_id": account.0],
+        )?;
+        conn.execute(
+            &format!("DELETE FROM {table_prefix}_received_notes WHERE account_id = :account_id"),
+            named_params![":account_id": account.0],
+        )?;
+        Ok(())
+    };
+    delete_received_notes(SAPLING_TABLES_PREFIX)?;
+    delete_received_notes(ORCHARD_TABLES_PREFIX)?;
+
+    conn.execute(
+        "DELETE FROM transparent_spend_search_queue
+         WHERE EXISTS (
+            SELECT 1 FROM transparent_received_outputs tro
+            WHERE tro.account_id = :account_id
+            AND tro.transaction_id = transparent_spend_search_queue.transaction_id
+            AND tro.output_index = transparent_spend_search_queue.output_index
+         )",
+        named_params![":account_id": account.0],
+    )?;
+    conn.execute(
+        "DELETE FROM transparent_received_output_spends
+         WHERE transparent_received_output_id IN (
+            SELECT id FROM transparent_received_outputs
+            WHERE account_id = :account_id
+         )",
+        named_params![":account_id": account.0],
+    )?;
+    conn.execute(
+        "DELETE FROM transparent_received_outputs WHERE account_id = :account_id",
+        named_params![":account_id": account.0],
+    )?;
+
+    // Outputs sent by this account are removed entirely. Outputs that this account received
+    // from other accounts in the wallet are retained as ordinary outgoing outputs if we know
+    // the address they were sent to; otherwise there is nothing left to describe them.
+    conn.execute(
+        "DELETE FROM sent_notes
+         WHERE from_account_id = :account_id
+         OR (to_account_id = :account_id AND to_address IS NULL)",
+        named_params![":account_id": account.0],
+    )?;
+    conn.execute(
+        "UPDATE sent_notes SET to_account_id = NULL WHERE to_account_id = :account_id",
+        named_params![":account_id": account.0],
+    )?;
+    memos::delete_orphaned_memos(conn)?;
+
+    conn.execute(
+        "DELETE FROM ephemeral_addresses WHERE account_id = :account_id",
+        named_params![":account_id": account.0],
+    )?;
+    conn.execute(
+        "DELETE FROM addresses WHERE account_id = :account_id",
+        named_params![":account_id": account.0],
+    )?;
+    conn.execute(
+        "DELETE FROM account_metadata WHERE account_id = :account_id",
+        named_params![":account_id": account.0],
+    )?;
+    conn.execute(
+        "DELETE FROM accounts WHERE id = :account_id",
+        named_params![":account_id": account.0],
+    )?;
+
+    Ok(())
+}
+
 /// Inserts information about a scanned block into the database.
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn put_block(
@@ -3282,6 +3897,13 @@ pub(crate) fn insert_sent_output<P: consensus::Parameters>(
     ];
 
     stmt_insert_sent_output.execute(sql_args)?;
+    memos::index_memo(
+        conn,
+        tx_ref,
+        pool_type,
+        output.output_index(),
+        output.memo(),
+    )?;
     flag_previously_received_change(conn, tx_ref)?;
 
     Ok(())
@@ -3337,6 +3959,7 @@ pub(crate) fn put_sent_output<P: consensus::Parameters>(
     ];
 
     stmt_upsert_sent_output.execute(sql_args)?;
+    memos::index_memo(conn, tx_ref, pool_type, output_index, memo)?;
     flag_previously_received_change(conn, tx_ref)?;
 
     Ok(())
@@ -3755,4 +4378,76 @@ mod tests {
             Ok(birthday) if birthday == st.sapling_activation_height()
         )
     }
+
+    #[test]
+    #[cfg(feature = "fiat")]
+    fn fiat_tx_history() {
+        use rust_decimal::Decimal;
+        use zcash_client_backend::{
+            data_api::{FiatRate, TransactionHistoryFilter},
+            fiat::Currency,
+        };
+        use zcash_primitives::transaction::TxId;
+        use zcash_protocol::value::ZatBalance;
+
+        use crate::error::SqliteClientError;
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory::default())
+            .with_block_cache(BlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        let account_id = st.test_account().unwrap().id();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 1);
+
+        let filter = TransactionHistoryFilter::new().with_account(account_id);
+
+        // No exchange rate has been recorded yet.
+        let history = st.wallet().get_transaction_history(&filter, 0, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].mined_height(), Some(h));
+        assert_eq!(history[0].account_value_delta(), ZatBalance::from(value));
+        assert!(history[0].fiat_rates().is_empty());
+        assert_eq!(history[0].fiat_value_delta(Currency::Usd), None);
+        let txid = history[0].txid();
+
+        // A recorded rate can be replaced, and rates are stored per currency.
+        for rate in [
+            FiatRate::new(Currency::Usd, Decimal::new(3050, 2)),
+            FiatRate::new(Currency::Usd, Decimal::new(4000, 2)),
+            FiatRate::new(Currency::Eur, Decimal::new(3700, 2)),
+        ] {
+            st.wallet_mut()
+                .set_transaction_fiat_rate(txid, rate)
+                .unwrap();
+        }
+
+        let history = st.wallet().get_transaction_history(&filter, 0, 10).unwrap();
+        assert_eq!(history[0].fiat_rates().len(), 2);
+        assert_eq!(
+            history[0].fiat_rate(Currency::Usd),
+            Some(&FiatRate::new(Currency::Usd, Decimal::new(40, 0)))
+        );
+        assert_eq!(
+            history[0].fiat_value_delta(Currency::Usd),
+            Some(Decimal::new(2, 2))
+        );
+        assert_eq!(
+            history[0].fiat_value_delta(Currency::Eur),
+            Some(Decimal::new(185, 4))
+        );
+
+        // Rates cannot be recorded for transactions that the wallet does not know about.
+        assert_matches!(
+            st.wallet_mut().set_transaction_fiat_rate(
+                TxId::from_bytes([0; 32]),
+                FiatRate::new(Currency::Usd, Decimal::ONE)
+            ),
+            Err(SqliteClientError::TransactionUnknown(_))
+        );
+    }
 }
@@ -21,6 +21,15 @@ use zcash_protocol::consensus::{NetworkUpgrade, Parameters};
 use crate::wallet::scanning::priority_code;
 
 /// Stores information about the accounts that the wallet is tracking.
+///
+/// ### Columns
+/// - `name`: The human-readable name of the account, if one has been set.
+/// - `key_source`: An identifier for the device or key store that holds the account's
+///   spending keys (for example, the key fingerprint of a hardware wallet), if known.
+/// - `key_derivation_seed_fingerprint`, `key_derivation_account_index`: The ZIP 32
+///   derivation of the spending keys of an imported account, if known. These are always
+///   null for derived accounts, whose derivation is given by `hd_seed_fingerprint` and
+///   `hd_account_index`.
 pub(super) const TABLE_ACCOUNTS: &str = r#"
 CREATE TABLE "accounts" (
     id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
@@ -37,6 +46,10 @@ CREATE TABLE "accounts" (
     birthday_orchard_tree_size INTEGER,
     recover_until_height INTEGER,
     has_spend_key INTEGER NOT NULL DEFAULT 1,
+    name TEXT,
+    key_source TEXT,
+    key_derivation_seed_fingerprint BLOB,
+    key_derivation_account_index INTEGER,
     CHECK (
         (
         account_kind = 0
@@ -59,6 +72,16 @@ pub(super) const INDEX_ACCOUNTS_UIVK: &str =
 pub(super) const INDEX_HD_ACCOUNT: &str =
     r#"CREATE UNIQUE INDEX hd_account ON "accounts" (hd_seed_fingerprint, hd_account_index)"#;
 
+/// Stores key/value metadata that has been attached to accounts in the wallet.
+pub(super) const TABLE_ACCOUNT_METADATA: &str = r#"
+CREATE TABLE account_metadata (
+    account_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE,
+    CONSTRAINT account_metadata_key UNIQUE (account_id, key)
+)"#;
+
 /// Stores diversified Unified Addresses that have been generated from accounts in the
 /// wallet.
 ///
@@ -155,6 +178,21 @@ CREATE TABLE blocks (
     sapling_output_count INTEGER,
     orchard_action_count INTEGER)";
 
+/// Stores the exchange rates between ZEC and fiat currencies that have been recorded for
+/// transactions, for reporting the fiat value of the wallet's transaction history.
+///
+/// ### Columns
+/// - `currency`: The ISO 4217 code of the fiat currency.
+/// - `rate`: The price of 1 ZEC in the fiat currency, as a decimal string.
+pub(super) const TABLE_TRANSACTION_FIAT_RATES: &str = r#"
+CREATE TABLE transaction_fiat_rates (
+    transaction_id INTEGER NOT NULL,
+    currency TEXT NOT NULL,
+    rate TEXT NOT NULL,
+    FOREIGN KEY (transaction_id) REFERENCES transactions(id_tx) ON DELETE CASCADE,
+    CONSTRAINT transaction_currency UNIQUE (transaction_id, currency)
+)"#;
+
 /// Stores the wallet's transactions.
 ///
 /// Any transactions that the wallet observes as "belonging to" one of the accounts in
@@ -438,6 +476,26 @@ pub(super) const INDEX_SENT_NOTES_TO_ACCOUNT: &str =
     r#"CREATE INDEX sent_notes_to_account ON "sent_notes" (to_account_id)"#;
 pub(super) const INDEX_SENT_NOTES_TX: &str = r#"CREATE INDEX sent_notes_tx ON "sent_notes" (tx)"#;
 
+/// A full-text index over the text memos of outputs received or sent by the wallet.
+///
+/// This is an FTS5 virtual table; its contents are stored by SQLite in a set of
+/// `memo_fts_*` shadow tables. Each output appears at most once, regardless of whether it
+/// is recorded in `sent_notes`, in one of the received notes tables, or both.
+///
+/// ### Columns:
+/// - `memo_text`: the text of the memo. This is the only indexed column.
+/// - `transaction_id`: a reference to the transaction containing the output.
+/// - `output_pool`: the pool of the output, encoded as for the `output_pool` column of
+///   `sent_notes`.
+/// - `output_index`: the index of the output within the transaction's bundle for its pool.
+pub(super) const TABLE_MEMO_FTS: &str = "
+CREATE VIRTUAL TABLE memo_fts USING fts5(
+    memo_text,
+    transaction_id UNINDEXED,
+    output_pool UNINDEXED,
+    output_index UNINDEXED
+)";
+
 /// Stores the set of transaction ids for which the backend required additional data.
 ///
 /// ### Columns:
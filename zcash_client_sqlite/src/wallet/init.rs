@@ -206,7 +206,9 @@ fn sqlite_client_error_to_wallet_migration_error(e: SqliteClientError) -> Wallet
         | SqliteClientError::AccountIdDiscontinuity
         | SqliteClientError::AccountIdOutOfRange
         | SqliteClientError::AccountCollision(_)
-        | SqliteClientError::CacheMiss(_) => {
+        | SqliteClientError::CacheMiss(_)
+        | SqliteClientError::TransactionUnknown(_)
+        | SqliteClientError::Zip32DerivationMismatch => {
             unreachable!("we only call WalletRead methods; mutations can't occur")
         }
         #[cfg(feature = "transparent-inputs")]
@@ -470,7 +472,13 @@ mod tests {
 
     pub(crate) fn describe_tables(conn: &Connection) -> Result<Vec<String>, rusqlite::Error> {
         let result = conn
-            .prepare("SELECT sql FROM sqlite_schema WHERE type = 'table' ORDER BY tbl_name")?
+            .prepare(
+                // Shadow tables are created and managed by SQLite for virtual tables.
+                "SELECT s.sql FROM sqlite_schema s
+                 JOIN pragma_table_list t ON t.schema = 'main' AND t.name = s.name
+                 WHERE s.type = 'table' AND t.type != 'shadow'
+                 ORDER BY s.tbl_name",
+            )?
             .query_and_then([], |row| row.get::<_, String>(0))?
             .collect::<Result<Vec<_>, _>>()?;
 
@@ -487,10 +495,12 @@ mod tests {
         let re = Regex::new(r"\s+").unwrap();
 
         let expected_tables = vec![
+            db::TABLE_ACCOUNT_METADATA,
             db::TABLE_ACCOUNTS,
             db::TABLE_ADDRESSES,
             db::TABLE_BLOCKS,
             db::TABLE_EPHEMERAL_ADDRESSES,
+            db::TABLE_MEMO_FTS,
             db::TABLE_NULLIFIER_MAP,
             db::TABLE_ORCHARD_RECEIVED_NOTE_SPENDS,
             db::TABLE_ORCHARD_RECEIVED_NOTES,
@@ -508,6 +518,7 @@ mod tests {
             db::TABLE_SCHEMERZ_MIGRATIONS,
             db::TABLE_SENT_NOTES,
             db::TABLE_SQLITE_SEQUENCE,
+            db::TABLE_TRANSACTION_FIAT_RATES,
             db::TABLE_TRANSACTIONS,
             db::TABLE_TRANSPARENT_RECEIVED_OUTPUT_SPENDS,
             db::TABLE_TRANSPARENT_RECEIVED_OUTPUTS,
@@ -1,3 +1,4 @@
+mod account_metadata;
 mod add_account_birthdays;
 mod add_transaction_views;
 mod add_utxo_account;
@@ -8,6 +9,7 @@ mod fix_bad_change_flagging;
 mod fix_broken_commitment_trees;
 mod full_account_ids;
 mod initial_setup;
+mod memo_fts;
 mod nullifier_map;
 mod orchard_received_notes;
 mod orchard_shardtree;
@@ -18,6 +20,7 @@ mod sent_notes_to_internal;
 mod shardtree_support;
 mod spend_key_available;
 mod support_legacy_sqlite;
+mod transaction_fiat_rates;
 mod tx_retrieval_queue;
 mod ufvk_support;
 mod utxos_table;
@@ -85,6 +88,12 @@ pub(super) fn all_migrations<P: consensus::Parameters + 'static>(
     //                                         fix_broken_commitment_trees
     //                                                     |
     //                                          fix_bad_change_flagging
+    //                                                     |
+    //                                           transaction_fiat_rates
+    //                                                     |
+    //                                              account_metadata
+    //                                                     |
+    //                                                  memo_fts
     vec![
         Box::new(initial_setup::Migration {}),
         Box::new(utxos_table::Migration {}),
@@ -147,6 +156,9 @@ pub(super) fn all_migrations<P: consensus::Parameters + 'static>(
             params: params.clone(),
         }),
         Box::new(fix_bad_change_flagging::Migration),
+        Box::new(transaction_fiat_rates::Migration),
+        Box::new(account_metadata::Migration),
+        Box::new(memo_fts::Migration),
     ]
 }
 
@@ -0,0 +1,72 @@
+//! Adds account names, key provenance and key/value account metadata to the wallet.
+use std::collections::HashSet;
+
+use schemerz_rusqlite::RusqliteMigration;
+use uuid::Uuid;
+
+use crate::wallet::init::WalletMigrationError;
+
+use super::transaction_fiat_rates;
+
+pub(super) const MIGRATION_ID: Uuid = Uuid::from_u128(0x5d8dc640_7433_4f16_9319_673d57e31204);
+
+const DEPENDENCIES: &[Uuid] = &[transaction_fiat_rates::MIGRATION_ID];
+
+pub(super) struct Migration;
+
+impl schemerz::Migration<Uuid> for Migration {
+    fn id(&self) -> Uuid {
+        MIGRATION_ID
+    }
+
+    fn dependencies(&self) -> HashSet<Uuid> {
+        DEPENDENCIES.iter().copied().collect()
+    }
+
+    fn description(&self) -> &'static str {
+        "Adds account names, key provenance and key/value account metadata."
+    }
+}
+
+impl RusqliteMigration for Migration {
+    type Error = WalletMigrationError;
+
+    fn up(&self, transaction: &rusqlite::Transaction) -> Result<(), WalletMigrationError> {
+        transaction.execute_batch(
+            "ALTER TABLE accounts ADD COLUMN name TEXT;
+            ALTER TABLE accounts ADD COLUMN key_source TEXT;
+            ALTER TABLE accounts ADD COLUMN key_derivation_seed_fingerprint BLOB;
+            ALTER TABLE accounts ADD COLUMN key_derivation_account_index INTEGER;
+
+            CREATE TABLE account_metadata (
+                account_id INTEGER NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE,
+                CONSTRAINT account_metadata_key UNIQUE (account_id, key)
+            );",
+        )?;
+        Ok(())
+    }
+
+    fn down(&self, transaction: &rusqlite::Transaction) -> Result<(), WalletMigrationError> {
+        transaction.execute_batch(
+            "DROP TABLE account_metadata;
+            ALTER TABLE accounts DROP COLUMN key_derivation_account_index;
+            ALTER TABLE accounts DROP COLUMN key_derivation_seed_fingerprint;
+            ALTER TABLE accounts DROP COLUMN key_source;
+            ALTER TABLE accounts DROP COLUMN name;",
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wallet::init::migrations::tests::test_migrate;
+
+    #[test]
+    fn migrate() {
+        test_migrate(&[super::MIGRATION_ID]);
+    }
+}
@@ -0,0 +1,101 @@
+//! Adds a full-text index over the text memos of outputs received or sent by the wallet,
+//! and populates it from the memos already stored in the wallet.
+use std::collections::{BTreeMap, HashSet};
+
+use rusqlite::named_params;
+use schemerz_rusqlite::RusqliteMigration;
+use uuid::Uuid;
+use zcash_client_backend::PoolType;
+use zcash_protocol::memo::Memo;
+
+use crate::wallet::{init::WalletMigrationError, pool_code};
+
+use super::account_metadata;
+
+pub(super) const MIGRATION_ID: Uuid = Uuid::from_u128(0x0b1f5e1c_4a0e_4c3d_9b8e_3e6f2d7a9c41);
+
+const DEPENDENCIES: &[Uuid] = &[account_metadata::MIGRATION_ID];
+
+pub(super) struct Migration;
+
+impl schemerz::Migration<Uuid> for Migration {
+    fn id(&self) -> Uuid {
+        MIGRATION_ID
+    }
+
+    fn dependencies(&self) -> HashSet<Uuid> {
+        DEPENDENCIES.iter().copied().collect()
+    }
+
+    fn description(&self) -> &'static str {
+        "Adds a full-text index over the text memos of wallet outputs."
+    }
+}
+
+impl RusqliteMigration for Migration {
+    type Error = WalletMigrationError;
+
+    fn up(&self, transaction: &rusqlite::Transaction) -> Result<(), WalletMigrationError> {
+        transaction.execute_batch(
+            "CREATE VIRTUAL TABLE memo_fts USING fts5(
+                memo_text,
+                transaction_id UNINDEXED,
+                output_pool UNINDEXED,
+                output_index UNINDEXED
+            );",
+        )?;
+
+        // Collect the memos of all known outputs. An output that was both sent and
+        // received by the wallet may have its memo recorded in either table.
+        let mut memos: BTreeMap<(i64, i64, i64), Vec<u8>> = BTreeMap::new();
+        let mut stmt_memos = transaction.prepare(&format!(
+            "SELECT tx, output_pool, output_index, memo FROM sent_notes
+             WHERE memo IS NOT NULL
+             UNION ALL
+             SELECT tx, {}, output_index, memo FROM sapling_received_notes
+             WHERE memo IS NOT NULL
+             UNION ALL
+             SELECT tx, {}, action_index, memo FROM orchard_received_notes
+             WHERE memo IS NOT NULL",
+            pool_code(PoolType::SAPLING),
+            pool_code(PoolType::ORCHARD),
+        ))?;
+        let mut rows = stmt_memos.query([])?;
+        while let Some(row) = rows.next()? {
+            memos.insert((row.get(0)?, row.get(1)?, row.get(2)?), row.get(3)?);
+        }
+
+        let mut stmt_insert = transaction.prepare(
+            "INSERT INTO memo_fts (memo_text, transaction_id, output_pool, output_index)
+             VALUES (:memo_text, :transaction_id, :output_pool, :output_index)",
+        )?;
+        for ((tx, output_pool, output_index), memo) in memos {
+            // Memos that cannot be parsed are not indexed.
+            if let Ok(Memo::Text(text)) = Memo::from_bytes(&memo) {
+                stmt_insert.execute(named_params![
+                    ":memo_text": &*text,
+                    ":transaction_id": tx,
+                    ":output_pool": output_pool,
+                    ":output_index": output_index,
+                ])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn down(&self, transaction: &rusqlite::Transaction) -> Result<(), WalletMigrationError> {
+        transaction.execute_batch("DROP TABLE memo_fts;")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wallet::init::migrations::tests::test_migrate;
+
+    #[test]
+    fn migrate() {
+        test_migrate(&[super::MIGRATION_ID]);
+    }
+}
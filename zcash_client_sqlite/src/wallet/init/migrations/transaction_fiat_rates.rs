@@ -0,0 +1,62 @@
+//! Adds a table for recording the exchange rate between ZEC and fiat currencies at the time
+//! of each transaction.
+use std::collections::HashSet;
+
+use schemerz_rusqlite::RusqliteMigration;
+use uuid::Uuid;
+
+use crate::wallet::init::WalletMigrationError;
+
+use super::fix_bad_change_flagging;
+
+pub(super) const MIGRATION_ID: Uuid = Uuid::from_u128(0x4c00d436_30a9_4a1d_805e_82614d7b0e65);
+
+const DEPENDENCIES: &[Uuid] = &[fix_bad_change_flagging::MIGRATION_ID];
+
+pub(super) struct Migration;
+
+impl schemerz::Migration<Uuid> for Migration {
+    fn id(&self) -> Uuid {
+        MIGRATION_ID
+    }
+
+    fn dependencies(&self) -> HashSet<Uuid> {
+        DEPENDENCIES.iter().copied().collect()
+    }
+
+    fn description(&self) -> &'static str {
+        "Adds a table for recording the fiat exchange rates of transactions."
+    }
+}
+
+impl RusqliteMigration for Migration {
+    type Error = WalletMigrationError;
+
+    fn up(&self, transaction: &rusqlite::Transaction) -> Result<(), WalletMigrationError> {
+        transaction.execute_batch(
+            "CREATE TABLE transaction_fiat_rates (
+                transaction_id INTEGER NOT NULL,
+                currency TEXT NOT NULL,
+                rate TEXT NOT NULL,
+                FOREIGN KEY (transaction_id) REFERENCES transactions(id_tx) ON DELETE CASCADE,
+                CONSTRAINT transaction_currency UNIQUE (transaction_id, currency)
+            );",
+        )?;
+        Ok(())
+    }
+
+    fn down(&self, transaction: &rusqlite::Transaction) -> Result<(), WalletMigrationError> {
+        transaction.execute_batch("DROP TABLE transaction_fiat_rates;")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wallet::init::migrations::tests::test_migrate;
+
+    #[test]
+    fn migrate() {
+        test_migrate(&[super::MIGRATION_ID]);
+    }
+}
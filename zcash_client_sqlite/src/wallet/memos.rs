@@ -0,0 +1,320 @@
+//! Functions for maintaining and querying the full-text index of text memos.
+//!
+//! The memos of received and sent outputs are stored in the `sapling_received_notes`,
+//! `orchard_received_notes` and `sent_notes` tables as raw memo bytes. Text memos are also
+//! added to the `memo_fts` FTS5 table, keyed by the output that they are attached to, so
+//! that they can be searched without decoding every memo in the wallet.
+use rusqlite::named_params;
+use zcash_client_backend::{wallet::NoteId, PoolType};
+use zcash_primitives::{
+    consensus::BlockHeight,
+    memo::{Memo, MemoBytes},
+    transaction::TxId,
+};
+
+use super::{parse_pool_code, pool_code, table_exists};
+use crate::{error::SqliteClientError, AccountId, TxRef};
+
+/// An output of a wallet transaction having a text memo that matches a memo search.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoMatch {
+    txid: TxId,
+    mined_height: Option<BlockHeight>,
+    pool: PoolType,
+    output_index: u32,
+    from_account_id: Option<AccountId>,
+    to_account_id: Option<AccountId>,
+    memo: String,
+}
+
+impl MemoMatch {
+    /// Returns the ID of the transaction containing the output.
+    pub fn txid(&self) -> TxId {
+        self.txid
+    }
+
+    /// Returns the height at which the transaction was mined, or `None` if it has not
+    /// been mined.
+    pub fn mined_height(&self) -> Option<BlockHeight> {
+        self.mined_height
+    }
+
+    /// Returns the pool in which the output was created.
+    pub fn pool(&self) -> PoolType {
+        self.pool
+    }
+
+    /// Returns the index of the output within the transaction's bundle for its pool.
+    pub fn output_index(&self) -> u32 {
+        self.output_index
+    }
+
+    /// Returns the identifier of the note to which the memo is attached, which may be
+    /// passed to [`WalletRead::get_memo`].
+    ///
+    /// Returns `None` if the output index is not representable as a [`NoteId`].
+    ///
+    /// [`WalletRead::get_memo`]: zcash_client_backend::data_api::WalletRead::get_memo
+    pub fn note_id(&self) -> Option<NoteId> {
+        match self.pool {
+            PoolType::Shielded(protocol) => u16::try_from(self.output_index)
+                .ok()
+                .map(|output_index| NoteId::new(self.txid, protocol, output_index)),
+            PoolType::Transparent => None,
+        }
+    }
+
+    /// Returns the account that sent the output, if it was sent by the wallet.
+    pub fn from_account_id(&self) -> Option<AccountId> {
+        self.from_account_id
+    }
+
+    /// Returns the account that received the output, if it was received by the wallet.
+    pub fn to_account_id(&self) -> Option<AccountId> {
+        self.to_account_id
+    }
+
+    /// Returns the text of the memo.
+    pub fn memo(&self) -> &str {
+        &self.memo
+    }
+}
+
+/// Updates the full-text index entry for the memo of the given output.
+///
+/// If `memo` is `None`, the memo is unknown and any existing entry is left unchanged.
+/// Otherwise, the entry is replaced by the memo if it is a text memo, or removed if it
+/// is not.
+///
+/// This does nothing if the database has not yet been migrated to include the index; the
+/// `memo_fts` migration indexes all memos that are stored before it is applied.
+pub(crate) fn index_memo(
+    conn: &rusqlite::Transaction,
+    tx_ref: TxRef,
+    pool: PoolType,
+    output_index: usize,
+    memo: Option<&MemoBytes>,
+) -> Result<(), SqliteClientError> {
+    let memo = match memo {
+        Some(memo) if table_exists(conn, "memo_fts")? => memo,
+        _ => return Ok(()),
+    };
+
+    let output_index =
+        i64::try_from(output_index).expect("output indices are representable as i64");
+    conn.prepare_cached(
+        "DELETE FROM memo_fts
+         WHERE transaction_id = :transaction_id
+         AND output_pool = :output_pool
+         AND output_index = :output_index",
+    )?
+    .execute(named_params![
+        ":transaction_id": tx_ref.0,
+        ":output_pool": pool_code(pool),
+        ":output_index": output_index,
+    ])?;
+
+    if let Ok(Memo::Text(text)) = Memo::try_from(memo) {
+        conn.prepare_cached(
+            "INSERT INTO memo_fts (memo_text, transaction_id, output_pool, output_index)
+             VALUES (:memo_text, :transaction_id, :output_pool, :output_index)",
+        )?
+        .execute(named_params![
+            ":memo_text": &*text,
+            ":transaction_id": tx_ref.0,
+            ":output_pool": pool_code(pool),
+            ":output_index": output_index,
+        ])?;
+    }
+
+    Ok(())
+}
+
+/// Removes the full-text index entries for outputs that are no longer recorded in the
+/// wallet.
+pub(crate) fn delete_orphaned_memos(conn: &rusqlite::Transaction) -> Result<(), SqliteClientError> {
+    if !table_exists(conn, "memo_fts")? {
+        return Ok(());
+    }
+
+    conn.execute(
+        "DELETE FROM memo_fts
+         WHERE NOT EXISTS (
+            SELECT 1 FROM sent_notes
+            WHERE sent_notes.tx = memo_fts.transaction_id
+            AND sent_notes.output_pool = memo_fts.output_pool
+            AND sent_notes.output_index = memo_fts.output_index
+         )
+         AND NOT EXISTS (
+            SELECT 1 FROM sapling_received_notes
+            WHERE sapling_received_notes.tx = memo_fts.transaction_id
+            AND memo_fts.output_pool = :sapling_pool
+            AND sapling_received_notes.output_index = memo_fts.output_index
+         )
+         AND NOT EXISTS (
+            SELECT 1 FROM orchard_received_notes
+            WHERE orchard_received_notes.tx = memo_fts.transaction_id
+            AND memo_fts.output_pool = :orchard_pool
+            AND orchard_received_notes.action_index = memo_fts.output_index
+         )",
+        named_params![
+            ":sapling_pool": pool_code(PoolType::SAPLING),
+            ":orchard_pool": pool_code(PoolType::ORCHARD),
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Returns the outputs of wallet transactions having text memos that contain the given
+/// phrase, ordered by relevance.
+///
+/// The query is tokenized in the same way as the indexed memos, and matches memos that
+/// contain all of its tokens consecutively and in order. FTS5 query syntax is not
+/// interpreted, so that user input such as an order reference can be passed verbatim.
+pub(crate) fn search_memos(
+    conn: &rusqlite::Connection,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<MemoMatch>, SqliteClientError> {
+    if query.trim().is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT transactions.txid, transactions.mined_height,
+                memo_fts.output_pool, memo_fts.output_index, memo_fts.memo_text,
+                o.from_account_id, o.to_account_id
+         FROM memo_fts
+         JOIN transactions ON transactions.id_tx = memo_fts.transaction_id
+         JOIN v_tx_outputs o
+              ON o.txid = transactions.txid
+              AND o.output_pool = memo_fts.output_pool
+              AND o.output_index = memo_fts.output_index
+         WHERE memo_fts MATCH :phrase
+         ORDER BY memo_fts.rank
+         LIMIT :limit",
+    )?;
+
+    let phrase = format!("\"{}\"", query.replace('"', "\"\""));
+    let matches = stmt
+        .query_and_then(
+            named_params![
+                ":phrase": phrase,
+                ":limit": i64::try_from(limit).unwrap_or(i64::MAX),
+            ],
+            |row| {
+                Ok(MemoMatch {
+                    txid: TxId::from_bytes(row.get("txid")?),
+                    mined_height: row
+                        .get::<_, Option<u32>>("mined_height")?
+                        .map(BlockHeight::from),
+                    pool: parse_pool_code(row.get("output_pool")?)?,
+                    output_index: row.get("output_index")?,
+                    from_account_id: row.get::<_, Option<u32>>("from_account_id")?.map(AccountId),
+                    to_account_id: row.get::<_, Option<u32>>("to_account_id")?.map(AccountId),
+                    memo: row.get("memo_text")?,
+                })
+            },
+        )?
+        .collect::<Result<Vec<_>, SqliteClientError>>()?;
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32, str::FromStr};
+
+    use zcash_client_backend::{
+        data_api::{
+            testing::{
+                pool::ShieldedPoolTester, sapling::SaplingPoolTester, AddressType, TestBuilder,
+                TestState,
+            },
+            Account as _, WalletWrite,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+        PoolType, ShieldedProtocol,
+    };
+    use zcash_primitives::{
+        block::BlockHash,
+        memo::{Memo, MemoBytes},
+    };
+    use zcash_protocol::{local_consensus::LocalNetwork, value::Zatoshis};
+
+    use crate::testing::{
+        db::{TestDb, TestDbFactory},
+        BlockCache,
+    };
+
+    #[test]
+    fn search_memos() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory::default())
+            .with_block_cache(BlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = SaplingPoolTester::test_account_fvk(&st);
+        let value = Zatoshis::const_from_u64(60000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 1);
+
+        // Send a payment with a memo to an external recipient.
+        let to = SaplingPoolTester::sk_default_address(&SaplingPoolTester::sk(&[0xf5; 32]));
+        let memo = "Payment for order INV-2024-0042";
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &to,
+                Zatoshis::const_from_u64(10000),
+                Some(MemoBytes::from(Memo::from_str(memo).unwrap())),
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txids = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap();
+
+        let search = |st: &TestState<BlockCache, TestDb, LocalNetwork>, query| {
+            st.wallet().db().search_memos(query, 10).unwrap()
+        };
+
+        let matches = search(&st, "inv-2024-0042");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].txid(), txids[0]);
+        assert_eq!(matches[0].pool(), PoolType::SAPLING);
+        assert_eq!(matches[0].from_account_id(), Some(account.id()));
+        assert_eq!(matches[0].to_account_id(), None);
+        assert_eq!(matches[0].memo(), memo);
+        assert_eq!(
+            matches[0].note_id().map(|note_id| *note_id.txid()),
+            Some(txids[0])
+        );
+
+        // The query is matched as a phrase, and FTS5 query syntax is not interpreted.
+        assert!(search(&st, "order 0042").is_empty());
+        assert!(search(&st, "INV-2024-0042 OR payment").is_empty());
+        assert!(search(&st, "").is_empty());
+
+        // Deleting the account removes its memos from the index.
+        st.wallet_mut().delete_account(account.id()).unwrap();
+        assert!(search(&st, "INV-2024-0042").is_empty());
+        let indexed: i64 = st
+            .wallet()
+            .conn()
+            .query_row("SELECT COUNT(*) FROM memo_fts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(indexed, 0);
+    }
+}
@@ -10,7 +10,7 @@ use rusqlite::{named_params, types::Value, Connection, Row, Transaction};
 use zcash_client_backend::{
     data_api::NullifierQuery,
     wallet::{ReceivedNote, WalletOrchardOutput},
-    DecryptedOutput, ShieldedProtocol, TransferType,
+    DecryptedOutput, PoolType, ShieldedProtocol, TransferType,
 };
 use zcash_keys::keys::UnifiedFullViewingKey;
 use zcash_primitives::transaction::TxId;
@@ -23,7 +23,7 @@ use zip32::Scope;
 
 use crate::{error::SqliteClientError, AccountId, ReceivedNoteId, TxRef};
 
-use super::{memo_repr, parse_scope, scope_code};
+use super::{memo_repr, memos, parse_scope, scope_code};
 
 /// This trait provides a generalization over shielded output representations.
 pub(crate) trait ReceivedOrchardOutput {
@@ -280,6 +280,13 @@ pub(crate) fn put_received_note<T: ReceivedOrchardOutput>(
     let received_note_id = stmt_upsert_received_note
         .query_row(sql_args, |row| row.get::<_, i64>(0))
         .map_err(SqliteClientError::from)?;
+    memos::index_memo(
+        conn,
+        tx_ref,
+        PoolType::ORCHARD,
+        output.index(),
+        output.memo(),
+    )?;
 
     if let Some(spent_in) = spent_in {
         conn.execute(
@@ -399,6 +406,18 @@ pub(crate) mod tests {
         testing::pool::send_single_step_proposed_transfer::<OrchardPoolTester>()
     }
 
+    #[test]
+    #[cfg(feature = "pczt")]
+    fn pczt_single_step() {
+        testing::pool::pczt_single_step::<OrchardPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "pczt")]
+    fn send_single_step_with_signer() {
+        testing::pool::send_single_step_with_signer::<OrchardPoolTester>()
+    }
+
     #[test]
     fn send_with_multiple_change_outputs() {
         testing::pool::send_with_multiple_change_outputs::<OrchardPoolTester>()
@@ -491,6 +510,16 @@ pub(crate) mod tests {
         testing::pool::metadata_queries_exclude_unwanted_notes::<OrchardPoolTester>()
     }
 
+    #[test]
+    fn account_deletion() {
+        testing::pool::account_deletion::<OrchardPoolTester>()
+    }
+
+    #[test]
+    fn transaction_history() {
+        testing::pool::transaction_history::<OrchardPoolTester>()
+    }
+
     #[test]
     fn pool_crossing_required() {
         testing::pool::pool_crossing_required::<OrchardPoolTester, SaplingPoolTester>()
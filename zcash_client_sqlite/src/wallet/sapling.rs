@@ -10,7 +10,7 @@ use sapling::{self, Diversifier, Nullifier, Rseed};
 use zcash_client_backend::{
     data_api::NullifierQuery,
     wallet::{ReceivedNote, WalletSaplingOutput},
-    DecryptedOutput, ShieldedProtocol, TransferType,
+    DecryptedOutput, PoolType, ShieldedProtocol, TransferType,
 };
 use zcash_keys::keys::UnifiedFullViewingKey;
 use zcash_primitives::transaction::{components::amount::NonNegativeAmount, TxId};
@@ -22,7 +22,7 @@ use zip32::Scope;
 
 use crate::{error::SqliteClientError, AccountId, ReceivedNoteId, TxRef};
 
-use super::{memo_repr, parse_scope, scope_code};
+use super::{memo_repr, memos, parse_scope, scope_code};
 
 /// This trait provides a generalization over shielded output representations.
 pub(crate) trait ReceivedSaplingOutput {
@@ -382,6 +382,13 @@ pub(crate) fn put_received_note<T: ReceivedSaplingOutput>(
     let received_note_id = stmt_upsert_received_note
         .query_row(sql_args, |row| row.get::<_, i64>(0))
         .map_err(SqliteClientError::from)?;
+    memos::index_memo(
+        conn,
+        tx_ref,
+        PoolType::SAPLING,
+        output.index(),
+        output.memo(),
+    )?;
 
     if let Some(spent_in) = spent_in {
         conn.execute(
@@ -412,6 +419,24 @@ pub(crate) mod tests {
         testing::pool::send_single_step_proposed_transfer::<SaplingPoolTester>()
     }
 
+    #[test]
+    #[cfg(feature = "pczt")]
+    fn pczt_single_step() {
+        testing::pool::pczt_single_step::<SaplingPoolTester>()
+    }
+
+    #[test]
+    #[cfg(feature = "pczt")]
+    fn pczt_single_step_sapling_only_ufvk() {
+        testing::pool::pczt_single_step_sapling_only_ufvk()
+    }
+
+    #[test]
+    #[cfg(feature = "pczt")]
+    fn send_single_step_with_signer() {
+        testing::pool::send_single_step_with_signer::<SaplingPoolTester>()
+    }
+
     #[test]
     fn send_with_multiple_change_outputs() {
         testing::pool::send_with_multiple_change_outputs::<SaplingPoolTester>()
@@ -504,6 +529,16 @@ pub(crate) mod tests {
         testing::pool::metadata_queries_exclude_unwanted_notes::<SaplingPoolTester>()
     }
 
+    #[test]
+    fn account_deletion() {
+        testing::pool::account_deletion::<SaplingPoolTester>()
+    }
+
+    #[test]
+    fn transaction_history() {
+        testing::pool::transaction_history::<SaplingPoolTester>()
+    }
+
     #[test]
     #[cfg(feature = "orchard")]
     fn pool_crossing_required() {
@@ -0,0 +1,630 @@
+//! Verification of chain history using [FlyClient] proofs.
+//!
+//! From Heartwood onwards, each block header commits (directly before NU5, and via
+//! `hashBlockCommitments` from NU5) to the root of a chain history tree containing every
+//! earlier block since the activation of the current network upgrade. A light client that
+//! has obtained and validated a chain tip header can then check that the server it talks
+//! to has the chain of work claimed by that header, by requesting inclusion proofs for a
+//! small number of randomly sampled blocks and checking them against the committed root.
+//!
+//! [FlyClient]: https://zips.z.cash/zip-0221
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use blake2b_simd::Params as Blake2Params;
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use primitive_types::U256;
+
+use crate::{store::InclusionProof, Version};
+
+/// Errors that can occur when verifying a FlyClient proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The tip height is not above the activation height, so the tip header does not
+    /// commit to any chain history.
+    NoHistory,
+    /// No sample was provided for the block at the given height.
+    MissingSample(u64),
+    /// The inclusion proof for the block at the given height is not for the expected leaf
+    /// of a tree of the expected size.
+    WrongLeaf(u64),
+    /// The inclusion proof for the block at the given height does not match the committed
+    /// history root.
+    InvalidProof(u64),
+    /// The leaf for the block at the given height does not commit to the given block
+    /// hash.
+    BlockHashMismatch(u64),
+    /// The sampled header at the given height has an invalid target.
+    InvalidTarget(u64),
+    /// The leaf for the block at the given height does not commit to the sampled header's
+    /// time and target.
+    HeaderMismatch(u64),
+    /// The work committed to for the block at the given height is inconsistent with the
+    /// sampled headers.
+    InvalidWork(u64),
+    /// The committed history tree does not cover the expected range of heights.
+    RootHeightMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoHistory => write!(f, "Tip header does not commit to any chain history"),
+            Error::MissingSample(height) => write!(f, "No sample for block at height {}", height),
+            Error::WrongLeaf(height) => write!(
+                f,
+                "Inclusion proof for block at height {} is for the wrong leaf",
+                height
+            ),
+            Error::InvalidProof(height) => write!(
+                f,
+                "Inclusion proof for block at height {} does not match the history root",
+                height
+            ),
+            Error::BlockHashMismatch(height) => write!(
+                f,
+                "History leaf for block at height {} does not commit to its block hash",
+                height
+            ),
+            Error::InvalidTarget(height) => {
+                write!(f, "Header at height {} has an invalid target", height)
+            }
+            Error::HeaderMismatch(height) => write!(
+                f,
+                "History leaf for block at height {} does not match its header",
+                height
+            ),
+            Error::InvalidWork(height) => write!(
+                f,
+                "History tree claims invalid work for block at height {}",
+                height
+            ),
+            Error::RootHeightMismatch => write!(
+                f,
+                "History root does not cover the expected range of heights"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A sampled block, along with a proof that it is included in the committed history tree.
+#[derive(Debug)]
+pub struct Sample<V: Version> {
+    /// The height of the sampled block.
+    pub height: u64,
+    /// The hash of the sampled block's header.
+    ///
+    /// The caller is responsible for checking that this is the hash of a valid header
+    /// with the given `time` and `bits`, that meets its own target.
+    pub block_hash: [u8; 32],
+    /// The `nTime` field of the sampled block's header.
+    pub time: u32,
+    /// The `nBits` field of the sampled block's header.
+    pub bits: u32,
+    /// A proof that the block is included in the history tree.
+    pub proof: InclusionProof<V>,
+}
+
+impl<V: Version> Sample<V> {
+    /// Reads a sample from its byte representation, as produced by [`Self::write`].
+    ///
+    /// `consensus_branch_id` is the consensus branch ID of the network upgrade for which
+    /// the tree commits to the chain history.
+    pub fn read<R: Read>(consensus_branch_id: u32, r: &mut R) -> io::Result<Self> {
+        let height = r.read_u64::<LittleEndian>()?;
+        let mut block_hash = [0; 32];
+        r.read_exact(&mut block_hash)?;
+        let time = r.read_u32::<LittleEndian>()?;
+        let bits = r.read_u32::<LittleEndian>()?;
+        let proof = InclusionProof::read(consensus_branch_id, r)?;
+        Ok(Sample {
+            height,
+            block_hash,
+            time,
+            bits,
+            proof,
+        })
+    }
+
+    /// Writes the byte representation of this sample.
+    ///
+    /// The sample is encoded as its height, block hash, time and bits, with integers in
+    /// little-endian order, followed by its inclusion proof.
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u64::<LittleEndian>(self.height)?;
+        w.write_all(&self.block_hash)?;
+        w.write_u32::<LittleEndian>(self.time)?;
+        w.write_u32::<LittleEndian>(self.bits)?;
+        self.proof.write(w)
+    }
+}
+
+/// Reads a FlyClient proof, as produced by [`write_samples`].
+pub fn read_samples<V: Version, R: Read>(
+    consensus_branch_id: u32,
+    r: &mut R,
+) -> io::Result<Vec<Sample<V>>> {
+    let count = r.read_u32::<LittleEndian>()?;
+    (0..count)
+        .map(|_| Sample::read(consensus_branch_id, r))
+        .collect()
+}
+
+/// Writes a FlyClient proof, consisting of the samples for the heights requested by a
+/// [`Verifier`], so that it can be sent to the client.
+///
+/// The proof is encoded as the number of samples, as a 32-bit little-endian integer,
+/// followed by each of the samples.
+pub fn write_samples<V: Version, W: Write>(samples: &[Sample<V>], w: &mut W) -> io::Result<()> {
+    let count = u32::try_from(samples.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many samples"))?;
+    w.write_u32::<LittleEndian>(count)?;
+    samples.iter().try_for_each(|sample| sample.write(w))
+}
+
+/// The part of the chain that has been verified by a FlyClient proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedChain {
+    /// The height of the first block committed to by the history tree.
+    pub start_height: u64,
+    /// The height of the last block committed to by the history tree.
+    pub end_height: u64,
+    /// The total work of the blocks committed to by the history tree.
+    pub total_work: U256,
+}
+
+/// A verifier for FlyClient proofs against the history root committed to by a chain tip.
+#[derive(Debug)]
+pub struct Verifier {
+    history_root: [u8; 32],
+    activation_height: u64,
+    tip_height: u64,
+    sampled_heights: Vec<u64>,
+}
+
+impl Verifier {
+    /// Constructs a verifier for the history root committed to by the header at
+    /// `tip_height`.
+    ///
+    /// `activation_height` is the activation height of the network upgrade in effect at
+    /// `tip_height`; the history tree committed to by the tip header contains the blocks
+    /// from `activation_height` to `tip_height - 1` inclusive. The caller is responsible
+    /// for checking that `history_root` is committed to by the tip header.
+    ///
+    /// Up to `sample_count` blocks are sampled, according to the FlyClient distribution
+    /// that favours recent blocks. The most recent block is always sampled. `seed` must be
+    /// chosen by the verifier, and not be predictable by the server providing the proofs.
+    ///
+    /// Returns [`Error::NoHistory`] if `tip_height <= activation_height`.
+    pub fn new(
+        history_root: [u8; 32],
+        activation_height: u64,
+        tip_height: u64,
+        sample_count: u32,
+        seed: &[u8; 32],
+    ) -> Result<Self, Error> {
+        if tip_height <= activation_height {
+            return Err(Error::NoHistory);
+        }
+        let leaf_count = tip_height - activation_height;
+
+        let mut sampled_heights: Vec<u64> = sample_leaves(leaf_count, sample_count, seed)
+            .into_iter()
+            .chain(Some(leaf_count - 1))
+            .map(|leaf| activation_height + leaf)
+            .collect();
+        sampled_heights.sort_unstable();
+        sampled_heights.dedup();
+
+        Ok(Verifier {
+            history_root,
+            activation_height,
+            tip_height,
+            sampled_heights,
+        })
+    }
+
+    /// Returns the heights of the blocks for which samples must be provided, in ascending
+    /// order.
+    pub fn sampled_heights(&self) -> &[u64] {
+        &self.sampled_heights
+    }
+
+    /// Verifies the given samples, which must include a sample for each of the heights
+    /// returned by [`Self::sampled_heights`].
+    ///
+    /// Each sampled leaf must commit to the time and target of its sampled header, and to
+    /// the work implied by that target. The work that each proof claims for the blocks
+    /// before its leaf must also be consistent with the sampled blocks before it, so that
+    /// a server cannot claim more work than its headers carry without being caught by the
+    /// sampling.
+    ///
+    /// Returns the range of blocks and their total work committed to by the history root.
+    /// Clients that are offered several chains should prefer the one with the most work.
+    pub fn verify<V: Version>(&self, samples: &[Sample<V>]) -> Result<VerifiedChain, Error> {
+        let leaf_count = self.tip_height - self.activation_height;
+
+        let mut verified = None;
+        // The height of the last verified sample, and the work up to and including it.
+        let mut previous: Option<(u64, U256)> = None;
+        for &height in &self.sampled_heights {
+            let sample = samples
+                .iter()
+                .find(|sample| sample.height == height)
+                .ok_or(Error::MissingSample(height))?;
+
+            let leaf = sample.proof.leaf();
+            if u64::from(sample.proof.leaf_index()) != height - self.activation_height
+                || u64::from(sample.proof.leaf_count()) != leaf_count
+                || V::start_height(leaf) != height
+                || V::end_height(leaf) != height
+            {
+                return Err(Error::WrongLeaf(height));
+            }
+            if V::subtree_commitment(leaf) != sample.block_hash {
+                return Err(Error::BlockHashMismatch(height));
+            }
+            if V::start_time(leaf) != sample.time
+                || V::end_time(leaf) != sample.time
+                || V::start_target(leaf) != sample.bits
+                || V::end_target(leaf) != sample.bits
+            {
+                return Err(Error::HeaderMismatch(height));
+            }
+            let leaf_work = work_from_bits(sample.bits).ok_or(Error::InvalidTarget(height))?;
+            if V::subtree_total_work(leaf) != leaf_work {
+                return Err(Error::InvalidWork(height));
+            }
+
+            let root = sample
+                .proof
+                .root()
+                .filter(|root| V::hash(root) == self.history_root)
+                .ok_or(Error::InvalidProof(height))?;
+
+            // Every block has non-zero work, so the work before this block must cover the
+            // previous sampled block and at least one unit for each block in between.
+            let work_before = sample
+                .proof
+                .work_before_leaf()
+                .ok_or(Error::InvalidProof(height))?;
+            let (previous_height, previous_work) =
+                previous.unwrap_or((self.activation_height, U256::zero()));
+            let blocks_between = height - previous_height - u64::from(previous.is_some());
+            if previous_work
+                .checked_add(blocks_between.into())
+                .map_or(true, |min_work| work_before < min_work)
+            {
+                return Err(Error::InvalidWork(height));
+            }
+            previous = Some((
+                height,
+                work_before
+                    .checked_add(leaf_work)
+                    .ok_or(Error::InvalidWork(height))?,
+            ));
+
+            verified = Some(VerifiedChain {
+                start_height: V::start_height(&root),
+                end_height: V::end_height(&root),
+                total_work: V::subtree_total_work(&root),
+            });
+        }
+
+        // All of the proofs commit to the same root, so any of them can be used.
+        let verified = verified.expect("the most recent block is always sampled");
+        if verified.start_height != self.activation_height
+            || verified.end_height != self.tip_height - 1
+        {
+            return Err(Error::RootHeightMismatch);
+        }
+        Ok(verified)
+    }
+}
+
+/// Returns the work represented by a block with the given compact target ("nBits").
+///
+/// Returns `None` if the target is negative, zero, or overflows 256 bits.
+fn work_from_bits(bits: u32) -> Option<U256> {
+    let size = bits >> 24;
+    let mantissa = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0
+        || mantissa == 0
+        || size > 34
+        || (mantissa > 0xff && size > 33)
+        || (mantissa > 0xffff && size > 32)
+    {
+        return None;
+    }
+    let target = if size <= 3 {
+        U256::from(mantissa >> (8 * (3 - size)))
+    } else {
+        U256::from(mantissa) << (8 * (size - 3))
+    };
+    if target.is_zero() {
+        return None;
+    }
+
+    // The work is 2^256 / (target + 1), computed without overflowing 256 bits.
+    Some((!target / (target + 1)) + 1)
+}
+
+/// Samples up to `count` leaves of a tree with `leaf_count` leaves.
+///
+/// Leaves are sampled such that the probability of sampling a leaf at relative position
+/// `x` in the tree is proportional to `1 / (1 - x)`, so that an adversary who has forked
+/// off the honest chain at any point is caught with high probability.
+fn sample_leaves(leaf_count: u64, count: u32, seed: &[u8; 32]) -> Vec<u64> {
+    if leaf_count <= 1 {
+        return vec![];
+    }
+
+    // The samples span the whole tree, down to the most recent leaf.
+    let delta = 1.0 / leaf_count as f64;
+    (0..count)
+        .map(|i| {
+            let mut counter = [0; 4];
+            LittleEndian::write_u32(&mut counter, i);
+            let hash = Blake2Params::new()
+                .hash_length(8)
+                .personal(b"ZcashFlyClient__")
+                .to_state()
+                .update(seed)
+                .update(&counter)
+                .finalize();
+            let u = LittleEndian::read_u64(hash.as_bytes()) as f64 / 2f64.powi(64);
+
+            // Invert the cumulative distribution function.
+            let x = 1.0 - delta.powf(u);
+            ((x * leaf_count as f64) as u64).min(leaf_count - 1)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use super::{
+        read_samples, sample_leaves, work_from_bits, write_samples, Error, Sample, Verifier,
+    };
+    use crate::{
+        store::{HistoryTree, MemoryStore},
+        NodeData, V1,
+    };
+
+    const ACTIVATION_HEIGHT: u64 = 1000;
+
+    fn bits(height: u64) -> u32 {
+        0x1f07_ffff - (height % 16) as u32 * 0x1000
+    }
+
+    fn leaf(height: u64) -> NodeData {
+        NodeData {
+            consensus_branch_id: 1,
+            subtree_commitment: block_hash(height),
+            start_time: height as u32,
+            end_time: height as u32,
+            start_target: bits(height),
+            end_target: bits(height),
+            start_sapling_root: [0u8; 32],
+            end_sapling_root: [0u8; 32],
+            subtree_total_work: work_from_bits(bits(height)).unwrap(),
+            start_height: height,
+            end_height: height,
+            sapling_tx: 7,
+        }
+    }
+
+    fn block_hash(height: u64) -> [u8; 32] {
+        let mut hash = [0; 32];
+        hash[..8].copy_from_slice(&height.to_le_bytes());
+        hash
+    }
+
+    fn history(tip_height: u64) -> HistoryTree<V1, MemoryStore> {
+        history_with(tip_height, leaf)
+    }
+
+    fn history_with(
+        tip_height: u64,
+        leaf: impl Fn(u64) -> NodeData,
+    ) -> HistoryTree<V1, MemoryStore> {
+        let mut history = HistoryTree::new(MemoryStore::new(), 1);
+        for height in ACTIVATION_HEIGHT..tip_height {
+            history.append_leaf(leaf(height)).unwrap();
+        }
+        history
+    }
+
+    fn samples(history: &HistoryTree<V1, MemoryStore>, heights: &[u64]) -> Vec<Sample<V1>> {
+        heights
+            .iter()
+            .map(|height| Sample {
+                height: *height,
+                block_hash: block_hash(*height),
+                time: *height as u32,
+                bits: bits(*height),
+                proof: history
+                    .inclusion_proof((height - ACTIVATION_HEIGHT) as u32)
+                    .unwrap(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sampling() {
+        let seed = [7; 32];
+        let leaves = sample_leaves(1000, 100, &seed);
+        assert_eq!(leaves.len(), 100);
+        assert!(leaves.iter().all(|leaf| *leaf < 1000));
+        assert_eq!(leaves, sample_leaves(1000, 100, &seed));
+        assert_ne!(leaves, sample_leaves(1000, 100, &[8; 32]));
+
+        // Recent leaves are sampled more densely.
+        let recent = leaves.iter().filter(|leaf| **leaf >= 500).count();
+        assert!(recent > 75);
+
+        assert!(sample_leaves(1, 100, &seed).is_empty());
+    }
+
+    #[test]
+    fn work() {
+        // Bitcoin's and Zcash's minimum difficulty targets.
+        assert_eq!(
+            work_from_bits(0x1d00_ffff),
+            Some(U256::from(0x1_0001_0001u64))
+        );
+        assert_eq!(work_from_bits(0x2007_ffff), Some(U256::from(32)));
+        assert_eq!(work_from_bits(0x0100_0001), None);
+        assert_eq!(work_from_bits(0x1d80_ffff), None);
+        assert_eq!(work_from_bits(0x2301_0000), None);
+    }
+
+    #[test]
+    fn no_history() {
+        assert_eq!(
+            Verifier::new([0; 32], ACTIVATION_HEIGHT, ACTIVATION_HEIGHT, 10, &[1; 32]).err(),
+            Some(Error::NoHistory)
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let tip_height = ACTIVATION_HEIGHT + 300;
+        let history = history(tip_height);
+        let root = history.root_hash().unwrap().unwrap();
+
+        let verifier = Verifier::new(root, ACTIVATION_HEIGHT, tip_height, 20, &[1; 32]).unwrap();
+        assert_eq!(verifier.sampled_heights().last(), Some(&(tip_height - 1)));
+
+        let mut samples = samples(&history, verifier.sampled_heights());
+        let verified = verifier.verify(&samples).unwrap();
+
+        // A proof produced by the server is verified by the client after a round trip
+        // through its encoding.
+        let mut bytes = vec![];
+        write_samples(&samples, &mut bytes).unwrap();
+        let decoded = read_samples::<V1, _>(1, &mut &bytes[..]).unwrap();
+        assert_eq!(decoded.len(), samples.len());
+        assert_eq!(verifier.verify(&decoded), Ok(verified.clone()));
+        assert!(read_samples::<V1, _>(1, &mut &bytes[..bytes.len() - 1]).is_err());
+        assert_eq!(verified.start_height, ACTIVATION_HEIGHT);
+        assert_eq!(verified.end_height, tip_height - 1);
+        assert_eq!(
+            verified.total_work,
+            (ACTIVATION_HEIGHT..tip_height)
+                .map(|h| work_from_bits(bits(h)).unwrap())
+                .fold(U256::zero(), |total, work| total + work)
+        );
+
+        // Every sampled height must be covered.
+        let missing = samples.remove(0);
+        assert_eq!(
+            verifier.verify(&samples),
+            Err(Error::MissingSample(missing.height))
+        );
+
+        // The block hash must match the leaf.
+        samples.insert(
+            0,
+            Sample {
+                block_hash: [0xff; 32],
+                ..missing
+            },
+        );
+        assert_eq!(
+            verifier.verify(&samples),
+            Err(Error::BlockHashMismatch(samples[0].height))
+        );
+
+        // The header's time and target must match the leaf.
+        samples[0].block_hash = block_hash(samples[0].height);
+        samples[0].time += 1;
+        assert_eq!(
+            verifier.verify(&samples),
+            Err(Error::HeaderMismatch(samples[0].height))
+        );
+        samples[0].time -= 1;
+        samples[0].bits = bits(samples[0].height + 1);
+        assert_eq!(
+            verifier.verify(&samples),
+            Err(Error::HeaderMismatch(samples[0].height))
+        );
+    }
+
+    #[test]
+    fn forged_work() {
+        let tip_height = ACTIVATION_HEIGHT + 200;
+        let verify = |history: HistoryTree<V1, MemoryStore>| {
+            let root = history.root_hash().unwrap().unwrap();
+            let verifier =
+                Verifier::new(root, ACTIVATION_HEIGHT, tip_height, 20, &[3; 32]).unwrap();
+            verifier.verify(&samples(&history, verifier.sampled_heights()))
+        };
+
+        // A chain whose history claims more work than its headers' targets imply.
+        let forged = history_with(tip_height, |height| NodeData {
+            subtree_total_work: work_from_bits(bits(height)).unwrap() * 1000,
+            ..leaf(height)
+        });
+        assert!(matches!(verify(forged), Err(Error::InvalidWork(_))));
+
+        // A chain whose sampled leaves are honest, but whose other leaves claim no work,
+        // so that the work before each sampled block does not cover its ancestors. The
+        // sampled heights only depend on the seed and the tip height.
+        let sampled = Verifier::new([0; 32], ACTIVATION_HEIGHT, tip_height, 20, &[3; 32])
+            .unwrap()
+            .sampled_heights()
+            .to_vec();
+        let inconsistent = history_with(tip_height, |height| NodeData {
+            subtree_total_work: if sampled.contains(&height) {
+                work_from_bits(bits(height)).unwrap()
+            } else {
+                U256::zero()
+            },
+            ..leaf(height)
+        });
+        assert!(matches!(verify(inconsistent), Err(Error::InvalidWork(_))));
+    }
+
+    #[test]
+    fn wrong_chain() {
+        let tip_height = ACTIVATION_HEIGHT + 100;
+        let root = history(tip_height).root_hash().unwrap().unwrap();
+        let verifier = Verifier::new(root, ACTIVATION_HEIGHT, tip_height, 10, &[2; 32]).unwrap();
+
+        // A chain that differs from the committed one in a single block.
+        let mut fork = HistoryTree::<V1, _>::new(MemoryStore::new(), 1);
+        for height in ACTIVATION_HEIGHT..tip_height {
+            let mut leaf = leaf(height);
+            if height == tip_height - 2 {
+                leaf.subtree_total_work += 1.into();
+            }
+            fork.append_leaf(leaf).unwrap();
+        }
+        assert!(matches!(
+            verifier.verify(&samples(&fork, verifier.sampled_heights())),
+            Err(Error::InvalidProof(_))
+        ));
+
+        // A proof from a shorter chain has the wrong shape.
+        let shorter = history(tip_height - 1);
+        let heights: Vec<_> = verifier
+            .sampled_heights()
+            .iter()
+            .map(|height| (*height).min(tip_height - 2))
+            .collect();
+        let mut samples = samples(&shorter, &heights);
+        for (sample, height) in samples.iter_mut().zip(verifier.sampled_heights()) {
+            sample.height = *height;
+        }
+        assert!(matches!(
+            verifier.verify(&samples),
+            Err(Error::WrongLeaf(_))
+        ));
+    }
+}
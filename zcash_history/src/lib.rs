@@ -7,7 +7,9 @@
 #![warn(missing_docs)]
 
 mod entry;
+pub mod flyclient;
 mod node_data;
+pub mod store;
 mod tree;
 mod version;
 
@@ -0,0 +1,727 @@
+//! Persistent storage for chain history trees.
+//!
+//! [`Tree`] only holds the entries of the array representation of the MMR that are needed
+//! for a few operations. [`HistoryTree`] keeps the full array representation in a
+//! [`NodeStore`], loading the entries it needs for each operation and persisting the
+//! entries that each operation adds or removes, so that the tree can be appended to,
+//! truncated on reorg, and reloaded across restarts.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use primitive_types::U256;
+
+use crate::{Entry, Tree, Version, MAX_ENTRY_SIZE};
+
+/// The byte representation of an [`Entry`], padded with zeroes to [`MAX_ENTRY_SIZE`].
+pub type SerializedEntry = [u8; MAX_ENTRY_SIZE];
+
+/// Storage for the array representation of a chain history tree.
+///
+/// Entries are identified by their position in the array representation, and are only
+/// ever added to or removed from the end of it.
+pub trait NodeStore {
+    /// The type of errors produced by this store.
+    type Error;
+
+    /// Returns the number of entries in the store.
+    fn len(&self) -> Result<u32, Self::Error>;
+
+    /// Returns `true` if the store contains no entries.
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Returns the entry at the given position, or `None` if there is no such entry.
+    fn get(&self, index: u32) -> Result<Option<SerializedEntry>, Self::Error>;
+
+    /// Appends the given entries, in order, to the end of the store.
+    fn append(&mut self, entries: &[SerializedEntry]) -> Result<(), Self::Error>;
+
+    /// Removes all entries at positions `len` and above.
+    fn truncate(&mut self, len: u32) -> Result<(), Self::Error>;
+}
+
+/// A [`NodeStore`] that holds its entries in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Vec<SerializedEntry>,
+}
+
+impl MemoryStore {
+    /// Constructs an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl NodeStore for MemoryStore {
+    type Error = Infallible;
+
+    fn len(&self) -> Result<u32, Self::Error> {
+        Ok(self.entries.len() as u32)
+    }
+
+    fn get(&self, index: u32) -> Result<Option<SerializedEntry>, Self::Error> {
+        Ok(self.entries.get(index as usize).copied())
+    }
+
+    fn append(&mut self, entries: &[SerializedEntry]) -> Result<(), Self::Error> {
+        self.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u32) -> Result<(), Self::Error> {
+        self.entries.truncate(len as usize);
+        Ok(())
+    }
+}
+
+/// A [`NodeStore`] backed by a file containing the entries in order, each padded to
+/// [`MAX_ENTRY_SIZE`] bytes.
+#[derive(Debug)]
+pub struct FileStore {
+    file: File,
+}
+
+impl FileStore {
+    /// Opens the store at the given path, creating an empty store if the file does not
+    /// exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() % MAX_ENTRY_SIZE as u64 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "history tree file length is not a multiple of the entry size",
+            ));
+        }
+        Ok(FileStore { file })
+    }
+}
+
+impl NodeStore for FileStore {
+    type Error = io::Error;
+
+    fn len(&self) -> Result<u32, Self::Error> {
+        Ok((self.file.metadata()?.len() / MAX_ENTRY_SIZE as u64) as u32)
+    }
+
+    fn get(&self, index: u32) -> Result<Option<SerializedEntry>, Self::Error> {
+        if index >= self.len()? {
+            return Ok(None);
+        }
+
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(u64::from(index) * MAX_ENTRY_SIZE as u64))?;
+        let mut entry = [0; MAX_ENTRY_SIZE];
+        file.read_exact(&mut entry)?;
+        Ok(Some(entry))
+    }
+
+    fn append(&mut self, entries: &[SerializedEntry]) -> Result<(), Self::Error> {
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&entries.concat())?;
+        self.file.sync_data()
+    }
+
+    fn truncate(&mut self, len: u32) -> Result<(), Self::Error> {
+        self.file.set_len(u64::from(len) * MAX_ENTRY_SIZE as u64)?;
+        self.file.sync_data()
+    }
+}
+
+/// Errors that can occur when operating on a [`HistoryTree`].
+#[derive(Debug)]
+pub enum Error<E> {
+    /// An error occurred in the underlying store.
+    Store(E),
+    /// An entry could not be serialized or parsed.
+    Entry(io::Error),
+    /// The entries in the store do not form a valid tree.
+    Tree(crate::Error),
+    /// The store contains a number of entries that does not correspond to a tree.
+    InvalidLength(u32),
+    /// The operation requires a non-empty tree.
+    EmptyTree,
+    /// The tree does not contain a leaf at the given index.
+    LeafOutOfRange(u32),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Store(e) => write!(f, "History tree store error: {}", e),
+            Error::Entry(e) => write!(f, "Invalid history tree entry: {}", e),
+            Error::Tree(e) => write!(f, "Invalid history tree: {}", e),
+            Error::InvalidLength(len) => {
+                write!(f, "{} entries do not form a valid history tree", len)
+            }
+            Error::EmptyTree => write!(f, "History tree is empty"),
+            Error::LeafOutOfRange(index) => {
+                write!(f, "History tree does not contain leaf {}", index)
+            }
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Store(e) => Some(e),
+            Error::Entry(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl<E> From<crate::Error> for Error<E> {
+    fn from(e: crate::Error) -> Self {
+        Error::Tree(e)
+    }
+}
+
+/// A peak of an MMR: the root of one of its maximal complete subtrees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Peak {
+    /// The position of the peak in the array representation.
+    position: u32,
+    /// The height of the peak above the leaves.
+    height: u32,
+}
+
+impl Peak {
+    fn leaf_count(&self) -> u32 {
+        1 << self.height
+    }
+}
+
+/// Returns the peaks of an MMR with the given number of entries, from left to right, or
+/// `None` if no MMR has that number of entries.
+fn peaks(len: u32) -> Option<Vec<Peak>> {
+    let mut peaks: Vec<Peak> = vec![];
+    let mut start = 0u64;
+    while start < u64::from(len) {
+        let remaining = u64::from(len) - start;
+        // The largest complete subtree, of 2^(height + 1) - 1 entries, that fits.
+        let height = 63 - (remaining + 1).leading_zeros() - 1;
+        if peaks.last().map_or(false, |peak| peak.height <= height) {
+            return None;
+        }
+        let size = (1u64 << (height + 1)) - 1;
+        peaks.push(Peak {
+            position: (start + size - 1) as u32,
+            height,
+        });
+        start += size;
+    }
+    Some(peaks)
+}
+
+/// A proof that a leaf is included in a chain history tree.
+#[derive(Debug)]
+pub struct InclusionProof<V: Version> {
+    leaf_index: u32,
+    leaf_count: u32,
+    leaf: V::NodeData,
+    /// The siblings of the nodes on the path from the leaf to its peak, starting with
+    /// the sibling of the leaf.
+    siblings: Vec<V::NodeData>,
+    /// The peaks of the tree other than the one above the leaf, from left to right.
+    other_peaks: Vec<V::NodeData>,
+}
+
+impl<V: Version> InclusionProof<V> {
+    /// Returns the index of the leaf, in the order that leaves were appended to the tree.
+    pub fn leaf_index(&self) -> u32 {
+        self.leaf_index
+    }
+
+    /// Returns the number of leaves in the tree that this proof commits to.
+    pub fn leaf_count(&self) -> u32 {
+        self.leaf_count
+    }
+
+    /// Returns the data of the leaf.
+    pub fn leaf(&self) -> &V::NodeData {
+        &self.leaf
+    }
+
+    /// Returns the index of the peak containing the leaf, and the number of leaves before
+    /// that peak, if the proof has the shape of a proof for a leaf at
+    /// [`Self::leaf_index`] in a tree with [`Self::leaf_count`] leaves.
+    fn locate(&self) -> Option<(usize, u32)> {
+        let (peak_index, leaves_before_peak, peak_heights) =
+            locate(self.leaf_index, self.leaf_count)?;
+        if self.siblings.len() != peak_heights[peak_index] as usize
+            || self.other_peaks.len() + 1 != peak_heights.len()
+        {
+            return None;
+        }
+        Some((peak_index, leaves_before_peak))
+    }
+
+    /// Computes the data of the root node of the tree that this proof commits to.
+    ///
+    /// Returns `None` if the proof does not have the shape of a proof for a leaf at
+    /// [`Self::leaf_index`] in a tree with [`Self::leaf_count`] leaves.
+    pub fn root(&self) -> Option<V::NodeData> {
+        let (peak_index, leaves_before_peak) = self.locate()?;
+
+        let offset = self.leaf_index - leaves_before_peak;
+        let mut node = copy::<V>(&self.leaf);
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            node = if offset & (1 << level) == 0 {
+                V::combine(&node, sibling)
+            } else {
+                V::combine(sibling, &node)
+            };
+        }
+
+        let (left, right) = self.other_peaks.split_at(peak_index);
+        let mut peaks = left.iter().chain(Some(&node)).chain(right.iter());
+        let first = copy::<V>(peaks.next().expect("there is at least one peak"));
+        Some(peaks.fold(first, |root, peak| V::combine(&root, peak)))
+    }
+
+    /// Computes the total work of the blocks committed to by the tree before the leaf.
+    ///
+    /// Returns `None` if the proof does not have the expected shape (as for
+    /// [`Self::root`]), or if the claimed work overflows.
+    pub fn work_before_leaf(&self) -> Option<U256> {
+        let (peak_index, leaves_before_peak) = self.locate()?;
+
+        // The siblings to the left of the path from the leaf to its peak cover the
+        // preceding leaves under that peak.
+        let offset = self.leaf_index - leaves_before_peak;
+        let left_siblings = self
+            .siblings
+            .iter()
+            .enumerate()
+            .filter(|(level, _)| offset & (1 << level) != 0)
+            .map(|(_, sibling)| sibling);
+        self.other_peaks[..peak_index]
+            .iter()
+            .chain(left_siblings)
+            .try_fold(U256::zero(), |work, node| {
+                work.checked_add(V::subtree_total_work(node))
+            })
+    }
+
+    /// Returns `true` if this proof is valid for a tree with the given root hash.
+    pub fn verify(&self, root_hash: &[u8; 32]) -> bool {
+        self.root()
+            .map_or(false, |root| &V::hash(&root) == root_hash)
+    }
+
+    /// Reads a proof from its byte representation, as produced by [`Self::write`].
+    ///
+    /// `consensus_branch_id` is the consensus branch ID of the network upgrade for which
+    /// the tree commits to the chain history.
+    pub fn read<R: Read>(consensus_branch_id: u32, r: &mut R) -> io::Result<Self> {
+        let leaf_index = r.read_u32::<LittleEndian>()?;
+        let leaf_count = r.read_u32::<LittleEndian>()?;
+        // The number of siblings and peaks is determined by the position of the leaf.
+        let (peak_index, _, peak_heights) = locate(leaf_index, leaf_count).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "leaf index is not less than the leaf count",
+            )
+        })?;
+
+        let leaf = V::read(consensus_branch_id, r)?;
+        let siblings = (0..peak_heights[peak_index])
+            .map(|_| V::read(consensus_branch_id, r))
+            .collect::<io::Result<_>>()?;
+        let other_peaks = (1..peak_heights.len())
+            .map(|_| V::read(consensus_branch_id, r))
+            .collect::<io::Result<_>>()?;
+
+        Ok(InclusionProof {
+            leaf_index,
+            leaf_count,
+            leaf,
+            siblings,
+            other_peaks,
+        })
+    }
+
+    /// Writes the byte representation of this proof.
+    ///
+    /// The proof is encoded as the leaf index and leaf count, as 32-bit little-endian
+    /// integers, followed by the data of the leaf, of its siblings from the leaf upwards,
+    /// and of the other peaks from left to right.
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u32::<LittleEndian>(self.leaf_index)?;
+        w.write_u32::<LittleEndian>(self.leaf_count)?;
+        V::write(&self.leaf, w)?;
+        for node in self.siblings.iter().chain(&self.other_peaks) {
+            V::write(node, w)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the index of the peak containing the leaf at `leaf_index` in a tree with
+/// `leaf_count` leaves, the number of leaves before that peak, and the heights of the
+/// peaks from left to right, or `None` if the leaf is not in the tree.
+fn locate(leaf_index: u32, leaf_count: u32) -> Option<(usize, u32, Vec<u32>)> {
+    // Each peak covers a power-of-two number of leaves, corresponding to the bits of the
+    // leaf count from most to least significant.
+    let peak_heights: Vec<u32> = (0..32)
+        .rev()
+        .filter(|height| leaf_count & (1 << height) != 0)
+        .collect();
+    let mut leaves_before_peak = 0;
+    let peak_index = peak_heights.iter().position(|height| {
+        let found = leaf_index < leaves_before_peak + (1 << height);
+        if !found {
+            leaves_before_peak += 1 << height;
+        }
+        found
+    })?;
+    Some((peak_index, leaves_before_peak, peak_heights))
+}
+
+fn copy<V: Version>(data: &V::NodeData) -> V::NodeData {
+    V::from_bytes(V::consensus_branch_id(data), V::to_bytes(data))
+        .expect("node data round-trips through its encoding")
+}
+
+/// A chain history tree whose entries are persisted in a [`NodeStore`].
+pub struct HistoryTree<V: Version, S: NodeStore> {
+    store: S,
+    consensus_branch_id: u32,
+    _version: PhantomData<V>,
+}
+
+impl<V: Version, S: NodeStore> HistoryTree<V, S> {
+    /// Constructs a history tree backed by the given store.
+    ///
+    /// `consensus_branch_id` is the consensus branch ID of the network upgrade for which
+    /// the tree commits to the chain history.
+    pub fn new(store: S, consensus_branch_id: u32) -> Self {
+        HistoryTree {
+            store,
+            consensus_branch_id,
+            _version: PhantomData,
+        }
+    }
+
+    /// Returns the underlying store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Consumes this tree, returning the underlying store.
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Returns the number of leaves in the tree.
+    pub fn leaf_count(&self) -> Result<u32, Error<S::Error>> {
+        Ok(self.peaks()?.iter().map(Peak::leaf_count).sum())
+    }
+
+    /// Appends a leaf to the tree, persisting the new entries.
+    pub fn append_leaf(&mut self, leaf: V::NodeData) -> Result<(), Error<S::Error>> {
+        let len = self.store.len().map_err(Error::Store)?;
+        let entries = if len == 0 {
+            vec![serialize(&Entry::<V>::new_leaf(leaf))?]
+        } else {
+            let mut tree = self.load(len)?;
+            tree.append_leaf(leaf)?
+                .into_iter()
+                .map(|link| serialize(tree.resolve_link(link)?.node()))
+                .collect::<Result<_, _>>()?
+        };
+        self.store.append(&entries).map_err(Error::Store)
+    }
+
+    /// Removes the most recently appended leaf from the tree, such as when its block is
+    /// disconnected during a reorg.
+    pub fn truncate_leaf(&mut self) -> Result<(), Error<S::Error>> {
+        let len = self.store.len().map_err(Error::Store)?;
+        let removed = match len {
+            0 => return Err(Error::EmptyTree),
+            1 => 1,
+            _ => self.load(len)?.truncate_leaf()?,
+        };
+        self.store.truncate(len - removed).map_err(Error::Store)
+    }
+
+    /// Returns the root hash of the tree, or `None` if the tree is empty.
+    pub fn root_hash(&self) -> Result<Option<[u8; 32]>, Error<S::Error>> {
+        let len = self.store.len().map_err(Error::Store)?;
+        if len == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(self.load(len)?.root_hash()?))
+        }
+    }
+
+    /// Returns a proof that the leaf with the given index, in the order that leaves were
+    /// appended to the tree, is included in the tree.
+    pub fn inclusion_proof(&self, leaf_index: u32) -> Result<InclusionProof<V>, Error<S::Error>> {
+        let peaks = self.peaks()?;
+
+        let mut leaves_before_peak = 0;
+        let peak_index = peaks
+            .iter()
+            .position(|peak| {
+                let found = leaf_index < leaves_before_peak + peak.leaf_count();
+                if !found {
+                    leaves_before_peak += peak.leaf_count();
+                }
+                found
+            })
+            .ok_or(Error::LeafOutOfRange(leaf_index))?;
+
+        // Descend from the peak to the leaf, collecting the siblings along the path.
+        let offset = leaf_index - leaves_before_peak;
+        let Peak {
+            mut position,
+            mut height,
+        } = peaks[peak_index];
+        let mut siblings = vec![];
+        while height > 0 {
+            let left = position - (1 << height);
+            let right = position - 1;
+            height -= 1;
+            if offset & (1 << height) == 0 {
+                siblings.push(self.read(right)?.data);
+                position = left;
+            } else {
+                siblings.push(self.read(left)?.data);
+                position = right;
+            }
+        }
+        siblings.reverse();
+
+        let other_peaks = peaks
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != peak_index)
+            .map(|(_, peak)| self.read(peak.position).map(|entry| entry.data))
+            .collect::<Result<_, _>>()?;
+
+        Ok(InclusionProof {
+            leaf_index,
+            leaf_count: peaks.iter().map(Peak::leaf_count).sum(),
+            leaf: self.read(position)?.data,
+            siblings,
+            other_peaks,
+        })
+    }
+
+    fn peaks(&self) -> Result<Vec<Peak>, Error<S::Error>> {
+        let len = self.store.len().map_err(Error::Store)?;
+        peaks(len).ok_or(Error::InvalidLength(len))
+    }
+
+    fn read(&self, position: u32) -> Result<Entry<V>, Error<S::Error>> {
+        let bytes = self
+            .store
+            .get(position)
+            .map_err(Error::Store)?
+            .ok_or(Error::InvalidLength(position))?;
+        Entry::from_bytes(self.consensus_branch_id, &bytes[..]).map_err(Error::Entry)
+    }
+
+    /// Loads the entries of a non-empty tree that are required to append a leaf to it, or
+    /// to truncate a leaf from it.
+    fn load(&self, len: u32) -> Result<Tree<V>, Error<S::Error>> {
+        let peaks = peaks(len).ok_or(Error::InvalidLength(len))?;
+
+        // Appending only requires the peaks, while truncating requires everything on the
+        // right slope of the last peak.
+        let mut extra = vec![];
+        let Peak {
+            mut position,
+            mut height,
+        } = *peaks.last().expect("tree is non-empty");
+        while height > 0 {
+            let left = position - (1 << height);
+            let right = position - 1;
+            extra.push((left, self.read(left)?));
+            extra.push((right, self.read(right)?));
+            position = right;
+            height -= 1;
+        }
+
+        let peaks = peaks
+            .iter()
+            .map(|peak| self.read(peak.position).map(|entry| (peak.position, entry)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Tree::new(len, peaks, extra))
+    }
+}
+
+fn serialize<V: Version, E>(entry: &Entry<V>) -> Result<SerializedEntry, Error<E>> {
+    let mut bytes = [0; MAX_ENTRY_SIZE];
+    entry.write(&mut &mut bytes[..]).map_err(Error::Entry)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{peaks, FileStore, HistoryTree, InclusionProof, MemoryStore, NodeStore, Peak};
+    use crate::{node_data, Entry, NodeData, Tree, V2};
+
+    fn leaf(height: u32) -> node_data::V2 {
+        node_data::V2 {
+            v1: NodeData {
+                consensus_branch_id: 1,
+                subtree_commitment: [height as u8; 32],
+                start_time: height,
+                end_time: height,
+                start_target: 0,
+                end_target: 0,
+                start_sapling_root: [0u8; 32],
+                end_sapling_root: [0u8; 32],
+                subtree_total_work: height.into(),
+                start_height: height as u64,
+                end_height: height as u64,
+                sapling_tx: 7,
+            },
+            start_orchard_root: [0u8; 32],
+            end_orchard_root: [0u8; 32],
+            orchard_tx: 42,
+        }
+    }
+
+    #[test]
+    fn peak_positions() {
+        let peak = |position, height| Peak { position, height };
+        assert_eq!(peaks(0), Some(vec![]));
+        assert_eq!(peaks(1), Some(vec![peak(0, 0)]));
+        assert_eq!(peaks(2), None);
+        assert_eq!(peaks(3), Some(vec![peak(2, 1)]));
+        assert_eq!(peaks(4), Some(vec![peak(2, 1), peak(3, 0)]));
+        assert_eq!(peaks(5), None);
+        assert_eq!(peaks(6), None);
+        assert_eq!(peaks(7), Some(vec![peak(6, 2)]));
+        assert_eq!(peaks(11), Some(vec![peak(6, 2), peak(9, 1), peak(10, 0)]));
+    }
+
+    #[test]
+    fn append_and_truncate() {
+        // Track the root hashes of an in-memory tree as leaves are appended.
+        let mut tree = Tree::<V2>::new(1, vec![(0, Entry::new_leaf(leaf(1)))], vec![]);
+        let mut root_hashes = vec![tree.root_hash().unwrap()];
+        for height in 2..=40 {
+            tree.append_leaf(leaf(height)).unwrap();
+            root_hashes.push(tree.root_hash().unwrap());
+        }
+
+        let mut history = HistoryTree::<V2, _>::new(MemoryStore::new(), 1);
+        assert_eq!(history.root_hash().unwrap(), None);
+        for (height, root_hash) in (1..=40).zip(&root_hashes) {
+            history.append_leaf(leaf(height)).unwrap();
+            assert_eq!(history.leaf_count().unwrap(), height);
+            assert_eq!(history.root_hash().unwrap().as_ref(), Some(root_hash));
+        }
+
+        // Truncating leaves restores the earlier roots.
+        for root_hash in root_hashes.iter().rev().skip(1) {
+            history.truncate_leaf().unwrap();
+            assert_eq!(history.root_hash().unwrap().as_ref(), Some(root_hash));
+        }
+        history.truncate_leaf().unwrap();
+        assert_eq!(history.store().len().unwrap(), 0);
+        assert!(history.truncate_leaf().is_err());
+    }
+
+    #[test]
+    fn file_store_reload() {
+        let path = std::env::temp_dir().join(format!(
+            "zcash_history_file_store_{}.dat",
+            std::process::id()
+        ));
+
+        let mut history = HistoryTree::<V2, _>::new(FileStore::open(&path).unwrap(), 1);
+        for height in 1..=20 {
+            history.append_leaf(leaf(height)).unwrap();
+        }
+        history.truncate_leaf().unwrap();
+        let root_hash = history.root_hash().unwrap();
+        drop(history);
+
+        let mut history = HistoryTree::<V2, _>::new(FileStore::open(&path).unwrap(), 1);
+        assert_eq!(history.leaf_count().unwrap(), 19);
+        assert_eq!(history.root_hash().unwrap(), root_hash);
+
+        // The reloaded tree can continue to be appended to.
+        let mut in_memory = HistoryTree::<V2, _>::new(MemoryStore::new(), 1);
+        for height in 1..=20 {
+            in_memory.append_leaf(leaf(height)).unwrap();
+        }
+        history.append_leaf(leaf(20)).unwrap();
+        assert_eq!(history.root_hash().unwrap(), in_memory.root_hash().unwrap());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn inclusion_proofs() {
+        for leaves in 1..=20 {
+            let mut history = HistoryTree::<V2, _>::new(MemoryStore::new(), 1);
+            for height in 1..=leaves {
+                history.append_leaf(leaf(height)).unwrap();
+            }
+            let root_hash = history.root_hash().unwrap().unwrap();
+
+            for leaf_index in 0..leaves {
+                let proof = history.inclusion_proof(leaf_index).unwrap();
+                assert_eq!(proof.leaf(), &leaf(leaf_index + 1));
+                assert!(proof.verify(&root_hash));
+
+                let root = proof.root().unwrap();
+                assert_eq!(root.v1.start_height, 1);
+                assert_eq!(root.v1.end_height, u64::from(leaves));
+
+                // The proof can be sent to a client that verifies it independently.
+                let mut bytes = vec![];
+                proof.write(&mut bytes).unwrap();
+                let decoded = InclusionProof::<V2>::read(1, &mut &bytes[..]).unwrap();
+                assert_eq!(decoded.leaf_index(), leaf_index);
+                assert_eq!(decoded.leaf_count(), leaves);
+                assert_eq!(decoded.leaf(), &leaf(leaf_index + 1));
+                assert!(decoded.verify(&root_hash));
+                let mut reencoded = vec![];
+                decoded.write(&mut reencoded).unwrap();
+                assert_eq!(reencoded, bytes);
+
+                // Truncated proofs cannot be decoded.
+                assert!(InclusionProof::<V2>::read(1, &mut &bytes[..bytes.len() - 1]).is_err());
+            }
+            assert!(history.inclusion_proof(leaves).is_err());
+
+            // A proof for a leaf outside of the tree cannot be decoded.
+            let mut bytes = vec![];
+            history
+                .inclusion_proof(0)
+                .unwrap()
+                .write(&mut bytes)
+                .unwrap();
+            bytes[..4].copy_from_slice(&leaves.to_le_bytes());
+            assert!(InclusionProof::<V2>::read(1, &mut &bytes[..]).is_err());
+
+            // A proof does not verify against a different tree.
+            history.append_leaf(leaf(leaves + 1)).unwrap();
+            let other_root_hash = history.root_hash().unwrap().unwrap();
+            assert!(!history.inclusion_proof(0).unwrap().verify(&root_hash));
+            assert!(history.inclusion_proof(0).unwrap().verify(&other_root_hash));
+        }
+    }
+}
@@ -278,6 +278,13 @@ impl<V: Version> Tree<V> {
         self.resolve_link(self.root)
     }
 
+    /// Hash of the root node.
+    ///
+    /// This is the chain history root that is committed to by block headers.
+    pub fn root_hash(&self) -> Result<[u8; 32], Error> {
+        self.root_node().map(|node| V::hash(node.data()))
+    }
+
     /// If this tree is empty.
     pub fn is_empty(&self) -> bool {
         self.stored_count == 0
@@ -605,6 +612,22 @@ mod tests {
         assert_eq!(tree.len(), 16);
     }
 
+    #[test]
+    fn root_hash() {
+        let mut tree = generated(4);
+        let root_hash = tree.root_hash().expect("Failed to resolve root");
+        assert_eq!(
+            root_hash,
+            V2::hash(tree.root_node().expect("Failed to resolve root").data())
+        );
+
+        tree.append_leaf(leaf(5)).expect("Failed to append");
+        assert_ne!(tree.root_hash().expect("Failed to resolve root"), root_hash);
+
+        tree.truncate_leaf().expect("Failed to truncate");
+        assert_eq!(tree.root_hash().expect("Failed to resolve root"), root_hash);
+    }
+
     #[test]
     fn tree_len() {
         let mut tree = initial();
@@ -3,6 +3,7 @@ use std::io;
 
 use blake2b_simd::Params as Blake2Params;
 use byteorder::{ByteOrder, LittleEndian};
+use primitive_types::U256;
 
 use crate::{node_data, NodeData, MAX_NODE_DATA_SIZE};
 
@@ -39,6 +40,26 @@ pub trait Version {
     /// Returns the end height for the given node data.
     fn end_height(data: &Self::NodeData) -> u64;
 
+    /// Returns the start time for the given node data.
+    fn start_time(data: &Self::NodeData) -> u32;
+
+    /// Returns the end time for the given node data.
+    fn end_time(data: &Self::NodeData) -> u32;
+
+    /// Returns the start target (in compact "nBits" form) for the given node data.
+    fn start_target(data: &Self::NodeData) -> u32;
+
+    /// Returns the end target (in compact "nBits" form) for the given node data.
+    fn end_target(data: &Self::NodeData) -> u32;
+
+    /// Returns the subtree commitment for the given node data.
+    ///
+    /// For a leaf, this is the hash of the block it commits to.
+    fn subtree_commitment(data: &Self::NodeData) -> [u8; 32];
+
+    /// Returns the total work of the blocks in the subtree for the given node data.
+    fn subtree_total_work(data: &Self::NodeData) -> U256;
+
     /// Combines two nodes' metadata.
     fn combine(left: &Self::NodeData, right: &Self::NodeData) -> Self::NodeData {
         assert_eq!(
@@ -126,6 +147,30 @@ impl Version for V1 {
         data.end_height
     }
 
+    fn start_time(data: &Self::NodeData) -> u32 {
+        data.start_time
+    }
+
+    fn end_time(data: &Self::NodeData) -> u32 {
+        data.end_time
+    }
+
+    fn start_target(data: &Self::NodeData) -> u32 {
+        data.start_target
+    }
+
+    fn end_target(data: &Self::NodeData) -> u32 {
+        data.end_target
+    }
+
+    fn subtree_commitment(data: &Self::NodeData) -> [u8; 32] {
+        data.subtree_commitment
+    }
+
+    fn subtree_total_work(data: &Self::NodeData) -> U256 {
+        data.subtree_total_work
+    }
+
     fn combine_inner(
         subtree_commitment: [u8; 32],
         left: &Self::NodeData,
@@ -163,6 +208,30 @@ impl Version for V2 {
         data.v1.end_height
     }
 
+    fn start_time(data: &Self::NodeData) -> u32 {
+        data.v1.start_time
+    }
+
+    fn end_time(data: &Self::NodeData) -> u32 {
+        data.v1.end_time
+    }
+
+    fn start_target(data: &Self::NodeData) -> u32 {
+        data.v1.start_target
+    }
+
+    fn end_target(data: &Self::NodeData) -> u32 {
+        data.v1.end_target
+    }
+
+    fn subtree_commitment(data: &Self::NodeData) -> [u8; 32] {
+        data.v1.subtree_commitment
+    }
+
+    fn subtree_total_work(data: &Self::NodeData) -> U256 {
+        data.v1.subtree_total_work
+    }
+
     fn combine_inner(
         subtree_commitment: [u8; 32],
         left: &Self::NodeData,
@@ -1,15 +1,26 @@
-//! Structs and methods for handling Zcash block headers.
+//! Structs and methods for handling Zcash blocks and block headers.
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use memuse::DynamicUsage;
 use sha2::{Digest, Sha256};
+use std::error;
 use std::fmt;
 use std::io::{self, Read, Write};
 use std::ops::Deref;
 use zcash_encoding::Vector;
 
+use crate::consensus::{BlockHeight, BranchId, NetworkUpgrade, Parameters};
+use crate::transaction::{Transaction, TxVersion};
+
 pub use equihash;
 
+const ZCASH_AUTH_DATA_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashAuthDatHash";
+const ZCASH_BLOCK_COMMITMENTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashBlockCommit";
+
+/// The auth digest of transactions prior to v5, which do not commit to their authorizing
+/// data separately from their transaction IDs.
+const LEGACY_TX_AUTH_DIGEST: [u8; 32] = [0xff; 32];
+
 /// The identifier for a Zcash block.
 ///
 /// This is the SHA-256d hash of the encoded [`BlockHeader`].
@@ -60,6 +71,7 @@ impl BlockHash {
 }
 
 /// A Zcash block header.
+#[derive(Clone, Debug)]
 pub struct BlockHeader {
     hash: BlockHash,
     data: BlockHeaderData,
@@ -74,6 +86,7 @@ impl Deref for BlockHeader {
 }
 
 /// The information contained in a Zcash block header.
+#[derive(Clone, Debug)]
 pub struct BlockHeaderData {
     pub version: i32,
     pub prev_block: BlockHash,
@@ -144,22 +157,294 @@ impl BlockHeader {
     }
 
     pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        self.write_equihash_input(&mut writer)?;
+        writer.write_all(&self.nonce)?;
+        Vector::write(&mut writer, &self.solution, |w, b| w.write_u8(*b))?;
+
+        Ok(())
+    }
+
+    /// Writes the fields of the header that precede the nonce, which form the input to
+    /// the Equihash proof-of-work function.
+    fn write_equihash_input<W: Write>(&self, mut writer: W) -> io::Result<()> {
         writer.write_i32::<LittleEndian>(self.version)?;
         writer.write_all(&self.prev_block.0)?;
         writer.write_all(&self.merkle_root)?;
         writer.write_all(&self.final_sapling_root)?;
         writer.write_u32::<LittleEndian>(self.time)?;
         writer.write_u32::<LittleEndian>(self.bits)?;
-        writer.write_all(&self.nonce)?;
-        Vector::write(&mut writer, &self.solution, |w, b| w.write_u8(*b))?;
 
         Ok(())
     }
+
+    /// Checks that the Equihash solution in this header is valid for the Zcash
+    /// parameters `(n = 200, k = 9)`.
+    pub fn check_solution(&self) -> Result<(), equihash::Error> {
+        let mut input = vec![];
+        self.write_equihash_input(&mut input)
+            .expect("writing to a Vec cannot fail");
+        equihash::is_valid_solution(200, 9, &input, &self.nonce, &self.solution)
+    }
+}
+
+/// Errors that can occur when checking a [`Block`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The block has no transactions.
+    NoTransactions,
+    /// The Merkle root in the block header does not match the block's transactions.
+    InvalidMerkleRoot,
+    /// The block contains duplicate transactions in a position that does not change its
+    /// Merkle root.
+    DuplicateTransactions,
+    /// The block commitments in the block header do not match the chain history root
+    /// and the block's transactions.
+    InvalidBlockCommitments,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoTransactions => write!(f, "Block has no transactions"),
+            Error::InvalidMerkleRoot => {
+                write!(f, "Merkle root does not match the block's transactions")
+            }
+            Error::DuplicateTransactions => write!(f, "Block contains duplicate transactions"),
+            Error::InvalidBlockCommitments => write!(
+                f,
+                "Block commitments do not match the chain history and the block's transactions"
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A Zcash block.
+#[derive(Clone, Debug)]
+pub struct Block {
+    header: BlockHeader,
+    vtx: Vec<Transaction>,
+}
+
+impl Block {
+    /// Constructs a block from its header and transactions.
+    pub fn from_parts(header: BlockHeader, vtx: Vec<Transaction>) -> Self {
+        Block { header, vtx }
+    }
+
+    /// Returns the header of this block.
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// Returns the hash of this block.
+    pub fn hash(&self) -> BlockHash {
+        self.header.hash()
+    }
+
+    /// Returns the transactions in this block, starting with the coinbase transaction.
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.vtx
+    }
+
+    /// Returns the header and transactions of this block.
+    pub fn into_parts(self) -> (BlockHeader, Vec<Transaction>) {
+        (self.header, self.vtx)
+    }
+
+    /// Reads a block from its consensus encoding.
+    ///
+    /// `consensus_branch_id` must be the consensus branch ID of the network upgrade that
+    /// is active at the height of the block (see [`BranchId::for_height`]). It is used
+    /// for transactions prior to v5, which do not encode their consensus branch ID.
+    pub fn read<R: Read>(mut reader: R, consensus_branch_id: BranchId) -> io::Result<Self> {
+        let header = BlockHeader::read(&mut reader)?;
+        let vtx = Vector::read(&mut reader, |r| Transaction::read(r, consensus_branch_id))?;
+        Ok(Block { header, vtx })
+    }
+
+    /// Writes this block in its consensus encoding.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        self.header.write(&mut writer)?;
+        Vector::write(&mut writer, &self.vtx, |w, tx| tx.write(w))
+    }
+
+    /// Computes the root of the Merkle tree of the IDs of this block's transactions.
+    ///
+    /// Also returns whether the tree is "mutated", meaning that the block contains
+    /// duplicate transactions that do not affect the root ([CVE-2012-2459]).
+    ///
+    /// [CVE-2012-2459]: https://nvd.nist.gov/vuln/detail/CVE-2012-2459
+    pub fn compute_merkle_root(&self) -> ([u8; 32], bool) {
+        let mut mutated = false;
+        let mut hashes: Vec<[u8; 32]> = self.vtx.iter().map(|tx| tx.txid().into()).collect();
+        if hashes.is_empty() {
+            return ([0; 32], mutated);
+        }
+
+        while hashes.len() > 1 {
+            mutated |= hashes.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+            if hashes.len() % 2 == 1 {
+                hashes.push(hashes[hashes.len() - 1]);
+            }
+            hashes = hashes
+                .chunks_exact(2)
+                .map(|pair| {
+                    let mut hash = [0; 32];
+                    hash.copy_from_slice(&Sha256::digest(
+                        Sha256::new()
+                            .chain_update(pair[0])
+                            .chain_update(pair[1])
+                            .finalize(),
+                    ));
+                    hash
+                })
+                .collect();
+        }
+
+        (hashes[0], mutated)
+    }
+
+    /// Checks that the Merkle root in this block's header commits to its transactions.
+    pub fn check_merkle_root(&self) -> Result<(), Error> {
+        if self.vtx.is_empty() {
+            return Err(Error::NoTransactions);
+        }
+
+        let (merkle_root, mutated) = self.compute_merkle_root();
+        if merkle_root != self.header.merkle_root {
+            Err(Error::InvalidMerkleRoot)
+        } else if mutated {
+            Err(Error::DuplicateTransactions)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Computes the root of the tree of auth digests of this block's transactions.
+    ///
+    /// See [`auth_data_root`] for details.
+    pub fn auth_data_root(&self) -> [u8; 32] {
+        auth_data_root(&self.vtx)
+    }
+
+    /// Checks the commitments to the chain history (and, from NU5, to the authorizing
+    /// data of the block's transactions) in this block's header.
+    ///
+    /// `history_root` must be the root hash of the chain history tree ([ZIP 221]) of the
+    /// epoch of the previous block, after the previous block was appended to it; this is
+    /// `[0; 32]` for the Heartwood activation block. The tree's root hash can be obtained
+    /// with `zcash_history::Tree::root_hash`.
+    ///
+    /// Prior to Heartwood activation, the corresponding header field commits to the
+    /// Sapling note commitment tree instead, which is not checked by this method.
+    ///
+    /// [ZIP 221]: https://zips.z.cash/zip-0221
+    pub fn check_block_commitments<P: Parameters>(
+        &self,
+        params: &P,
+        height: BlockHeight,
+        history_root: &[u8; 32],
+    ) -> Result<(), Error> {
+        let expected = if params.is_nu_active(NetworkUpgrade::Nu5, height) {
+            block_commitments_hash(history_root, &self.auth_data_root())
+        } else if params.is_nu_active(NetworkUpgrade::Heartwood, height) {
+            *history_root
+        } else {
+            return Ok(());
+        };
+
+        if self.header.final_sapling_root == expected {
+            Ok(())
+        } else {
+            Err(Error::InvalidBlockCommitments)
+        }
+    }
+}
+
+/// Computes `hashAuthDataRoot` for a block containing the given transactions, as defined
+/// in [ZIP 244].
+///
+/// This is the root of a binary Merkle tree of the auth digests of the transactions,
+/// padded with leaves of `[0; 32]` to a power-of-two width. Transactions prior to v5 have
+/// an auth digest of `[0xff; 32]`.
+///
+/// [ZIP 244]: https://zips.z.cash/zip-0244#block-header-changes
+pub fn auth_data_root<'a, I: IntoIterator<Item = &'a Transaction>>(vtx: I) -> [u8; 32] {
+    let mut layer: Vec<[u8; 32]> = vtx
+        .into_iter()
+        .map(|tx| match tx.version() {
+            TxVersion::Sprout(_) | TxVersion::Overwinter | TxVersion::Sapling => {
+                LEGACY_TX_AUTH_DIGEST
+            }
+            _ => tx.auth_commitment().as_bytes().try_into().unwrap(),
+        })
+        .collect();
+    if layer.is_empty() {
+        return [0; 32];
+    }
+
+    layer.resize(layer.len().next_power_of_two(), [0; 32]);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks_exact(2)
+            .map(|pair| {
+                blake2b_simd::Params::new()
+                    .hash_length(32)
+                    .personal(ZCASH_AUTH_DATA_HASH_PERSONALIZATION)
+                    .to_state()
+                    .update(&pair[0])
+                    .update(&pair[1])
+                    .finalize()
+                    .as_bytes()
+                    .try_into()
+                    .unwrap()
+            })
+            .collect();
+    }
+
+    layer[0]
+}
+
+/// Computes `hashBlockCommitments`, as defined in [ZIP 244], from the chain history root
+/// and `hashAuthDataRoot`.
+///
+/// [ZIP 244]: https://zips.z.cash/zip-0244#block-header-changes
+pub fn block_commitments_hash(history_root: &[u8; 32], auth_data_root: &[u8; 32]) -> [u8; 32] {
+    blake2b_simd::Params::new()
+        .hash_length(32)
+        .personal(ZCASH_BLOCK_COMMITMENTS_HASH_PERSONALIZATION)
+        .to_state()
+        .update(history_root)
+        .update(auth_data_root)
+        // Reserved for future use.
+        .update(&[0; 32])
+        .finalize()
+        .as_bytes()
+        .try_into()
+        .unwrap()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::BlockHeader;
+    use sha2::{Digest, Sha256};
+
+    use super::{
+        auth_data_root, block_commitments_hash, Block, BlockHash, BlockHeader, BlockHeaderData,
+        Error,
+    };
+    use crate::{
+        consensus::{BranchId, NetworkUpgrade, Parameters, TEST_NETWORK},
+        legacy::Script,
+        transaction::{
+            components::{
+                amount::NonNegativeAmount,
+                transparent::{self, OutPoint, TxIn, TxOut},
+            },
+            Transaction, TransactionData, TxVersion,
+        },
+    };
 
     const HEADER_MAINNET_415000: [u8; 1487] = [
         0x04, 0x00, 0x00, 0x00, 0x52, 0x74, 0xb4, 0x3b, 0x9e, 0x4a, 0xd8, 0xf4, 0x3e, 0x93, 0xf7,
@@ -264,6 +549,36 @@ mod tests {
         0xdf, 0x5e,
     ];
 
+    /// The genesis block of zcashd's regtest network.
+    const BLOCK_REGTEST_GENESIS: [u8; 382] = [
+        0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xdb, 0x4d, 0x7a, 0x85, 0xb7, 0x68, 0x12, 0x3f, 0x1d,
+        0xff, 0x1d, 0x4c, 0x4c, 0xec, 0xe7, 0x00, 0x83, 0xb2, 0xd2, 0x7e, 0x11, 0x7b, 0x4a, 0xc2,
+        0xe3, 0x1d, 0x08, 0x79, 0x88, 0xa5, 0xea, 0xc4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xda, 0xe5, 0x49, 0x4d, 0x0f,
+        0x0f, 0x0f, 0x20, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x24, 0x01, 0x93, 0x6b, 0x7d, 0xb1, 0xeb, 0x4a, 0xc3, 0x9f,
+        0x15, 0x1b, 0x87, 0x04, 0x64, 0x2d, 0x0a, 0x8b, 0xda, 0x13, 0xec, 0x54, 0x7d, 0x54, 0xcd,
+        0x5e, 0x43, 0xba, 0x14, 0x2f, 0xc6, 0xd8, 0x87, 0x7c, 0xab, 0x07, 0xb3, 0x01, 0x01, 0x00,
+        0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x4d, 0x04, 0xff, 0xff, 0x07, 0x1f,
+        0x01, 0x04, 0x45, 0x5a, 0x63, 0x61, 0x73, 0x68, 0x30, 0x62, 0x39, 0x63, 0x34, 0x65, 0x65,
+        0x66, 0x38, 0x62, 0x37, 0x63, 0x63, 0x34, 0x31, 0x37, 0x65, 0x65, 0x35, 0x30, 0x30, 0x31,
+        0x65, 0x33, 0x35, 0x30, 0x30, 0x39, 0x38, 0x34, 0x62, 0x36, 0x66, 0x65, 0x61, 0x33, 0x35,
+        0x36, 0x38, 0x33, 0x61, 0x37, 0x63, 0x61, 0x63, 0x31, 0x34, 0x31, 0x61, 0x30, 0x34, 0x33,
+        0x63, 0x34, 0x32, 0x30, 0x36, 0x34, 0x38, 0x33, 0x35, 0x64, 0x33, 0x34, 0xff, 0xff, 0xff,
+        0xff, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x43, 0x41, 0x04, 0x67, 0x8a,
+        0xfd, 0xb0, 0xfe, 0x55, 0x48, 0x27, 0x19, 0x67, 0xf1, 0xa6, 0x71, 0x30, 0xb7, 0x10, 0x5c,
+        0xd6, 0xa8, 0x28, 0xe0, 0x39, 0x09, 0xa6, 0x79, 0x62, 0xe0, 0xea, 0x1f, 0x61, 0xde, 0xb6,
+        0x49, 0xf6, 0xbc, 0x3f, 0x4c, 0xef, 0x38, 0xc4, 0xf3, 0x55, 0x04, 0xe5, 0x1e, 0xc1, 0x12,
+        0xde, 0x5c, 0x38, 0x4d, 0xf7, 0xba, 0x0b, 0x8d, 0x57, 0x8a, 0x4c, 0x70, 0x2b, 0x6b, 0xf1,
+        0x1d, 0x5f, 0xac, 0x00, 0x00, 0x00, 0x00,
+    ];
+
     #[test]
     fn header_read_write() {
         let header = BlockHeader::read(&HEADER_MAINNET_415000[..]).unwrap();
@@ -275,4 +590,244 @@ mod tests {
         header.write(&mut encoded).unwrap();
         assert_eq!(&HEADER_MAINNET_415000[..], &encoded[..]);
     }
+
+    #[test]
+    fn header_solution() {
+        let header = BlockHeader::read(&HEADER_MAINNET_415000[..]).unwrap();
+        assert!(header.check_solution().is_ok());
+
+        let mut data = BlockHeader::read(&HEADER_MAINNET_415000[..]).unwrap().data;
+        data.nonce[0] ^= 1;
+        assert!(data.freeze().unwrap().check_solution().is_err());
+    }
+
+    /// Returns a v5 coinbase transaction for the given height.
+    fn coinbase(height: u32) -> Transaction {
+        versioned_coinbase(TxVersion::Zip225, BranchId::Nu5, height)
+    }
+
+    fn versioned_coinbase(
+        version: TxVersion,
+        consensus_branch_id: BranchId,
+        height: u32,
+    ) -> Transaction {
+        TransactionData::from_parts(
+            version,
+            consensus_branch_id,
+            0,
+            height.into(),
+            Some(transparent::Bundle {
+                vin: vec![TxIn {
+                    prevout: OutPoint::new([0; 32], u32::MAX),
+                    script_sig: Script([&[0x04][..], &height.to_le_bytes()].concat()),
+                    sequence: u32::MAX,
+                }],
+                vout: vec![TxOut {
+                    value: NonNegativeAmount::const_from_u64(312_500_000),
+                    script_pubkey: Script(vec![]),
+                }],
+                authorization: transparent::Authorized,
+            }),
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap()
+    }
+
+    fn block(merkle_root: [u8; 32], vtx: Vec<Transaction>) -> Block {
+        block_with_commitments(merkle_root, [0; 32], vtx)
+    }
+
+    fn block_with_commitments(
+        merkle_root: [u8; 32],
+        final_sapling_root: [u8; 32],
+        vtx: Vec<Transaction>,
+    ) -> Block {
+        let header = BlockHeaderData {
+            version: 4,
+            prev_block: BlockHash([0; 32]),
+            merkle_root,
+            final_sapling_root,
+            time: 0,
+            bits: 0,
+            nonce: [0; 32],
+            solution: vec![],
+        }
+        .freeze()
+        .unwrap();
+        Block::from_parts(header, vtx)
+    }
+
+    fn sha256d(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+        Sha256::digest(Sha256::digest([&a[..], &b[..]].concat())).into()
+    }
+
+    fn auth_data_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+        blake2b_simd::Params::new()
+            .hash_length(32)
+            .personal(b"ZcashAuthDatHash")
+            .hash(&[&a[..], &b[..]].concat())
+            .as_bytes()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn merkle_root() {
+        let txids: Vec<[u8; 32]> = (1..=3).map(|h| coinbase(h).txid().into()).collect();
+
+        // The root of a single transaction is its ID.
+        let single = block(txids[0], vec![coinbase(1)]);
+        assert_eq!(single.compute_merkle_root(), (txids[0], false));
+        assert_eq!(single.check_merkle_root(), Ok(()));
+
+        // Odd layers are padded by duplicating their last element.
+        let root = sha256d(
+            &sha256d(&txids[0], &txids[1]),
+            &sha256d(&txids[2], &txids[2]),
+        );
+        let three = block(root, (1..=3).map(coinbase).collect());
+        assert_eq!(three.compute_merkle_root(), (root, false));
+        assert_eq!(three.check_merkle_root(), Ok(()));
+
+        // Explicitly duplicating the last transaction results in the same root.
+        let mutated = block(root, [1, 2, 3, 3].into_iter().map(coinbase).collect());
+        assert_eq!(mutated.compute_merkle_root(), (root, true));
+        assert_eq!(
+            mutated.check_merkle_root(),
+            Err(Error::DuplicateTransactions)
+        );
+
+        let reordered = block(root, [2, 1, 3].into_iter().map(coinbase).collect());
+        assert_eq!(reordered.check_merkle_root(), Err(Error::InvalidMerkleRoot));
+        assert_eq!(
+            block([0; 32], vec![]).check_merkle_root(),
+            Err(Error::NoTransactions)
+        );
+    }
+
+    #[test]
+    fn block_read_write() {
+        let vtx: Vec<_> = (1..=3).map(coinbase).collect();
+        let (merkle_root, _) = block([0; 32], vtx).compute_merkle_root();
+        let original = block(merkle_root, (1..=3).map(coinbase).collect());
+
+        let mut encoded = vec![];
+        original.write(&mut encoded).unwrap();
+        let decoded = Block::read(&encoded[..], BranchId::Nu5).unwrap();
+
+        assert_eq!(decoded.hash(), original.hash());
+        assert_eq!(
+            decoded
+                .transactions()
+                .iter()
+                .map(|tx| tx.txid())
+                .collect::<Vec<_>>(),
+            original
+                .transactions()
+                .iter()
+                .map(|tx| tx.txid())
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(decoded.check_merkle_root(), Ok(()));
+
+        let mut reencoded = vec![];
+        decoded.write(&mut reencoded).unwrap();
+        assert_eq!(encoded, reencoded);
+    }
+
+    #[test]
+    fn regtest_genesis_block() {
+        let block = Block::read(&BLOCK_REGTEST_GENESIS[..], BranchId::Sprout).unwrap();
+        assert_eq!(
+            block.hash().to_string(),
+            "029f11d80ef9765602235e1bc9727e3eb6ba20839319f761fee920d63401e327"
+        );
+        assert_eq!(block.check_merkle_root(), Ok(()));
+
+        let vtx = block.transactions();
+        assert_eq!(vtx.len(), 1);
+        assert!(vtx[0].transparent_bundle().unwrap().is_coinbase());
+
+        // Regtest uses the Equihash parameters (n = 48, k = 5).
+        let header = block.header();
+        let mut input = vec![];
+        header.write_equihash_input(&mut input).unwrap();
+        assert!(
+            equihash::is_valid_solution(48, 5, &input, &header.nonce, &header.solution).is_ok()
+        );
+        assert!(header.check_solution().is_err());
+
+        let mut encoded = vec![];
+        block.clone().write(&mut encoded).unwrap();
+        assert_eq!(&encoded[..], &BLOCK_REGTEST_GENESIS[..]);
+    }
+
+    #[test]
+    fn auth_data_roots() {
+        let digests: Vec<[u8; 32]> = (1..=3)
+            .map(|h| coinbase(h).auth_commitment().as_bytes().try_into().unwrap())
+            .collect();
+
+        assert_eq!(auth_data_root(&[]), [0; 32]);
+        assert_eq!(auth_data_root(&[coinbase(1)]), digests[0]);
+
+        // The tree is padded with zero leaves to a power-of-two width.
+        let root = auth_data_hash(
+            &auth_data_hash(&digests[0], &digests[1]),
+            &auth_data_hash(&digests[2], &[0; 32]),
+        );
+        let three = block([0; 32], (1..=3).map(coinbase).collect());
+        assert_eq!(three.auth_data_root(), root);
+
+        // Transactions prior to v5 have a placeholder auth digest.
+        let legacy = versioned_coinbase(TxVersion::Sapling, BranchId::Canopy, 1);
+        assert_eq!(auth_data_root(&[legacy]), [0xff; 32]);
+    }
+
+    #[test]
+    fn block_commitments() {
+        let history_root = [7; 32];
+        let vtx = || (1..=3).map(coinbase).collect::<Vec<_>>();
+        let commitments = block_commitments_hash(&history_root, &auth_data_root(&vtx()));
+        assert_ne!(commitments, history_root);
+
+        let height = |nu| TEST_NETWORK.activation_height(nu).unwrap();
+        let nu5_height = height(NetworkUpgrade::Nu5);
+        let heartwood_height = height(NetworkUpgrade::Heartwood);
+
+        // From NU5, the header commits to the history root and the auth data root.
+        let nu5_block = block_with_commitments([0; 32], commitments, vtx());
+        assert_eq!(
+            nu5_block.check_block_commitments(&TEST_NETWORK, nu5_height, &history_root),
+            Ok(())
+        );
+        assert_eq!(
+            nu5_block.check_block_commitments(&TEST_NETWORK, nu5_height, &[0; 32]),
+            Err(Error::InvalidBlockCommitments)
+        );
+        assert_eq!(
+            nu5_block.check_block_commitments(&TEST_NETWORK, nu5_height - 1, &history_root),
+            Err(Error::InvalidBlockCommitments)
+        );
+
+        // Between Heartwood and NU5, the header commits only to the history root.
+        let heartwood_block = block_with_commitments([0; 32], history_root, vtx());
+        assert_eq!(
+            heartwood_block.check_block_commitments(&TEST_NETWORK, heartwood_height, &history_root),
+            Ok(())
+        );
+        assert_eq!(
+            heartwood_block.check_block_commitments(&TEST_NETWORK, nu5_height, &history_root),
+            Err(Error::InvalidBlockCommitments)
+        );
+
+        // Before Heartwood, the header commits to the Sapling note commitment tree.
+        assert_eq!(
+            heartwood_block.check_block_commitments(&TEST_NETWORK, heartwood_height - 1, &[0; 32]),
+            Ok(())
+        );
+    }
 }
@@ -9,6 +9,8 @@ use std::ops::Shl;
 
 use zcash_encoding::Vector;
 
+#[cfg(feature = "transaction-verification")]
+pub mod interpreter;
 #[cfg(feature = "transparent-inputs")]
 pub mod keys;
 
@@ -271,6 +273,12 @@ impl OpCode {
     }
 }
 
+/// The maximum number of public keys in a P2SH multisig redeem script.
+///
+/// A redeem script over 16 compressed public keys is 547 bytes long, which exceeds the
+/// 520-byte limit on pushed script elements, so it could never be revealed in a scriptSig.
+pub(crate) const MAX_P2SH_MULTISIG_KEYS: usize = 15;
+
 /// A serialized script, used inside transparent inputs and outputs of a transaction.
 #[derive(Clone, Default, PartialEq, Eq)]
 pub struct Script(pub Vec<u8>);
@@ -328,8 +336,62 @@ impl Script {
         Vector::serialized_size_of_u8_vec(&self.0)
     }
 
+    /// Returns the standard `required`-of-`n` multisig script for the given compressed
+    /// public keys, where `n` is the number of public keys.
+    ///
+    /// This is the redeem script of a P2SH multisig address. Returns `None` unless
+    /// `1 <= required <= n <= 15`; a redeem script over more keys would be too large to
+    /// be pushed in the scriptSig that spends from its address.
+    pub fn multisig(required: usize, pubkeys: &[[u8; 33]]) -> Option<Self> {
+        if required == 0 || required > pubkeys.len() || pubkeys.len() > MAX_P2SH_MULTISIG_KEYS {
+            return None;
+        }
+
+        let mut script = Script(vec![OpCode::Reserved as u8 + required as u8]);
+        for pubkey in pubkeys {
+            script = script << &pubkey[..];
+        }
+        script.0.push(OpCode::Reserved as u8 + pubkeys.len() as u8);
+        Some(script << OpCode::CheckMultisig)
+    }
+
+    /// Parses this script as a standard multisig script, returning the number of
+    /// signatures required to satisfy it and the compressed public keys it contains.
+    ///
+    /// Returns `None` if this is not a standard multisig script over compressed public
+    /// keys.
+    pub fn parse_multisig(&self) -> Option<(usize, Vec<[u8; 33]>)> {
+        let small_int = |b: u8| {
+            (OpCode::Op1 as u8..=OpCode::Op16 as u8)
+                .contains(&b)
+                .then(|| usize::from(b - OpCode::Reserved as u8))
+        };
+
+        let (&first, rest) = self.0.split_first()?;
+        let (&last, rest) = rest.split_last()?;
+        let (&n_op, mut rest) = rest.split_last()?;
+        let required = small_int(first)?;
+        let n = small_int(n_op)?;
+        if last != OpCode::CheckMultisig as u8 || required > n || rest.len() != n * 34 {
+            return None;
+        }
+
+        let mut pubkeys = Vec::with_capacity(n);
+        while let Some((&len, tail)) = rest.split_first() {
+            if len != 33 {
+                return None;
+            }
+            let mut pubkey = [0; 33];
+            pubkey.copy_from_slice(&tail[..33]);
+            pubkeys.push(pubkey);
+            rest = &tail[33..];
+        }
+
+        Some((required, pubkeys))
+    }
+
     /// Returns the address that this Script contains, if any.
-    pub(crate) fn address(&self) -> Option<TransparentAddress> {
+    pub fn address(&self) -> Option<TransparentAddress> {
         if self.0.len() == 25
             && self.0[0..3] == [OpCode::Dup as u8, OpCode::Hash160 as u8, 0x14]
             && self.0[23..25] == [OpCode::EqualVerify as u8, OpCode::CheckSig as u8]
@@ -406,6 +468,15 @@ impl TransparentAddress {
             }
         }
     }
+
+    /// Returns the P2SH address for the given redeem script.
+    #[cfg(feature = "transparent-inputs")]
+    pub fn from_redeem_script(redeem_script: &Script) -> Self {
+        use ripemd::Ripemd160;
+        use sha2::{Digest, Sha256};
+
+        TransparentAddress::ScriptHash(Ripemd160::digest(Sha256::digest(&redeem_script.0)).into())
+    }
 }
 
 impl TryFromRawAddress for TransparentAddress {
@@ -494,6 +565,66 @@ mod tests {
         assert_eq!(addr.script().address(), Some(addr));
     }
 
+    #[test]
+    fn multisig() {
+        let pubkeys = [[2; 33], [3; 33], [4; 33]];
+        let script = Script::multisig(2, &pubkeys).unwrap();
+        assert_eq!(script.0.len(), 3 + 3 * 34);
+        assert_eq!(script.0[0], OpCode::Op2 as u8);
+        assert_eq!(&script.0[1..3], &[33, 2][..]);
+        assert_eq!(script.0[script.0.len() - 2], OpCode::Op3 as u8);
+        assert_eq!(script.0[script.0.len() - 1], OpCode::CheckMultisig as u8);
+        assert_eq!(script.parse_multisig(), Some((2, pubkeys.to_vec())));
+
+        assert_eq!(Script::multisig(0, &pubkeys), None);
+        assert_eq!(Script::multisig(4, &pubkeys), None);
+        assert!(Script::multisig(1, &[[2; 33]; 15]).is_some());
+        assert_eq!(Script::multisig(1, &[[2; 33]; 16]), None);
+        assert_eq!(Script::multisig(1, &[[2; 33]; 17]), None);
+
+        // Non-multisig scripts are not parsed.
+        assert_eq!(
+            TransparentAddress::PublicKeyHash([4; 20])
+                .script()
+                .parse_multisig(),
+            None
+        );
+        let mut truncated = script.clone();
+        truncated.0.remove(5);
+        assert_eq!(truncated.parse_multisig(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "transparent-inputs")]
+    fn multisig_p2sh_vector() {
+        // A 2-of-3 redeem script over the compressed public keys of the secret keys 1, 2
+        // and 3 (the secp256k1 points G, 2G and 3G). The expected script and script hash
+        // were computed independently of this crate; the hash corresponds to the mainnet
+        // address t3LZs3ATHhkVcyUmWMizBpVdvYnRPZdSB58.
+        let pubkeys = [
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+            "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+        ]
+        .map(|pk| <[u8; 33]>::try_from(hex::decode(pk).unwrap()).unwrap());
+        let script = Script::multisig(2, &pubkeys).unwrap();
+        assert_eq!(
+            hex::encode(&script.0),
+            "52210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+             2102c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5\
+             2102f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f953ae"
+        );
+
+        let addr = TransparentAddress::from_redeem_script(&script);
+        let mut script_hash = [0; 20];
+        hex::decode_to_slice("15fc0754e73eb85d1cbce08786fadb7320ecb8dc", &mut script_hash).unwrap();
+        assert_eq!(addr, TransparentAddress::ScriptHash(script_hash));
+        assert_eq!(
+            hex::encode(addr.script().0),
+            "a91415fc0754e73eb85d1cbce08786fadb7320ecb8dc87"
+        );
+    }
+
     #[test]
     fn p2sh() {
         let addr = TransparentAddress::ScriptHash([7; 20]);
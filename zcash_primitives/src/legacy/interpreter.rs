@@ -0,0 +1,1808 @@
+//! An interpreter for transparent scripts, implementing the Zcash consensus rules for
+//! evaluating a `scriptSig` against the `scriptPubKey` of the output it spends.
+
+use std::fmt;
+
+use blake2b_simd::Hash as Blake2bHash;
+use ripemd::Ripemd160;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use super::{OpCode, Script};
+use crate::sapling;
+use crate::transaction::{
+    components::{amount::NonNegativeAmount, transparent, TxIn, TxOut},
+    sighash::{
+        signature_hash, SignableInput, TransparentAuthorizingContext, SIGHASH_ALL,
+        SIGHASH_ANYONECANPAY, SIGHASH_SINGLE,
+    },
+    txid::TxIdDigester,
+    Authorization, Transaction, TransactionData, TxDigests, TxVersion,
+};
+
+#[cfg(zcash_unstable = "zfuture")]
+use crate::transaction::components::tze;
+
+/// The maximum size of a value pushed onto the stack.
+const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// The maximum number of non-push operations in a script.
+const MAX_OPS_PER_SCRIPT: usize = 201;
+/// The maximum size of a script.
+const MAX_SCRIPT_SIZE: usize = 10_000;
+/// The maximum combined size of the main and alternate stacks.
+const MAX_STACK_SIZE: usize = 1000;
+/// The maximum number of public keys in an `OP_CHECKMULTISIG` operation.
+const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+/// Lock times below this threshold are block heights; lock times at or above it are UNIX
+/// timestamps.
+const LOCKTIME_THRESHOLD: i64 = 500_000_000;
+
+/// The rules to enforce when evaluating a script.
+///
+/// [`VerificationFlags::CONSENSUS`] is the set of rules enforced by the Zcash consensus
+/// rules. [`VerificationFlags::STANDARD`] additionally enforces the policy rules that
+/// `zcashd` applies to transactions that it relays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerificationFlags {
+    /// Evaluate the redeem scripts of P2SH spends ([BIP 16]).
+    ///
+    /// [BIP 16]: https://github.com/bitcoin/bips/blob/master/bip-0016.mediawiki
+    pub p2sh: bool,
+    /// Enforce `OP_CHECKLOCKTIMEVERIFY` ([BIP 65]), instead of treating it as a no-op.
+    ///
+    /// [BIP 65]: https://github.com/bitcoin/bips/blob/master/bip-0065.mediawiki
+    pub check_lock_time_verify: bool,
+    /// Require signatures to be strictly DER-encoded ([BIP 66]).
+    ///
+    /// Zcash has enforced this since launch, so it is part of [`Self::CONSENSUS`]. It is
+    /// also implied by [`Self::strict_encoding`] and [`Self::low_s`].
+    ///
+    /// [BIP 66]: https://github.com/bitcoin/bips/blob/master/bip-0066.mediawiki
+    pub der_sig: bool,
+    /// Require signatures to have a defined hash type, and public keys to be encoded in
+    /// compressed or uncompressed SEC1 form.
+    pub strict_encoding: bool,
+    /// Require the S value of signatures to be in the lower half of the curve order.
+    pub low_s: bool,
+    /// Require values to be pushed, and numbers to be encoded, in their minimal form.
+    pub minimal_data: bool,
+    /// Require the extra stack element consumed by `OP_CHECKMULTISIG` to be empty.
+    pub null_dummy: bool,
+    /// Require exactly one element to remain on the stack after evaluation.
+    ///
+    /// This has no effect unless [`Self::p2sh`] is also set.
+    pub clean_stack: bool,
+    /// Fail on the use of the no-op opcodes that are reserved for soft-fork upgrades.
+    pub discourage_upgradable_nops: bool,
+}
+
+impl VerificationFlags {
+    /// The rules enforced by consensus.
+    pub const CONSENSUS: Self = VerificationFlags {
+        p2sh: true,
+        check_lock_time_verify: true,
+        der_sig: true,
+        strict_encoding: false,
+        low_s: false,
+        minimal_data: false,
+        null_dummy: false,
+        clean_stack: false,
+        discourage_upgradable_nops: false,
+    };
+
+    /// The rules enforced by `zcashd` for transactions that it relays.
+    pub const STANDARD: Self = VerificationFlags {
+        p2sh: true,
+        check_lock_time_verify: true,
+        der_sig: true,
+        strict_encoding: true,
+        low_s: true,
+        minimal_data: true,
+        null_dummy: true,
+        clean_stack: true,
+        discourage_upgradable_nops: true,
+    };
+}
+
+/// Errors that can occur when evaluating a script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    /// The script evaluated to false.
+    EvalFalse,
+    /// `OP_RETURN` was executed.
+    OpReturn,
+    /// The script is larger than the maximum script size.
+    ScriptSize,
+    /// A pushed value is larger than the maximum element size.
+    PushSize,
+    /// The script contains too many non-push operations.
+    OpCount,
+    /// The stack grew larger than the maximum stack size.
+    StackSize,
+    /// The signature count for `OP_CHECKMULTISIG` is out of range.
+    SigCount,
+    /// The public key count for `OP_CHECKMULTISIG` is out of range.
+    PubKeyCount,
+    /// `OP_VERIFY` failed.
+    Verify,
+    /// `OP_EQUALVERIFY` failed.
+    EqualVerify,
+    /// `OP_CHECKSIGVERIFY` failed.
+    CheckSigVerify,
+    /// `OP_CHECKMULTISIGVERIFY` failed.
+    CheckMultisigVerify,
+    /// `OP_NUMEQUALVERIFY` failed.
+    NumEqualVerify,
+    /// The script contains an invalid opcode or a truncated push.
+    BadOpcode,
+    /// The script contains a disabled opcode.
+    DisabledOpcode,
+    /// An operation required more elements than are on the stack.
+    InvalidStackOperation,
+    /// An operation required more elements than are on the alternate stack.
+    InvalidAltstackOperation,
+    /// The script contains an `OP_IF`, `OP_NOTIF`, `OP_ELSE` or `OP_ENDIF` without its
+    /// counterpart.
+    UnbalancedConditional,
+    /// A numeric operand is too large, or is not minimally encoded.
+    InvalidNumber,
+    /// The lock time checked by `OP_CHECKLOCKTIMEVERIFY` is negative.
+    NegativeLockTime,
+    /// The lock time checked by `OP_CHECKLOCKTIMEVERIFY` has not been reached.
+    UnsatisfiedLockTime,
+    /// A signature has an undefined hash type.
+    SigHashType,
+    /// A signature is not strictly DER-encoded.
+    SigDer,
+    /// A value was not pushed in its minimal form.
+    MinimalData,
+    /// The `scriptSig` of a P2SH spend contains operations other than pushes.
+    SigPushOnly,
+    /// A signature has an S value in the upper half of the curve order.
+    SigHighS,
+    /// The extra stack element consumed by `OP_CHECKMULTISIG` is not empty.
+    SigNullDummy,
+    /// A public key is not in compressed or uncompressed SEC1 form.
+    PubKeyType,
+    /// More than one element remained on the stack after evaluation.
+    CleanStack,
+    /// A no-op opcode reserved for soft-fork upgrades was executed.
+    DiscourageUpgradableNops,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ScriptError::EvalFalse => {
+                "Script evaluated without error but finished with a false/empty top stack element"
+            }
+            ScriptError::OpReturn => "OP_RETURN was encountered",
+            ScriptError::ScriptSize => "Script is too big",
+            ScriptError::PushSize => "Push value size limit exceeded",
+            ScriptError::OpCount => "Operation limit exceeded",
+            ScriptError::StackSize => "Stack size limit exceeded",
+            ScriptError::SigCount => "Signature count negative or greater than pubkey count",
+            ScriptError::PubKeyCount => "Pubkey count negative or limit exceeded",
+            ScriptError::Verify => "Script failed an OP_VERIFY operation",
+            ScriptError::EqualVerify => "Script failed an OP_EQUALVERIFY operation",
+            ScriptError::CheckSigVerify => "Script failed an OP_CHECKSIGVERIFY operation",
+            ScriptError::CheckMultisigVerify => "Script failed an OP_CHECKMULTISIGVERIFY operation",
+            ScriptError::NumEqualVerify => "Script failed an OP_NUMEQUALVERIFY operation",
+            ScriptError::BadOpcode => "Opcode missing or not understood",
+            ScriptError::DisabledOpcode => "Attempted to use a disabled opcode",
+            ScriptError::InvalidStackOperation => "Operation not valid with the current stack size",
+            ScriptError::InvalidAltstackOperation => {
+                "Operation not valid with the current altstack size"
+            }
+            ScriptError::UnbalancedConditional => "Invalid OP_IF construction",
+            ScriptError::InvalidNumber => {
+                "Numeric operand is out of range or not minimally encoded"
+            }
+            ScriptError::NegativeLockTime => "Negative locktime",
+            ScriptError::UnsatisfiedLockTime => "Locktime requirement not satisfied",
+            ScriptError::SigHashType => "Signature hash type missing or not understood",
+            ScriptError::SigDer => "Non-canonical DER signature",
+            ScriptError::MinimalData => "Data push larger than necessary",
+            ScriptError::SigPushOnly => "Only non-push operators allowed in signatures",
+            ScriptError::SigHighS => "Non-canonical signature: S value is unnecessarily high",
+            ScriptError::SigNullDummy => "Dummy CHECKMULTISIG argument must be zero",
+            ScriptError::PubKeyType => "Public key is neither compressed or uncompressed",
+            ScriptError::CleanStack => "Extra items left on stack after execution",
+            ScriptError::DiscourageUpgradableNops => "NOPx reserved for soft-fork upgrades",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// The operations of script evaluation that depend on the transaction being validated.
+pub trait SignatureChecker {
+    /// Returns `true` if `sig` is a valid signature by `pubkey` for the input being
+    /// validated.
+    ///
+    /// `sig` is the signature as it appears in the script, including the trailing hash
+    /// type byte. `script_code` is the script that is being executed, which is committed
+    /// to by the signature.
+    fn check_sig(&self, sig: &[u8], pubkey: &[u8], script_code: &Script) -> bool;
+
+    /// Returns `true` if the lock time of the input being validated satisfies the given
+    /// `OP_CHECKLOCKTIMEVERIFY` lock time.
+    fn check_lock_time(&self, lock_time: i64) -> bool;
+}
+
+/// A [`SignatureChecker`] for an input of a transaction.
+pub struct TransactionSignatureChecker<'a, A: Authorization> {
+    tx: &'a TransactionData<A>,
+    txid_parts: &'a TxDigests<Blake2bHash>,
+    index: usize,
+    spent_output: &'a TxOut,
+    secp: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+}
+
+impl<'a, A: Authorization> TransactionSignatureChecker<'a, A> {
+    /// Constructs a checker for the transparent input at `index` of the given transaction,
+    /// which spends `spent_output`.
+    ///
+    /// `txid_parts` must be the digests of `tx` produced by
+    /// [`crate::transaction::txid::TxIdDigester`].
+    pub fn new(
+        tx: &'a TransactionData<A>,
+        txid_parts: &'a TxDigests<Blake2bHash>,
+        index: usize,
+        spent_output: &'a TxOut,
+    ) -> Self {
+        TransactionSignatureChecker {
+            tx,
+            txid_parts,
+            index,
+            spent_output,
+            secp: secp256k1::Secp256k1::verification_only(),
+        }
+    }
+}
+
+impl<'a, TA, SA, A> SignatureChecker for TransactionSignatureChecker<'a, A>
+where
+    TA: TransparentAuthorizingContext,
+    SA: sapling::bundle::Authorization<
+        SpendProof = sapling::bundle::GrothProofBytes,
+        OutputProof = sapling::bundle::GrothProofBytes,
+    >,
+    A: Authorization<TransparentAuth = TA, SaplingAuth = SA>,
+{
+    fn check_sig(&self, sig: &[u8], pubkey: &[u8], script_code: &Script) -> bool {
+        let (hash_type, sig) = match sig.split_last() {
+            Some((hash_type, sig)) => (*hash_type, sig),
+            None => return false,
+        };
+
+        // ZIP 244 transactions must only use defined hash types.
+        let is_v5 = match self.tx.version() {
+            TxVersion::Sprout(_) | TxVersion::Overwinter | TxVersion::Sapling => false,
+            TxVersion::Zip225 => true,
+            #[cfg(zcash_unstable = "zfuture")]
+            TxVersion::ZFuture => true,
+        };
+        if is_v5 && !is_defined_hash_type(hash_type) {
+            return false;
+        }
+
+        let pubkey = match secp256k1::PublicKey::from_slice(pubkey) {
+            Ok(pubkey) => pubkey,
+            Err(_) => return false,
+        };
+        // Signatures that are not strictly DER-encoded have already been rejected if
+        // required by the verification flags, so we parse them leniently here as consensus
+        // did before BIP 66. Consensus also accepts signatures with high S values, so we
+        // normalize them before verification.
+        let mut sig = match secp256k1::ecdsa::Signature::from_der_lax(sig) {
+            Ok(sig) => sig,
+            Err(_) => return false,
+        };
+        sig.normalize_s();
+
+        let sighash = signature_hash(
+            self.tx,
+            &SignableInput::Transparent {
+                hash_type,
+                index: self.index,
+                script_code,
+                script_pubkey: &self.spent_output.script_pubkey,
+                value: self.spent_output.value,
+            },
+            self.txid_parts,
+        );
+        let msg = secp256k1::Message::from_slice(sighash.as_ref()).expect("32 bytes");
+
+        self.secp.verify_ecdsa(&msg, &sig, &pubkey).is_ok()
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = i64::from(self.tx.lock_time());
+
+        // The lock times must both be heights, or both be timestamps.
+        if (tx_lock_time < LOCKTIME_THRESHOLD) != (lock_time < LOCKTIME_THRESHOLD) {
+            return false;
+        }
+        if lock_time > tx_lock_time {
+            return false;
+        }
+
+        // The transaction's lock time is ignored if the input is final, so we must not
+        // allow it to satisfy the script's lock time.
+        self.tx
+            .transparent_bundle()
+            .and_then(|b| b.vin.get(self.index))
+            .map_or(false, |txin| txin.sequence != u32::MAX)
+    }
+}
+
+/// Verifies that `script_sig` satisfies `script_pubkey` under the given rules.
+pub fn verify_script<C: SignatureChecker>(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    flags: VerificationFlags,
+    checker: &C,
+) -> Result<(), ScriptError> {
+    let mut stack = vec![];
+    eval_script(&mut stack, script_sig, flags, checker)?;
+    let stack_copy = if flags.p2sh { stack.clone() } else { vec![] };
+    eval_script(&mut stack, script_pubkey, flags, checker)?;
+    if !stack.last().map_or(false, |v| cast_to_bool(v)) {
+        return Err(ScriptError::EvalFalse);
+    }
+
+    if flags.p2sh && is_p2sh(script_pubkey) {
+        // The scriptSig of a P2SH spend must only push data, so that the redeem script
+        // cannot be modified by the spender.
+        if !is_push_only(script_sig) {
+            return Err(ScriptError::SigPushOnly);
+        }
+
+        // The scriptSig was evaluated successfully and is push-only, so it pushed at least
+        // the redeem script.
+        stack = stack_copy;
+        let redeem_script = Script(stack.pop().expect("stack is not empty"));
+        eval_script(&mut stack, &redeem_script, flags, checker)?;
+        if !stack.last().map_or(false, |v| cast_to_bool(v)) {
+            return Err(ScriptError::EvalFalse);
+        }
+    }
+
+    if flags.clean_stack && flags.p2sh && stack.len() != 1 {
+        return Err(ScriptError::CleanStack);
+    }
+
+    Ok(())
+}
+
+/// Errors that can occur when verifying the transparent inputs of a transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The number of spent outputs provided does not match the number of transparent
+    /// inputs of the transaction.
+    SpentOutputsMismatch { inputs: usize, spent_outputs: usize },
+    /// The script of the transparent input at the given index failed to verify.
+    Script { index: usize, error: ScriptError },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::SpentOutputsMismatch {
+                inputs,
+                spent_outputs,
+            } => write!(
+                f,
+                "Transaction has {} transparent inputs, but {} spent outputs were provided",
+                inputs, spent_outputs
+            ),
+            Error::Script { index, error } => {
+                write!(f, "Transparent input {} is invalid: {}", index, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Verifies the scripts of all transparent inputs of the given transaction.
+///
+/// `spent_outputs` must contain the outputs spent by the transaction's transparent inputs,
+/// in the same order as the inputs. The inputs of coinbase transactions do not spend any
+/// outputs, and are not verified.
+pub fn verify_transparent_inputs(
+    tx: &Transaction,
+    spent_outputs: &[TxOut],
+    flags: VerificationFlags,
+) -> Result<(), Error> {
+    let bundle = match tx.transparent_bundle() {
+        Some(bundle) if !bundle.is_coinbase() => bundle,
+        _ => return Ok(()),
+    };
+    if bundle.vin.len() != spent_outputs.len() {
+        return Err(Error::SpentOutputsMismatch {
+            inputs: bundle.vin.len(),
+            spent_outputs: spent_outputs.len(),
+        });
+    }
+
+    let tx_data = with_spent_outputs(tx, spent_outputs);
+    let txid_parts = tx_data.digest(TxIdDigester);
+    for (index, (txin, spent_output)) in bundle.vin.iter().zip(spent_outputs).enumerate() {
+        let checker = TransactionSignatureChecker::new(&tx_data, &txid_parts, index, spent_output);
+        verify_script(
+            &txin.script_sig,
+            &spent_output.script_pubkey,
+            flags,
+            &checker,
+        )
+        .map_err(|error| Error::Script { index, error })?;
+    }
+
+    Ok(())
+}
+
+/// The authorization of a transparent bundle, along with the outputs that it spends.
+#[derive(Debug)]
+pub(crate) struct SpentOutputs(Vec<TxOut>);
+
+impl transparent::Authorization for SpentOutputs {
+    type ScriptSig = Script;
+}
+
+impl TransparentAuthorizingContext for SpentOutputs {
+    fn input_amounts(&self) -> Vec<NonNegativeAmount> {
+        self.0.iter().map(|output| output.value).collect()
+    }
+
+    fn input_scriptpubkeys(&self) -> Vec<Script> {
+        self.0
+            .iter()
+            .map(|output| output.script_pubkey.clone())
+            .collect()
+    }
+}
+
+/// An authorized transaction whose transparent inputs' spent outputs are known, which is
+/// sufficient to compute the signature hashes of its transparent inputs.
+#[derive(Debug)]
+pub(crate) struct WithSpentOutputs;
+
+impl Authorization for WithSpentOutputs {
+    type TransparentAuth = SpentOutputs;
+    type SaplingAuth = sapling::bundle::Authorized;
+    type OrchardAuth = orchard::bundle::Authorized;
+
+    #[cfg(zcash_unstable = "zfuture")]
+    type TzeAuth = tze::Authorized;
+}
+
+pub(crate) fn with_spent_outputs(
+    tx: &Transaction,
+    spent_outputs: &[TxOut],
+) -> TransactionData<WithSpentOutputs> {
+    let transparent_bundle = tx.transparent_bundle().map(|b| transparent::Bundle {
+        vin: b
+            .vin
+            .iter()
+            .map(|txin| TxIn {
+                prevout: txin.prevout.clone(),
+                script_sig: txin.script_sig.clone(),
+                sequence: txin.sequence,
+            })
+            .collect(),
+        vout: b.vout.clone(),
+        authorization: SpentOutputs(spent_outputs.to_vec()),
+    });
+
+    #[cfg(not(zcash_unstable = "zfuture"))]
+    return TransactionData::from_parts(
+        tx.version(),
+        tx.consensus_branch_id(),
+        tx.lock_time(),
+        tx.expiry_height(),
+        transparent_bundle,
+        tx.sprout_bundle().cloned(),
+        tx.sapling_bundle().cloned(),
+        tx.orchard_bundle().cloned(),
+    );
+
+    #[cfg(zcash_unstable = "zfuture")]
+    return TransactionData::from_parts_zfuture(
+        tx.version(),
+        tx.consensus_branch_id(),
+        tx.lock_time(),
+        tx.expiry_height(),
+        transparent_bundle,
+        tx.sprout_bundle().cloned(),
+        tx.sapling_bundle().cloned(),
+        tx.orchard_bundle().cloned(),
+        tx.tze_bundle().cloned(),
+    );
+}
+
+fn is_defined_hash_type(hash_type: u8) -> bool {
+    (SIGHASH_ALL..=SIGHASH_SINGLE).contains(&(hash_type & !SIGHASH_ANYONECANPAY))
+}
+
+/// Returns `true` if the given script is a P2SH `scriptPubKey`.
+fn is_p2sh(script: &Script) -> bool {
+    script.0.len() == 23
+        && script.0[0] == OpCode::Hash160 as u8
+        && script.0[1] == 0x14
+        && script.0[22] == OpCode::Equal as u8
+}
+
+/// Returns `true` if the given script only pushes data onto the stack.
+fn is_push_only(script: &Script) -> bool {
+    let mut pc = 0;
+    while pc < script.0.len() {
+        match next_op(&script.0, &mut pc) {
+            Ok((opcode, _)) if opcode <= OpCode::Op16 as u8 => (),
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Reads the opcode at `pc` and the data it pushes (if any), and advances `pc` past them.
+fn next_op<'s>(script: &'s [u8], pc: &mut usize) -> Result<(u8, &'s [u8]), ScriptError> {
+    let opcode = script[*pc];
+    *pc += 1;
+
+    let mut read_len = |size: usize| -> Result<usize, ScriptError> {
+        let bytes = script.get(*pc..*pc + size).ok_or(ScriptError::BadOpcode)?;
+        *pc += size;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0usize, |acc, b| (acc << 8) | usize::from(*b)))
+    };
+    let len = match opcode {
+        n if n < OpCode::PushData1 as u8 => usize::from(n),
+        n if n == OpCode::PushData1 as u8 => read_len(1)?,
+        n if n == OpCode::PushData2 as u8 => read_len(2)?,
+        n if n == OpCode::PushData4 as u8 => read_len(4)?,
+        _ => return Ok((opcode, &[])),
+    };
+
+    let data = script
+        .get(*pc..pc.saturating_add(len))
+        .ok_or(ScriptError::BadOpcode)?;
+    *pc += len;
+    Ok((opcode, data))
+}
+
+/// Returns `true` if `data` was pushed with the smallest possible push operation.
+fn is_minimal_push(opcode: u8, data: &[u8]) -> bool {
+    match data {
+        [] => opcode == OpCode::Op0 as u8,
+        // Should have used OP_1 .. OP_16.
+        [1..=16] => false,
+        // Should have used OP_1NEGATE.
+        [0x81] => false,
+        _ if data.len() < OpCode::PushData1 as usize => usize::from(opcode) == data.len(),
+        _ if data.len() <= 0xff => opcode == OpCode::PushData1 as u8,
+        _ if data.len() <= 0xffff => opcode == OpCode::PushData2 as u8,
+        _ => true,
+    }
+}
+
+fn cast_to_bool(value: &[u8]) -> bool {
+    match value.split_last() {
+        // Negative zero is false.
+        Some((last, rest)) => rest.iter().any(|b| *b != 0) || (*last & 0x7f) != 0,
+        None => false,
+    }
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        vec![]
+    }
+}
+
+/// Decodes a number from its little-endian sign-magnitude stack encoding.
+fn decode_num(bytes: &[u8], require_minimal: bool, max_len: usize) -> Result<i64, ScriptError> {
+    if bytes.len() > max_len {
+        return Err(ScriptError::InvalidNumber);
+    }
+
+    let (last, rest) = match bytes.split_last() {
+        Some(split) => split,
+        None => return Ok(0),
+    };
+    // The most significant byte must contain something other than the sign bit, unless
+    // the sign bit would otherwise collide with the magnitude.
+    if require_minimal && last & 0x7f == 0 && rest.last().map_or(true, |b| b & 0x80 == 0) {
+        return Err(ScriptError::InvalidNumber);
+    }
+
+    let magnitude = bytes
+        .iter()
+        .rev()
+        .fold(0i64, |acc, b| (acc << 8) | i64::from(*b))
+        & !(0x80 << (8 * rest.len()));
+    Ok(if last & 0x80 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    })
+}
+
+/// Encodes a number in its minimal little-endian sign-magnitude stack encoding.
+fn encode_num(value: i64) -> Vec<u8> {
+    let mut magnitude = value.unsigned_abs();
+    let mut bytes = vec![];
+    while magnitude > 0 {
+        bytes.push((magnitude & 0xff) as u8);
+        magnitude >>= 8;
+    }
+
+    if let Some(last) = bytes.last_mut() {
+        if *last & 0x80 != 0 {
+            bytes.push(if value < 0 { 0x80 } else { 0 });
+        } else if value < 0 {
+            *last |= 0x80;
+        }
+    }
+    bytes
+}
+
+fn is_valid_pubkey_encoding(pubkey: &[u8]) -> bool {
+    match pubkey {
+        [0x02 | 0x03, rest @ ..] => rest.len() == 32,
+        [0x04, rest @ ..] => rest.len() == 64,
+        _ => false,
+    }
+}
+
+/// Returns `true` if `sig` (excluding the hash type) is a strictly DER-encoded signature,
+/// as specified by [BIP 66].
+///
+/// [BIP 66]: https://github.com/bitcoin/bips/blob/master/bip-0066.mediawiki
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    // Format: 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [hash-type]
+    if sig.len() < 9 || sig.len() > 73 || sig[0] != 0x30 || usize::from(sig[1]) != sig.len() - 3 {
+        return false;
+    }
+
+    let len_r = usize::from(sig[3]);
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = usize::from(sig[5 + len_r]);
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+
+    // R and S must be non-empty, non-negative integers without unnecessary leading zeroes.
+    let valid_integer = |marker: u8, bytes: &[u8]| {
+        marker == 0x02
+            && !bytes.is_empty()
+            && bytes[0] & 0x80 == 0
+            && !(bytes.len() > 1 && bytes[0] == 0 && bytes[1] & 0x80 == 0)
+    };
+    valid_integer(sig[2], &sig[4..4 + len_r])
+        && valid_integer(sig[4 + len_r], &sig[6 + len_r..6 + len_r + len_s])
+}
+
+fn check_signature_encoding(sig: &[u8], flags: VerificationFlags) -> Result<(), ScriptError> {
+    // An empty signature is not strictly DER-encoded, but is allowed as a compact way to
+    // provide an invalid signature for use with OP_CHECKSIG and OP_CHECKMULTISIG.
+    if sig.is_empty() {
+        return Ok(());
+    }
+    if (flags.der_sig || flags.strict_encoding || flags.low_s) && !is_valid_signature_encoding(sig)
+    {
+        return Err(ScriptError::SigDer);
+    }
+    if flags.low_s {
+        let mut normalized = secp256k1::ecdsa::Signature::from_der_lax(&sig[..sig.len() - 1])
+            .map_err(|_| ScriptError::SigDer)?;
+        let original = normalized;
+        normalized.normalize_s();
+        if normalized != original {
+            return Err(ScriptError::SigHighS);
+        }
+    }
+    if flags.strict_encoding && !is_defined_hash_type(sig[sig.len() - 1]) {
+        return Err(ScriptError::SigHashType);
+    }
+    Ok(())
+}
+
+fn check_pubkey_encoding(pubkey: &[u8], flags: VerificationFlags) -> Result<(), ScriptError> {
+    if flags.strict_encoding && !is_valid_pubkey_encoding(pubkey) {
+        Err(ScriptError::PubKeyType)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the element at `depth` from the top of the stack, where the top element has a
+/// depth of 1.
+fn stack_top(stack: &[Vec<u8>], depth: usize) -> Result<&Vec<u8>, ScriptError> {
+    stack
+        .len()
+        .checked_sub(depth)
+        .map(|i| &stack[i])
+        .ok_or(ScriptError::InvalidStackOperation)
+}
+
+fn require_stack(stack: &[Vec<u8>], len: usize) -> Result<(), ScriptError> {
+    if stack.len() < len {
+        Err(ScriptError::InvalidStackOperation)
+    } else {
+        Ok(())
+    }
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::InvalidStackOperation)
+}
+
+/// Evaluates `script`, using and modifying the given stack.
+fn eval_script<C: SignatureChecker>(
+    stack: &mut Vec<Vec<u8>>,
+    script: &Script,
+    flags: VerificationFlags,
+    checker: &C,
+) -> Result<(), ScriptError> {
+    if script.0.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptSize);
+    }
+
+    let require_minimal = flags.minimal_data;
+    let num = |bytes: &[u8]| decode_num(bytes, require_minimal, 4);
+
+    let mut pc = 0;
+    let mut op_count = 0;
+    let mut exec_stack: Vec<bool> = vec![];
+    let mut alt_stack: Vec<Vec<u8>> = vec![];
+
+    while pc < script.0.len() {
+        let executing = exec_stack.iter().all(|b| *b);
+
+        let (opcode, data) = next_op(&script.0, &mut pc)?;
+        if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(ScriptError::PushSize);
+        }
+
+        // Every non-push opcode counts towards the limit, whether or not it is executed.
+        if opcode > OpCode::Op16 as u8 {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+
+        let op = OpCode::parse(opcode);
+
+        // Disabled opcodes fail the script even if they are not executed.
+        if matches!(
+            op,
+            Some(
+                OpCode::Cat
+                    | OpCode::Substr
+                    | OpCode::Left
+                    | OpCode::Right
+                    | OpCode::Invert
+                    | OpCode::And
+                    | OpCode::Or
+                    | OpCode::Xor
+                    | OpCode::Mul2
+                    | OpCode::Div2
+                    | OpCode::Mul
+                    | OpCode::Div
+                    | OpCode::Mod
+                    | OpCode::LShift
+                    | OpCode::RShift
+                    | OpCode::CodeSeparator
+            )
+        ) {
+            return Err(ScriptError::DisabledOpcode);
+        }
+
+        if opcode <= OpCode::PushData4 as u8 {
+            if executing {
+                if require_minimal && !is_minimal_push(opcode, data) {
+                    return Err(ScriptError::MinimalData);
+                }
+                stack.push(data.to_vec());
+            }
+        } else if executing || (OpCode::If as u8..=OpCode::EndIf as u8).contains(&opcode) {
+            match op {
+                //
+                // Push value
+                //
+                Some(
+                    OpCode::Negative1
+                    | OpCode::Op1
+                    | OpCode::Op2
+                    | OpCode::Op3
+                    | OpCode::Op4
+                    | OpCode::Op5
+                    | OpCode::Op6
+                    | OpCode::Op7
+                    | OpCode::Op8
+                    | OpCode::Op9
+                    | OpCode::Op10
+                    | OpCode::Op11
+                    | OpCode::Op12
+                    | OpCode::Op13
+                    | OpCode::Op14
+                    | OpCode::Op15
+                    | OpCode::Op16,
+                ) => {
+                    stack.push(encode_num(i64::from(opcode) - OpCode::Reserved as i64));
+                }
+
+                //
+                // Control
+                //
+                Some(OpCode::Nop) => (),
+                Some(OpCode::CheckLockTimeVerify) if flags.check_lock_time_verify => {
+                    // Lock times are up to 5 bytes, because they can exceed 2^31.
+                    let lock_time = decode_num(stack_top(stack, 1)?, require_minimal, 5)?;
+                    if lock_time < 0 {
+                        return Err(ScriptError::NegativeLockTime);
+                    }
+                    if !checker.check_lock_time(lock_time) {
+                        return Err(ScriptError::UnsatisfiedLockTime);
+                    }
+                }
+                Some(
+                    OpCode::Nop1
+                    | OpCode::CheckLockTimeVerify
+                    | OpCode::Nop3
+                    | OpCode::Nop4
+                    | OpCode::Nop5
+                    | OpCode::Nop6
+                    | OpCode::Nop7
+                    | OpCode::Nop8
+                    | OpCode::Nop9
+                    | OpCode::Nop10,
+                ) => {
+                    if flags.discourage_upgradable_nops {
+                        return Err(ScriptError::DiscourageUpgradableNops);
+                    }
+                }
+                Some(OpCode::If | OpCode::NotIf) => {
+                    let mut value = false;
+                    if executing {
+                        let top = pop(stack).map_err(|_| ScriptError::UnbalancedConditional)?;
+                        value = cast_to_bool(&top);
+                        if opcode == OpCode::NotIf as u8 {
+                            value = !value;
+                        }
+                    }
+                    exec_stack.push(value);
+                }
+                Some(OpCode::Else) => {
+                    let last = exec_stack
+                        .last_mut()
+                        .ok_or(ScriptError::UnbalancedConditional)?;
+                    *last = !*last;
+                }
+                Some(OpCode::EndIf) => {
+                    exec_stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                }
+                Some(OpCode::Verify) => {
+                    if cast_to_bool(stack_top(stack, 1)?) {
+                        stack.pop();
+                    } else {
+                        return Err(ScriptError::Verify);
+                    }
+                }
+                Some(OpCode::Return) => return Err(ScriptError::OpReturn),
+
+                //
+                // Stack ops
+                //
+                Some(OpCode::ToAltStack) => {
+                    let value = pop(stack)?;
+                    alt_stack.push(value);
+                }
+                Some(OpCode::FromAltStack) => {
+                    let value = alt_stack
+                        .pop()
+                        .ok_or(ScriptError::InvalidAltstackOperation)?;
+                    stack.push(value);
+                }
+                Some(OpCode::Drop2) => {
+                    require_stack(stack, 2)?;
+                    stack.truncate(stack.len() - 2);
+                }
+                Some(OpCode::Dup2) => {
+                    require_stack(stack, 2)?;
+                    stack.extend_from_within(stack.len() - 2..);
+                }
+                Some(OpCode::Dup3) => {
+                    require_stack(stack, 3)?;
+                    stack.extend_from_within(stack.len() - 3..);
+                }
+                Some(OpCode::Over2) => {
+                    require_stack(stack, 4)?;
+                    stack.extend_from_within(stack.len() - 4..stack.len() - 2);
+                }
+                Some(OpCode::Rot2) => {
+                    require_stack(stack, 6)?;
+                    let start = stack.len() - 6;
+                    let moved = stack.drain(start..start + 2).collect::<Vec<_>>();
+                    stack.extend(moved);
+                }
+                Some(OpCode::Swap2) => {
+                    require_stack(stack, 4)?;
+                    let len = stack.len();
+                    stack.swap(len - 4, len - 2);
+                    stack.swap(len - 3, len - 1);
+                }
+                Some(OpCode::IfDup) => {
+                    let top = stack_top(stack, 1)?;
+                    if cast_to_bool(top) {
+                        stack.push(top.clone());
+                    }
+                }
+                Some(OpCode::Depth) => {
+                    stack.push(encode_num(stack.len() as i64));
+                }
+                Some(OpCode::Drop) => {
+                    pop(stack)?;
+                }
+                Some(OpCode::Dup) => {
+                    let top = stack_top(stack, 1)?.clone();
+                    stack.push(top);
+                }
+                Some(OpCode::Nip) => {
+                    require_stack(stack, 2)?;
+                    stack.remove(stack.len() - 2);
+                }
+                Some(OpCode::Over) => {
+                    let value = stack_top(stack, 2)?.clone();
+                    stack.push(value);
+                }
+                Some(OpCode::Pick | OpCode::Roll) => {
+                    require_stack(stack, 2)?;
+                    let n = num(&pop(stack)?)?;
+                    if n < 0 || n as usize >= stack.len() {
+                        return Err(ScriptError::InvalidStackOperation);
+                    }
+                    let i = stack.len() - 1 - n as usize;
+                    let value = if opcode == OpCode::Roll as u8 {
+                        stack.remove(i)
+                    } else {
+                        stack[i].clone()
+                    };
+                    stack.push(value);
+                }
+                Some(OpCode::Rot) => {
+                    require_stack(stack, 3)?;
+                    let value = stack.remove(stack.len() - 3);
+                    stack.push(value);
+                }
+                Some(OpCode::Swap) => {
+                    require_stack(stack, 2)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                }
+                Some(OpCode::Tuck) => {
+                    require_stack(stack, 2)?;
+                    let top = stack[stack.len() - 1].clone();
+                    stack.insert(stack.len() - 2, top);
+                }
+
+                //
+                // Splice ops
+                //
+                Some(OpCode::Size) => {
+                    let size = stack_top(stack, 1)?.len();
+                    stack.push(encode_num(size as i64));
+                }
+
+                //
+                // Bitwise logic
+                //
+                Some(OpCode::Equal | OpCode::EqualVerify) => {
+                    require_stack(stack, 2)?;
+                    let equal = pop(stack)? == pop(stack)?;
+                    if opcode == OpCode::EqualVerify as u8 {
+                        if !equal {
+                            return Err(ScriptError::EqualVerify);
+                        }
+                    } else {
+                        stack.push(encode_bool(equal));
+                    }
+                }
+
+                //
+                // Numeric
+                //
+                Some(
+                    OpCode::Add1
+                    | OpCode::Sub1
+                    | OpCode::Negate
+                    | OpCode::Abs
+                    | OpCode::Not
+                    | OpCode::NotEqual0,
+                ) => {
+                    let n = num(stack_top(stack, 1)?)?;
+                    let result = match op {
+                        Some(OpCode::Add1) => n + 1,
+                        Some(OpCode::Sub1) => n - 1,
+                        Some(OpCode::Negate) => -n,
+                        Some(OpCode::Abs) => n.abs(),
+                        Some(OpCode::Not) => i64::from(n == 0),
+                        _ => i64::from(n != 0),
+                    };
+                    stack.pop();
+                    stack.push(encode_num(result));
+                }
+                Some(
+                    OpCode::Add
+                    | OpCode::Sub
+                    | OpCode::BoolAnd
+                    | OpCode::BoolOr
+                    | OpCode::NumEqual
+                    | OpCode::NumEqualVerify
+                    | OpCode::NumNotEqual
+                    | OpCode::LessThan
+                    | OpCode::GreaterThan
+                    | OpCode::LessThanOrEqual
+                    | OpCode::GreaterThanOrEqual
+                    | OpCode::Min
+                    | OpCode::Max,
+                ) => {
+                    let a = num(stack_top(stack, 2)?)?;
+                    let b = num(stack_top(stack, 1)?)?;
+                    let result = match op {
+                        Some(OpCode::Add) => a + b,
+                        Some(OpCode::Sub) => a - b,
+                        Some(OpCode::BoolAnd) => i64::from(a != 0 && b != 0),
+                        Some(OpCode::BoolOr) => i64::from(a != 0 || b != 0),
+                        Some(OpCode::NumEqual | OpCode::NumEqualVerify) => i64::from(a == b),
+                        Some(OpCode::NumNotEqual) => i64::from(a != b),
+                        Some(OpCode::LessThan) => i64::from(a < b),
+                        Some(OpCode::GreaterThan) => i64::from(a > b),
+                        Some(OpCode::LessThanOrEqual) => i64::from(a <= b),
+                        Some(OpCode::GreaterThanOrEqual) => i64::from(a >= b),
+                        Some(OpCode::Min) => a.min(b),
+                        _ => a.max(b),
+                    };
+                    stack.truncate(stack.len() - 2);
+                    if opcode == OpCode::NumEqualVerify as u8 {
+                        if result == 0 {
+                            return Err(ScriptError::NumEqualVerify);
+                        }
+                    } else {
+                        stack.push(encode_num(result));
+                    }
+                }
+                Some(OpCode::Within) => {
+                    let x = num(stack_top(stack, 3)?)?;
+                    let min = num(stack_top(stack, 2)?)?;
+                    let max = num(stack_top(stack, 1)?)?;
+                    stack.truncate(stack.len() - 3);
+                    stack.push(encode_bool(min <= x && x < max));
+                }
+
+                //
+                // Crypto
+                //
+                Some(
+                    OpCode::Ripemd160
+                    | OpCode::Sha1
+                    | OpCode::Sha256
+                    | OpCode::Hash160
+                    | OpCode::Hash256,
+                ) => {
+                    let value = pop(stack)?;
+                    let hash = match op {
+                        Some(OpCode::Ripemd160) => Ripemd160::digest(&value).to_vec(),
+                        Some(OpCode::Sha1) => Sha1::digest(&value).to_vec(),
+                        Some(OpCode::Sha256) => Sha256::digest(&value).to_vec(),
+                        Some(OpCode::Hash160) => Ripemd160::digest(Sha256::digest(&value)).to_vec(),
+                        _ => Sha256::digest(Sha256::digest(&value)).to_vec(),
+                    };
+                    stack.push(hash);
+                }
+                Some(OpCode::CheckSig | OpCode::CheckSigVerify) => {
+                    require_stack(stack, 2)?;
+                    let pubkey = pop(stack)?;
+                    let sig = pop(stack)?;
+                    check_signature_encoding(&sig, flags)?;
+                    check_pubkey_encoding(&pubkey, flags)?;
+
+                    let success = checker.check_sig(&sig, &pubkey, script);
+                    if opcode == OpCode::CheckSigVerify as u8 {
+                        if !success {
+                            return Err(ScriptError::CheckSigVerify);
+                        }
+                    } else {
+                        stack.push(encode_bool(success));
+                    }
+                }
+                Some(OpCode::CheckMultisig | OpCode::CheckMultisigVerify) => {
+                    // ([dummy] [sig ...] num_of_signatures [pubkey ...] num_of_pubkeys -- bool)
+                    let keys_count = num(stack_top(stack, 1)?)?;
+                    if keys_count < 0 || keys_count as usize > MAX_PUBKEYS_PER_MULTISIG {
+                        return Err(ScriptError::PubKeyCount);
+                    }
+                    let keys_count = keys_count as usize;
+                    op_count += keys_count;
+                    if op_count > MAX_OPS_PER_SCRIPT {
+                        return Err(ScriptError::OpCount);
+                    }
+
+                    let sigs_count = num(stack_top(stack, keys_count + 2)?)?;
+                    if sigs_count < 0 || sigs_count as usize > keys_count {
+                        return Err(ScriptError::SigCount);
+                    }
+                    let sigs_count = sigs_count as usize;
+                    // Include the dummy element.
+                    require_stack(stack, keys_count + sigs_count + 3)?;
+
+                    let len = stack.len();
+                    let pubkeys = &stack[len - 1 - keys_count..len - 1];
+                    let sigs = &stack[len - 2 - keys_count - sigs_count..len - 2 - keys_count];
+
+                    // Signatures must be in the same order as their public keys. Both are
+                    // consumed from the top of the stack, which holds the last of each.
+                    let mut pubkeys = pubkeys.iter().rev().peekable();
+                    let mut sigs = sigs.iter().rev().peekable();
+                    let mut success = true;
+                    while let Some(sig) = sigs.peek() {
+                        let pubkey = match pubkeys.next() {
+                            Some(pubkey) => pubkey,
+                            None => {
+                                success = false;
+                                break;
+                            }
+                        };
+                        check_signature_encoding(sig, flags)?;
+                        check_pubkey_encoding(pubkey, flags)?;
+
+                        if checker.check_sig(sig, pubkey, script) {
+                            sigs.next();
+                        }
+
+                        // There must be enough public keys remaining to check the
+                        // remaining signatures.
+                        if sigs.len() > pubkeys.len() {
+                            success = false;
+                            break;
+                        }
+                    }
+
+                    // Remove the arguments, including the dummy element, which must be empty
+                    // when the null dummy rule is enforced.
+                    stack.truncate(len - 2 - keys_count - sigs_count);
+                    let dummy = pop(stack)?;
+                    if flags.null_dummy && !dummy.is_empty() {
+                        return Err(ScriptError::SigNullDummy);
+                    }
+
+                    if opcode == OpCode::CheckMultisigVerify as u8 {
+                        if !success {
+                            return Err(ScriptError::CheckMultisigVerify);
+                        }
+                    } else {
+                        stack.push(encode_bool(success));
+                    }
+                }
+
+                _ => return Err(ScriptError::BadOpcode),
+            }
+        }
+
+        if stack.len() + alt_stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if exec_stack.is_empty() {
+        Ok(())
+    } else {
+        Err(ScriptError::UnbalancedConditional)
+    }
+}
+
+#[cfg(all(test, feature = "transparent-inputs"))]
+mod tests {
+    use std::convert::Infallible;
+
+    use rand_core::OsRng;
+
+    use sha2::{Digest, Sha256};
+
+    use super::{
+        decode_num, encode_num, signature_hash, verify_script, verify_transparent_inputs,
+        with_spent_outputs, Error, ScriptError, SignableInput, SignatureChecker, TxIdDigester,
+        VerificationFlags, SIGHASH_ALL, SIGHASH_SINGLE,
+    };
+    use crate::{
+        consensus::{BranchId, NetworkUpgrade, Parameters, TEST_NETWORK},
+        legacy::{
+            keys::{AccountPrivKey, IncomingViewingKey, NonHardenedChildIndex},
+            OpCode, Script, TransparentAddress,
+        },
+        sapling::prover::mock::{MockOutputProver, MockSpendProver},
+        transaction::{
+            builder::{BuildConfig, Builder},
+            components::{amount::NonNegativeAmount, transparent, OutPoint, TxIn, TxOut},
+            fees::zip317,
+            Authorized, TransactionData, TxVersion,
+        },
+        zip32::AccountId,
+    };
+
+    /// A checker that rejects all signatures, and accepts lock times up to a limit.
+    struct TestChecker {
+        lock_time: i64,
+    }
+
+    impl SignatureChecker for TestChecker {
+        fn check_sig(&self, _: &[u8], _: &[u8], _: &Script) -> bool {
+            false
+        }
+
+        fn check_lock_time(&self, lock_time: i64) -> bool {
+            lock_time <= self.lock_time
+        }
+    }
+
+    fn eval(
+        script_sig: Script,
+        script_pubkey: Script,
+        flags: VerificationFlags,
+    ) -> Result<(), ScriptError> {
+        verify_script(
+            &script_sig,
+            &script_pubkey,
+            flags,
+            &TestChecker { lock_time: 100 },
+        )
+    }
+
+    #[test]
+    fn num_encoding() {
+        for value in [
+            0,
+            1,
+            -1,
+            127,
+            -127,
+            128,
+            -128,
+            255,
+            256,
+            0x7fff_ffff,
+            -0x7fff_ffff,
+        ] {
+            assert_eq!(decode_num(&encode_num(value), true, 4), Ok(value));
+        }
+        assert_eq!(encode_num(-1), vec![0x81]);
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+
+        // Non-minimal encodings are only rejected when minimal encoding is required.
+        assert_eq!(decode_num(&[0x01, 0x00], false, 4), Ok(1));
+        assert_eq!(
+            decode_num(&[0x01, 0x00], true, 4),
+            Err(ScriptError::InvalidNumber)
+        );
+        assert_eq!(decode_num(&[0x80], false, 4), Ok(0));
+        assert_eq!(
+            decode_num(&[0x80], true, 4),
+            Err(ScriptError::InvalidNumber)
+        );
+        assert_eq!(
+            decode_num(&[1, 2, 3, 4, 5], false, 4),
+            Err(ScriptError::InvalidNumber)
+        );
+    }
+
+    #[test]
+    fn evaluation() {
+        let flags = VerificationFlags::STANDARD;
+
+        assert_eq!(
+            eval(
+                Script::default() << OpCode::Op2 << OpCode::Op3,
+                Script::default() << OpCode::Add << OpCode::Op5 << OpCode::NumEqual,
+                flags,
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            eval(
+                Script::default() << OpCode::Op1 << OpCode::Op2,
+                Script::default()
+                    << OpCode::Swap
+                    << OpCode::Op1
+                    << OpCode::EqualVerify
+                    << OpCode::Op2
+                    << OpCode::Equal,
+                flags,
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            eval(Script::default(), Script::default() << OpCode::Op0, flags),
+            Err(ScriptError::EvalFalse)
+        );
+        assert_eq!(
+            eval(
+                Script::default(),
+                Script::default() << OpCode::Return,
+                flags
+            ),
+            Err(ScriptError::OpReturn)
+        );
+
+        // Conditionals
+        let if_else = Script::default()
+            << OpCode::If
+            << OpCode::Op0
+            << OpCode::Else
+            << OpCode::Op1
+            << OpCode::EndIf;
+        assert_eq!(
+            eval(Script::default() << OpCode::Op0, if_else.clone(), flags),
+            Ok(())
+        );
+        assert_eq!(
+            eval(Script::default() << OpCode::Op1, if_else, flags),
+            Err(ScriptError::EvalFalse)
+        );
+        assert_eq!(
+            eval(
+                Script::default() << OpCode::Op1,
+                Script::default() << OpCode::If << OpCode::Op1,
+                flags,
+            ),
+            Err(ScriptError::UnbalancedConditional)
+        );
+
+        // Unexecuted branches are skipped, except for disabled opcodes.
+        let unexecuted = |opcode| {
+            Script::default() << OpCode::Op0 << OpCode::If << opcode << OpCode::EndIf << OpCode::Op1
+        };
+        assert_eq!(
+            eval(Script::default(), unexecuted(OpCode::Return), flags),
+            Ok(())
+        );
+        assert_eq!(
+            eval(Script::default(), unexecuted(OpCode::Cat), flags),
+            Err(ScriptError::DisabledOpcode)
+        );
+        assert_eq!(
+            eval(Script::default(), unexecuted(OpCode::CodeSeparator), flags),
+            Err(ScriptError::DisabledOpcode)
+        );
+
+        // Non-minimal pushes are only rejected by policy.
+        let script_sig = Script::default() << &[1][..];
+        let script_pubkey = Script::default() << OpCode::Op1 << OpCode::Equal;
+        assert_eq!(
+            eval(script_sig.clone(), script_pubkey.clone(), flags),
+            Err(ScriptError::MinimalData)
+        );
+        assert_eq!(
+            eval(script_sig, script_pubkey, VerificationFlags::CONSENSUS),
+            Ok(())
+        );
+
+        // Extra stack elements are only rejected by policy.
+        let script_sig = Script::default() << OpCode::Op1 << OpCode::Op1;
+        assert_eq!(
+            eval(script_sig.clone(), Script::default(), flags),
+            Err(ScriptError::CleanStack)
+        );
+        assert_eq!(
+            eval(script_sig, Script::default(), VerificationFlags::CONSENSUS),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn check_lock_time_verify() {
+        let script_pubkey = |lock_time| {
+            Script::default()
+                << &encode_num(lock_time)[..]
+                << OpCode::CheckLockTimeVerify
+                << OpCode::Drop
+                << OpCode::Op1
+        };
+        let flags = VerificationFlags::CONSENSUS;
+
+        assert_eq!(eval(Script::default(), script_pubkey(100), flags), Ok(()));
+        assert_eq!(
+            eval(Script::default(), script_pubkey(101), flags),
+            Err(ScriptError::UnsatisfiedLockTime)
+        );
+        assert_eq!(
+            eval(Script::default(), script_pubkey(-1), flags),
+            Err(ScriptError::NegativeLockTime)
+        );
+
+        // Without BIP 65, OP_CHECKLOCKTIMEVERIFY is a no-op.
+        let flags = VerificationFlags {
+            check_lock_time_verify: false,
+            ..flags
+        };
+        assert_eq!(eval(Script::default(), script_pubkey(101), flags), Ok(()));
+    }
+
+    /// Cases in the style of `zcashd`'s `script_tests.json`, as
+    /// `(scriptSig, scriptPubKey, flags, expected result)`.
+    #[test]
+    fn script_vectors() {
+        const STANDARD: VerificationFlags = VerificationFlags::STANDARD;
+        const CONSENSUS: VerificationFlags = VerificationFlags::CONSENSUS;
+        const NO_DER_SIG: VerificationFlags = VerificationFlags {
+            der_sig: false,
+            ..VerificationFlags::CONSENSUS
+        };
+        const NO_P2SH: VerificationFlags = VerificationFlags {
+            p2sh: false,
+            ..VerificationFlags::CONSENSUS
+        };
+        const P2SH_OP_1: &str = "a914da1745e9b549bd0bfa1a569971c77eba30cd5a4b87";
+
+        let vectors: &[(&str, &str, VerificationFlags, Result<(), ScriptError>)] = &[
+            // DEPTH 0 EQUAL
+            ("", "740087", STANDARD, Ok(())),
+            // 1 2 / 2 EQUALVERIFY 1 EQUAL
+            ("5152", "52885187", STANDARD, Ok(())),
+            // PUSHDATA1 0x07 / 7 EQUAL
+            ("4c0107", "5787", STANDARD, Err(ScriptError::MinimalData)),
+            ("4c0107", "5787", CONSENSUS, Ok(())),
+            // 0x01 0x07 / 7 EQUAL
+            ("0107", "5787", STANDARD, Err(ScriptError::MinimalData)),
+            ("0107", "5787", CONSENSUS, Ok(())),
+            // 1 / NOP10
+            (
+                "51",
+                "b9",
+                STANDARD,
+                Err(ScriptError::DiscourageUpgradableNops),
+            ),
+            ("51", "b9", CONSENSUS, Ok(())),
+            // 1 / ENDIF
+            (
+                "51",
+                "68",
+                CONSENSUS,
+                Err(ScriptError::UnbalancedConditional),
+            ),
+            // IF 1 ENDIF, with an empty stack
+            (
+                "",
+                "635168",
+                CONSENSUS,
+                Err(ScriptError::UnbalancedConditional),
+            ),
+            // 1 / RETURN
+            ("51", "6a", CONSENSUS, Err(ScriptError::OpReturn)),
+            // 0x02 0x0000 / 0 NUMEQUAL
+            ("020000", "009c", STANDARD, Err(ScriptError::InvalidNumber)),
+            ("020000", "009c", CONSENSUS, Ok(())),
+            // 1 / 0 0 CHECKMULTISIG
+            ("51", "0000ae", STANDARD, Err(ScriptError::SigNullDummy)),
+            ("51", "0000ae", CONSENSUS, Ok(())),
+            // 0 0 / CHECKSIG NOT
+            ("0000", "ac91", STANDARD, Err(ScriptError::PubKeyType)),
+            ("0000", "ac91", CONSENSUS, Ok(())),
+            // 0x01 0x01 0 / CHECKSIG NOT
+            ("010100", "ac91", CONSENSUS, Err(ScriptError::SigDer)),
+            ("010100", "ac91", NO_DER_SIG, Ok(())),
+            // 0x01 0x51 / HASH160 <HASH160(1)> EQUAL
+            ("0151", P2SH_OP_1, STANDARD, Ok(())),
+            // NOP 0x01 0x51 / HASH160 <HASH160(1)> EQUAL
+            (
+                "610151",
+                P2SH_OP_1,
+                CONSENSUS,
+                Err(ScriptError::SigPushOnly),
+            ),
+            ("610151", P2SH_OP_1, NO_P2SH, Ok(())),
+        ];
+
+        for (script_sig, script_pubkey, flags, expected) in vectors {
+            assert_eq!(
+                eval(
+                    Script(hex::decode(script_sig).unwrap()),
+                    Script(hex::decode(script_pubkey).unwrap()),
+                    *flags,
+                ),
+                *expected,
+                "{} / {}",
+                script_sig,
+                script_pubkey,
+            );
+        }
+    }
+
+    #[test]
+    fn p2pkh_spend() {
+        let tsk = AccountPrivKey::from_seed(&TEST_NETWORK, &[0u8; 32], AccountId::ZERO).unwrap();
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50000),
+            script_pubkey: tsk
+                .to_account_pubkey()
+                .derive_external_ivk()
+                .unwrap()
+                .derive_address(NonHardenedChildIndex::ZERO)
+                .unwrap()
+                .script(),
+        };
+
+        // Check both the ZIP 243 and ZIP 244 signature hashes.
+        for nu in [NetworkUpgrade::Sapling, NetworkUpgrade::Nu5] {
+            let mut builder = Builder::new(
+                TEST_NETWORK,
+                TEST_NETWORK.activation_height(nu).unwrap(),
+                BuildConfig::Standard {
+                    sapling_anchor: None,
+                    orchard_anchor: None,
+                },
+            );
+            builder
+                .add_transparent_input(
+                    tsk.derive_external_secret_key(NonHardenedChildIndex::ZERO)
+                        .unwrap(),
+                    OutPoint::fake(),
+                    prev_coin.clone(),
+                )
+                .unwrap();
+            builder
+                .add_transparent_output(
+                    &TransparentAddress::PublicKeyHash([0; 20]),
+                    NonNegativeAmount::const_from_u64(40000),
+                )
+                .unwrap();
+            let res = builder.mock_build(OsRng).unwrap();
+            let tx = res.transaction();
+
+            assert_eq!(
+                verify_transparent_inputs(tx, &[prev_coin.clone()], VerificationFlags::STANDARD),
+                Ok(())
+            );
+            assert_eq!(
+                verify_transparent_inputs(tx, &[], VerificationFlags::STANDARD),
+                Err(Error::SpentOutputsMismatch {
+                    inputs: 1,
+                    spent_outputs: 0
+                })
+            );
+
+            // The signature does not authorize spending a different output.
+            let other_coin = TxOut {
+                script_pubkey: prev_coin.script_pubkey.clone(),
+                value: NonNegativeAmount::const_from_u64(50001),
+            };
+            assert_eq!(
+                verify_transparent_inputs(tx, &[other_coin], VerificationFlags::STANDARD),
+                Err(Error::Script {
+                    index: 0,
+                    error: ScriptError::EvalFalse
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn pre_overwinter_spend() {
+        let secp = secp256k1::Secp256k1::new();
+        let tsk = AccountPrivKey::from_seed(&TEST_NETWORK, &[0u8; 32], AccountId::ZERO).unwrap();
+        let sk = tsk
+            .derive_external_secret_key(NonHardenedChildIndex::ZERO)
+            .unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&secp, &sk).serialize();
+        let spent_outputs = [
+            TxOut {
+                value: NonNegativeAmount::const_from_u64(50000),
+                script_pubkey: tsk
+                    .to_account_pubkey()
+                    .derive_external_ivk()
+                    .unwrap()
+                    .derive_address(NonHardenedChildIndex::ZERO)
+                    .unwrap()
+                    .script(),
+            },
+            TxOut {
+                value: NonNegativeAmount::const_from_u64(20000),
+                script_pubkey: Script::default() << OpCode::Op1,
+            },
+        ];
+        let output = TxOut {
+            value: NonNegativeAmount::const_from_u64(60000),
+            script_pubkey: TransparentAddress::PublicKeyHash([7; 20]).script(),
+        };
+
+        let tx_with = |script_sig: Script, sequence: u32| {
+            TransactionData::<Authorized>::from_parts(
+                TxVersion::Sprout(1),
+                BranchId::Sprout,
+                17,
+                0.into(),
+                Some(transparent::Bundle {
+                    vin: vec![
+                        TxIn {
+                            prevout: OutPoint::new([1; 32], 3),
+                            script_sig,
+                            sequence: u32::MAX,
+                        },
+                        TxIn {
+                            prevout: OutPoint::new([2; 32], 0),
+                            script_sig: Script::default(),
+                            sequence,
+                        },
+                    ],
+                    vout: vec![output.clone()],
+                    authorization: transparent::Authorized,
+                }),
+                None,
+                None,
+                None,
+            )
+            .freeze()
+            .unwrap()
+        };
+
+        // The signature hash is the double SHA-256 hash of the transaction, with the
+        // script of the input being signed replaced by its script code, and the scripts
+        // of the other inputs blanked, followed by the hash type.
+        let unsigned = tx_with(Script::default(), 5);
+        let mut preimage = vec![1, 0, 0, 0, 2];
+        preimage.extend_from_slice(&[1; 32]);
+        preimage.extend_from_slice(&[3, 0, 0, 0]);
+        spent_outputs[0].script_pubkey.write(&mut preimage).unwrap();
+        preimage.extend_from_slice(&[0xff; 4]);
+        preimage.extend_from_slice(&[2; 32]);
+        preimage.extend_from_slice(&[0, 0, 0, 0, 0, 5, 0, 0, 0, 1]);
+        output.write(&mut preimage).unwrap();
+        preimage.extend_from_slice(&[17, 0, 0, 0, SIGHASH_ALL, 0, 0, 0]);
+        let expected: [u8; 32] = Sha256::digest(Sha256::digest(&preimage)).into();
+
+        let tx_data = with_spent_outputs(&unsigned, &spent_outputs);
+        let txid_parts = tx_data.digest(TxIdDigester);
+        let sighash = |hash_type, index| {
+            *signature_hash(
+                &tx_data,
+                &SignableInput::Transparent {
+                    hash_type,
+                    index,
+                    script_code: &spent_outputs[index].script_pubkey,
+                    script_pubkey: &spent_outputs[index].script_pubkey,
+                    value: spent_outputs[index].value,
+                },
+                &txid_parts,
+            )
+            .as_ref()
+        };
+        assert_eq!(sighash(SIGHASH_ALL, 0), expected);
+
+        // SIGHASH_SINGLE for an input without a corresponding output signs the value one.
+        let mut one = [0; 32];
+        one[0] = 1;
+        assert_eq!(sighash(SIGHASH_SINGLE, 1), one);
+
+        let msg = secp256k1::Message::from_slice(&expected).unwrap();
+        let mut sig = secp.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        sig.push(SIGHASH_ALL);
+        let script_sig = Script::default() << &sig[..] << &pubkey[..];
+
+        assert_eq!(
+            verify_transparent_inputs(
+                &tx_with(script_sig.clone(), 5),
+                &spent_outputs,
+                VerificationFlags::STANDARD
+            ),
+            Ok(())
+        );
+
+        // SIGHASH_ALL commits to the sequence numbers of the other inputs.
+        assert_eq!(
+            verify_transparent_inputs(
+                &tx_with(script_sig, 6),
+                &spent_outputs,
+                VerificationFlags::STANDARD
+            ),
+            Err(Error::Script {
+                index: 0,
+                error: ScriptError::EvalFalse
+            })
+        );
+    }
+
+    #[test]
+    fn p2sh_multisig_spend() {
+        let secp = secp256k1::Secp256k1::new();
+        let sks =
+            [[1u8; 32], [2; 32], [3; 32]].map(|b| secp256k1::SecretKey::from_slice(&b).unwrap());
+        let pubkeys = sks
+            .iter()
+            .map(|sk| secp256k1::PublicKey::from_secret_key(&secp, sk).serialize())
+            .collect::<Vec<_>>();
+        let redeem_script = Script::multisig(2, &pubkeys).unwrap();
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50000),
+            script_pubkey: TransparentAddress::from_redeem_script(&redeem_script).script(),
+        };
+
+        let mut builder = Builder::new(
+            TEST_NETWORK,
+            TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap(),
+            BuildConfig::Standard {
+                sapling_anchor: None,
+                orchard_anchor: None,
+            },
+        );
+        builder
+            .add_transparent_p2sh_multisig_input(
+                redeem_script.clone(),
+                OutPoint::fake(),
+                prev_coin.clone(),
+            )
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0; 20]),
+                NonNegativeAmount::const_from_u64(40000),
+            )
+            .unwrap();
+
+        #[allow(deprecated)]
+        let mut proven_tx = builder
+            .build_unauthorized::<_, MockSpendProver, MockOutputProver, _>(
+                OsRng,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap()
+            .create_proofs::<_, _, _, Infallible>(
+                &MockSpendProver,
+                &MockOutputProver,
+                None,
+                OsRng,
+                (),
+            )
+            .unwrap();
+        proven_tx.sign_transparent_input(0, &sks[0]).unwrap();
+        proven_tx.sign_transparent_input(0, &sks[2]).unwrap();
+        let res = proven_tx
+            .apply_signatures::<_, Infallible>(OsRng, &[], &[])
+            .unwrap();
+
+        assert_eq!(
+            verify_transparent_inputs(
+                res.transaction(),
+                &[prev_coin.clone()],
+                VerificationFlags::STANDARD
+            ),
+            Ok(())
+        );
+
+        // The redeem script must match the spent output.
+        let other_redeem_script = Script::multisig(1, &pubkeys).unwrap();
+        let other_coin = TxOut {
+            script_pubkey: TransparentAddress::from_redeem_script(&other_redeem_script).script(),
+            ..prev_coin
+        };
+        assert_eq!(
+            verify_transparent_inputs(
+                res.transaction(),
+                &[other_coin],
+                VerificationFlags::STANDARD
+            ),
+            Err(Error::Script {
+                index: 0,
+                error: ScriptError::EvalFalse
+            })
+        );
+    }
+}
@@ -247,6 +247,22 @@ impl AccountPubKey {
             .map(EphemeralIvk)
     }
 
+    /// Derives the BIP44 public key for the child path
+    /// `m/44'/<coin_type>'/<account>'/<scope>/<address_index>`.
+    ///
+    /// This is the public counterpart of [`AccountPrivKey::derive_secret_key`].
+    pub fn derive_address_pubkey(
+        &self,
+        scope: TransparentKeyScope,
+        address_index: NonHardenedChildIndex,
+    ) -> Result<secp256k1::PublicKey, bip32::Error> {
+        Ok(*self
+            .0
+            .derive_child(scope.into())?
+            .derive_child(address_index.into())?
+            .public_key())
+    }
+
     /// Derives the internal ovk and external ovk corresponding to this
     /// transparent fvk. As specified in [ZIP 316][transparent-ovk].
     ///
@@ -3,9 +3,14 @@
 use std::cmp::Ordering;
 use std::error;
 use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
 use std::sync::mpsc::Sender;
 
+use blake2b_simd::Hash as Blake2bHash;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use rand::{CryptoRng, RngCore};
+use zcash_encoding::Optional;
 
 use crate::{
     consensus::{self, BlockHeight, BranchId, NetworkUpgrade},
@@ -13,7 +18,6 @@ use crate::{
     memo::MemoBytes,
     sapling::{
         self,
-        builder::SaplingMetadata,
         prover::{OutputProver, SpendProver},
         Note, PaymentAddress,
     },
@@ -28,12 +32,12 @@ use crate::{
         },
         sighash::{signature_hash, SignableInput},
         txid::TxIdDigester,
-        Transaction, TransactionData, TxVersion, Unauthorized,
+        Transaction, TransactionData, TxDigests, TxVersion, Unauthorized, Unproven,
     },
 };
 
 #[cfg(feature = "transparent-inputs")]
-use crate::transaction::components::transparent::builder::TransparentInputInfo;
+use crate::{legacy::Script, transaction::components::transparent::builder::TransparentInputInfo};
 
 #[cfg(not(feature = "transparent-inputs"))]
 use std::convert::Infallible;
@@ -43,7 +47,7 @@ use crate::{
     extensions::transparent::{ExtensionTxBuilder, ToPayload},
     transaction::{
         components::{
-            tze::builder::TzeBuilder,
+            tze::builder::{TzeBuilder, TzeSigner},
             tze::{self, TzeOut},
         },
         fees::FutureFeeRule,
@@ -51,7 +55,15 @@ use crate::{
 };
 
 use super::components::amount::NonNegativeAmount;
-use super::components::sapling::zip212_enforcement;
+use super::components::sapling::{read_zkproof, zip212_enforcement};
+
+use self::shielded::OrchardBuilder;
+pub use self::shielded::{
+    OrchardMetadata, OrchardProven, OrchardProvingParts, OrchardSigningParts, OrchardUnproven,
+    SaplingInProgress, SaplingMetadata, SaplingSigningParts,
+};
+
+mod shielded;
 
 /// Since Blossom activation, the default transaction expiry delta should be 40 blocks.
 /// <https://zips.z.cash/zip-0203#changes-for-blossom>
@@ -102,6 +114,9 @@ pub enum Error<FE> {
     /// The builder was constructed with a target height before NU5 activation, but an Orchard
     /// spend or output was added.
     OrchardBuilderNotAvailable,
+    /// The transaction has an Orchard bundle, but no Orchard proving key was provided with
+    /// which to create its proof.
+    OrchardProvingKeyRequired,
     /// An error occurred in constructing the TZE parts of a transaction.
     #[cfg(zcash_unstable = "zfuture")]
     TzeBuild(tze::builder::Error),
@@ -135,6 +150,10 @@ impl<FE: fmt::Display> fmt::Display for Error<FE> {
                 f,
                 "Cannot create Orchard transactions without an Orchard anchor, or before NU5 activation"
             ),
+            Error::OrchardProvingKeyRequired => write!(
+                f,
+                "An Orchard proving key is required to prove transactions with Orchard components"
+            ),
             #[cfg(zcash_unstable = "zfuture")]
             Error::TzeBuild(err) => err.fmt(f),
         }
@@ -250,7 +269,7 @@ impl BuildConfig {
 pub struct BuildResult {
     transaction: Transaction,
     sapling_meta: SaplingMetadata,
-    orchard_meta: orchard::builder::BundleMetadata,
+    orchard_meta: OrchardMetadata,
 }
 
 impl BuildResult {
@@ -267,7 +286,7 @@ impl BuildResult {
 
     /// Returns the mapping from Orchard inputs and outputs to the randomized positions of the
     /// Actions that contain them in the Orchard bundle in the newly constructed transaction.
-    pub fn orchard_meta(&self) -> &orchard::builder::BundleMetadata {
+    pub fn orchard_meta(&self) -> &OrchardMetadata {
         &self.orchard_meta
     }
 }
@@ -280,11 +299,12 @@ pub struct Builder<'a, P, U: sapling::builder::ProverProgress> {
     expiry_height: BlockHeight,
     transparent_builder: TransparentBuilder,
     sapling_builder: Option<sapling::builder::Builder>,
-    orchard_builder: Option<orchard::builder::Builder>,
-    // TODO: In the future, instead of taking the spending keys as arguments when calling
-    // `add_sapling_spend` or `add_orchard_spend`, we will build an unauthorized, unproven
-    // transaction, and then the caller will be responsible for using the spending keys or their
-    // derivatives for proving and signing to complete transaction creation.
+    orchard_builder: Option<OrchardBuilder>,
+    // These are only used by `Builder::build`. Callers that build the transaction in stages
+    // via `Builder::build_unauthorized` provide the spend authorizing keys when applying
+    // signatures instead.
+    // TODO: In the future, we will stop taking the spending keys as arguments when calling
+    // `add_sapling_spend` or `add_orchard_spend`.
     sapling_asks: Vec<sapling::keys::SpendAuthorizingKey>,
     orchard_saks: Vec<orchard::keys::SpendAuthorizingKey>,
     #[cfg(zcash_unstable = "zfuture")]
@@ -347,7 +367,7 @@ impl<'a, P: consensus::Parameters> Builder<'a, P, ()> {
         let orchard_builder = if params.is_nu_active(NetworkUpgrade::Nu5, target_height) {
             build_config
                 .orchard_builder_config()
-                .map(|(bundle_type, anchor)| orchard::builder::Builder::new(bundle_type, anchor))
+                .map(|(bundle_type, anchor)| OrchardBuilder::new(bundle_type, anchor))
         } else {
             None
         };
@@ -415,7 +435,7 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         &mut self,
         sk: &orchard::keys::SpendingKey,
         note: orchard::Note,
-        merkle_path: orchard::tree::MerklePath,
+        merkle_path: incrementalmerkletree::MerklePath<orchard::tree::MerkleHashOrchard, 32>,
     ) -> Result<(), Error<FE>> {
         if let Some(builder) = self.orchard_builder.as_mut() {
             builder.add_spend(orchard::keys::FullViewingKey::from(sk), note, merkle_path)?;
@@ -500,6 +520,23 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         self.transparent_builder.add_input(sk, utxo, coin)
     }
 
+    /// Adds a transparent coin sent to a P2SH multisig address to be spent in this
+    /// transaction.
+    ///
+    /// The signatures for this input must be provided to the [`ProvenTransaction`] (see
+    /// [`ProvenTransaction::append_transparent_signature`]) before it can be authorized, so
+    /// transactions containing such inputs must be built with [`Builder::build_unauthorized`].
+    #[cfg(feature = "transparent-inputs")]
+    pub fn add_transparent_p2sh_multisig_input(
+        &mut self,
+        redeem_script: Script,
+        utxo: transparent::OutPoint,
+        coin: TxOut,
+    ) -> Result<(), transparent::builder::Error> {
+        self.transparent_builder
+            .add_p2sh_multisig_input(redeem_script, utxo, coin)
+    }
+
     /// Adds a transparent address to send funds to.
     pub fn add_transparent_output(
         &mut self,
@@ -667,13 +704,67 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         self.build_internal(rng, spend_prover, output_prover, fee)
     }
 
-    fn build_internal<R: RngCore + CryptoRng, SP: SpendProver, OP: OutputProver, FE>(
+    /// Builds a transaction from the configured spends and outputs, without creating its
+    /// proofs or signatures.
+    ///
+    /// The returned [`UnauthorizedTransaction`] can be proven with
+    /// [`UnauthorizedTransaction::create_proofs`], and the resulting
+    /// [`ProvenTransaction`] then authorized with [`ProvenTransaction::apply_signatures`].
+    /// The spend authorizing keys and progress notifier provided to this builder are not
+    /// used; the caller is responsible for providing them to the later stages.
+    ///
+    /// The type parameters `SP` and `OP` are used to select the Sapling circuit types for
+    /// which the bundle's proving data is prepared.
+    pub fn build_unauthorized<
+        R: RngCore + CryptoRng,
+        SP: SpendProver,
+        OP: OutputProver,
+        FR: FeeRule,
+    >(
         self,
+        rng: R,
+        fee_rule: &FR,
+    ) -> Result<UnauthorizedTransaction<'a>, Error<FR::Error>> {
+        let fee = self.get_fee(fee_rule).map_err(Error::Fee)?;
+        self.build_unproven::<_, SP, OP, _>(rng, fee)
+            .map(|(unauthed_tx, _)| unauthed_tx)
+    }
+
+    fn build_internal<R: RngCore + CryptoRng, SP: SpendProver, OP: OutputProver, FE>(
+        mut self,
         mut rng: R,
         spend_prover: &SP,
         output_prover: &OP,
         fee: NonNegativeAmount,
     ) -> Result<BuildResult, Error<FE>> {
+        let sapling_asks = mem::take(&mut self.sapling_asks);
+        let orchard_saks = mem::take(&mut self.orchard_saks);
+        let (unauthed_tx, progress_notifier) =
+            self.build_unproven::<_, SP, OP, _>(&mut rng, fee)?;
+
+        // Only build the Orchard proving key if we need it, as doing so is expensive.
+        let orchard_pk = unauthed_tx
+            .tx_data
+            .orchard_bundle
+            .as_ref()
+            .map(|_| orchard::circuit::ProvingKey::build());
+
+        unauthed_tx
+            .prove(
+                spend_prover,
+                output_prover,
+                orchard_pk.as_ref(),
+                &mut rng,
+                progress_notifier,
+            )?
+            .apply_signatures(&mut rng, &sapling_asks, &orchard_saks)
+    }
+
+    fn build_unproven<R: RngCore + CryptoRng, SP: SpendProver, OP: OutputProver, FE>(
+        self,
+        mut rng: R,
+        fee: NonNegativeAmount,
+    ) -> Result<(UnauthorizedTransaction<'a>, U), Error<FE>> {
         let consensus_branch_id = BranchId::for_height(&self.params, self.target_height);
 
         // determine transaction version
@@ -702,26 +793,9 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         let (sapling_bundle, sapling_meta) = match self
             .sapling_builder
             .and_then(|builder| {
-                builder
-                    .build::<SP, OP, _, _>(&mut rng)
+                shielded::build_sapling::<SP, OP, _>(builder, &mut rng)
                     .map_err(Error::SaplingBuild)
                     .transpose()
-                    .map(|res| {
-                        res.map(|(bundle, sapling_meta)| {
-                            // We need to create proofs before signatures, because we still support
-                            // creating V4 transactions, which commit to the Sapling proofs in the
-                            // transaction digest.
-                            (
-                                bundle.create_proofs(
-                                    spend_prover,
-                                    output_prover,
-                                    &mut rng,
-                                    self.progress_notifier,
-                                ),
-                                sapling_meta,
-                            )
-                        })
-                    })
             })
             .transpose()?
         {
@@ -740,15 +814,15 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
             .transpose()?
         {
             Some((bundle, meta)) => (Some(bundle), meta),
-            None => (None, orchard::builder::BundleMetadata::empty()),
+            None => (None, OrchardMetadata::empty()),
         };
 
         #[cfg(zcash_unstable = "zfuture")]
         let (tze_bundle, tze_signers) = self.tze_builder.build();
 
-        let unauthed_tx: TransactionData<Unauthorized> = TransactionData {
+        let tx_data: TransactionData<Unproven> = TransactionData {
             version,
-            consensus_branch_id: BranchId::for_height(&self.params, self.target_height),
+            consensus_branch_id,
             lock_time: 0,
             expiry_height: self.expiry_height,
             transparent_bundle,
@@ -759,41 +833,504 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
             tze_bundle,
         };
 
-        //
-        // Signatures -- everything but the signatures must already have been added.
-        //
-        let txid_parts = unauthed_tx.digest(TxIdDigester);
-
-        let transparent_bundle = unauthed_tx.transparent_bundle.clone().map(|b| {
-            b.apply_signatures(
-                #[cfg(feature = "transparent-inputs")]
-                &unauthed_tx,
-                #[cfg(feature = "transparent-inputs")]
-                &txid_parts,
+        Ok((
+            UnauthorizedTransaction {
+                tx_data,
+                sapling_meta,
+                orchard_meta,
+                #[cfg(zcash_unstable = "zfuture")]
+                tze_signers,
+                #[cfg(not(zcash_unstable = "zfuture"))]
+                tze_signers: std::marker::PhantomData,
+            },
+            self.progress_notifier,
+        ))
+    }
+}
+
+/// The tag that identifies a serialized [`UnauthorizedTransaction`].
+const UNAUTHORIZED_STAGE: u8 = 0;
+/// The tag that identifies a serialized [`ProvenTransaction`].
+const PROVEN_STAGE: u8 = 1;
+
+/// Writes the transparent parts of a partially-built transaction, prefixed by the tag of
+/// its build stage.
+fn write_stage<A, W: Write>(
+    stage: u8,
+    tx_data: &TransactionData<A>,
+    mut writer: W,
+) -> io::Result<()>
+where
+    A: crate::transaction::Authorization<TransparentAuth = transparent::builder::Unauthorized>,
+{
+    #[cfg(zcash_unstable = "zfuture")]
+    let has_tze_bundle = tx_data.tze_bundle.is_some();
+    #[cfg(not(zcash_unstable = "zfuture"))]
+    let has_tze_bundle = false;
+
+    if tx_data.sprout_bundle.is_some() || has_tze_bundle {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "transactions with Sprout or TZE components cannot be serialized before they are authorized",
+        ));
+    }
+
+    writer.write_u8(stage)?;
+    tx_data.version.write(&mut writer)?;
+    writer.write_u32::<LittleEndian>(u32::from(tx_data.consensus_branch_id))?;
+    writer.write_u32::<LittleEndian>(tx_data.lock_time)?;
+    writer.write_u32::<LittleEndian>(u32::from(tx_data.expiry_height))?;
+    Optional::write(
+        &mut writer,
+        tx_data.transparent_bundle.as_ref(),
+        |w, bundle| bundle.write_unauthorized(w),
+    )
+}
+
+/// Reads the transaction data written by [`write_stage`] for the given build stage.
+///
+/// The returned transaction data does not have any shielded bundles; these are read
+/// separately for each stage.
+fn read_stage<A, R: Read>(stage: u8, mut reader: R) -> io::Result<TransactionData<A>>
+where
+    A: crate::transaction::Authorization<TransparentAuth = transparent::builder::Unauthorized>,
+{
+    if reader.read_u8()? != stage {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "serialized transaction is not at the expected build stage",
+        ));
+    }
+
+    let version = TxVersion::read(&mut reader)?;
+    let consensus_branch_id = BranchId::try_from(reader.read_u32::<LittleEndian>()?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let lock_time = reader.read_u32::<LittleEndian>()?;
+    let expiry_height = reader.read_u32::<LittleEndian>()?.into();
+    let transparent_bundle = Optional::read(&mut reader, |r| {
+        transparent::Bundle::<transparent::builder::Unauthorized>::read_unauthorized(r)
+    })?;
+
+    Ok(TransactionData {
+        version,
+        consensus_branch_id,
+        lock_time,
+        expiry_height,
+        transparent_bundle,
+        sprout_bundle: None,
+        sapling_bundle: None,
+        orchard_bundle: None,
+        #[cfg(zcash_unstable = "zfuture")]
+        tze_bundle: None,
+    })
+}
+
+/// A transaction that has been constructed from a [`Builder`]'s spends and outputs, but
+/// which does not yet have any proofs or signatures.
+///
+/// The transaction can be serialized with [`UnauthorizedTransaction::write`], and read
+/// back (for example, by another process) with [`UnauthorizedTransaction::read`], as long
+/// as it does not spend P2PKH inputs (as this would expose their secret keys). Its
+/// Sapling and Orchard bundles are written along with the private data from which their
+/// proofs are created, which includes secrets that authorize those proofs; see
+/// [`UnauthorizedTransaction::write`].
+pub struct UnauthorizedTransaction<'a> {
+    tx_data: TransactionData<Unproven>,
+    sapling_meta: SaplingMetadata,
+    orchard_meta: OrchardMetadata,
+    #[cfg(zcash_unstable = "zfuture")]
+    tze_signers: Vec<TzeSigner<'a, TransactionData<Unauthorized>>>,
+    #[cfg(not(zcash_unstable = "zfuture"))]
+    tze_signers: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a> UnauthorizedTransaction<'a> {
+    /// Returns the transaction data that has been built so far.
+    pub fn tx_data(&self) -> &TransactionData<Unproven> {
+        &self.tx_data
+    }
+
+    /// Returns the mapping from Sapling inputs and outputs to their randomized positions in the
+    /// Sapling bundle.
+    pub fn sapling_meta(&self) -> &SaplingMetadata {
+        &self.sapling_meta
+    }
+
+    /// Returns the mapping from Orchard inputs and outputs to the randomized positions of the
+    /// Actions that contain them in the Orchard bundle.
+    pub fn orchard_meta(&self) -> &OrchardMetadata {
+        &self.orchard_meta
+    }
+
+    /// Writes this transaction, so that it can be proven and signed elsewhere.
+    ///
+    /// Returns an error if the transaction has TZE components, or if it spends P2PKH
+    /// inputs.
+    ///
+    /// # Security
+    ///
+    /// Unlike the secret keys of P2PKH inputs, the secrets needed to create the Sapling and
+    /// Orchard proofs are written. The output includes the proof generation key (including
+    /// `nsk`) of each Sapling spend, the full viewing key of each Orchard spend, the value
+    /// commitment trapdoors and spend authorization randomizers of both bundles, and the
+    /// spending keys of Orchard dummy spends. Anyone who reads it can create proofs for
+    /// these spends and link them to the spent notes, so it must only be sent to a trusted
+    /// prover, over a confidential channel.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_stage(UNAUTHORIZED_STAGE, &self.tx_data, &mut writer)?;
+        Optional::write(
+            &mut writer,
+            self.tx_data.sapling_bundle.as_ref(),
+            |w, bundle| {
+                shielded::write_sapling_bundle(
+                    w,
+                    bundle,
+                    |w, circuit| shielded::write_sapling_spend_circuit(w, circuit),
+                    |w, circuit| shielded::write_sapling_output_circuit(w, circuit),
+                )
+            },
+        )?;
+        Optional::write(
+            &mut writer,
+            self.tx_data.orchard_bundle.as_ref(),
+            |w, bundle| {
+                shielded::write_orchard_bundle(
+                    w,
+                    bundle,
+                    |w, parts| shielded::write_orchard_proving_parts(w, parts),
+                    |w, auth| shielded::write_orchard_unproven(w, auth),
+                )
+            },
+        )?;
+        self.sapling_meta.write(&mut writer)?;
+        self.orchard_meta.write(writer)
+    }
+
+    /// Reads a transaction that was written with [`UnauthorizedTransaction::write`].
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut tx_data: TransactionData<Unproven> = read_stage(UNAUTHORIZED_STAGE, &mut reader)?;
+        tx_data.sapling_bundle = Optional::read(&mut reader, |r| {
+            shielded::read_sapling_bundle(
+                r,
+                |r| shielded::read_sapling_spend_circuit(r),
+                |r| shielded::read_sapling_output_circuit(r),
+            )
+        })?;
+        tx_data.orchard_bundle = Optional::read(&mut reader, |r| {
+            shielded::read_orchard_bundle(
+                r,
+                |r, action| shielded::read_orchard_proving_parts(r, action),
+                |r| shielded::read_orchard_unproven(r),
+            )
+        })?;
+        let sapling_meta = SaplingMetadata::read(&mut reader)?;
+        let orchard_meta = OrchardMetadata::read(reader)?;
+
+        Ok(UnauthorizedTransaction {
+            tx_data,
+            sapling_meta,
+            orchard_meta,
+            #[cfg(zcash_unstable = "zfuture")]
+            tze_signers: vec![],
+            #[cfg(not(zcash_unstable = "zfuture"))]
+            tze_signers: std::marker::PhantomData,
+        })
+    }
+
+    /// Creates the Sapling and Orchard proofs for this transaction.
+    ///
+    /// An Orchard proving key is only required if the transaction has an Orchard bundle;
+    /// [`Error::OrchardProvingKeyRequired`] is returned if it does, and `orchard_pk` is
+    /// `None`.
+    pub fn create_proofs<R: RngCore + CryptoRng, SP: SpendProver, OP: OutputProver, FE>(
+        self,
+        spend_prover: &SP,
+        output_prover: &OP,
+        orchard_pk: Option<&orchard::circuit::ProvingKey>,
+        rng: R,
+        progress_notifier: impl sapling::builder::ProverProgress,
+    ) -> Result<ProvenTransaction<'a>, Error<FE>> {
+        self.prove(
+            spend_prover,
+            output_prover,
+            orchard_pk,
+            rng,
+            progress_notifier,
+        )
+    }
+
+    fn prove<R: RngCore + CryptoRng, SP: SpendProver, OP: OutputProver, FE>(
+        self,
+        spend_prover: &SP,
+        output_prover: &OP,
+        orchard_pk: Option<&orchard::circuit::ProvingKey>,
+        mut rng: R,
+        progress_notifier: impl sapling::builder::ProverProgress,
+    ) -> Result<ProvenTransaction<'a>, Error<FE>> {
+        // We need to create proofs before signatures, because we still support creating V4
+        // transactions, which commit to the Sapling proofs in the transaction digest.
+        let sapling_bundle = self.tx_data.sapling_bundle.map(|b| {
+            shielded::create_sapling_proofs(
+                b,
+                spend_prover,
+                output_prover,
+                &mut rng,
+                progress_notifier,
             )
         });
 
+        let orchard_bundle = self
+            .tx_data
+            .orchard_bundle
+            .map(|b| {
+                shielded::create_orchard_proof(
+                    b,
+                    orchard_pk.ok_or(Error::OrchardProvingKeyRequired)?,
+                    &mut rng,
+                )
+                .map_err(Error::OrchardBuild)
+            })
+            .transpose()?;
+
+        let tx_data: TransactionData<Unauthorized> = TransactionData {
+            version: self.tx_data.version,
+            consensus_branch_id: self.tx_data.consensus_branch_id,
+            lock_time: self.tx_data.lock_time,
+            expiry_height: self.tx_data.expiry_height,
+            transparent_bundle: self.tx_data.transparent_bundle,
+            sprout_bundle: self.tx_data.sprout_bundle,
+            sapling_bundle,
+            orchard_bundle,
+            #[cfg(zcash_unstable = "zfuture")]
+            tze_bundle: self.tx_data.tze_bundle,
+        };
+        let txid_parts = tx_data.digest(TxIdDigester);
+
+        Ok(ProvenTransaction {
+            tx_data,
+            txid_parts,
+            sapling_meta: self.sapling_meta,
+            orchard_meta: self.orchard_meta,
+            tze_signers: self.tze_signers,
+        })
+    }
+}
+
+/// A transaction that has all of its proofs, but none of its signatures.
+///
+/// The transaction can be serialized with [`ProvenTransaction::write`] under the same
+/// conditions as an [`UnauthorizedTransaction`], so that it can be passed between the
+/// holders of its spend authorizing keys and of the keys for its P2SH multisig inputs.
+/// It is serialized with its proofs, the signatures that have been appended to it, and the
+/// randomness needed to create its Sapling and Orchard signatures. Unlike a serialized
+/// [`UnauthorizedTransaction`], it does not contain any proof generation keys or viewing
+/// keys.
+pub struct ProvenTransaction<'a> {
+    tx_data: TransactionData<Unauthorized>,
+    txid_parts: TxDigests<Blake2bHash>,
+    sapling_meta: SaplingMetadata,
+    orchard_meta: OrchardMetadata,
+    #[cfg(zcash_unstable = "zfuture")]
+    tze_signers: Vec<TzeSigner<'a, TransactionData<Unauthorized>>>,
+    #[cfg(not(zcash_unstable = "zfuture"))]
+    tze_signers: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a> ProvenTransaction<'a> {
+    /// Returns the transaction data that has been built so far.
+    pub fn tx_data(&self) -> &TransactionData<Unauthorized> {
+        &self.tx_data
+    }
+
+    /// Returns the mapping from Sapling inputs and outputs to their randomized positions in the
+    /// Sapling bundle.
+    pub fn sapling_meta(&self) -> &SaplingMetadata {
+        &self.sapling_meta
+    }
+
+    /// Returns the mapping from Orchard inputs and outputs to the randomized positions of the
+    /// Actions that contain them in the Orchard bundle.
+    pub fn orchard_meta(&self) -> &OrchardMetadata {
+        &self.orchard_meta
+    }
+
+    /// Writes this transaction, along with the signatures that have been appended to it.
+    ///
+    /// Returns an error if the transaction has TZE components, or if it spends P2PKH
+    /// inputs.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_stage(PROVEN_STAGE, &self.tx_data, &mut writer)?;
+        Optional::write(
+            &mut writer,
+            self.tx_data.sapling_bundle.as_ref(),
+            |w, bundle| {
+                shielded::write_sapling_bundle(
+                    w,
+                    bundle,
+                    |w, proof| w.write_all(proof),
+                    |w, proof| w.write_all(proof),
+                )
+            },
+        )?;
+        Optional::write(
+            &mut writer,
+            self.tx_data.orchard_bundle.as_ref(),
+            |w, bundle| {
+                shielded::write_orchard_bundle(
+                    w,
+                    bundle,
+                    |w, parts| shielded::write_orchard_signing_parts(w, parts),
+                    |w, auth| shielded::write_orchard_proven(w, auth),
+                )
+            },
+        )?;
+        self.sapling_meta.write(&mut writer)?;
+        self.orchard_meta.write(writer)
+    }
+
+    /// Reads a transaction that was written with [`ProvenTransaction::write`].
+    ///
+    /// Returns an error if any of the signatures appended to the transaction's inputs are
+    /// invalid.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut tx_data: TransactionData<Unauthorized> = read_stage(PROVEN_STAGE, &mut reader)?;
+        tx_data.sapling_bundle = Optional::read(&mut reader, |r| {
+            shielded::read_sapling_bundle(r, |r| read_zkproof(r), |r| read_zkproof(r))
+        })?;
+        tx_data.orchard_bundle = Optional::read(&mut reader, |r| {
+            shielded::read_orchard_bundle(
+                r,
+                |r, _| shielded::read_orchard_signing_parts(r),
+                |r| shielded::read_orchard_proven(r),
+            )
+        })?;
+        let sapling_meta = SaplingMetadata::read(&mut reader)?;
+        let orchard_meta = OrchardMetadata::read(reader)?;
+
+        let txid_parts = tx_data.digest(TxIdDigester);
+
+        #[cfg(feature = "transparent-inputs")]
+        if let Some(bundle) = &tx_data.transparent_bundle {
+            bundle
+                .verify_multisig_signatures(&tx_data, &txid_parts)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        }
+
+        Ok(ProvenTransaction {
+            tx_data,
+            txid_parts,
+            sapling_meta,
+            orchard_meta,
+            #[cfg(zcash_unstable = "zfuture")]
+            tze_signers: vec![],
+            #[cfg(not(zcash_unstable = "zfuture"))]
+            tze_signers: std::marker::PhantomData,
+        })
+    }
+
+    /// Returns the sighash that is signed by the Sapling and Orchard spend authorization
+    /// signatures, and by the binding signatures.
+    pub fn shielded_sighash(&self) -> [u8; 32] {
+        // Once V4 transactions are deprecated this should just be the txid, but for now we
+        // need to continue to compute it here.
+        *signature_hash(&self.tx_data, &SignableInput::Shielded, &self.txid_parts).as_ref()
+    }
+
+    /// Returns the sighash that must be signed to authorize the transparent input at the
+    /// given index, or `None` if there is no such input.
+    #[cfg(feature = "transparent-inputs")]
+    pub fn transparent_sighash(&self, index: usize) -> Option<[u8; 32]> {
+        self.tx_data.transparent_bundle.as_ref()?.input_sighash(
+            index,
+            &self.tx_data,
+            &self.txid_parts,
+        )
+    }
+
+    /// Adds a signature by the given public key to the P2SH multisig input at the given
+    /// index.
+    #[cfg(feature = "transparent-inputs")]
+    pub fn append_transparent_signature(
+        &mut self,
+        index: usize,
+        pubkey: &secp256k1::PublicKey,
+        signature: &secp256k1::ecdsa::Signature,
+    ) -> Result<(), transparent::builder::Error> {
+        let sighash = self
+            .transparent_sighash(index)
+            .ok_or(transparent::builder::Error::NotMultisigInput(index))?;
+        self.tx_data
+            .transparent_bundle
+            .as_mut()
+            .expect("checked above")
+            .append_multisig_signature(index, &sighash, pubkey, signature)
+    }
+
+    /// Signs the P2SH multisig input at the given index with the given secret key.
+    #[cfg(feature = "transparent-inputs")]
+    pub fn sign_transparent_input(
+        &mut self,
+        index: usize,
+        sk: &secp256k1::SecretKey,
+    ) -> Result<(), transparent::builder::Error> {
+        let sighash = self
+            .transparent_sighash(index)
+            .ok_or(transparent::builder::Error::NotMultisigInput(index))?;
+        self.tx_data
+            .transparent_bundle
+            .as_mut()
+            .expect("checked above")
+            .sign_multisig_input(index, &sighash, sk)
+    }
+
+    /// Applies the transparent, Sapling, and Orchard signatures to this transaction, using
+    /// the given spend authorizing keys.
+    ///
+    /// A key must be provided for every Sapling spend and every Orchard spend in the
+    /// transaction; the signatures for P2PKH transparent inputs are created with the keys
+    /// that were provided to the [`Builder`]. P2SH multisig inputs must already have enough
+    /// signatures appended to them.
+    pub fn apply_signatures<R: RngCore + CryptoRng, FE>(
+        self,
+        mut rng: R,
+        sapling_asks: &[sapling::keys::SpendAuthorizingKey],
+        orchard_saks: &[orchard::keys::SpendAuthorizingKey],
+    ) -> Result<BuildResult, Error<FE>> {
+        let shielded_sig_commitment = self.shielded_sighash();
+        let unauthed_tx = self.tx_data;
+        #[cfg(feature = "transparent-inputs")]
+        let txid_parts = self.txid_parts;
+
+        let transparent_bundle = unauthed_tx
+            .transparent_bundle
+            .clone()
+            .map(|b| {
+                b.apply_signatures(
+                    #[cfg(feature = "transparent-inputs")]
+                    &unauthed_tx,
+                    #[cfg(feature = "transparent-inputs")]
+                    &txid_parts,
+                )
+            })
+            .transpose()
+            .map_err(Error::TransparentBuild)?;
+
         #[cfg(zcash_unstable = "zfuture")]
         let tze_bundle = unauthed_tx
             .tze_bundle
             .clone()
-            .map(|b| b.into_authorized(&unauthed_tx, tze_signers))
+            .map(|b| b.into_authorized(&unauthed_tx, self.tze_signers))
             .transpose()
             .map_err(Error::TzeBuild)?;
 
-        // the commitment being signed is shared across all Sapling inputs; once
-        // V4 transactions are deprecated this should just be the txid, but
-        // for now we need to continue to compute it here.
-        let shielded_sig_commitment =
-            signature_hash(&unauthed_tx, &SignableInput::Shielded, &txid_parts);
-
         let sapling_bundle = unauthed_tx
             .sapling_bundle
             .map(|b| {
-                b.apply_signatures(
+                shielded::apply_sapling_signatures(
+                    b,
                     &mut rng,
-                    *shielded_sig_commitment.as_ref(),
-                    &self.sapling_asks,
+                    shielded_sig_commitment,
+                    sapling_asks,
                 )
             })
             .transpose()
@@ -802,14 +1339,12 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         let orchard_bundle = unauthed_tx
             .orchard_bundle
             .map(|b| {
-                b.create_proof(&orchard::circuit::ProvingKey::build(), &mut rng)
-                    .and_then(|b| {
-                        b.apply_signatures(
-                            &mut rng,
-                            *shielded_sig_commitment.as_ref(),
-                            &self.orchard_saks,
-                        )
-                    })
+                shielded::apply_orchard_signatures(
+                    b,
+                    &mut rng,
+                    shielded_sig_commitment,
+                    orchard_saks,
+                )
             })
             .transpose()
             .map_err(Error::OrchardBuild)?;
@@ -831,8 +1366,8 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         // of freeze() should be infalliable.
         Ok(BuildResult {
             transaction: authorized_tx.freeze().unwrap(),
-            sapling_meta,
-            orchard_meta,
+            sapling_meta: self.sapling_meta,
+            orchard_meta: self.orchard_meta,
         })
     }
 }
@@ -936,10 +1471,16 @@ mod tests {
         consensus::{NetworkUpgrade, Parameters, TEST_NETWORK},
         legacy::TransparentAddress,
         memo::MemoBytes,
-        sapling::{self, zip32::ExtendedSpendingKey, Node, Rseed},
+        sapling::{
+            self,
+            prover::mock::{MockOutputProver, MockSpendProver},
+            zip32::ExtendedSpendingKey,
+            Node, Rseed,
+        },
         transaction::{
             builder::BuildConfig,
             components::amount::{Amount, BalanceError, NonNegativeAmount},
+            fees::zip317,
         },
     };
 
@@ -1067,6 +1608,887 @@ mod tests {
         assert!(res.transaction().sapling_bundle().is_some());
     }
 
+    #[test]
+    fn build_in_stages() {
+        let extsk = ExtendedSpendingKey::master(&[]);
+        let dfvk = extsk.to_diversifiable_full_viewing_key();
+        let to = dfvk.default_address().1;
+
+        let mut rng = OsRng;
+
+        let note1 = to.create_note(
+            sapling::value::NoteValue::from_raw(50000),
+            Rseed::BeforeZip212(jubjub::Fr::random(&mut rng)),
+        );
+        let cmu1 = Node::from_cmu(&note1.cmu());
+        let mut tree = CommitmentTree::<Node, 32>::empty();
+        tree.append(cmu1).unwrap();
+        let witness1 = IncrementalWitness::from_tree(tree);
+
+        let tx_height = TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap();
+
+        let build_config = BuildConfig::Standard {
+            sapling_anchor: Some(witness1.root().into()),
+            orchard_anchor: None,
+        };
+        let mut builder = Builder::new(TEST_NETWORK, tx_height, build_config);
+        builder
+            .add_sapling_spend::<Infallible>(&extsk, note1, witness1.path().unwrap())
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0; 20]),
+                NonNegativeAmount::const_from_u64(35000),
+            )
+            .unwrap();
+
+        #[allow(deprecated)]
+        let unauthed_tx = builder
+            .build_unauthorized::<_, MockSpendProver, MockOutputProver, _>(
+                OsRng,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap();
+        assert_eq!(
+            unauthed_tx
+                .tx_data()
+                .sapling_bundle()
+                .unwrap()
+                .shielded_spends()
+                .len(),
+            1
+        );
+
+        let proven_tx = unauthed_tx
+            .create_proofs::<_, _, _, Infallible>(
+                &MockSpendProver,
+                &MockOutputProver,
+                None,
+                OsRng,
+                (),
+            )
+            .unwrap();
+        let sighash = proven_tx.shielded_sighash();
+        let rk = *proven_tx
+            .tx_data()
+            .sapling_bundle()
+            .unwrap()
+            .shielded_spends()[0]
+            .rk();
+
+        let res = proven_tx
+            .apply_signatures::<_, Infallible>(OsRng, &[extsk.expsk.ask.clone()], &[])
+            .unwrap();
+        let spend = &res
+            .transaction()
+            .sapling_bundle()
+            .unwrap()
+            .shielded_spends()[0];
+        assert!(rk.verify(&sighash, spend.spend_auth_sig()).is_ok());
+        assert_eq!(*res.transaction().txid().as_ref(), sighash);
+    }
+
+    #[test]
+    #[cfg(all(feature = "transparent-inputs", feature = "transaction-verification"))]
+    fn p2sh_multisig_spend() {
+        use crate::{
+            legacy::{
+                interpreter::{
+                    verify_script, verify_transparent_inputs, with_spent_outputs,
+                    TransactionSignatureChecker, VerificationFlags,
+                },
+                Script,
+            },
+            transaction::{components::transparent, sighash::SIGHASH_ALL, txid::TxIdDigester},
+        };
+
+        let secp = secp256k1::Secp256k1::new();
+        let sks =
+            [[1u8; 32], [2; 32], [3; 32]].map(|b| secp256k1::SecretKey::from_slice(&b).unwrap());
+        let pubkeys = sks
+            .iter()
+            .map(|sk| secp256k1::PublicKey::from_secret_key(&secp, sk))
+            .collect::<Vec<_>>();
+        let redeem_script = Script::multisig(
+            2,
+            &pubkeys.iter().map(|pk| pk.serialize()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50000),
+            script_pubkey: TransparentAddress::from_redeem_script(&redeem_script).script(),
+        };
+
+        let tx_height = TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap();
+        let build = || {
+            let mut builder = Builder::new(
+                TEST_NETWORK,
+                tx_height,
+                BuildConfig::Standard {
+                    sapling_anchor: None,
+                    orchard_anchor: None,
+                },
+            );
+
+            // The redeem script must match the coin's address.
+            assert_matches!(
+                builder.add_transparent_p2sh_multisig_input(
+                    Script::multisig(1, &[pubkeys[0].serialize()]).unwrap(),
+                    OutPoint::fake(),
+                    prev_coin.clone(),
+                ),
+                Err(transparent::builder::Error::InvalidAddress)
+            );
+
+            // A redeem script over 16 keys is too large to be pushed in a scriptSig.
+            let mut oversized_script = Script(vec![0x51]);
+            for _ in 0..16 {
+                oversized_script = oversized_script << &pubkeys[0].serialize()[..];
+            }
+            oversized_script.0.extend([0x60, 0xae]);
+            assert_matches!(
+                builder.add_transparent_p2sh_multisig_input(
+                    oversized_script.clone(),
+                    OutPoint::fake(),
+                    TxOut {
+                        value: NonNegativeAmount::const_from_u64(50000),
+                        script_pubkey: TransparentAddress::from_redeem_script(&oversized_script)
+                            .script(),
+                    },
+                ),
+                Err(transparent::builder::Error::InvalidRedeemScript)
+            );
+
+            builder
+                .add_transparent_p2sh_multisig_input(
+                    redeem_script.clone(),
+                    OutPoint::fake(),
+                    prev_coin.clone(),
+                )
+                .unwrap();
+            builder
+                .add_transparent_output(
+                    &TransparentAddress::PublicKeyHash([0; 20]),
+                    NonNegativeAmount::const_from_u64(40000),
+                )
+                .unwrap();
+
+            #[allow(deprecated)]
+            builder
+                .build_unauthorized::<_, MockSpendProver, MockOutputProver, _>(
+                    OsRng,
+                    &zip317::FeeRule::standard(),
+                )
+                .unwrap()
+                .create_proofs::<_, _, _, Infallible>(
+                    &MockSpendProver,
+                    &MockOutputProver,
+                    None,
+                    OsRng,
+                    (),
+                )
+                .unwrap()
+        };
+
+        // A single signature is not enough to authorize the input.
+        let mut proven_tx = build();
+        proven_tx.sign_transparent_input(0, &sks[0]).unwrap();
+        assert_matches!(
+            proven_tx.apply_signatures::<_, Infallible>(OsRng, &[], &[]),
+            Err(Error::TransparentBuild(
+                transparent::builder::Error::MissingSignatures(0)
+            ))
+        );
+
+        let mut proven_tx = build();
+        let sighash = proven_tx.transparent_sighash(0).unwrap();
+        let msg = secp256k1::Message::from_slice(&sighash).unwrap();
+        let sigs = sks
+            .iter()
+            .map(|sk| secp.sign_ecdsa(&msg, sk))
+            .collect::<Vec<_>>();
+
+        // Signatures must be valid, and from keys in the redeem script.
+        assert_matches!(
+            proven_tx.append_transparent_signature(0, &pubkeys[1], &sigs[2]),
+            Err(transparent::builder::Error::InvalidSignature)
+        );
+        let other_sk = secp256k1::SecretKey::from_slice(&[4; 32]).unwrap();
+        assert_matches!(
+            proven_tx.sign_transparent_input(0, &other_sk),
+            Err(transparent::builder::Error::InvalidSignature)
+        );
+        assert_matches!(
+            proven_tx.sign_transparent_input(1, &sks[0]),
+            Err(transparent::builder::Error::NotMultisigInput(1))
+        );
+
+        // Signatures can be provided in any order.
+        proven_tx
+            .append_transparent_signature(0, &pubkeys[2], &sigs[2])
+            .unwrap();
+        proven_tx.sign_transparent_input(0, &sks[0]).unwrap();
+
+        let res = proven_tx
+            .apply_signatures::<_, Infallible>(OsRng, &[], &[])
+            .unwrap();
+
+        // The scriptSig contains the signatures in the order of their keys in the redeem
+        // script, followed by the redeem script.
+        let with_hash_type = |sig: &secp256k1::ecdsa::Signature| {
+            let mut bytes = sig.serialize_der().to_vec();
+            bytes.push(SIGHASH_ALL);
+            bytes
+        };
+        let expected_script_sig = Script::default()
+            << &[][..]
+            << &with_hash_type(&sigs[0])[..]
+            << &with_hash_type(&sigs[2])[..]
+            << &redeem_script.0[..];
+        assert_eq!(
+            res.transaction().transparent_bundle().unwrap().vin[0].script_sig,
+            expected_script_sig
+        );
+
+        // The script interpreter accepts the scriptSig under zcashd's standardness rules,
+        // and rejects it if the signatures are not in redeem script key order.
+        let spent_outputs = [prev_coin.clone()];
+        verify_transparent_inputs(
+            res.transaction(),
+            &spent_outputs,
+            VerificationFlags::STANDARD,
+        )
+        .unwrap();
+
+        let tx_data = with_spent_outputs(res.transaction(), &spent_outputs);
+        let txid_parts = tx_data.digest(TxIdDigester);
+        let checker = TransactionSignatureChecker::new(&tx_data, &txid_parts, 0, &prev_coin);
+        let reordered_script_sig = Script::default()
+            << &[][..]
+            << &with_hash_type(&sigs[2])[..]
+            << &with_hash_type(&sigs[0])[..]
+            << &redeem_script.0[..];
+        assert!(verify_script(
+            &reordered_script_sig,
+            &prev_coin.script_pubkey,
+            VerificationFlags::STANDARD,
+            &checker,
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "transparent-inputs", feature = "transaction-verification"))]
+    fn p2sh_multisig_v4_sighash_and_script_sig() {
+        use blake2b_simd::Params;
+
+        use crate::{
+            consensus::BranchId,
+            legacy::{
+                interpreter::{verify_transparent_inputs, VerificationFlags},
+                Script,
+            },
+            transaction::sighash::SIGHASH_ALL,
+        };
+
+        let secp = secp256k1::Secp256k1::new();
+        let sks =
+            [[1u8; 32], [2; 32], [3; 32]].map(|b| secp256k1::SecretKey::from_slice(&b).unwrap());
+        let pubkeys = sks
+            .iter()
+            .map(|sk| secp256k1::PublicKey::from_secret_key(&secp, sk).serialize())
+            .collect::<Vec<_>>();
+        let redeem_script = Script::multisig(2, &pubkeys).unwrap();
+        let prevout = OutPoint::new([0x11; 32], 1);
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50000),
+            script_pubkey: TransparentAddress::from_redeem_script(&redeem_script).script(),
+        };
+
+        // Before NU5, the builder creates v4 transactions, which zcashd signs with the
+        // ZIP 243 signature hash.
+        let tx_height = TEST_NETWORK
+            .activation_height(NetworkUpgrade::Canopy)
+            .unwrap();
+        let mut builder = Builder::new(
+            TEST_NETWORK,
+            tx_height,
+            BuildConfig::Standard {
+                sapling_anchor: None,
+                orchard_anchor: None,
+            },
+        );
+        builder
+            .add_transparent_p2sh_multisig_input(
+                redeem_script.clone(),
+                prevout.clone(),
+                prev_coin.clone(),
+            )
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0x22; 20]),
+                NonNegativeAmount::const_from_u64(40000),
+            )
+            .unwrap();
+        #[allow(deprecated)]
+        let mut proven_tx = builder
+            .build_unauthorized::<_, MockSpendProver, MockOutputProver, _>(
+                OsRng,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap()
+            .create_proofs::<_, _, _, Infallible>(
+                &MockSpendProver,
+                &MockOutputProver,
+                None,
+                OsRng,
+                (),
+            )
+            .unwrap();
+        let sighash = proven_tx.transparent_sighash(0).unwrap();
+        proven_tx.sign_transparent_input(0, &sks[2]).unwrap();
+        proven_tx.sign_transparent_input(0, &sks[0]).unwrap();
+        let res = proven_tx
+            .apply_signatures::<_, Infallible>(OsRng, &[], &[])
+            .unwrap();
+        let tx = res.transaction();
+        assert_eq!(tx.consensus_branch_id(), BranchId::Canopy);
+
+        // The ZIP 243 signature hash preimage for the input, with the redeem script as its
+        // script code.
+        let blake2b = |personal: &[u8; 16], data: &[u8]| {
+            Params::new()
+                .hash_length(32)
+                .personal(personal)
+                .hash(data)
+                .as_bytes()
+                .to_vec()
+        };
+        let mut prevout_bytes = [0x11; 32].to_vec();
+        prevout_bytes.extend_from_slice(&1u32.to_le_bytes());
+        let mut output_bytes = 40000i64.to_le_bytes().to_vec();
+        output_bytes.extend_from_slice(&[0x19, 0x76, 0xa9, 0x14]);
+        output_bytes.extend_from_slice(&[0x22; 20]);
+        output_bytes.extend_from_slice(&[0x88, 0xac]);
+
+        let mut preimage = vec![];
+        preimage.extend_from_slice(&0x8000_0004u32.to_le_bytes());
+        preimage.extend_from_slice(&0x892f_2085u32.to_le_bytes());
+        preimage.extend(blake2b(b"ZcashPrevoutHash", &prevout_bytes));
+        preimage.extend(blake2b(b"ZcashSequencHash", &0xffff_ffffu32.to_le_bytes()));
+        preimage.extend(blake2b(b"ZcashOutputsHash", &output_bytes));
+        // No JoinSplits, Sapling spends or Sapling outputs.
+        preimage.extend_from_slice(&[0; 96]);
+        preimage.extend_from_slice(&tx.lock_time().to_le_bytes());
+        preimage.extend_from_slice(&u32::from(tx.expiry_height()).to_le_bytes());
+        preimage.extend_from_slice(&0i64.to_le_bytes());
+        preimage.extend_from_slice(&u32::from(SIGHASH_ALL).to_le_bytes());
+        preimage.extend_from_slice(&prevout_bytes);
+        preimage.push(105);
+        preimage.extend_from_slice(&redeem_script.0);
+        preimage.extend_from_slice(&50000i64.to_le_bytes());
+        preimage.extend_from_slice(&0xffff_ffffu32.to_le_bytes());
+
+        let mut personal = *b"ZcashSigHash\0\0\0\0";
+        personal[12..].copy_from_slice(&u32::from(BranchId::Canopy).to_le_bytes());
+        assert_eq!(sighash.to_vec(), blake2b(&personal, &preimage));
+
+        // The scriptSig is laid out as zcashd lays out P2SH multisig spends: OP_0 (for the
+        // extra value popped by OP_CHECKMULTISIG), the signatures in the order of their
+        // keys in the redeem script, and the 105-byte redeem script pushed with
+        // OP_PUSHDATA1.
+        let msg = secp256k1::Message::from_slice(&sighash).unwrap();
+        let mut expected_script_sig = vec![0x00];
+        for sk in [&sks[0], &sks[2]] {
+            let mut sig = secp.sign_ecdsa(&msg, sk).serialize_der().to_vec();
+            sig.push(SIGHASH_ALL);
+            expected_script_sig.push(sig.len() as u8);
+            expected_script_sig.extend(sig);
+        }
+        expected_script_sig.extend_from_slice(&[0x4c, 105]);
+        expected_script_sig.extend_from_slice(&redeem_script.0);
+        let vin = &tx.transparent_bundle().unwrap().vin;
+        assert_eq!(vin[0].prevout, prevout);
+        assert_eq!(vin[0].script_sig.0, expected_script_sig);
+
+        verify_transparent_inputs(tx, &[prev_coin], VerificationFlags::STANDARD).unwrap();
+    }
+
+    /// Pins the v4 2-of-3 P2SH multisig spend built (with deterministic RFC 6979
+    /// signatures) by `p2sh_multisig_v4_sighash_and_script_sig`.
+    ///
+    /// This is a regression check of the builder against its own earlier output, so that
+    /// changes to the serialized transaction, its signature hash or its scriptSig are caught.
+    /// It is not a cross-implementation check; that is provided by the ZIP 243 preimage and
+    /// the scriptSig layout checked in `p2sh_multisig_v4_sighash_and_script_sig`.
+    #[test]
+    #[cfg(all(feature = "transparent-inputs", feature = "transaction-verification"))]
+    fn p2sh_multisig_v4_regression() {
+        use crate::{
+            consensus::BranchId,
+            legacy::{
+                interpreter::{verify_transparent_inputs, with_spent_outputs, VerificationFlags},
+                Script,
+            },
+            transaction::{
+                sighash::{signature_hash, SignableInput, SIGHASH_ALL},
+                txid::TxIdDigester,
+                Transaction,
+            },
+        };
+
+        const RAW_TX: &str = "0400008085202f890111111111111111111111111111111111111111111111111111\
+            1111111111111101000000fdfd000047304402206f6d35def1df163051c2c93f0ce911a9f39055a1590068\
+            d32448a8a94d41aad302206a3c35d772c1cb733d264d3d916ed251c08cf56faa810cecd51eff8be610a272\
+            01483045022100923d6028f66b75037b6e5f7cae26baea3aa593c1c1b08c5c4d9a2550d236140802206b35\
+            b818647d4ed59385f8a67e23bbcb175dcc21d1c6841879e39cc9398adc85014c695221031b84c5567b1264\
+            40995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f21024d4b6cd1361032ca9bd2aeb9d900aa4d\
+            45d9ead80ac9423374c451a7254d07662102531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3\
+            c5bdbdcb1fe33753aeffffffff01409c0000000000001976a914222222222222222222222222222222222\
+            222222288ac00000000bcb10f000000000000000000000000";
+        const PREVOUT_SCRIPT_PUBKEY: &str = "a914ae79902ae33900b679c76ced8576362e4abb15e887";
+        const PREVOUT_VALUE: u64 = 50000;
+        const REDEEM_SCRIPT: &str = "5221031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c\
+            17f5e9d5dd078f21024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766\
+            2102531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe33753ae";
+        const SIGHASH: &str = "6d6a52c63ca2469acd8fb1aa46c790d6813d0d720b4e48ea0494a580d03e82e7";
+
+        let raw_tx = hex::decode(RAW_TX).unwrap();
+        let redeem_script = Script(hex::decode(REDEEM_SCRIPT).unwrap());
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(PREVOUT_VALUE),
+            script_pubkey: Script(hex::decode(PREVOUT_SCRIPT_PUBKEY).unwrap()),
+        };
+        assert_eq!(
+            TransparentAddress::from_redeem_script(&redeem_script).script(),
+            prev_coin.script_pubkey,
+        );
+
+        // The transaction round-trips.
+        let tx = Transaction::read(&raw_tx[..], BranchId::Canopy).unwrap();
+        let mut serialized = vec![];
+        tx.write(&mut serialized).unwrap();
+        assert_eq!(serialized, raw_tx);
+
+        // The signature hash of the input, with the redeem script as its script code.
+        let tx_data = with_spent_outputs(&tx, &[prev_coin.clone()]);
+        let txid_parts = tx_data.digest(TxIdDigester);
+        let sighash = signature_hash(
+            &tx_data,
+            &SignableInput::Transparent {
+                hash_type: SIGHASH_ALL,
+                index: 0,
+                script_code: &redeem_script,
+                script_pubkey: &prev_coin.script_pubkey,
+                value: prev_coin.value,
+            },
+            &txid_parts,
+        );
+        assert_eq!(hex::encode(sighash.as_ref()), SIGHASH);
+
+        // Rebuilding the transaction and signing it with the first and third keys
+        // reproduces the transaction, and therefore its scriptSig, byte for byte.
+        let sks =
+            [[1u8; 32], [2; 32], [3; 32]].map(|b| secp256k1::SecretKey::from_slice(&b).unwrap());
+        let mut builder = Builder::new(
+            TEST_NETWORK,
+            tx.expiry_height() - 40,
+            BuildConfig::Standard {
+                sapling_anchor: None,
+                orchard_anchor: None,
+            },
+        );
+        builder
+            .add_transparent_p2sh_multisig_input(
+                redeem_script,
+                tx.transparent_bundle().unwrap().vin[0].prevout.clone(),
+                prev_coin.clone(),
+            )
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0x22; 20]),
+                NonNegativeAmount::const_from_u64(40000),
+            )
+            .unwrap();
+        #[allow(deprecated)]
+        let mut proven_tx = builder
+            .build_unauthorized::<_, MockSpendProver, MockOutputProver, _>(
+                OsRng,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap()
+            .create_proofs::<_, _, _, Infallible>(
+                &MockSpendProver,
+                &MockOutputProver,
+                None,
+                OsRng,
+                (),
+            )
+            .unwrap();
+        assert_eq!(proven_tx.transparent_sighash(0).unwrap(), *sighash.as_ref());
+        proven_tx.sign_transparent_input(0, &sks[0]).unwrap();
+        proven_tx.sign_transparent_input(0, &sks[2]).unwrap();
+        let res = proven_tx
+            .apply_signatures::<_, Infallible>(OsRng, &[], &[])
+            .unwrap();
+        assert_eq!(
+            res.transaction().transparent_bundle().unwrap().vin[0].script_sig,
+            tx.transparent_bundle().unwrap().vin[0].script_sig,
+        );
+        let mut rebuilt = vec![];
+        res.transaction().write(&mut rebuilt).unwrap();
+        assert_eq!(rebuilt, raw_tx);
+
+        verify_transparent_inputs(&tx, &[prev_coin], VerificationFlags::STANDARD).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "transparent-inputs")]
+    fn serialize_intermediate_stages() {
+        use crate::legacy::{keys::NonHardenedChildIndex, Script};
+
+        use super::{ProvenTransaction, UnauthorizedTransaction};
+
+        let secp = secp256k1::Secp256k1::new();
+        let sks =
+            [[1u8; 32], [2; 32], [3; 32]].map(|b| secp256k1::SecretKey::from_slice(&b).unwrap());
+        let pubkeys = sks
+            .iter()
+            .map(|sk| secp256k1::PublicKey::from_secret_key(&secp, sk).serialize())
+            .collect::<Vec<_>>();
+        let redeem_script = Script::multisig(2, &pubkeys).unwrap();
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50000),
+            script_pubkey: TransparentAddress::from_redeem_script(&redeem_script).script(),
+        };
+
+        let tx_height = TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap();
+        let build_config = BuildConfig::Standard {
+            sapling_anchor: Some(sapling::Anchor::empty_tree()),
+            orchard_anchor: None,
+        };
+        let new_builder = || {
+            let mut builder = Builder::new(TEST_NETWORK, tx_height, build_config);
+            builder
+                .add_transparent_p2sh_multisig_input(
+                    redeem_script.clone(),
+                    OutPoint::fake(),
+                    prev_coin.clone(),
+                )
+                .unwrap();
+            builder
+        };
+
+        let mut builder = new_builder();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0; 20]),
+                NonNegativeAmount::const_from_u64(40000),
+            )
+            .unwrap();
+        #[allow(deprecated)]
+        let unauthorized = builder
+            .build_unauthorized::<_, MockSpendProver, MockOutputProver, _>(
+                OsRng,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap();
+
+        // The unauthorized transaction can be proven after a round trip.
+        let mut buf = vec![];
+        unauthorized.write(&mut buf).unwrap();
+        let unauthorized = UnauthorizedTransaction::read(&buf[..]).unwrap();
+        let mut proven_tx = unauthorized
+            .create_proofs::<_, _, _, Infallible>(
+                &MockSpendProver,
+                &MockOutputProver,
+                None,
+                OsRng,
+                (),
+            )
+            .unwrap();
+
+        // The proven transaction can be passed between signers, retaining their signatures.
+        proven_tx.sign_transparent_input(0, &sks[0]).unwrap();
+        let mut buf = vec![];
+        proven_tx.write(&mut buf).unwrap();
+        assert!(UnauthorizedTransaction::read(&buf[..]).is_err());
+        let mut proven_tx = ProvenTransaction::read(&buf[..]).unwrap();
+        proven_tx.sign_transparent_input(0, &sks[2]).unwrap();
+        let res = proven_tx
+            .apply_signatures::<_, Infallible>(OsRng, &[], &[])
+            .unwrap();
+        assert_eq!(
+            res.transaction().transparent_bundle().unwrap().vin[0]
+                .script_sig
+                .0
+                .last(),
+            redeem_script.0.last()
+        );
+
+        // Corrupting a stored signature is detected when the transaction is read. The
+        // signature is written after its public key, which also appears earlier in the
+        // redeem script.
+        let pubkey_pos = buf
+            .windows(pubkeys[0].len())
+            .rposition(|w| w == pubkeys[0])
+            .unwrap();
+        // Skip the public key, the signature's length, and its DER header.
+        buf[pubkey_pos + pubkeys[0].len() + 1 + 6] ^= 1;
+        assert!(ProvenTransaction::read(&buf[..]).is_err());
+
+        // Transactions that spend P2PKH inputs cannot be serialized.
+        let tsk = AccountPrivKey::from_seed(&TEST_NETWORK, &[0u8; 32], AccountId::ZERO).unwrap();
+        let p2pkh_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50000),
+            script_pubkey: tsk
+                .to_account_pubkey()
+                .derive_external_ivk()
+                .unwrap()
+                .derive_address(NonHardenedChildIndex::ZERO)
+                .unwrap()
+                .script(),
+        };
+        let mut builder = new_builder();
+        builder
+            .add_transparent_input(
+                tsk.derive_external_secret_key(NonHardenedChildIndex::ZERO)
+                    .unwrap(),
+                OutPoint::fake(),
+                p2pkh_coin,
+            )
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0; 20]),
+                NonNegativeAmount::const_from_u64(85000),
+            )
+            .unwrap();
+        #[allow(deprecated)]
+        let unauthorized = builder
+            .build_unauthorized::<_, MockSpendProver, MockOutputProver, _>(
+                OsRng,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap();
+        assert_matches!(unauthorized.write(&mut vec![]), Err(_));
+    }
+
+    #[test]
+    fn serialize_shielded_stages() {
+        use ff::PrimeField;
+        use orchard::{
+            keys::Scope,
+            note::{RandomSeed, Rho},
+            tree::MerkleHashOrchard,
+        };
+
+        use super::{ProvenTransaction, UnauthorizedTransaction};
+        use crate::transaction::txid::TxIdDigester;
+
+        let extsk = ExtendedSpendingKey::master(&[]);
+        let sapling_addr = extsk.default_address().1;
+        let sapling_note = sapling_addr.create_note(
+            sapling::value::NoteValue::from_raw(50000),
+            Rseed::AfterZip212([4; 32]),
+        );
+        let mut sapling_tree = CommitmentTree::<Node, 32>::empty();
+        sapling_tree
+            .append(Node::from_cmu(&sapling_note.cmu()))
+            .unwrap();
+        let sapling_witness = IncrementalWitness::from_tree(sapling_tree);
+
+        let orchard_sk = orchard::keys::SpendingKey::from_bytes([2; 32]).unwrap();
+        let orchard_fvk = orchard::keys::FullViewingKey::from(&orchard_sk);
+        let orchard_addr = orchard_fvk.address_at(0u32, Scope::External);
+        let rho = Rho::from_bytes(&[0; 32]).unwrap();
+        let orchard_note = orchard::Note::from_parts(
+            orchard_addr,
+            orchard::value::NoteValue::from_raw(60000),
+            rho,
+            RandomSeed::from_bytes([5; 32], &rho).unwrap(),
+        )
+        .unwrap();
+        let mut orchard_tree = CommitmentTree::<MerkleHashOrchard, 32>::empty();
+        orchard_tree
+            .append(MerkleHashOrchard::from_cmx(
+                &orchard_note.commitment().into(),
+            ))
+            .unwrap();
+        let orchard_witness = IncrementalWitness::from_tree(orchard_tree);
+
+        let tx_height = TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap();
+        let build_config = BuildConfig::Standard {
+            sapling_anchor: Some(sapling_witness.root().into()),
+            orchard_anchor: Some(orchard_witness.root().into()),
+        };
+        let mut builder = Builder::new(TEST_NETWORK, tx_height, build_config);
+        builder
+            .add_sapling_spend::<Infallible>(&extsk, sapling_note, sapling_witness.path().unwrap())
+            .unwrap();
+        builder
+            .add_orchard_spend::<Infallible>(
+                &orchard_sk,
+                orchard_note,
+                orchard_witness.path().unwrap(),
+            )
+            .unwrap();
+        builder
+            .add_sapling_output::<Infallible>(
+                None,
+                sapling_addr,
+                NonNegativeAmount::const_from_u64(40000),
+                MemoBytes::empty(),
+            )
+            .unwrap();
+        builder
+            .add_orchard_output::<Infallible>(
+                Some(orchard_fvk.to_ovk(Scope::Internal)),
+                orchard_addr,
+                50000,
+                MemoBytes::empty(),
+            )
+            .unwrap();
+
+        #[allow(deprecated)]
+        let unauthorized = builder
+            .build_unauthorized::<_, MockSpendProver, MockOutputProver, _>(
+                OsRng,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap();
+
+        // The shielded bundles are read back identically, along with the private data
+        // needed to prove them.
+        let mut buf = vec![];
+        unauthorized.write(&mut buf).unwrap();
+        let nsk = extsk.expsk.nsk.to_repr();
+        assert!(buf.windows(nsk.len()).any(|w| w == nsk));
+        let read_back = UnauthorizedTransaction::read(&buf[..]).unwrap();
+        let digests = unauthorized.tx_data().digest(TxIdDigester);
+        let read_back_digests = read_back.tx_data().digest(TxIdDigester);
+        assert_eq!(digests.sapling_digest, read_back_digests.sapling_digest);
+        assert_eq!(digests.orchard_digest, read_back_digests.orchard_digest);
+        assert_eq!(
+            read_back.sapling_meta().spend_index(0),
+            unauthorized.sapling_meta().spend_index(0)
+        );
+        assert_eq!(
+            read_back.orchard_meta().spend_action_index(0),
+            unauthorized.orchard_meta().spend_action_index(0)
+        );
+
+        let orchard_pk = orchard::circuit::ProvingKey::build();
+        let proven_tx = read_back
+            .create_proofs::<_, _, _, Infallible>(
+                &MockSpendProver,
+                &MockOutputProver,
+                Some(&orchard_pk),
+                OsRng,
+                (),
+            )
+            .unwrap();
+        let sighash = proven_tx.shielded_sighash();
+
+        // The proven transaction is written with its proofs, and without the proof
+        // generation keys of its spends.
+        let mut buf = vec![];
+        proven_tx.write(&mut buf).unwrap();
+        assert!(!buf.windows(nsk.len()).any(|w| w == nsk));
+        assert!(UnauthorizedTransaction::read(&buf[..]).is_err());
+        let proven_tx = ProvenTransaction::read(&buf[..]).unwrap();
+        assert_eq!(proven_tx.shielded_sighash(), sighash);
+
+        let res = proven_tx
+            .apply_signatures::<_, Infallible>(
+                OsRng,
+                &[extsk.expsk.ask.clone()],
+                &[orchard::keys::SpendAuthorizingKey::from(&orchard_sk)],
+            )
+            .unwrap();
+        assert_eq!(*res.transaction().txid().as_ref(), sighash);
+        let spend = &res
+            .transaction()
+            .sapling_bundle()
+            .unwrap()
+            .shielded_spends()[0];
+        assert!(spend.rk().verify(&sighash, spend.spend_auth_sig()).is_ok());
+        let orchard_bundle = res.transaction().orchard_bundle().unwrap();
+        assert!(orchard_bundle
+            .verify_proof(&orchard::circuit::VerifyingKey::build())
+            .is_ok());
+        assert!(orchard_bundle
+            .actions()
+            .iter()
+            .all(|action| action.rk().verify(&sighash, action.authorization()).is_ok()));
+        assert!(orchard_bundle
+            .binding_validating_key()
+            .verify(&sighash, orchard_bundle.authorization().binding_signature())
+            .is_ok());
+    }
+
+    #[test]
+    fn signing_fails_without_sapling_keys() {
+        let extsk = ExtendedSpendingKey::master(&[]);
+        let dfvk = extsk.to_diversifiable_full_viewing_key();
+        let to = dfvk.default_address().1;
+
+        let mut rng = OsRng;
+
+        let note1 = to.create_note(
+            sapling::value::NoteValue::from_raw(50000),
+            Rseed::BeforeZip212(jubjub::Fr::random(&mut rng)),
+        );
+        let cmu1 = Node::from_cmu(&note1.cmu());
+        let mut tree = CommitmentTree::<Node, 32>::empty();
+        tree.append(cmu1).unwrap();
+        let witness1 = IncrementalWitness::from_tree(tree);
+
+        let tx_height = TEST_NETWORK
+            .activation_height(NetworkUpgrade::Sapling)
+            .unwrap();
+
+        let build_config = BuildConfig::Standard {
+            sapling_anchor: Some(witness1.root().into()),
+            orchard_anchor: None,
+        };
+        let mut builder = Builder::new(TEST_NETWORK, tx_height, build_config);
+        builder
+            .add_sapling_spend::<Infallible>(&extsk, note1, witness1.path().unwrap())
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0; 20]),
+                NonNegativeAmount::const_from_u64(35000),
+            )
+            .unwrap();
+
+        #[allow(deprecated)]
+        let proven_tx = builder
+            .build_unauthorized::<_, MockSpendProver, MockOutputProver, _>(
+                OsRng,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap()
+            .create_proofs::<_, _, _, Infallible>(
+                &MockSpendProver,
+                &MockOutputProver,
+                None,
+                OsRng,
+                (),
+            )
+            .unwrap();
+
+        assert_matches!(
+            proven_tx.apply_signatures::<_, Infallible>(OsRng, &[], &[]),
+            Err(Error::SaplingBuild(
+                sapling::builder::Error::MissingSignatures
+            ))
+        );
+    }
+
     #[test]
     fn fails_on_negative_change() {
         use crate::transaction::fees::zip317::MINIMUM_FEE;
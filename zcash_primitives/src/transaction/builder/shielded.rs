@@ -0,0 +1,1264 @@
+//! The shielded bundles of a transaction under construction.
+//!
+//! The in-progress bundle types of the `sapling` and `orchard` crates do not expose the
+//! data that is needed to prove and sign them, so a partially-built transaction that
+//! contains them cannot be serialized. Instead, the [`Builder`] keeps its shielded bundles
+//! in the authorization states defined here, which can be written and read at each build
+//! stage:
+//!
+//! - Sapling bundles are laid out by [`sapling::builder::Builder`], which exposes the
+//!   circuits of the bundle's spends and outputs; the proofs and signatures are then
+//!   created from these circuits.
+//! - Orchard bundles are laid out by [`OrchardBuilder`], as [`orchard::builder::Builder`]
+//!   does not expose the circuits of its actions.
+//!
+//! [`Builder`]: super::Builder
+
+use std::io::{self, Read, Write};
+use std::iter;
+use std::marker::PhantomData;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use ff::{Field, PrimeField};
+use group::GroupEncoding;
+use incrementalmerkletree::{MerklePath, Position};
+use nonempty::NonEmpty;
+use orchard::{
+    builder::{BuildError, OutputError, SpendError},
+    keys::{FullViewingKey, Scope, SpendAuthorizingKey, SpendValidatingKey, SpendingKey},
+    note::{ExtractedNoteCommitment, RandomSeed, Rho, TransmittedNoteCiphertext},
+    note_encryption::{OrchardDomain, OrchardNoteEncryption},
+    primitives::redpallas,
+    tree::MerkleHashOrchard,
+    value::{OverflowError, ValueCommitTrapdoor, ValueCommitment},
+};
+use pasta_curves::pallas;
+use rand::seq::SliceRandom;
+use rand_core::{CryptoRng, RngCore};
+use zcash_encoding::{CompactSize, Optional, Vector};
+use zcash_note_encryption::Domain;
+
+use crate::{
+    memo::MemoBytes,
+    merkle_tree::{read_position, write_position, HashSer},
+    sapling::{
+        self,
+        bundle::{OutputDescription, SpendDescription},
+        circuit::ValueCommitmentOpening,
+        prover::{OutputProver, SpendProver},
+        value::NoteValue,
+        PaymentAddress, ProofGenerationKey,
+    },
+    transaction::components::{
+        amount::Amount,
+        orchard as orchard_serialization,
+        sapling::{read_base, read_cmu, read_nullifier, read_rk, read_value_commitment},
+    },
+};
+
+/// Metadata about the Sapling bundle of a transaction built by a [`Builder`].
+///
+/// [`Builder`]: super::Builder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaplingMetadata {
+    spend_indices: Vec<usize>,
+    output_indices: Vec<usize>,
+}
+
+impl SaplingMetadata {
+    pub fn empty() -> Self {
+        SaplingMetadata {
+            spend_indices: vec![],
+            output_indices: vec![],
+        }
+    }
+
+    /// Returns the index within the transaction of the [`SpendDescription`] corresponding
+    /// to the `n`-th call to [`Builder::add_sapling_spend`].
+    ///
+    /// Note positions are randomized when building transactions for indistinguishability.
+    /// This means that the transaction consumer cannot assume that e.g. the first spend
+    /// they added (via the first call to [`Builder::add_sapling_spend`]) is the first
+    /// [`SpendDescription`] in the transaction.
+    ///
+    /// [`Builder::add_sapling_spend`]: super::Builder::add_sapling_spend
+    pub fn spend_index(&self, n: usize) -> Option<usize> {
+        self.spend_indices.get(n).copied()
+    }
+
+    /// Returns the index within the transaction of the [`OutputDescription`] corresponding
+    /// to the `n`-th call to [`Builder::add_sapling_output`].
+    ///
+    /// Note positions are randomized when building transactions for indistinguishability.
+    /// This means that the transaction consumer cannot assume that e.g. the first output
+    /// they added (via the first call to [`Builder::add_sapling_output`]) is the first
+    /// [`OutputDescription`] in the transaction.
+    ///
+    /// [`Builder::add_sapling_output`]: super::Builder::add_sapling_output
+    pub fn output_index(&self, n: usize) -> Option<usize> {
+        self.output_indices.get(n).copied()
+    }
+
+    fn from_builder(
+        meta: &sapling::builder::SaplingMetadata,
+        num_spends: usize,
+        num_outputs: usize,
+    ) -> Self {
+        SaplingMetadata {
+            spend_indices: (0..num_spends)
+                .map(|n| meta.spend_index(n).expect("the builder added this spend"))
+                .collect(),
+            output_indices: (0..num_outputs)
+                .map(|n| meta.output_index(n).expect("the builder added this output"))
+                .collect(),
+        }
+    }
+
+    pub(super) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_indices(&mut writer, &self.spend_indices)?;
+        write_indices(writer, &self.output_indices)
+    }
+
+    pub(super) fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        Ok(SaplingMetadata {
+            spend_indices: read_indices(&mut reader)?,
+            output_indices: read_indices(reader)?,
+        })
+    }
+}
+
+/// Metadata about the Orchard bundle of a transaction built by a [`Builder`].
+///
+/// [`Builder`]: super::Builder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrchardMetadata {
+    spend_indices: Vec<usize>,
+    output_indices: Vec<usize>,
+}
+
+impl OrchardMetadata {
+    pub fn empty() -> Self {
+        OrchardMetadata {
+            spend_indices: vec![],
+            output_indices: vec![],
+        }
+    }
+
+    /// Returns the index within the bundle of the [`orchard::Action`] containing the
+    /// spend of the `n`-th note that was added to the [`Builder`].
+    ///
+    /// [`Builder`]: super::Builder
+    pub fn spend_action_index(&self, n: usize) -> Option<usize> {
+        self.spend_indices.get(n).copied()
+    }
+
+    /// Returns the index within the bundle of the [`orchard::Action`] containing the
+    /// `n`-th output that was added to the [`Builder`].
+    ///
+    /// [`Builder`]: super::Builder
+    pub fn output_action_index(&self, n: usize) -> Option<usize> {
+        self.output_indices.get(n).copied()
+    }
+
+    pub(super) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_indices(&mut writer, &self.spend_indices)?;
+        write_indices(writer, &self.output_indices)
+    }
+
+    pub(super) fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        Ok(OrchardMetadata {
+            spend_indices: read_indices(&mut reader)?,
+            output_indices: read_indices(reader)?,
+        })
+    }
+}
+
+fn write_indices<W: Write>(writer: W, indices: &[usize]) -> io::Result<()> {
+    Vector::write(writer, indices, |w, index| CompactSize::write(w, *index))
+}
+
+fn read_indices<R: Read>(reader: R) -> io::Result<Vec<usize>> {
+    Vector::read(reader, |r| CompactSize::read_t(r))
+}
+
+/// The authorizing data of a Sapling bundle under construction, which has not yet been
+/// signed.
+///
+/// `P` is [`sapling::builder::Unproven`] for a bundle whose spends and outputs carry the
+/// circuits for their proofs, and [`sapling::builder::Proven`] once those proofs have been
+/// created.
+#[derive(Clone, Debug)]
+pub struct SaplingInProgress<P> {
+    bsk: jubjub::Fr,
+    _proof_state: PhantomData<P>,
+}
+
+impl<P: sapling::builder::InProgressProofs> sapling::bundle::Authorization
+    for SaplingInProgress<P>
+{
+    type SpendProof = P::SpendProof;
+    type OutputProof = P::OutputProof;
+    type AuthSig = SaplingSigningParts;
+}
+
+/// The data needed to create the spend authorization signature for a Sapling spend.
+#[derive(Clone, Debug)]
+pub struct SaplingSigningParts {
+    alpha: jubjub::Fr,
+}
+
+/// Builds the Sapling bundle laid out by the given bundle builder, keeping the circuits of
+/// its spends and outputs as the data from which its proofs will be created.
+///
+/// The spend authorizing keys of dummy spends are not exposed by the bundle builder, so a
+/// bundle that requires dummy spends could not be signed, and an error is returned for it.
+/// The [`Builder`] never requires such bundles.
+///
+/// [`Builder`]: super::Builder
+#[allow(clippy::type_complexity)]
+pub(super) fn build_sapling<SP: SpendProver, OP: OutputProver, R: RngCore>(
+    builder: sapling::builder::Builder,
+    rng: R,
+) -> Result<
+    Option<(
+        sapling::Bundle<SaplingInProgress<sapling::builder::Unproven>, Amount>,
+        SaplingMetadata,
+    )>,
+    sapling::builder::Error,
+> {
+    let num_spends = builder.inputs().len();
+    let num_outputs = builder.outputs().len();
+    let (bundle, meta) = match builder.build::<SP, OP, _, Amount>(rng)? {
+        Some(built) => built,
+        None => return Ok(None),
+    };
+    if bundle.shielded_spends().len() != num_spends {
+        return Err(sapling::builder::Error::BundleTypeNotSatisfiable);
+    }
+
+    let rcv = |opening: &Option<ValueCommitmentOpening>| {
+        opening
+            .as_ref()
+            .expect("circuits are prepared with their value commitment openings")
+            .randomness
+    };
+    let bsk = bundle
+        .shielded_spends()
+        .iter()
+        .map(|spend| rcv(&spend.zkproof().value_commitment_opening))
+        .sum::<jubjub::Fr>()
+        - bundle
+            .shielded_outputs()
+            .iter()
+            .map(|output| rcv(&output.zkproof().value_commitment_opening))
+            .sum::<jubjub::Fr>();
+
+    let alphas = bundle
+        .shielded_spends()
+        .iter()
+        .map(|spend| {
+            spend
+                .zkproof()
+                .ar
+                .expect("circuits are prepared with their spend authorization randomizers")
+        })
+        .collect::<Vec<_>>();
+
+    let bundle = bundle.map_authorization(
+        alphas.into_iter(),
+        |_, circuit| circuit,
+        |_, circuit| circuit,
+        |alphas, _| SaplingSigningParts {
+            alpha: alphas.next().expect("there is one randomizer per spend"),
+        },
+        |_, _| SaplingInProgress {
+            bsk,
+            _proof_state: PhantomData,
+        },
+    );
+
+    Ok(Some((
+        bundle,
+        SaplingMetadata::from_builder(&meta, num_spends, num_outputs),
+    )))
+}
+
+/// Creates the proofs for an unproven Sapling bundle.
+pub(super) fn create_sapling_proofs<SP: SpendProver, OP: OutputProver, R: RngCore>(
+    bundle: sapling::Bundle<SaplingInProgress<sapling::builder::Unproven>, Amount>,
+    spend_prover: &SP,
+    output_prover: &OP,
+    mut rng: R,
+    mut progress_notifier: impl sapling::builder::ProverProgress,
+) -> sapling::Bundle<SaplingInProgress<sapling::builder::Proven>, Amount> {
+    let total_progress = (bundle.shielded_spends().len() + bundle.shielded_outputs().len()) as u32;
+    let mut progress = 0u32;
+    let mut update_progress = |notifier: &mut dyn sapling::builder::ProverProgress| {
+        progress += 1;
+        notifier.update(progress, total_progress);
+    };
+
+    let spends = bundle
+        .shielded_spends()
+        .iter()
+        .map(|spend| {
+            let proof = spend_prover.create_proof(spend.zkproof().clone(), &mut rng);
+            update_progress(&mut progress_notifier);
+            SP::encode_proof(proof)
+        })
+        .collect::<Vec<_>>();
+    let outputs = bundle
+        .shielded_outputs()
+        .iter()
+        .map(|output| {
+            let proof = output_prover.create_proof(output.zkproof().clone(), &mut rng);
+            update_progress(&mut progress_notifier);
+            OP::encode_proof(proof)
+        })
+        .collect::<Vec<_>>();
+
+    bundle.map_authorization(
+        (spends.into_iter(), outputs.into_iter()),
+        |(spends, _), _| spends.next().expect("there is one proof per spend"),
+        |(_, outputs), _| outputs.next().expect("there is one proof per output"),
+        |_, parts| parts,
+        |_, auth| SaplingInProgress {
+            bsk: auth.bsk,
+            _proof_state: PhantomData,
+        },
+    )
+}
+
+/// Signs a proven Sapling bundle with the given spend authorizing keys.
+///
+/// Returns [`sapling::builder::Error::MissingSignatures`] if none of the keys authorizes
+/// one of the bundle's spends.
+pub(super) fn apply_sapling_signatures<R: RngCore + CryptoRng>(
+    bundle: sapling::Bundle<SaplingInProgress<sapling::builder::Proven>, Amount>,
+    mut rng: R,
+    sighash: [u8; 32],
+    asks: &[sapling::keys::SpendAuthorizingKey],
+) -> Result<sapling::Bundle<sapling::bundle::Authorized, Amount>, sapling::builder::Error> {
+    let spend_auth_sigs = bundle
+        .shielded_spends()
+        .iter()
+        .map(|spend| {
+            let rk = <[u8; 32]>::from(*spend.rk());
+            asks.iter()
+                .map(|ask| ask.randomize(&spend.spend_auth_sig().alpha))
+                .find(|rsk| <[u8; 32]>::from(redjubjub::VerificationKey::from(rsk)) == rk)
+                .map(|rsk| rsk.sign(&mut rng, &sighash))
+                .ok_or(sapling::builder::Error::MissingSignatures)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let binding_sig =
+        redjubjub::SigningKey::<redjubjub::Binding>::try_from(bundle.authorization().bsk.to_repr())
+            .expect("bsk is a valid scalar")
+            .sign(&mut rng, &sighash);
+
+    Ok(bundle.map_authorization(
+        spend_auth_sigs.into_iter(),
+        |_, proof| proof,
+        |_, proof| proof,
+        |sigs, _| sigs.next().expect("there is one signature per spend"),
+        |_, _| sapling::bundle::Authorized { binding_sig },
+    ))
+}
+
+/// Writes a Sapling bundle under construction.
+pub(super) fn write_sapling_bundle<W: Write, P: sapling::builder::InProgressProofs>(
+    mut writer: W,
+    bundle: &sapling::Bundle<SaplingInProgress<P>, Amount>,
+    write_spend_proof: impl Fn(&mut W, &P::SpendProof) -> io::Result<()>,
+    write_output_proof: impl Fn(&mut W, &P::OutputProof) -> io::Result<()>,
+) -> io::Result<()> {
+    writer.write_i64::<LittleEndian>(i64::from(*bundle.value_balance()))?;
+    Vector::write(&mut writer, bundle.shielded_spends(), |w, spend| {
+        w.write_all(&spend.cv().to_bytes())?;
+        w.write_all(&spend.anchor().to_repr())?;
+        w.write_all(&spend.nullifier().0)?;
+        w.write_all(&<[u8; 32]>::from(*spend.rk()))?;
+        write_spend_proof(w, spend.zkproof())?;
+        w.write_all(&spend.spend_auth_sig().alpha.to_repr())
+    })?;
+    Vector::write(&mut writer, bundle.shielded_outputs(), |w, output| {
+        w.write_all(&output.cv().to_bytes())?;
+        w.write_all(&output.cmu().to_bytes())?;
+        w.write_all(&output.ephemeral_key().0)?;
+        w.write_all(output.enc_ciphertext())?;
+        w.write_all(output.out_ciphertext())?;
+        write_output_proof(w, output.zkproof())
+    })?;
+    writer.write_all(&bundle.authorization().bsk.to_repr())
+}
+
+/// Reads a Sapling bundle that was written by [`write_sapling_bundle`].
+pub(super) fn read_sapling_bundle<R: Read, P: sapling::builder::InProgressProofs>(
+    mut reader: R,
+    read_spend_proof: impl Fn(&mut R) -> io::Result<P::SpendProof>,
+    read_output_proof: impl Fn(&mut R) -> io::Result<P::OutputProof>,
+) -> io::Result<sapling::Bundle<SaplingInProgress<P>, Amount>> {
+    let value_balance = Amount::from_i64(reader.read_i64::<LittleEndian>()?)
+        .map_err(|_| invalid_data("invalid Sapling value balance"))?;
+    let spends = Vector::read(&mut reader, |r| {
+        let cv = read_value_commitment(&mut *r)?;
+        let anchor = read_base(&mut *r, "anchor")?;
+        let nullifier = read_nullifier(&mut *r)?;
+        let rk = read_rk(&mut *r)?;
+        let zkproof = read_spend_proof(r)?;
+        let alpha = read_scalar(&mut *r, "invalid Sapling spend authorization randomizer")?;
+        Ok(SpendDescription::from_parts(
+            cv,
+            anchor,
+            nullifier,
+            rk,
+            zkproof,
+            SaplingSigningParts { alpha },
+        ))
+    })?;
+    let outputs = Vector::read(&mut reader, |r| {
+        let cv = read_value_commitment(&mut *r)?;
+        let cmu = read_cmu(&mut *r)?;
+        let ephemeral_key = zcash_note_encryption::EphemeralKeyBytes(read_array(&mut *r)?);
+        let enc_ciphertext = read_array(&mut *r)?;
+        let out_ciphertext = read_array(&mut *r)?;
+        let zkproof = read_output_proof(r)?;
+        Ok(OutputDescription::from_parts(
+            cv,
+            cmu,
+            ephemeral_key,
+            enc_ciphertext,
+            out_ciphertext,
+            zkproof,
+        ))
+    })?;
+    let bsk = read_scalar(&mut reader, "invalid Sapling binding signing key")?;
+
+    sapling::Bundle::from_parts(
+        spends,
+        outputs,
+        value_balance,
+        SaplingInProgress {
+            bsk,
+            _proof_state: PhantomData,
+        },
+    )
+    .ok_or_else(|| invalid_data("empty Sapling bundle"))
+}
+
+pub(super) fn write_sapling_spend_circuit<W: Write>(
+    mut writer: W,
+    circuit: &sapling::circuit::Spend,
+) -> io::Result<()> {
+    write_value_commitment_opening(&mut writer, &circuit.value_commitment_opening)?;
+    Optional::write(
+        &mut writer,
+        circuit.proof_generation_key.as_ref(),
+        |w, key| {
+            w.write_all(&jubjub::ExtendedPoint::from(&key.ak).to_bytes())?;
+            w.write_all(&key.nsk.to_repr())
+        },
+    )?;
+    Optional::write(&mut writer, circuit.payment_address.as_ref(), |w, addr| {
+        w.write_all(&addr.to_bytes())
+    })?;
+    Optional::write(&mut writer, circuit.commitment_randomness, |w, rcm| {
+        w.write_all(&rcm.to_repr())
+    })?;
+    Optional::write(&mut writer, circuit.ar, |w, ar| w.write_all(&ar.to_repr()))?;
+    Vector::write(&mut writer, &circuit.auth_path, |w, elem| {
+        Optional::write(w, *elem, |w, (node, is_right)| {
+            w.write_all(&node.to_repr())?;
+            w.write_u8(is_right.into())
+        })
+    })?;
+    Optional::write(writer, circuit.anchor, |mut w, anchor| {
+        w.write_all(&anchor.to_repr())
+    })
+}
+
+pub(super) fn read_sapling_spend_circuit<R: Read>(
+    mut reader: R,
+) -> io::Result<sapling::circuit::Spend> {
+    let value_commitment_opening = read_value_commitment_opening(&mut reader)?;
+    let proof_generation_key = Optional::read(&mut reader, read_proof_generation_key)?;
+    let payment_address = Optional::read(&mut reader, read_sapling_address)?;
+    let commitment_randomness = Optional::read(&mut reader, |r| {
+        read_scalar(r, "invalid Sapling note commitment randomness")
+    })?;
+    let ar = Optional::read(&mut reader, |r| {
+        read_scalar(r, "invalid Sapling spend authorization randomizer")
+    })?;
+    let auth_path = Vector::read(&mut reader, |r| {
+        Optional::read(r, |r| {
+            let node = read_base(&mut *r, "Merkle path node")?;
+            let is_right = match r.read_u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(invalid_data("invalid Sapling Merkle path")),
+            };
+            Ok((node, is_right))
+        })
+    })?;
+    let anchor = Optional::read(&mut reader, |r| read_base(r, "anchor"))?;
+
+    Ok(sapling::circuit::Spend {
+        value_commitment_opening,
+        proof_generation_key,
+        payment_address,
+        commitment_randomness,
+        ar,
+        auth_path,
+        anchor,
+    })
+}
+
+pub(super) fn write_sapling_output_circuit<W: Write>(
+    mut writer: W,
+    circuit: &sapling::circuit::Output,
+) -> io::Result<()> {
+    write_value_commitment_opening(&mut writer, &circuit.value_commitment_opening)?;
+    Optional::write(&mut writer, circuit.payment_address.as_ref(), |w, addr| {
+        w.write_all(&addr.to_bytes())
+    })?;
+    Optional::write(&mut writer, circuit.commitment_randomness, |w, rcm| {
+        w.write_all(&rcm.to_repr())
+    })?;
+    Optional::write(writer, circuit.esk, |mut w, esk| {
+        w.write_all(&esk.to_repr())
+    })
+}
+
+pub(super) fn read_sapling_output_circuit<R: Read>(
+    mut reader: R,
+) -> io::Result<sapling::circuit::Output> {
+    let value_commitment_opening = read_value_commitment_opening(&mut reader)?;
+    let payment_address = Optional::read(&mut reader, read_sapling_address)?;
+    let commitment_randomness = Optional::read(&mut reader, |r| {
+        read_scalar(r, "invalid Sapling note commitment randomness")
+    })?;
+    let esk = Optional::read(&mut reader, |r| {
+        read_scalar(r, "invalid Sapling ephemeral secret key")
+    })?;
+
+    Ok(sapling::circuit::Output {
+        value_commitment_opening,
+        payment_address,
+        commitment_randomness,
+        esk,
+    })
+}
+
+fn write_value_commitment_opening<W: Write>(
+    writer: W,
+    opening: &Option<ValueCommitmentOpening>,
+) -> io::Result<()> {
+    Optional::write(writer, opening.as_ref(), |mut w, opening| {
+        w.write_u64::<LittleEndian>(opening.value.inner())?;
+        w.write_all(&opening.randomness.to_repr())
+    })
+}
+
+fn read_value_commitment_opening<R: Read>(reader: R) -> io::Result<Option<ValueCommitmentOpening>> {
+    Optional::read(reader, |mut r| {
+        let value = NoteValue::from_raw(r.read_u64::<LittleEndian>()?);
+        let randomness = read_scalar(r, "invalid Sapling value commitment trapdoor")?;
+        Ok(ValueCommitmentOpening { value, randomness })
+    })
+}
+
+/// Reads a Sapling proof generation key.
+///
+/// The `sapling` crate only decodes a [`sapling::keys::SpendValidatingKey`] as part of a
+/// full viewing key, so `ak` is decoded as part of the full viewing key with the `nk` that
+/// is derived from `nsk`.
+fn read_proof_generation_key<R: Read>(mut reader: R) -> io::Result<ProofGenerationKey> {
+    let ak = read_array::<_, 32>(&mut reader)?;
+    let nsk: jubjub::Fr = read_scalar(&mut reader, "invalid Sapling proof authorizing key")?;
+    let nk = sapling::constants::PROOF_GENERATION_KEY_GENERATOR * nsk;
+
+    let mut fvk = [0; 96];
+    fvk[..32].copy_from_slice(&ak);
+    fvk[32..64].copy_from_slice(&nk.to_bytes());
+    let fvk = sapling::keys::FullViewingKey::read(&fvk[..])
+        .map_err(|_| invalid_data("invalid Sapling spend validating key"))?;
+
+    Ok(ProofGenerationKey { ak: fvk.vk.ak, nsk })
+}
+
+/// A spend that has been added to an [`OrchardBuilder`].
+pub(super) struct OrchardSpend {
+    fvk: FullViewingKey,
+    note: orchard::Note,
+    merkle_path: MerklePath<MerkleHashOrchard, 32>,
+    dummy_sk: Option<SpendingKey>,
+}
+
+impl OrchardSpend {
+    /// Generates a dummy spent note, as defined in [Zcash Protocol Spec § 4.8.3: Dummy
+    /// Notes (Orchard)][orcharddummynotes].
+    ///
+    /// [orcharddummynotes]: https://zips.z.cash/protocol/nu5.pdf#orcharddummynotes
+    fn dummy<R: RngCore>(mut rng: R) -> Self {
+        let sk = random_orchard_sk(&mut rng);
+        let fvk = FullViewingKey::from(&sk);
+        let recipient = fvk.address_at(0u32, Scope::External);
+        let note = loop {
+            let rho = Option::from(Rho::from_bytes(&pallas::Base::random(&mut rng).to_repr()))
+                .expect("valid field element");
+            if let Some(note) = random_orchard_note(
+                &mut rng,
+                recipient,
+                orchard::value::NoteValue::from_raw(0),
+                rho,
+            ) {
+                break note;
+            }
+        };
+
+        // The Merkle path of a dummy note is not checked by the circuit.
+        let merkle_path = MerklePath::from_parts(
+            iter::repeat_with(|| {
+                Option::from(MerkleHashOrchard::from_bytes(
+                    &pallas::Base::random(&mut rng).to_repr(),
+                ))
+                .expect("valid field element")
+            })
+            .take(32)
+            .collect(),
+            Position::from(0),
+        )
+        .expect("the path has the correct length");
+
+        OrchardSpend {
+            fvk,
+            note,
+            merkle_path,
+            dummy_sk: Some(sk),
+        }
+    }
+}
+
+/// An output that has been added to an [`OrchardBuilder`].
+pub(super) struct OrchardOutput {
+    ovk: Option<orchard::keys::OutgoingViewingKey>,
+    recipient: orchard::Address,
+    value: orchard::value::NoteValue,
+    memo: [u8; 512],
+}
+
+impl OrchardOutput {
+    fn dummy<R: RngCore>(rng: R) -> Self {
+        let fvk = FullViewingKey::from(&random_orchard_sk(rng));
+        OrchardOutput {
+            ovk: None,
+            recipient: fvk.address_at(0u32, Scope::External),
+            value: orchard::value::NoteValue::from_raw(0),
+            memo: *MemoBytes::empty().as_array(),
+        }
+    }
+}
+
+/// A builder for the Orchard bundle of a transaction.
+///
+/// This lays out the bundle in the same way as [`orchard::builder::Builder`], but keeps
+/// the data needed to prove and sign each action.
+pub(super) struct OrchardBuilder {
+    bundle_type: orchard::builder::BundleType,
+    anchor: orchard::Anchor,
+    spends: Vec<OrchardSpend>,
+    outputs: Vec<OrchardOutput>,
+}
+
+impl OrchardBuilder {
+    pub(super) fn new(bundle_type: orchard::builder::BundleType, anchor: orchard::Anchor) -> Self {
+        OrchardBuilder {
+            bundle_type,
+            anchor,
+            spends: vec![],
+            outputs: vec![],
+        }
+    }
+
+    /// Adds a note to be spent in this bundle.
+    ///
+    /// Returns an error if the given Merkle path does not have the required anchor for
+    /// the given note, or if `fvk` does not own the note.
+    pub(super) fn add_spend(
+        &mut self,
+        fvk: FullViewingKey,
+        note: orchard::Note,
+        merkle_path: MerklePath<MerkleHashOrchard, 32>,
+    ) -> Result<(), SpendError> {
+        if !self.bundle_type.flags().spends_enabled() {
+            return Err(SpendError::SpendsDisabled);
+        }
+        if fvk.scope_for_address(&note.recipient()).is_none() {
+            return Err(SpendError::FvkMismatch);
+        }
+        let path_root = orchard::tree::MerklePath::from(merkle_path.clone())
+            .root(ExtractedNoteCommitment::from(note.commitment()));
+        if path_root != self.anchor {
+            return Err(SpendError::AnchorMismatch);
+        }
+
+        self.spends.push(OrchardSpend {
+            fvk,
+            note,
+            merkle_path,
+            dummy_sk: None,
+        });
+        Ok(())
+    }
+
+    /// Adds an address which will receive funds in this bundle.
+    pub(super) fn add_output(
+        &mut self,
+        ovk: Option<orchard::keys::OutgoingViewingKey>,
+        recipient: orchard::Address,
+        value: orchard::value::NoteValue,
+        memo: Option<[u8; 512]>,
+    ) -> Result<(), OutputError> {
+        if !self.bundle_type.flags().outputs_enabled() {
+            return Err(OutputError);
+        }
+
+        self.outputs.push(OrchardOutput {
+            ovk,
+            recipient,
+            value,
+            memo: memo.unwrap_or_else(|| *MemoBytes::empty().as_array()),
+        });
+        Ok(())
+    }
+
+    /// Returns the spends that have been added to the bundle.
+    pub(super) fn spends(&self) -> &[OrchardSpend] {
+        &self.spends
+    }
+
+    /// Returns the outputs that have been added to the bundle.
+    pub(super) fn outputs(&self) -> &[OrchardOutput] {
+        &self.outputs
+    }
+
+    /// Returns the net value of the spends and outputs that have been added.
+    pub(super) fn value_balance<V: TryFrom<i64>>(&self) -> Result<V, OverflowError> {
+        let value_balance = self
+            .spends
+            .iter()
+            .map(|spend| i128::from(spend.note.value().inner()))
+            .sum::<i128>()
+            - self
+                .outputs
+                .iter()
+                .map(|output| i128::from(output.value.inner()))
+                .sum::<i128>();
+        i64::try_from(value_balance)
+            .ok()
+            .and_then(|value_balance| V::try_from(value_balance).ok())
+            .ok_or(OverflowError)
+    }
+
+    /// Builds the unproven bundle, or returns `None` if the bundle type does not require a
+    /// bundle and no spends or outputs have been added.
+    ///
+    /// The spends and outputs are padded with dummies to the number of actions required by
+    /// the bundle type, and shuffled.
+    #[allow(clippy::type_complexity)]
+    pub(super) fn build<R: RngCore>(
+        self,
+        mut rng: R,
+    ) -> Result<Option<(orchard::Bundle<OrchardUnproven, Amount>, OrchardMetadata)>, BuildError>
+    {
+        let value_balance = self.value_balance().map_err(BuildError::ValueSum)?;
+        let num_requested_spends = self.spends.len();
+        let num_requested_outputs = self.outputs.len();
+        let num_actions = self
+            .bundle_type
+            .num_actions(num_requested_spends, num_requested_outputs)
+            .map_err(|_| BuildError::BundleTypeNotSatisfiable)?;
+
+        let mut indexed_spends = self
+            .spends
+            .into_iter()
+            .map(Some)
+            .chain(iter::repeat_with(|| None))
+            .enumerate()
+            .take(num_actions)
+            .collect::<Vec<_>>();
+        let mut indexed_outputs = self
+            .outputs
+            .into_iter()
+            .map(Some)
+            .chain(iter::repeat_with(|| None))
+            .enumerate()
+            .take(num_actions)
+            .collect::<Vec<_>>();
+        indexed_spends.shuffle(&mut rng);
+        indexed_outputs.shuffle(&mut rng);
+
+        let mut meta = OrchardMetadata {
+            spend_indices: vec![0; num_requested_spends],
+            output_indices: vec![0; num_requested_outputs],
+        };
+        let mut bsk = pallas::Scalar::ZERO;
+        let mut actions = vec![];
+        for (action_index, ((spend_index, spend), (output_index, output))) in
+            indexed_spends.into_iter().zip(indexed_outputs).enumerate()
+        {
+            if spend_index < num_requested_spends {
+                meta.spend_indices[spend_index] = action_index;
+            }
+            if output_index < num_requested_outputs {
+                meta.output_indices[output_index] = action_index;
+            }
+            let spend = spend.unwrap_or_else(|| OrchardSpend::dummy(&mut rng));
+            let output = output.unwrap_or_else(|| OrchardOutput::dummy(&mut rng));
+
+            let nf = spend.note.nullifier(&spend.fvk);
+            let rho = Option::from(Rho::from_bytes(&nf.to_bytes()))
+                .expect("nullifiers are valid rho values");
+            let output_note = loop {
+                if let Some(note) =
+                    random_orchard_note(&mut rng, output.recipient, output.value, rho)
+                {
+                    break note;
+                }
+            };
+            let cmx = ExtractedNoteCommitment::from(output_note.commitment());
+
+            let alpha = pallas::Scalar::random(&mut rng);
+            let rk = SpendValidatingKey::from(spend.fvk.clone()).randomize(&alpha);
+
+            let rcv = pallas::Scalar::random(&mut rng);
+            let cv_net = ValueCommitment::derive(
+                spend.note.value() - output_note.value(),
+                orchard_rcv(&rcv),
+            );
+
+            let encryptor = OrchardNoteEncryption::new(output.ovk, output_note, output.memo);
+            let encrypted_note = TransmittedNoteCiphertext {
+                epk_bytes: OrchardDomain::epk_bytes(encryptor.epk()).0,
+                enc_ciphertext: encryptor.encrypt_note_plaintext(),
+                out_ciphertext: encryptor.encrypt_outgoing_plaintext(&cv_net, &cmx, &mut rng),
+            };
+
+            bsk += rcv;
+
+            actions.push(orchard::Action::from_parts(
+                nf,
+                rk,
+                cmx,
+                encrypted_note,
+                cv_net,
+                OrchardProvingParts {
+                    fvk: spend.fvk,
+                    spent_note: spend.note,
+                    merkle_path: spend.merkle_path,
+                    output_note,
+                    rcv,
+                    signing_parts: OrchardSigningParts {
+                        alpha,
+                        dummy_sk: spend.dummy_sk,
+                    },
+                },
+            ));
+        }
+
+        Ok(NonEmpty::from_vec(actions).map(|actions| {
+            (
+                orchard::Bundle::from_parts(
+                    actions,
+                    self.bundle_type.flags(),
+                    value_balance,
+                    self.anchor,
+                    OrchardUnproven { bsk },
+                ),
+                meta,
+            )
+        }))
+    }
+}
+
+/// The authorizing data of an Orchard bundle under construction, which has not yet been
+/// proven.
+#[derive(Debug)]
+pub struct OrchardUnproven {
+    bsk: pallas::Scalar,
+}
+
+impl orchard::bundle::Authorization for OrchardUnproven {
+    type SpendAuth = OrchardProvingParts;
+}
+
+/// The private data of an Orchard action that is needed to create its proof and signature.
+#[derive(Clone, Debug)]
+pub struct OrchardProvingParts {
+    fvk: FullViewingKey,
+    spent_note: orchard::Note,
+    merkle_path: MerklePath<MerkleHashOrchard, 32>,
+    output_note: orchard::Note,
+    rcv: pallas::Scalar,
+    signing_parts: OrchardSigningParts,
+}
+
+/// The authorizing data of an Orchard bundle under construction, which has been proven but
+/// not yet signed.
+#[derive(Debug)]
+pub struct OrchardProven {
+    proof: orchard::Proof,
+    bsk: pallas::Scalar,
+}
+
+impl orchard::bundle::Authorization for OrchardProven {
+    type SpendAuth = OrchardSigningParts;
+}
+
+/// The data needed to create the spend authorization signature for an Orchard action.
+#[derive(Clone, Debug)]
+pub struct OrchardSigningParts {
+    alpha: pallas::Scalar,
+    /// The spending key of the action's spent note, if that note is a dummy.
+    dummy_sk: Option<SpendingKey>,
+}
+
+/// Creates the proof for an unproven Orchard bundle.
+pub(super) fn create_orchard_proof<R: RngCore>(
+    bundle: orchard::Bundle<OrchardUnproven, Amount>,
+    pk: &orchard::circuit::ProvingKey,
+    rng: R,
+) -> Result<orchard::Bundle<OrchardProven, Amount>, BuildError> {
+    let flags = bundle.flags();
+    let anchor = *bundle.anchor();
+    let (circuits, instances): (Vec<_>, Vec<_>) = bundle
+        .actions()
+        .iter()
+        .map(|action| {
+            let parts = action.authorization();
+            let spend = orchard::builder::SpendInfo::new(
+                parts.fvk.clone(),
+                parts.spent_note,
+                parts.merkle_path.clone().into(),
+            )
+            .expect("the spent note is owned by its full viewing key");
+            let circuit = orchard::circuit::Circuit::from_action_context(
+                spend,
+                parts.output_note,
+                parts.signing_parts.alpha,
+                orchard_rcv(&parts.rcv),
+            )
+            .expect("the output note's rho is the spent note's nullifier");
+            let instance = orchard::circuit::Instance::from_parts(
+                anchor,
+                action.cv_net().clone(),
+                *action.nullifier(),
+                action.rk().clone(),
+                *action.cmx(),
+                flags.spends_enabled(),
+                flags.outputs_enabled(),
+            );
+            (circuit, instance)
+        })
+        .unzip();
+
+    let proof =
+        orchard::Proof::create(pk, &circuits, &instances, rng).map_err(BuildError::Proof)?;
+
+    Ok(bundle.map_authorization(
+        &mut (),
+        |_, _, parts| parts.signing_parts,
+        |_, auth| OrchardProven {
+            proof,
+            bsk: auth.bsk,
+        },
+    ))
+}
+
+/// Signs a proven Orchard bundle with the given spend authorizing keys.
+///
+/// The spends of dummy notes are signed with their own keys. Returns
+/// [`BuildError::MissingSignatures`] if none of the keys authorizes one of the bundle's
+/// other spends.
+pub(super) fn apply_orchard_signatures<R: RngCore + CryptoRng>(
+    bundle: orchard::Bundle<OrchardProven, Amount>,
+    mut rng: R,
+    sighash: [u8; 32],
+    saks: &[SpendAuthorizingKey],
+) -> Result<orchard::Bundle<orchard::bundle::Authorized, Amount>, BuildError> {
+    let spend_auth_sigs = bundle
+        .actions()
+        .iter()
+        .map(|action| {
+            let parts = action.authorization();
+            let rk = <[u8; 32]>::from(action.rk());
+            let dummy_ask = parts.dummy_sk.as_ref().map(SpendAuthorizingKey::from);
+            dummy_ask
+                .iter()
+                .chain(saks)
+                .map(|ask| ask.randomize(&parts.alpha))
+                .find(|rsk| <[u8; 32]>::from(&redpallas::VerificationKey::from(rsk)) == rk)
+                .map(|rsk| rsk.sign(&mut rng, &sighash))
+                .ok_or(BuildError::MissingSignatures)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let binding_signature =
+        redpallas::SigningKey::<redpallas::Binding>::try_from(bundle.authorization().bsk.to_repr())
+            .expect("bsk is a valid scalar")
+            .sign(&mut rng, &sighash);
+
+    Ok(bundle.map_authorization(
+        &mut spend_auth_sigs.into_iter(),
+        |sigs, _, _| sigs.next().expect("there is one signature per action"),
+        |_, auth| orchard::bundle::Authorized::from_parts(auth.proof, binding_signature),
+    ))
+}
+
+/// Writes an Orchard bundle under construction.
+pub(super) fn write_orchard_bundle<W: Write, A: orchard::bundle::Authorization>(
+    mut writer: W,
+    bundle: &orchard::Bundle<A, Amount>,
+    write_action_parts: impl Fn(&mut W, &A::SpendAuth) -> io::Result<()>,
+    write_authorization: impl FnOnce(&mut W, &A) -> io::Result<()>,
+) -> io::Result<()> {
+    writer.write_u8(bundle.flags().to_byte())?;
+    writer.write_i64::<LittleEndian>(i64::from(*bundle.value_balance()))?;
+    writer.write_all(&bundle.anchor().to_bytes())?;
+    Vector::write_nonempty(&mut writer, bundle.actions(), |w, action| {
+        orchard_serialization::write_action_without_auth(&mut *w, action)?;
+        write_action_parts(w, action.authorization())
+    })?;
+    write_authorization(&mut writer, bundle.authorization())
+}
+
+/// Reads an Orchard bundle that was written by [`write_orchard_bundle`].
+pub(super) fn read_orchard_bundle<R: Read, A: orchard::bundle::Authorization>(
+    mut reader: R,
+    read_action_parts: impl Fn(&mut R, &orchard::Action<()>) -> io::Result<A::SpendAuth>,
+    read_authorization: impl FnOnce(&mut R) -> io::Result<A>,
+) -> io::Result<orchard::Bundle<A, Amount>> {
+    let flags = orchard_serialization::read_flags(&mut reader)?;
+    let value_balance = Amount::from_i64(reader.read_i64::<LittleEndian>()?)
+        .map_err(|_| invalid_data("invalid Orchard value balance"))?;
+    let anchor = orchard_serialization::read_anchor(&mut reader)?;
+    let actions = Vector::read(&mut reader, |r| {
+        let action = orchard_serialization::read_action_without_auth(&mut *r)?;
+        let parts = read_action_parts(r, &action)?;
+        Ok(action.map(|_| parts))
+    })?;
+    let actions =
+        NonEmpty::from_vec(actions).ok_or_else(|| invalid_data("empty Orchard bundle"))?;
+    let authorization = read_authorization(&mut reader)?;
+
+    Ok(orchard::Bundle::from_parts(
+        actions,
+        flags,
+        value_balance,
+        anchor,
+        authorization,
+    ))
+}
+
+pub(super) fn write_orchard_proving_parts<W: Write>(
+    mut writer: W,
+    parts: &OrchardProvingParts,
+) -> io::Result<()> {
+    writer.write_all(&parts.fvk.to_bytes())?;
+    write_orchard_note(&mut writer, &parts.spent_note)?;
+    writer.write_all(&parts.spent_note.rho().to_bytes())?;
+    write_merkle_path(&mut writer, &parts.merkle_path)?;
+    write_orchard_note(&mut writer, &parts.output_note)?;
+    writer.write_all(&parts.rcv.to_repr())?;
+    write_orchard_signing_parts(writer, &parts.signing_parts)
+}
+
+/// Reads the private data of the given Orchard action.
+///
+/// Returns an error if the data is inconsistent with the action.
+pub(super) fn read_orchard_proving_parts<R: Read>(
+    mut reader: R,
+    action: &orchard::Action<()>,
+) -> io::Result<OrchardProvingParts> {
+    let fvk = FullViewingKey::from_bytes(&read_array(&mut reader)?)
+        .ok_or_else(|| invalid_data("invalid Orchard full viewing key"))?;
+    let spent_note = {
+        let (recipient, value, rseed) = read_orchard_note_parts(&mut reader)?;
+        let rho = Option::from(Rho::from_bytes(&read_array(&mut reader)?))
+            .ok_or_else(|| invalid_data("invalid Orchard note rho"))?;
+        orchard_note(recipient, value, rho, rseed)?
+    };
+    if fvk.scope_for_address(&spent_note.recipient()).is_none()
+        || spent_note.nullifier(&fvk) != *action.nullifier()
+    {
+        return Err(invalid_data("Orchard spent note does not match its action"));
+    }
+    let merkle_path = read_merkle_path(&mut reader)?;
+    let output_note = {
+        let (recipient, value, rseed) = read_orchard_note_parts(&mut reader)?;
+        orchard_note(recipient, value, action.rho(), rseed)?
+    };
+    if ExtractedNoteCommitment::from(output_note.commitment()) != *action.cmx() {
+        return Err(invalid_data(
+            "Orchard output note does not match its action",
+        ));
+    }
+    let rcv = read_scalar(&mut reader, "invalid Orchard value commitment trapdoor")?;
+    let signing_parts = read_orchard_signing_parts(reader)?;
+
+    Ok(OrchardProvingParts {
+        fvk,
+        spent_note,
+        merkle_path,
+        output_note,
+        rcv,
+        signing_parts,
+    })
+}
+
+pub(super) fn write_orchard_signing_parts<W: Write>(
+    mut writer: W,
+    parts: &OrchardSigningParts,
+) -> io::Result<()> {
+    writer.write_all(&parts.alpha.to_repr())?;
+    Optional::write(writer, parts.dummy_sk.as_ref(), |mut w, sk| {
+        w.write_all(sk.to_bytes())
+    })
+}
+
+pub(super) fn read_orchard_signing_parts<R: Read>(
+    mut reader: R,
+) -> io::Result<OrchardSigningParts> {
+    let alpha = read_scalar(
+        &mut reader,
+        "invalid Orchard spend authorization randomizer",
+    )?;
+    let dummy_sk = Optional::read(reader, |r| {
+        Option::from(SpendingKey::from_bytes(read_array(r)?))
+            .ok_or_else(|| invalid_data("invalid Orchard spending key"))
+    })?;
+    Ok(OrchardSigningParts { alpha, dummy_sk })
+}
+
+pub(super) fn write_orchard_unproven<W: Write>(
+    mut writer: W,
+    auth: &OrchardUnproven,
+) -> io::Result<()> {
+    writer.write_all(&auth.bsk.to_repr())
+}
+
+pub(super) fn read_orchard_unproven<R: Read>(reader: R) -> io::Result<OrchardUnproven> {
+    Ok(OrchardUnproven {
+        bsk: read_scalar(reader, "invalid Orchard binding signing key")?,
+    })
+}
+
+pub(super) fn write_orchard_proven<W: Write>(
+    mut writer: W,
+    auth: &OrchardProven,
+) -> io::Result<()> {
+    Vector::write(&mut writer, auth.proof.as_ref(), |w, b| w.write_u8(*b))?;
+    writer.write_all(&auth.bsk.to_repr())
+}
+
+pub(super) fn read_orchard_proven<R: Read>(mut reader: R) -> io::Result<OrchardProven> {
+    let proof = orchard::Proof::new(Vector::read(&mut reader, |r| r.read_u8())?);
+    let bsk = read_scalar(reader, "invalid Orchard binding signing key")?;
+    Ok(OrchardProven { proof, bsk })
+}
+
+fn orchard_rcv(rcv: &pallas::Scalar) -> ValueCommitTrapdoor {
+    Option::from(ValueCommitTrapdoor::from_bytes(rcv.to_repr())).expect("valid scalar")
+}
+
+fn random_orchard_sk<R: RngCore>(mut rng: R) -> SpendingKey {
+    loop {
+        let mut bytes = [0; 32];
+        rng.fill_bytes(&mut bytes);
+        if let Some(sk) = Option::from(SpendingKey::from_bytes(bytes)) {
+            break sk;
+        }
+    }
+}
+
+/// Returns a note with a random `rseed`, or `None` if the `rseed` is not valid for the
+/// note.
+fn random_orchard_note<R: RngCore>(
+    mut rng: R,
+    recipient: orchard::Address,
+    value: orchard::value::NoteValue,
+    rho: Rho,
+) -> Option<orchard::Note> {
+    let mut rseed = [0; 32];
+    rng.fill_bytes(&mut rseed);
+    Option::from(RandomSeed::from_bytes(rseed, &rho))
+        .and_then(|rseed| Option::from(orchard::Note::from_parts(recipient, value, rho, rseed)))
+}
+
+fn write_orchard_note<W: Write>(mut writer: W, note: &orchard::Note) -> io::Result<()> {
+    writer.write_all(&note.recipient().to_raw_address_bytes())?;
+    writer.write_u64::<LittleEndian>(note.value().inner())?;
+    writer.write_all(note.rseed().as_bytes())
+}
+
+fn read_orchard_note_parts<R: Read>(
+    mut reader: R,
+) -> io::Result<(orchard::Address, orchard::value::NoteValue, [u8; 32])> {
+    let recipient = Option::from(orchard::Address::from_raw_address_bytes(&read_array(
+        &mut reader,
+    )?))
+    .ok_or_else(|| invalid_data("invalid Orchard address"))?;
+    let value = orchard::value::NoteValue::from_raw(reader.read_u64::<LittleEndian>()?);
+    let rseed = read_array(reader)?;
+    Ok((recipient, value, rseed))
+}
+
+fn orchard_note(
+    recipient: orchard::Address,
+    value: orchard::value::NoteValue,
+    rho: Rho,
+    rseed: [u8; 32],
+) -> io::Result<orchard::Note> {
+    Option::from(RandomSeed::from_bytes(rseed, &rho))
+        .and_then(|rseed| Option::from(orchard::Note::from_parts(recipient, value, rho, rseed)))
+        .ok_or_else(|| invalid_data("invalid Orchard note"))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_array<R: Read, const N: usize>(mut reader: R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_scalar<R: Read, F: PrimeField<Repr = [u8; 32]>>(reader: R, msg: &str) -> io::Result<F> {
+    Option::from(F::from_repr(read_array(reader)?)).ok_or_else(|| invalid_data(msg))
+}
+
+fn read_sapling_address<R: Read>(reader: R) -> io::Result<PaymentAddress> {
+    PaymentAddress::from_bytes(&read_array(reader)?)
+        .ok_or_else(|| invalid_data("invalid Sapling payment address"))
+}
+
+fn write_merkle_path<H: HashSer, W: Write>(
+    mut writer: W,
+    merkle_path: &MerklePath<H, 32>,
+) -> io::Result<()> {
+    write_position(&mut writer, merkle_path.position())?;
+    merkle_path
+        .path_elems()
+        .iter()
+        .try_for_each(|node| node.write(&mut writer))
+}
+
+fn read_merkle_path<H: HashSer, R: Read>(mut reader: R) -> io::Result<MerklePath<H, 32>> {
+    let position = read_position(&mut reader)?;
+    let path_elems = (0..32)
+        .map(|_| H::read(&mut reader))
+        .collect::<io::Result<Vec<_>>>()?;
+    MerklePath::from_parts(path_elems, position).map_err(|()| invalid_data("invalid Merkle path"))
+}
@@ -253,10 +253,7 @@ pub fn write_note_ciphertext<W: Write>(
     writer.write_all(&nc.out_ciphertext)
 }
 
-pub fn write_action_without_auth<W: Write>(
-    mut writer: W,
-    act: &Action<<Authorized as Authorization>::SpendAuth>,
-) -> io::Result<()> {
+pub fn write_action_without_auth<W: Write, A>(mut writer: W, act: &Action<A>) -> io::Result<()> {
     write_value_commitment(&mut writer, act.cv_net())?;
     write_nullifier(&mut writer, act.nullifier())?;
     write_verification_key(&mut writer, act.rk())?;
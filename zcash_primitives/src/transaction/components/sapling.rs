@@ -87,7 +87,7 @@ impl MapAuth<Authorized, Authorized> for () {
 /// Consensus rules (§4.4) & (§4.5):
 /// - Canonical encoding is enforced here.
 /// - "Not small order" is enforced here.
-fn read_value_commitment<R: Read>(mut reader: R) -> io::Result<ValueCommitment> {
+pub(crate) fn read_value_commitment<R: Read>(mut reader: R) -> io::Result<ValueCommitment> {
     let mut bytes = [0u8; 32];
     reader.read_exact(&mut bytes)?;
     let cv = ValueCommitment::from_bytes_not_small_order(&bytes);
@@ -101,7 +101,7 @@ fn read_value_commitment<R: Read>(mut reader: R) -> io::Result<ValueCommitment>
 
 /// Consensus rules (§7.3) & (§7.4):
 /// - Canonical encoding is enforced here
-fn read_cmu<R: Read>(mut reader: R) -> io::Result<ExtractedNoteCommitment> {
+pub(crate) fn read_cmu<R: Read>(mut reader: R) -> io::Result<ExtractedNoteCommitment> {
     let mut f = [0u8; 32];
     reader.read_exact(&mut f)?;
     Option::from(ExtractedNoteCommitment::from_bytes(&f))
@@ -133,7 +133,7 @@ pub fn read_zkproof<R: Read>(mut reader: R) -> io::Result<GrothProofBytes> {
     Ok(zkproof)
 }
 
-fn read_nullifier<R: Read>(mut reader: R) -> io::Result<Nullifier> {
+pub(crate) fn read_nullifier<R: Read>(mut reader: R) -> io::Result<Nullifier> {
     let mut nullifier = Nullifier([0u8; 32]);
     reader.read_exact(&mut nullifier.0)?;
     Ok(nullifier)
@@ -142,7 +142,7 @@ fn read_nullifier<R: Read>(mut reader: R) -> io::Result<Nullifier> {
 /// Consensus rules (§4.4):
 /// - Canonical encoding is enforced here.
 /// - "Not small order" is enforced in SaplingVerificationContext::check_spend()
-fn read_rk<R: Read>(mut reader: R) -> io::Result<redjubjub::VerificationKey<SpendAuth>> {
+pub(crate) fn read_rk<R: Read>(mut reader: R) -> io::Result<redjubjub::VerificationKey<SpendAuth>> {
     let mut bytes = [0; 32];
     reader.read_exact(&mut bytes)?;
     redjubjub::VerificationKey::try_from(bytes)
@@ -1,6 +1,9 @@
 //! Types and functions for building transparent transaction components.
 
 use std::fmt;
+use std::io::{self, Read, Write};
+
+use zcash_encoding::Vector;
 
 use crate::{
     legacy::{Script, TransparentAddress},
@@ -13,22 +16,39 @@ use crate::{
     },
 };
 
+#[cfg(not(feature = "transparent-inputs"))]
+use zcash_encoding::CompactSize;
+
 #[cfg(feature = "transparent-inputs")]
 use {
+    crate::legacy::MAX_P2SH_MULTISIG_KEYS,
     crate::transaction::{
         self as tx,
         components::transparent::OutPoint,
+        fees::transparent::{InputSize, InputView},
         sighash::{signature_hash, SignableInput, SIGHASH_ALL},
         TransactionData, TxDigests,
     },
     blake2b_simd::Hash as Blake2bHash,
+    byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt},
     sha2::Digest,
+    std::collections::BTreeMap,
 };
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Error {
     InvalidAddress,
     InvalidAmount,
+    /// The redeem script for a P2SH input is not a standard multisig script.
+    InvalidRedeemScript,
+    /// The given index does not correspond to a P2SH multisig input.
+    NotMultisigInput(usize),
+    /// A signature is not valid for the input it was provided for, or was not created by
+    /// one of the keys in the input's redeem script.
+    InvalidSignature,
+    /// A P2SH multisig input at the given index has fewer signatures than its redeem
+    /// script requires.
+    MissingSignatures(usize),
 }
 
 impl fmt::Display for Error {
@@ -36,15 +56,50 @@ impl fmt::Display for Error {
         match self {
             Error::InvalidAddress => write!(f, "Invalid address"),
             Error::InvalidAmount => write!(f, "Invalid amount"),
+            Error::InvalidRedeemScript => {
+                write!(f, "Redeem script is not a standard multisig script")
+            }
+            Error::NotMultisigInput(index) => {
+                write!(
+                    f,
+                    "Transparent input {} is not a P2SH multisig input",
+                    index
+                )
+            }
+            Error::InvalidSignature => write!(f, "Invalid signature for transparent input"),
+            Error::MissingSignatures(index) => write!(
+                f,
+                "Transparent input {} does not have enough signatures",
+                index
+            ),
         }
     }
 }
 
+/// The data required to authorize the spend of a transparent input.
+#[cfg(feature = "transparent-inputs")]
+#[derive(Debug, Clone)]
+enum InputSigner {
+    /// A P2PKH input, which is signed with the given secret key.
+    P2pkh {
+        sk: secp256k1::SecretKey,
+        pubkey: [u8; secp256k1::constants::PUBLIC_KEY_SIZE],
+    },
+    /// A P2SH multisig input, which is authorized by signatures collected from the keys
+    /// in its redeem script.
+    P2shMultisig {
+        redeem_script: Script,
+        required: usize,
+        pubkeys: Vec<[u8; secp256k1::constants::PUBLIC_KEY_SIZE]>,
+        /// Signatures (with the sighash type appended) indexed by public key.
+        signatures: BTreeMap<[u8; secp256k1::constants::PUBLIC_KEY_SIZE], Vec<u8>>,
+    },
+}
+
 #[cfg(feature = "transparent-inputs")]
 #[derive(Debug, Clone)]
 pub struct TransparentInputInfo {
-    sk: secp256k1::SecretKey,
-    pubkey: [u8; secp256k1::constants::PUBLIC_KEY_SIZE],
+    signer: InputSigner,
     utxo: OutPoint,
     coin: TxOut,
 }
@@ -58,6 +113,120 @@ impl TransparentInputInfo {
     pub fn coin(&self) -> &TxOut {
         &self.coin
     }
+
+    /// Returns the script that is committed to by the signatures for this input.
+    fn script_code(&self) -> &Script {
+        match &self.signer {
+            // for p2pkh, always the same as script_pubkey
+            InputSigner::P2pkh { .. } => &self.coin.script_pubkey,
+            InputSigner::P2shMultisig { redeem_script, .. } => redeem_script,
+        }
+    }
+
+    /// Writes this input, along with the data required to authorize it.
+    ///
+    /// Returns an error for P2PKH inputs, because writing them would expose their secret
+    /// keys.
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        match &self.signer {
+            InputSigner::P2pkh { .. } => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "P2PKH inputs cannot be serialized without exposing their secret keys",
+            )),
+            InputSigner::P2shMultisig {
+                redeem_script,
+                signatures,
+                ..
+            } => {
+                self.utxo.write(&mut writer)?;
+                self.coin.write(&mut writer)?;
+                redeem_script.write(&mut writer)?;
+                Vector::write_sized(&mut writer, signatures.iter(), |w, (pubkey, sig)| {
+                    w.write_all(pubkey)?;
+                    Vector::write(w, sig, |w, b| w.write_u8(*b))
+                })
+            }
+        }
+    }
+
+    /// Reads an input that was written with [`Self::write`].
+    ///
+    /// The signatures of the input are not checked; see
+    /// [`Bundle::verify_multisig_signatures`].
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let utxo = OutPoint::read(&mut reader)?;
+        let coin = TxOut::read(&mut reader)?;
+        let redeem_script = Script::read(&mut reader)?;
+
+        let (required, pubkeys) = redeem_script.parse_multisig().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "redeem script is not a standard multisig script",
+            )
+        })?;
+        if coin.script_pubkey.address()
+            != Some(TransparentAddress::from_redeem_script(&redeem_script))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "redeem script does not match the address of the spent coin",
+            ));
+        }
+
+        let signatures = Vector::read_collected(&mut reader, |r| {
+            let mut pubkey = [0; secp256k1::constants::PUBLIC_KEY_SIZE];
+            r.read_exact(&mut pubkey)?;
+            if !pubkeys.contains(&pubkey) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "signature is for a public key that is not in the redeem script",
+                ));
+            }
+            let sig = Vector::read(r, |r| r.read_u8())?;
+            Ok((pubkey, sig))
+        })?;
+
+        Ok(TransparentInputInfo {
+            signer: InputSigner::P2shMultisig {
+                redeem_script,
+                required,
+                pubkeys,
+                signatures,
+            },
+            utxo,
+            coin,
+        })
+    }
+}
+
+#[cfg(feature = "transparent-inputs")]
+impl InputView for TransparentInputInfo {
+    fn outpoint(&self) -> &OutPoint {
+        self.outpoint()
+    }
+
+    fn coin(&self) -> &TxOut {
+        self.coin()
+    }
+
+    fn serialized_size(&self) -> InputSize {
+        match &self.signer {
+            InputSigner::P2pkh { .. } => InputSize::STANDARD_P2PKH,
+            InputSigner::P2shMultisig {
+                redeem_script,
+                required,
+                ..
+            } => {
+                // The largest DER-encoded signature is 72 bytes, plus the sighash type.
+                // Pushing empty data is equivalent to OP_0.
+                let script_sig = Script::default() << &[][..];
+                let script_sig = (0..*required).fold(script_sig, |s, _| s << &[0; 73][..]);
+                let script_sig = script_sig << &redeem_script.0[..];
+                // outpoint + scriptSig + nSequence
+                InputSize::Known(36 + script_sig.serialized_size() + 4)
+            }
+        }
+    }
 }
 
 pub struct TransparentBuilder {
@@ -129,8 +298,45 @@ impl TransparentBuilder {
         }
 
         self.inputs.push(TransparentInputInfo {
-            sk,
-            pubkey,
+            signer: InputSigner::P2pkh { sk, pubkey },
+            utxo,
+            coin,
+        });
+
+        Ok(())
+    }
+
+    /// Adds a coin sent to a P2SH multisig address to be spent in the transaction.
+    ///
+    /// `redeem_script` must be a standard multisig script over at most 15 keys (see
+    /// [`Script::multisig`]) that hashes to the coin's address. The input must be
+    /// authorized by signatures from the keys in the redeem script, which are provided
+    /// once the transaction has been built (see [`Bundle::append_multisig_signature`]).
+    #[cfg(feature = "transparent-inputs")]
+    pub fn add_p2sh_multisig_input(
+        &mut self,
+        redeem_script: Script,
+        utxo: OutPoint,
+        coin: TxOut,
+    ) -> Result<(), Error> {
+        let (required, pubkeys) = redeem_script
+            .parse_multisig()
+            .filter(|(_, pubkeys)| pubkeys.len() <= MAX_P2SH_MULTISIG_KEYS)
+            .ok_or(Error::InvalidRedeemScript)?;
+
+        if coin.script_pubkey.address()
+            != Some(TransparentAddress::from_redeem_script(&redeem_script))
+        {
+            return Err(Error::InvalidAddress);
+        }
+
+        self.inputs.push(TransparentInputInfo {
+            signer: InputSigner::P2shMultisig {
+                redeem_script,
+                required,
+                pubkeys,
+                signatures: BTreeMap::new(),
+            },
             utxo,
             coin,
         });
@@ -239,45 +445,255 @@ impl TransparentAuthorizingContext for Unauthorized {
 }
 
 impl Bundle<Unauthorized> {
+    /// Writes this bundle, along with the data required to authorize its inputs.
+    ///
+    /// P2SH multisig inputs are written along with the signatures that have been appended
+    /// to them. Returns an error if the bundle contains P2PKH inputs, because writing them
+    /// would expose their secret keys.
+    pub fn write_unauthorized<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        #[cfg(feature = "transparent-inputs")]
+        Vector::write_sized(
+            &mut writer,
+            self.vin.iter().zip(self.authorization.inputs.iter()),
+            |w, (txin, input)| {
+                input.write(&mut *w)?;
+                w.write_u32::<LittleEndian>(txin.sequence)
+            },
+        )?;
+        #[cfg(not(feature = "transparent-inputs"))]
+        CompactSize::write(&mut writer, 0)?;
+
+        Vector::write(&mut writer, &self.vout, |w, out| out.write(w))
+    }
+
+    /// Reads a bundle that was written with [`Self::write_unauthorized`].
+    ///
+    /// The signatures of P2SH multisig inputs are not checked, because this requires the
+    /// transaction containing the bundle; see [`Self::verify_multisig_signatures`].
+    pub fn read_unauthorized<R: Read>(mut reader: R) -> io::Result<Self> {
+        #[cfg(feature = "transparent-inputs")]
+        let (vin, inputs): (Vec<_>, Vec<_>) = Vector::read(&mut reader, |r| {
+            let input = TransparentInputInfo::read(&mut *r)?;
+            let sequence = r.read_u32::<LittleEndian>()?;
+            Ok((
+                TxIn {
+                    prevout: input.utxo.clone(),
+                    script_sig: (),
+                    sequence,
+                },
+                input,
+            ))
+        })?
+        .into_iter()
+        .unzip();
+        #[cfg(not(feature = "transparent-inputs"))]
+        let vin =
+            match CompactSize::read(&mut reader)? {
+                0 => vec![],
+                _ => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "transparent inputs are not supported without the transparent-inputs feature",
+                )),
+            };
+
+        let vout = Vector::read(&mut reader, TxOut::read)?;
+
+        Ok(transparent::Bundle {
+            vin,
+            vout,
+            authorization: Unauthorized {
+                #[cfg(feature = "transparent-inputs")]
+                secp: secp256k1::Secp256k1::gen_new(),
+                #[cfg(feature = "transparent-inputs")]
+                inputs,
+            },
+        })
+    }
+
+    /// Checks that every signature appended to the P2SH multisig inputs of this bundle is
+    /// valid.
+    ///
+    /// `mtx` must be the transaction containing this bundle.
+    #[cfg(feature = "transparent-inputs")]
+    pub fn verify_multisig_signatures(
+        &self,
+        mtx: &TransactionData<tx::Unauthorized>,
+        txid_parts_cache: &TxDigests<Blake2bHash>,
+    ) -> Result<(), Error> {
+        let verifier = secp256k1::Secp256k1::verification_only();
+        for (index, input) in self.authorization.inputs.iter().enumerate() {
+            if let InputSigner::P2shMultisig { signatures, .. } = &input.signer {
+                let sighash = self
+                    .input_sighash(index, mtx, txid_parts_cache)
+                    .expect("index is in range");
+                let msg = secp256k1::Message::from_slice(&sighash).expect("32 bytes");
+                for (pubkey, sig) in signatures {
+                    let (hash_type, sig) = sig.split_last().ok_or(Error::InvalidSignature)?;
+                    let valid = *hash_type == SIGHASH_ALL
+                        && secp256k1::ecdsa::Signature::from_der(sig)
+                            .ok()
+                            .zip(secp256k1::PublicKey::from_slice(pubkey).ok())
+                            .map_or(false, |(sig, pubkey)| {
+                                verifier.verify_ecdsa(&msg, &sig, &pubkey).is_ok()
+                            });
+                    if !valid {
+                        return Err(Error::InvalidSignature);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the sighash that must be signed to authorize the input at the given index.
+    ///
+    /// `mtx` must be the transaction containing this bundle.
+    #[cfg(feature = "transparent-inputs")]
+    pub fn input_sighash(
+        &self,
+        index: usize,
+        mtx: &TransactionData<tx::Unauthorized>,
+        txid_parts_cache: &TxDigests<Blake2bHash>,
+    ) -> Option<[u8; 32]> {
+        let info = self.authorization.inputs.get(index)?;
+        let sighash = signature_hash(
+            mtx,
+            &SignableInput::Transparent {
+                hash_type: SIGHASH_ALL,
+                index,
+                script_code: info.script_code(),
+                script_pubkey: &info.coin.script_pubkey,
+                value: info.coin.value,
+            },
+            txid_parts_cache,
+        );
+        Some(*sighash.as_ref())
+    }
+
+    /// Adds a signature by the given public key to the P2SH multisig input at the given
+    /// index.
+    ///
+    /// `sighash` must be the input's sighash, as returned by [`Self::input_sighash`].
+    /// Returns an error if the public key is not in the input's redeem script, or if the
+    /// signature is not valid.
+    #[cfg(feature = "transparent-inputs")]
+    pub fn append_multisig_signature(
+        &mut self,
+        index: usize,
+        sighash: &[u8; 32],
+        pubkey: &secp256k1::PublicKey,
+        signature: &secp256k1::ecdsa::Signature,
+    ) -> Result<(), Error> {
+        match self
+            .authorization
+            .inputs
+            .get_mut(index)
+            .map(|i| &mut i.signer)
+        {
+            Some(InputSigner::P2shMultisig {
+                pubkeys,
+                signatures,
+                ..
+            }) => {
+                let pubkey_bytes = pubkey.serialize();
+                if !pubkeys.contains(&pubkey_bytes) {
+                    return Err(Error::InvalidSignature);
+                }
+
+                let msg = secp256k1::Message::from_slice(sighash).expect("32 bytes");
+                secp256k1::Secp256k1::verification_only()
+                    .verify_ecdsa(&msg, signature, pubkey)
+                    .map_err(|_| Error::InvalidSignature)?;
+
+                // Signature has to have "SIGHASH_ALL" appended to it
+                let mut sig_bytes: Vec<u8> = signature.serialize_der()[..].to_vec();
+                sig_bytes.extend([SIGHASH_ALL]);
+
+                signatures.insert(pubkey_bytes, sig_bytes);
+                Ok(())
+            }
+            _ => Err(Error::NotMultisigInput(index)),
+        }
+    }
+
+    /// Signs the P2SH multisig input at the given index with the given secret key.
+    ///
+    /// `sighash` must be the input's sighash, as returned by [`Self::input_sighash`].
+    #[cfg(feature = "transparent-inputs")]
+    pub fn sign_multisig_input(
+        &mut self,
+        index: usize,
+        sighash: &[u8; 32],
+        sk: &secp256k1::SecretKey,
+    ) -> Result<(), Error> {
+        let msg = secp256k1::Message::from_slice(sighash).expect("32 bytes");
+        let signature = self.authorization.secp.sign_ecdsa(&msg, sk);
+        let pubkey = secp256k1::PublicKey::from_secret_key(&self.authorization.secp, sk);
+        self.append_multisig_signature(index, sighash, &pubkey, &signature)
+    }
+
+    /// Signs the P2PKH inputs of this bundle, and combines the signatures collected for
+    /// its P2SH multisig inputs, to produce the authorized bundle.
+    ///
+    /// Returns an error if a P2SH multisig input has fewer signatures than its redeem
+    /// script requires.
     pub fn apply_signatures(
         self,
         #[cfg(feature = "transparent-inputs")] mtx: &TransactionData<tx::Unauthorized>,
         #[cfg(feature = "transparent-inputs")] txid_parts_cache: &TxDigests<Blake2bHash>,
-    ) -> Bundle<Authorized> {
+    ) -> Result<Bundle<Authorized>, Error> {
         #[cfg(feature = "transparent-inputs")]
         let script_sigs = self
             .authorization
             .inputs
             .iter()
             .enumerate()
-            .map(|(index, info)| {
-                let sighash = signature_hash(
-                    mtx,
-                    &SignableInput::Transparent {
-                        hash_type: SIGHASH_ALL,
-                        index,
-                        script_code: &info.coin.script_pubkey, // for p2pkh, always the same as script_pubkey
-                        script_pubkey: &info.coin.script_pubkey,
-                        value: info.coin.value,
-                    },
-                    txid_parts_cache,
-                );
-
-                let msg = secp256k1::Message::from_slice(sighash.as_ref()).expect("32 bytes");
-                let sig = self.authorization.secp.sign_ecdsa(&msg, &info.sk);
+            .map(|(index, info)| match &info.signer {
+                InputSigner::P2pkh { sk, pubkey } => {
+                    let sighash = self
+                        .input_sighash(index, mtx, txid_parts_cache)
+                        .expect("index is in range");
 
-                // Signature has to have "SIGHASH_ALL" appended to it
-                let mut sig_bytes: Vec<u8> = sig.serialize_der()[..].to_vec();
-                sig_bytes.extend([SIGHASH_ALL]);
+                    let msg = secp256k1::Message::from_slice(&sighash).expect("32 bytes");
+                    let sig = self.authorization.secp.sign_ecdsa(&msg, sk);
+
+                    // Signature has to have "SIGHASH_ALL" appended to it
+                    let mut sig_bytes: Vec<u8> = sig.serialize_der()[..].to_vec();
+                    sig_bytes.extend([SIGHASH_ALL]);
 
-                // P2PKH scriptSig
-                Script::default() << &sig_bytes[..] << &info.pubkey[..]
-            });
+                    // P2PKH scriptSig
+                    Ok(Script::default() << &sig_bytes[..] << &pubkey[..])
+                }
+                InputSigner::P2shMultisig {
+                    redeem_script,
+                    required,
+                    pubkeys,
+                    signatures,
+                } => {
+                    // Signatures must be in the same order as their keys in the redeem script.
+                    let sigs = pubkeys
+                        .iter()
+                        .filter_map(|pubkey| signatures.get(pubkey))
+                        .take(*required)
+                        .collect::<Vec<_>>();
+                    if sigs.len() < *required {
+                        return Err(Error::MissingSignatures(index));
+                    }
+
+                    // P2SH multisig scriptSig. The initial empty push (OP_0) is consumed by
+                    // the off-by-one bug in OP_CHECKMULTISIG.
+                    let script_sig = sigs
+                        .into_iter()
+                        .fold(Script::default() << &[][..], |s, sig| s << &sig[..]);
+                    Ok(script_sig << &redeem_script.0[..])
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
         #[cfg(not(feature = "transparent-inputs"))]
-        let script_sigs = std::iter::empty::<Script>();
+        let script_sigs = Vec::<Script>::new();
 
-        transparent::Bundle {
+        Ok(transparent::Bundle {
             vin: self
                 .vin
                 .iter()
@@ -290,6 +706,6 @@ impl Bundle<Unauthorized> {
                 .collect(),
             vout: self.vout,
             authorization: Authorized,
-        }
+        })
     }
 }
@@ -11,9 +11,6 @@ use crate::{
     },
 };
 
-#[cfg(feature = "transparent-inputs")]
-use crate::transaction::components::transparent::builder::TransparentInputInfo;
-
 /// The size of a transparent input, or the outpoint corresponding to the input
 /// if the size of the script required to spend that input is unknown.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -48,17 +45,6 @@ pub trait InputView: std::fmt::Debug {
     }
 }
 
-#[cfg(feature = "transparent-inputs")]
-impl InputView for TransparentInputInfo {
-    fn outpoint(&self) -> &OutPoint {
-        self.outpoint()
-    }
-
-    fn coin(&self) -> &TxOut {
-        self.coin()
-    }
-}
-
 impl InputView for Infallible {
     fn outpoint(&self) -> &OutPoint {
         unreachable!()
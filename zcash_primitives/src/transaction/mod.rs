@@ -7,6 +7,8 @@ pub mod sighash_v4;
 pub mod sighash_v5;
 pub mod txid;
 pub mod util;
+#[cfg(feature = "transaction-verification")]
+pub mod verify;
 
 #[cfg(test)]
 mod tests;
@@ -280,18 +282,28 @@ impl Authorization for Authorized {
     type TzeAuth = tze::Authorized;
 }
 
+/// [`Authorization`] marker type for transactions without proofs or authorization data.
+pub struct Unproven;
+
+impl Authorization for Unproven {
+    type TransparentAuth = transparent::builder::Unauthorized;
+    type SaplingAuth = builder::SaplingInProgress<sapling_builder::Unproven>;
+    type OrchardAuth = builder::OrchardUnproven;
+
+    #[cfg(zcash_unstable = "zfuture")]
+    type TzeAuth = tze::builder::Unauthorized;
+}
+
 /// [`Authorization`] marker type for transactions without authorization data.
 ///
-/// Currently this includes Sapling proofs because the types in this crate support v4
-/// transactions, which commit to the Sapling proofs in the transaction digest.
+/// This includes the Sapling and Orchard proofs, because the types in this crate support
+/// v4 transactions, which commit to the Sapling proofs in the transaction digest.
 pub struct Unauthorized;
 
 impl Authorization for Unauthorized {
     type TransparentAuth = transparent::builder::Unauthorized;
-    type SaplingAuth =
-        sapling_builder::InProgress<sapling_builder::Proven, sapling_builder::Unsigned>;
-    type OrchardAuth =
-        orchard::builder::InProgress<orchard::builder::Unproven, orchard::builder::Unauthorized>;
+    type SaplingAuth = builder::SaplingInProgress<sapling_builder::Proven>;
+    type OrchardAuth = builder::OrchardProven;
 
     #[cfg(zcash_unstable = "zfuture")]
     type TzeAuth = tze::builder::Unauthorized;
@@ -318,6 +330,26 @@ impl PartialEq for Transaction {
     }
 }
 
+impl Clone for Transaction {
+    fn clone(&self) -> Self {
+        Transaction {
+            txid: self.txid,
+            data: TransactionData {
+                version: self.version,
+                consensus_branch_id: self.consensus_branch_id,
+                lock_time: self.lock_time,
+                expiry_height: self.expiry_height,
+                transparent_bundle: self.transparent_bundle.clone(),
+                sprout_bundle: self.sprout_bundle.clone(),
+                sapling_bundle: self.sapling_bundle.clone(),
+                orchard_bundle: self.orchard_bundle.clone(),
+                #[cfg(zcash_unstable = "zfuture")]
+                tze_bundle: self.tze_bundle.clone(),
+            },
+        }
+    }
+}
+
 /// The information contained in a Zcash transaction.
 #[derive(Debug)]
 pub struct TransactionData<A: Authorization> {
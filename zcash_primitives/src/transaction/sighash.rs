@@ -2,7 +2,7 @@ use blake2b_simd::Hash as Blake2bHash;
 
 use super::{
     components::{amount::NonNegativeAmount, transparent},
-    sighash_v4::v4_signature_hash,
+    sighash_v4::{pre_overwinter_signature_hash, v4_signature_hash},
     sighash_v5::v5_signature_hash,
     Authorization, TransactionData, TxDigests, TxVersion,
 };
@@ -48,11 +48,11 @@ impl<'a> SignableInput<'a> {
     }
 }
 
-pub struct SignatureHash(Blake2bHash);
+pub struct SignatureHash([u8; 32]);
 
 impl AsRef<[u8; 32]> for SignatureHash {
     fn as_ref(&self) -> &[u8; 32] {
-        self.0.as_ref().try_into().unwrap()
+        &self.0
     }
 }
 
@@ -84,14 +84,17 @@ pub fn signature_hash<
     signable_input: &SignableInput,
     txid_parts: &TxDigests<Blake2bHash>,
 ) -> SignatureHash {
+    let to_array = |hash: Blake2bHash| hash.as_bytes().try_into().expect("32 bytes");
     SignatureHash(match tx.version {
-        TxVersion::Sprout(_) | TxVersion::Overwinter | TxVersion::Sapling => {
-            v4_signature_hash(tx, signable_input)
+        TxVersion::Sprout(_) => pre_overwinter_signature_hash(tx, signable_input),
+
+        TxVersion::Overwinter | TxVersion::Sapling => {
+            to_array(v4_signature_hash(tx, signable_input))
         }
 
-        TxVersion::Zip225 => v5_signature_hash(tx, signable_input, txid_parts),
+        TxVersion::Zip225 => to_array(v5_signature_hash(tx, signable_input, txid_parts)),
 
         #[cfg(zcash_unstable = "zfuture")]
-        TxVersion::ZFuture => v5_signature_hash(tx, signable_input, txid_parts),
+        TxVersion::ZFuture => to_array(v5_signature_hash(tx, signable_input, txid_parts)),
     })
 }
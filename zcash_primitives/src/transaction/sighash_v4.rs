@@ -1,8 +1,11 @@
 use blake2b_simd::{Hash as Blake2bHash, Params as Blake2bParams};
 use ff::PrimeField;
+use sha2::{Digest, Sha256};
+use zcash_encoding::{CompactSize, Vector};
 
 use crate::{
     consensus::BranchId,
+    legacy::Script,
     sapling::{
         self,
         bundle::{GrothProofBytes, OutputDescription, SpendDescription},
@@ -266,3 +269,106 @@ pub fn v4_signature_hash<
         panic!("Signature hashing for pre-overwinter transactions is not supported.")
     }
 }
+
+/// Computes the signature hash of a pre-Overwinter transaction, using the algorithm that
+/// Zcash inherited from Bitcoin.
+///
+/// This is a double SHA-256 hash of a copy of the transaction, in which the scripts of
+/// every input other than the one being signed are blanked, and the joinsplit signature
+/// (if any) is zeroed, followed by the hash type. When signing the JoinSplits rather
+/// than a transparent input, every input script is blanked.
+pub(crate) fn pre_overwinter_signature_hash<A: Authorization>(
+    tx: &TransactionData<A>,
+    signable_input: &SignableInput<'_>,
+) -> [u8; 32] {
+    let hash_type = signable_input.hash_type();
+    let (index, script_code) = match signable_input {
+        SignableInput::Shielded => (None, None),
+        SignableInput::Transparent {
+            index, script_code, ..
+        } => (Some(*index), Some(*script_code)),
+        #[cfg(zcash_unstable = "zfuture")]
+        SignableInput::Tze { .. } => {
+            panic!("A request has been made to sign a TZE input, but the transaction version is not ZFuture");
+        }
+    };
+    let (vin, vout) = tx
+        .transparent_bundle
+        .as_ref()
+        .map_or((&[][..], &[][..]), |b| (&b.vin[..], &b.vout[..]));
+
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+    let hash_none = hash_type & SIGHASH_MASK == SIGHASH_NONE;
+    let hash_single = hash_type & SIGHASH_MASK == SIGHASH_SINGLE;
+
+    // Signing an input that does not exist, or using SIGHASH_SINGLE for an input without
+    // a corresponding output, signs the value one instead of failing, as in Bitcoin.
+    let mut one = [0; 32];
+    one[0] = 1;
+    match index {
+        Some(index) if index >= vin.len() || (hash_single && index >= vout.len()) => return one,
+        None if hash_single || anyone_can_pay => return one,
+        _ => (),
+    }
+
+    let mut data = vec![];
+    data.extend_from_slice(&tx.version.header().to_le_bytes());
+
+    let inputs = match index {
+        Some(index) if anyone_can_pay => index..index + 1,
+        _ => 0..vin.len(),
+    };
+    CompactSize::write(&mut data, inputs.len()).unwrap();
+    for i in inputs {
+        vin[i].prevout.write(&mut data).unwrap();
+        if Some(i) == index {
+            script_code
+                .expect("present for transparent inputs")
+                .write(&mut data)
+        } else {
+            Script::default().write(&mut data)
+        }
+        .unwrap();
+        // Unless all outputs are signed, the other inputs may be updated at will.
+        let sequence = if Some(i) != index && (hash_none || hash_single) {
+            0
+        } else {
+            vin[i].sequence
+        };
+        data.extend_from_slice(&sequence.to_le_bytes());
+    }
+
+    let outputs = match index {
+        _ if hash_none => 0,
+        Some(index) if hash_single => index + 1,
+        _ => vout.len(),
+    };
+    CompactSize::write(&mut data, outputs).unwrap();
+    for (i, txout) in vout.iter().enumerate().take(outputs) {
+        if hash_single && Some(i) != index {
+            // The outputs before the signed one are replaced by null outputs.
+            data.extend_from_slice(&(-1i64).to_le_bytes());
+            Script::default().write(&mut data).unwrap();
+        } else {
+            txout.write(&mut data).unwrap();
+        }
+    }
+
+    data.extend_from_slice(&tx.lock_time.to_le_bytes());
+
+    if tx.version.has_sprout() {
+        let joinsplits = tx
+            .sprout_bundle
+            .as_ref()
+            .map_or(&[][..], |b| &b.joinsplits[..]);
+        Vector::write(&mut data, joinsplits, |w, js| js.write(w)).unwrap();
+        if let Some(bundle) = tx.sprout_bundle.as_ref().filter(|_| !joinsplits.is_empty()) {
+            data.extend_from_slice(&bundle.joinsplit_pubkey);
+            data.extend_from_slice(&[0; 64]);
+        }
+    }
+
+    data.extend_from_slice(&u32::from(hash_type).to_le_bytes());
+
+    Sha256::digest(Sha256::digest(&data)).into()
+}
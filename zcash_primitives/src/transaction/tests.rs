@@ -356,3 +356,56 @@ fn zip_0244() {
         );
     }
 }
+
+#[test]
+fn zip_0244_block_commitments() {
+    use crate::{
+        block::{Block, BlockHash, BlockHeaderData, Error},
+        consensus::{NetworkUpgrade, Parameters, MAIN_NETWORK},
+    };
+
+    // The expected roots were computed independently of this crate, from the auth digests
+    // of the first three ZIP 244 test vectors and an arbitrary chain history root.
+    let history_root: [u8; 32] = std::array::from_fn(|i| i as u8);
+    let mut auth_data_root = [0; 32];
+    hex::decode_to_slice(
+        "4229264d53d9fa2243773e47c980dc65fdee99743a1341c8721047cd2ce0a007",
+        &mut auth_data_root,
+    )
+    .unwrap();
+    let mut block_commitments = [0; 32];
+    hex::decode_to_slice(
+        "3563cd24bc7403e6a79203fb3acc5783659698a90c32db7c7d94e34080653180",
+        &mut block_commitments,
+    )
+    .unwrap();
+
+    let vtx = self::data::zip_0244::make_test_vectors()[..3]
+        .iter()
+        .map(|tv| Transaction::read(&tv.tx[..], BranchId::Nu5).unwrap())
+        .collect::<Vec<_>>();
+    let header = BlockHeaderData {
+        version: 4,
+        prev_block: BlockHash([0; 32]),
+        merkle_root: [0; 32],
+        final_sapling_root: block_commitments,
+        time: 0,
+        bits: 0,
+        nonce: [0; 32],
+        solution: vec![],
+    }
+    .freeze()
+    .unwrap();
+    let block = Block::from_parts(header, vtx);
+    assert_eq!(block.auth_data_root(), auth_data_root);
+
+    let nu5_height = MAIN_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap();
+    assert_eq!(
+        block.check_block_commitments(&MAIN_NETWORK, nu5_height, &history_root),
+        Ok(())
+    );
+    assert_eq!(
+        block.check_block_commitments(&MAIN_NETWORK, nu5_height, &[0; 32]),
+        Err(Error::InvalidBlockCommitments)
+    );
+}
@@ -0,0 +1,1203 @@
+//! Verification of transactions against the Zcash consensus rules.
+//!
+//! The checks performed here are those that can be made given only the transaction, the
+//! height of the block in which it is to be mined, and the transparent outputs that it
+//! spends. Rules that depend on further chain state, such as the validity of shielded
+//! anchors, the uniqueness of nullifiers across the chain, and coinbase maturity, are the
+//! responsibility of the caller.
+//!
+//! The structure, values and joinsplit signature of Sprout JoinSplits are verified, but
+//! their zk-SNARK proofs are not, as that requires the Sprout verifying keys. Callers that
+//! need to verify Sprout proofs must do so separately.
+
+use std::collections::BTreeSet;
+use std::error;
+use std::fmt;
+
+use rand::{CryptoRng, RngCore};
+
+use crate::{
+    consensus::{BlockHeight, BranchId, NetworkUpgrade, Parameters},
+    legacy::interpreter::{self, verify_script, TransactionSignatureChecker, VerificationFlags},
+    sapling::{
+        self,
+        circuit::{OutputVerifyingKey, SpendVerifyingKey},
+    },
+    transaction::{
+        components::{
+            amount::{Amount, BalanceError},
+            sprout, TxOut,
+        },
+        sighash::{signature_hash, SignableInput},
+        txid::TxIdDigester,
+        Transaction, TxVersion,
+    },
+};
+
+/// Expiry heights at or above this threshold are invalid.
+///
+/// This ensures that expiry heights cannot be confused with Unix timestamps.
+const TX_EXPIRY_HEIGHT_THRESHOLD: u32 = 500_000_000;
+
+/// Errors that can occur when verifying a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The transaction version is not valid at the height being verified.
+    InvalidVersion(TxVersion),
+    /// The consensus branch ID of the transaction does not match that of the network
+    /// upgrade active at the height being verified.
+    WrongConsensusBranchId {
+        expected: BranchId,
+        actual: BranchId,
+    },
+    /// The expiry height of the transaction is at or above the maximum expiry height.
+    ExpiryHeightTooHigh(BlockHeight),
+    /// The transaction expired before the height being verified.
+    Expired { expiry_height: BlockHeight },
+    /// The expiry height of a coinbase transaction is not the height of its block.
+    CoinbaseExpiryHeight(BlockHeight),
+    /// The transaction has no inputs.
+    NoInputs,
+    /// The transaction has no outputs.
+    NoOutputs,
+    /// The transaction spends the same transparent output more than once.
+    DuplicateInput,
+    /// The transaction reveals the same nullifier more than once.
+    DuplicateNullifier,
+    /// A coinbase transaction spends shielded notes.
+    CoinbaseShieldedSpend,
+    /// A JoinSplit both takes value from and adds value to the transparent value pool.
+    JoinSplitValues,
+    /// A JoinSplit adds value to the Sprout value pool, which is not allowed from Canopy
+    /// onwards ([ZIP 211]).
+    ///
+    /// [ZIP 211]: https://zips.z.cash/zip-0211
+    SproutDeposit,
+    /// The joinsplit signature of the transaction is invalid.
+    JoinSplitSignature,
+    /// The value balance of the transaction could not be computed.
+    Balance(BalanceError),
+    /// The value of the transaction's outputs exceeds the value of its inputs.
+    NegativeFee,
+    /// A transparent input of the transaction failed verification.
+    Transparent(interpreter::Error),
+    /// The Sapling bundle of the transaction is invalid.
+    Sapling,
+    /// The Orchard bundle of the transaction is invalid.
+    Orchard,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidVersion(version) => write!(
+                f,
+                "Transaction version {:?} is not valid at this height",
+                version
+            ),
+            Error::WrongConsensusBranchId { expected, actual } => write!(
+                f,
+                "Transaction has consensus branch ID {:?}, expected {:?}",
+                actual, expected
+            ),
+            Error::ExpiryHeightTooHigh(expiry_height) => write!(
+                f,
+                "Expiry height {} is at or above the maximum of {}",
+                expiry_height, TX_EXPIRY_HEIGHT_THRESHOLD
+            ),
+            Error::Expired { expiry_height } => {
+                write!(f, "Transaction expired at height {}", expiry_height)
+            }
+            Error::CoinbaseExpiryHeight(expiry_height) => write!(
+                f,
+                "Coinbase transaction has expiry height {}, which is not its block height",
+                expiry_height
+            ),
+            Error::NoInputs => write!(f, "Transaction has no inputs"),
+            Error::NoOutputs => write!(f, "Transaction has no outputs"),
+            Error::DuplicateInput => {
+                write!(f, "Transaction spends the same transparent output twice")
+            }
+            Error::DuplicateNullifier => {
+                write!(f, "Transaction reveals the same nullifier twice")
+            }
+            Error::CoinbaseShieldedSpend => {
+                write!(f, "Coinbase transaction spends shielded notes")
+            }
+            Error::JoinSplitValues => {
+                write!(f, "JoinSplit has both a non-zero vpub_old and vpub_new")
+            }
+            Error::SproutDeposit => {
+                write!(f, "JoinSplit adds value to the Sprout pool after Canopy")
+            }
+            Error::JoinSplitSignature => write!(f, "Invalid joinsplit signature"),
+            Error::Balance(e) => write!(f, "Invalid value balance: {}", e),
+            Error::NegativeFee => write!(f, "Transaction outputs exceed its inputs"),
+            Error::Transparent(e) => write!(f, "Invalid transparent input: {}", e),
+            Error::Sapling => write!(f, "Invalid Sapling bundle"),
+            Error::Orchard => write!(f, "Invalid Orchard bundle"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Transparent(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<BalanceError> for Error {
+    fn from(e: BalanceError) -> Self {
+        Error::Balance(e)
+    }
+}
+
+/// The verifying keys for the proofs of the shielded protocols.
+pub struct VerifyingKeys<'a> {
+    sapling_spend: &'a SpendVerifyingKey,
+    sapling_output: &'a OutputVerifyingKey,
+    orchard: &'a orchard::circuit::VerifyingKey,
+}
+
+impl<'a> VerifyingKeys<'a> {
+    /// Constructs a set of verifying keys from its parts.
+    pub fn new(
+        sapling_spend: &'a SpendVerifyingKey,
+        sapling_output: &'a OutputVerifyingKey,
+        orchard: &'a orchard::circuit::VerifyingKey,
+    ) -> Self {
+        VerifyingKeys {
+            sapling_spend,
+            sapling_output,
+            orchard,
+        }
+    }
+}
+
+/// Verifies a transaction against the consensus rules.
+///
+/// `height` is the height of the block in which the transaction is to be mined, and
+/// `spent_outputs` are the transparent outputs spent by the transaction's inputs, in
+/// order. `spent_outputs` must be empty for coinbase transactions.
+///
+/// To verify many transactions at once, use [`BatchValidator`], which amortizes the cost
+/// of verifying their proofs and signatures.
+pub fn verify_transaction<P: Parameters, R: RngCore + CryptoRng>(
+    params: &P,
+    height: BlockHeight,
+    tx: &Transaction,
+    spent_outputs: &[TxOut],
+    keys: &VerifyingKeys,
+    rng: R,
+) -> Result<(), Error> {
+    let mut validator = BatchValidator::new(params);
+    validator.check_transaction(height, tx, spent_outputs)?;
+    validator.validate(keys, rng)
+}
+
+/// Batch validation context for transactions.
+///
+/// Each transaction is checked against the consensus rules as it is added to the batch
+/// with [`BatchValidator::check_transaction`], while the verification of its shielded
+/// proofs and signatures is deferred to [`BatchValidator::validate`].
+pub struct BatchValidator<'a, P> {
+    params: &'a P,
+    sapling: sapling::BatchValidator,
+    orchard: orchard::bundle::BatchValidator,
+}
+
+impl<'a, P: Parameters> BatchValidator<'a, P> {
+    /// Constructs a new batch validation context.
+    pub fn new(params: &'a P) -> Self {
+        BatchValidator {
+            params,
+            sapling: sapling::BatchValidator::new(),
+            orchard: orchard::bundle::BatchValidator::new(),
+        }
+    }
+
+    /// Checks a transaction against the consensus rules, and adds its shielded proofs
+    /// and signatures to the batch.
+    ///
+    /// The arguments are as for [`verify_transaction`]. If this returns an error, the
+    /// batch may contain some of the transaction's proofs and signatures, and should not
+    /// be relied upon to validate the remaining transactions.
+    pub fn check_transaction(
+        &mut self,
+        height: BlockHeight,
+        tx: &Transaction,
+        spent_outputs: &[TxOut],
+    ) -> Result<(), Error> {
+        let is_coinbase = tx
+            .transparent_bundle()
+            .map_or(false, |bundle| bundle.is_coinbase());
+
+        check_version(self.params, height, tx)?;
+        check_expiry(self.params, height, tx, is_coinbase)?;
+        check_structure(tx, is_coinbase)?;
+        if let Some(bundle) = tx.sprout_bundle() {
+            check_joinsplits(self.params, height, bundle)?;
+        }
+
+        let inputs = if is_coinbase {
+            0
+        } else {
+            tx.transparent_bundle().map_or(0, |bundle| bundle.vin.len())
+        };
+        if inputs != spent_outputs.len() {
+            return Err(Error::Transparent(
+                interpreter::Error::SpentOutputsMismatch {
+                    inputs,
+                    spent_outputs: spent_outputs.len(),
+                },
+            ));
+        }
+
+        // The value balance of a coinbase transaction is checked against the block
+        // subsidy and fees, which requires knowledge of the rest of the block.
+        if !is_coinbase {
+            let mut spent_values = spent_outputs.iter().map(|txout| Amount::from(txout.value));
+            let fee = tx.fee_paid(|_| {
+                Ok::<_, Error>(spent_values.next().expect("inputs match spent outputs"))
+            })?;
+            if fee.is_negative() {
+                return Err(Error::NegativeFee);
+            }
+        }
+
+        let tx_data = interpreter::with_spent_outputs(tx, spent_outputs);
+        let txid_parts = tx_data.digest(TxIdDigester);
+
+        if let Some(bundle) = tx.transparent_bundle().filter(|_| !is_coinbase) {
+            for (index, (txin, spent_output)) in bundle.vin.iter().zip(spent_outputs).enumerate() {
+                let checker =
+                    TransactionSignatureChecker::new(&tx_data, &txid_parts, index, spent_output);
+                verify_script(
+                    &txin.script_sig,
+                    &spent_output.script_pubkey,
+                    VerificationFlags::CONSENSUS,
+                    &checker,
+                )
+                .map_err(|error| Error::Transparent(interpreter::Error::Script { index, error }))?;
+            }
+        }
+
+        let has_shielded_bundle = tx.sprout_bundle().is_some()
+            || tx.sapling_bundle().is_some()
+            || tx.orchard_bundle().is_some();
+        if has_shielded_bundle {
+            let sighash: [u8; 32] =
+                *signature_hash(&tx_data, &SignableInput::Shielded, &txid_parts).as_ref();
+
+            if let Some(bundle) = tx.sprout_bundle() {
+                // Signatures are validated according to ZIP 215.
+                let valid = ed25519_zebra::VerificationKey::try_from(bundle.joinsplit_pubkey)
+                    .and_then(|key| {
+                        key.verify(
+                            &ed25519_zebra::Signature::from(bundle.joinsplit_sig),
+                            &sighash,
+                        )
+                    })
+                    .is_ok();
+                if !valid {
+                    return Err(Error::JoinSplitSignature);
+                }
+            }
+            if let Some(bundle) = tx.sapling_bundle() {
+                if !self.sapling.check_bundle(bundle.clone(), sighash) {
+                    return Err(Error::Sapling);
+                }
+            }
+            if let Some(bundle) = tx.orchard_bundle() {
+                self.orchard.add_bundle(bundle, sighash);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the shielded proofs and signatures of every transaction added to the
+    /// batch.
+    ///
+    /// No attempt is made to identify which of the transactions are invalid; if that
+    /// information is desired, use separate batches for subsets of the transactions.
+    pub fn validate<R: RngCore + CryptoRng>(
+        self,
+        keys: &VerifyingKeys,
+        mut rng: R,
+    ) -> Result<(), Error> {
+        if !self
+            .sapling
+            .validate(keys.sapling_spend, keys.sapling_output, &mut rng)
+        {
+            return Err(Error::Sapling);
+        }
+        if !self.orchard.validate(keys.orchard, rng) {
+            return Err(Error::Orchard);
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the version and consensus branch ID of the transaction are valid at the
+/// given height.
+fn check_version<P: Parameters>(
+    params: &P,
+    height: BlockHeight,
+    tx: &Transaction,
+) -> Result<(), Error> {
+    let is_active = |nu| params.is_nu_active(nu, height);
+    let valid = match tx.version() {
+        // Transactions must be overwintered once Overwinter has activated.
+        TxVersion::Sprout(_) => !is_active(NetworkUpgrade::Overwinter),
+        // v3 transactions are only valid during the Overwinter epoch.
+        TxVersion::Overwinter => {
+            is_active(NetworkUpgrade::Overwinter) && !is_active(NetworkUpgrade::Sapling)
+        }
+        TxVersion::Sapling => is_active(NetworkUpgrade::Sapling),
+        TxVersion::Zip225 => is_active(NetworkUpgrade::Nu5),
+        #[cfg(zcash_unstable = "zfuture")]
+        TxVersion::ZFuture => is_active(NetworkUpgrade::ZFuture),
+    };
+    if !valid {
+        return Err(Error::InvalidVersion(tx.version()));
+    }
+
+    if tx.version().has_overwinter() {
+        let expected = BranchId::for_height(params, height);
+        if tx.consensus_branch_id() != expected {
+            return Err(Error::WrongConsensusBranchId {
+                expected,
+                actual: tx.consensus_branch_id(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the expiry height of the transaction.
+fn check_expiry<P: Parameters>(
+    params: &P,
+    height: BlockHeight,
+    tx: &Transaction,
+    is_coinbase: bool,
+) -> Result<(), Error> {
+    if !tx.version().has_overwinter() {
+        return Ok(());
+    }
+
+    let expiry_height = tx.expiry_height();
+    if u32::from(expiry_height) >= TX_EXPIRY_HEIGHT_THRESHOLD {
+        return Err(Error::ExpiryHeightTooHigh(expiry_height));
+    }
+
+    if is_coinbase {
+        // From NU5, the expiry height of a coinbase transaction must be its block height.
+        if params.is_nu_active(NetworkUpgrade::Nu5, height) && expiry_height != height {
+            return Err(Error::CoinbaseExpiryHeight(expiry_height));
+        }
+    } else if u32::from(expiry_height) != 0 && height > expiry_height {
+        return Err(Error::Expired { expiry_height });
+    }
+
+    Ok(())
+}
+
+/// Checks the inputs and outputs of the transaction, independently of their values.
+fn check_structure(tx: &Transaction, is_coinbase: bool) -> Result<(), Error> {
+    let transparent = tx.transparent_bundle();
+    let sprout = tx.sprout_bundle();
+    let sapling = tx.sapling_bundle();
+    let orchard = tx.orchard_bundle();
+
+    // JoinSplits both spend and create notes.
+    let has_joinsplits = sprout.map_or(false, |b| !b.joinsplits.is_empty());
+    let has_inputs = transparent.map_or(false, |b| !b.vin.is_empty())
+        || has_joinsplits
+        || sapling.map_or(false, |b| !b.shielded_spends().is_empty())
+        || orchard.map_or(false, |b| b.flags().spends_enabled());
+    if !has_inputs {
+        return Err(Error::NoInputs);
+    }
+    let has_outputs = transparent.map_or(false, |b| !b.vout.is_empty())
+        || has_joinsplits
+        || sapling.map_or(false, |b| !b.shielded_outputs().is_empty())
+        || orchard.map_or(false, |b| b.flags().outputs_enabled());
+    if !has_outputs {
+        return Err(Error::NoOutputs);
+    }
+
+    if let Some(bundle) = transparent {
+        let mut prevouts = BTreeSet::new();
+        if !bundle.vin.iter().all(|txin| prevouts.insert(&txin.prevout)) {
+            return Err(Error::DuplicateInput);
+        }
+    }
+    if let Some(bundle) = sprout {
+        let mut nullifiers = BTreeSet::new();
+        if !bundle
+            .joinsplits
+            .iter()
+            .flat_map(|js| &js.nullifiers)
+            .all(|nf| nullifiers.insert(nf))
+        {
+            return Err(Error::DuplicateNullifier);
+        }
+    }
+    if let Some(bundle) = sapling {
+        let mut nullifiers = BTreeSet::new();
+        if !bundle
+            .shielded_spends()
+            .iter()
+            .all(|spend| nullifiers.insert(spend.nullifier()))
+        {
+            return Err(Error::DuplicateNullifier);
+        }
+    }
+    if let Some(bundle) = orchard {
+        let mut nullifiers = BTreeSet::new();
+        if !bundle
+            .actions()
+            .iter()
+            .all(|action| nullifiers.insert(action.nullifier().to_bytes()))
+        {
+            return Err(Error::DuplicateNullifier);
+        }
+    }
+
+    if is_coinbase
+        && (has_joinsplits
+            || sapling.map_or(false, |b| !b.shielded_spends().is_empty())
+            || orchard.map_or(false, |b| b.flags().spends_enabled()))
+    {
+        return Err(Error::CoinbaseShieldedSpend);
+    }
+
+    Ok(())
+}
+
+/// Checks the values of the JoinSplits of the transaction.
+fn check_joinsplits<P: Parameters>(
+    params: &P,
+    height: BlockHeight,
+    bundle: &sprout::Bundle,
+) -> Result<(), Error> {
+    let is_canopy = params.is_nu_active(NetworkUpgrade::Canopy, height);
+    for js in &bundle.joinsplits {
+        if js.vpub_old.is_positive() && js.vpub_new.is_positive() {
+            return Err(Error::JoinSplitValues);
+        }
+        if is_canopy && js.vpub_old.is_positive() {
+            return Err(Error::SproutDeposit);
+        }
+    }
+
+    // The total values taken from and added to the transparent value pool must each be
+    // in range, not just their difference.
+    let vpub_old = bundle
+        .joinsplits
+        .iter()
+        .map(|js| js.vpub_old)
+        .sum::<Option<Amount>>();
+    let vpub_new = bundle
+        .joinsplits
+        .iter()
+        .map(|js| js.vpub_new)
+        .sum::<Option<Amount>>();
+    if vpub_old.is_none() || vpub_new.is_none() {
+        return Err(Error::Balance(BalanceError::Overflow));
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "transparent-inputs"))]
+mod tests {
+    use bellman::groth16::generate_random_parameters;
+    use bls12_381::Bls12;
+    use rand_core::OsRng;
+
+    use super::{verify_transaction, BatchValidator, Error, VerifyingKeys};
+    use crate::{
+        consensus::{BranchId, NetworkUpgrade, Parameters, TEST_NETWORK},
+        legacy::{
+            interpreter::{self, ScriptError},
+            keys::{AccountPrivKey, IncomingViewingKey, NonHardenedChildIndex},
+            TransparentAddress,
+        },
+        memo::MemoBytes,
+        sapling::{
+            self,
+            circuit::{OutputParameters, SpendParameters},
+            zip32::ExtendedSpendingKey,
+        },
+        transaction::{
+            builder::{BuildConfig, Builder},
+            components::{
+                amount::{Amount, NonNegativeAmount},
+                sprout::{self, JsDescription, SproutProof, PHGR_PROOF_SIZE},
+                transparent, OutPoint, TxOut, GROTH_PROOF_SIZE,
+            },
+            fees::zip317,
+            sighash::{signature_hash, SignableInput},
+            txid::TxIdDigester,
+            Authorized, Transaction, TransactionData, TxVersion,
+        },
+        zip32::AccountId,
+    };
+
+    fn check(height: u32, tx: &Transaction, spent_outputs: &[TxOut]) -> Result<(), Error> {
+        BatchValidator::new(&TEST_NETWORK).check_transaction(height.into(), tx, spent_outputs)
+    }
+
+    /// Generates random parameters for the Sapling Output circuit, with which Sapling
+    /// outputs can be proven and verified without the parameters from the Sapling
+    /// trusted setup.
+    ///
+    /// The same parameters stand in for those of the Spend circuit, which are much slower
+    /// to generate, in the tests that do not spend Sapling notes.
+    fn sapling_params() -> (SpendParameters, OutputParameters) {
+        let params = generate_random_parameters::<Bls12, _, _>(
+            sapling::circuit::Output {
+                value_commitment_opening: None,
+                payment_address: None,
+                commitment_randomness: None,
+                esk: None,
+            },
+            &mut OsRng,
+        )
+        .unwrap();
+        let mut buf = vec![];
+        params.write(&mut buf).unwrap();
+        (
+            SpendParameters::read(&buf[..], false).unwrap(),
+            OutputParameters::read(&buf[..], false).unwrap(),
+        )
+    }
+
+    /// Generates random parameters for the Sapling Spend circuit.
+    #[cfg(feature = "expensive-tests")]
+    fn spend_params() -> SpendParameters {
+        let params = generate_random_parameters::<Bls12, _, _>(
+            sapling::circuit::Spend {
+                value_commitment_opening: None,
+                proof_generation_key: None,
+                payment_address: None,
+                commitment_randomness: None,
+                ar: None,
+                auth_path: vec![None; 32],
+                anchor: None,
+            },
+            &mut OsRng,
+        )
+        .unwrap();
+        let mut buf = vec![];
+        params.write(&mut buf).unwrap();
+        SpendParameters::read(&buf[..], false).unwrap()
+    }
+
+    /// Rebuilds a transaction with the given version and bundles, and the lock time and
+    /// expiry height of `tx`.
+    fn rebuild(
+        version: TxVersion,
+        consensus_branch_id: BranchId,
+        tx: &Transaction,
+        transparent_bundle: Option<transparent::Bundle<transparent::Authorized>>,
+        sapling_bundle: Option<sapling::Bundle<sapling::bundle::Authorized, Amount>>,
+        orchard_bundle: Option<orchard::Bundle<orchard::bundle::Authorized, Amount>>,
+    ) -> Transaction {
+        TransactionData::<Authorized>::from_parts(
+            version,
+            consensus_branch_id,
+            tx.lock_time(),
+            tx.expiry_height(),
+            transparent_bundle,
+            None,
+            sapling_bundle,
+            orchard_bundle,
+        )
+        .freeze()
+        .unwrap()
+    }
+
+    #[test]
+    fn shielded_outputs() {
+        let tsk = AccountPrivKey::from_seed(&TEST_NETWORK, &[0u8; 32], AccountId::ZERO).unwrap();
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(100000),
+            script_pubkey: tsk
+                .to_account_pubkey()
+                .derive_external_ivk()
+                .unwrap()
+                .derive_address(NonHardenedChildIndex::ZERO)
+                .unwrap()
+                .script(),
+        };
+        let sapling_to = ExtendedSpendingKey::master(&[]).default_address().1;
+        let orchard_to = orchard::keys::FullViewingKey::from(
+            &orchard::keys::SpendingKey::from_bytes([7; 32]).unwrap(),
+        )
+        .address_at(0u32, orchard::keys::Scope::External);
+
+        let nu5_height = u32::from(TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap());
+        let mut builder = Builder::new(
+            TEST_NETWORK,
+            nu5_height.into(),
+            BuildConfig::Standard {
+                sapling_anchor: Some(sapling::Anchor::empty_tree()),
+                orchard_anchor: Some(orchard::Anchor::empty_tree()),
+            },
+        );
+        builder
+            .add_transparent_input(
+                tsk.derive_external_secret_key(NonHardenedChildIndex::ZERO)
+                    .unwrap(),
+                OutPoint::fake(),
+                prev_coin.clone(),
+            )
+            .unwrap();
+        builder
+            .add_sapling_output::<zip317::FeeError>(
+                None,
+                sapling_to,
+                NonNegativeAmount::const_from_u64(30000),
+                MemoBytes::empty(),
+            )
+            .unwrap();
+        builder
+            .add_orchard_output::<zip317::FeeError>(None, orchard_to, 30000, MemoBytes::empty())
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0; 20]),
+                NonNegativeAmount::const_from_u64(15000),
+            )
+            .unwrap();
+
+        let (spend_params, output_params) = sapling_params();
+        let res = builder
+            .build(
+                OsRng,
+                &spend_params,
+                &output_params,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap();
+        let tx = res.transaction();
+        assert!(tx.sapling_bundle().is_some());
+        assert!(tx.orchard_bundle().is_some());
+
+        let spend_vk = spend_params.verifying_key();
+        let output_vk = output_params.verifying_key();
+        let orchard_vk = orchard::circuit::VerifyingKey::build();
+        let keys = VerifyingKeys::new(&spend_vk, &output_vk, &orchard_vk);
+        let verify = |tx: &Transaction, keys: &VerifyingKeys| {
+            verify_transaction(
+                &TEST_NETWORK,
+                nu5_height.into(),
+                tx,
+                &[prev_coin.clone()],
+                keys,
+                OsRng,
+            )
+        };
+
+        assert_eq!(verify(tx, &keys), Ok(()));
+
+        // A batch only validates if all of its transactions are valid.
+        let mut validator = BatchValidator::new(&TEST_NETWORK);
+        validator
+            .check_transaction(nu5_height.into(), tx, &[prev_coin.clone()])
+            .unwrap();
+        validator
+            .check_transaction(nu5_height.into(), tx, &[prev_coin.clone()])
+            .unwrap();
+        assert_eq!(validator.validate(&keys, OsRng), Ok(()));
+
+        // The Sapling proofs must be valid for the verifying key.
+        let (_, other_output_params) = sapling_params();
+        let other_output_vk = other_output_params.verifying_key();
+        assert_eq!(
+            verify(
+                tx,
+                &VerifyingKeys::new(&spend_vk, &other_output_vk, &orchard_vk)
+            ),
+            Err(Error::Sapling)
+        );
+
+        // Corrupting the proofs of either shielded bundle, which are not committed to by
+        // the transaction's signatures, invalidates the transaction.
+        let corrupt_sapling = rebuild(
+            tx.version(),
+            tx.consensus_branch_id(),
+            tx,
+            tx.transparent_bundle().cloned(),
+            tx.sapling_bundle().cloned().map(|bundle| {
+                bundle.map_authorization(
+                    (),
+                    |_, proof| proof,
+                    |_, mut proof| {
+                        proof[10] ^= 1;
+                        proof
+                    },
+                    |_, sig| sig,
+                    |_, auth| auth,
+                )
+            }),
+            tx.orchard_bundle().cloned(),
+        );
+        assert_eq!(verify(&corrupt_sapling, &keys), Err(Error::Sapling));
+        let corrupt_orchard = rebuild(
+            tx.version(),
+            tx.consensus_branch_id(),
+            tx,
+            tx.transparent_bundle().cloned(),
+            tx.sapling_bundle().cloned(),
+            tx.orchard_bundle().cloned().map(|bundle| {
+                bundle.map_authorization(
+                    &mut (),
+                    |_, _, sig| sig,
+                    |_, auth| {
+                        let mut proof = auth.proof().as_ref().to_vec();
+                        proof[10] ^= 1;
+                        orchard::bundle::Authorized::from_parts(
+                            orchard::Proof::new(proof),
+                            auth.binding_signature().clone(),
+                        )
+                    },
+                )
+            }),
+        );
+        assert_eq!(verify(&corrupt_orchard, &keys), Err(Error::Orchard));
+
+        // Transactions that predate Overwinter have a different signature hash, so the
+        // transparent signature does not carry over to them.
+        let pre_overwinter = TEST_NETWORK
+            .activation_height(NetworkUpgrade::Overwinter)
+            .unwrap()
+            - 1;
+        let v1 = rebuild(
+            TxVersion::Sprout(1),
+            BranchId::Sprout,
+            tx,
+            tx.transparent_bundle().cloned(),
+            None,
+            None,
+        );
+        assert_eq!(
+            check(pre_overwinter.into(), &v1, &[prev_coin.clone()]),
+            Err(Error::Transparent(interpreter::Error::Script {
+                index: 0,
+                error: ScriptError::EvalFalse
+            }))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "expensive-tests")]
+    fn shielded_spend() {
+        use incrementalmerkletree::{frontier::CommitmentTree, witness::IncrementalWitness};
+        use sapling::{Node, Rseed};
+
+        let extsk = ExtendedSpendingKey::master(&[]);
+        let to = extsk.default_address().1;
+        let note = to.create_note(
+            sapling::value::NoteValue::from_raw(50000),
+            Rseed::AfterZip212([4; 32]),
+        );
+        let mut tree = CommitmentTree::<Node, 32>::empty();
+        tree.append(Node::from_cmu(&note.cmu())).unwrap();
+        let witness = IncrementalWitness::from_tree(tree);
+
+        let nu5_height = u32::from(TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap());
+        let mut builder = Builder::new(
+            TEST_NETWORK,
+            nu5_height.into(),
+            BuildConfig::Standard {
+                sapling_anchor: Some(witness.root().into()),
+                orchard_anchor: None,
+            },
+        );
+        builder
+            .add_sapling_spend::<zip317::FeeError>(&extsk, note, witness.path().unwrap())
+            .unwrap();
+        builder
+            .add_sapling_output::<zip317::FeeError>(
+                None,
+                to,
+                NonNegativeAmount::const_from_u64(40000),
+                MemoBytes::empty(),
+            )
+            .unwrap();
+
+        let spend_params = spend_params();
+        let (_, output_params) = sapling_params();
+        let res = builder
+            .build(
+                OsRng,
+                &spend_params,
+                &output_params,
+                &zip317::FeeRule::standard(),
+            )
+            .unwrap();
+        let tx = res.transaction();
+        assert_eq!(tx.sapling_bundle().unwrap().shielded_spends().len(), 1);
+
+        let spend_vk = spend_params.verifying_key();
+        let output_vk = output_params.verifying_key();
+        let orchard_vk = orchard::circuit::VerifyingKey::build();
+        let keys = VerifyingKeys::new(&spend_vk, &output_vk, &orchard_vk);
+        let verify = |tx: &Transaction, keys: &VerifyingKeys| {
+            verify_transaction(&TEST_NETWORK, nu5_height.into(), tx, &[], keys, OsRng)
+        };
+
+        assert_eq!(verify(tx, &keys), Ok(()));
+
+        // Corrupting the spend proof or the spend authorization signature invalidates
+        // the transaction.
+        let corrupt = |corrupt_proof: bool| {
+            rebuild(
+                tx.version(),
+                tx.consensus_branch_id(),
+                tx,
+                None,
+                tx.sapling_bundle().cloned().map(|bundle| {
+                    bundle.map_authorization(
+                        (),
+                        |_, mut proof| {
+                            if corrupt_proof {
+                                proof[10] ^= 1;
+                            }
+                            proof
+                        },
+                        |_, proof| proof,
+                        |_, sig| {
+                            if corrupt_proof {
+                                sig
+                            } else {
+                                let mut bytes = <[u8; 64]>::from(sig);
+                                bytes[40] ^= 1;
+                                redjubjub::Signature::from(bytes)
+                            }
+                        },
+                        |_, auth| auth,
+                    )
+                }),
+                None,
+            )
+        };
+        assert_eq!(verify(&corrupt(true), &keys), Err(Error::Sapling));
+        assert_eq!(verify(&corrupt(false), &keys), Err(Error::Sapling));
+    }
+
+    #[test]
+    fn sprout_joinsplits() {
+        let signing_key = ed25519_zebra::SigningKey::new(OsRng);
+        let joinsplit = |vpub_old: u64, vpub_new: u64, nf: u8, groth: bool| JsDescription {
+            vpub_old: Amount::from_u64(vpub_old).unwrap(),
+            vpub_new: Amount::from_u64(vpub_new).unwrap(),
+            anchor: [1; 32],
+            nullifiers: [[nf; 32], [nf + 1; 32]],
+            commitments: [[2; 32], [3; 32]],
+            ephemeral_key: [4; 32],
+            random_seed: [5; 32],
+            macs: [[6; 32], [7; 32]],
+            proof: if groth {
+                SproutProof::Groth([8; GROTH_PROOF_SIZE])
+            } else {
+                SproutProof::PHGR([8; PHGR_PROOF_SIZE])
+            },
+            ciphertexts: [[9; 601]; 2],
+        };
+        // Builds a transaction with the given JoinSplits and a single transparent
+        // output, signed by `signing_key`.
+        let build = |version: TxVersion,
+                     consensus_branch_id: BranchId,
+                     joinsplits: Vec<JsDescription>,
+                     value: u64| {
+            let tx_with = |joinsplit_sig| {
+                TransactionData::<Authorized>::from_parts(
+                    version,
+                    consensus_branch_id,
+                    0,
+                    0.into(),
+                    Some(transparent::Bundle {
+                        vin: vec![],
+                        vout: vec![TxOut {
+                            value: NonNegativeAmount::from_u64(value).unwrap(),
+                            script_pubkey: TransparentAddress::PublicKeyHash([0; 20]).script(),
+                        }],
+                        authorization: transparent::Authorized,
+                    }),
+                    Some(sprout::Bundle {
+                        joinsplits: joinsplits.clone(),
+                        joinsplit_pubkey: ed25519_zebra::VerificationKeyBytes::from(&signing_key)
+                            .into(),
+                        joinsplit_sig,
+                    }),
+                    None,
+                    None,
+                )
+                .freeze()
+                .unwrap()
+            };
+            let unsigned = tx_with([0; 64]);
+            let tx_data = interpreter::with_spent_outputs(&unsigned, &[]);
+            let txid_parts = tx_data.digest(TxIdDigester);
+            let sighash = signature_hash(&tx_data, &SignableInput::Shielded, &txid_parts);
+            tx_with(signing_key.sign(sighash.as_ref()).into())
+        };
+
+        let sapling_height = u32::from(
+            TEST_NETWORK
+                .activation_height(NetworkUpgrade::Sapling)
+                .unwrap(),
+        );
+        let v4 =
+            |joinsplits, value| build(TxVersion::Sapling, BranchId::Sapling, joinsplits, value);
+
+        let tx = v4(vec![joinsplit(0, 50000, 1, true)], 40000);
+        assert_eq!(check(sapling_height, &tx, &[]), Ok(()));
+
+        // The joinsplit signature commits to the transaction.
+        let mut sprout_bundle = tx.sprout_bundle().cloned().unwrap();
+        sprout_bundle.joinsplit_sig[5] ^= 1;
+        let bad_sig = TransactionData::<Authorized>::from_parts(
+            tx.version(),
+            tx.consensus_branch_id(),
+            tx.lock_time(),
+            tx.expiry_height(),
+            tx.transparent_bundle().cloned(),
+            Some(sprout_bundle),
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+        assert_eq!(
+            check(sapling_height, &bad_sig, &[]),
+            Err(Error::JoinSplitSignature)
+        );
+
+        // The value taken from the Sprout pool must cover the outputs.
+        assert_eq!(
+            check(
+                sapling_height,
+                &v4(vec![joinsplit(0, 50000, 1, true)], 60000),
+                &[]
+            ),
+            Err(Error::NegativeFee)
+        );
+
+        // A JoinSplit cannot move value in both directions.
+        assert_eq!(
+            check(
+                sapling_height,
+                &v4(vec![joinsplit(10000, 50000, 1, true)], 30000),
+                &[]
+            ),
+            Err(Error::JoinSplitValues)
+        );
+
+        // Nullifiers must be unique across JoinSplits.
+        assert_eq!(
+            check(
+                sapling_height,
+                &v4(
+                    vec![joinsplit(0, 20000, 1, true), joinsplit(0, 30000, 2, true)],
+                    40000
+                ),
+                &[]
+            ),
+            Err(Error::DuplicateNullifier)
+        );
+
+        // From Canopy, no value can be added to the Sprout pool.
+        let canopy_height = u32::from(
+            TEST_NETWORK
+                .activation_height(NetworkUpgrade::Canopy)
+                .unwrap(),
+        );
+        assert_eq!(
+            check(
+                canopy_height,
+                &build(
+                    TxVersion::Sapling,
+                    BranchId::Canopy,
+                    vec![joinsplit(10000, 0, 1, true)],
+                    0
+                ),
+                &[]
+            ),
+            Err(Error::SproutDeposit)
+        );
+
+        // Transactions that predate Overwinter are signed with the legacy signature hash.
+        let pre_overwinter = u32::from(
+            TEST_NETWORK
+                .activation_height(NetworkUpgrade::Overwinter)
+                .unwrap(),
+        ) - 1;
+        let v2 = build(
+            TxVersion::Sprout(2),
+            BranchId::Sprout,
+            vec![joinsplit(0, 50000, 1, false)],
+            40000,
+        );
+        assert_eq!(check(pre_overwinter, &v2, &[]), Ok(()));
+    }
+
+    #[test]
+    fn transparent_spend() {
+        let tsk = AccountPrivKey::from_seed(&TEST_NETWORK, &[0u8; 32], AccountId::ZERO).unwrap();
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50000),
+            script_pubkey: tsk
+                .to_account_pubkey()
+                .derive_external_ivk()
+                .unwrap()
+                .derive_address(NonHardenedChildIndex::ZERO)
+                .unwrap()
+                .script(),
+        };
+
+        let nu5_height = u32::from(TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap());
+        let mut builder = Builder::new(
+            TEST_NETWORK,
+            nu5_height.into(),
+            BuildConfig::Standard {
+                sapling_anchor: None,
+                orchard_anchor: None,
+            },
+        );
+        builder
+            .add_transparent_input(
+                tsk.derive_external_secret_key(NonHardenedChildIndex::ZERO)
+                    .unwrap(),
+                OutPoint::fake(),
+                prev_coin.clone(),
+            )
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0; 20]),
+                NonNegativeAmount::const_from_u64(40000),
+            )
+            .unwrap();
+        let res = builder.mock_build(OsRng).unwrap();
+        let tx = res.transaction();
+        let expiry_height = u32::from(tx.expiry_height());
+
+        assert_eq!(check(nu5_height, tx, &[prev_coin.clone()]), Ok(()));
+        assert_eq!(check(expiry_height, tx, &[prev_coin.clone()]), Ok(()));
+
+        // The transaction is only valid from NU5 activation until it expires.
+        assert_eq!(
+            check(nu5_height - 1, tx, &[prev_coin.clone()]),
+            Err(Error::InvalidVersion(TxVersion::Zip225))
+        );
+        assert_eq!(
+            check(expiry_height + 1, tx, &[prev_coin.clone()]),
+            Err(Error::Expired {
+                expiry_height: tx.expiry_height()
+            })
+        );
+
+        // The spent outputs must correspond to the transaction's inputs.
+        assert_eq!(
+            check(nu5_height, tx, &[]),
+            Err(Error::Transparent(
+                interpreter::Error::SpentOutputsMismatch {
+                    inputs: 1,
+                    spent_outputs: 0
+                }
+            ))
+        );
+
+        // The inputs must cover the outputs.
+        let small_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(30000),
+            script_pubkey: prev_coin.script_pubkey.clone(),
+        };
+        assert_eq!(
+            check(nu5_height, tx, &[small_coin]),
+            Err(Error::NegativeFee)
+        );
+
+        // The signature does not authorize spending a different output.
+        let other_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50001),
+            script_pubkey: prev_coin.script_pubkey.clone(),
+        };
+        assert_eq!(
+            check(nu5_height, tx, &[other_coin]),
+            Err(Error::Transparent(interpreter::Error::Script {
+                index: 0,
+                error: ScriptError::EvalFalse
+            }))
+        );
+    }
+
+    #[test]
+    fn branch_id() {
+        let tsk = AccountPrivKey::from_seed(&TEST_NETWORK, &[0u8; 32], AccountId::ZERO).unwrap();
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50000),
+            script_pubkey: tsk
+                .to_account_pubkey()
+                .derive_external_ivk()
+                .unwrap()
+                .derive_address(NonHardenedChildIndex::ZERO)
+                .unwrap()
+                .script(),
+        };
+
+        // A v4 transaction built for Canopy remains a valid version under NU5, but its
+        // consensus branch ID does not.
+        let canopy_height = TEST_NETWORK
+            .activation_height(NetworkUpgrade::Canopy)
+            .unwrap();
+        let nu5_height = TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap();
+        let mut builder = Builder::new(
+            TEST_NETWORK,
+            nu5_height - 1,
+            BuildConfig::Standard {
+                sapling_anchor: None,
+                orchard_anchor: None,
+            },
+        );
+        builder
+            .add_transparent_input(
+                tsk.derive_external_secret_key(NonHardenedChildIndex::ZERO)
+                    .unwrap(),
+                OutPoint::fake(),
+                prev_coin.clone(),
+            )
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0; 20]),
+                NonNegativeAmount::const_from_u64(40000),
+            )
+            .unwrap();
+        let res = builder.mock_build(OsRng).unwrap();
+        let tx = res.transaction();
+        assert_eq!(tx.consensus_branch_id(), BranchId::Canopy);
+
+        assert_eq!(
+            check(canopy_height.into(), tx, &[prev_coin.clone()]),
+            Ok(())
+        );
+        assert_eq!(
+            check(nu5_height.into(), tx, &[prev_coin]),
+            Err(Error::WrongConsensusBranchId {
+                expected: BranchId::Nu5,
+                actual: BranchId::Canopy,
+            })
+        );
+    }
+}